@@ -10,6 +10,36 @@ use crate::u;
 use rustc_hash::FxHashSet;
 use screeps::{Direction, RoomXY, ROOM_SIZE};
 
+/// The directions towards which chokepoint tiles go based on the direction to "outside".
+fn check_directions(direction: Direction) -> [Direction; 2] {
+    match direction {
+        Direction::Top => [Direction::Left, Direction::Right],
+        Direction::TopRight => [Direction::Bottom, Direction::Left],
+        Direction::Right => [Direction::Top, Direction::Bottom],
+        Direction::BottomRight => [Direction::Top, Direction::Left],
+        Direction::Bottom => [Direction::Right, Direction::Left],
+        Direction::BottomLeft => [Direction::Top, Direction::Right],
+        Direction::Left => [Direction::Bottom, Direction::Top],
+        Direction::TopLeft => [Direction::Right, Direction::Bottom],
+    }
+}
+
+/// The tiles of the chokepoint through given tile checked by `chokepoint_matrix` in given direction,
+/// in the same order as they are considered there. The number of tiles is the chokepoint width.
+pub fn chokepoint_tiles(chunk_graph: &ChunkGraph, xy: RoomXY, direction: Direction) -> Vec<RoomXY> {
+    let check_directions = check_directions(direction);
+    let free_tiles_towards = |direction: Direction| {
+        (1i8..)
+            .map_while(move |dist| xy.try_add_diff(mul_offsets(OFFSET_BY_DIRECTION[direction as usize], dist)).ok())
+            .take_while(|&chokepoint_xy| chunk_graph.xy_chunks.get(chokepoint_xy) != invalid_chunk_node_index())
+    };
+    let mut chokepoint_xys = free_tiles_towards(check_directions[0]).collect::<Vec<_>>();
+    chokepoint_xys.reverse();
+    chokepoint_xys.push(xy);
+    chokepoint_xys.extend(free_tiles_towards(check_directions[1]));
+    chokepoint_xys
+}
+
 /// Checks chokepoint in a single direction. The direction is outward vector from the protected area.
 /// The result is a matrix with a tuple `(a, b)` for each tile with `a` being the chokepoint width or
 /// `obstacle_cost` for obstacles and `b` being `min_separated_tiles` for chokepoints wider than `max_chokepoint_width`
@@ -28,16 +58,7 @@ pub fn chokepoint_matrix(
 
     // Various preprocessing.
     // The directions towards which chokepoint tiles go based on the direction to "outside".
-    let check_directions = match direction {
-        Direction::Top => [Direction::Left, Direction::Right],
-        Direction::TopRight => [Direction::Bottom, Direction::Left],
-        Direction::Right => [Direction::Top, Direction::Bottom],
-        Direction::BottomRight => [Direction::Top, Direction::Left],
-        Direction::Bottom => [Direction::Right, Direction::Left],
-        Direction::BottomLeft => [Direction::Top, Direction::Right],
-        Direction::Left => [Direction::Bottom, Direction::Top],
-        Direction::TopLeft => [Direction::Right, Direction::Bottom],
-    };
+    let check_directions = check_directions(direction);
 
     let mut dt = chunk_graph.xy_chunks.map(|xy, chunk| {
         if chunk == invalid_chunk_node_index() {
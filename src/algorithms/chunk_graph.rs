@@ -11,11 +11,17 @@ use petgraph::stable_graph::{NodeIndex, StableGraph};
 use petgraph::Undirected;
 use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::RoomXY;
+use serde::{Deserialize, Serialize};
 use std::cmp::Reverse;
 use std::iter::once;
 
 pub type ChunkId = NodeIndex<u16>;
 
+/// Distance from the chunk center of tiles that are not in any chunk.
+const UNASSIGNED_DISTANCE: u8 = u8::MAX;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(into = "ChunkGraphData", from = "ChunkGraphData")]
 pub struct ChunkGraph {
     /// The assignment tiles -> chunks.
     pub xy_chunks: RoomMatrix<ChunkId>,
@@ -25,9 +31,222 @@ pub struct ChunkGraph {
     /// Nodes are labelled by chunk centers. Weights of edges are the distance between chunk
     /// centers.
     pub graph: StableGraph<RoomXY, u8, Undirected, u16>,
+    /// The chunk radius used to select chunk centers.
+    pub chunk_radius: u8,
+    /// The obstacles matrix the chunks are computed for.
+    obstacles: RoomMatrix<u8>,
+    /// Distances of tiles from the centers of their chunks, `UNASSIGNED_DISTANCE` if not in any.
+    center_distances: RoomMatrix<u8>,
+}
+
+/// The persisted part of the chunk graph, from which the rest is computed.
+#[derive(Serialize, Deserialize)]
+struct ChunkGraphData {
+    chunk_radius: u8,
+    obstacles: RoomMatrix<u8>,
+    /// Chunk centers in the order of chunk IDs.
+    centers: Vec<RoomXY>,
+}
+
+impl From<ChunkGraph> for ChunkGraphData {
+    fn from(chunk_graph: ChunkGraph) -> Self {
+        ChunkGraphData {
+            chunk_radius: chunk_graph.chunk_radius,
+            centers: chunk_graph.graph.node_weights().copied().collect(),
+            obstacles: chunk_graph.obstacles,
+        }
+    }
+}
+
+impl From<ChunkGraphData> for ChunkGraph {
+    fn from(data: ChunkGraphData) -> Self {
+        ChunkGraph::from_centers(&data.obstacles, data.centers.into_iter(), data.chunk_radius)
+    }
 }
 
 impl ChunkGraph {
+    /// Creates the chunk graph with given chunk centers, in the order of chunk IDs. Each tile is
+    /// assigned to the chunk with the closest center, with ties broken in favor of lower chunk IDs.
+    pub fn from_centers<C>(obstacles: &RoomMatrix<u8>, centers: C, chunk_radius: u8) -> Self
+    where
+        C: Iterator<Item = RoomXY>,
+    {
+        let mut graph = StableGraph::default();
+        for center in centers {
+            graph.add_node(center);
+        }
+        Self::flood_from_centers(obstacles.clone(), graph, chunk_radius)
+    }
+
+    /// Assigns the tiles to the chunks of the graph, which should have no edges yet.
+    fn flood_from_centers(
+        obstacles: RoomMatrix<u8>,
+        graph: StableGraph<RoomXY, u8, Undirected, u16>,
+        chunk_radius: u8,
+    ) -> Self {
+        let mut chunk_graph = ChunkGraph {
+            xy_chunks: RoomMatrix::new(invalid_chunk_node_index()),
+            chunk_sizes: graph.node_indices().map(|chunk_id| (chunk_id, 0)).collect(),
+            graph,
+            chunk_radius,
+            obstacles,
+            center_distances: RoomMatrix::new(UNASSIGNED_DISTANCE),
+        };
+
+        let mut queue = vec![Vec::new()];
+        for chunk_id in chunk_graph.graph.node_indices() {
+            queue[0].push((chunk_id, chunk_graph.graph[chunk_id]));
+        }
+        let mut changed_chunks = FxHashSet::default();
+        chunk_graph.flood(queue, &mut changed_chunks);
+        chunk_graph.update_edges(&changed_chunks);
+
+        chunk_graph
+    }
+
+    /// The obstacles matrix the chunks are computed for.
+    pub fn obstacles(&self) -> &RoomMatrix<u8> {
+        &self.obstacles
+    }
+
+    /// Updates the chunks after the given tiles became obstacles or stopped being ones. Only the
+    /// tiles whose chunk may change are assigned again and only the edges of chunks that changed
+    /// are recomputed. Chunk centers are kept, except for the ones that became obstacles, whose
+    /// chunks are removed. No new chunks are created, so tiles that are no longer reachable from
+    /// any chunk center are left without a chunk.
+    /// The result is the same as `ChunkGraph::from_centers` with the new obstacles and the
+    /// remaining centers.
+    pub fn update(&mut self, changed_tiles: &[(RoomXY, bool)]) {
+        let mut changed_chunks = FxHashSet::default();
+
+        // Unassigning the tiles that became obstacles along with all tiles whose distance from
+        // the chunk center may depend on them.
+        let mut dependencies = Vec::new();
+        let mut seeds = Vec::new();
+        for &(xy, obstacle) in changed_tiles.iter() {
+            if (self.obstacles.get(xy) == OBSTACLE_COST) == obstacle {
+                continue;
+            }
+            if obstacle {
+                self.obstacles.set(xy, OBSTACLE_COST);
+                let chunk_id = self.xy_chunks.get(xy);
+                if self.graph.node_weight(chunk_id) == Some(&xy) {
+                    self.graph.remove_node(chunk_id);
+                    self.chunk_sizes.remove(&chunk_id);
+                    changed_chunks.remove(&chunk_id);
+                    self.xy_chunks.set(xy, invalid_chunk_node_index());
+                    self.center_distances.set(xy, UNASSIGNED_DISTANCE);
+                    dependencies.push((xy, 0, chunk_id));
+                } else {
+                    dependencies.push((xy, self.center_distances.get(xy), chunk_id));
+                    self.assign(xy, UNASSIGNED_DISTANCE, invalid_chunk_node_index(), &mut changed_chunks);
+                }
+            } else {
+                self.obstacles.set(xy, 0);
+                seeds.push(xy);
+            }
+        }
+
+        while let Some((xy, distance, chunk_id)) = dependencies.pop() {
+            if chunk_id == invalid_chunk_node_index() {
+                continue;
+            }
+            for near in xy.around() {
+                if self.xy_chunks.get(near) == chunk_id && self.center_distances.get(near) == distance + 1 {
+                    dependencies.push((near, distance + 1, chunk_id));
+                    self.assign(near, UNASSIGNED_DISTANCE, invalid_chunk_node_index(), &mut changed_chunks);
+                    seeds.push(near);
+                }
+            }
+        }
+
+        // Assigning the unassigned tiles again from their neighbors. This also propagates shorter
+        // distances through the tiles that are no longer obstacles.
+        let mut queue = Vec::new();
+        for xy in seeds {
+            if let Some((distance, chunk_id)) = self.closest_chunk_around(xy) {
+                push_to_queue(&mut queue, distance, chunk_id, xy);
+            }
+        }
+        self.flood(queue, &mut changed_chunks);
+        self.update_edges(&changed_chunks);
+    }
+
+    /// The closest chunk among the ones of neighboring tiles and the distance to its center
+    /// through them.
+    fn closest_chunk_around(&self, xy: RoomXY) -> Option<(u8, ChunkId)> {
+        xy.around()
+            .filter(|&near| self.xy_chunks.get(near) != invalid_chunk_node_index())
+            .map(|near| (self.center_distances.get(near).saturating_add(1), self.xy_chunks.get(near)))
+            .filter(|&(distance, _)| distance < UNASSIGNED_DISTANCE)
+            .min()
+    }
+
+    /// Assigns tiles to chunks in the order of the queue of tiles indexed by the distance from
+    /// the chunk center. A tile is assigned if it is closer to the chunk center than to the
+    /// current one, or equally close and the chunk ID is lower.
+    fn flood(&mut self, mut queue: Vec<Vec<(ChunkId, RoomXY)>>, changed_chunks: &mut FxHashSet<ChunkId>) {
+        let mut distance = 0;
+        while distance < queue.len() {
+            let layer = std::mem::take(&mut queue[distance]);
+            for (chunk_id, xy) in layer {
+                let distance = distance as u8;
+                if (distance, chunk_id) >= (self.center_distances.get(xy), self.xy_chunks.get(xy)) {
+                    continue;
+                }
+                self.assign(xy, distance, chunk_id, changed_chunks);
+                if distance + 1 == UNASSIGNED_DISTANCE {
+                    continue;
+                }
+                for near in xy.around() {
+                    if self.obstacles.get(near) != OBSTACLE_COST
+                        && (distance + 1, chunk_id) < (self.center_distances.get(near), self.xy_chunks.get(near))
+                    {
+                        push_to_queue(&mut queue, distance + 1, chunk_id, near);
+                    }
+                }
+            }
+            distance += 1;
+        }
+    }
+
+    fn assign(&mut self, xy: RoomXY, distance: u8, chunk_id: ChunkId, changed_chunks: &mut FxHashSet<ChunkId>) {
+        let previous_chunk_id = self.xy_chunks.get(xy);
+        if previous_chunk_id != chunk_id {
+            if let Some(size) = self.chunk_sizes.get_mut(&previous_chunk_id) {
+                *size -= 1;
+                changed_chunks.insert(previous_chunk_id);
+            }
+            if let Some(size) = self.chunk_sizes.get_mut(&chunk_id) {
+                *size += 1;
+                changed_chunks.insert(chunk_id);
+            }
+            self.xy_chunks.set(xy, chunk_id);
+        }
+        self.center_distances.set(xy, distance);
+    }
+
+    /// Recomputes the edges of given chunks from neighboring tiles in different chunks.
+    fn update_edges(&mut self, chunks: &FxHashSet<ChunkId>) {
+        if chunks.is_empty() {
+            return;
+        }
+        self.graph.retain_edges(|graph, edge| {
+            let (a, b) = graph.edge_endpoints(edge).unwrap();
+            !chunks.contains(&a) && !chunks.contains(&b)
+        });
+        for (xy, chunk_id) in self.xy_chunks.iter() {
+            if chunks.contains(&chunk_id) {
+                for near in xy.around() {
+                    let near_chunk_id = self.xy_chunks.get(near);
+                    if near_chunk_id != invalid_chunk_node_index() && near_chunk_id != chunk_id {
+                        self.graph.update_edge(chunk_id, near_chunk_id, 1);
+                    }
+                }
+            }
+        }
+    }
+
     /// Returns vector with all chunks containing an exit tile.
     pub fn exit_chunks(&self) -> FxHashSet<ChunkId> {
         let mut result = FxHashSet::default();
@@ -193,71 +412,26 @@ pub fn chunk_graph(terrain: &RoomMatrix<u8>, chunk_radius: u8) -> ChunkGraph {
 
     let minimum_chunk_size = min_chunk_size(chunk_radius);
 
-    // We remove all chunks that are smaller than min_chunk_size and save all remaining chunks'
-    // centers.
-    let mut layer = Vec::new();
-    chunk_sizes.retain(|&chunk_id, &mut size| {
-        if size >= minimum_chunk_size {
-            layer.push((*graph.node_weight(chunk_id).unwrap(), chunk_id));
-            true
-        } else {
+    // We remove all chunks that are smaller than min_chunk_size.
+    for (chunk_id, size) in chunk_sizes {
+        if size < minimum_chunk_size {
             let removal = graph.remove_node(chunk_id);
             debug_assert!(removal.is_some());
-            false
-        }
-    });
-
-    // We remove nodes from the graph too. We do not update xy_chunks, as this will be taken care
-    // of in the next step.
-    for chunk_id in graph.node_indices() {
-        if !chunk_sizes.contains_key(&chunk_id) {
-            chunk_sizes.remove(&chunk_id);
         }
     }
 
     // We have decided on chunk centers. But there are unassigned tiles and potentially tiles
     // disconnected from the rest of chunks now. We solve this by running a BFS from chunk centers.
-    xy_chunks = RoomMatrix::new(invalid_chunk_node_index());
-    for (xy, chunk_id) in layer.iter().copied() {
-        xy_chunks.set(xy, chunk_id);
-        *chunk_sizes.get_mut(&chunk_id).unwrap() = 1;
-    }
-
-    // Performing a BFS from chunk centers to remove any tiles that are disconnected from them.
     // Note that this will not remove the center tile, so none of the chunks will become empty.
-    let mut k = 0;
-    while !layer.is_empty() {
-        let mut next_layer = Vec::new();
-
-        // Reversing the layer every second iteration so that no single direction will be prioritized.
-        if k % 2 == 1 {
-            layer.reverse();
-        }
-
-        for (xy, chunk_id) in layer.into_iter() {
-            for near in xy.around() {
-                if terrain.get(near) != OBSTACLE_COST {
-                    let near_chunk_id = xy_chunks.get(near);
-                    if near_chunk_id == invalid_chunk_node_index() {
-                        next_layer.push((near, chunk_id));
-                        xy_chunks.set(near, chunk_id);
-                        *chunk_sizes.get_mut(&chunk_id).unwrap() += 1;
-                    } else if near_chunk_id != chunk_id {
-                        graph.update_edge(chunk_id, near_chunk_id, 1);
-                    }
-                }
-            }
-        }
-
-        layer = next_layer;
-        k += 1;
-    }
+    ChunkGraph::flood_from_centers(terrain.clone(), graph, chunk_radius)
+}
 
-    ChunkGraph {
-        xy_chunks,
-        chunk_sizes,
-        graph,
+fn push_to_queue(queue: &mut Vec<Vec<(ChunkId, RoomXY)>>, distance: u8, chunk_id: ChunkId, xy: RoomXY) {
+    let distance = distance as usize;
+    if queue.len() <= distance {
+        queue.resize_with(distance + 1, Vec::new);
     }
+    queue[distance].push((chunk_id, xy));
 }
 
 #[inline]
@@ -267,12 +441,18 @@ pub fn min_chunk_size(chunk_radius: u8) -> u16 {
 
 #[cfg(test)]
 mod tests {
-    use crate::algorithms::chunk_graph::chunk_graph;
+    use crate::algorithms::chunk_graph::{chunk_graph, ChunkGraph};
     use crate::algorithms::matrix_common::MatrixCommon;
     use crate::algorithms::room_matrix::RoomMatrix;
     use crate::consts::OBSTACLE_COST;
-    use crate::geometry::rect::{room_rect, Rect};
-    use more_asserts::{assert_ge, assert_le};
+    use crate::geometry::rect::{ball, room_rect, Rect};
+    use crate::global_state::binary_format::{from_bytes, to_bytes};
+    use crate::utils::random::{RandomSource, SeededRandom};
+    use more_asserts::{assert_ge, assert_le, assert_lt};
+    use crate::geometry::room_xy::RoomXYUtils;
+    use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+    use screeps::{RoomXY, ROOM_SIZE};
+    use std::collections::{BTreeMap, BTreeSet};
     use std::error::Error;
 
     #[test]
@@ -316,4 +496,100 @@ mod tests {
         assert_ge!(chunks.graph.node_count(), 10);
         Ok(())
     }
+
+    fn random_xy(rng: &SeededRandom) -> RoomXY {
+        let random_coord = || 1 + (rng.random() * (ROOM_SIZE - 2) as f64) as u8;
+        (random_coord(), random_coord()).try_into().unwrap()
+    }
+
+    /// A room with walls in random blobs.
+    fn generated_terrain(rng: &SeededRandom) -> RoomMatrix<u8> {
+        let mut terrain = RoomMatrix::new(0);
+        for _ in 0..30 {
+            for xy in ball(random_xy(rng), (rng.random() * 4.0) as u8).iter() {
+                terrain.set(xy, OBSTACLE_COST);
+            }
+        }
+        terrain
+    }
+
+    /// Chunks of tiles, their distances from chunk centers, chunk sizes, edges and enclosures with chunks identified
+    /// by their centers.
+    #[allow(clippy::type_complexity)]
+    fn by_centers(
+        chunks: &ChunkGraph,
+    ) -> (
+        Vec<Option<RoomXY>>,
+        Vec<u8>,
+        BTreeMap<RoomXY, u16>,
+        BTreeSet<(RoomXY, RoomXY)>,
+        BTreeMap<RoomXY, (RoomXY, bool)>,
+    ) {
+        let center = |chunk_id| chunks.graph[chunk_id];
+        (
+            chunks.xy_chunks.iter().map(|(_, chunk_id)| chunks.graph.node_weight(chunk_id).copied()).collect(),
+            chunks.center_distances.data.to_vec(),
+            chunks.chunk_sizes.iter().map(|(&chunk_id, &size)| (center(chunk_id), size)).collect(),
+            (&chunks.graph)
+                .edge_references()
+                .map(|edge| {
+                    let (a, b) = (center(edge.source()), center(edge.target()));
+                    (a.min(b), a.max(b))
+                })
+                .collect(),
+            chunks
+                .enclosures()
+                .into_iter()
+                .map(|(chunk_id, (chokepoint, is_chokepoint))| (center(chunk_id), (center(chokepoint), is_chokepoint)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_incremental_update_equal_to_rebuild() {
+        for seed in 0..5 {
+            let rng = SeededRandom::new(seed);
+            let mut obstacles = generated_terrain(&rng);
+            let mut chunks = chunk_graph(&obstacles, 5);
+            let initial_chunks_count = chunks.graph.node_count();
+
+            for i in 0..30 {
+                // Alternating between toggling single tiles and placing or removing whole blocks.
+                let changed_tiles = if i % 2 == 0 {
+                    (0..1 + (rng.random() * 10.0) as usize)
+                        .map(|_| random_xy(&rng))
+                        .map(|xy| (xy, obstacles.get(xy) != OBSTACLE_COST))
+                        .collect::<Vec<_>>()
+                } else {
+                    let obstacle = rng.random() < 0.5;
+                    ball(random_xy(&rng), 1 + (rng.random() * 2.0) as u8)
+                        .iter()
+                        .filter(|xy| !xy.is_on_boundary())
+                        .map(|xy| (xy, obstacle))
+                        .collect::<Vec<_>>()
+                };
+                for &(xy, obstacle) in changed_tiles.iter() {
+                    obstacles.set(xy, if obstacle { OBSTACLE_COST } else { 0 });
+                }
+
+                chunks.update(&changed_tiles);
+
+                let rebuilt_chunks = ChunkGraph::from_centers(&obstacles, chunks.graph.node_weights().copied(), 5);
+                assert_eq!(chunks.obstacles().data, obstacles.data);
+                assert!(by_centers(&chunks) == by_centers(&rebuilt_chunks), "seed {}, step {}", seed, i);
+            }
+
+            // Some centers should have been covered by obstacles.
+            assert_lt!(chunks.graph.node_count(), initial_chunks_count);
+        }
+    }
+
+    #[test]
+    fn test_chunk_graph_serialization() {
+        let rng = SeededRandom::new(1);
+        let chunks = chunk_graph(&generated_terrain(&rng), 5);
+        let deserialized_chunks: ChunkGraph = from_bytes(&to_bytes(&chunks).unwrap()).unwrap();
+        assert_eq!(deserialized_chunks.chunk_radius, 5);
+        assert!(by_centers(&deserialized_chunks) == by_centers(&chunks));
+    }
 }
@@ -47,6 +47,118 @@ where
     result
 }
 
+/// Same as `distance_matrix`, but stops expanding beyond `max_distance`. Tiles further away than that are
+/// `UNREACHABLE_COST`.
+pub fn bounded_distance_matrix<O, S>(obstacles: O, sources: S, max_distance: u8) -> RoomMatrix<u8>
+where
+    O: Iterator<Item = RoomXY>,
+    S: Iterator<Item = RoomXY>,
+{
+    let mut result = RoomMatrix::new(UNREACHABLE_COST);
+
+    for xy in obstacles {
+        result.set(xy, OBSTACLE_COST);
+    }
+
+    let mut layer = Vec::new();
+
+    for xy in sources {
+        result.set(xy, 0);
+        layer.push(xy);
+    }
+
+    let mut distance = 1u8;
+
+    while !layer.is_empty() && distance <= max_distance {
+        let mut next_layer = Vec::new();
+        for xy in layer {
+            for near in xy.around() {
+                if result.get(near) == UNREACHABLE_COST {
+                    result.set(near, distance);
+                    next_layer.push(near);
+                }
+            }
+        }
+        layer = next_layer;
+        distance = min(UNREACHABLE_COST - 1, distance + 1);
+    }
+
+    result
+}
+
+/// Same as `distance_matrix`, but stops expanding once all targets have their distance set. Tiles that were not
+/// reached by then are `UNREACHABLE_COST`. Also returns the reached targets in the order of reaching them.
+/// Targets that are obstacles are never reached.
+pub fn distance_until<O, S, T>(obstacles: O, sources: S, targets: T) -> (RoomMatrix<u8>, Vec<RoomXY>)
+where
+    O: Iterator<Item = RoomXY>,
+    S: Iterator<Item = RoomXY>,
+    T: Iterator<Item = RoomXY>,
+{
+    let mut result = RoomMatrix::new(UNREACHABLE_COST);
+
+    for xy in obstacles {
+        result.set(xy, OBSTACLE_COST);
+    }
+
+    let mut is_target = RoomMatrix::new(false);
+    let mut targets_left = 0usize;
+    for xy in targets {
+        if result.get(xy) != OBSTACLE_COST && !is_target.get(xy) {
+            is_target.set(xy, true);
+            targets_left += 1;
+        }
+    }
+
+    let mut reached = Vec::new();
+    let mut layer = Vec::new();
+
+    for xy in sources {
+        if is_target.get(xy) && result.get(xy) != 0 {
+            reached.push(xy);
+            targets_left -= 1;
+        }
+        result.set(xy, 0);
+        layer.push(xy);
+    }
+
+    let mut distance = 1u8;
+
+    'main_loop: while !layer.is_empty() && targets_left > 0 {
+        let mut next_layer = Vec::new();
+        for xy in layer {
+            for near in xy.around() {
+                if result.get(near) == UNREACHABLE_COST {
+                    result.set(near, distance);
+                    next_layer.push(near);
+                    if is_target.get(near) {
+                        reached.push(near);
+                        targets_left -= 1;
+                        if targets_left == 0 {
+                            break 'main_loop;
+                        }
+                    }
+                }
+            }
+        }
+        layer = next_layer;
+        distance = min(UNREACHABLE_COST - 1, distance + 1);
+    }
+
+    (result, reached)
+}
+
+/// Distances from the sources to each of the targets, in the same order, with the search stopping as soon as all
+/// targets are reached. `UNREACHABLE_COST` for targets that cannot be reached and `OBSTACLE_COST` for obstacles.
+pub fn distance_to_targets<O, S>(obstacles: O, sources: S, targets: &[RoomXY]) -> Vec<u8>
+where
+    O: Iterator<Item = RoomXY>,
+    S: Iterator<Item = RoomXY>,
+{
+    let (dm, _) = distance_until(obstacles, sources, targets.iter().copied());
+    targets.iter().map(|&xy| dm.get(xy)).collect()
+}
+
 pub fn rect_restricted_distance_matrix<O, T>(
     obstacles: O,
     target: T,
@@ -192,15 +304,18 @@ where
 #[cfg(test)]
 mod tests {
     use crate::algorithms::distance_matrix::{
-        distance_matrix, targeted_distance_matrix, rect_restricted_distance_matrix,
+        bounded_distance_matrix, distance_matrix, distance_to_targets, distance_until, targeted_distance_matrix,
+        rect_restricted_distance_matrix,
     };
     use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
     use crate::consts::{OBSTACLE_COST, ROOM_AREA, UNREACHABLE_COST};
     use crate::geometry::rect::Rect;
+    use crate::geometry::room_xy::RoomXYUtils;
     use more_asserts::assert_ge;
     use screeps::RoomXY;
     use std::error::Error;
-    use std::iter::once;
+    use std::iter::{empty, once};
 
     #[test]
     fn test_restricted_grid_bfs_distances() -> Result<(), Box<dyn Error>> {
@@ -329,4 +444,101 @@ mod tests {
         assert_eq!(dm.get((24, 25).try_into().unwrap()), OBSTACLE_COST);
         assert_eq!(dm.get((25, 19).try_into().unwrap()), UNREACHABLE_COST);
     }
+
+    fn test_obstacles() -> Vec<RoomXY> {
+        (10..40)
+            .map(|y| (25, y).try_into().unwrap())
+            .chain((20..30).map(|x| (x, 10).try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_bounded_distance_matrix_equal_to_full_within_bound() {
+        let obstacles = test_obstacles();
+        let source: RoomXY = (24, 20).try_into().unwrap();
+        let full_dm = distance_matrix(obstacles.iter().copied(), once(source));
+        let bounded_dm = bounded_distance_matrix(obstacles.iter().copied(), once(source), 5);
+
+        for (xy, dist) in full_dm.iter() {
+            if dist <= 5 || dist == OBSTACLE_COST {
+                assert_eq!(bounded_dm.get(xy), dist);
+            } else {
+                assert_eq!(bounded_dm.get(xy), UNREACHABLE_COST);
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_until_stops_after_reaching_targets() {
+        let obstacles = test_obstacles();
+        let source: RoomXY = (24, 20).try_into().unwrap();
+        let near_target: RoomXY = (21, 20).try_into().unwrap();
+        let far_target: RoomXY = (24, 25).try_into().unwrap();
+        let obstacle_target: RoomXY = (25, 20).try_into().unwrap();
+        let full_dm = distance_matrix(obstacles.iter().copied(), once(source));
+        let (dm, reached) = distance_until(
+            obstacles.iter().copied(),
+            once(source),
+            [far_target, near_target, obstacle_target].into_iter(),
+        );
+
+        assert_eq!(reached, vec![near_target, far_target]);
+        assert_eq!(dm.get(far_target), 5);
+        assert_eq!(dm.get(obstacle_target), OBSTACLE_COST);
+        for (xy, dist) in full_dm.iter() {
+            if dist < 5 || dist == OBSTACLE_COST {
+                assert_eq!(dm.get(xy), dist);
+            } else if dist > 5 {
+                assert_eq!(dm.get(xy), UNREACHABLE_COST);
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_until_unreachable_target() {
+        let walled_in: RoomXY = (10, 10).try_into().unwrap();
+        let obstacles = walled_in.around().collect::<Vec<_>>();
+        let source: RoomXY = (30, 30).try_into().unwrap();
+        let full_dm = distance_matrix(obstacles.iter().copied(), once(source));
+        let (dm, reached) = distance_until(obstacles.iter().copied(), once(source), once(walled_in));
+
+        assert!(reached.is_empty());
+        for (xy, dist) in full_dm.iter() {
+            assert_eq!(dm.get(xy), dist);
+        }
+    }
+
+    #[test]
+    fn test_bounded_distance_matrix_settles_fraction_of_tiles() {
+        let source: RoomXY = (24, 24).try_into().unwrap();
+        let settled = |dm: &RoomMatrix<u8>| {
+            dm.iter().filter(|&(_, dist)| dist < UNREACHABLE_COST).count()
+        };
+        let full_dm = distance_matrix(empty(), once(source));
+        let bounded_dm = bounded_distance_matrix(empty(), once(source), 6);
+
+        assert_eq!(settled(&full_dm), ROOM_AREA);
+        // Only the 13x13 square around the source.
+        assert_eq!(settled(&bounded_dm), 13 * 13);
+    }
+
+    #[test]
+    fn test_distance_to_targets() {
+        let obstacles = test_obstacles();
+        let source: RoomXY = (24, 20).try_into().unwrap();
+        let walled_in: RoomXY = (10, 10).try_into().unwrap();
+        let obstacles = obstacles.into_iter().chain(walled_in.around()).collect::<Vec<_>>();
+        let targets: [RoomXY; 4] = [
+            (24, 25).try_into().unwrap(),
+            (21, 20).try_into().unwrap(),
+            (25, 20).try_into().unwrap(),
+            walled_in,
+        ];
+        let full_dm = distance_matrix(obstacles.iter().copied(), once(source));
+
+        let distances = distance_to_targets(obstacles.iter().copied(), once(source), &targets);
+        assert_eq!(distances, targets.iter().map(|&xy| full_dm.get(xy)).collect::<Vec<_>>());
+        assert_eq!(distances[2], OBSTACLE_COST);
+        assert_eq!(distances[3], UNREACHABLE_COST);
+    }
 }
@@ -0,0 +1,180 @@
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::geometry::room_xy::RoomXYUtils;
+use screeps::RoomXY;
+use std::iter::once;
+
+/// Region of obstacles in the result of `label_regions`.
+pub const NO_REGION: u16 = u16::MAX;
+
+/// Visits all tiles reachable from the start tiles through passable tiles, moving in all eight
+/// directions, and returns a matrix of visited tiles. The start tiles are always visited, regardless
+/// of being passable. Each tile is visited once, in the order of the distance from the start.
+pub fn flood_fill<S, P, V>(start: S, passable: P, mut visit: V) -> RoomMatrix<bool>
+where
+    S: IntoIterator<Item = RoomXY>,
+    P: Fn(RoomXY) -> bool,
+    V: FnMut(RoomXY),
+{
+    let mut visited = RoomMatrix::new(false);
+
+    let mut layer = Vec::new();
+    for xy in start {
+        if !visited.get(xy) {
+            visited.set(xy, true);
+            visit(xy);
+            layer.push(xy);
+        }
+    }
+
+    while !layer.is_empty() {
+        let mut next_layer = Vec::new();
+        for xy in layer {
+            for near in xy.around() {
+                if !visited.get(near) && passable(near) {
+                    visited.set(near, true);
+                    visit(near);
+                    next_layer.push(near);
+                }
+            }
+        }
+        layer = next_layer;
+    }
+
+    visited
+}
+
+/// Assigns consecutive region ids to connected components of tiles that are not obstacles, ordered
+/// by their first tile in the order of `RoomMatrix::iter`. Obstacles are `NO_REGION`. Returns the
+/// matrix of region ids and the sizes of the regions indexed by their ids.
+pub fn label_regions<O>(obstacles: O) -> (RoomMatrix<u16>, Vec<u16>)
+where
+    O: Iterator<Item = RoomXY>,
+{
+    let mut is_obstacle = RoomMatrix::new(false);
+    for xy in obstacles {
+        is_obstacle.set(xy, true);
+    }
+
+    let mut regions = RoomMatrix::new(NO_REGION);
+    let mut sizes = Vec::new();
+    for (xy, obstacle) in is_obstacle.iter() {
+        if !obstacle && regions.get(xy) == NO_REGION {
+            let region = sizes.len() as u16;
+            let mut size = 0;
+            flood_fill(once(xy), |near| !is_obstacle.get(near), |visited_xy| {
+                regions.set(visited_xy, region);
+                size += 1;
+            });
+            sizes.push(size);
+        }
+    }
+
+    (regions, sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::flood_fill::{flood_fill, label_regions, NO_REGION};
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::geometry::room_xy::RoomXYUtils;
+    use crate::utils::random::{RandomSource, SeededRandom};
+    use rustc_hash::FxHashMap;
+    use screeps::ROOM_SIZE;
+    use std::iter::once;
+    use crate::utils::test_utils::xy;
+
+    /// Sizes of the regions, sorted, computed using union-find.
+    fn reference_region_sizes(is_obstacle: &RoomMatrix<bool>) -> Vec<u16> {
+        fn find(parents: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parents[root] != root {
+                root = parents[root];
+            }
+            parents[i] = root;
+            root
+        }
+
+        let mut parents = (0..(ROOM_SIZE as usize * ROOM_SIZE as usize)).collect::<Vec<_>>();
+        for (xy, obstacle) in is_obstacle.iter() {
+            if !obstacle {
+                for near in xy.around() {
+                    if !is_obstacle.get(near) {
+                        let root = find(&mut parents, xy.to_index());
+                        let near_root = find(&mut parents, near.to_index());
+                        parents[root] = near_root;
+                    }
+                }
+            }
+        }
+
+        let mut sizes = FxHashMap::default();
+        for (xy, obstacle) in is_obstacle.iter() {
+            if !obstacle {
+                *sizes.entry(find(&mut parents, xy.to_index())).or_insert(0u16) += 1;
+            }
+        }
+        let mut sizes = sizes.into_values().collect::<Vec<_>>();
+        sizes.sort();
+        sizes
+    }
+
+    #[test]
+    fn test_flood_fill_visits_reachable_tiles_once() {
+        let mut walls = RoomMatrix::new(false);
+        for y in 0..ROOM_SIZE {
+            walls.set(xy(10, y), true);
+        }
+
+        let mut visit_count = RoomMatrix::new(0u8);
+        let visited = flood_fill([xy(1, 1), xy(1, 1)], |xy| !walls.get(xy), |xy| {
+            visit_count.set(xy, visit_count.get(xy) + 1)
+        });
+        assert!(visit_count.iter().all(|(_, count)| count <= 1));
+        assert_eq!(visited.find_xy(true).count(), 10 * ROOM_SIZE as usize);
+        assert!(!visited.get(xy(11, 5)));
+
+        // The start tile is visited even when it is not passable.
+        let visited = flood_fill([xy(10, 5)], |xy| !walls.get(xy), |_| {});
+        assert_eq!(visited.find_xy(true).count(), 49 * ROOM_SIZE as usize + 1);
+        assert!(!visited.get(xy(10, 6)));
+    }
+
+    #[test]
+    fn test_label_regions() {
+        let walls = (0..ROOM_SIZE).map(|y| xy(10, y)).chain((0..10).map(|x| xy(x, 20)));
+        let (regions, sizes) = label_regions(walls);
+        assert_eq!(sizes, vec![200, 39 * 50, 290]);
+        assert_eq!(regions.get(xy(0, 0)), 0);
+        assert_eq!(regions.get(xy(11, 0)), 1);
+        assert_eq!(regions.get(xy(0, 21)), 2);
+        assert_eq!(regions.get(xy(10, 0)), NO_REGION);
+        assert_eq!(regions.get(xy(0, 20)), NO_REGION);
+
+        let (regions, sizes) = label_regions(once(xy(0, 0)));
+        assert_eq!(sizes, vec![2499]);
+        assert_eq!(regions.get(xy(0, 0)), NO_REGION);
+    }
+
+    #[test]
+    fn test_label_regions_matches_union_find_on_random_terrain() {
+        let rng = SeededRandom::new(1);
+        for wall_chance in [0.1, 0.3, 0.45, 0.55, 0.7] {
+            for _ in 0..10 {
+                let is_obstacle = RoomMatrix::new(false).map(|_, _| rng.random() < wall_chance);
+                let (regions, sizes) = label_regions(is_obstacle.find_xy(true));
+
+                let mut sorted_sizes = sizes.clone();
+                sorted_sizes.sort();
+                assert_eq!(sorted_sizes, reference_region_sizes(&is_obstacle));
+                for (region, &size) in sizes.iter().enumerate() {
+                    assert_eq!(regions.find_xy(region as u16).count(), size as usize);
+                }
+                assert!(regions
+                    .iter()
+                    .all(|(xy, region)| (region == NO_REGION) == is_obstacle.get(xy)));
+            }
+        }
+    }
+}
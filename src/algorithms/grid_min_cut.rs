@@ -1,12 +1,11 @@
 use crate::algorithms::matrix_common::MatrixCommon;
 use crate::algorithms::room_matrix::RoomMatrix;
-use crate::consts::OBSTACLE_COST;
+use crate::consts::{OBSTACLE_COST, ROOM_AREA};
 use crate::geometry::room_xy::RoomXYUtils;
 use screeps::{RoomXY, ROOM_SIZE};
 use std::fmt::{Display, Formatter};
 use crate::geometry::grid_direction::GridDirection::*;
 use crate::algorithms::grid_min_cut::TileVertexKind::*;
-use enum_iterator::all;
 use crate::geometry::grid_direction;
 use crate::geometry::grid_direction::GridDirection;
 
@@ -20,258 +19,114 @@ const DEBUG: bool = false;
 /// edge from the input to the output with cost equal to the tile's cost. Outputs of tiles
 /// are connected to all surrounding tiles' inputs with an edge of infinite cost.
 ///
+/// In each phase, the levels of the nodes are their exact distances to the exits, computed by
+/// a BFS from the exits (a global relabel), so that all nodes in the level graph lead to an exit.
+/// Then the blocking flow is found using a DFS with current edges. Nodes that turn out to be dead
+/// ends are removed from the level graph and when no nodes are left at some level (a gap), the
+/// phase ends early as there are no more paths to the exits in it.
+///
 /// The costs matrix represents costs for tiles, 0 for starting tiles or OBSTACLE_COST for
 /// obstacles.
 pub fn grid_min_cut(costs: &RoomMatrix<u8>) -> Vec<RoomXY> {
-    let mut capacity: [u8; GRID_EDGE_ID_CAPACITY as usize] = [0; GRID_EDGE_ID_CAPACITY as usize];
-    let mut initial_nodes: Vec<GridGraphNode> = Vec::new();
-
-    for y in 1..(ROOM_SIZE - 1) {
-        for x in 1..(ROOM_SIZE - 1) {
-            let xy = (x, y).try_into().unwrap();
-            let raw_tile_cost = costs.get(xy);
-            // No edges in or around obstacles or the start are supposed to have any capacity.
-            // Exits are supposed to have only their input nodes at the tile next to an exit tile
-            // accessible (this is handled later). Note that "next to an exit" refers to travel distance not just
-            // distance from the border.
-            if raw_tile_cost != OBSTACLE_COST && raw_tile_cost != 0 {
-                // No internal edge saturation may happen outside of the result_rect.
-                let tile_cost = if xy.exit_distance() < 2 && xy.around().any(|near| near.exit_distance() == 0 && costs.get(near) != OBSTACLE_COST) {
-                    OBSTACLE_COST
-                } else {
-                    raw_tile_cost
-                };
-                // Initial capacity of input's non-internal edges is 0.
-                // It only has an internal edge with the capacity equal to the tile cost.
-                let input_node = grid_node(x, y, Input);
-                capacity[grid_edge(input_node, Center).usize()] = tile_cost;
-                let output_node = grid_node(x, y, Output);
-                let mut is_near_start = false;
-                for_each_node_around(output_node, |near_node, edge| {
-                    // Initial capacity of output's internal edge is 0.
-                    if !is_internal_edge(edge) {
-                        let near = grid_node_to_xy(near_node);
-                        let near_tile_cost = costs.get(near);
-                        // No capacity to start or obstacle tiles.
-                        // However, capacity to exit tiles is normal.
-                        if near_tile_cost != OBSTACLE_COST && near_tile_cost != 0 {
-                            // Capacity of edges between tiles set to maximum that is higher
-                            // than maximum cost.
-                            capacity[edge.usize()] = OBSTACLE_COST;
-                        } else if near_tile_cost == 0 {
-                            // If the output node is next to a start node then its input is
-                            // one of starting nodes for the flow.
-                            is_near_start = true;
-                        }
-                    }
-                });
-                if is_near_start {
-                    initial_nodes.push(input_node);
-                }
-            }
-        }
-    }
+    let (mut capacity, initial_nodes) = initial_capacity(costs);
 
     if DEBUG {
         eprintln!(
             "Initial nodes: {:?}.",
             initial_nodes.iter().map(|node| format!("{}", *node)).collect::<Vec<String>>().join(", ")
         );
-        eprintln!();
     }
 
-    loop {
-        // Computing BFS distances from the initial flow nodes. The BFS ends when a node on a tile
-        // next to an exit tile is reached. This is sufficient to only traverse the shortest paths.
-        // The BFS only goes through not saturated edges.
-
-        let mut bfs_distances = [OBSTACLE_COST; GRID_NODE_ID_CAPACITY as usize];
-        let mut layer = initial_nodes.clone();
-        let mut distance = 0u8;
-        let mut exit_reached = false;
-
-        while !layer.is_empty() && distance < OBSTACLE_COST - 1 {
-            let mut next_layer = Vec::new();
-
-            for node in layer {
-                bfs_distances[node.usize()] = distance;
-                for_each_node_around(node, |near_node, edge| {
-                    let near = grid_node_to_xy(near_node);
-                    if bfs_distances[near_node.usize()] == OBSTACLE_COST && capacity[edge.usize()] > 0 {
-                        bfs_distances[near_node.usize()] = distance + 1;
-                        if near.exit_distance() == 0 {
-                            exit_reached = true;
-                        } else {
-                            next_layer.push(near_node);
+    let mut graph = LevelGraph::new(&initial_nodes);
+    let mut current_direction = vec![0u8; NODES_COUNT];
+    let mut path: Vec<GridGraphEdge> = Vec::new();
+
+    while let Some(initial_level) = graph.relabel(&capacity) {
+        current_direction.fill(0);
+        let mut gap = false;
+
+        for &initial_node in initial_nodes.iter() {
+            let mut node = initial_node;
+            path.clear();
+
+            while !gap && graph.level[initial_node.usize()] == initial_level {
+                let node_level = graph.level[node.usize()];
+                if node_level == 0 {
+                    // We reached the exit - adding the flow through the edges we have followed and
+                    // going back to before the first saturated edge.
+                    let flow = path.iter().map(|&edge| capacity[edge.usize()]).min().unwrap();
+                    debug_assert!(flow > 0);
+                    let mut saturated_edge_ix = None;
+                    for (i, &edge) in path.iter().enumerate() {
+                        capacity[edge.usize()] -= flow;
+                        capacity[reverse_edge(edge).usize()] += flow;
+                        if saturated_edge_ix.is_none() && capacity[edge.usize()] == 0 {
+                            saturated_edge_ix = Some(i);
                         }
                     }
-                });
-            }
-
-            distance += 1;
-            layer = next_layer;
-        }
+                    let saturated_edge_ix = saturated_edge_ix.unwrap();
+                    node = edge_node(path[saturated_edge_ix]);
+                    path.truncate(saturated_edge_ix);
 
-        if DEBUG {
-            for y in 0..ROOM_SIZE {
-                for x in 0..ROOM_SIZE {
-                    let dist = bfs_distances[grid_node(x, y, Input).usize()];
-                    if dist == OBSTACLE_COST {
-                        eprint!(" X ");
-                    } else {
-                        eprint!("{:2} ", dist);
+                    if DEBUG {
+                        eprintln!("Flow {} to an exit, resuming from {}.", flow, node);
                     }
-                }
-                println!();
-            }
-            println!();
-        }
-
-        if !exit_reached {
-            break;
-        }
-
-        // We start finding blocking flow from the nodes that are next to start tiles.
-        // We have a BFS that will be used to restrict ourselves to only the shortest paths.
-        // We travel only to nodes with strictly smaller BFS distances from exits.
-        // We repeatedly perform DFS with backtracking and removing vertices when it cannot move
-        // towards exits as a result of saturated capacities.
-
-        let mut dfs_stack: Vec<(GridGraphNode, GridGraphEdge)> =
-            initial_nodes.iter().map(|node| (*node, UNKNOWN_EDGE)).collect();
-        let mut path = Vec::new();
-        while !dfs_stack.is_empty() {
-            let node = dfs_stack[dfs_stack.len() - 1].0;
-            path.push(dfs_stack[dfs_stack.len() - 1]);
-            let xy = grid_node_to_xy(node);
-            if xy.exit_distance() == 0 {
-                if DEBUG {
-                    eprintln!(
-                        "Found exit with path {:?}.",
-                        path.iter().map(|(node, _)| format!("{}", *node)).collect::<Vec<String>>().join(", ")
-                    );
+                    continue;
                 }
 
-                // We reached the exit - adding the flow through the edges we have followed.
-                let mut flow = 255;
-                // We skip the first, unknown edge.
-                for i in 1..path.len() {
-                    let travelled_edge = path[i].1;
-                    if capacity[travelled_edge.usize()] < flow {
-                        flow = capacity[travelled_edge.usize()];
+                // Advancing through the current edge of the node or the next one in the level graph.
+                let mut advanced = false;
+                while (current_direction[node.usize()] as usize) < DIRECTIONS_COUNT {
+                    let edge = grid_edge(node, current_direction[node.usize()].into());
+                    let target_node = edge_target_node(edge);
+                    if capacity[edge.usize()] > 0 && graph.level[target_node.usize()] == node_level - 1 {
+                        path.push(edge);
+                        node = target_node;
+                        advanced = true;
+                        break;
                     }
+                    current_direction[node.usize()] += 1;
                 }
-                debug_assert!(flow > 0);
-                let mut still_valid_path_length = 256;
-                for i in 1..path.len() {
-                    let travelled_edge = path[i].1;
-                    capacity[travelled_edge.usize()] -= flow;
-                    capacity[reverse_edge(travelled_edge).usize()] += flow;
-                    if still_valid_path_length == 256 && capacity[travelled_edge.usize()] == 0 {
-                        still_valid_path_length = i;
-                    }
-                }
-                debug_assert!(still_valid_path_length != 256);
-                // We reuse the dfs_stack.
-                while path.len() > still_valid_path_length {
-                    path.pop();
-                    while dfs_stack[dfs_stack.len() - 1].0 != path[path.len() - 1].0 {
-                        dfs_stack.pop();
-                    }
-                }
-                path.pop();
 
-                if DEBUG {
-                    eprintln!("Flow was: {}.", flow);
-                    eprintln!("Still valid path length: {}.", still_valid_path_length);
-                    eprintln!(
-                        "Path after backtracking: {:?}.",
-                        path.iter().map(|(node, _)| format!("{}", *node)).collect::<Vec<String>>().join(", ")
-                    );
-                }
-            } else {
-                let mut dead_end = true;
-                for_each_node_around(node, |near_node, edge| {
-                    if capacity[edge.usize()] > 0 && bfs_distances[near_node.usize()] == (path.len() as u8) {
-                        dead_end = false;
-                        dfs_stack.push((near_node, edge));
-
-                        debug_assert!(grid_node_to_xy(near_node).dist(grid_node_to_xy(node)) <= 1);
-                    }
-                });
-                if dead_end {
-                    if DEBUG {
-                        eprintln!(
-                            "Dead end at {}.",
-                            path.iter().map(|(node, _)| format!("{}", *node)).collect::<Vec<String>>().join(", ")
-                        );
-                        for_each_node_around(node, |near_node, edge| {
-                            eprintln!(
-                                "  Near node {}: cap {}, bfs dist {}, path len {}",
-                                near_node,
-                                capacity[edge.usize()],
-                                bfs_distances[near_node.usize()],
-                                path.len() as u8
-                            );
-                        });
-                    }
-
-                    while !path.is_empty() && dfs_stack.last().unwrap().0 == path.last().unwrap().0 {
-                        // Two same nodes being at the end of path and DFS stack mean that the other
-                        // children were already processed and it is a dead end also.
-                        // Setting the BFS distance of a node to 0 means that no other node may
-                        // traverse to it as a result of the strictly increasing distance rule.
-                        bfs_distances[path.last().unwrap().0.usize()] = 0;
-                        dfs_stack.pop();
-                        path.pop();
-                    }
-
-                    if DEBUG && !dfs_stack.is_empty() {
-                        eprintln!("Resuming from {}.", dfs_stack.last().unwrap().0);
+                if !advanced {
+                    // A dead end. Removing it from the level graph and retreating.
+                    gap = graph.remove(node);
+                    match path.pop() {
+                        Some(edge) => {
+                            node = edge_node(edge);
+                            current_direction[node.usize()] += 1;
+                        }
+                        None => break,
                     }
                 }
             }
-        }
 
-        if DEBUG {
-            for y in 15..37 {
-                for x in 15..49 {
-                    let residual_cap = capacity[grid_node(x, y, Input).usize()];
-                    let cap = unsafe { costs.get_xy(x, y) };
-                    eprint!("{}/{} ", residual_cap, cap);
+            if gap {
+                if DEBUG {
+                    eprintln!("Gap in the level graph.");
                 }
-                eprintln!();
-            }
-            eprintln!();
-        }
-    }
-
-    if DEBUG {
-        for y in 15..37 {
-            for x in 15..49 {
-                let residual_cap = capacity[grid_node(x, y, Input).usize()];
-                let cap = unsafe { costs.get_xy(x, y) };
-                eprint!("{}/{} ", residual_cap, cap);
+                break;
             }
-            eprintln!();
         }
-        eprintln!();
     }
 
     // We get the min-cut tiles by running the BFS from the start and selecting first tiles with
     // saturated internal edges.
 
-    let mut layer = initial_nodes.clone();
-    let mut bfs_visited = [false; GRID_NODE_ID_CAPACITY as usize];
+    let mut bfs_visited = vec![false; NODES_COUNT];
+    let mut layer = initial_nodes;
+    for node in layer.iter() {
+        bfs_visited[node.usize()] = true;
+    }
 
     while !layer.is_empty() {
         let mut next_layer = Vec::new();
 
         for node in layer {
-            bfs_visited[node.usize()] = true;
-            // eprintln!("Visiting xy={} output={}.", grid_node_id_to_xy(node), node & 1);
+            if node.xy().exit_distance() == 0 {
+                continue;
+            }
             for_each_node_around(node, |near_node, edge| {
-                // eprintln!("Near xy={} output={} with capacity={} visited={}.", grid_node_id_to_xy(near_node), near_node & 1, capacity[edge as usize], bfs_visited[near_node.usize()]);
                 if !bfs_visited[near_node.usize()] && capacity[edge.usize()] > 0 {
                     next_layer.push(near_node);
                     bfs_visited[near_node.usize()] = true;
@@ -285,48 +140,27 @@ pub fn grid_min_cut(costs: &RoomMatrix<u8>) -> Vec<RoomXY> {
     let mut result = Vec::new();
     for y in 1..(ROOM_SIZE - 1) {
         for x in 1..(ROOM_SIZE - 1) {
-            let input_node = grid_node(x, y, Input);
-            let output_node = grid_node(x, y, Output);
-            if bfs_visited[input_node.usize()] && !bfs_visited[output_node.usize()] {
-                result.push(grid_node_to_xy(input_node));
+            let xy = unsafe { RoomXY::unchecked_new(x, y) };
+            if bfs_visited[GridGraphNode::new(xy, Input).usize()] && !bfs_visited[GridGraphNode::new(xy, Output).usize()] {
+                result.push(xy);
             }
         }
     }
 
-    // for y in 0..ROOM_SIZE {
-    //     let mut line = "".to_string();
-    //     for x in 0..ROOM_SIZE {
-    //         let cost = unsafe { costs.get_xy(x, y) };
-    //         if cost == OBSTACLE_COST {
-    //             line += " # ";
-    //         } else if cost == 0 {
-    //             line += " S ";
-    //         } else {
-    //             let input_node = grid_node(x, y, Input);
-    //             let output_node = grid_node(x, y, Output);
-    //             line += ["F", "T"][bfs_visited[input_node.usize()] as usize];
-    //             line += ["F", "T"][bfs_visited[output_node.usize()] as usize];
-    //             line += " ";
-    //         }
-    //     }
-    //     debug!("{}", line);
-    // }
-
     if DEBUG {
         for y in 0..ROOM_SIZE {
             for x in 0..ROOM_SIZE {
-                let cost = unsafe { costs.get_xy(x, y) };
+                let xy = unsafe { RoomXY::unchecked_new(x, y) };
+                let cost = costs.get(xy);
                 if cost == OBSTACLE_COST {
                     eprint!(" # ");
                 } else if cost == 0 {
                     eprint!(" S ");
                 } else {
-                    let input_node = grid_node(x, y, Input);
-                    let output_node = grid_node(x, y, Output);
                     eprint!(
                         "{}{} ",
-                        ["F", "T"][bfs_visited[input_node.usize()] as usize],
-                        ["F", "T"][bfs_visited[output_node.usize()] as usize]
+                        ["F", "T"][bfs_visited[GridGraphNode::new(xy, Input).usize()] as usize],
+                        ["F", "T"][bfs_visited[GridGraphNode::new(xy, Output).usize()] as usize]
                     );
                 }
             }
@@ -337,6 +171,181 @@ pub fn grid_min_cut(costs: &RoomMatrix<u8>) -> Vec<RoomXY> {
     result
 }
 
+/// Initial capacity of the edges of the graph and the nodes the flow starts from.
+fn initial_capacity(costs: &RoomMatrix<u8>) -> (Vec<u8>, Vec<GridGraphNode>) {
+    let mut capacity = vec![0u8; EDGES_COUNT];
+    let mut initial_nodes = Vec::new();
+
+    for y in 1..(ROOM_SIZE - 1) {
+        for x in 1..(ROOM_SIZE - 1) {
+            let xy = unsafe { RoomXY::unchecked_new(x, y) };
+            let raw_tile_cost = costs.get(xy);
+            // No edges in or around obstacles or the start are supposed to have any capacity.
+            // Exits are supposed to have only their input nodes at the tile next to an exit tile
+            // accessible (this is handled later). Note that "next to an exit" refers to travel distance not just
+            // distance from the border.
+            if raw_tile_cost != OBSTACLE_COST && raw_tile_cost != 0 {
+                // No internal edge saturation may happen outside of the result_rect.
+                let tile_cost = if xy.exit_distance() < 2 && xy.around().any(|near| near.exit_distance() == 0 && costs.get(near) != OBSTACLE_COST) {
+                    OBSTACLE_COST
+                } else {
+                    raw_tile_cost
+                };
+                // Initial capacity of input's non-internal edges is 0.
+                // It only has an internal edge with the capacity equal to the tile cost.
+                let input_node = GridGraphNode::new(xy, Input);
+                capacity[grid_edge(input_node, Center).usize()] = tile_cost;
+                let output_node = GridGraphNode::new(xy, Output);
+                let mut is_near_start = false;
+                for_each_node_around(output_node, |near_node, edge| {
+                    // Initial capacity of output's internal edge is 0.
+                    if !is_internal_edge(edge) {
+                        let near_tile_cost = costs.get(near_node.xy());
+                        // No capacity to start or obstacle tiles.
+                        // However, capacity to exit tiles is normal.
+                        if near_tile_cost != OBSTACLE_COST && near_tile_cost != 0 {
+                            // Capacity of edges between tiles set to maximum that is higher
+                            // than maximum cost.
+                            capacity[edge.usize()] = OBSTACLE_COST;
+                        } else if near_tile_cost == 0 {
+                            // If the output node is next to a start node then its input is
+                            // one of starting nodes for the flow.
+                            is_near_start = true;
+                        }
+                    }
+                });
+                if is_near_start {
+                    initial_nodes.push(input_node);
+                }
+            }
+        }
+    }
+
+    (capacity, initial_nodes)
+}
+
+/// Levels of nodes in the level graph, i.e., their distances to the exits through not saturated
+/// edges, along with the number of nodes at each level.
+struct LevelGraph {
+    level: Vec<u16>,
+    level_counts: Vec<u16>,
+    is_initial: Vec<bool>,
+    /// Input nodes of exit tiles.
+    exit_nodes: Vec<GridGraphNode>,
+    /// Nodes of tiles next to the room border, the only ones that may have edges to the exits.
+    exit_adjacent_nodes: Vec<GridGraphNode>,
+}
+
+impl LevelGraph {
+    fn new(initial_nodes: &[GridGraphNode]) -> Self {
+        let mut is_initial = vec![false; NODES_COUNT];
+        for node in initial_nodes.iter() {
+            is_initial[node.usize()] = true;
+        }
+
+        let mut exit_nodes = Vec::new();
+        let mut exit_adjacent_nodes = Vec::new();
+        for y in 0..ROOM_SIZE {
+            for x in 0..ROOM_SIZE {
+                let xy = unsafe { RoomXY::unchecked_new(x, y) };
+                match xy.exit_distance() {
+                    0 => exit_nodes.push(GridGraphNode::new(xy, Input)),
+                    1 => {
+                        exit_adjacent_nodes.push(GridGraphNode::new(xy, Input));
+                        exit_adjacent_nodes.push(GridGraphNode::new(xy, Output));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        LevelGraph {
+            level: vec![NO_LEVEL; NODES_COUNT],
+            level_counts: Vec::new(),
+            is_initial,
+            exit_nodes,
+            exit_adjacent_nodes,
+        }
+    }
+
+    /// Computes the levels of nodes with a BFS from the exits through reversed not saturated
+    /// edges, up to the level of the closest initial node. Returns that level or `None` if no
+    /// initial node can reach the exits.
+    fn relabel(&mut self, capacity: &[u8]) -> Option<u16> {
+        self.level.fill(NO_LEVEL);
+        self.level_counts.clear();
+
+        // Exit nodes are not counted since they are never removed from the level graph.
+        for node in self.exit_nodes.iter() {
+            self.level[node.usize()] = 0;
+        }
+        self.level_counts.push(0);
+
+        // Nodes around the exit tiles are handled separately, since the grid does not extend
+        // beyond the room border.
+        let mut layer = Vec::new();
+        for &node in self.exit_adjacent_nodes.iter() {
+            let mut leads_to_exit = false;
+            for_each_node_around(node, |near_node, edge| {
+                leads_to_exit |= capacity[edge.usize()] > 0 && self.level[near_node.usize()] == 0;
+            });
+            if leads_to_exit {
+                self.level[node.usize()] = 1;
+                layer.push(node);
+            }
+        }
+
+        let mut level = 1;
+        while !layer.is_empty() {
+            self.level_counts.push(layer.len() as u16);
+            if layer.iter().any(|node| self.is_initial[node.usize()]) {
+                if DEBUG {
+                    eprintln!("Level graph with initial nodes at level {}: {:?}.", level, self.level_counts);
+                }
+                return Some(level);
+            }
+
+            let mut next_layer = Vec::new();
+            for node in layer {
+                for (direction, reverse_direction) in REVERSE_DIRECTIONS.into_iter().enumerate() {
+                    let near_node = near_node(node, (direction as u8).into());
+                    if self.level[near_node.usize()] == NO_LEVEL
+                        && capacity[grid_edge(near_node, reverse_direction).usize()] > 0
+                    {
+                        self.level[near_node.usize()] = level + 1;
+                        next_layer.push(near_node);
+                    }
+                }
+            }
+
+            layer = next_layer;
+            level += 1;
+        }
+
+        None
+    }
+
+    /// Removes a dead end from the level graph. Returns whether its level became empty.
+    fn remove(&mut self, node: GridGraphNode) -> bool {
+        let level = self.level[node.usize()] as usize;
+        self.level[node.usize()] = NO_LEVEL;
+        self.level_counts[level] -= 1;
+        self.level_counts[level] == 0
+    }
+}
+
+/// Level of nodes outside of the level graph.
+const NO_LEVEL: u16 = u16::MAX;
+
+const DIRECTIONS_COUNT: usize = 9;
+
+/// Grid node IDs are twice the index of the tile, plus 1 for the output node.
+const NODES_COUNT: usize = 2 * ROOM_AREA;
+
+/// Edge IDs are grid node IDs times the number of directions plus the direction.
+/// The maximum value is 5000 * 9 - 1 = 44999, which fits in u16.
+const EDGES_COUNT: usize = NODES_COUNT * DIRECTIONS_COUNT;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 struct GridGraphEdge(u16);
 
@@ -354,98 +363,97 @@ impl GridGraphEdge {
     }
 }
 
-/// Edge IDs are grid node IDs plus the direction constant times GRID_NODE_ID_CAPACITY.
-/// The maximum value is 6372 * 9 = 57348, which fits in u16.
-/// The multiplication is slow, but edges are always iterated over, never computed directly.
 #[inline]
 fn grid_edge(node: GridGraphNode, direction: GridDirection) -> GridGraphEdge {
-    GridGraphEdge(node.0 + GRID_NODE_ID_CAPACITY * (direction as u16))
+    GridGraphEdge(node.0 * (DIRECTIONS_COUNT as u16) + (direction as u16))
 }
 
 #[inline]
 fn edge_node(edge: GridGraphEdge) -> GridGraphNode {
-    GridGraphNode(edge.0 % GRID_NODE_ID_CAPACITY)
+    GridGraphNode(edge.0 / (DIRECTIONS_COUNT as u16))
 }
 
 #[inline]
 fn edge_direction(edge: GridGraphEdge) -> GridDirection {
-    ((edge.0 / GRID_NODE_ID_CAPACITY) as u8).into()
+    ((edge.0 % (DIRECTIONS_COUNT as u16)) as u8).into()
 }
 
 #[inline]
 fn edge_target_node(edge: GridGraphEdge) -> GridGraphNode {
-    let direction = edge_direction(edge);
-    let source_node = edge_node(edge);
-    let (x, y) = grid_direction::direction_to_offset(direction);
-    GridGraphNode((((source_node.0 ^ 1) as i16) + (x as i16) * (1 << 1) + (y as i16) * (1 << 7)) as u16)
+    near_node(edge_node(edge), edge_direction(edge))
 }
 
 #[inline]
 fn reverse_edge(edge: GridGraphEdge) -> GridGraphEdge {
-    let direction = edge_direction(edge);
-    let target_node = edge_target_node(edge);
-    grid_edge(target_node, grid_direction::reverse_direction(direction))
+    grid_edge(edge_target_node(edge), grid_direction::reverse_direction(edge_direction(edge)))
 }
 
 #[inline]
 fn is_internal_edge(edge: GridGraphEdge) -> bool {
-    edge.0 < GRID_NODE_ID_CAPACITY
+    edge_direction(edge) == Center
 }
 
-const GRID_EDGE_ID_CAPACITY: u16 = GRID_NODE_ID_CAPACITY * 9;
-const UNKNOWN_EDGE: GridGraphEdge = GridGraphEdge(GRID_EDGE_ID_CAPACITY - 1);
-
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 struct GridGraphNode(u16);
 
 impl Display for GridGraphNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let xy = grid_node_to_xy(*self);
-        let output_str = ["0", "I"][is_output_node(*self) as usize];
-        write!(f, "{}{}", xy, output_str)
+        let output_str = ["0", "I"][self.is_output() as usize];
+        write!(f, "{}{}", self.xy(), output_str)
     }
 }
 
 impl GridGraphNode {
+    fn new(xy: RoomXY, kind: TileVertexKind) -> Self {
+        GridGraphNode(2 * (xy.to_index() as u16) + (kind as u16))
+    }
+
     fn usize(self) -> usize {
         self.0 as usize
     }
-}
 
-/// Grid node IDs (from least significant bits):
-/// - 1 bit for Input / Output
-/// - 6 bits for the X axis coordinate
-/// - 6 bits for the Y axis coordinate
-/// Note that some grid node ID space is wasted since ROOM_SIZE < 64. But log2 5000 > 12.
-/// The maximum value is 6371 < 8192, leaving some free space that will be used in edge IDs.
-fn grid_node(x: u8, y: u8, kind: TileVertexKind) -> GridGraphNode {
-    GridGraphNode((kind as u16) | ((x as u16) << 1) | ((y as u16) << 7))
-}
+    fn is_output(self) -> bool {
+        (self.0 & 1) == 1
+    }
 
-fn is_output_node(node: GridGraphNode) -> bool {
-    (node.0 & 1) == 1
+    fn xy(self) -> RoomXY {
+        let index = self.0 / 2;
+        // Safe as long as the grid node ID is correct.
+        unsafe { RoomXY::unchecked_new((index % (ROOM_SIZE as u16)) as u8, (index / (ROOM_SIZE as u16)) as u8) }
+    }
 }
 
-fn grid_node_to_xy(node: GridGraphNode) -> RoomXY {
-    // Safe as long as the grid node ID is correct.
-    unsafe { RoomXY::unchecked_new(((node.0 >> 1) & ((1 << 6) - 1)) as u8, (node.0 >> 7) as u8) }
-}
+/// Offsets of the IDs of nodes on tiles in each direction, in the order of `GridDirection`.
+/// The direction of the reverse edge for each direction, in the order of `GridDirection`.
+const REVERSE_DIRECTIONS: [GridDirection; DIRECTIONS_COUNT] =
+    [Center, Bottom, BottomLeft, Left, TopLeft, Top, TopRight, Right, BottomRight];
 
-const GRID_NODE_ID_CAPACITY: u16 = 1 + (1 | (((ROOM_SIZE - 1) as u16) << 1) | (((ROOM_SIZE - 1) as u16) << 7));
+const NODE_ID_OFFSETS: [i16; DIRECTIONS_COUNT] = {
+    const ROW: i16 = 2 * ROOM_SIZE as i16;
+    [0, -ROW, 2 - ROW, 2, 2 + ROW, ROW, ROW - 2, -2, -2 - ROW]
+};
+
+/// The node of the other kind on the tile in given direction, i.e., the target of the edge in that direction.
+/// Must not be called on an exit tile with a direction outside of the room.
+#[inline]
+fn near_node(node: GridGraphNode, direction: GridDirection) -> GridGraphNode {
+    GridGraphNode(((node.0 ^ 1) as i16 + NODE_ID_OFFSETS[direction as usize]) as u16)
+}
 
-/// Invokes function f on each edge (normal and backflow) coming from vertex with given `id`.
+/// Invokes function f on each edge (normal and backflow) coming from given node.
 /// Must not be called on an exit tile or else an integer overflow is possible.
 #[inline]
 fn for_each_node_around<F, R>(node: GridGraphNode, mut f: F)
 where
     F: FnMut(GridGraphNode, GridGraphEdge) -> R,
 {
-    let mut edge = node.0;
-
-    for direction in all::<GridDirection>() {
-        let (x, y) = grid_direction::direction_to_offset(direction);
-        f(GridGraphNode((((node.0 ^ 1) as i16) + (x as i16) * (1 << 1) + (y as i16) * (1 << 7)) as u16), GridGraphEdge(edge));
-        edge += GRID_NODE_ID_CAPACITY;
+    let other_kind_node_id = (node.0 ^ 1) as i16;
+    let first_edge_id = node.0 * (DIRECTIONS_COUNT as u16);
+    for (direction, node_id_offset) in NODE_ID_OFFSETS.into_iter().enumerate() {
+        f(
+            GridGraphNode((other_kind_node_id + node_id_offset) as u16),
+            GridGraphEdge(first_edge_id + direction as u16),
+        );
     }
 }
 
@@ -459,51 +467,72 @@ mod tests {
     use crate::geometry::grid_direction::GridDirection::{BottomRight, Center};
     use crate::algorithms::grid_min_cut::TileVertexKind::{Input, Output};
     use crate::algorithms::grid_min_cut::{
-        edge_direction, edge_node, edge_target_node, for_each_node_around, grid_edge, grid_min_cut, grid_node,
-        grid_node_to_xy, is_internal_edge, reverse_edge,
+        edge_direction, edge_node, edge_target_node, for_each_node_around, grid_edge, grid_min_cut, is_internal_edge,
+        reverse_edge, GridGraphNode, REVERSE_DIRECTIONS,
     };
+    use crate::algorithms::distance_matrix::distance_matrix;
+    use crate::algorithms::old_grid_min_cut;
     use crate::algorithms::matrix_common::MatrixCommon;
     use crate::algorithms::room_matrix::RoomMatrix;
-    use crate::consts::OBSTACLE_COST;
-    use crate::geometry::rect::Rect;
+    use crate::consts::{OBSTACLE_COST, UNREACHABLE_COST};
+    use crate::geometry::rect::{ball, Rect};
     use crate::geometry::room_xy::RoomXYUtils;
+    use crate::room_states::packed_terrain::PackedTerrain;
+    use crate::utils::random::{RandomSource, SeededRandom};
     use enum_iterator::all;
+    use screeps::Terrain::Wall;
     use screeps::{RoomXY, ROOM_SIZE};
     use std::error::Error;
-    use crate::geometry::grid_direction::GridDirection;
+    use crate::geometry::grid_direction::{direction_to_offset, reverse_direction, GridDirection};
+
+    const FIXTURES: [&str; 3] = [
+        include_str!("../room_planning/fixtures/W1N1.txt"),
+        include_str!("../room_planning/fixtures/W2N5.txt"),
+        include_str!("../room_planning/fixtures/W7N3.txt"),
+    ];
 
     #[test]
     fn test_helper_functions() {
         let node_xy = RoomXY::try_from((12, 12)).unwrap();
-        let input_node = grid_node(12, 12, Input);
+        let input_node = GridGraphNode::new(node_xy, Input);
+        assert_eq!(input_node.xy(), node_xy);
+        assert!(!input_node.is_output());
 
         let input_to_output_edge = grid_edge(input_node, Center);
         assert_eq!(edge_node(input_to_output_edge), input_node);
         assert_eq!(edge_direction(input_to_output_edge), Center);
         assert_eq!(reverse_edge(reverse_edge(input_to_output_edge)), input_to_output_edge);
 
-        let output_node = grid_node(12, 12, Output);
+        let output_node = GridGraphNode::new(node_xy, Output);
+        assert_eq!(output_node.xy(), node_xy);
+        assert!(output_node.is_output());
 
         for direction in all::<GridDirection>() {
             let output_to_something_edge = grid_edge(output_node, direction);
             assert_eq!(reverse_edge(reverse_edge(output_to_something_edge)), output_to_something_edge);
+            assert_eq!(REVERSE_DIRECTIONS[direction as usize], reverse_direction(direction));
+            let near_xy = edge_target_node(output_to_something_edge).xy();
+            assert_eq!(near_xy.sub(node_xy), direction_to_offset(direction));
         }
 
-        let target_node_xy = grid_node_to_xy(edge_node(reverse_edge(grid_edge(output_node, BottomRight))));
+        let target_node_xy = edge_node(reverse_edge(grid_edge(output_node, BottomRight))).xy();
         assert_eq!(target_node_xy, RoomXY::try_from((13, 13)).unwrap());
 
         for_each_node_around(input_node, |near_node, edge| {
             assert_eq!(edge_node(edge), input_node);
+            assert_eq!(edge_target_node(edge), near_node);
             if edge_direction(edge) == Center {
                 assert_eq!(edge_target_node(edge), output_node);
             } else {
-                let xy = grid_node_to_xy(edge_target_node(edge));
+                let xy = edge_target_node(edge).xy();
                 assert_eq!(xy.dist(node_xy), 1);
+                assert!(edge_target_node(edge).is_output());
             }
         });
 
-        assert!(is_internal_edge(grid_edge(grid_node(25, 26, Input), Center)));
-        assert!(!is_internal_edge(grid_edge(grid_node(25, 26, Input), BottomRight)));
+        let xy = RoomXY::try_from((25, 26)).unwrap();
+        assert!(is_internal_edge(grid_edge(GridGraphNode::new(xy, Input), Center)));
+        assert!(!is_internal_edge(grid_edge(GridGraphNode::new(xy, Input), BottomRight)));
     }
 
     #[test]
@@ -600,4 +629,94 @@ mod tests {
         }
         Ok(())
     }
+
+    /// Cost matrix of a fixture room with the area around the controller and the first source as the start and costs
+    /// increasing with the distance from it, similar to the one in `place_main_ramparts`.
+    fn fixture_costs(contents: &str) -> RoomMatrix<u8> {
+        let mut terrain = None;
+        let mut start_xys = Vec::new();
+        for line in contents.lines() {
+            let (key, value) = line.split_once(':').unwrap();
+            match key {
+                "terrain" => terrain = Some(PackedTerrain::from_base64(value.trim()).unwrap()),
+                "controller" | "sources" => {
+                    let mut coords = value.split(',').next().unwrap().split_whitespace().map(|c| c.parse::<u8>().unwrap());
+                    start_xys.push(RoomXY::try_from((coords.next().unwrap(), coords.next().unwrap())).unwrap());
+                }
+                _ => {}
+            }
+        }
+        let terrain = terrain.unwrap();
+        let start_xys = start_xys
+            .into_iter()
+            .flat_map(|xy| ball(xy, 4).iter())
+            .filter(|&xy| terrain.get(xy) != Wall)
+            .collect::<Vec<_>>();
+        let dm = distance_matrix(terrain.walls(), start_xys.into_iter());
+        dm.map(|_, dist| match dist {
+            0..=2 => 0,
+            OBSTACLE_COST | UNREACHABLE_COST => OBSTACLE_COST,
+            _ => dist.saturating_add(10).min(OBSTACLE_COST - 1),
+        })
+    }
+
+    /// An open room with random costs, some walls and scattered zero-cost regions.
+    fn scattered_costs(seed: u64) -> RoomMatrix<u8> {
+        let rng = SeededRandom::new(seed);
+        let random_u8 = |min: u8, max: u8| min + (rng.random() * (max - min + 1) as f64) as u8;
+        let mut costs = RoomMatrix::new(0u8).map(|_, _| random_u8(10, 30));
+        for _ in 0..100 {
+            costs.set((random_u8(0, ROOM_SIZE - 1), random_u8(0, ROOM_SIZE - 1)).try_into().unwrap(), OBSTACLE_COST);
+        }
+        for _ in 0..8 {
+            let center = (random_u8(8, ROOM_SIZE - 9), random_u8(8, ROOM_SIZE - 9)).try_into().unwrap();
+            for xy in ball(center, random_u8(0, 3)).iter() {
+                costs.set(xy, 0);
+            }
+        }
+        costs
+    }
+
+    fn cut_cost(costs: &RoomMatrix<u8>, cut: &[RoomXY]) -> u32 {
+        cut.iter().map(|&xy| costs.get(xy) as u32).sum()
+    }
+
+    #[test]
+    fn test_grid_min_cut_equal_to_old_implementation() {
+        let cases = FIXTURES
+            .iter()
+            .map(|contents| fixture_costs(contents))
+            .chain((0..3).map(scattered_costs));
+        for costs in cases {
+            let min_cut = grid_min_cut(&costs);
+            let old_min_cut = old_grid_min_cut::grid_min_cut(&costs);
+            assert_eq!(cut_cost(&costs, &min_cut), cut_cost(&costs, &old_min_cut));
+            // The cut closest to the start is unique.
+            assert_eq!(min_cut, old_min_cut);
+        }
+    }
+
+    /// Run with `cargo test --release --features offline bench_grid_min_cut -- --nocapture` to see the timings.
+    #[cfg(feature = "offline")]
+    #[test]
+    fn bench_grid_min_cut_on_scattered_regions() {
+        use std::time::Instant;
+
+        let cases = (0..20).map(scattered_costs).collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let min_cuts = cases.iter().map(grid_min_cut).collect::<Vec<_>>();
+        let duration = start.elapsed();
+
+        let start = Instant::now();
+        let old_min_cuts = cases.iter().map(old_grid_min_cut::grid_min_cut).collect::<Vec<_>>();
+        let old_duration = start.elapsed();
+
+        assert_eq!(min_cuts, old_min_cuts);
+        println!(
+            "grid_min_cut: {:.2}ms per room, old grid_min_cut: {:.2}ms per room.",
+            duration.as_secs_f64() * 1000.0 / cases.len() as f64,
+            old_duration.as_secs_f64() * 1000.0 / cases.len() as f64
+        );
+    }
 }
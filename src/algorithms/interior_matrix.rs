@@ -1,8 +1,9 @@
+use crate::algorithms::flood_fill::{label_regions, NO_REGION};
 use crate::algorithms::matrix_common::MatrixCommon;
 use crate::algorithms::room_matrix::RoomMatrix;
 use crate::geometry::rect::room_rect;
+use rustc_hash::FxHashSet;
 use screeps::RoomXY;
-use crate::geometry::room_xy::RoomXYUtils;
 
 /// Returns a matrix with information what is outside or an obstacle (false) and what inside or on the cut (true),
 /// given a list of obstacles and a cut supposed to divide exit tiles from the interior.
@@ -11,46 +12,17 @@ where
     O: Iterator<Item = RoomXY>,
     C: Iterator<Item = RoomXY>,
 {
-    let mut result = RoomMatrix::new(true);
-    let mut obstacles_vec = Vec::new();
-    for xy in obstacles {
-        result.set(xy, false);
-        if obstacles_included {
-            obstacles_vec.push(xy);
-        }
-    }
-
-    let not_obstacle_matrix = result.clone();
-
-    let mut layer = room_rect()
-        .boundary()
-        .filter(|&xy| not_obstacle_matrix.get(xy))
-        .collect::<Vec<_>>();
-
-    for &xy in layer.iter() {
-        result.set(xy, false);
-    }
-
+    let obstacles_vec = obstacles.collect::<Vec<_>>();
     let cut_vec = cut.collect::<Vec<_>>();
 
-    for &xy in cut_vec.iter() {
-        result.set(xy, false);
-    }
-
-    while !layer.is_empty() {
-        let mut next_layer = Vec::new();
-
-        for xy in layer.into_iter() {
-            for near in xy.around() {
-                if not_obstacle_matrix.get(near) && result.get(near) {
-                    next_layer.push(near);
-                    result.set(near, false);
-                }
-            }
-        }
-
-        layer = next_layer;
-    }
+    // The interior consists of the regions without exit tiles when both the obstacles and the cut are blocking.
+    let (regions, _) = label_regions(obstacles_vec.iter().chain(cut_vec.iter()).copied());
+    let outside_regions = room_rect()
+        .boundary()
+        .map(|xy| regions.get(xy))
+        .filter(|&region| region != NO_REGION)
+        .collect::<FxHashSet<_>>();
+    let mut result = regions.map(|_, region| region != NO_REGION && !outside_regions.contains(&region));
 
     if cut_included {
         for xy in cut_vec.into_iter() {
@@ -1,10 +1,9 @@
-use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::distance_matrix::{bounded_distance_matrix, distance_matrix};
 use crate::algorithms::matrix_common::MatrixCommon;
 use crate::algorithms::room_matrix::RoomMatrix;
 use crate::algorithms::weighted_distance_matrix::{obstacle_cost, unreachable_cost};
 use crate::geometry::rect::ball;
 use crate::geometry::room_xy::RoomXYUtils;
-use crate::utils::multi_map_utils::{MultiMapUtils, OrderedMultiMapUtils};
 use derive_more::Constructor;
 use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::RoomXY;
@@ -25,8 +24,20 @@ pub struct PathSpec {
 }
 
 impl PathSpec {
-    fn source_dm(&self, obstacles: &[RoomXY]) -> RoomMatrix<u8> {
-        distance_matrix(obstacles.iter().copied(), self.sources.iter().copied())
+    /// Distances from the sources up to the length of the shortest path plus `dist_tolerance`, which is all that is
+    /// needed to find the shortest path area. The length is read from `target_dm`.
+    fn source_dm(&self, obstacles: &[RoomXY], target_dm: &RoomMatrix<u8>, dist_tolerance: u8) -> RoomMatrix<u8> {
+        let min_dist = self
+            .sources
+            .iter()
+            .map(|&xy| target_dm.get(xy))
+            .min()
+            .unwrap_or(unreachable_cost());
+        bounded_distance_matrix(
+            obstacles.iter().copied(),
+            self.sources.iter().copied(),
+            min_dist.saturating_add(dist_tolerance),
+        )
     }
 
     fn target_dm(&self, obstacles: &[RoomXY], cost_matrix: &RoomMatrix<u8>) -> RoomMatrix<u8> {
@@ -42,6 +53,9 @@ impl PathSpec {
 /// Creates a graph of paths between given targets and the nearest source (separately for each target), each path being
 /// the shortest (without weights). Tries heuristically to minimize the total sum of weights of used tiles by leading
 /// the paths through the same tiles.
+/// The result is deterministic. Paths are found in the order of their length, with ties broken by their order in
+/// `path_specs`. Tiles with equal cost are processed in the order of their preference and then their index, with
+/// neighbors visited in a fixed direction order, so the result never depends on the iteration order of hash maps.
 pub fn minimal_shortest_paths_tree(
     cost_matrix: &RoomMatrix<u8>,
     preference_matrix: &RoomMatrix<u8>,
@@ -49,73 +63,142 @@ pub fn minimal_shortest_paths_tree(
     sqrt_target_scaling: bool,
     dist_tolerance: u8,
 ) -> Option<Vec<Vec<RoomXY>>> {
-    // Obstacles and reserved fields - real targets.
-    let mut obstacles = cost_matrix.find_xy(obstacle_cost()).collect::<Vec<_>>();
+    let mut tree = MinimalShortestPathsTree::new(
+        cost_matrix.clone(),
+        preference_matrix.clone(),
+        path_specs.clone(),
+        sqrt_target_scaling,
+        dist_tolerance,
+    );
+    tree.advance(usize::MAX)?;
+    Some(tree.into_paths())
+}
 
-    let (path_ixs, mut path_areas): (Vec<_>, Vec<_>) = {
-        let mut path_areas_data = path_specs
-            .iter()
-            .enumerate()
-            .map(|(path_ix, path_spec)| {
-                shortest_path_area(
-                    &path_spec.source_dm(&obstacles),
-                    &path_spec.target_dm(&obstacles, &cost_matrix),
-                    dist_tolerance,
-                )
-                .map(|(path_area, dist)| (path_ix, path_area, dist))
-            })
-            .collect::<Option<Vec<_>>>()?;
-        // TODO Detecting continuous (maybe with tolerance) fragments and selecting roads more or less in
-        //      the middle will most likely result in less roads.
-        path_areas_data.sort_by_key(|(i, _, dist)| *dist);
-        path_areas_data
-            .into_iter()
-            .map(|(path_ix, path_area, _)| (path_ix, path_area))
-            .unzip()
-    };
-
-    let mut path_xys = FxHashSet::default();
-
-    let mut paths = path_ixs.iter().map(|_| Vec::new()).collect::<Vec<_>>();
-
-    // debug!("Finding routes with cost matrix\n{}", cost_matrix);
-
-    for i in 0..path_areas.len() {
-        let path_ix = path_ixs[i];
-        let path_spec: &PathSpec = &path_specs[path_ix];
-        let target_dm = path_spec.target_dm(&obstacles, cost_matrix);
+/// Computation of `minimal_shortest_paths_tree` that can be spread over multiple calls of `advance`.
+pub struct MinimalShortestPathsTree {
+    cost_matrix: RoomMatrix<u8>,
+    preference_matrix: RoomMatrix<u8>,
+    path_specs: Vec<PathSpec>,
+    sqrt_target_scaling: bool,
+    dist_tolerance: u8,
+    /// Obstacles and reserved fields - real targets.
+    obstacles: Vec<RoomXY>,
+    /// Shortest path areas found so far along with the indexes of their paths and the lengths of the paths.
+    path_areas_data: Vec<(usize, FxHashSet<RoomXY>, u8)>,
+    /// Indexes of paths in the order they are found in, set once all shortest path areas are found.
+    path_ixs: Vec<usize>,
+    path_areas: Vec<FxHashSet<RoomXY>>,
+    path_xys: FxHashSet<RoomXY>,
+    paths: Vec<Vec<RoomXY>>,
+    found_paths_count: usize,
+}
+
+impl MinimalShortestPathsTree {
+    pub fn new(
+        cost_matrix: RoomMatrix<u8>,
+        preference_matrix: RoomMatrix<u8>,
+        path_specs: Vec<PathSpec>,
+        sqrt_target_scaling: bool,
+        dist_tolerance: u8,
+    ) -> Self {
+        let obstacles = cost_matrix.find_xy(obstacle_cost()).collect::<Vec<_>>();
+        let paths = path_specs.iter().map(|_| Vec::new()).collect();
+        MinimalShortestPathsTree {
+            cost_matrix,
+            preference_matrix,
+            path_specs,
+            sqrt_target_scaling,
+            dist_tolerance,
+            obstacles,
+            path_areas_data: Vec::new(),
+            path_ixs: Vec::new(),
+            path_areas: Vec::new(),
+            path_xys: FxHashSet::default(),
+            paths,
+            found_paths_count: 0,
+        }
+    }
+
+    /// Continues the computation by finding at most `max_steps` shortest path areas or paths, the areas of all paths
+    /// being found first. Returns whether all paths were found or `None` if some target is unreachable.
+    pub fn advance(&mut self, max_steps: usize) -> Option<bool> {
+        for _ in 0..max_steps {
+            if self.path_ixs.len() < self.path_specs.len() {
+                self.find_path_area()?;
+            } else if self.found_paths_count < self.path_specs.len() {
+                self.find_path()?;
+            } else {
+                break;
+            }
+        }
+        Some(self.found_paths_count == self.path_specs.len())
+    }
+
+    /// The paths in the order of `path_specs`, all of them found if `advance` returned true.
+    pub fn into_paths(self) -> Vec<Vec<RoomXY>> {
+        self.paths
+    }
+
+    fn find_path_area(&mut self) -> Option<()> {
+        let path_ix = self.path_areas_data.len();
+        let path_spec = &self.path_specs[path_ix];
+        let target_dm = path_spec.target_dm(&self.obstacles, &self.cost_matrix);
+        let (path_area, dist) = shortest_path_area(
+            &path_spec.source_dm(&self.obstacles, &target_dm, self.dist_tolerance),
+            &target_dm,
+            self.dist_tolerance,
+        )?;
+        self.path_areas_data.push((path_ix, path_area, dist));
+
+        if self.path_areas_data.len() == self.path_specs.len() {
+            // TODO Detecting continuous (maybe with tolerance) fragments and selecting roads more or less in
+            //      the middle will most likely result in less roads.
+            let mut path_areas_data = std::mem::take(&mut self.path_areas_data);
+            path_areas_data.sort_by_key(|&(path_ix, _, dist)| (dist, path_ix));
+            (self.path_ixs, self.path_areas) = path_areas_data
+                .into_iter()
+                .map(|(path_ix, path_area, _)| (path_ix, path_area))
+                .unzip();
+        }
+
+        Some(())
+    }
+
+    fn find_path(&mut self) -> Option<()> {
+        let i = self.found_paths_count;
+        let cost_matrix = &self.cost_matrix;
+        let preference_matrix = &self.preference_matrix;
+        let path_ix = self.path_ixs[i];
+        let path_spec: &PathSpec = &self.path_specs[path_ix];
+        let target_dm = path_spec.target_dm(&self.obstacles, cost_matrix);
 
         let mut number_of_areas = RoomMatrix::new(0u8);
-        for path_area in path_areas.iter().skip(i + 1) {
+        for path_area in self.path_areas.iter().skip(i + 1) {
             for &xy in path_area.iter() {
                 number_of_areas.set(xy, number_of_areas.get(xy) + 1);
             }
         }
 
         // Implementation of Dijkstra with respect to the cost matrix and penalizing not following decreasing distance
-        // from the source.
+        // from the source. The queue is ordered by `(cost, preference, index)` of the tiles to break ties
+        // deterministically.
         let mut distances = RoomMatrix::new(unreachable_cost());
-        let mut queue: BTreeMap<u32, Vec<RoomXY>> = BTreeMap::new();
+        let mut queue: BTreeMap<(u32, u8, usize), RoomXY> = BTreeMap::new();
+        let queue_key = |dist: u32, xy: RoomXY| (dist, preference_matrix.get(xy), xy.to_index());
         let mut prev = FxHashMap::default();
 
         for &source in path_spec.sources.iter() {
             distances.set(source, 0u32);
-            queue.push_or_insert(0, source);
+            queue.insert(queue_key(0, source), source);
         }
 
         let mut best_target = None;
         let mut best_target_dist = unreachable_cost();
 
-        // debug!(
-        //     "Finding route {:?} -> {} / {}\n{}",
-        //     path_spec.sources, path_spec.target, path_spec.target_range, number_of_areas
-        // );
-
-        while let Some((dist, xy)) = queue.pop_from_first() {
+        while let Some(((dist, _, _), xy)) = queue.pop_first() {
             if dist >= best_target_dist {
                 break;
             }
-            // debug!("Processing from {} at dist {}.", xy, dist);
             if distances.get(xy) == dist {
                 for near in xy.around() {
                     if target_dm.get(near) < unreachable_cost() {
@@ -124,13 +207,13 @@ pub fn minimal_shortest_paths_tree(
                         assert!(dist_diff <= 2);
                         let extra_dist_cost =
                             ((dist_diff as f32 * path_spec.extra_length_cost) * (2 << 14) as f32) as u32;
-                        let near_cost = if cost_matrix.get(near) == 0 || path_xys.contains(&near) {
+                        let near_cost = if cost_matrix.get(near) == 0 || self.path_xys.contains(&near) {
                             extra_dist_cost
                         } else {
                             let shared_cost =
                                 (((cost_matrix.get(near) as u32) << 8) + preference_matrix.get(near) as u32) << 3;
                             let shared_targets = (number_of_areas.get(near) + 1) as f32;
-                            let sharing_factor = if sqrt_target_scaling {
+                            let sharing_factor = if self.sqrt_target_scaling {
                                 shared_targets.sqrt()
                             } else {
                                 shared_targets
@@ -143,20 +226,12 @@ pub fn minimal_shortest_paths_tree(
                             distances.set(near, new_dist);
                             prev.insert(near, xy);
                             if target_dm.get(near) == 0 {
-                                // debug!(
-                                //     "solution {} -> {} at cost {} (dist_diff {} extra_dist_cost {} total {})",
-                                //     xy, near, near_cost, dist_diff, extra_dist_cost, new_dist
-                                // );
                                 if new_dist < best_target_dist {
                                     best_target = Some(near);
                                     best_target_dist = new_dist;
                                 }
                             } else {
-                                // debug!(
-                                //     "{} -> {} at cost {} (dist_diff {} extra_dist_cost {} total {})",
-                                //     xy, near, near_cost, dist_diff, extra_dist_cost, new_dist
-                                // );
-                                queue.push_or_insert(new_dist, near);
+                                queue.insert(queue_key(new_dist, near), near);
                             }
                         }
                     }
@@ -168,39 +243,36 @@ pub fn minimal_shortest_paths_tree(
 
         let mut path = vec![target];
         let mut current = target;
-        path_xys.insert(target);
+        self.path_xys.insert(target);
         while let Some(current_prev) = prev.get(&current) {
             current = *current_prev;
             path.push(current);
-            path_xys.insert(current);
+            self.path_xys.insert(current);
         }
         path.reverse();
 
-        // debug!(
-        //     "Found route {} -> {} ({:?} -> {} / {})\n{:?}\n{}",
-        //     path[0], target, path_spec.sources, path_spec.target, path_spec.target_range, path, distances
-        // );
-
-        paths[path_ix] = path;
+        self.paths[path_ix] = path;
+        self.found_paths_count += 1;
 
         // If the target is marked as impassable, paths with path area going through it need updating.
         if path_spec.impassable_target {
-            obstacles.push(target);
-            for j in i + 1..path_areas.len() {
-                if path_areas[j].contains(&target) {
-                    let ps: &PathSpec = &path_specs[path_ixs[j]];
-                    path_areas[j] = shortest_path_area(
-                        &ps.source_dm(&obstacles),
-                        &ps.target_dm(&obstacles, cost_matrix),
-                        dist_tolerance,
+            self.obstacles.push(target);
+            for j in i + 1..self.path_areas.len() {
+                if self.path_areas[j].contains(&target) {
+                    let ps: &PathSpec = &self.path_specs[self.path_ixs[j]];
+                    let target_dm = ps.target_dm(&self.obstacles, cost_matrix);
+                    self.path_areas[j] = shortest_path_area(
+                        &ps.source_dm(&self.obstacles, &target_dm, self.dist_tolerance),
+                        &target_dm,
+                        self.dist_tolerance,
                     )?
                     .0;
                 }
             }
         }
-    }
 
-    Some(paths)
+        Some(())
+    }
 }
 
 fn shortest_path_area(
@@ -231,8 +303,12 @@ fn shortest_path_area(
 
 #[cfg(test)]
 mod tests {
-    use crate::algorithms::minimal_shortest_paths_tree::{minimal_shortest_paths_tree, PathSpec};
+    use crate::algorithms::distance_matrix::distance_matrix;
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::minimal_shortest_paths_tree::{minimal_shortest_paths_tree, shortest_path_area, PathSpec};
     use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::algorithms::weighted_distance_matrix::obstacle_cost;
+    use screeps::RoomXY;
 
     #[test]
     fn test_minimal_shortest_paths_tree_single() {
@@ -346,4 +422,56 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_ties_do_not_depend_on_order_of_sources() {
+        let cost_matrix = RoomMatrix::new(1u8);
+        let preference_matrix = RoomMatrix::new(1u8);
+        let sources: Vec<RoomXY> = vec![(20, 20).try_into().unwrap(), (30, 20).try_into().unwrap()];
+        let paths = |sources: Vec<RoomXY>| {
+            minimal_shortest_paths_tree(
+                &cost_matrix,
+                &preference_matrix,
+                &vec![PathSpec::new(sources, (25, 26).try_into().unwrap(), 1, false, 1.0)],
+                false,
+                0,
+            )
+            .unwrap()
+        };
+
+        let reversed_sources = sources.iter().rev().copied().collect::<Vec<_>>();
+        let path = paths(sources).remove(0);
+        assert_eq!(path.len(), 6);
+        assert_eq!(path, paths(reversed_sources).remove(0));
+    }
+
+    #[test]
+    fn test_bounded_source_dm_gives_the_same_path_area() {
+        let mut cost_matrix = RoomMatrix::new(1u8);
+        for y in 15..35 {
+            cost_matrix.set((25, y).try_into().unwrap(), obstacle_cost());
+        }
+        let obstacles = cost_matrix.find_xy(obstacle_cost()).collect::<Vec<_>>();
+        let path_spec = PathSpec::new(
+            vec![(20, 25).try_into().unwrap(), (10, 40).try_into().unwrap()],
+            (30, 24).try_into().unwrap(),
+            1,
+            false,
+            1.0,
+        );
+
+        let target_dm = path_spec.target_dm(&obstacles, &cost_matrix);
+        let full_source_dm = distance_matrix(obstacles.iter().copied(), path_spec.sources.iter().copied());
+        for dist_tolerance in [0, 2] {
+            let source_dm = path_spec.source_dm(&obstacles, &target_dm, dist_tolerance);
+            let (path_area, dist) = shortest_path_area(&source_dm, &target_dm, dist_tolerance).unwrap();
+            let (full_path_area, full_dist) = shortest_path_area(&full_source_dm, &target_dm, dist_tolerance).unwrap();
+            assert_eq!(dist, full_dist);
+            let mut path_area = path_area.into_iter().collect::<Vec<RoomXY>>();
+            let mut full_path_area = full_path_area.into_iter().collect::<Vec<RoomXY>>();
+            path_area.sort();
+            full_path_area.sort();
+            assert_eq!(path_area, full_path_area);
+        }
+    }
 }
@@ -1,13 +1,18 @@
 // Algorithms and data structures.
 pub mod room_matrix_slice;
 pub mod room_matrix;
+pub mod room_matrix_view;
+pub mod packed_room_matrix;
 pub mod distance_matrix;
 pub mod matrix_common;
 pub mod grid_min_cut;
+#[cfg(test)]
+pub mod old_grid_min_cut;
 pub mod chunk_graph;
 pub mod distance_transform;
 pub mod shortest_path_by_distance_matrix;
 pub mod interior_matrix;
+pub mod flood_fill;
 pub mod max_boundary_distance;
 pub mod binary_search;
 pub mod weighted_distance_matrix;
@@ -0,0 +1,166 @@
+//! Compact serialization of room matrices with values packed in `u16`, to be used with
+//! `#[serde(with = "crate::algorithms::packed_room_matrix")]`.
+//! In human-readable formats, the matrix is run-length encoded row by row and stored as a single
+//! base64 string. Matrices serialized as a sequence of values are still accepted. Binary formats
+//! use the serialization of `RoomMatrix`, which is run-length encoded already.
+use std::fmt::Formatter;
+use std::marker::PhantomData;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::value::SeqAccessDeserializer;
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::consts::ROOM_AREA;
+use crate::global_state::binary_format::{read_varint, write_varint, BinaryFormatError};
+
+pub fn serialize<T, S>(matrix: &RoomMatrix<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + Copy + PartialEq + Into<u16>,
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&STANDARD.encode(encode_runs(matrix)))
+    } else {
+        matrix.serialize(serializer)
+    }
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<RoomMatrix<T>, D::Error>
+where
+    T: Deserialize<'de> + Serialize + Default + Copy + PartialEq + From<u16>,
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(PackedRoomMatrixVisitor(PhantomData))
+    } else {
+        RoomMatrix::deserialize(deserializer)
+    }
+}
+
+/// Runs of equal values in the order of indexes, each as a varint with its length followed by
+/// the value in two bytes.
+pub fn encode_runs<T>(matrix: &RoomMatrix<T>) -> Vec<u8>
+where
+    T: Copy + PartialEq + Into<u16>,
+{
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < ROOM_AREA {
+        let value = matrix.data[i];
+        let len = matrix.data[i..].iter().take_while(|&&other_value| other_value == value).count();
+        write_varint(&mut bytes, len as u64);
+        bytes.extend_from_slice(&value.into().to_le_bytes());
+        i += len;
+    }
+    bytes
+}
+
+pub fn decode_runs<T>(mut bytes: &[u8]) -> Result<RoomMatrix<T>, BinaryFormatError>
+where
+    T: Default + Copy + PartialEq + From<u16>,
+{
+    let mut matrix = RoomMatrix::default();
+    let mut filled = 0;
+    while !bytes.is_empty() {
+        let end = filled + read_varint(&mut bytes)? as usize;
+        if end > ROOM_AREA {
+            return Err(BinaryFormatError::InvalidValue("run length"));
+        }
+        let (value_bytes, rest) = bytes.split_first_chunk::<2>().ok_or(BinaryFormatError::UnexpectedEnd)?;
+        bytes = rest;
+        matrix.data[filled..end].fill(T::from(u16::from_le_bytes(*value_bytes)));
+        filled = end;
+    }
+    if filled != ROOM_AREA {
+        return Err(BinaryFormatError::UnexpectedEnd);
+    }
+    Ok(matrix)
+}
+
+struct PackedRoomMatrixVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for PackedRoomMatrixVisitor<T>
+where
+    T: Deserialize<'de> + Serialize + Default + Copy + PartialEq + From<u16>,
+{
+    type Value = RoomMatrix<T>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "a base64 string with runs of values or a sequence of {} values", ROOM_AREA)
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        let bytes = STANDARD.decode(v).map_err(E::custom)?;
+        decode_runs(&bytes).map_err(E::custom)
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        RoomMatrix::deserialize(SeqAccessDeserializer::new(seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::packed_room_matrix::{decode_runs, encode_runs};
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::global_state::binary_format::{from_bytes, to_bytes};
+
+    #[derive(Serialize, Deserialize)]
+    struct Packed {
+        #[serde(with = "crate::algorithms::packed_room_matrix")]
+        matrix: RoomMatrix<u16>,
+    }
+
+    #[derive(Serialize)]
+    struct Unpacked {
+        matrix: RoomMatrix<u16>,
+    }
+
+    fn test_matrix() -> RoomMatrix<u16> {
+        let mut matrix = RoomMatrix::new(0u16);
+        for x in 10..40 {
+            matrix.set((x, 20).try_into().unwrap(), 0x1234);
+        }
+        matrix.set((0, 0).try_into().unwrap(), u16::MAX);
+        matrix.set((49, 49).try_into().unwrap(), 1);
+        matrix
+    }
+
+    #[test]
+    fn test_runs_round_trip() {
+        let matrix = test_matrix();
+        let bytes = encode_runs(&matrix);
+        // Five runs, two of which have lengths over 127.
+        assert_eq!(bytes.len(), 5 * 3 + 2);
+        assert_eq!(decode_runs::<u16>(&bytes).unwrap().data, matrix.data);
+
+        assert!(decode_runs::<u16>(&bytes[..bytes.len() - 3]).is_err());
+        assert!(decode_runs::<u16>(&[]).is_err());
+    }
+
+    #[test]
+    fn test_serialization_round_trip() {
+        let packed = Packed { matrix: test_matrix() };
+        let json = serde_json::to_string(&packed).unwrap();
+        assert!(json.len() < 50);
+        let deserialized: Packed = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.matrix.data, packed.matrix.data);
+
+        let deserialized: Packed = from_bytes(&to_bytes(&packed).unwrap()).unwrap();
+        assert_eq!(deserialized.matrix.data, packed.matrix.data);
+
+        // Matrices serialized before packing are still read.
+        let json = serde_json::to_string(&Unpacked { matrix: test_matrix() }).unwrap();
+        let deserialized: Packed = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.matrix.data, packed.matrix.data);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::algorithms::matrix_common::MatrixCommon;
 use crate::consts::ROOM_AREA;
-use crate::geometry::rect::room_rect;
+use crate::algorithms::room_matrix_view::RoomMatrixView;
+use crate::geometry::rect::{room_rect, Rect};
 use crate::geometry::room_xy::RoomXYUtils;
 use screeps::{RoomXY, ROOM_SIZE};
 use serde::de::{Error, SeqAccess, Visitor};
@@ -40,6 +41,26 @@ where
         }
         RoomMatrix { data }
     }
+
+    /// A view of the tiles within the rectangle.
+    pub fn slice(&self, rect: Rect) -> RoomMatrixView<'_, T> {
+        RoomMatrixView::new(self, rect)
+    }
+
+    /// A view of the tiles within the rectangle, writing through to the matrix.
+    pub fn slice_mut(&mut self, rect: Rect) -> RoomMatrixView<'_, T> {
+        RoomMatrixView::new_mut(self, rect)
+    }
+
+    /// Copies the tiles of the view into the same tiles of the matrix.
+    pub fn paste(&mut self, view: &RoomMatrixView<T>) {
+        let rect = view.rect;
+        let width = rect.width() as usize;
+        for y in rect.top_left.y.u8()..=rect.bottom_right.y.u8() {
+            let start = unsafe { RoomXY::unchecked_new(rect.top_left.x.u8(), y) }.to_index();
+            self.data[start..start + width].copy_from_slice(&view.matrix().data[start..start + width]);
+        }
+    }
 }
 
 impl<T> MatrixCommon<T> for RoomMatrix<T>
@@ -120,17 +141,33 @@ where
 
 impl<T> Serialize for RoomMatrix<T>
 where
-    T: Serialize + Copy,
+    T: Serialize + Copy + PartialEq,
 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut seq_serializer = serializer.serialize_seq(Some(2500))?;
-        self.data
-            .iter()
-            .try_for_each(|val| seq_serializer.serialize_element(val))?;
-        seq_serializer.end()
+        if serializer.is_human_readable() {
+            let mut seq_serializer = serializer.serialize_seq(Some(2500))?;
+            self.data
+                .iter()
+                .try_for_each(|val| seq_serializer.serialize_element(val))?;
+            seq_serializer.end()
+        } else {
+            // Binary formats get runs of equal values as pairs of the length of the run and the
+            // value, which is much shorter for matrices that are mostly empty.
+            let mut runs: Vec<(u16, T)> = Vec::new();
+            for &val in self.data.iter() {
+                match runs.last_mut() {
+                    Some((len, run_val)) if *run_val == val => *len += 1,
+                    _ => runs.push((1, val)),
+                }
+            }
+            let mut seq_serializer = serializer.serialize_seq(Some(runs.len()))?;
+            runs.iter()
+                .try_for_each(|run| seq_serializer.serialize_element(run))?;
+            seq_serializer.end()
+        }
     }
 }
 
@@ -142,7 +179,11 @@ where
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_seq(RoomMatrixVisitor::default())
+        let visitor = RoomMatrixVisitor {
+            run_length_encoded: !deserializer.is_human_readable(),
+            ..RoomMatrixVisitor::default()
+        };
+        deserializer.deserialize_seq(visitor)
     }
 }
 
@@ -155,6 +196,8 @@ where
     buffer: RoomMatrix<T>,
     /// The number of elements of the buffer that are already filled.
     filled: usize,
+    /// Whether the sequence consists of pairs of the length of a run of equal values and the value.
+    run_length_encoded: bool,
 }
 
 impl<'de, T> Visitor<'de> for RoomMatrixVisitor<T>
@@ -164,13 +207,28 @@ where
     type Value = RoomMatrix<T>;
 
     fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
-        write!(formatter, "a sequence of {} serialized values", ROOM_AREA)
+        write!(formatter, "a sequence of {} serialized values or of runs of them", ROOM_AREA)
     }
 
     fn visit_seq<A>(mut self, mut seq: A) -> Result<Self::Value, A::Error>
     where
         A: SeqAccess<'de>,
     {
+        if self.run_length_encoded {
+            while let Some((len, val)) = seq.next_element::<(u16, T)>()? {
+                let end = self.filled + len as usize;
+                if end > ROOM_AREA {
+                    return Err(Error::invalid_length(end, &self));
+                }
+                self.buffer.data[self.filled..end].fill(val);
+                self.filled = end;
+            }
+            if self.filled != ROOM_AREA {
+                return Err(Error::invalid_length(self.filled, &self));
+            }
+            return Ok(self.buffer);
+        }
+
         for i in 0..ROOM_AREA {
             let val = seq.next_element()?.ok_or(Error::invalid_length(ROOM_AREA, &self))?;
             self.buffer.data[i] = val;
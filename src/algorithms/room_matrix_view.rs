@@ -0,0 +1,168 @@
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::algorithms::room_matrix_slice::RoomMatrixSlice;
+use crate::geometry::rect::Rect;
+use crate::geometry::room_xy::RoomXYUtils;
+use screeps::RoomXY;
+
+#[derive(Debug)]
+enum ViewData<'a, T> {
+    Borrowed(&'a RoomMatrix<T>),
+    BorrowedMut(&'a mut RoomMatrix<T>),
+    Owned(Box<RoomMatrix<T>>),
+}
+
+/// A view of the tiles of a `RoomMatrix` within a rectangle, created by `RoomMatrix::slice` or
+/// `RoomMatrix::slice_mut`, working like a `RoomMatrixSlice` without copying the data.
+/// Writes to a view created by `slice_mut` go to the matrix. A view created by `slice` copies the
+/// matrix on the first write, leaving the original intact.
+#[derive(Debug)]
+pub struct RoomMatrixView<'a, T> {
+    pub rect: Rect,
+    data: ViewData<'a, T>,
+}
+
+impl<'a, T> RoomMatrixView<'a, T>
+where
+    T: Copy + PartialEq,
+{
+    pub(crate) fn new(matrix: &'a RoomMatrix<T>, rect: Rect) -> Self {
+        RoomMatrixView {
+            rect,
+            data: ViewData::Borrowed(matrix),
+        }
+    }
+
+    pub(crate) fn new_mut(matrix: &'a mut RoomMatrix<T>, rect: Rect) -> Self {
+        RoomMatrixView {
+            rect,
+            data: ViewData::BorrowedMut(matrix),
+        }
+    }
+
+    /// The whole matrix the view is over. Only the tiles within `rect` are a part of the view.
+    pub fn matrix(&self) -> &RoomMatrix<T> {
+        match &self.data {
+            ViewData::Borrowed(matrix) => matrix,
+            ViewData::BorrowedMut(matrix) => matrix,
+            ViewData::Owned(matrix) => matrix,
+        }
+    }
+
+    pub fn map<F, S>(&self, mut f: F) -> RoomMatrixSlice<S>
+    where
+        F: FnMut(RoomXY, T) -> S,
+        S: Copy + PartialEq + Default,
+    {
+        let mut result = RoomMatrixSlice::new(self.rect, S::default());
+        for (xy, value) in self.iter() {
+            result.set(xy, f(xy, value));
+        }
+        result
+    }
+}
+
+impl<T> MatrixCommon<T> for RoomMatrixView<'_, T>
+where
+    T: Copy + PartialEq,
+{
+    #[inline]
+    fn get(&self, xy: RoomXY) -> T {
+        debug_assert!(self.rect.contains(xy));
+        self.matrix().get(xy)
+    }
+
+    fn get_mut(&mut self, xy: RoomXY) -> &mut T {
+        debug_assert!(self.rect.contains(xy));
+        if let ViewData::Borrowed(matrix) = self.data {
+            self.data = ViewData::Owned(Box::new(matrix.clone()));
+        }
+        match &mut self.data {
+            ViewData::Borrowed(_) => unreachable!(),
+            ViewData::BorrowedMut(matrix) => matrix.get_mut(xy),
+            ViewData::Owned(matrix) => matrix.get_mut(xy),
+        }
+    }
+
+    fn clone_filled(&self, fill: T) -> Self {
+        RoomMatrixView {
+            rect: self.rect,
+            data: ViewData::Owned(Box::new(RoomMatrix::new(fill))),
+        }
+    }
+
+    fn around_xy(&self, xy: RoomXY) -> impl Iterator<Item = RoomXY> {
+        xy.restricted_around(self.rect)
+    }
+
+    fn iter_xy<'b>(&self) -> impl Iterator<Item = RoomXY> + 'b {
+        self.rect.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::geometry::rect::Rect;
+    use crate::utils::test_utils::xy;
+
+    fn numbered_matrix() -> RoomMatrix<u16> {
+        RoomMatrix::new(0u16).map(|xy, _| xy.x.u8() as u16 + 100 * xy.y.u8() as u16)
+    }
+
+    #[test]
+    fn test_view_iteration_stays_within_rect() {
+        let matrix = numbered_matrix();
+        let rect = Rect::new(xy(10, 20), xy(13, 22)).unwrap();
+        let view = matrix.slice(rect);
+
+        let xys = view.iter_xy().collect::<Vec<_>>();
+        assert_eq!(xys.len(), 12);
+        assert_eq!(xys.first(), Some(&xy(10, 20)));
+        assert_eq!(xys.last(), Some(&xy(13, 22)));
+        assert!(xys.iter().all(|&xy| rect.contains(xy)));
+        assert!(view.iter().all(|(xy, value)| value == matrix.get(xy)));
+
+        // Values outside of the rect are not found.
+        assert_eq!(view.find_xy(2011).collect::<Vec<_>>(), vec![xy(11, 20)]);
+        assert_eq!(view.find_xy(0).count(), 0);
+        assert_eq!(view.min(), (xy(10, 20), 2010));
+        assert_eq!(view.around_xy(xy(10, 20)).count(), 3);
+
+        let mapped = view.map(|_, value| value % 100);
+        assert_eq!(mapped.rect, rect);
+        assert_eq!(mapped.get(xy(13, 21)), 13);
+    }
+
+    #[test]
+    fn test_view_mutation() {
+        let mut matrix = numbered_matrix();
+        let rect = Rect::new(xy(1, 1), xy(2, 2)).unwrap();
+
+        // Writes to a mutable view pass through to the matrix.
+        let mut view = matrix.slice_mut(rect);
+        view.update(|_, value| value + 1);
+        assert_eq!(matrix.get(xy(1, 1)), 102);
+        assert_eq!(matrix.get(xy(2, 2)), 203);
+        assert_eq!(matrix.get(xy(3, 3)), 303);
+
+        // Writes to an immutable view do not.
+        let mut view = matrix.slice(rect);
+        view.set(xy(1, 1), 0);
+        assert_eq!(view.get(xy(1, 1)), 0);
+        assert_eq!(view.get(xy(2, 1)), 2 + 100 + 1);
+        assert_eq!(matrix.get(xy(1, 1)), 102);
+
+        // Pasting a view of another matrix only changes the tiles within the rect.
+        let mut other_matrix = RoomMatrix::new(7u16);
+        other_matrix.set(xy(3, 3), 8);
+        let mut view = other_matrix.slice_mut(rect);
+        view.set(xy(2, 2), 9);
+        matrix.paste(&other_matrix.slice(rect));
+        assert_eq!(matrix.get(xy(1, 1)), 7);
+        assert_eq!(matrix.get(xy(2, 2)), 9);
+        assert_eq!(matrix.get(xy(3, 3)), 303);
+        assert_eq!(matrix.get(xy(0, 1)), 100);
+    }
+}
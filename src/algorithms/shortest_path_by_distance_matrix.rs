@@ -30,6 +30,8 @@ where
 
 /// Uses matrix produced by `distance_matrix` to find a shortest route from start wherever gradient goes, up to
 /// distance `final_dist`, inclusive, or until it cannot decrease anymore.
+/// The result is deterministic. Neighbors are checked in the order of directions, starting from the top one clockwise,
+/// and the first one closer to the target is taken.
 pub fn shortest_path_by_distance_matrix<M, D>(distance_matrix: &M, start: RoomXY, final_dist: D) -> Vec<RoomXY>
     where
         M: MatrixCommon<D>,
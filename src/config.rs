@@ -1,6 +1,104 @@
 use log::LevelFilter;
+use screeps::ResourceType;
+use crate::economy::upgrade_allocation::UpgradeStrategy;
 
 pub const LOG_LEVEL: LevelFilter = LevelFilter::Trace;
+/// Maximum number of lines kept in the log buffer until they are taken by `take_log`. The oldest
+/// lines are dropped first.
+pub const LOG_BUFFER_MAX_LINES: usize = 10000;
+/// Maximum total length of the lines kept in the log buffer.
+pub const LOG_BUFFER_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// Number of ticks over which the CPU used by spans measured by the profiler is aggregated.
+pub const PROFILER_WINDOW_TICKS: u32 = 1000;
 
 pub const FIRST_MEMORY_SAVE_TICK: u32 = 21;
-pub const MEMORY_SAVE_INTERVAL: u32 = 7;
\ No newline at end of file
+pub const MEMORY_SAVE_INTERVAL: u32 = 7;
+/// RawMemory segments the global state is saved in, at most 10 since only that many can be active at once.
+pub const GLOBAL_STATE_SEGMENTS: [u8; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+/// Whether to send a game notification with the report after each incident in an owned room.
+pub const NOTIFY_INCIDENT_REPORTS: bool = true;
+
+/// How the energy for upgrading controllers is distributed among owned rooms.
+pub const UPGRADE_STRATEGY: UpgradeStrategy = UpgradeStrategy::Even;
+/// Percentage of the energy income each room may use for upgrading when using
+/// `UpgradeStrategy::Manual`.
+pub const MANUAL_UPGRADE_ALLOCATIONS: &[(&str, u8)] = &[];
+
+/// The text upgraders sign owned controllers with.
+pub const CONTROLLER_SIGN: &str = "xi";
+
+/// Fraction of the tick CPU limit after which the remaining processes are skipped and only
+/// the last call flushes are run, so that the script is not killed mid-tick.
+pub const LAST_CALL_CPU_FRACTION: f64 = 0.95;
+
+/// Whether a panic inside a process is to be propagated instead of only killing the process. Enabled in development
+/// builds to get the full backtrace, but not in tests, which check that the kernel survives the panic.
+pub const RERAISE_PROCESS_PANICS: bool = cfg!(all(debug_assertions, not(test)));
+
+/// Fraction of the tick CPU limit split evenly into CPU budgets of processes maintaining owned rooms, so that one
+/// expensive room does not starve the others.
+pub const OWNED_ROOMS_CPU_FRACTION: f64 = 0.6;
+
+/// Maximum distance from the previous stop of a hauler to another request of the same resource that is
+/// fulfilled in the same trip.
+pub const HAUL_CHAIN_MAX_DETOUR: u32 = 5;
+
+/// Hits to which ramparts and walls are repaired at each RCL. Above the hits that keep them from
+/// decaying soon, they are grown gradually, after other repairs.
+pub const RAMPART_TARGET_HITS: [u32; 9] = [0, 0, 0, 0, 0, 0, 25_000, 50_000, 100_000];
+
+/// Towers repair critical ramparts and roads only when there is at least this much energy in
+/// the storage.
+pub const TOWER_REPAIR_MIN_STORAGE_ENERGY: u32 = 20_000;
+
+/// Maximum CPU used by room planning in a tick. A planning step is only started if the most expensive step so far
+/// still fits, except for the first step in the tick. Unfinished plans are continued in the next tick.
+pub const ROOM_PLANNING_TICK_CPU: f64 = 10.0;
+
+/// Rooms are claimed only within this linear distance of already owned rooms.
+pub const MAX_EXPANSION_RANGE: u32 = 5;
+/// Candidate rooms are planned and claimed only while the CPU bucket has at least this much CPU.
+pub const MIN_EXPANSION_CPU_BUCKET: i32 = 5000;
+
+/// Energy kept in the storage of a room at RCL8 with nothing to build before the surplus is spent
+/// on anything else.
+pub const STEADY_STATE_STORAGE_TARGET_ENERGY: u32 = 200_000;
+
+/// An owned room enters the emergency economy mode, spawning only the creeps needed to restore its
+/// energy income, when its storage energy drops below this.
+pub const EMERGENCY_STORAGE_ENERGY_LOW: u32 = 10_000;
+/// The emergency economy mode ends once the storage energy is back above this.
+pub const EMERGENCY_STORAGE_ENERGY_HIGH: u32 = 30_000;
+
+/// The compound produced in the labs and used to boost the upgraders.
+pub const UPGRADER_BOOST_COMPOUND: ResourceType = ResourceType::CatalyzedGhodiumAcid;
+/// Labs produce the upgrader boost until the room has this much of it.
+pub const TARGET_BOOST_COMPOUND_AMOUNT: u32 = 3000;
+
+/// Energy kept in the terminal of each room for sending resources.
+pub const TERMINAL_ENERGY_BUFFER: u32 = 20_000;
+/// Minerals and compounds in the storage and terminal of a room above this amount of each are
+/// moved to the terminal and sold.
+pub const TERMINAL_RESOURCE_CAP: u32 = 30_000;
+/// The value of energy in credits, used to subtract the energy cost of market transactions from
+/// the price.
+pub const CREDITS_PER_ENERGY: f64 = 0.5;
+
+/// Energy is sent through terminals to owned rooms with less than this much energy in the storage
+/// and terminal.
+pub const MIN_BALANCED_ROOM_ENERGY: u32 = 50_000;
+/// Only rooms with more than this much energy in the storage and terminal send it to other rooms.
+pub const MIN_ENERGY_DONOR_ROOM_ENERGY: u32 = 100_000;
+/// Base minerals are sent between owned rooms when they differ from the average amount by more
+/// than this.
+pub const MINERAL_BALANCING_THRESHOLD: u32 = 5_000;
+/// Maximum amount of a resource sent in a single transfer when balancing rooms.
+pub const MAX_BALANCING_TRANSFER_AMOUNT: u32 = 25_000;
+
+/// The CPU level is low, and critical if the bucket is still being drained, when the CPU bucket
+/// has less than this.
+pub const LOW_CPU_BUCKET: i32 = 2000;
+/// The CPU level is high when the CPU bucket has at least this and there is CPU to spare.
+pub const HIGH_CPU_BUCKET: i32 = 9000;
@@ -0,0 +1,237 @@
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::construction::place_construction_sites::{GameStructureRemover, StructureRemover};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::requests::HaulFlow::Loot;
+use crate::hauling::requests::HaulRequest;
+use crate::hauling::requests::HaulRequestKind::WithdrawRequest;
+use crate::hauling::requests::HaulRequestTargetKind::RegularTarget;
+use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::get_used_capacities_with_object;
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::kernel::sleep::sleep;
+use crate::room_planning::plan::Plan;
+use crate::room_states::room_states::with_room_state;
+use crate::utils::find::get_structure;
+use crate::utils::game_tick::game_tick;
+use crate::utils::priority::Priority;
+use crate::{local_debug, u};
+use log::{debug, warn};
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::StructureType::*;
+use screeps::{find, game, HasPosition, MaybeHasId, ObjectId, RoomName, RoomXY, Structure, StructureType};
+
+const DEBUG: bool = true;
+
+/// Number of ticks between checks for structures to demolish.
+const DEMOLITION_INTERVAL: u32 = 100;
+
+/// Maximum number of ticks spent looting a structure before it is destroyed anyway.
+const MAX_LOOTING_TICKS: u32 = 1500;
+
+/// Destroys hostile structures and unowned structures conflicting with the plan in an owned room,
+/// e.g., ones left after claiming a room that belonged to another player. The storage and terminal
+/// are looted before being destroyed.
+pub async fn demolish_hostile_structures(room_name: RoomName) {
+    loop {
+        let hostile_structures = hostile_structures(room_name);
+        let demolished_structures = u!(with_room_state(room_name, |room_state| {
+            demolition_order(&room_state.structures, &hostile_structures, room_state.plan.as_ref())
+        }));
+
+        for (structure_type, xy) in demolished_structures {
+            if structure_type == Storage || structure_type == Terminal {
+                loot_structure(room_name, structure_type, xy).await;
+            }
+
+            debug!("Demolishing {:?} in {} at {}.", structure_type, room_name, xy);
+            GameStructureRemover.destroy_structure(room_name, xy, structure_type);
+        }
+
+        sleep(DEMOLITION_INTERVAL).await;
+    }
+}
+
+/// Structures in the room owned by another player.
+fn hostile_structures(room_name: RoomName) -> FxHashSet<(StructureType, RoomXY)> {
+    let Some(room) = game::rooms().get(room_name) else {
+        return FxHashSet::default();
+    };
+
+    room.find(find::HOSTILE_STRUCTURES, None)
+        .into_iter()
+        .map(|structure_obj| {
+            let structure = structure_obj.as_structure();
+            (structure.structure_type(), structure.pos().xy())
+        })
+        .collect()
+}
+
+/// Computes the order in which structures in an owned room are destroyed. Hostile structures are
+/// always destroyed, while unowned ones only when they are not in the plan. Own structures are
+/// handled when placing construction sites.
+/// Walls and ramparts standing where roads are planned go first so that the room can be traversed.
+/// The terminal and then the storage go last so that they can be looted beforehand.
+pub fn demolition_order(
+    structures: &FxHashMap<StructureType, FxHashMap<RoomXY, ObjectId<Structure>>>,
+    hostile_structures: &FxHashSet<(StructureType, RoomXY)>,
+    plan: Option<&Plan>,
+) -> Vec<(StructureType, RoomXY)> {
+    let is_planned = |structure_type: StructureType, xy: RoomXY| {
+        plan.is_some_and(|plan| plan.tiles.get(xy).iter().any(|planned_type| planned_type == structure_type))
+    };
+
+    let mut demolished_structures = structures
+        .iter()
+        .flat_map(|(&structure_type, xys)| xys.keys().map(move |&xy| (structure_type, xy)))
+        .filter(|&(structure_type, xy)| {
+            if hostile_structures.contains(&(structure_type, xy)) {
+                true
+            } else {
+                let unowned = matches!(structure_type, Wall | Road | Container);
+                unowned && plan.is_some() && !is_planned(structure_type, xy)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    demolished_structures.sort_by_key(|&(structure_type, xy)| {
+        let stage = match structure_type {
+            Wall | Rampart if is_planned(Road, xy) => 0,
+            Terminal => 2,
+            Storage => 3,
+            _ => 1,
+        };
+        // Ramparts first, so that what they protect becomes accessible.
+        (stage, structure_type != Rampart, xy)
+    });
+
+    demolished_structures
+}
+
+/// Withdraws all resources from the structure until it is empty or for at most
+/// `MAX_LOOTING_TICKS` ticks.
+async fn loot_structure(room_name: RoomName, structure_type: StructureType, xy: RoomXY) {
+    let pos = xy.to_pos(room_name);
+    let start_tick = game_tick();
+    let mut withdraw_requests = FxHashMap::default();
+
+    while game_tick() < start_tick + MAX_LOOTING_TICKS {
+        let Some(structure_obj) = get_structure(room_name, xy, structure_type) else {
+            return;
+        };
+        let id = u!(structure_obj.as_structure().try_id());
+        let Some(store) = structure_obj.as_has_store() else {
+            return;
+        };
+        let used_capacities = get_used_capacities_with_object(store, id.into(), AfterAllTransfers);
+        if used_capacities.is_empty() {
+            local_debug!("Finished looting {:?} in {} at {}.", structure_type, room_name, xy);
+            return;
+        }
+
+        // Resources that are no longer in the structure do not need to be withdrawn.
+        withdraw_requests.retain(|resource_type, _| used_capacities.contains_key(resource_type));
+
+        for (&resource_type, &amount) in used_capacities.iter() {
+            // The previous requests are replaced by these ones.
+            let mut withdraw_request = HaulRequest::new(
+                WithdrawRequest,
+                room_name,
+                resource_type,
+                id,
+                RegularTarget,
+                false,
+                pos
+            );
+            withdraw_request.amount = amount;
+            withdraw_request.priority = Priority(120);
+            withdraw_request.flow = Loot;
+            let previous_withdraw_request = withdraw_requests.remove(&resource_type);
+            withdraw_requests.insert(resource_type, schedule_haul(withdraw_request, previous_withdraw_request));
+        }
+
+        sleep(1).await;
+    }
+
+    warn!(
+        "Failed to fully loot {:?} in {} at {} within {} ticks.",
+        structure_type, room_name, xy, MAX_LOOTING_TICKS
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::construction::demolish_hostile_structures::demolition_order;
+    use crate::room_planning::plan::{Plan, PlanScore, PlannedControllerData};
+    use crate::room_planning::planned_tile::PlannedTile;
+    use crate::room_states::room_state::RoomState;
+    use rustc_hash::{FxHashMap, FxHashSet};
+    use screeps::StructureType::{Extension, Rampart, Road, Spawn, Storage, Terminal, Tower, Wall};
+    use screeps::{ObjectId, RoomName, RoomXY, StructureType};
+
+    #[test]
+    fn test_demolition_order() {
+        let mut room_state = RoomState::new(RoomName::new("W1N1").unwrap());
+        let xy = |x: u8, y: u8| -> RoomXY { (x, y).try_into().unwrap() };
+
+        let mut tiles = RoomMatrix::default();
+        tiles.set(xy(11, 11), PlannedTile::from(Road));
+        tiles.set(xy(12, 12), PlannedTile::from(Road));
+        tiles.set(xy(30, 30), PlannedTile::from(Road));
+        tiles.set(xy(5, 5), PlannedTile::from(Wall));
+        room_state.plan = Some(Plan::new(
+            tiles,
+            PlannedControllerData::default(),
+            Vec::new(),
+            None,
+            PlanScore::default(),
+            Vec::new(),
+            Vec::new(),
+        ));
+
+        let seeded_structures: [(StructureType, RoomXY); 11] = [
+            (Spawn, xy(10, 10)),
+            (Rampart, xy(10, 10)),
+            (Rampart, xy(11, 11)),
+            (Wall, xy(12, 12)),
+            (Tower, xy(15, 15)),
+            (Storage, xy(20, 20)),
+            (Terminal, xy(21, 21)),
+            (Wall, xy(25, 25)),
+            (Road, xy(30, 30)),
+            (Wall, xy(5, 5)),
+            (Extension, xy(40, 40)),
+        ];
+        for (i, &(structure_type, xy)) in seeded_structures.iter().enumerate() {
+            room_state
+                .structures
+                .entry(structure_type)
+                .or_default()
+                .insert(xy, ObjectId::from_packed(i as u128 + 1));
+        }
+        // The extension is owned by the player.
+        let hostile_structures = FxHashSet::from_iter([
+            (Spawn, xy(10, 10)),
+            (Rampart, xy(10, 10)),
+            (Rampart, xy(11, 11)),
+            (Tower, xy(15, 15)),
+            (Storage, xy(20, 20)),
+            (Terminal, xy(21, 21)),
+        ]);
+
+        let order = demolition_order(&room_state.structures, &hostile_structures, room_state.plan.as_ref());
+
+        assert_eq!(order, vec![
+            (Rampart, xy(11, 11)),
+            (Wall, xy(12, 12)),
+            (Rampart, xy(10, 10)),
+            (Spawn, xy(10, 10)),
+            (Tower, xy(15, 15)),
+            (Wall, xy(25, 25)),
+            (Terminal, xy(21, 21)),
+            (Storage, xy(20, 20)),
+        ]);
+        assert!(demolition_order(&FxHashMap::default(), &hostile_structures, None).is_empty());
+    }
+}
@@ -1,4 +1,8 @@
 pub mod build_structures;
+pub mod demolish_hostile_structures;
 pub mod place_construction_sites;
+pub mod reachability;
 pub mod repair_structures;
+pub mod repair_tours;
+pub mod road_usage;
 pub mod triage_repair_sites;
\ No newline at end of file
@@ -12,7 +12,11 @@ use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::game::{construction_sites, rooms};
 use screeps::StructureType::*;
 use screeps::{game, ConstructionSite, HasPosition, MaybeHasId, ObjectId, Position, RoomName, RoomXY, Structure, StructureType};
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::room_planning::plan::Plan;
 use crate::room_states::room_state::StructuresMap;
+use crate::construction::road_usage::used_planned_roads;
+use crate::construction::reachability::is_reachable;
 
 const DEBUG: bool = true;
 
@@ -81,14 +85,22 @@ pub async fn place_construction_sites() {
                     "Computing what construction sites to place in room {} at RCL {}.",
                     room_name, room_state.rcl
                 );
+                // Roads are only built where creeps walk.
+                let mut planned_structures = room_state.current_rcl_structures.clone();
+                if let Some(plan) = room_state.plan.as_ref() {
+                    planned_structures.insert(Road, used_planned_roads(plan, room_state.rcl, &room_state.road_usage));
+                }
+
                 // Computing which structures are missing and which are not in the plan.
                 let StructuresDiff {
                     extra_structures,
-                    missing_structures_by_priority
+                    mut missing_structures_by_priority
                 } = room_structures_diff_from_current_rcl_structures(
-                    &room_state.current_rcl_structures,
+                    &planned_structures,
+                    &room_state.current_rcl_build_order,
                     &room_state.structures
                 );
+                prioritize_recently_destroyed(&mut missing_structures_by_priority, &room_state.recently_destroyed);
 
                 // Cannot remove a structure that cannot be in the same place as the new one
                 // and create a construction site in the same tick in the same place.
@@ -102,47 +114,28 @@ pub async fn place_construction_sites() {
                     .copied()
                     .collect::<FxHashSet<_>>();
 
-                // Removing extra structures.
-                // TODO Remove all previous owner's structures.
-                let mut number_of_spawns = room_state
+                // Tiles where a structure required at the current RCL is missing. Structures there
+                // that cannot coexist with it have to be removed even if they are in the plan.
+                let blocking_xys = missing_structures_by_priority
+                    .iter()
+                    .map(|&(_, xy)| xy)
+                    .collect::<FxHashSet<_>>();
+
+                // Removing extra structures. Previous owner's structures are removed when demolishing
+                // hostile structures.
+                let number_of_spawns = room_state
                     .structures
                     .get(&Spawn)
                     .map(|xys| xys.len())
                     .unwrap_or(0);
-                for (structure_type, xys) in extra_structures {
-                    for xy in xys {
-                        // There is an extra structure in the room. It might happen upon claiming
-                        // a room with structures present or when the room was downgraded.
-                        if structure_type == Spawn && number_of_spawns == 1 {
-                            warn!(
-                                "The only {:?} in {} at {} is in an incorrect place. Not removing it.",
-                                structure_type, room_name, xy,
-                            );
-                        } else {
-                            // Destroying the structure.
-                            if let Some(structure_obj) = get_structure(room_name, xy, structure_type) {
-                                // TODO Do not destroy the structure if it is owned and supposed
-                                //      to be built at RCL8 in that location unless it being
-                                //      inactive breaks something (e.g., remote links being
-                                //      active while the fast filler link is not).
-                                // TODO This should be some API constant, not just zero.
-                                if structure_obj.as_structure().destroy() != 0 {
-                                    warn!(
-                                        "Failed to remove a structure {:?} in {} at {}",
-                                        structure_type, room_name, xy
-                                    );
-                                }
-
-                                if structure_type == Spawn {
-                                    number_of_spawns -= 1;
-                                }
-                            } else {
-                                error!("Failed to find the structure {:?} in {} at {} that was about to be removed",
-                                    structure_type, room_name, xy);
-                            }
-                        }
-                    }
-                }
+                remove_extra_structures(
+                    room_name,
+                    extra_structures,
+                    &blocking_xys,
+                    room_state.plan.as_ref(),
+                    number_of_spawns,
+                    &mut GameStructureRemover,
+                );
 
                 // Computing which construction sites are missing and which are not in the plan
                 // or not top priority.
@@ -152,7 +145,7 @@ pub async fn place_construction_sites() {
                 let room_construction_sites_count = room_construction_sites.len();
 
                 let ConstructionSitesDiff {
-                    extra_construction_sites,
+                    mut extra_construction_sites,
                     correct_construction_sites,
                     missing_construction_sites
                 } = construction_sites_diff_from_top_priority_missing_structures(
@@ -160,6 +153,17 @@ pub async fn place_construction_sites() {
                     room_construction_sites
                 );
 
+                // Keeping construction sites of structures in the plan for a higher RCL unless they
+                // block a structure required now. They still count towards the limit.
+                extra_construction_sites.retain(|cs| {
+                    !is_in_plan_and_not_blocking(
+                        room_state.plan.as_ref(),
+                        &blocking_xys,
+                        cs.structure_type,
+                        cs.pos.xy()
+                    )
+                });
+
                 xys_not_for_new_cs.extend(
                     extra_construction_sites
                         .iter()
@@ -178,15 +182,17 @@ pub async fn place_construction_sites() {
                 room_state
                     .construction_site_queue
                     .extend(room_state.extra_construction_sites.iter().cloned());
+
+                // Only building what is allowed in the current lifecycle of the room.
+                let lifecycle = room_state.lifecycle;
+                room_state
+                    .construction_site_queue
+                    .retain(|cs| lifecycle.allows_construction(cs.structure_type));
                 
                 // Removing invalid construction sites.
                 // TODO Do not remove construction site with decent progress on them.
-                for cs in extra_construction_sites {
-                    let construction_site = u!(game::get_object_by_id_typed(&cs.id));
-                    construction_site.remove().warn_if_err(&format!(
-                        "Failed to remove a construction site of {:?} in {} at {}",
-                        cs.structure_type, room_name, cs.pos.xy()
-                    ));
+                for cs in extra_construction_sites.iter() {
+                    GameStructureRemover.remove_construction_site(room_name, cs);
                 }
                 
                 // Placing construction sites with the top priority.
@@ -196,6 +202,7 @@ pub async fn place_construction_sites() {
                 // other construction sites only to remove
                 let placed_construction_sites = missing_construction_sites
                     .iter()
+                    .filter(|&&(structure_type, _)| lifecycle.allows_construction(structure_type))
                     .take(construction_sites_left_to_limit);
                 for &(structure_type, xy) in placed_construction_sites {
                     if xys_not_for_new_cs.contains(&xy) {
@@ -203,6 +210,12 @@ pub async fn place_construction_sites() {
                             "Cannot place construction site for {:?} in {} at {} since something else is there.",
                             structure_type, room_name, xy
                         );
+                    } else if !is_reachable(room_state, xy) {
+                        // Builders would fail to get there while the construction site counts towards the limit.
+                        debug!(
+                            "Deferring construction site for {:?} in {} at {} since it is not reachable.",
+                            structure_type, room_name, xy
+                        );
                     } else {
                         xys_not_for_new_cs.insert(xy);
                         debug!(
@@ -233,13 +246,101 @@ pub async fn place_construction_sites() {
     }
 }
 
+/// Removes structures and construction sites in the game. Abstracted away from deciding what to
+/// remove so that the decisions can be tested.
+pub trait StructureRemover {
+    /// Destroys the structure. Returns whether it was found.
+    fn destroy_structure(&mut self, room_name: RoomName, xy: RoomXY, structure_type: StructureType) -> bool;
+
+    fn remove_construction_site(&mut self, room_name: RoomName, cs: &ConstructionSiteData);
+}
+
+pub struct GameStructureRemover;
+
+impl StructureRemover for GameStructureRemover {
+    fn destroy_structure(&mut self, room_name: RoomName, xy: RoomXY, structure_type: StructureType) -> bool {
+        if let Some(structure_obj) = get_structure(room_name, xy, structure_type) {
+            // TODO This should be some API constant, not just zero.
+            if structure_obj.as_structure().destroy() != 0 {
+                warn!(
+                    "Failed to remove a structure {:?} in {} at {}",
+                    structure_type, room_name, xy
+                );
+            }
+            true
+        } else {
+            error!("Failed to find the structure {:?} in {} at {} that was about to be removed",
+                structure_type, room_name, xy);
+            false
+        }
+    }
+
+    fn remove_construction_site(&mut self, room_name: RoomName, cs: &ConstructionSiteData) {
+        let construction_site = u!(game::get_object_by_id_typed(&cs.id));
+        construction_site.remove().warn_if_err(&format!(
+            "Failed to remove a construction site of {:?} in {} at {}",
+            cs.structure_type, room_name, cs.pos.xy()
+        ));
+    }
+}
+
+/// Whether the structure is anywhere in the plan for RCL8 and does not stand where a structure
+/// required at the current RCL is missing. Ramparts never block other structures.
+fn is_in_plan_and_not_blocking(
+    plan: Option<&Plan>,
+    blocking_xys: &FxHashSet<RoomXY>,
+    structure_type: StructureType,
+    xy: RoomXY
+) -> bool {
+    let in_plan = plan.is_some_and(|plan| plan.tiles.get(xy).iter().any(|planned_type| planned_type == structure_type));
+    in_plan && (structure_type == Rampart || !blocking_xys.contains(&xy))
+}
+
+/// Removes the extra structures, except ones that are in the plan and do not block structures
+/// required at the current RCL, and the only spawn.
+fn remove_extra_structures<R>(
+    room_name: RoomName,
+    extra_structures: FxHashMap<StructureType, Vec<RoomXY>>,
+    blocking_xys: &FxHashSet<RoomXY>,
+    plan: Option<&Plan>,
+    mut number_of_spawns: usize,
+    remover: &mut R,
+) where
+    R: StructureRemover,
+{
+    for (structure_type, xys) in extra_structures {
+        for xy in xys {
+            // There is an extra structure in the room. It might happen upon claiming
+            // a room with structures present or when the room was downgraded.
+            if is_in_plan_and_not_blocking(plan, blocking_xys, structure_type, xy) {
+                // TODO Remove the structure anyway if it being inactive breaks something (e.g.,
+                //      remote links being active while the fast filler link is not).
+                trace!(
+                    "Keeping {:?} in {} at {} since it is in the plan for a higher RCL.",
+                    structure_type, room_name, xy
+                );
+            } else if structure_type == Spawn && number_of_spawns == 1 {
+                warn!(
+                    "The only {:?} in {} at {} is in an incorrect place. Not removing it.",
+                    structure_type, room_name, xy,
+                );
+            } else if remover.destroy_structure(room_name, xy, structure_type) && structure_type == Spawn {
+                number_of_spawns -= 1;
+            }
+        }
+    }
+}
+
 struct StructuresDiff {
     extra_structures: FxHashMap<StructureType, Vec<RoomXY>>,
     missing_structures_by_priority: Vec<(StructureType, RoomXY)>,
 }
 
+/// Computes extra and missing structures. Missing structures are ordered by the priority of their
+/// type and then by the build order within the type.
 fn room_structures_diff_from_current_rcl_structures(
     planned_structures: &StructuresMap,
+    build_order: &FxHashMap<StructureType, Vec<RoomXY>>,
     existing_structures: &FxHashMap<StructureType, FxHashMap<RoomXY, ObjectId<Structure>>>
 ) -> StructuresDiff {
     let mut extra_structures = FxHashMap::<_, Vec<_>>::default();
@@ -264,12 +365,19 @@ fn room_structures_diff_from_current_rcl_structures(
             }
         }
 
-        // Computing missing structures that should be placed.
-        for &xy in planned_structure_xys.iter() {
-            if !existing_structure_xys.contains(&xy) {
-                missing_structures_by_priority.push((structure_type, xy));
-            }
-        }
+        // Computing missing structures that should be placed, in the build order.
+        let build_order_indices = build_order
+            .get(&structure_type)
+            .iter()
+            .flat_map(|xys| xys.iter().enumerate().map(|(i, &xy)| (xy, i)))
+            .collect::<FxHashMap<_, _>>();
+        let mut missing_structure_xys = planned_structure_xys
+            .iter()
+            .copied()
+            .filter(|xy| !existing_structure_xys.contains(xy))
+            .collect::<Vec<_>>();
+        missing_structure_xys.sort_by_key(|xy| (build_order_indices.get(xy).copied().unwrap_or(usize::MAX), *xy));
+        missing_structures_by_priority.extend(missing_structure_xys.into_iter().map(|xy| (structure_type, xy)));
     }
 
     StructuresDiff {
@@ -278,6 +386,31 @@ fn room_structures_diff_from_current_rcl_structures(
     }
 }
 
+/// Moves recently destroyed structures before the others, with towers and ramparts first, keeping
+/// the order otherwise.
+fn prioritize_recently_destroyed(
+    missing_structures_by_priority: &mut [(StructureType, RoomXY)],
+    recently_destroyed: &[(StructureType, RoomXY, u32)]
+) {
+    if recently_destroyed.is_empty() {
+        return;
+    }
+
+    let recently_destroyed_set = recently_destroyed
+        .iter()
+        .map(|&(structure_type, xy, _)| (structure_type, xy))
+        .collect::<FxHashSet<_>>();
+    missing_structures_by_priority.sort_by_key(|&(structure_type, xy)| {
+        if !recently_destroyed_set.contains(&(structure_type, xy)) {
+            2
+        } else if structure_type == Tower || structure_type == Rampart {
+            0
+        } else {
+            1
+        }
+    });
+}
+
 struct ConstructionSitesDiff {
     correct_construction_sites: Vec<ConstructionSiteData>,
     extra_construction_sites: Vec<ConstructionSiteData>,
@@ -333,3 +466,144 @@ fn structure_js_name(structure_type: StructureType, room_name: RoomName, xy: Roo
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::construction::place_construction_sites::{prioritize_recently_destroyed, remove_extra_structures, room_structures_diff_from_current_rcl_structures, ConstructionSiteData, StructureRemover};
+    use crate::room_planning::plan::{Plan, PlanScore, PlannedControllerData};
+    use crate::room_planning::planned_tile::PlannedTile;
+    use crate::room_planning::plan_rooms::build_order;
+    use crate::room_states::packed_terrain::PackedTerrain;
+    use crate::room_states::room_state::RoomState;
+    use rustc_hash::{FxHashMap, FxHashSet};
+    use screeps::StructureType::{Extension, Road, Spawn, Storage, Tower};
+    use screeps::{ObjectId, RoomName, RoomXY, StructureType};
+
+    #[derive(Default)]
+    struct TestStructureRemover {
+        destroyed: Vec<(StructureType, RoomXY)>,
+    }
+
+    impl StructureRemover for TestStructureRemover {
+        fn destroy_structure(&mut self, _: RoomName, xy: RoomXY, structure_type: StructureType) -> bool {
+            self.destroyed.push((structure_type, xy));
+            true
+        }
+
+        fn remove_construction_site(&mut self, _: RoomName, _: &ConstructionSiteData) {}
+    }
+
+    #[test]
+    fn test_structures_in_plan_are_not_destroyed() {
+        let mut room_state = RoomState::new(RoomName::new("W1N1").unwrap());
+        let extension_xy: RoomXY = (20, 20).try_into().unwrap();
+        let blocking_extension_xy: RoomXY = (21, 20).try_into().unwrap();
+        let road_xy: RoomXY = (22, 20).try_into().unwrap();
+        let mut tiles = RoomMatrix::default();
+        tiles.set(extension_xy, PlannedTile::from(Extension).with_min_rcl(8));
+        tiles.set(blocking_extension_xy, PlannedTile::from(Extension).with_min_rcl(8));
+        room_state.plan = Some(Plan::new(
+            tiles,
+            PlannedControllerData::default(),
+            Vec::new(),
+            None,
+            PlanScore::default(),
+            Vec::new(),
+            Vec::new(),
+        ));
+        // A spawn is required at the current RCL where one of the extensions is.
+        let blocking_xys = FxHashSet::from_iter([blocking_extension_xy]);
+
+        let mut extra_structures = FxHashMap::default();
+        extra_structures.insert(Extension, vec![extension_xy, blocking_extension_xy]);
+        extra_structures.insert(Road, vec![road_xy]);
+        extra_structures.insert(Spawn, vec![(30, 30).try_into().unwrap()]);
+
+        let mut remover = TestStructureRemover::default();
+        remove_extra_structures(
+            room_state.room_name,
+            extra_structures,
+            &blocking_xys,
+            room_state.plan.as_ref(),
+            1,
+            &mut remover,
+        );
+
+        remover.destroyed.sort_by_key(|&(_, xy)| xy);
+        assert_eq!(remover.destroyed, vec![(Extension, blocking_extension_xy), (Road, road_xy)]);
+    }
+
+    #[test]
+    fn test_missing_structures_in_build_order() {
+        let mut tiles = RoomMatrix::default();
+        tiles.set((25, 25).try_into().unwrap(), PlannedTile::from(Storage).with_min_rcl(4));
+        let extensions = [
+            ((26, 25), 2),
+            ((30, 25), 2),
+            ((27, 25), 3),
+            ((25, 27), 2),
+            ((20, 25), 3),
+            ((25, 26), 4),
+            ((35, 25), 2),
+            ((28, 28), 3),
+        ];
+        for ((x, y), min_rcl) in extensions {
+            tiles.set((x, y).try_into().unwrap(), PlannedTile::from(Extension).with_min_rcl(min_rcl));
+        }
+        let plan = Plan::new(
+            tiles,
+            PlannedControllerData::default(),
+            Vec::new(),
+            None,
+            PlanScore::default(),
+            Vec::new(),
+            Vec::new(),
+        );
+        let mut planned_structures = FxHashMap::default();
+        planned_structures.insert(
+            Extension,
+            extensions
+                .iter()
+                .map(|&((x, y), _)| RoomXY::try_from((x, y)).unwrap())
+                .collect::<FxHashSet<_>>(),
+        );
+
+        let order = build_order(&plan, &PackedTerrain::new(), &planned_structures);
+        let diff = room_structures_diff_from_current_rcl_structures(&planned_structures, &order, &FxHashMap::default());
+
+        let chosen = diff
+            .missing_structures_by_priority
+            .into_iter()
+            .take(5)
+            .map(|(_, xy)| (xy.x.u8(), xy.y.u8()))
+            .collect::<Vec<_>>();
+        assert_eq!(chosen, vec![(26, 25), (25, 27), (30, 25), (35, 25), (27, 25)]);
+    }
+
+    #[test]
+    fn test_recently_destroyed_tower_is_rebuilt_first() {
+        let spawn_xy: RoomXY = (25, 25).try_into().unwrap();
+        let extension_xy: RoomXY = (26, 26).try_into().unwrap();
+        let tower_xy: RoomXY = (27, 27).try_into().unwrap();
+        let mut planned_structures = FxHashMap::default();
+        planned_structures.insert(Spawn, FxHashSet::from_iter([spawn_xy]));
+        planned_structures.insert(Extension, FxHashSet::from_iter([extension_xy]));
+        planned_structures.insert(Tower, FxHashSet::from_iter([tower_xy]));
+        // Only the spawn exists after the tower was destroyed and before the extension was built.
+        let mut existing_structures = FxHashMap::default();
+        existing_structures.insert(Spawn, FxHashMap::from_iter([(spawn_xy, ObjectId::from_packed(1))]));
+
+        let diff = room_structures_diff_from_current_rcl_structures(
+            &planned_structures,
+            &FxHashMap::default(),
+            &existing_structures,
+        );
+        let mut missing_structures = diff.missing_structures_by_priority;
+        assert_eq!(missing_structures, vec![(Extension, extension_xy), (Tower, tower_xy)]);
+
+        prioritize_recently_destroyed(&mut missing_structures, &[(Tower, tower_xy, 100)]);
+        assert_eq!(missing_structures, vec![(Tower, tower_xy), (Extension, extension_xy)]);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::consts::UNREACHABLE_COST;
+use crate::geometry::rect::{ball, room_rect};
+use crate::room_states::room_state::RoomState;
+use crate::travel::surface::Surface;
+use screeps::StructureType::{Spawn, Storage};
+use screeps::{RoomXY, CREEP_RANGED_ACTION_RANGE};
+
+/// Whether a creep coming from a spawn or the storage can get within the building range of the tile, with current
+/// structures and construction sites as obstacles. Assumed to be true when the room has neither a spawn nor storage.
+pub fn is_reachable(room_state: &RoomState, xy: RoomXY) -> bool {
+    let origin_xys = [Spawn, Storage]
+        .into_iter()
+        .flat_map(|structure_type| room_state.structures.get(&structure_type))
+        .flat_map(|xys| xys.keys().copied())
+        .collect::<Vec<_>>();
+    if origin_xys.is_empty() {
+        return true;
+    }
+
+    let obstacles = room_rect()
+        .iter()
+        .filter(|&xy| room_state.tile_surface(xy) == Surface::Obstacle);
+    let dm = distance_matrix(obstacles, origin_xys.into_iter());
+
+    ball(xy, CREEP_RANGED_ACTION_RANGE)
+        .iter()
+        .any(|near| dm.get(near) < UNREACHABLE_COST)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::construction::reachability::is_reachable;
+    use crate::geometry::rect::ball;
+    use crate::room_states::room_state::RoomState;
+    use screeps::StructureType::{Spawn, Wall};
+    use screeps::{ObjectId, RoomName, RoomXY};
+
+    #[test]
+    fn test_enclosed_tile_is_not_reachable() {
+        let mut room_state = RoomState::new(RoomName::new("W1N1").unwrap());
+        let spawn_xy: RoomXY = (10, 10).try_into().unwrap();
+        let enclosed_xy: RoomXY = (30, 30).try_into().unwrap();
+        let outside_xy: RoomXY = (30, 40).try_into().unwrap();
+
+        room_state
+            .structures
+            .entry(Spawn)
+            .or_default()
+            .insert(spawn_xy, ObjectId::from_packed(1));
+        // A ring of walls just outside of the building range of the enclosed tile.
+        for (i, xy) in ball(enclosed_xy, 4).boundary().enumerate() {
+            room_state
+                .structures
+                .entry(Wall)
+                .or_default()
+                .insert(xy, ObjectId::from_packed(i as u128 + 2));
+        }
+        room_state.update_structures_matrix();
+
+        assert!(!is_reachable(&room_state, enclosed_xy));
+        assert!(is_reachable(&room_state, outside_xy));
+    }
+}
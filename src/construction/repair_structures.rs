@@ -1,16 +1,27 @@
-use screeps::{ResourceType, RoomName, CREEP_RANGED_ACTION_RANGE};
+use std::cell::RefCell;
+use std::rc::Rc;
+use log::trace;
+use rustc_hash::FxHashSet;
+use screeps::{ObjectId, ResourceType, RoomName, RoomXY, Structure, StructureType, CREEP_RANGED_ACTION_RANGE};
+use crate::construction::repair_tours::{plan_repair_tour, RepairTour, RepairTourStop};
+use crate::construction::triage_repair_sites::RepairSiteData;
+use crate::creeps::actions::withdraw_when_able;
 use crate::creeps::creep_role::CreepRole::Repairer;
+use crate::creeps::creeps::CreepRef;
 use crate::geometry::room_xy::RoomXYUtils;
 use crate::hauling::requests::HaulRequest;
 use crate::hauling::requests::HaulRequestKind::DepositRequest;
 use crate::hauling::requests::HaulRequestTargetKind::CreepTarget;
 use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::get_used_capacity_unchecked;
 use crate::hauling::transfers::TransferStage::AfterAllTransfers;
 use crate::kernel::sleep::sleep;
 use crate::kernel::wait_until_some::wait_until_some;
+use crate::room_states::room_state::RoomState;
 use crate::room_states::room_states::with_room_state;
 use crate::spawning::spawn_pool::{SpawnPool, SpawnPoolOptions};
 use crate::spawning::spawn_schedule::generic_base_spawn_request;
+use crate::travel::park::park;
 use crate::travel::travel::travel;
 use crate::travel::travel_spec::TravelSpec;
 use crate::u;
@@ -18,6 +29,43 @@ use crate::utils::get_object_by_id::structure_object_by_id;
 use crate::utils::priority::Priority;
 use crate::utils::result_utils::ResultUtils;
 
+type ClaimedRepairSites = Rc<RefCell<FxHashSet<ObjectId<Structure>>>>;
+
+/// Repair sites in the tour of a single repairer. Other repairers do not include them in their
+/// tours. The claim is released when dropped, e.g., when the repairer dies.
+struct RepairTourClaim {
+    claimed_repair_sites: ClaimedRepairSites,
+    ids: Vec<ObjectId<Structure>>,
+}
+
+impl RepairTourClaim {
+    fn new(claimed_repair_sites: ClaimedRepairSites) -> Self {
+        RepairTourClaim {
+            claimed_repair_sites,
+            ids: Vec::new(),
+        }
+    }
+
+    fn claim(&mut self, tour: &RepairTour) {
+        self.release();
+        self.ids = tour.site_ids().collect();
+        self.claimed_repair_sites.borrow_mut().extend(self.ids.iter().copied());
+    }
+
+    fn release(&mut self) {
+        let mut claimed_repair_sites = self.claimed_repair_sites.borrow_mut();
+        for id in self.ids.drain(..) {
+            claimed_repair_sites.remove(&id);
+        }
+    }
+}
+
+impl Drop for RepairTourClaim {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
 pub async fn repair_structures(room_name: RoomName) {
     let base_spawn_request = u!(with_room_state(room_name, |room_state| {
         generic_base_spawn_request(room_state, Repairer)
@@ -26,6 +74,8 @@ pub async fn repair_structures(room_name: RoomName) {
     let spawn_pool_options = SpawnPoolOptions::default();
     let mut spawn_pool = SpawnPool::new(room_name, base_spawn_request, spawn_pool_options);
 
+    let claimed_repair_sites = ClaimedRepairSites::default();
+
     loop {
         let (repairers_required, repairer_body) = wait_until_some(|| with_room_state(room_name, |room_state| {
             room_state
@@ -37,98 +87,237 @@ pub async fn repair_structures(room_name: RoomName) {
         }).flatten()).await;
         spawn_pool.target_number_of_creeps = repairers_required;
         spawn_pool.base_spawn_request.body = repairer_body;
-        
-        spawn_pool.with_spawned_creeps(|creep_ref| async move {
-            let capacity = u!(creep_ref.borrow_mut().carry_capacity());
-            let creep_id = u!(creep_ref.borrow_mut().screeps_id());
-            let repair_energy_consumption = creep_ref.borrow().body.repair_energy_usage();
-            
-            loop {
-                let creep_pos = creep_ref.borrow().travel_state.pos;
-                let best_repair_site = u!(with_room_state(room_name, |room_state| {
-                    room_state.triaged_repair_sites.choose_repair_site(creep_pos.xy())
-                }));
-                
-                if let Some(repair_site) = best_repair_site {
-                    let travel_spec = TravelSpec::new(
-                        repair_site.xy.to_pos(creep_pos.room_name()),
-                        CREEP_RANGED_ACTION_RANGE
-                    );
-                    
-                    if let Err(err) = travel(&creep_ref, travel_spec.clone()).await {
-                        err.warn("Repairer could not reach its destination");
-                        // Trying next tick (if the creep didn't die).
-                        sleep(1).await;
-                        continue;
-                    }
-        
-                    let mut store_request = None;
-        
-                    loop {
+
+        spawn_pool.with_spawned_creeps(|creep_ref| {
+            let claimed_repair_sites = claimed_repair_sites.clone();
+            async move {
+                let capacity = u!(creep_ref.borrow_mut().carry_capacity());
+                let mut tour_claim = RepairTourClaim::new(claimed_repair_sites.clone());
+                let mut tour = RepairTour::default();
+                let mut energy_delivered = true;
+                let mut idle_ticks = 0;
+
+                loop {
+                    let creep_pos = creep_ref.borrow().travel_state.pos;
+
+                    // Planning the tour again only if it is finished or there are new critical
+                    // repair sites.
+                    let replan = u!(with_room_state(room_name, |room_state| {
+                        tour.stops.is_empty()
+                            || tour.critical_sites_hash != room_state.triaged_repair_sites.critical_sites_hash()
+                    }));
+                    if replan {
+                        tour_claim.release();
                         // This can only fail if the creep died, but then this process would be killed.
-                        let current_energy = u!(creep_ref.borrow_mut().used_capacity(Some(ResourceType::Energy), AfterAllTransfers));
-                        if current_energy < capacity {
-                            with_room_state(room_name, |room_state| {
-                                if let Some(eco_stats) = room_state.eco_stats.as_mut() {
-                                    eco_stats.register_idle_creep(Repairer, &creep_ref);
-                                }
-                            });
-                            
-                            let mut new_store_request = HaulRequest::new(
-                                DepositRequest,
-                                room_name,
-                                ResourceType::Energy,
-                                creep_id,
-                                CreepTarget,
-                                false,
-                                creep_ref.borrow().travel_state.pos
+                        let energy = u!(creep_ref.borrow_mut().used_capacity(Some(ResourceType::Energy), AfterAllTransfers));
+                        tour = u!(with_room_state(room_name, |room_state| {
+                            let energy_xys = repair_energy_source_xys(room_state);
+                            energy_delivered = energy_xys.is_empty();
+                            let tour = plan_repair_tour(
+                                &room_state.triaged_repair_sites,
+                                &claimed_repair_sites.borrow(),
+                                creep_pos.xy(),
+                                energy,
+                                capacity,
+                                &energy_xys
                             );
-                            new_store_request.amount = capacity;
-                            new_store_request.priority = Priority(100);
-                            new_store_request.change = repair_energy_consumption as i32;
-                            new_store_request.max_amount = capacity;
-        
-                            store_request = Some(schedule_haul(new_store_request, store_request.take()));
-                        } else {
-                            store_request = None;
+                            if tour.stops.is_empty() {
+                                // All repair sites are in other repairers' tours, so sharing them.
+                                plan_repair_tour(
+                                    &room_state.triaged_repair_sites,
+                                    &FxHashSet::default(),
+                                    creep_pos.xy(),
+                                    energy,
+                                    capacity,
+                                    &energy_xys
+                                )
+                            } else {
+                                tour
+                            }
+                        }));
+                        tour_claim.claim(&tour);
+                        trace!("Repairer in {} planned a tour with {} stops.", room_name, tour.stops.len());
+                    }
+
+                    if !tour.stops.is_empty() {
+                        idle_ticks = 0;
+                    }
+
+                    match tour.stops.pop_front() {
+                        Some(RepairTourStop::Repair(repair_site)) => {
+                            let refill_next = matches!(tour.stops.front(), Some(RepairTourStop::Refill(_)));
+                            let out_of_energy = repair(&creep_ref, room_name, &repair_site, energy_delivered).await;
+                            if out_of_energy && !refill_next {
+                                // The energy estimate of the tour was off. Planning a new one
+                                // starting with a refill.
+                                tour.stops.clear();
+                            }
+                        }
+                        Some(RepairTourStop::Refill(xy)) => {
+                            refill(&creep_ref, room_name, xy).await;
                         }
-                        
-                        // TODO Does this current_energy work or does it need to be one before transfers?
-                        if current_energy >= repair_energy_consumption {
-                            match structure_object_by_id(repair_site.id) {
-                                Ok(target) => {
-                                    let structure_obj = target.as_structure();
-                                    if structure_obj.hits() == structure_obj.hits_max() {
-                                        // Structure is already fully repaired. Remove it from
-                                        // the list and finding a new one.
-                                        with_room_state(room_name, |room_state| {
-                                            room_state.triaged_repair_sites.remove_repair_site(repair_site.id);
-                                        });
-                                        break;
-                                    }
-                                    
-                                    creep_ref
-                                        .borrow_mut()
-                                        .repair(u!(target.as_repairable()))
-                                        .warn_if_err("Failed to repair the structure");
-                                }
-                                Err(e) => {
-                                    e.warn(&format!(
-                                        "Failed to repair {} {}",
-                                        repair_site.structure_type, repair_site.id
-                                    ));
-                                }
+                        None => {
+                            // There is nothing to repair. The creep gets out of the way if it
+                            // stays idle.
+                            idle_ticks += 1;
+                            if idle_ticks > 1 {
+                                with_room_state(room_name, |room_state| park(&creep_ref, room_state));
                             }
+                            sleep(1).await;
                         }
-        
-                        sleep(1).await;
-                    }       
-                } else {
-                    sleep(1).await;
+                    }
                 }
             }
         });
-        
+
+        sleep(1).await;
+    }
+}
+
+/// Positions of structures from which the repairers withdraw energy themselves. If there are none,
+/// the energy is delivered to them by haulers.
+fn repair_energy_source_xys(room_state: &RoomState) -> Vec<RoomXY> {
+    [StructureType::Storage, StructureType::Container]
+        .into_iter()
+        .map(|structure_type| {
+            room_state
+                .structures
+                .get(&structure_type)
+                .map(|structures| structures.keys().copied().collect::<Vec<_>>())
+                .unwrap_or_default()
+        })
+        .find(|xys| !xys.is_empty())
+        .unwrap_or_default()
+}
+
+/// Repairs the structure until it has its target hits. If `energy_delivered` is set, requests
+/// energy from haulers. Otherwise, stops earlier when out of energy and returns true.
+async fn repair(
+    creep_ref: &CreepRef,
+    room_name: RoomName,
+    repair_site: &RepairSiteData,
+    energy_delivered: bool
+) -> bool {
+    let capacity = u!(creep_ref.borrow_mut().carry_capacity());
+    let creep_id = u!(creep_ref.borrow_mut().screeps_id());
+    let repair_energy_consumption = creep_ref.borrow().body.repair_energy_usage();
+    let creep_pos = creep_ref.borrow().travel_state.pos;
+
+    let travel_spec = TravelSpec::new(
+        repair_site.xy.to_pos(creep_pos.room_name()),
+        CREEP_RANGED_ACTION_RANGE
+    );
+
+    if let Err(err) = travel(creep_ref, travel_spec.clone()).await {
+        err.warn("Repairer could not reach its destination");
+        // Trying next tick (if the creep didn't die).
+        sleep(1).await;
+        return false;
+    }
+
+    let mut store_request = None;
+
+    loop {
+        // This can only fail if the creep died, but then this process would be killed.
+        let current_energy = u!(creep_ref.borrow_mut().used_capacity(Some(ResourceType::Energy), AfterAllTransfers));
+
+        if current_energy < repair_energy_consumption && !energy_delivered {
+            return true;
+        }
+
+        if current_energy < capacity && energy_delivered {
+            with_room_state(room_name, |room_state| {
+                if let Some(eco_stats) = room_state.eco_stats.as_mut() {
+                    eco_stats.register_idle_creep(Repairer, creep_ref);
+                }
+            });
+
+            let mut new_store_request = HaulRequest::new(
+                DepositRequest,
+                room_name,
+                ResourceType::Energy,
+                creep_id,
+                CreepTarget,
+                false,
+                creep_ref.borrow().travel_state.pos
+            );
+            new_store_request.amount = capacity;
+            new_store_request.priority = Priority(100);
+            new_store_request.change = repair_energy_consumption as i32;
+            new_store_request.max_amount = capacity;
+
+            store_request = Some(schedule_haul(new_store_request, store_request.take()));
+        } else {
+            store_request = None;
+        }
+
+        // TODO Does this current_energy work or does it need to be one before transfers?
+        if current_energy >= repair_energy_consumption {
+            match structure_object_by_id(repair_site.id) {
+                Ok(target) => {
+                    let structure_obj = target.as_structure();
+                    if structure_obj.hits() >= repair_site.target_hits.min(structure_obj.hits_max()) {
+                        // Structure is already repaired. Removing it from the list.
+                        with_room_state(room_name, |room_state| {
+                            room_state.triaged_repair_sites.remove_repair_site(repair_site.id);
+                        });
+                        return false;
+                    }
+
+                    creep_ref
+                        .borrow_mut()
+                        .repair(u!(target.as_repairable()))
+                        .warn_if_err("Failed to repair the structure");
+                }
+                Err(e) => {
+                    e.warn(&format!(
+                        "Failed to repair {} {}",
+                        repair_site.structure_type, repair_site.id
+                    ));
+                    return false;
+                }
+            }
+        }
+
+        sleep(1).await;
+    }
+}
+
+/// Withdraws energy from the storage or container at given position.
+async fn refill(creep_ref: &CreepRef, room_name: RoomName, xy: RoomXY) {
+    let maybe_id = u!(with_room_state(room_name, |room_state| {
+        [StructureType::Storage, StructureType::Container]
+            .into_iter()
+            .find_map(|structure_type| {
+                room_state
+                    .structures
+                    .get(&structure_type)
+                    .and_then(|structures| structures.get(&xy).copied())
+            })
+    }));
+
+    let Some(id) = maybe_id else {
+        // The structure no longer exists.
+        return;
+    };
+
+    let travel_spec = TravelSpec::new(xy.to_pos(room_name), 1);
+    if let Err(err) = travel(creep_ref, travel_spec).await {
+        err.warn("Repairer could not reach the energy source");
+        sleep(1).await;
+        return;
+    }
+
+    // This can only fail if the creep died, but then this process would be killed.
+    let free_capacity = u!(creep_ref.borrow_mut().free_capacity(AfterAllTransfers));
+    let available_energy = get_used_capacity_unchecked(id.into(), Some(ResourceType::Energy), AfterAllTransfers)
+        .unwrap_or(0);
+    let amount = free_capacity.min(available_energy);
+    if amount > 0 {
+        withdraw_when_able(creep_ref, id.into(), ResourceType::Energy, amount, true)
+            .await
+            .warn_if_err("Repairer failed to withdraw energy");
+        // Waiting for the withdrawal to take effect.
         sleep(1).await;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use rustc_hash::{FxHashSet, FxHasher};
+use screeps::{ObjectId, RoomXY, Structure, REPAIR_POWER};
+use crate::construction::triage_repair_sites::{RepairSiteData, TriagedRepairSites};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::u;
+
+/// The maximum number of full loads of energy the repairer spends on a single tour.
+pub const MAX_TOUR_TRIPS: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub enum RepairTourStop {
+    /// Repair the structure until it has target hits or the repairer runs out of energy.
+    Repair(RepairSiteData),
+    /// Withdraw energy from the container or storage at given position.
+    Refill(RoomXY),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RepairTour {
+    pub stops: VecDeque<RepairTourStop>,
+    /// Hash of the critical repair sites at the time the tour was planned. The tour is planned
+    /// again when it changes.
+    pub critical_sites_hash: u64,
+}
+
+impl RepairTour {
+    pub fn site_ids(&self) -> impl Iterator<Item = ObjectId<Structure>> + '_ {
+        self.stops.iter().filter_map(|stop| match stop {
+            RepairTourStop::Repair(repair_site) => Some(repair_site.id),
+            RepairTourStop::Refill(_) => None,
+        })
+    }
+
+    /// Total distance travelled when following the tour from given position.
+    pub fn travel_distance(&self, start_xy: RoomXY) -> u32 {
+        let mut current_xy = start_xy;
+        let mut distance = 0;
+        for stop in self.stops.iter() {
+            let xy = match stop {
+                RepairTourStop::Repair(repair_site) => repair_site.xy,
+                RepairTourStop::Refill(xy) => *xy,
+            };
+            distance += current_xy.dist(xy) as u32;
+            current_xy = xy;
+        }
+        distance
+    }
+}
+
+impl TriagedRepairSites {
+    /// Hash of IDs of the critical repair sites. Changes in regular repair sites are not deemed
+    /// important enough to re-plan repair tours.
+    pub fn critical_sites_hash(&self) -> u64 {
+        let mut ids = self.critical.iter().map(|repair_site| repair_site.id).collect::<Vec<_>>();
+        ids.sort();
+        let mut hasher = FxHasher::default();
+        ids.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Energy required to fully repair given number of hits.
+pub fn repair_energy_cost(hits: u32) -> u32 {
+    hits.div_ceil(REPAIR_POWER)
+}
+
+/// Plans a tour of a repairer through triaged repair sites, critical ones first and rampart growth
+/// last. Within each priority, the sites are chained greedily to the nearest one. When the energy
+/// carried runs out, a refill at the nearest of `energy_xys` is inserted. If there are none,
+/// the repairer is assumed to have the energy delivered. The tour uses at most `MAX_TOUR_TRIPS`
+/// loads of energy.
+/// Sites in `excluded_ids` are skipped, e.g., because they are in another repairer's tour.
+pub fn plan_repair_tour(
+    triaged_repair_sites: &TriagedRepairSites,
+    excluded_ids: &FxHashSet<ObjectId<Structure>>,
+    start_xy: RoomXY,
+    energy: u32,
+    carry_capacity: u32,
+    energy_xys: &[RoomXY],
+) -> RepairTour {
+    let mut tour = RepairTour {
+        stops: VecDeque::new(),
+        critical_sites_hash: triaged_repair_sites.critical_sites_hash(),
+    };
+
+    if carry_capacity == 0 {
+        return tour;
+    }
+
+    let mut current_xy = start_xy;
+    let mut current_energy = energy;
+    let mut remaining_budget = carry_capacity * MAX_TOUR_TRIPS;
+
+    for repair_sites in [
+        &triaged_repair_sites.critical,
+        &triaged_repair_sites.regular,
+        &triaged_repair_sites.rampart_growth
+    ] {
+        // Pairs of repair sites and energy left to spend on them.
+        let mut remaining = repair_sites
+            .iter()
+            .filter(|repair_site| !excluded_ids.contains(&repair_site.id))
+            .map(|repair_site| (repair_site, repair_energy_cost(repair_site.hits_to_repair)))
+            .collect::<Vec<_>>();
+
+        while !remaining.is_empty() && remaining_budget > 0 {
+            if current_energy == 0 {
+                if let Some(&refill_xy) = energy_xys.iter().min_by_key(|xy| xy.dist(current_xy)) {
+                    tour.stops.push_back(RepairTourStop::Refill(refill_xy));
+                    current_xy = refill_xy;
+                }
+                current_energy = carry_capacity;
+            }
+
+            let (i, _) = u!(remaining
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (repair_site, _))| repair_site.xy.dist(current_xy)));
+            let (repair_site, cost) = &mut remaining[i];
+
+            // Without refills in between, the repairer just continues repairing the same site.
+            let same_as_last_stop = matches!(
+                tour.stops.back(),
+                Some(RepairTourStop::Repair(last_repair_site)) if last_repair_site.id == repair_site.id
+            );
+            if !same_as_last_stop {
+                tour.stops.push_back(RepairTourStop::Repair((*repair_site).clone()));
+            }
+            current_xy = repair_site.xy;
+
+            let spent = (*cost).min(current_energy).min(remaining_budget);
+            *cost -= spent;
+            current_energy -= spent;
+            remaining_budget -= spent;
+
+            if *cost == 0 {
+                remaining.swap_remove(i);
+            }
+        }
+    }
+
+    tour
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashSet;
+    use screeps::{ObjectId, StructureType};
+    use crate::construction::repair_tours::{plan_repair_tour, RepairTour, RepairTourStop};
+    use crate::construction::triage_repair_sites::{RepairSiteData, TriagedRepairSites};
+    use crate::utils::test_utils::xy;
+
+    fn repair_site(id: u128, x: u8, y: u8, hits_to_repair: u32) -> RepairSiteData {
+        RepairSiteData {
+            id: ObjectId::from_packed(id),
+            structure_type: StructureType::Road,
+            xy: xy(x, y),
+            hits_to_repair,
+            target_hits: 5000,
+            ticks_to_critical: None,
+        }
+    }
+
+    #[test]
+    fn test_tour_travels_less_than_naive_ordering() {
+        // Sites alternating between two far away clusters.
+        let mut triaged_repair_sites = TriagedRepairSites::default();
+        for i in 0..10 {
+            let (x, y) = if i % 2 == 0 { (5 + i, 5) } else { (40 + i, 40) };
+            triaged_repair_sites.regular.push(repair_site(i as u128 + 1, x, y, 100));
+        }
+
+        let start_xy = xy(25, 25);
+        let tour = plan_repair_tour(&triaged_repair_sites, &FxHashSet::default(), start_xy, 50, 50, &[]);
+
+        let naive_tour = RepairTour {
+            stops: triaged_repair_sites.regular.iter().cloned().map(RepairTourStop::Repair).collect(),
+            critical_sites_hash: 0,
+        };
+
+        assert_eq!(tour.site_ids().count(), 10);
+        assert!(tour.travel_distance(start_xy) < naive_tour.travel_distance(start_xy));
+    }
+
+    #[test]
+    fn test_critical_sites_go_first() {
+        let mut triaged_repair_sites = TriagedRepairSites::default();
+        triaged_repair_sites.regular.push(repair_site(1, 11, 10, 100));
+        triaged_repair_sites.critical.push(repair_site(2, 40, 40, 100));
+
+        let tour = plan_repair_tour(&triaged_repair_sites, &FxHashSet::default(), xy(10, 10), 50, 50, &[]);
+
+        assert_eq!(tour.site_ids().collect::<Vec<_>>(), vec![ObjectId::from_packed(2), ObjectId::from_packed(1)]);
+    }
+
+    #[test]
+    fn test_refills_are_inserted_at_nearest_energy_source() {
+        let mut triaged_repair_sites = TriagedRepairSites::default();
+        // Each site requires 30 energy with 50 capacity.
+        triaged_repair_sites.regular.push(repair_site(1, 10, 10, 3000));
+        triaged_repair_sites.regular.push(repair_site(2, 12, 10, 3000));
+        let energy_xys = [xy(40, 40), xy(14, 12)];
+
+        let tour = plan_repair_tour(&triaged_repair_sites, &FxHashSet::default(), xy(10, 11), 50, 50, &energy_xys);
+
+        let stops = tour
+            .stops
+            .iter()
+            .map(|stop| match stop {
+                RepairTourStop::Repair(repair_site) => (true, repair_site.xy),
+                RepairTourStop::Refill(xy) => (false, *xy),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(stops, vec![(true, xy(10, 10)), (true, xy(12, 10)), (false, xy(14, 12)), (true, xy(12, 10))]);
+    }
+
+    #[test]
+    fn test_excluded_sites_and_budget() {
+        let mut triaged_repair_sites = TriagedRepairSites::default();
+        triaged_repair_sites.regular.push(repair_site(1, 10, 10, 100));
+        triaged_repair_sites.regular.push(repair_site(2, 20, 20, 1_000_000));
+        triaged_repair_sites.regular.push(repair_site(3, 30, 30, 100));
+
+        let excluded_ids = FxHashSet::from_iter([ObjectId::from_packed(1)]);
+        let tour = plan_repair_tour(&triaged_repair_sites, &excluded_ids, xy(20, 21), 50, 50, &[]);
+
+        // The large site uses up the whole budget of the tour.
+        assert_eq!(tour.site_ids().collect::<Vec<_>>(), vec![ObjectId::from_packed(2)]);
+    }
+}
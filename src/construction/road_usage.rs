@@ -0,0 +1,117 @@
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::kernel::sleep::sleep;
+use crate::room_planning::plan::Plan;
+use crate::room_states::room_states::for_each_room;
+use rustc_hash::FxHashSet;
+use screeps::RoomXY;
+
+/// Number of ticks over which road usage is counted.
+const ROAD_USAGE_WINDOW: u32 = 5000;
+
+/// Number of ticks between decays of road usage. Each decay removes the fraction of the usage
+/// corresponding to this interval within the window, so that the usage approximates the number of
+/// steps over the last `ROAD_USAGE_WINDOW` ticks.
+const ROAD_USAGE_DECAY_INTERVAL: u32 = 500;
+
+/// Number of steps over the last `ROAD_USAGE_WINDOW` ticks above which a planned road is built
+/// regardless of its minimum RCL.
+const ROAD_USAGE_THRESHOLD: u16 = 500;
+
+/// Registers a step of a creep on given tile.
+pub fn register_step(road_usage: &mut RoomMatrix<u16>, xy: RoomXY) {
+    road_usage.set(xy, road_usage.get(xy).saturating_add(1));
+}
+
+/// Decays the road usage in all rooms every `ROAD_USAGE_DECAY_INTERVAL` ticks.
+pub async fn decay_road_usage() {
+    loop {
+        sleep(ROAD_USAGE_DECAY_INTERVAL).await;
+
+        for_each_room(|_, room_state| {
+            decay_room_road_usage(&mut room_state.road_usage);
+        });
+    }
+}
+
+fn decay_room_road_usage(road_usage: &mut RoomMatrix<u16>) {
+    for usage in road_usage.data.iter_mut() {
+        *usage = (*usage as u32 * (ROAD_USAGE_WINDOW - ROAD_USAGE_DECAY_INTERVAL) / ROAD_USAGE_WINDOW) as u16;
+    }
+}
+
+/// Planned roads that should be built. These are the roads used more than `ROAD_USAGE_THRESHOLD`
+/// times regardless of their minimum RCL and the roads available at given RCL that are used at all.
+pub fn used_planned_roads(plan: &Plan, rcl: u8, road_usage: &RoomMatrix<u16>) -> FxHashSet<RoomXY> {
+    plan.tiles
+        .iter()
+        .filter(|&(xy, tile)| {
+            let usage = road_usage.get(xy);
+            tile.structures().road() && (usage > ROAD_USAGE_THRESHOLD || (tile.min_rcl() <= rcl && usage > 0))
+        })
+        .map(|(xy, _)| xy)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::construction::road_usage::{decay_room_road_usage, register_step, used_planned_roads, ROAD_USAGE_THRESHOLD};
+    use crate::room_planning::plan::{Plan, PlanScore, PlannedControllerData};
+    use crate::room_planning::planned_tile::PlannedTile;
+    use rustc_hash::FxHashSet;
+    use screeps::RoomXY;
+    use screeps::StructureType::{Extension, Road};
+
+    #[test]
+    fn test_used_planned_roads() {
+        let early_road_xy: RoomXY = (10, 10).try_into().unwrap();
+        let unused_early_road_xy: RoomXY = (11, 10).try_into().unwrap();
+        let busy_late_road_xy: RoomXY = (12, 10).try_into().unwrap();
+        let quiet_late_road_xy: RoomXY = (13, 10).try_into().unwrap();
+        let extension_xy: RoomXY = (14, 10).try_into().unwrap();
+        let mut tiles = RoomMatrix::default();
+        tiles.set(early_road_xy, PlannedTile::from(Road).with_min_rcl(3));
+        tiles.set(unused_early_road_xy, PlannedTile::from(Road).with_min_rcl(3));
+        tiles.set(busy_late_road_xy, PlannedTile::from(Road).with_min_rcl(6));
+        tiles.set(quiet_late_road_xy, PlannedTile::from(Road).with_min_rcl(6));
+        tiles.set(extension_xy, PlannedTile::from(Extension).with_min_rcl(2));
+        let plan = Plan::new(
+            tiles,
+            PlannedControllerData::default(),
+            Vec::new(),
+            None,
+            PlanScore::default(),
+            Vec::new(),
+            Vec::new(),
+        );
+
+        let mut road_usage = RoomMatrix::default();
+        register_step(&mut road_usage, early_road_xy);
+        register_step(&mut road_usage, extension_xy);
+        for _ in 0..10 {
+            register_step(&mut road_usage, quiet_late_road_xy);
+        }
+        for _ in 0..2 * ROAD_USAGE_THRESHOLD {
+            register_step(&mut road_usage, busy_late_road_xy);
+        }
+
+        assert_eq!(
+            used_planned_roads(&plan, 4, &road_usage),
+            FxHashSet::from_iter([early_road_xy, busy_late_road_xy])
+        );
+        assert_eq!(
+            used_planned_roads(&plan, 6, &road_usage),
+            FxHashSet::from_iter([early_road_xy, busy_late_road_xy, quiet_late_road_xy])
+        );
+
+        // Without further steps, the usage of the busy road eventually decays below the threshold.
+        for _ in 0..10 {
+            decay_room_road_usage(&mut road_usage);
+        }
+        assert!(road_usage.get(busy_late_road_xy) < ROAD_USAGE_THRESHOLD);
+        assert_eq!(road_usage.get(early_road_xy), 0);
+        assert_eq!(used_planned_roads(&plan, 4, &road_usage), FxHashSet::default());
+    }
+}
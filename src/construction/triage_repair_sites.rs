@@ -1,5 +1,8 @@
+use std::cmp::Reverse;
 use std::default::Default;
-use screeps::{ObjectId, RoomName, RoomXY, Structure, StructureType};
+use rustc_hash::FxHashMap;
+use screeps::{find, game, ObjectId, RoomName, RoomXY, Structure, StructureType};
+use crate::config::RAMPART_TARGET_HITS;
 use crate::kernel::sleep::{sleep, sleep_until};
 use crate::room_planning::plan_rooms::MIN_CONTAINER_RCL;
 use crate::room_states::room_states::with_room_state;
@@ -21,12 +24,18 @@ pub struct StructureToRepair {
 #[derive(Default, Clone, Debug)]
 pub struct TriagedRepairSites {
     /// Repair sites that need to be repaired immediately. Specifically ones that are decaying and
-    /// sufficiently close to expiration.
+    /// sufficiently close to expiration and, while the room is under attack, ramparts and walls
+    /// below their target hits.
     pub critical: Vec<RepairSiteData>,
     /// Other repair sites, including ones that are low on hits, but are not decaying.
     pub regular: Vec<RepairSiteData>,
-    /// Total hits to repair.
+    /// Ramparts and walls that are not critical, but are below their target hits. They are repaired
+    /// only after all other sites.
+    pub rampart_growth: Vec<RepairSiteData>,
+    /// Total hits to repair in critical and regular repair sites.
     pub total_hits_to_repair: u32,
+    /// Total hits to repair in rampart growth repair sites.
+    pub total_rampart_growth_hits: u32,
 }
 
 #[derive(Clone, Debug)]
@@ -37,88 +46,120 @@ pub struct RepairSiteData {
     pub hits_to_repair: u32,
     /// The number of hits to which the structure is supposed to be repaired.
     pub target_hits: u32,
+    /// The number of ticks until the structure decays enough to become critical or `None` if it
+    /// does not decay.
+    pub ticks_to_critical: Option<u32>,
 }
 
 pub async fn triage_repair_sites(room_name: RoomName) {
-    // TODO Figure out which structures need repairing and how many repairers need to be spawned
-    //      for that.
-    // TODO Triage structures into critical and normal priority. Priority ones are the decaying
-    //      ones that are close to death. Spawn if any is critical.
-    // TODO Otherwise spawn only if a repairer can reasonably use its whole life on repair.
     // TODO This involves economy and energy usage too, so maybe it should be done in a process
     //      that then signals eco_config to include it in calculations.
-    // TODO Do not fully repair ramparts all at once. Actually, beyond some point that should depend
-    //      on economy.
 
     sleep_until(first_tick() + 10).await;
 
     loop {
+        let under_attack = game::rooms()
+            .get(room_name)
+            .is_some_and(|room| !room.find(find::HOSTILE_CREEPS, None).is_empty());
+
         u!(with_room_state(room_name, |room_state| {
-            let mut triaged_repair_sites = TriagedRepairSites::default();
+            room_state.triaged_repair_sites = triage_structures_to_repair(
+                &room_state.structures_to_repair,
+                room_state.rcl,
+                under_attack
+            );
+        }));
+
+        // TODO It is not required to check it each tick, but no new JS calls have to be made anyway.
+        sleep(3).await;
+    }
+}
+
+/// Sorts the structures to repair in an owned room into critical, regular and rampart growth repair
+/// sites. Each of them is ordered by the number of ticks until the structure becomes critical, with
+/// the ones not decaying last, ordered by decreasing hits to repair.
+pub fn triage_structures_to_repair(
+    structures_to_repair: &FxHashMap<StructureType, Vec<StructureToRepair>>,
+    rcl: u8,
+    under_attack: bool
+) -> TriagedRepairSites {
+    let mut triaged_repair_sites = TriagedRepairSites::default();
+
+    for (&structure_type, structures_to_repair) in structures_to_repair.iter() {
+        let is_rampart = matches!(structure_type, StructureType::Wall | StructureType::Rampart);
+
+        for structure_to_repair in structures_to_repair.iter() {
+            let target_hits = match structure_type {
+                StructureType::Wall | StructureType::Rampart => rampart_target_hits(rcl),
+                StructureType::Container if rcl <= MIN_CONTAINER_RCL => 0,
+                _ => structure_to_repair.hits_max
+            };
+
+            if structure_to_repair.hits < target_hits {
+                let hits_to_repair = target_hits - structure_to_repair.hits;
+                let ticks_to_critical = ticks_to_critical(structure_type, structure_to_repair.hits);
 
-            for (&structure_type, structures_to_repair) in room_state.structures_to_repair.iter() {
-                let min_non_critical_hits;
-                if let (Some(decay_amount), Some(decay_ticks)) = (structure_type.decay_amount(), structure_type.decay_ticks(true)) {
-                    min_non_critical_hits = decay_amount * CRITICAL_TICKS_TO_EXPIRATION.div_ceil(decay_ticks) + 1;
+                let repair_site_data = RepairSiteData {
+                    id: structure_to_repair.id,
+                    structure_type,
+                    xy: structure_to_repair.xy,
+                    hits_to_repair,
+                    target_hits,
+                    ticks_to_critical,
+                };
+
+                if ticks_to_critical == Some(0) || (is_rampart && under_attack) {
+                    triaged_repair_sites.critical.push(repair_site_data);
+                    triaged_repair_sites.total_hits_to_repair += hits_to_repair;
+                } else if is_rampart {
+                    triaged_repair_sites.rampart_growth.push(repair_site_data);
+                    triaged_repair_sites.total_rampart_growth_hits += hits_to_repair;
                 } else {
-                    min_non_critical_hits = 1;
-                }
-                
-                for structure_to_repair in structures_to_repair.iter() {
-                    let target_hits = match structure_type {
-                        StructureType::Wall | StructureType::Rampart => rampart_target_hits(room_state.rcl),
-                        StructureType::Container if room_state.rcl <= MIN_CONTAINER_RCL => 0,
-                        _ => structure_to_repair.hits_max
-                    };
-                    
-                    if structure_to_repair.hits < target_hits {
-                        let hits_to_repair = target_hits - structure_to_repair.hits;
-                        
-                        let repair_site_data = RepairSiteData {
-                            id: structure_to_repair.id,
-                            structure_type,
-                            xy: structure_to_repair.xy,
-                            hits_to_repair,
-                            target_hits,
-                        };
-    
-                        if structure_to_repair.hits < min_non_critical_hits {
-                            triaged_repair_sites.critical.push(repair_site_data);
-                        } else {
-                            triaged_repair_sites.regular.push(repair_site_data);
-                        }
-    
-                        triaged_repair_sites.total_hits_to_repair += hits_to_repair;
-                    }
+                    triaged_repair_sites.regular.push(repair_site_data);
+                    triaged_repair_sites.total_hits_to_repair += hits_to_repair;
                 }
             }
-            
-            room_state.triaged_repair_sites = triaged_repair_sites;
-        }));
+        }
+    }
 
-        // TODO It is not required to check it each tick, but no new JS calls have to be made anyway.
-        sleep(3).await;
+    for repair_sites in [
+        &mut triaged_repair_sites.critical,
+        &mut triaged_repair_sites.regular,
+        &mut triaged_repair_sites.rampart_growth
+    ] {
+        repair_sites.sort_by_key(|repair_site| {
+            (repair_site.ticks_to_critical.unwrap_or(u32::MAX), Reverse(repair_site.hits_to_repair))
+        });
     }
+
+    triaged_repair_sites
+}
+
+/// The number of ticks until a structure of given type in an owned room decays to fewer than
+/// `CRITICAL_TICKS_TO_EXPIRATION` ticks to expiration, assuming it just decayed. Zero if it is
+/// already critical and `None` if it does not decay.
+pub fn ticks_to_critical(structure_type: StructureType, hits: u32) -> Option<u32> {
+    let decay_amount = structure_type.decay_amount()?;
+    let decay_ticks = structure_type.decay_ticks(true)?;
+    let ticks_to_expiration = hits.div_ceil(decay_amount) * decay_ticks;
+    Some(ticks_to_expiration.saturating_sub(CRITICAL_TICKS_TO_EXPIRATION))
 }
 
 // TODO More dynamic, especially for high RCL. Also different for walls.
 pub fn rampart_target_hits(rcl: u8) -> u32 {
-    match rcl {
-        6 => 25_000,
-        7 => 50_000,
-        8 => 100_000,
-        _ => 0
-    }
+    RAMPART_TARGET_HITS.get(rcl as usize).copied().unwrap_or(0)
 }
 
 impl TriagedRepairSites {
     /// Chooses the closest repair site to given position. Prioritizes critical ones over regular
-    /// ones regardless of the distance.
+    /// ones and regular ones over rampart growth regardless of the distance.
     pub fn choose_repair_site(&self, xy: RoomXY) -> Option<RepairSiteData> {
         let source = if !self.critical.is_empty() {
             Some(&self.critical)
         } else if !self.regular.is_empty() {
             Some(&self.regular)
+        } else if !self.rampart_growth.is_empty() {
+            Some(&self.rampart_growth)
         } else {
             None
         };
@@ -135,18 +176,93 @@ impl TriagedRepairSites {
     pub fn remove_repair_site(&mut self, id: ObjectId<Structure>) {
         self.critical.retain(|repair_site| repair_site.id != id);
         self.regular.retain(|repair_site| repair_site.id != id);
+        self.rampart_growth.retain(|repair_site| repair_site.id != id);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::construction::triage_repair_sites::rampart_target_hits;
+    use rustc_hash::FxHashMap;
+    use screeps::{ObjectId, StructureType};
+    use crate::construction::triage_repair_sites::{
+        rampart_target_hits,
+        ticks_to_critical,
+        triage_structures_to_repair,
+        RepairSiteData,
+        StructureToRepair
+    };
     use crate::room_planning::room_planner::MIN_RAMPART_RCL;
 
+    fn structure(id: u128, hits: u32, hits_max: u32) -> StructureToRepair {
+        StructureToRepair {
+            id: ObjectId::from_packed(id),
+            xy: (10, id as u8).try_into().unwrap(),
+            hits,
+            hits_max,
+        }
+    }
+
+    fn test_structures_to_repair() -> FxHashMap<StructureType, Vec<StructureToRepair>> {
+        FxHashMap::from_iter([
+            (StructureType::Road, vec![structure(1, 500, 5000), structure(2, 1000, 5000), structure(3, 3000, 5000)]),
+            (StructureType::Spawn, vec![structure(4, 4000, 5000)]),
+            (StructureType::Container, vec![structure(5, 150_000, 250_000)]),
+            (StructureType::Rampart, vec![structure(6, 24_000, 300_000), structure(7, 20_000, 300_000), structure(8, 30_000, 300_000)]),
+            (StructureType::Wall, vec![structure(9, 10_000, 300_000_000)]),
+        ])
+    }
+
+    fn packed_ids(repair_sites: &[RepairSiteData]) -> Vec<u128> {
+        repair_sites.iter().map(|repair_site| repair_site.id.to_u128()).collect()
+    }
+
     #[test]
     fn check_rampart_target_hits_consistency() {
         for rcl in 0u8..=8u8 {
             assert_eq!(rampart_target_hits(rcl) > 0, rcl >= MIN_RAMPART_RCL); 
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_ticks_to_critical() {
+        // Roads lose 100 hits per 1000 ticks.
+        assert_eq!(ticks_to_critical(StructureType::Road, 5000), Some(42_500));
+        assert_eq!(ticks_to_critical(StructureType::Road, 1000), Some(2500));
+        assert_eq!(ticks_to_critical(StructureType::Road, 500), Some(0));
+        // Ramparts lose 300 hits per 100 ticks.
+        assert_eq!(ticks_to_critical(StructureType::Rampart, 24_000), Some(500));
+        assert_eq!(ticks_to_critical(StructureType::Spawn, 1000), None);
+        assert_eq!(ticks_to_critical(StructureType::Wall, 1000), None);
+    }
+
+    #[test]
+    fn test_triage_sorts_by_ticks_to_critical() {
+        let triaged_repair_sites = triage_structures_to_repair(&test_structures_to_repair(), 6, false);
+        // Both are decaying soon, the one with more hits to repair goes first.
+        assert_eq!(packed_ids(&triaged_repair_sites.critical), vec![7, 1]);
+        // Non-decaying structures go last.
+        assert_eq!(packed_ids(&triaged_repair_sites.regular), vec![2, 5, 3, 4]);
+        // The rampart above the target hits is not repaired.
+        assert_eq!(packed_ids(&triaged_repair_sites.rampart_growth), vec![6, 9]);
+        assert_eq!(triaged_repair_sites.total_hits_to_repair, 5000 + 4500 + 4000 + 100_000 + 2000 + 1000);
+        assert_eq!(triaged_repair_sites.total_rampart_growth_hits, 1000 + 15_000);
+    }
+
+    #[test]
+    fn test_triage_under_attack() {
+        let triaged_repair_sites = triage_structures_to_repair(&test_structures_to_repair(), 6, true);
+        assert_eq!(packed_ids(&triaged_repair_sites.critical), vec![7, 1, 6, 9]);
+        assert_eq!(packed_ids(&triaged_repair_sites.regular), vec![2, 5, 3, 4]);
+        assert!(triaged_repair_sites.rampart_growth.is_empty());
+        assert_eq!(triaged_repair_sites.total_rampart_growth_hits, 0);
+    }
+
+    #[test]
+    fn test_triage_without_ramparts_at_low_rcl() {
+        let triaged_repair_sites = triage_structures_to_repair(&test_structures_to_repair(), 3, true);
+        assert_eq!(packed_ids(&triaged_repair_sites.critical), vec![1]);
+        // The container is not repaired before it is needed.
+        assert_eq!(packed_ids(&triaged_repair_sites.regular), vec![2, 3, 4]);
+        assert!(triaged_repair_sites.rampart_growth.is_empty());
+    }
+}
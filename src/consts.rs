@@ -8,4 +8,8 @@ pub const UNREACHABLE_COST: u8 = 254;
 /// Cost of repairing something with a single `Work` part.
 pub const REPAIR_COST_PER_PART: u32 = 1;
 
+/// Ticks to live added by renewing a creep, divided by the number of its parts. Equal to
+/// `SPAWN_RENEW_RATIO * CREEP_LIFE_TIME / CREEP_SPAWN_TIME`.
+pub const RENEW_TICKS_PER_BODY: u32 = 600;
+
 pub const FAR_FUTURE: u32 = 1_000_000_000;
\ No newline at end of file
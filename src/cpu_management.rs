@@ -0,0 +1,127 @@
+use std::cell::Cell;
+#[cfg(feature = "mmo")]
+use log::info;
+use screeps::game;
+use crate::config::{HIGH_CPU_BUCKET, LOW_CPU_BUCKET};
+
+/// Weight of the CPU used in the current tick in the moving average of CPU usage.
+const CPU_USAGE_AVERAGE_WEIGHT: f64 = 0.05;
+/// The fraction of the CPU limit the bot may use on average for the CPU level to be high.
+const HIGH_CPU_USAGE: f64 = 0.8;
+
+/// How much CPU the bot can afford to spend, from the most constrained to the least.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum CpuLevel {
+    /// The bucket is almost empty and still being drained.
+    Critical,
+    /// The bucket is low or being drained.
+    Low,
+    Normal,
+    /// The bucket is almost full and there is CPU to spare.
+    High,
+}
+
+/// Parts of the bot that only run when there is enough CPU.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CpuFeature {
+    RoomPlanning,
+    Visualizations,
+    Scouting,
+    /// Room processes other than spawning, defense and hauling.
+    NonEssentialRoomProcesses,
+}
+
+impl CpuLevel {
+    pub fn enables(self, feature: CpuFeature) -> bool {
+        match feature {
+            CpuFeature::RoomPlanning => self >= CpuLevel::Normal,
+            CpuFeature::Visualizations => self >= CpuLevel::High,
+            CpuFeature::Scouting => self >= CpuLevel::Normal,
+            CpuFeature::NonEssentialRoomProcesses => self >= CpuLevel::Low,
+        }
+    }
+}
+
+thread_local! {
+    /// Moving average of the CPU used per tick.
+    static AVERAGE_CPU_USED: Cell<Option<f64>> = const { Cell::new(None) };
+}
+
+/// The CPU level given the CPU bucket and the average CPU used per tick relative to the CPU limit.
+/// The level is critical only when the bucket is low and still being drained, so that it is not
+/// critical right after generating a pixel.
+pub fn derive_cpu_level(bucket: i32, average_cpu_used: f64, cpu_limit: f64) -> CpuLevel {
+    let draining = average_cpu_used > cpu_limit;
+    if bucket < LOW_CPU_BUCKET && draining {
+        CpuLevel::Critical
+    } else if bucket < LOW_CPU_BUCKET || draining {
+        CpuLevel::Low
+    } else if bucket >= HIGH_CPU_BUCKET && average_cpu_used <= HIGH_CPU_USAGE * cpu_limit {
+        CpuLevel::High
+    } else {
+        CpuLevel::Normal
+    }
+}
+
+/// The current CPU level based on the bucket and the CPU used in recent ticks.
+pub fn cpu_level() -> CpuLevel {
+    let average_cpu_used = AVERAGE_CPU_USED.with(|average| average.get()).unwrap_or(0.0);
+    derive_cpu_level(game::cpu::bucket(), average_cpu_used, game::cpu::limit() as f64)
+}
+
+/// Updates the average CPU usage at the end of the tick and generates a pixel when the bucket is
+/// full, if the shard has them.
+pub fn manage_cpu() {
+    let cpu_used = game::cpu::get_used();
+    AVERAGE_CPU_USED.with(|average| {
+        let new_average = match average.get() {
+            Some(previous_average) => (1.0 - CPU_USAGE_AVERAGE_WEIGHT) * previous_average + CPU_USAGE_AVERAGE_WEIGHT * cpu_used,
+            None => cpu_used,
+        };
+        average.set(Some(new_average));
+    });
+
+    #[cfg(feature = "mmo")]
+    if game::cpu::bucket() >= screeps::PIXEL_CPU_COST as i32 {
+        match game::cpu::generate_pixel() {
+            Ok(()) => info!("Generated a pixel."),
+            Err(err) => info!("Failed to generate a pixel: {:?}.", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_management::{derive_cpu_level, CpuFeature, CpuLevel};
+    use crate::cpu_management::CpuFeature::*;
+    use crate::cpu_management::CpuLevel::*;
+
+    #[test]
+    fn test_cpu_level_derivation() {
+        assert_eq!(derive_cpu_level(10_000, 10.0, 20.0), High);
+        // Using most of the limit.
+        assert_eq!(derive_cpu_level(10_000, 19.0, 20.0), Normal);
+        assert_eq!(derive_cpu_level(5_000, 10.0, 20.0), Normal);
+        // Draining the bucket.
+        assert_eq!(derive_cpu_level(10_000, 25.0, 20.0), Low);
+        // Refilling the bucket, e.g., after generating a pixel.
+        assert_eq!(derive_cpu_level(0, 10.0, 20.0), Low);
+        assert_eq!(derive_cpu_level(500, 25.0, 20.0), Critical);
+    }
+
+    #[test]
+    fn test_features_enabled_by_cpu_level() {
+        let table: [(CpuLevel, [bool; 4]); 4] = [
+            (Critical, [false, false, false, false]),
+            (Low, [false, false, false, true]),
+            (Normal, [true, false, true, true]),
+            (High, [true, true, true, true]),
+        ];
+        let features: [CpuFeature; 4] = [RoomPlanning, Visualizations, Scouting, NonEssentialRoomProcesses];
+        for (level, enabled) in table {
+            for (feature, feature_enabled) in features.into_iter().zip(enabled) {
+                assert_eq!(level.enables(feature), feature_enabled, "{:?} at {:?}", feature, level);
+            }
+        }
+    }
+}
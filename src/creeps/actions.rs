@@ -1,10 +1,14 @@
 use log::trace;
-use screeps::{RawObjectId, Resource, ResourceType};
+use screeps::{HasPosition, ObjectId, Position, RawObjectId, Resource, ResourceType, StructureSpawn};
+use screeps::game::get_object_by_id_typed;
 use wasm_bindgen::JsCast;
 use crate::creeps::creeps::CreepRef;
 use crate::errors::XiError;
+use crate::errors::XiError::{CreepRecycleFailed, ObjectDoesNotExist};
 use crate::utils::game_tick::game_tick;
 use crate::kernel::sleep::sleep;
+use crate::travel::travel::travel;
+use crate::travel::travel_spec::TravelSpec;
 use crate::utils::get_object_by_id::erased_object_by_id;
 
 // This module contains creep actions combined with waiting if not possible in the same tick.
@@ -79,4 +83,17 @@ pub async fn drop_when_able(creep_ref: &CreepRef, resource_type: ResourceType, a
             return Ok(());
         }
     }
+}
+
+/// Travels next to the spawn and has the spawn recycle the creep. Returns the position where
+/// the creep was recycled, which is where its resources end up.
+pub async fn recycle(creep_ref: &CreepRef, spawn_id: ObjectId<StructureSpawn>) -> Result<Position, XiError> {
+    let spawn_pos = get_object_by_id_typed(&spawn_id).ok_or(ObjectDoesNotExist)?.pos();
+    travel(creep_ref, TravelSpec::new(spawn_pos, 1)).await?;
+
+    let spawn = get_object_by_id_typed(&spawn_id).ok_or(ObjectDoesNotExist)?;
+    let mut borrowed_creep = creep_ref.borrow_mut();
+    let creep_pos = borrowed_creep.travel_state.pos;
+    spawn.recycle_creep(borrowed_creep.screeps_obj()?).or(Err(CreepRecycleFailed))?;
+    Ok(creep_pos)
 }
\ No newline at end of file
@@ -2,7 +2,7 @@ use log::warn;
 use rustc_hash::FxHashMap;
 use crate::travel::travel_state::TravelState;
 use crate::{log_err, u};
-use screeps::{game, ConstructionSite, Direction, HasId, MaybeHasId, MoveToOptions, ObjectId, PolyStyle, Position, RawObjectId, Repairable, Resource, ResourceType, SharedCreepProperties, Source, StructureController, Transferable, Withdrawable};
+use screeps::{game, Attackable, ConstructionSite, Direction, ErrorCode, HasId, MaybeHasId, MoveToOptions, ObjectId, PolyStyle, Position, RawObjectId, RoomName, Repairable, Resource, ResourceType, SharedCreepProperties, Source, StructureController, Transferable, Withdrawable};
 use crate::creeps::creep_body::CreepBody;
 use crate::creeps::creep_role::CreepRole;
 use crate::creeps::generic_creep::GenericCreep;
@@ -25,8 +25,6 @@ use crate::utils::unchecked_withdrawable::UncheckedWithdrawable;
 
 pub type CrId = u32;
 
-// TODO A creep should have its room assigned.
-// TODO To enable assigning that upon restart, make a function to find the nearest room.
 #[derive(Debug)]
 pub struct Creep {
     /// Globally unique creep name.
@@ -37,6 +35,9 @@ pub struct Creep {
     pub role: CreepRole,
     /// Unique creep identifier, separate for each role.
     pub number: CrId,
+    /// The owned room the creep belongs to, i.e., the one that spawned it or the nearest one for
+    /// creeps found after a restart. `None` if there were no owned rooms.
+    pub home_room: Option<RoomName>,
     /// State of travel of the creep with information about location where it is supposed to be
     /// and temporary state to be managed by the travel module.
     pub travel_state: TravelState,
@@ -59,6 +60,7 @@ impl Creep {
         id: Option<ObjectId<screeps::Creep>>,
         role: CreepRole,
         number: CrId,
+        home_room: Option<RoomName>,
         body: CreepBody,
         pos: Position
     ) -> Self {
@@ -73,6 +75,7 @@ impl Creep {
             id,
             role,
             number,
+            home_room,
             travel_state: TravelState::new(pos),
             last_withdraw_tick: 0,
             last_pickup_tick: 0,
@@ -193,6 +196,9 @@ impl Creep {
                 target_id,
                 e
             );
+            if e == ErrorCode::Full {
+                return Err(CreepTransferTargetFull);
+            }
             return Err(CreepTransferFailed);
         }
         
@@ -232,6 +238,32 @@ impl Creep {
     pub fn claim(&mut self, target: &StructureController) -> Result<(), XiError> {
         self.screeps_obj()?.claim_controller(target).or(Err(CreepClaimFailed))
     }
+
+    pub fn reserve_controller(&mut self, target: &StructureController) -> Result<(), XiError> {
+        self.screeps_obj()?.reserve_controller(target).or(Err(CreepReserveControllerFailed))
+    }
+
+    pub fn attack_controller(&mut self, target: &StructureController) -> Result<(), XiError> {
+        self.screeps_obj()?.attack_controller(target).or(Err(CreepAttackControllerFailed))
+    }
+
+    pub fn sign_controller(&mut self, target: &StructureController, text: &str) -> Result<(), XiError> {
+        self.screeps_obj()?.sign_controller(target, text).or(Err(CreepSignControllerFailed))
+    }
+
+    pub fn attack<T>(&mut self, target: &T) -> Result<(), XiError>
+    where
+        T: ?Sized + Attackable
+    {
+        self.screeps_obj()?.attack(target).or(Err(CreepAttackFailed))
+    }
+
+    pub fn ranged_attack<T>(&mut self, target: &T) -> Result<(), XiError>
+    where
+        T: ?Sized + Attackable
+    {
+        self.screeps_obj()?.ranged_attack(target).or(Err(CreepRangedAttackFailed))
+    }
     
     // Current information about the creep
 
@@ -327,4 +359,12 @@ impl GenericCreep for Creep {
     fn get_fatigue(&mut self) -> Result<u32, XiError> {
         self.fatigue()
     }
+
+    fn get_body(&self) -> &CreepBody {
+        &self.body
+    }
+
+    fn get_ticks_to_live(&mut self) -> u32 {
+        self.ticks_to_live()
+    }
 }
\ No newline at end of file
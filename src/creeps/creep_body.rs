@@ -19,7 +19,7 @@ use std::fmt::{Display, Formatter};
 use std::iter::repeat;
 use rustc_hash::FxHashMap;
 use enum_iterator::all;
-use crate::consts::REPAIR_COST_PER_PART;
+use crate::consts::{RENEW_TICKS_PER_BODY, REPAIR_COST_PER_PART};
 use crate::travel::surface::Surface;
 use crate::utils::part_extras::PartExtras;
 
@@ -63,6 +63,17 @@ impl CreepBody {
         self.total_part_count() as u32 * CREEP_SPAWN_TIME
     }
 
+    /// Energy cost of renewing a creep with this body once. Equal to
+    /// `ceil(SPAWN_RENEW_RATIO * energy_cost / CREEP_SPAWN_TIME / parts)`, computed without floats.
+    pub fn renew_energy_cost(&self) -> u32 {
+        (2 * self.energy_cost()).div_ceil(5 * max(1, self.total_part_count() as u32))
+    }
+
+    /// Ticks to live added by renewing a creep with this body once.
+    pub fn renew_ticks(&self) -> u32 {
+        RENEW_TICKS_PER_BODY / max(1, self.total_part_count() as u32)
+    }
+
     pub fn energy_cost(&self) -> u32 {
         self.parts.iter().map(|(part, (count, _))| part.cost() * (*count as u32)).sum()
     }
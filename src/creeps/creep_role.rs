@@ -12,6 +12,7 @@ pub enum CreepRole {
     Builder,
     Repairer,
     Claimer,
+    Defender,
 }
 
 impl Display for CreepRole {
@@ -30,6 +31,7 @@ impl CreepRole {
             CreepRole::Builder => "builder",
             CreepRole::Repairer => "repairer",
             CreepRole::Claimer => "claimer",
+            CreepRole::Defender => "defender",
         }
     }
 
@@ -42,6 +44,7 @@ impl CreepRole {
             "builder" => Some(CreepRole::Builder),
             "repairer" => Some(CreepRole::Repairer),
             "claimer" => Some(CreepRole::Claimer),
+            "defender" => Some(CreepRole::Defender),
             _ => None
         }
     }
@@ -55,6 +58,7 @@ impl CreepRole {
             CreepRole::Builder => Part::Work,
             CreepRole::Repairer => Part::Work,
             CreepRole::Claimer => Part::Claim,
+            CreepRole::Defender => Part::Attack,
         }
     }
 }
\ No newline at end of file
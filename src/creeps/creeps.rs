@@ -1,5 +1,5 @@
 use rustc_hash::FxHashMap;
-use screeps::{game, HasPosition, Position};
+use screeps::{game, HasPosition, Position, RoomName};
 use log::{info, warn};
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -11,6 +11,8 @@ use crate::creeps::creep_role::CreepRole;
 use crate::fresh_number::fresh_number_if_some;
 use crate::kernel::sleep::sleep;
 use crate::spawning::reserved_creep::{register_unassigned_creep, with_unassigned_creeps};
+use crate::travel::nearest_room::find_nearest_owned_room;
+use crate::travel::route::register_creep_killed;
 use crate::travel::traffic::register_creep_pos;
 use crate::u;
 use crate::utils::result_utils::ResultUtils;
@@ -59,6 +61,7 @@ pub async fn cleanup_creeps() {
                     None,
                     role,
                     number,
+                    find_nearest_owned_room(creep_pos.room_name(), 0),
                     creep_obj.body().into(),
                     creep_pos
                 );
@@ -93,7 +96,18 @@ pub async fn cleanup_creeps() {
                     if game_creeps.get(creep_ref.borrow().name.clone()).is_none() {
                         // The creep is dead.
                         // TODO inform its process
-                        creep_ref.borrow_mut().dead = true;
+                        let mut creep = creep_ref.borrow_mut();
+                        creep.dead = true;
+                        // The cached object is from the previous tick, when the creep was last
+                        // seen alive. If it was not about to die of old age, it was killed.
+                        let last_ticks_to_live = creep
+                            .cached_screeps_obj
+                            .data
+                            .as_ref()
+                            .and_then(|creep_obj| creep_obj.ticks_to_live());
+                        if last_ticks_to_live.is_some_and(|ticks_to_live| ticks_to_live > 1) {
+                            register_creep_killed(creep.travel_state.pos.room_name());
+                        }
                         false
                     } else {
                         register_creep_pos(creep_ref);
@@ -107,9 +121,9 @@ pub async fn cleanup_creeps() {
     }
 }
 
-/// Registers a new creep within the creeps module. May be called on the tick the creep is spawned
-/// after `cleanup_creeps`.
-pub fn register_creep(role: CreepRole, body: CreepBody, pos: Position) -> CreepRef {
+/// Registers a new creep belonging to given room within the creeps module. May be called on the tick
+/// the creep is spawned after `cleanup_creeps`.
+pub fn register_creep(role: CreepRole, home_room: RoomName, body: CreepBody, pos: Position) -> CreepRef {
     with_creeps(|creeps| {
         // Note that it may not overlap with existing creeps after a reset, so UId is insufficient.
         let number = fresh_number_if_some(creeps.get(&role));
@@ -120,6 +134,7 @@ pub fn register_creep(role: CreepRole, body: CreepBody, pos: Position) -> CreepR
             None,
             role,
             number,
+            Some(home_room),
             body,
             pos
         );
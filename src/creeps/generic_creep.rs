@@ -1,4 +1,5 @@
 use screeps::ObjectId;
+use crate::creeps::creep_body::CreepBody;
 use crate::errors::XiError;
 use crate::travel::surface::Surface;
 use crate::travel::travel_state::TravelState;
@@ -12,4 +13,6 @@ pub trait GenericCreep {
     fn get_travel_state_mut(&mut self) -> &mut TravelState;
     fn get_ticks_per_tile(&self, surface: Surface) -> u8;
     fn get_fatigue(&mut self) -> Result<u32, XiError>;
+    fn get_body(&self) -> &CreepBody;
+    fn get_ticks_to_live(&mut self) -> u32;
 }
\ No newline at end of file
@@ -11,6 +11,7 @@ pub struct TestCreep {
     pub travel_state: TravelState,
     pub body: CreepBody,
     pub fatigue: u32,
+    pub ticks_to_live: u32,
 }
 
 impl TestCreep {
@@ -19,6 +20,7 @@ impl TestCreep {
             name: format!("creep{}", id),
             id,
             travel_state: TravelState::new(pos),
+            ticks_to_live: body.lifetime(),
             body,
             fatigue: 0,
         }
@@ -49,4 +51,12 @@ impl GenericCreep for TestCreep {
     fn get_fatigue(&mut self) -> Result<u32, XiError> {
         Ok(self.fatigue)
     }
+
+    fn get_body(&self) -> &CreepBody {
+        &self.body
+    }
+
+    fn get_ticks_to_live(&mut self) -> u32 {
+        self.ticks_to_live
+    }
 }
\ No newline at end of file
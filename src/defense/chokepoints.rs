@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::iter::once;
+use enum_iterator::all;
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::{Direction, RoomName, RoomXY, StructureType};
+use crate::algorithms::chokepoint_matrix::{chokepoint_matrix, chokepoint_tiles};
+use crate::algorithms::chunk_graph::ChunkGraph;
+use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::flood_fill::flood_fill;
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::consts::OBSTACLE_COST;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::room_states::chunk_graphs::with_chunk_graph;
+use crate::room_states::packed_terrain::PackedTerrain;
+use crate::room_states::room_state::RoomState;
+
+/// Radius of the chunks of the chunk graph the chokepoints are searched in.
+const CHUNK_RADIUS: u8 = 5;
+/// The maximum number of tiles across a chokepoint.
+pub const MAX_CHOKEPOINT_WIDTH: u8 = 5;
+/// The minimum number of tiles a chokepoint must cut off from the exits.
+const MIN_GUARDED_AREA: u8 = 16;
+
+/// A narrow passage whose tiles, when blocked, cut off an area of the room from the exits.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Chokepoint {
+    /// Tiles across the passage, sorted.
+    pub tiles: Vec<RoomXY>,
+    pub width: u8,
+    /// The number of tiles cut off from the exits by blocking the chokepoint.
+    pub guards_area: u16,
+}
+
+/// Chokepoints of the room along with the generation of its structures and the terrain they were
+/// found for.
+struct CachedChokepoints {
+    generation: u32,
+    terrain: PackedTerrain,
+    chokepoints: Vec<Chokepoint>,
+}
+
+thread_local! {
+    static CHOKEPOINTS: RefCell<FxHashMap<RoomName, CachedChokepoints>> = RefCell::new(FxHashMap::default());
+}
+
+/// Tiles hostiles cannot walk through, i.e., walls and ramparts.
+pub fn hostile_obstacles(room_state: &RoomState) -> RoomMatrix<u8> {
+    let mut obstacles = room_state.terrain.to_obstacle_matrix(0);
+    if let Some(ramparts) = room_state.structures.get(&StructureType::Rampart) {
+        for &xy in ramparts.keys() {
+            obstacles.set(xy, OBSTACLE_COST);
+        }
+    }
+    obstacles
+}
+
+/// The chokepoints of the room for hostiles coming from the exits, as found by `find_chokepoints`.
+/// The result is cached until the terrain changes or the structures in the room change, as
+/// signalled by `structures_broadcast`, since that may change the rampart layout.
+pub fn chokepoints(room_state: &RoomState) -> Vec<Chokepoint> {
+    let generation = room_state.structures_broadcast.broadcasts_count();
+    CHOKEPOINTS.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(cached) = cache.get(&room_state.room_name) {
+            if cached.generation == generation && cached.terrain.data == room_state.terrain.data {
+                return cached.chokepoints.clone();
+            }
+        }
+
+        let obstacles = hostile_obstacles(room_state);
+        let chokepoints = with_chunk_graph(room_state.room_name, &obstacles, CHUNK_RADIUS, find_chokepoints);
+        cache.insert(room_state.room_name, CachedChokepoints {
+            generation,
+            terrain: room_state.terrain,
+            chokepoints: chokepoints.clone(),
+        });
+        chokepoints
+    })
+}
+
+/// Finds passages at most `MAX_CHOKEPOINT_WIDTH` wide that cut off at least `MIN_GUARDED_AREA`
+/// tiles from the exits. The candidates are the chokepoints from `chokepoint_matrix` in all
+/// directions. From the ones cutting off the same area, only the narrowest one is kept, preferring
+/// the one closer to the exits. The result is ordered by the width.
+pub fn find_chokepoints(chunk_graph: &ChunkGraph) -> Vec<Chokepoint> {
+    let obstacles = chunk_graph.obstacles();
+    let exits = obstacles
+        .boundary()
+        .filter_map(|(xy, value)| (value != OBSTACLE_COST).then_some(xy))
+        .collect::<Vec<_>>();
+    let reachable = flood_fill(exits.iter().copied(), |xy| obstacles.get(xy) != OBSTACLE_COST, |_| {});
+
+    let mut candidates = FxHashSet::default();
+    for direction in all::<Direction>() {
+        let matrix = chokepoint_matrix(chunk_graph, direction, MAX_CHOKEPOINT_WIDTH + 1, MIN_GUARDED_AREA);
+        for (xy, (width, separated_tiles)) in matrix.iter() {
+            if width <= MAX_CHOKEPOINT_WIDTH && separated_tiles >= MIN_GUARDED_AREA {
+                let mut tiles = chokepoint_tiles(chunk_graph, xy, direction);
+                debug_assert_eq!(tiles.len(), width as usize);
+                tiles.sort();
+                candidates.insert(tiles);
+            }
+        }
+    }
+
+    let mut guarded_candidates = candidates
+        .into_iter()
+        .filter_map(|tiles| {
+            let guarded = guarded_area(obstacles, &reachable, &exits, &tiles);
+            let guards_area = guarded.find_xy(true).count() as u16;
+            (guards_area >= MIN_GUARDED_AREA as u16).then(|| {
+                let chokepoint = Chokepoint {
+                    width: tiles.len() as u8,
+                    tiles,
+                    guards_area,
+                };
+                (chokepoint, guarded)
+            })
+        })
+        .collect::<Vec<_>>();
+    guarded_candidates.sort_by_key(|(chokepoint, _)| {
+        (chokepoint.width, Reverse(chokepoint.guards_area), chokepoint.tiles.clone())
+    });
+
+    let mut selected: Vec<(Chokepoint, RoomMatrix<bool>)> = Vec::new();
+    for (chokepoint, guarded) in guarded_candidates {
+        let redundant = selected.iter().any(|(selected_chokepoint, selected_guarded)| {
+            chokepoint
+                .tiles
+                .iter()
+                .any(|&xy| selected_guarded.get(xy) || selected_chokepoint.tiles.contains(&xy))
+                || selected_chokepoint.tiles.iter().any(|&xy| guarded.get(xy))
+        });
+        if !redundant {
+            selected.push((chokepoint, guarded));
+        }
+    }
+
+    selected.into_iter().map(|(chokepoint, _)| chokepoint).collect()
+}
+
+/// Tiles reachable from the exits that are no longer reachable once the chokepoint tiles are
+/// blocked.
+fn guarded_area(
+    obstacles: &RoomMatrix<u8>,
+    reachable: &RoomMatrix<bool>,
+    exits: &[RoomXY],
+    chokepoint_xys: &[RoomXY],
+) -> RoomMatrix<bool> {
+    let passable = |xy: RoomXY| obstacles.get(xy) != OBSTACLE_COST && !chokepoint_xys.contains(&xy);
+    let blocked_reachable = flood_fill(exits.iter().copied().filter(|&xy| passable(xy)), passable, |_| {});
+    reachable.map(|xy, reachable| reachable && passable(xy) && !blocked_reachable.get(xy))
+}
+
+/// Whether blocking the chokepoint cuts off all the hostiles from all the protected tiles that
+/// they can currently reach or get next to. `false` if they cannot reach any of them anyway or a hostile already
+/// stands on the chokepoint.
+pub fn separates(
+    obstacles: &RoomMatrix<u8>,
+    chokepoint: &Chokepoint,
+    hostile_xys: &[RoomXY],
+    protected_xys: &[RoomXY],
+) -> bool {
+    if hostile_xys.iter().any(|xy| chokepoint.tiles.contains(xy)) {
+        return false;
+    }
+    let sources = hostile_xys.iter().copied().filter(|&xy| obstacles.get(xy) != OBSTACLE_COST);
+    let reachable = flood_fill(sources.clone(), |xy| obstacles.get(xy) != OBSTACLE_COST, |_| {});
+    if !protected_xys.iter().any(|&xy| in_reach(&reachable, xy)) {
+        return false;
+    }
+    let blocked_reachable = flood_fill(
+        sources,
+        |xy| obstacles.get(xy) != OBSTACLE_COST && !chokepoint.tiles.contains(&xy),
+        |_| {},
+    );
+    !protected_xys.iter().any(|&xy| in_reach(&blocked_reachable, xy))
+}
+
+/// Whether the tile or a tile next to it is reachable, so that protected structures under ramparts
+/// count as reachable when the hostiles can get next to them.
+fn in_reach(reachable: &RoomMatrix<bool>, xy: RoomXY) -> bool {
+    once(xy).chain(xy.around()).any(|near| reachable.get(near))
+}
+
+/// Tiles of the narrowest chokepoint between the hostiles and the protected tiles that can be
+/// fully blocked by `count` creeps, ordered by the distance to the hostiles. Empty if there is no
+/// such chokepoint.
+pub fn chokepoint_guard_positions(
+    obstacles: &RoomMatrix<u8>,
+    chokepoints: &[Chokepoint],
+    hostile_xys: &[RoomXY],
+    protected_xys: &[RoomXY],
+    count: usize,
+) -> Vec<RoomXY> {
+    let Some(chokepoint) = chokepoints
+        .iter()
+        .filter(|chokepoint| chokepoint.width as usize <= count)
+        .find(|chokepoint| separates(obstacles, chokepoint, hostile_xys, protected_xys))
+    else {
+        return Vec::new();
+    };
+
+    let sources = hostile_xys.iter().copied().filter(|&xy| obstacles.get(xy) != OBSTACLE_COST);
+    let dm = distance_matrix(obstacles.find_xy(OBSTACLE_COST), sources);
+    let mut guard_xys = chokepoint.tiles.clone();
+    guard_xys.sort_by_key(|&xy| (dm.get(xy), xy));
+    guard_xys
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::chunk_graph::chunk_graph;
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::consts::OBSTACLE_COST;
+    use crate::defense::chokepoints::{chokepoint_guard_positions, find_chokepoints, Chokepoint};
+    use crate::utils::test_utils::xy;
+
+    /// A room open in the top 16 rows, with walls below except for two chambers, each reached from
+    /// the open area through a corridor.
+    /// ```text
+    /// y=16..=29  ....####....##########..#####  the corridors at x=4..=7 with a pinch at (5, 22)
+    ///                                           and at x=24..=25 until y=25
+    /// y=26..=45  ##.........##....#########...  the chambers at x=2..=10 from y=30 and
+    ///                                           at x=15..=34 until y=40
+    /// ```
+    fn corridor_map() -> RoomMatrix<u8> {
+        let mut obstacles = RoomMatrix::new(OBSTACLE_COST);
+        let mut carve = |min_x: u8, min_y: u8, max_x: u8, max_y: u8| {
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    obstacles.set(xy(x, y), 0);
+                }
+            }
+        };
+        carve(0, 0, 49, 15);
+        // The left corridor, four tiles wide with a pinch one tile wide.
+        carve(4, 16, 7, 21);
+        carve(5, 22, 5, 22);
+        carve(4, 23, 7, 29);
+        carve(2, 30, 10, 45);
+        // The right corridor, two tiles wide.
+        carve(24, 16, 25, 25);
+        carve(15, 26, 34, 40);
+        obstacles
+    }
+
+    #[test]
+    fn test_chokepoints_of_corridor_map() {
+        let chunks = chunk_graph(&corridor_map(), 5);
+        let chokepoints = find_chokepoints(&chunks);
+        assert_eq!(chokepoints, vec![
+            Chokepoint {
+                tiles: vec![xy(5, 22)],
+                width: 1,
+                guards_area: 7 * 4 + 9 * 16,
+            },
+            Chokepoint {
+                tiles: vec![xy(24, 16), xy(25, 16)],
+                width: 2,
+                guards_area: 9 * 2 + 20 * 15,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_rampart_narrows_chokepoint() {
+        let mut obstacles = corridor_map();
+        obstacles.set(xy(24, 16), OBSTACLE_COST);
+        let chokepoints = find_chokepoints(&chunk_graph(&obstacles, 5));
+        assert_eq!(chokepoints.len(), 2);
+        assert_eq!(chokepoints[0], Chokepoint {
+            tiles: vec![xy(25, 16)],
+            width: 1,
+            guards_area: 9 * 2 + 20 * 15,
+        });
+    }
+
+    #[test]
+    fn test_guards_stand_on_chokepoint_between_hostiles_and_protected_area() {
+        let obstacles = corridor_map();
+        let chokepoints = find_chokepoints(&chunk_graph(&obstacles, 5));
+        let spawn_xy = xy(25, 35);
+
+        let guard_xys = chokepoint_guard_positions(&obstacles, &chokepoints, &[xy(20, 5)], &[spawn_xy], 3);
+        assert_eq!(guard_xys.len(), 2);
+        assert!(guard_xys.contains(&xy(24, 16)) && guard_xys.contains(&xy(25, 16)));
+
+        // Not enough creeps to block the chokepoint.
+        assert!(chokepoint_guard_positions(&obstacles, &chokepoints, &[xy(20, 5)], &[spawn_xy], 1).is_empty());
+        // The hostile is already past the chokepoint.
+        assert!(chokepoint_guard_positions(&obstacles, &chokepoints, &[xy(30, 30)], &[spawn_xy], 3).is_empty());
+        // The left chamber is guarded by the pinch.
+        let guard_xys = chokepoint_guard_positions(&obstacles, &chokepoints, &[xy(5, 18)], &[xy(5, 40)], 1);
+        assert_eq!(guard_xys, vec![xy(5, 22)]);
+    }
+}
@@ -0,0 +1,543 @@
+use std::cell::RefCell;
+use std::cmp::min;
+use std::rc::Rc;
+use rustc_hash::FxHashMap;
+use screeps::{
+    find,
+    game,
+    HasPosition,
+    RoomName,
+    RoomXY,
+    ATTACK_POWER,
+    CREEP_RANGED_ACTION_RANGE,
+    MAX_CREEP_SIZE,
+    RANGED_ATTACK_POWER,
+    StructureType,
+};
+use screeps::Part::{Attack, Move, RangedAttack};
+use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::interior_matrix::interior_matrix;
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::algorithms::shortest_path_by_distance_matrix::shortest_path_by_distance_matrix;
+use crate::consts::UNREACHABLE_COST;
+use crate::creeps::creep_body::CreepBody;
+use crate::creeps::creep_role::CreepRole::Defender;
+use crate::creeps::creeps::CreepRef;
+use crate::defense::chokepoints::{chokepoint_guard_positions, chokepoints, hostile_obstacles};
+use crate::defense::incidents::with_incidents;
+use crate::defense::threat::ThreatReport;
+use crate::geometry::rect::room_rect;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::kernel::sleep::sleep;
+use crate::local_debug;
+use crate::priorities::DEFENDER_SPAWN_PRIORITY;
+use crate::room_states::room_state::RoomState;
+use crate::room_states::room_states::with_room_state;
+use crate::spawning::spawn_pool::{SpawnPool, SpawnPoolOptions};
+use crate::spawning::spawn_schedule::generic_base_spawn_request;
+use crate::travel::travel::travel;
+use crate::travel::travel_spec::TravelSpec;
+use crate::u;
+use crate::utils::result_utils::ResultUtils;
+
+const DEBUG: bool = true;
+
+/// The maximum number of defenders spawned at the same time in a room.
+const MAX_DEFENDERS: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DefenderKind {
+    Melee,
+    Ranged,
+}
+
+impl DefenderKind {
+    /// The repeated part of the body, consisting of two attacking parts and one `Move` part.
+    fn body_unit(self) -> CreepBody {
+        match self {
+            DefenderKind::Melee => vec![(Move, 1), (Attack, 2)].into(),
+            DefenderKind::Ranged => vec![(Move, 1), (RangedAttack, 2)].into(),
+        }
+    }
+
+    fn attack_power(self) -> u32 {
+        match self {
+            DefenderKind::Melee => ATTACK_POWER,
+            DefenderKind::Ranged => RANGED_ATTACK_POWER,
+        }
+    }
+
+    fn attack_part(self) -> screeps::Part {
+        match self {
+            DefenderKind::Melee => Attack,
+            DefenderKind::Ranged => RangedAttack,
+        }
+    }
+
+    fn range(self) -> u8 {
+        match self {
+            DefenderKind::Melee => 1,
+            DefenderKind::Ranged => CREEP_RANGED_ACTION_RANGE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DefenderSpawnPlan {
+    pub kind: DefenderKind,
+    pub body: CreepBody,
+    pub count: u32,
+}
+
+/// Decides which defenders to spawn against the threat. The defenders together deal more damage
+/// per tick than all the hostiles can heal. Melee defenders are preferred as they deal more damage
+/// per energy, unless the hostiles mostly attack from range and thus do not need to come next to
+/// the ramparts. `None` if the hostiles cannot damage the structures or not even the smallest
+/// defender can be afforded.
+pub fn defender_spawn_plan(threat: &ThreatReport, spawn_energy_capacity: u32) -> Option<DefenderSpawnPlan> {
+    if threat.structure_dps() == 0 {
+        return None;
+    }
+
+    let kind = if threat.total_ranged > threat.total_attack + threat.total_dismantle {
+        DefenderKind::Ranged
+    } else {
+        DefenderKind::Melee
+    };
+    let unit = kind.body_unit();
+    let unit_parts = unit.count_parts(kind.attack_part()) as u32;
+
+    let required_parts = threat.total_heal / kind.attack_power() + 1;
+    let required_units = required_parts.div_ceil(unit_parts);
+
+    let max_units_per_creep = min(
+        spawn_energy_capacity / unit.energy_cost(),
+        MAX_CREEP_SIZE / unit.total_part_count() as u32
+    );
+    if max_units_per_creep == 0 {
+        return None;
+    }
+    let count = required_units.div_ceil(max_units_per_creep).clamp(1, MAX_DEFENDERS);
+    let units_per_creep = min(required_units.div_ceil(count), max_units_per_creep);
+
+    let body = vec![
+        (Move, units_per_creep as u8),
+        (kind.attack_part(), (units_per_creep * unit_parts) as u8),
+    ].into();
+
+    Some(DefenderSpawnPlan {
+        kind,
+        body,
+        count,
+    })
+}
+
+/// Up to `count` rampart tiles closest to the hostiles, ordered from the closest one. The distance
+/// is the number of steps the hostiles need to reach the rampart, with ties broken by the
+/// straight-line distance.
+pub fn duty_positions<O>(walls: O, rampart_xys: &[RoomXY], hostile_xys: &[RoomXY], count: usize) -> Vec<RoomXY>
+where
+    O: Iterator<Item = RoomXY>,
+{
+    let dm = distance_matrix(walls, hostile_xys.iter().copied());
+    let mut duty_xys = rampart_xys
+        .iter()
+        .copied()
+        .filter(|&xy| dm.get(xy) < UNREACHABLE_COST)
+        .map(|xy| {
+            let squared_dist = hostile_xys
+                .iter()
+                .map(|hostile_xy| {
+                    let dx = xy.x.u8() as i32 - hostile_xy.x.u8() as i32;
+                    let dy = xy.y.u8() as i32 - hostile_xy.y.u8() as i32;
+                    dx * dx + dy * dy
+                })
+                .min()
+                .unwrap_or(0);
+            (dm.get(xy), squared_dist, xy)
+        })
+        .collect::<Vec<_>>();
+    duty_xys.sort();
+    duty_xys.into_iter().take(count).map(|(_, _, xy)| xy).collect()
+}
+
+/// Assigns the duty positions to the defenders at given positions. Going from the most important
+/// duty position, each one is taken by the closest defender without one, so that the defenders
+/// already in place keep their positions. Defenders left without a duty position get `None`.
+pub fn assign_duty_positions(duty_xys: &[RoomXY], defender_xys: &[RoomXY]) -> Vec<Option<RoomXY>> {
+    let mut assignment = vec![None; defender_xys.len()];
+    for &duty_xy in duty_xys.iter() {
+        let closest_defender = defender_xys
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| assignment[i].is_none())
+            .min_by_key(|&(_, xy)| xy.dist(duty_xy));
+        if let Some((i, _)) = closest_defender {
+            assignment[i] = Some(duty_xy);
+        }
+    }
+    assignment
+}
+
+/// A shortest path from `start` to `target`, both inclusive, going only through the tiles marked
+/// in `inside`. `None` if there is no such path.
+pub fn inside_path(inside: &RoomMatrix<bool>, start: RoomXY, target: RoomXY) -> Option<Vec<RoomXY>> {
+    let obstacles = room_rect().iter().filter(|&xy| !inside.get(xy));
+    let dm = distance_matrix(obstacles, [target].into_iter());
+    if dm.get(start) >= UNREACHABLE_COST {
+        return None;
+    }
+    Some(shortest_path_by_distance_matrix(&dm, start, 0))
+}
+
+/// The main ramparts from the plan that are built.
+fn built_main_ramparts(room_state: &RoomState) -> Vec<RoomXY> {
+    let Some(plan) = room_state.plan.as_ref() else {
+        return Vec::new();
+    };
+    let Some(ramparts) = room_state.structures.get(&StructureType::Rampart) else {
+        return Vec::new();
+    };
+    plan.main_ramparts
+        .iter()
+        .copied()
+        .filter(|xy| ramparts.contains_key(xy))
+        .collect()
+}
+
+/// Spawns defenders while the hostiles in the room are able to damage structures and the safe mode
+/// is not active. The defenders block the chokepoint between the hostiles and the spawns if there
+/// are enough of them, and otherwise stand on the built main ramparts closest to the hostiles and
+/// attack them from there, moving to other ramparts as the hostiles move. They only walk through
+/// the tiles inside the rampart line.
+pub async fn defend_ramparts(room_name: RoomName) {
+    let base_spawn_request = u!(with_room_state(room_name, |room_state| {
+        let mut base_spawn_request = generic_base_spawn_request(room_state, Defender);
+        base_spawn_request.priority = DEFENDER_SPAWN_PRIORITY;
+        base_spawn_request
+    }));
+    // The tiles inside the rampart line, recomputed when the built ramparts change.
+    let mut rampart_xys = None;
+    let inside = Rc::new(RefCell::new(RoomMatrix::new(false)));
+
+    let spawn_pool_options = SpawnPoolOptions::default().target_number_of_creeps(0);
+    let mut spawn_pool = SpawnPool::new(room_name, base_spawn_request, spawn_pool_options);
+
+    // Duty positions of the defenders by their names.
+    let duty_assignment = Rc::new(RefCell::new(FxHashMap::<String, RoomXY>::default()));
+
+    loop {
+        let safe_mode_active = game::rooms()
+            .get(room_name)
+            .and_then(|room| room.controller())
+            .is_some_and(|controller| controller.safe_mode().is_some());
+        let hostile_xys = game::rooms()
+            .get(room_name)
+            .map(|room| {
+                room.find(find::HOSTILE_CREEPS, None)
+                    .iter()
+                    .map(|creep| creep.pos().xy())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let spawn_plan = with_room_state(room_name, |room_state| {
+            if safe_mode_active {
+                None
+            } else {
+                defender_spawn_plan(&room_state.threat_report, room_state.resources.spawn_energy_capacity)
+            }
+        }).flatten();
+        match spawn_plan {
+            Some(spawn_plan) => {
+                spawn_pool.target_number_of_creeps = spawn_plan.count;
+                spawn_pool.base_spawn_request.body = spawn_plan.body;
+            }
+            None => {
+                spawn_pool.target_number_of_creeps = 0;
+            }
+        }
+
+        u!(with_room_state(room_name, |room_state| {
+            let current_rampart_xys = built_main_ramparts(room_state);
+            if rampart_xys.as_ref() != Some(&current_rampart_xys) {
+                *inside.borrow_mut() = if current_rampart_xys.is_empty() {
+                    // Without a rampart line, the defenders may go anywhere.
+                    let mut passable = RoomMatrix::new(true);
+                    for xy in room_state.terrain.walls() {
+                        passable.set(xy, false);
+                    }
+                    passable
+                } else {
+                    interior_matrix(room_state.terrain.walls(), current_rampart_xys.iter().copied(), false, true)
+                };
+                rampart_xys = Some(current_rampart_xys);
+            }
+        }));
+
+        // Reassigning the duty positions to follow the hostiles.
+        let mut defenders = Vec::new();
+        spawn_pool.for_each_creep(|creep_ref| {
+            let creep = creep_ref.borrow();
+            defenders.push((creep.name.clone(), creep.travel_state.pos.xy()));
+        });
+        let duty_xys = if hostile_xys.is_empty() || defenders.is_empty() {
+            Vec::new()
+        } else {
+            u!(with_room_state(room_name, |room_state| {
+                // Blocking a chokepoint between the hostiles and the spawns, if there are enough
+                // defenders for that, with the rest of them on the ramparts.
+                let spawn_xys = room_state
+                    .structures
+                    .get(&StructureType::Spawn)
+                    .map(|spawns| spawns.keys().copied().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                let mut duty_xys = chokepoint_guard_positions(
+                    &hostile_obstacles(room_state),
+                    &chokepoints(room_state),
+                    &hostile_xys,
+                    &spawn_xys,
+                    defenders.len()
+                );
+                // The defenders may not leave the rampart line to block a chokepoint outside of it.
+                if !duty_xys.iter().all(|&xy| inside.borrow().get(xy)) {
+                    duty_xys.clear();
+                }
+                let rampart_duty_xys = duty_positions(
+                    room_state.terrain.walls(),
+                    rampart_xys.as_deref().unwrap_or_default(),
+                    &hostile_xys,
+                    defenders.len() - duty_xys.len()
+                );
+                duty_xys.extend(rampart_duty_xys);
+                duty_xys
+            }))
+        };
+        let defender_xys = defenders.iter().map(|&(_, xy)| xy).collect::<Vec<_>>();
+        let assignment = assign_duty_positions(&duty_xys, &defender_xys);
+        {
+            let mut duty_assignment = duty_assignment.borrow_mut();
+            // Defenders without a duty position stay where they are.
+            if !duty_xys.is_empty() {
+                duty_assignment.clear();
+            }
+            for ((name, _), duty_xy) in defenders.into_iter().zip(assignment) {
+                if let Some(duty_xy) = duty_xy {
+                    duty_assignment.insert(name, duty_xy);
+                }
+            }
+        }
+
+        spawn_pool.with_spawned_creeps(|creep_ref| {
+            let energy_cost = creep_ref.borrow().body.energy_cost();
+            with_incidents(|incidents| {
+                if let Some(incident) = incidents.get_mut(&room_name) {
+                    incident.record_defender(energy_cost);
+                }
+            });
+
+            let duty_assignment = duty_assignment.clone();
+            let inside = inside.clone();
+            async move {
+                let kind = if creep_ref.borrow().body.count_parts(Attack) > 0 {
+                    DefenderKind::Melee
+                } else {
+                    DefenderKind::Ranged
+                };
+
+                loop {
+                    let (name, xy) = {
+                        let creep = creep_ref.borrow();
+                        (creep.name.clone(), creep.travel_state.pos.xy())
+                    };
+                    let duty_xy = duty_assignment.borrow().get(&name).copied();
+                    if let Some(duty_xy) = duty_xy {
+                        move_towards_duty_position(room_name, &creep_ref, &inside.borrow(), xy, duty_xy);
+                    }
+
+                    attack_hostile_in_range(room_name, &creep_ref, kind);
+
+                    sleep(1).await;
+                }
+            }
+        });
+
+        sleep(1).await;
+    }
+}
+
+/// Moves the defender one tile closer to its duty position, only through the tiles inside the
+/// rampart line. If there is no such path, e.g., when the line is breached, the defender holds its
+/// position.
+fn move_towards_duty_position(
+    room_name: RoomName,
+    creep_ref: &CreepRef,
+    inside: &RoomMatrix<bool>,
+    xy: RoomXY,
+    duty_xy: RoomXY
+) {
+    if xy == duty_xy {
+        return;
+    }
+    let Some(path) = inside_path(inside, xy, duty_xy) else {
+        local_debug!("No path inside the ramparts from {} to {} in {}.", xy, duty_xy, room_name);
+        return;
+    };
+    let next_xy = u!(path.get(1).copied());
+    // The arrival is checked again in the next tick.
+    travel(creep_ref, TravelSpec::new(next_xy.to_pos(room_name), 0));
+}
+
+/// Attacks the hostile in range of the defender with the fewest hits.
+fn attack_hostile_in_range(room_name: RoomName, creep_ref: &CreepRef, kind: DefenderKind) {
+    let Some(room) = game::rooms().get(room_name) else {
+        return;
+    };
+    let pos = creep_ref.borrow().travel_state.pos;
+    let Some(target) = room
+        .find(find::HOSTILE_CREEPS, None)
+        .into_iter()
+        .filter(|hostile| hostile.pos().get_range_to(pos) <= kind.range() as u32)
+        .min_by_key(|hostile| hostile.hits())
+    else {
+        return;
+    };
+
+    let mut creep = creep_ref.borrow_mut();
+    local_debug!("Defender {} attacking a hostile at {}.", creep.name, target.pos());
+    let result = match kind {
+        DefenderKind::Melee => creep.attack(&target),
+        DefenderKind::Ranged => creep.ranged_attack(&target),
+    };
+    result.warn_if_err(&format!("Defender {} failed to attack", creep.name));
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{RoomXY, ATTACK_POWER, RANGED_ATTACK_POWER};
+    use screeps::Part::{Attack, Move, RangedAttack};
+    use crate::algorithms::interior_matrix::interior_matrix;
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::defense::defend_ramparts::{
+        assign_duty_positions,
+        defender_spawn_plan,
+        duty_positions,
+        inside_path,
+        DefenderKind,
+    };
+    use crate::defense::threat::ThreatReport;
+    use crate::geometry::rect::Rect;
+    use crate::geometry::room_xy::RoomXYUtils;
+    use crate::u;
+    use crate::utils::test_utils::xy;
+
+    fn threat(total_attack: u32, total_ranged: u32, total_heal: u32) -> ThreatReport {
+        ThreatReport {
+            total_attack,
+            total_ranged,
+            total_heal,
+            ..ThreatReport::default()
+        }
+    }
+
+    /// Ramparts on the boundary of the square from (20, 20) to (30, 30).
+    fn rampart_ring() -> Vec<RoomXY> {
+        u!(Rect::new(xy(20, 20), xy(30, 30))).boundary().collect()
+    }
+
+    #[test]
+    fn test_defender_body_outdamages_heal() {
+        // An unhealed invader is handled by the smallest defender.
+        let plan = defender_spawn_plan(&threat(60, 0, 0), 300).unwrap();
+        assert_eq!(plan.kind, DefenderKind::Melee);
+        assert_eq!(plan.count, 1);
+        assert_eq!(plan.body.count_parts(Attack), 2);
+        assert_eq!(plan.body.count_parts(Move), 1);
+
+        // 12 heal parts heal 144 hits per tick, requiring 5 attack parts, i.e., 3 units.
+        let plan = defender_spawn_plan(&threat(60, 0, 144), 5600).unwrap();
+        assert_eq!(plan.count, 1);
+        assert_eq!(plan.body.count_parts(Attack), 6);
+        assert_eq!(plan.body.count_parts(Move), 3);
+        assert!(plan.count * plan.body.count_parts(Attack) as u32 * ATTACK_POWER > 144);
+    }
+
+    #[test]
+    fn test_ranged_defenders_split_when_energy_is_limited() {
+        // Hostiles attacking from range, healing 144 hits per tick, requiring 15 ranged attack
+        // parts, i.e., 8 units, while only 3 units fit in a single creep.
+        let plan = defender_spawn_plan(&threat(0, 200, 144), 1300).unwrap();
+        assert_eq!(plan.kind, DefenderKind::Ranged);
+        assert_eq!(plan.count, 3);
+        assert_eq!(plan.body.count_parts(RangedAttack), 6);
+        assert_eq!(plan.body.count_parts(Move), 3);
+        assert!(plan.count * plan.body.count_parts(RangedAttack) as u32 * RANGED_ATTACK_POWER > 144);
+        assert!(plan.body.energy_cost() <= 1300);
+    }
+
+    #[test]
+    fn test_no_defenders_when_not_needed_or_affordable() {
+        // Healers cannot damage anything.
+        assert_eq!(defender_spawn_plan(&threat(0, 0, 120), 5600), None);
+        // Not enough energy for the smallest defender.
+        assert_eq!(defender_spawn_plan(&threat(60, 0, 0), 200), None);
+        // The number of defenders is limited, even if they cannot outdamage the heal.
+        let plan = defender_spawn_plan(&threat(60, 0, 10_000), 300).unwrap();
+        assert_eq!(plan.count, 4);
+    }
+
+    #[test]
+    fn test_duty_positions_follow_hostiles() {
+        let ramparts = rampart_ring();
+
+        // A hostile north of the ring.
+        let north_duty_xys = duty_positions([].into_iter(), &ramparts, &[xy(25, 15)], 3);
+        assert_eq!(north_duty_xys, vec![xy(25, 20), xy(24, 20), xy(26, 20)]);
+
+        // The defenders take their positions.
+        let defender_xys = vec![xy(25, 22), xy(23, 21), xy(27, 21)];
+        let assignment = assign_duty_positions(&north_duty_xys, &defender_xys);
+        assert_eq!(assignment, vec![Some(xy(25, 20)), Some(xy(24, 20)), Some(xy(26, 20))]);
+
+        // The hostile moves east of the ring and the defenders rotate towards it.
+        let east_duty_xys = duty_positions([].into_iter(), &ramparts, &[xy(36, 26)], 3);
+        assert_eq!(east_duty_xys, vec![xy(30, 26), xy(30, 25), xy(30, 27)]);
+        let defender_xys = vec![xy(24, 20), xy(25, 20), xy(26, 20)];
+        let assignment = assign_duty_positions(&east_duty_xys, &defender_xys);
+        assert_eq!(assignment, vec![Some(xy(30, 26)), Some(xy(30, 25)), Some(xy(30, 27))]);
+
+        // Extra defenders are left without a duty position.
+        let assignment = assign_duty_positions(&east_duty_xys[..1], &defender_xys);
+        assert_eq!(assignment, vec![Some(xy(30, 26)), None, None]);
+    }
+
+    #[test]
+    fn test_duty_positions_skip_ramparts_unreachable_by_hostiles() {
+        let ramparts = rampart_ring();
+        // A wall line west of the ring, cutting off the hostile from the west ramparts.
+        let walls = (0..50).map(|y| xy(18, y)).collect::<Vec<_>>();
+        let duty_xys = duty_positions(walls.into_iter(), &ramparts, &[xy(10, 25)], 100);
+        assert!(duty_xys.is_empty());
+    }
+
+    #[test]
+    fn test_inside_path_stays_inside_the_rampart_line() {
+        let ramparts = rampart_ring();
+        let inside = interior_matrix([].into_iter(), ramparts.iter().copied(), false, true);
+        assert!(inside.get(xy(25, 25)));
+        assert!(inside.get(xy(20, 20)));
+        assert!(!inside.get(xy(19, 25)));
+
+        // From the north rampart to the east one.
+        let path = inside_path(&inside, xy(25, 20), xy(30, 26)).unwrap();
+        assert_eq!(path.first(), Some(&xy(25, 20)));
+        assert_eq!(path.last(), Some(&xy(30, 26)));
+        assert_eq!(path.len(), 7);
+        assert!(path.iter().all(|&xy| inside.get(xy)));
+        assert!(path.windows(2).all(|w| w[0].dist(w[1]) == 1));
+
+        // Outside of the rampart line.
+        assert_eq!(inside_path(&inside, xy(10, 10), xy(25, 20)), None);
+    }
+}
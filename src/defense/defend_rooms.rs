@@ -0,0 +1,98 @@
+use log::info;
+use rustc_hash::FxHashMap;
+use screeps::{find, game, Creep, HasId, Room, StructureObject};
+use crate::config::NOTIFY_INCIDENT_REPORTS;
+use crate::defense::incidents::{push_recent_incident, with_incidents, Incident, IncidentObservation};
+use crate::defense::safe_mode::maybe_activate_safe_mode;
+use crate::defense::threat::{assess_threat, hostile_body};
+use crate::kernel::sleep::sleep;
+use crate::room_states::room_states::{for_each_owned_room};
+use crate::utils::game_tick::game_tick;
+
+pub async fn defend_rooms() {
+    loop {
+        for_each_owned_room(|room_name, room_state| {
+            // TODO This should not be needed. Was an error before since lost room was included in owned rooms.
+            if let Some(room) = game::rooms().get(room_name) {
+                let enemies = room.find(find::HOSTILE_CREEPS, None);
+                // The towers are operated by `operate_towers`, which runs earlier in the tick.
+                let tower_attacks = room_state.tower_attacks;
+
+                if !enemies.is_empty() {
+                    info!("{} enemies present in room {}.", enemies.len(), room_name);
+                }
+
+                let hostile_bodies = enemies.iter().map(hostile_body).collect::<Vec<_>>();
+                let threat_report = assess_threat(room_state, &hostile_bodies);
+                if threat_report != room_state.threat_report {
+                    room_state.threat_report = threat_report;
+                    room_state.threat_broadcast.broadcast(threat_report);
+                }
+                if !enemies.is_empty() {
+                    maybe_activate_safe_mode(room_state, &threat_report);
+                }
+
+                with_incidents(|incidents| {
+                    if !enemies.is_empty() || incidents.contains_key(&room_name) {
+                        let observation = observe_incident(&room, &enemies, tower_attacks);
+                        let incident = incidents
+                            .entry(room_name)
+                            .and_modify(|incident| incident.record(&observation))
+                            .or_insert_with(|| Incident::new(&observation));
+
+                        if incident.is_over(observation.tick) {
+                            let summary = incident.summary();
+                            let report = format!("Incident report for room {}.\n{}", room_name, summary);
+                            info!("{}", report);
+                            if NOTIFY_INCIDENT_REPORTS {
+                                game::notify(&report, None);
+                            }
+                            push_recent_incident(&mut room_state.recent_incidents, summary);
+                            incidents.remove(&room_name);
+                        }
+                    }
+                });
+            }
+        });
+
+        // Forgetting incidents in rooms that are no longer owned.
+        with_incidents(|incidents| incidents.retain(|room_name, _| game::rooms().get(*room_name).is_some()));
+
+        sleep(1).await;
+    }
+}
+
+fn observe_incident(room: &Room, enemies: &[Creep], tower_attacks: u32) -> IncidentObservation {
+    let mut hostile_parts = FxHashMap::default();
+    for creep in enemies.iter() {
+        for body_part in creep.body() {
+            *hostile_parts.entry(body_part.part()).or_insert(0) += 1;
+        }
+    }
+
+    let structure_hits = room
+        .find(find::STRUCTURES, None)
+        .into_iter()
+        .filter_map(|structure| {
+            let hits = match &structure {
+                StructureObject::StructureController(_) => None,
+                _ => structure.as_attackable().map(|attackable| attackable.hits()),
+            }?;
+            let structure = structure.as_structure();
+            Some((structure.id(), (structure.structure_type(), hits)))
+        })
+        .collect();
+
+    let safe_mode_active = room
+        .controller()
+        .is_some_and(|controller| controller.safe_mode().is_some());
+
+    IncidentObservation {
+        tick: game_tick(),
+        hostile_creeps: enemies.len() as u32,
+        hostile_parts,
+        structure_hits,
+        tower_attacks,
+        safe_mode_active,
+    }
+}
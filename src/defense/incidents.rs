@@ -0,0 +1,320 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use enum_iterator::all;
+use rustc_hash::FxHashMap;
+use screeps::{ObjectId, Part, RoomName, Structure, StructureType, REPAIR_POWER, TOWER_ENERGY_COST};
+use serde::{Deserialize, Serialize};
+use crate::utils::part_extras::PartExtras;
+
+/// The number of ticks without hostiles after which the incident is deemed over.
+pub const INCIDENT_END_TICKS: u32 = 20;
+/// The number of incident summaries kept in the room state.
+pub const RECENT_INCIDENTS_COUNT: usize = 5;
+
+thread_local! {
+    /// Incidents in progress by the rooms they happen in.
+    static INCIDENTS: RefCell<FxHashMap<RoomName, Incident>> = RefCell::new(FxHashMap::default());
+}
+
+pub fn with_incidents<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut FxHashMap<RoomName, Incident>) -> R,
+{
+    INCIDENTS.with(|incidents| f(&mut incidents.borrow_mut()))
+}
+
+/// What was observed in the room in a single tick of an incident.
+#[derive(Clone, Debug, Default)]
+pub struct IncidentObservation {
+    pub tick: u32,
+    pub hostile_creeps: u32,
+    /// Total body parts of all hostile creeps in the room.
+    pub hostile_parts: FxHashMap<Part, u32>,
+    /// Types and hits of all structures in the room.
+    pub structure_hits: FxHashMap<ObjectId<Structure>, (StructureType, u32)>,
+    pub tower_attacks: u32,
+    pub safe_mode_active: bool,
+}
+
+/// Counters accumulated while an incident in a room is in progress.
+#[derive(Clone, Debug)]
+pub struct Incident {
+    start_tick: u32,
+    last_hostile_tick: u32,
+    peak_hostile_creeps: u32,
+    peak_hostile_parts: FxHashMap<Part, u32>,
+    /// Types and hits of structures when first observed during the incident.
+    initial_hits: FxHashMap<ObjectId<Structure>, (StructureType, u32)>,
+    /// Lowest hits of structures observed during the incident. Destroyed structures are removed.
+    min_hits: FxHashMap<ObjectId<Structure>, (StructureType, u32)>,
+    tower_attacks: u32,
+    defenders_spawned: u32,
+    defenders_energy_cost: u32,
+    safe_mode_used: bool,
+}
+
+impl Incident {
+    pub fn new(observation: &IncidentObservation) -> Self {
+        let mut incident = Incident {
+            start_tick: observation.tick,
+            last_hostile_tick: observation.tick,
+            peak_hostile_creeps: 0,
+            peak_hostile_parts: FxHashMap::default(),
+            initial_hits: FxHashMap::default(),
+            min_hits: FxHashMap::default(),
+            tower_attacks: 0,
+            defenders_spawned: 0,
+            defenders_energy_cost: 0,
+            safe_mode_used: false,
+        };
+        incident.record(observation);
+        incident
+    }
+
+    pub fn record(&mut self, observation: &IncidentObservation) {
+        if observation.hostile_creeps > 0 {
+            self.last_hostile_tick = observation.tick;
+        }
+
+        self.peak_hostile_creeps = self.peak_hostile_creeps.max(observation.hostile_creeps);
+        let total_parts = observation.hostile_parts.values().sum::<u32>();
+        if total_parts > self.peak_hostile_parts.values().sum::<u32>() {
+            self.peak_hostile_parts = observation.hostile_parts.clone();
+        }
+
+        for (&id, &(structure_type, hits)) in observation.structure_hits.iter() {
+            self.initial_hits.entry(id).or_insert((structure_type, hits));
+            let min_hits = self.min_hits.entry(id).or_insert((structure_type, hits));
+            min_hits.1 = min_hits.1.min(hits);
+        }
+        // Structures that were present before, but are missing now were destroyed.
+        self.min_hits.retain(|id, _| observation.structure_hits.contains_key(id));
+
+        self.tower_attacks += observation.tower_attacks;
+        self.safe_mode_used |= observation.safe_mode_active;
+    }
+
+    pub fn record_defender(&mut self, energy_cost: u32) {
+        self.defenders_spawned += 1;
+        self.defenders_energy_cost += energy_cost;
+    }
+
+    pub fn is_over(&self, tick: u32) -> bool {
+        tick >= self.last_hostile_tick + INCIDENT_END_TICKS
+    }
+
+    pub fn summary(&self) -> IncidentSummary {
+        let mut structures_lost = FxHashMap::default();
+        let mut structures_damaged = 0;
+        let mut hits_lost = 0;
+        let mut ramparts_lost = 0;
+        let mut rampart_hits_lost = 0;
+        let mut rebuild_energy_cost = 0;
+
+        for (id, &(structure_type, initial_hits)) in self.initial_hits.iter() {
+            let lost_hits = match self.min_hits.get(id) {
+                Some(&(_, min_hits)) => initial_hits.saturating_sub(min_hits),
+                None => {
+                    *structures_lost.entry(structure_type).or_insert(0) += 1;
+                    rebuild_energy_cost += structure_type.construction_cost().unwrap_or(0);
+                    if structure_type == StructureType::Rampart {
+                        ramparts_lost += 1;
+                    }
+                    initial_hits
+                }
+            };
+
+            if lost_hits > 0 {
+                structures_damaged += 1;
+                hits_lost += lost_hits;
+                if structure_type == StructureType::Rampart {
+                    rampart_hits_lost += lost_hits;
+                }
+            }
+        }
+
+        let tower_energy_spent = self.tower_attacks * TOWER_ENERGY_COST;
+        let repair_energy_cost = hits_lost.div_ceil(REPAIR_POWER);
+
+        IncidentSummary {
+            start_tick: self.start_tick,
+            end_tick: self.last_hostile_tick,
+            peak_hostile_creeps: self.peak_hostile_creeps,
+            peak_hostile_parts: self.peak_hostile_parts.clone(),
+            structures_lost,
+            structures_damaged,
+            hits_lost,
+            ramparts_lost,
+            rampart_hits_lost,
+            tower_energy_spent,
+            defenders_spawned: self.defenders_spawned,
+            defenders_energy_cost: self.defenders_energy_cost,
+            safe_mode_used: self.safe_mode_used,
+            net_energy_cost: tower_energy_spent + self.defenders_energy_cost + rebuild_energy_cost + repair_energy_cost,
+        }
+    }
+}
+
+/// Summary of an incident that is logged when it is over and kept in the room state.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IncidentSummary {
+    pub start_tick: u32,
+    pub end_tick: u32,
+    pub peak_hostile_creeps: u32,
+    /// Total body parts of hostile creeps at the moment they had the most of them.
+    pub peak_hostile_parts: FxHashMap<Part, u32>,
+    pub structures_lost: FxHashMap<StructureType, u32>,
+    /// The number of structures that lost any hits, including destroyed ones.
+    pub structures_damaged: u32,
+    pub hits_lost: u32,
+    pub ramparts_lost: u32,
+    pub rampart_hits_lost: u32,
+    pub tower_energy_spent: u32,
+    pub defenders_spawned: u32,
+    pub defenders_energy_cost: u32,
+    pub safe_mode_used: bool,
+    /// Energy spent on the defense and required to repair and rebuild what was damaged.
+    pub net_energy_cost: u32,
+}
+
+impl Display for IncidentSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Incident from tick {} to {} ({} ticks).",
+            self.start_tick,
+            self.end_tick,
+            self.end_tick - self.start_tick
+        )?;
+        write!(f, "* peak hostiles: {} creeps with ", self.peak_hostile_creeps)?;
+        for part in all::<Part>() {
+            if let Some(count) = self.peak_hostile_parts.get(&part) {
+                write!(f, "{}{}", count, part.single_char())?;
+            }
+        }
+        writeln!(f)?;
+        let mut structures_lost = self.structures_lost.iter().collect::<Vec<_>>();
+        structures_lost.sort_by_key(|(structure_type, _)| format!("{:?}", structure_type));
+        write!(f, "* structures lost:")?;
+        if structures_lost.is_empty() {
+            write!(f, " none")?;
+        }
+        for (structure_type, count) in structures_lost {
+            write!(f, " {}x {:?}", count, structure_type)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "* structures damaged: {} with {} hits lost", self.structures_damaged, self.hits_lost)?;
+        writeln!(f, "* ramparts: {} lost, {} hits lost", self.ramparts_lost, self.rampart_hits_lost)?;
+        writeln!(f, "* tower energy spent: {}", self.tower_energy_spent)?;
+        writeln!(
+            f,
+            "* defenders spawned: {} costing {} energy",
+            self.defenders_spawned, self.defenders_energy_cost
+        )?;
+        writeln!(f, "* safe mode used: {}", if self.safe_mode_used { "yes" } else { "no" })?;
+        write!(f, "Net energy cost: {}", self.net_energy_cost)
+    }
+}
+
+/// Adds the summary to recent incidents, forgetting the oldest ones.
+pub fn push_recent_incident(recent_incidents: &mut VecDeque<IncidentSummary>, summary: IncidentSummary) {
+    recent_incidents.push_back(summary);
+    while recent_incidents.len() > RECENT_INCIDENTS_COUNT {
+        recent_incidents.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use rustc_hash::FxHashMap;
+    use screeps::{ObjectId, Part, Structure, StructureType};
+    use crate::defense::incidents::{
+        push_recent_incident, Incident, IncidentObservation, INCIDENT_END_TICKS, RECENT_INCIDENTS_COUNT,
+    };
+
+    fn observation(
+        tick: u32,
+        hostile_parts: &[(Part, u32)],
+        hostile_creeps: u32,
+        structure_hits: &[(u128, StructureType, u32)],
+        tower_attacks: u32,
+    ) -> IncidentObservation {
+        IncidentObservation {
+            tick,
+            hostile_creeps,
+            hostile_parts: FxHashMap::from_iter(hostile_parts.iter().copied()),
+            structure_hits: structure_hits
+                .iter()
+                .map(|&(id, structure_type, hits)| (ObjectId::<Structure>::from_packed(id), (structure_type, hits)))
+                .collect(),
+            tower_attacks,
+            safe_mode_active: false,
+        }
+    }
+
+    #[test]
+    fn test_scripted_incident() {
+        use StructureType::{Extension, Rampart, Tower};
+
+        let mut incident = Incident::new(&observation(
+            100,
+            &[(Part::Attack, 5), (Part::Move, 5)],
+            1,
+            &[(1, Rampart, 10_000), (2, Rampart, 10_000), (3, Extension, 1_000), (4, Tower, 3_000)],
+            2,
+        ));
+        // The attack peaks with more hostiles and a rampart is destroyed.
+        incident.record(&observation(
+            101,
+            &[(Part::Attack, 10), (Part::Move, 10), (Part::Heal, 4)],
+            3,
+            &[(1, Rampart, 2_000), (3, Extension, 1_000), (4, Tower, 3_000)],
+            3,
+        ));
+        // The hostiles are weakened and the tower repairs the rampart a bit.
+        incident.record(&observation(
+            102,
+            &[(Part::Attack, 2)],
+            1,
+            &[(1, Rampart, 2_800), (3, Extension, 400), (4, Tower, 3_000)],
+            3,
+        ));
+        incident.record(&observation(103, &[], 0, &[(1, Rampart, 3_000), (3, Extension, 400), (4, Tower, 3_000)], 0));
+
+        assert!(!incident.is_over(103));
+        assert!(incident.is_over(102 + INCIDENT_END_TICKS));
+
+        let summary = incident.summary();
+        assert_eq!(summary.start_tick, 100);
+        assert_eq!(summary.end_tick, 102);
+        assert_eq!(summary.peak_hostile_creeps, 3);
+        assert_eq!(summary.peak_hostile_parts.get(&Part::Heal), Some(&4));
+        assert_eq!(summary.structures_lost, FxHashMap::from_iter([(Rampart, 1)]));
+        assert_eq!(summary.structures_damaged, 3);
+        assert_eq!(summary.hits_lost, 8_000 + 10_000 + 600);
+        assert_eq!(summary.ramparts_lost, 1);
+        assert_eq!(summary.rampart_hits_lost, 18_000);
+        assert_eq!(summary.tower_energy_spent, 80);
+        assert!(!summary.safe_mode_used);
+        // Towers, rebuilding a rampart and repairs.
+        assert_eq!(summary.net_energy_cost, 80 + 1 + 186);
+
+        let report = summary.to_string();
+        assert!(report.contains("Incident from tick 100 to 102 (2 ticks)."));
+        assert!(report.contains("* peak hostiles: 3 creeps with "));
+        assert!(report.contains("* structures lost: 1x Rampart"));
+        assert!(report.contains("Net energy cost: 267"));
+    }
+
+    #[test]
+    fn test_recent_incidents_are_limited() {
+        let incident = Incident::new(&observation(1, &[], 1, &[], 0));
+        let mut recent_incidents = VecDeque::new();
+        for _ in 0..RECENT_INCIDENTS_COUNT + 2 {
+            push_recent_incident(&mut recent_incidents, incident.summary());
+        }
+        assert_eq!(recent_incidents.len(), RECENT_INCIDENTS_COUNT);
+    }
+}
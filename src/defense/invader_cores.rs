@@ -0,0 +1,261 @@
+use std::cmp::min;
+use log::info;
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::{game, RoomName, ATTACK_POWER, INVADER_CORE_HITS, MAX_CREEP_SIZE};
+use screeps::Part::{Attack, Move};
+use crate::creeps::creep_body::CreepBody;
+use crate::creeps::creep_role::CreepRole::Defender;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::scheduling_hauls::retain_haul_requests;
+use crate::kernel::sleep::sleep;
+use crate::priorities::INVADER_CORE_ATTACKER_SPAWN_PRIORITY;
+use crate::room_states::room_state::InvaderCoreData;
+use crate::room_states::room_states::with_room_state;
+use crate::spawning::spawn_pool::{SpawnPool, SpawnPoolOptions};
+use crate::spawning::spawn_schedule::generic_base_spawn_request;
+use crate::travel::travel::move_to_cached;
+use crate::travel::travel_spec::TravelSpec;
+use crate::u;
+use crate::utils::game_tick::game_tick;
+use crate::utils::result_utils::ResultUtils;
+
+/// Cores of higher levels are strongholds defended by towers and invaders, so they are waited out
+/// rather than attacked.
+const MAX_ATTACKED_CORE_LEVEL: u8 = 0;
+/// The attackers are sized to destroy the core within this many ticks.
+const CORE_KILL_TICKS: u32 = 300;
+/// The maximum number of attackers sent against a single core.
+const MAX_CORE_ATTACKERS: u32 = 3;
+
+/// Body of the attackers to send against an invader core of given level and their number. `None`
+/// if the core is not to be attacked or not even the smallest attacker can be afforded.
+pub fn core_attacker_squad(level: u8, spawn_energy_capacity: u32) -> Option<(CreepBody, u32)> {
+    if level > MAX_ATTACKED_CORE_LEVEL {
+        return None;
+    }
+
+    let unit = CreepBody::from(vec![(Move, 1), (Attack, 1)]);
+    let required_parts = INVADER_CORE_HITS / (ATTACK_POWER * CORE_KILL_TICKS) + 1;
+    let max_parts_per_creep = min(
+        spawn_energy_capacity / unit.energy_cost(),
+        MAX_CREEP_SIZE / unit.total_part_count() as u32
+    );
+    if max_parts_per_creep == 0 {
+        return None;
+    }
+    let count = required_parts.div_ceil(max_parts_per_creep).clamp(1, MAX_CORE_ATTACKERS);
+    let parts_per_creep = min(required_parts.div_ceil(count), max_parts_per_creep) as u8;
+
+    Some((vec![(Move, parts_per_creep), (Attack, parts_per_creep)].into(), count))
+}
+
+/// Pauses hauling in the remotes with an invader core and resumes it in the ones where the core
+/// was destroyed or collapsed. The haul requests in paused remotes are cancelled, also the ones
+/// scheduled while paused, so that the haulers do not travel there.
+pub fn update_paused_remotes(
+    room_name: RoomName,
+    paused_remotes: &mut FxHashSet<RoomName>,
+    remote_invader_cores: &FxHashMap<RoomName, Option<InvaderCoreData>>,
+    tick: u32
+) {
+    for (&remote_name, invader_core) in remote_invader_cores.iter() {
+        let core_present = invader_core.is_some_and(|invader_core| invader_core.is_present(tick));
+        if core_present {
+            if paused_remotes.insert(remote_name) {
+                info!("Pausing hauling from {} in {} due to an invader core.", room_name, remote_name);
+            }
+            retain_haul_requests(room_name, |request| request.pos.room_name() != remote_name);
+        } else if paused_remotes.remove(&remote_name) {
+            info!("Resuming hauling from {} in {}.", room_name, remote_name);
+        }
+    }
+
+    // Remotes that are no longer remotes of the room.
+    paused_remotes.retain(|remote_name| remote_invader_cores.contains_key(remote_name));
+}
+
+/// Pauses hauling in remotes of the room with invader cores in them and sends attackers against
+/// the cores that can be destroyed. The attackers are released when the core is gone.
+pub async fn handle_invader_cores(room_name: RoomName) {
+    let mut attacker_pools: FxHashMap<RoomName, SpawnPool> = FxHashMap::default();
+
+    loop {
+        let remote_names = with_room_state(room_name, |room_state| {
+            room_state.remote_plans.keys().copied().collect::<Vec<_>>()
+        }).unwrap_or_default();
+        let remote_invader_cores = remote_names
+            .into_iter()
+            .map(|remote_name| {
+                let invader_core = with_room_state(remote_name, |remote_state| remote_state.invader_core).flatten();
+                (remote_name, invader_core)
+            })
+            .collect::<FxHashMap<_, _>>();
+
+        let spawn_energy_capacity = u!(with_room_state(room_name, |room_state| {
+            update_paused_remotes(room_name, &mut room_state.paused_remotes, &remote_invader_cores, game_tick());
+            room_state.resources.spawn_energy_capacity
+        }));
+
+        for (&remote_name, invader_core) in remote_invader_cores.iter() {
+            let squad = invader_core
+                .filter(|invader_core| invader_core.is_present(game_tick()))
+                .and_then(|invader_core| {
+                    core_attacker_squad(invader_core.level, spawn_energy_capacity).map(|squad| (invader_core, squad))
+                });
+            let Some((invader_core, (body, count))) = squad else {
+                // Dropping the spawn pool releases the attackers.
+                attacker_pools.remove(&remote_name);
+                continue;
+            };
+
+            let spawn_pool = attacker_pools.entry(remote_name).or_insert_with(|| {
+                let mut base_spawn_request = u!(with_room_state(room_name, |room_state| {
+                    generic_base_spawn_request(room_state, Defender)
+                }));
+                base_spawn_request.priority = INVADER_CORE_ATTACKER_SPAWN_PRIORITY;
+                let travel_spec = TravelSpec::new(invader_core.xy.to_pos(remote_name), 1);
+                SpawnPool::new(room_name, base_spawn_request, SpawnPoolOptions::default().travel_spec(Some(travel_spec)))
+            });
+            spawn_pool.target_number_of_creeps = count;
+            spawn_pool.base_spawn_request.body = body;
+
+            spawn_pool.with_spawned_creeps(|creep_ref| async move {
+                let core_pos = invader_core.xy.to_pos(remote_name);
+                loop {
+                    if let Err(err) = move_to_cached(&creep_ref, core_pos, 1).await {
+                        err.warn(&format!("Attacker could not reach the invader core in {}", remote_name));
+                        sleep(1).await;
+                        continue;
+                    }
+                    if let Some(target) = game::get_object_by_id_typed(&invader_core.id) {
+                        creep_ref
+                            .borrow_mut()
+                            .attack(&target)
+                            .warn_if_err(&format!("Failed to attack the invader core in {}", remote_name));
+                    }
+                    sleep(1).await;
+                }
+            });
+        }
+
+        sleep(1).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::{FxHashMap, FxHashSet};
+    use screeps::{ObjectId, Position, RawObjectId, ResourceType, RoomName, StructureContainer};
+    use screeps::Part::{Attack, Move};
+    use crate::defense::invader_cores::{core_attacker_squad, update_paused_remotes};
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::hauling::requests::{HaulRequest, HaulRequestHandle};
+    use crate::hauling::requests::HaulRequestKind::WithdrawRequest;
+    use crate::hauling::requests::HaulRequestTargetKind::RegularTarget;
+    use crate::hauling::scheduling_hauls::schedule_haul;
+    use crate::room_states::room_state::{test_empty_unowned_room_name, InvaderCoreData};
+    use crate::utils::game_tick::game_tick;
+
+    fn remote_room_name() -> RoomName {
+        RoomName::new("W2N1").unwrap()
+    }
+
+    fn schedule_withdraw(packed_id: u128, target_room_name: RoomName, replaced: Option<HaulRequestHandle>) -> HaulRequestHandle {
+        let container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(packed_id).into();
+        let mut request = HaulRequest::new(
+            WithdrawRequest,
+            test_empty_unowned_room_name(),
+            ResourceType::Energy,
+            container_id,
+            RegularTarget,
+            false,
+            Position::new_from_raw(25, 25, target_room_name)
+        );
+        request.amount = 500;
+        schedule_haul(request, replaced)
+    }
+
+    fn invader_core(collapse_tick: Option<u32>) -> InvaderCoreData {
+        InvaderCoreData {
+            id: RawObjectId::from_packed(100).into(),
+            xy: (30, 30).try_into().unwrap(),
+            level: 0,
+            collapse_tick,
+        }
+    }
+
+    #[test]
+    fn test_core_attacker_squad() {
+        // 12 attack parts are needed to destroy the core in time.
+        let (body, count) = core_attacker_squad(0, 5600).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(body.count_parts(Attack), 12);
+        assert_eq!(body.count_parts(Move), 12);
+
+        let (body, count) = core_attacker_squad(0, 800).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(body.count_parts(Attack), 6);
+        assert!(body.energy_cost() <= 800);
+
+        // Strongholds are not attacked.
+        assert!(core_attacker_squad(1, 12_900).is_none());
+        // Not enough energy for a single attacker.
+        assert!(core_attacker_squad(0, 100).is_none());
+    }
+
+    #[test]
+    fn test_remote_hauling_paused_while_core_present() {
+        let room_name = test_empty_unowned_room_name();
+        let remote_name = remote_room_name();
+        let mut paused_remotes = FxHashSet::default();
+        let local_handle = schedule_withdraw(1, room_name, None);
+        let remote_handle = schedule_withdraw(2, remote_name, None);
+
+        // No core, nothing happens.
+        let mut remote_invader_cores = FxHashMap::from_iter([(remote_name, None)]);
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick());
+        assert!(paused_remotes.is_empty());
+        assert_eq!(remote_handle.request.borrow().amount, 500);
+
+        // The core appears and the requests in the remote are cancelled.
+        remote_invader_cores.insert(remote_name, Some(invader_core(None)));
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick());
+        assert!(paused_remotes.contains(&remote_name));
+        assert_eq!(remote_handle.request.borrow().amount, 0);
+        assert_eq!(local_handle.request.borrow().amount, 500);
+
+        // Requests scheduled while paused are cancelled too.
+        let remote_handle = schedule_withdraw(2, remote_name, Some(remote_handle));
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick());
+        assert_eq!(remote_handle.request.borrow().amount, 0);
+
+        // The core is destroyed and the hauling is resumed.
+        remote_invader_cores.insert(remote_name, None);
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick());
+        assert!(paused_remotes.is_empty());
+        let remote_handle = schedule_withdraw(2, remote_name, Some(remote_handle));
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick());
+        assert_eq!(remote_handle.request.borrow().amount, 500);
+        assert_eq!(local_handle.request.borrow().amount, 500);
+    }
+
+    #[test]
+    fn test_remote_hauling_resumed_when_core_collapses() {
+        let room_name = test_empty_unowned_room_name();
+        let remote_name = remote_room_name();
+        let mut paused_remotes = FxHashSet::default();
+        let remote_invader_cores = FxHashMap::from_iter([(remote_name, Some(invader_core(Some(game_tick() + 10))))]);
+
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick());
+        assert!(paused_remotes.contains(&remote_name));
+
+        // The core collapses, even if the remote is not visible to notice it.
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick() + 10);
+        assert!(paused_remotes.is_empty());
+
+        // Remotes the room no longer has are forgotten.
+        paused_remotes.insert(RoomName::new("W3N1").unwrap());
+        update_paused_remotes(room_name, &mut paused_remotes, &remote_invader_cores, game_tick() + 10);
+        assert!(paused_remotes.is_empty());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod chokepoints;
+pub mod defend_ramparts;
+pub mod defend_rooms;
+pub mod incidents;
+pub mod invader_cores;
+pub mod safe_mode;
+pub mod threat;
\ No newline at end of file
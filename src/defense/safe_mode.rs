@@ -0,0 +1,206 @@
+use log::warn;
+use screeps::{find, game, HasPosition, ResourceType, StructureTower, TOWER_ENERGY_COST};
+use screeps::game::get_object_by_id_typed;
+use screeps::StructureType::{Rampart, Spawn, Storage, Terminal, Tower};
+use crate::defense::threat::ThreatReport;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::room_states::room_state::RoomState;
+use crate::towers::tower_attack_power;
+use crate::utils::game_tick::game_tick;
+use crate::utils::result_utils::ResultUtils;
+
+/// Number of ticks needed to spawn defenders and have them take positions at the ramparts. Safe
+/// mode is only activated when the ramparts would fall sooner than that.
+const DEFENDER_RESPONSE_TICKS: u32 = 200;
+
+/// State of the room relevant to deciding whether to activate safe mode.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SafeModeSituation {
+    /// Number of safe mode activations available in the controller.
+    pub safe_mode_available: u32,
+    /// Whether safe mode is on cooldown.
+    pub on_cooldown: bool,
+    /// Whether safe mode is already active.
+    pub active: bool,
+    /// Total damage per tick the towers deal to the hostile they damage the most.
+    pub tower_damage: u32,
+    /// Whether there is a spawn, storage or terminal in the room not covered by a rampart.
+    pub core_exposed: bool,
+}
+
+/// Activates safe mode in the room if the policy in `safe_mode_required` says so and records
+/// the tick of the activation.
+pub fn maybe_activate_safe_mode(room_state: &mut RoomState, threat: &ThreatReport) {
+    let Some(room) = game::rooms().get(room_state.room_name) else {
+        return;
+    };
+    let Some(controller) = room.controller() else {
+        return;
+    };
+
+    let hostile_xys = room
+        .find(find::HOSTILE_CREEPS, None)
+        .iter()
+        .map(|creep| creep.pos().xy())
+        .collect::<Vec<_>>();
+    let tower_xys = room_state
+        .structures_with_type::<StructureTower>(Tower)
+        .filter(|(_, id)| {
+            get_object_by_id_typed(id)
+                .is_some_and(|tower| tower.store().get_used_capacity(Some(ResourceType::Energy)) >= TOWER_ENERGY_COST)
+        })
+        .map(|(xy, _)| xy)
+        .collect::<Vec<_>>();
+    let tower_damage = hostile_xys
+        .iter()
+        .map(|hostile_xy| {
+            tower_xys
+                .iter()
+                .map(|tower_xy| tower_attack_power(tower_xy.dist(*hostile_xy)) as u32)
+                .sum::<u32>()
+        })
+        .max()
+        .unwrap_or(0);
+
+    let situation = SafeModeSituation {
+        safe_mode_available: controller.safe_mode_available(),
+        on_cooldown: controller.safe_mode_cooldown().is_some_and(|cooldown| cooldown > 0),
+        active: controller.safe_mode().is_some_and(|ticks| ticks > 0),
+        tower_damage,
+        core_exposed: core_exposed(room_state),
+    };
+
+    if safe_mode_required(&situation, threat) {
+        warn!(
+            "Activating safe mode in {}. Threat: {:?}, situation: {:?}.",
+            room_state.room_name,
+            threat,
+            situation
+        );
+        let result = controller.activate_safe_mode();
+        if result.is_ok() {
+            room_state.safe_mode_activation_tick = Some(game_tick());
+        }
+        result.warn_if_err(&format!("Failed to activate safe mode in {}", room_state.room_name));
+    }
+}
+
+/// Decides whether to activate safe mode. The policy is conservative: safe mode is activated only if
+/// it is available, the ramparts are going to be breached before defenders can respond, the towers
+/// cannot kill the hostiles through their healing and a spawn, storage or terminal would be left
+/// exposed.
+pub fn safe_mode_required(situation: &SafeModeSituation, threat: &ThreatReport) -> bool {
+    if situation.active || situation.on_cooldown || situation.safe_mode_available == 0 {
+        return false;
+    }
+    if !situation.core_exposed {
+        return false;
+    }
+    if threat.breach_eta.is_none_or(|breach_eta| breach_eta >= DEFENDER_RESPONSE_TICKS) {
+        return false;
+    }
+    situation.tower_damage <= threat.total_heal
+}
+
+/// Whether there is a spawn, storage or terminal in the room without a rampart on it.
+fn core_exposed(room_state: &RoomState) -> bool {
+    let rampart_xys = room_state.structures.get(&Rampart);
+    [Spawn, Storage, Terminal].into_iter().any(|structure_type| {
+        room_state.structures.get(&structure_type).is_some_and(|structures| {
+            structures
+                .keys()
+                .any(|xy| !rampart_xys.is_some_and(|rampart_xys| rampart_xys.contains_key(xy)))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Part::{Attack, Heal, Move, RangedAttack, Tough, Work};
+    use screeps::ResourceType::{
+        CatalyzedGhodiumAlkalide,
+        CatalyzedKeaniumAlkalide,
+        CatalyzedLemergiumAlkalide,
+        CatalyzedZynthiumAcid,
+    };
+    use crate::defense::safe_mode::{safe_mode_required, SafeModeSituation};
+    use crate::defense::threat::{breach_eta, total_threat, HostileBodyPart, ThreatReport};
+    use crate::towers::tower_attack_power;
+
+    fn threat(hostiles: &[Vec<HostileBodyPart>], weakest_rampart_hits: u32) -> ThreatReport {
+        let mut report = total_threat(hostiles);
+        report.breach_eta = breach_eta([weakest_rampart_hits].into_iter(), report.structure_dps());
+        report
+    }
+
+    fn situation(tower_damage: u32) -> SafeModeSituation {
+        SafeModeSituation {
+            safe_mode_available: 1,
+            on_cooldown: false,
+            active: false,
+            tower_damage,
+            core_exposed: true,
+        }
+    }
+
+    fn boosted_quad() -> Vec<Vec<HostileBodyPart>> {
+        let mut body = Vec::new();
+        body.extend([(Tough, Some(CatalyzedGhodiumAlkalide)); 10]);
+        body.extend([(Work, Some(CatalyzedZynthiumAcid)); 10]);
+        body.extend([(RangedAttack, Some(CatalyzedKeaniumAlkalide)); 5]);
+        body.extend([(Heal, Some(CatalyzedLemergiumAlkalide)); 15]);
+        body.extend([(Move, None); 10]);
+        vec![body; 4]
+    }
+
+    #[test]
+    fn test_npc_invader_does_not_trigger_safe_mode() {
+        let invader = vec![(Move, None), (Move, None), (Attack, None), (Attack, None), (Heal, None)];
+        let threat = threat(&[invader], 10_000);
+        // The invader breaches the weak rampart soon, but a single tower easily outdamages its
+        // healing.
+        assert!(threat.breach_eta.unwrap() < 200);
+        assert!(!safe_mode_required(&situation(tower_attack_power(20) as u32), &threat));
+    }
+
+    #[test]
+    fn test_boosted_quad_triggers_safe_mode() {
+        let threat = threat(&boosted_quad(), 1_000_000);
+        assert!(threat.boosted);
+        // Three towers at full power do not outdamage the healing of the quad.
+        let tower_damage = 3 * tower_attack_power(5) as u32;
+        assert!(tower_damage <= threat.total_heal);
+        assert!(safe_mode_required(&situation(tower_damage), &threat));
+    }
+
+    #[test]
+    fn test_safe_mode_not_activated_when_unavailable_or_unnecessary() {
+        let quad_threat = threat(&boosted_quad(), 1_000_000);
+        let base_situation = situation(1800);
+
+        let mut unavailable = base_situation;
+        unavailable.safe_mode_available = 0;
+        assert!(!safe_mode_required(&unavailable, &quad_threat));
+
+        let mut on_cooldown = base_situation;
+        on_cooldown.on_cooldown = true;
+        assert!(!safe_mode_required(&on_cooldown, &quad_threat));
+
+        let mut active = base_situation;
+        active.active = true;
+        assert!(!safe_mode_required(&active, &quad_threat));
+
+        // Everything important is covered by ramparts.
+        let mut core_covered = base_situation;
+        core_covered.core_exposed = false;
+        assert!(!safe_mode_required(&core_covered, &quad_threat));
+
+        // The ramparts hold long enough for the defenders to arrive.
+        let strong_ramparts_threat = threat(&boosted_quad(), 100_000_000);
+        assert!(!safe_mode_required(&base_situation, &strong_ramparts_threat));
+
+        // Healers that cannot damage anything.
+        let healers_threat = threat(&[vec![(Heal, None); 20]], 0);
+        assert!(!safe_mode_required(&situation(0), &healers_threat));
+    }
+}
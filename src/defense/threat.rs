@@ -0,0 +1,250 @@
+use screeps::{
+    rampart_hits_max,
+    Boost,
+    Creep,
+    Part,
+    ResourceType,
+    ATTACK_POWER,
+    DISMANTLE_POWER,
+    HEAL_POWER,
+    RANGED_ATTACK_POWER,
+};
+use screeps::StructureType::Rampart;
+use crate::room_states::room_state::RoomState;
+
+/// An active body part of a hostile creep along with its boost.
+pub type HostileBodyPart = (Part, Option<ResourceType>);
+
+/// The danger the hostile creeps in a room pose, in hits per tick of the whole group.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ThreatReport {
+    pub total_attack: u32,
+    pub total_ranged: u32,
+    pub total_heal: u32,
+    pub total_dismantle: u32,
+    /// Whether any of the hostiles is boosted.
+    pub boosted: bool,
+    /// The number of ticks until the weakest main rampart falls if the hostiles attack and dismantle
+    /// it with all they have. `None` if they cannot damage it or there are no main ramparts.
+    pub breach_eta: Option<u32>,
+}
+
+impl ThreatReport {
+    /// Damage per tick the hostiles can deal to a structure.
+    pub fn structure_dps(&self) -> u32 {
+        self.total_attack + self.total_ranged + self.total_dismantle
+    }
+}
+
+/// Assesses the threat posed by hostile creeps with given bodies to the room, including how soon
+/// they can breach the main ramparts.
+pub fn assess_threat(room_state: &RoomState, hostiles: &[Vec<HostileBodyPart>]) -> ThreatReport {
+    let mut report = total_threat(hostiles);
+    report.breach_eta = breach_eta(main_rampart_hits(room_state).into_iter(), report.structure_dps());
+    report
+}
+
+/// Sums up the damage and healing per tick of hostile creeps with given bodies, without the breach
+/// ETA.
+pub fn total_threat(hostiles: &[Vec<HostileBodyPart>]) -> ThreatReport {
+    let mut report = ThreatReport::default();
+    for body in hostiles.iter() {
+        let body_report = body_threat(body);
+        report.total_attack += body_report.total_attack;
+        report.total_ranged += body_report.total_ranged;
+        report.total_heal += body_report.total_heal;
+        report.total_dismantle += body_report.total_dismantle;
+        report.boosted |= body_report.boosted;
+    }
+    report
+}
+
+/// Computes the damage and healing per tick of a single creep with given body, including boosts.
+pub fn body_threat(body: &[HostileBodyPart]) -> ThreatReport {
+    let mut report = ThreatReport::default();
+    for &(part, boost) in body.iter() {
+        let boost = boost.and_then(|resource_type| resource_type.boost());
+        match (part, boost) {
+            (Part::Attack, Some(Boost::Attack(multiplier))) => report.total_attack += ATTACK_POWER * multiplier,
+            (Part::Attack, _) => report.total_attack += ATTACK_POWER,
+            (Part::RangedAttack, Some(Boost::RangedAttack(multiplier))) => report.total_ranged += RANGED_ATTACK_POWER * multiplier,
+            (Part::RangedAttack, _) => report.total_ranged += RANGED_ATTACK_POWER,
+            (Part::Heal, Some(Boost::Heal(multiplier))) => report.total_heal += HEAL_POWER * multiplier,
+            (Part::Heal, _) => report.total_heal += HEAL_POWER,
+            (Part::Work, Some(Boost::Dismantle(multiplier))) => report.total_dismantle += DISMANTLE_POWER * multiplier,
+            (Part::Work, _) => report.total_dismantle += DISMANTLE_POWER,
+            _ => (),
+        }
+        report.boosted |= boost.is_some();
+    }
+    report
+}
+
+/// The number of ticks until the weakest of the ramparts with given hits falls under given damage
+/// per tick. `None` if there are no ramparts or no damage.
+pub fn breach_eta<I>(rampart_hits: I, dps: u32) -> Option<u32>
+where
+    I: Iterator<Item = u32>,
+{
+    if dps == 0 {
+        return None;
+    }
+    rampart_hits.min().map(|hits| hits.div_ceil(dps))
+}
+
+/// Current hits of ramparts planned as the main ones, with zero for the ones not built. Fully
+/// repaired ramparts are not among the structures to repair, so they have the maximum hits.
+fn main_rampart_hits(room_state: &RoomState) -> Vec<u32> {
+    let Some(plan) = room_state.plan.as_ref() else {
+        return Vec::new();
+    };
+    let built_ramparts = room_state.structures.get(&Rampart);
+    let damaged_ramparts = room_state.structures_to_repair.get(&Rampart);
+    plan.main_ramparts
+        .iter()
+        .map(|xy| {
+            if !built_ramparts.is_some_and(|ramparts| ramparts.contains_key(xy)) {
+                return 0;
+            }
+            damaged_ramparts
+                .and_then(|ramparts| ramparts.iter().find(|rampart| rampart.xy == *xy))
+                .map(|rampart| rampart.hits)
+                .unwrap_or_else(|| rampart_hits_max(room_state.rcl as u32))
+        })
+        .collect()
+}
+
+/// Active body parts of a hostile creep along with their boosts.
+pub fn hostile_body(creep: &Creep) -> Vec<HostileBodyPart> {
+    creep
+        .body()
+        .iter()
+        .filter(|body_part| body_part.hits() > 0)
+        .map(|body_part| (body_part.part(), body_part.boost()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{ObjectId, Part, ResourceType, StructureType};
+    use screeps::Part::{Attack, Heal, Move, RangedAttack, Tough, Work};
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::construction::triage_repair_sites::StructureToRepair;
+    use crate::defense::threat::{assess_threat, body_threat, breach_eta, ThreatReport};
+    use crate::room_planning::plan::{Plan, PlanScore, PlannedControllerData};
+    use crate::room_states::room_state::empty_unowned_room_state;
+    use crate::utils::multi_map_utils::MultiMapUtils;
+    use crate::utils::test_utils::xy;
+
+    #[test]
+    fn test_unboosted_body_threat() {
+        let body = [(Move, None), (Attack, None), (Attack, None), (RangedAttack, None), (Heal, None), (Work, None)];
+        assert_eq!(body_threat(&body), ThreatReport {
+            total_attack: 60,
+            total_ranged: 10,
+            total_heal: 12,
+            total_dismantle: 50,
+            boosted: false,
+            breach_eta: None,
+        });
+    }
+
+    #[test]
+    fn test_boosted_body_threat() {
+        let body = [
+            (Tough, Some(ResourceType::CatalyzedGhodiumAlkalide)),
+            (Attack, Some(ResourceType::UtriumHydride)),
+            (RangedAttack, Some(ResourceType::KeaniumAlkalide)),
+            (Heal, Some(ResourceType::CatalyzedLemergiumAlkalide)),
+            (Work, Some(ResourceType::CatalyzedZynthiumAcid)),
+        ];
+        assert_eq!(body_threat(&body), ThreatReport {
+            total_attack: 60,
+            total_ranged: 30,
+            total_heal: 48,
+            total_dismantle: 200,
+            boosted: true,
+            breach_eta: None,
+        });
+
+        // Work parts boosted for harvesting dismantle at the base rate.
+        let harvester_body = [(Work, Some(ResourceType::UtriumOxide))];
+        let report = body_threat(&harvester_body);
+        assert_eq!(report.total_dismantle, 50);
+        assert!(report.boosted);
+    }
+
+    #[test]
+    fn test_breach_eta() {
+        assert_eq!(breach_eta([100_000, 30_000, 50_000].into_iter(), 1000), Some(30));
+        assert_eq!(breach_eta([30_001].into_iter(), 1000), Some(31));
+        assert_eq!(breach_eta([30_000].into_iter(), 0), None);
+        assert_eq!(breach_eta([].into_iter(), 1000), None);
+    }
+
+    #[test]
+    fn test_assess_threat_against_main_ramparts() {
+        let mut room_state = empty_unowned_room_state();
+        room_state.rcl = 6;
+        let mut plan = Plan::new(
+            RoomMatrix::default(),
+            PlannedControllerData::default(),
+            Vec::new(),
+            None,
+            PlanScore::default(),
+            Vec::new(),
+            vec![xy(10, 10), xy(11, 10)],
+        );
+        room_state.plan = Some(plan.clone());
+        for (id, rampart_xy) in [(1, xy(10, 10)), (2, xy(11, 10))] {
+            room_state
+                .structures
+                .entry(StructureType::Rampart)
+                .or_default()
+                .insert(rampart_xy, ObjectId::from_packed(id));
+        }
+        room_state.structures_to_repair.push_or_insert(StructureType::Rampart, StructureToRepair {
+            id: ObjectId::from_packed(2),
+            xy: xy(11, 10),
+            hits: 10_000,
+            hits_max: 30_000_000,
+        });
+
+        let dismantler = vec![(Work, None); 10];
+        let healer = vec![(Heal, None); 5];
+        let report = assess_threat(&room_state, &[dismantler.clone(), healer.clone()]);
+        assert_eq!(report.total_dismantle, 500);
+        assert_eq!(report.total_heal, 60);
+        assert_eq!(report.breach_eta, Some(20));
+
+        // Healers alone cannot breach anything.
+        assert_eq!(assess_threat(&room_state, &[healer]).breach_eta, None);
+
+        // A planned rampart that is not built is already breached.
+        plan.main_ramparts.push(xy(12, 10));
+        room_state.plan = Some(plan);
+        assert_eq!(assess_threat(&room_state, &[dismantler]).breach_eta, Some(0));
+    }
+
+    #[test]
+    fn test_fully_repaired_ramparts_have_max_hits() {
+        let mut room_state = empty_unowned_room_state();
+        room_state.rcl = 2;
+        room_state.plan = Some(Plan::new(
+            RoomMatrix::default(),
+            PlannedControllerData::default(),
+            Vec::new(),
+            None,
+            PlanScore::default(),
+            Vec::new(),
+            vec![xy(10, 10)],
+        ));
+        room_state
+            .structures
+            .entry(StructureType::Rampart)
+            .or_default()
+            .insert(xy(10, 10), ObjectId::from_packed(1));
+        let report = assess_threat(&room_state, &[vec![(Part::Attack, None); 10]]);
+        assert_eq!(report.breach_eta, Some(1000));
+    }
+}
@@ -1,10 +1,11 @@
 use log::debug;
-use screeps::Part::{Carry, Move, Work};
+use screeps::Part::{Carry, Claim, Move, Work};
 use screeps::{
     RoomName, CARRY_CAPACITY, CONSTRUCTION_COST_ROAD_SWAMP_RATIO, CONSTRUCTION_COST_ROAD_WALL_RATIO, CONTAINER_DECAY,
-    CONTAINER_DECAY_TIME_OWNED, CREEP_LIFE_TIME, ENERGY_REGEN_TIME, EXTRACTOR_COOLDOWN, HARVEST_MINERAL_POWER,
-    HARVEST_POWER, LAB_REACTION_AMOUNT, LINK_CAPACITY, LINK_LOSS_RATIO, MINERAL_REGEN_TIME,
-    RAMPART_DECAY_AMOUNT, REPAIR_POWER, ROAD_DECAY_AMOUNT, ROAD_DECAY_TIME, SOURCE_ENERGY_CAPACITY, INTENT_CPU_COST,
+    CONTAINER_DECAY_TIME, CONTAINER_DECAY_TIME_OWNED, CREEP_CLAIM_LIFE_TIME, CREEP_LIFE_TIME, ENERGY_REGEN_TIME,
+    EXTRACTOR_COOLDOWN, HARVEST_MINERAL_POWER, HARVEST_POWER, LAB_REACTION_AMOUNT, LINK_CAPACITY, LINK_LOSS_RATIO,
+    MINERAL_REGEN_TIME, RAMPART_DECAY_AMOUNT, REPAIR_COST, REPAIR_POWER, ROAD_DECAY_AMOUNT, ROAD_DECAY_TIME,
+    SOURCE_ENERGY_CAPACITY, INTENT_CPU_COST,
 };
 
 const FAST_FILLER_CARRY: [u32; 4] = [18, 4, 4, 6];
@@ -20,7 +21,7 @@ const AVERAGE_MINERAL_DENSITY: f32 = 15_000.0 * 0.1 + 35_000.0 * 0.4 + 70_000.0
 pub fn energy_balance_and_cpu_cost(
     room_name: RoomName,
     source_distances: Vec<u8>,
-    mineral_distance: u8,
+    mineral_distance: Option<u8>,
     controller_distance: u8,
     plain_roads_count: u32,
     plain_roads_avg_dist: f32,
@@ -69,17 +70,21 @@ pub fn energy_balance_and_cpu_cost(
     // Mineral mining.
     // These computations are approximate as the specifics (such as number of required miner creeps) vary depending on density.
     let average_mineral_amount = AVERAGE_MINERAL_DENSITY;
-    let mineral_miner_work = 40;
-    let mineral_miner_move = 10;
-    let mineral_miner_energy_cost = mineral_miner_work * Work.cost() + mineral_miner_move * Move.cost();
-    let mineral_miner_speed = mineral_miner_work / (2 * mineral_miner_move);
-    let mining_time = CREEP_LIFE_TIME as f32 - mineral_miner_speed as f32 * mineral_distance as f32;
-    let total_extractions = mining_time as f32 / (1 + EXTRACTOR_COOLDOWN) as f32;
-    let extractions_required = AVERAGE_MINERAL_DENSITY / (mineral_miner_work as f32 * HARVEST_MINERAL_POWER as f32);
-    // This equals 1 unless the mineral is very far away.
-    let number_of_miners_per_regen = (extractions_required / total_extractions).ceil();
-    let mineral_miner_energy_cost_per_tick =
-        mineral_miner_energy_cost as f32 * number_of_miners_per_regen / MINERAL_REGEN_TIME as f32;
+    // Rooms without a mineral have no mineral mining costs.
+    let mineral_miner_energy_cost_per_tick = if let Some(mineral_distance) = mineral_distance {
+        let mineral_miner_work = 40;
+        let mineral_miner_move = 10;
+        let mineral_miner_energy_cost = mineral_miner_work * Work.cost() + mineral_miner_move * Move.cost();
+        let mineral_miner_speed = mineral_miner_work / (2 * mineral_miner_move);
+        let mining_time = CREEP_LIFE_TIME as f32 - mineral_miner_speed as f32 * mineral_distance as f32;
+        let total_extractions = mining_time as f32 / (1 + EXTRACTOR_COOLDOWN) as f32;
+        let extractions_required = AVERAGE_MINERAL_DENSITY / (mineral_miner_work as f32 * HARVEST_MINERAL_POWER as f32);
+        // This equals 1 unless the mineral is very far away.
+        let number_of_miners_per_regen = (extractions_required / total_extractions).ceil();
+        mineral_miner_energy_cost as f32 * number_of_miners_per_regen / MINERAL_REGEN_TIME as f32
+    } else {
+        0.0
+    };
     // TODO CPU
 
     // Fast filler creeps.
@@ -214,6 +219,76 @@ pub fn energy_balance_and_cpu_cost(
     (total_energy_balance, total_intents_per_tick * INTENT_CPU_COST as f32)
 }
 
+/// Approximate energy balance per tick of mining a reserved remote room with sources at given round-trip
+/// distances from the storage, including maintenance of given number of roads and containers.
+pub fn remote_energy_balance(
+    room_name: RoomName,
+    round_trip_distances: &[u32],
+    roads_count: u32,
+    containers_count: u32,
+) -> f32 {
+    let source_energy_per_tick = SOURCE_ENERGY_PER_TICK * (round_trip_distances.len() as f32);
+
+    // Source mining with miners standing on the containers.
+    let miner_work = 6;
+    let miner_carry = 1;
+    let miner_move = 3;
+    let miner_energy_cost = miner_work * Work.cost() + miner_carry * Carry.cost() + miner_move * Move.cost();
+    let miners_energy_cost_per_tick = round_trip_distances
+        .iter()
+        .map(|&dist| miner_energy_cost as f32 / (CREEP_LIFE_TIME as f32 - (dist / 2) as f32))
+        .sum::<f32>();
+
+    // Hauling on roads, so that one move part is required per two carry parts.
+    let haul_carry_parts = round_trip_distances
+        .iter()
+        .map(|&dist| SOURCE_ENERGY_PER_TICK * dist as f32 / CARRY_CAPACITY as f32)
+        .sum::<f32>();
+    let haulers_energy_cost_per_tick =
+        haul_carry_parts * (Carry.cost() as f32 + Move.cost() as f32 / 2.0) / CREEP_LIFE_TIME as f32;
+
+    // Reserving the controller to double the capacity of the sources.
+    // TODO Include the travel time of the reserver.
+    let reserver_claim = 2;
+    let reserver_move = 2;
+    let reserver_energy_cost = reserver_claim * Claim.cost() + reserver_move * Move.cost();
+    let reserver_energy_cost_per_tick = reserver_energy_cost as f32 / CREEP_CLAIM_LIFE_TIME as f32;
+
+    // Road and container maintenance, assuming the roads are on plains and repaired by the haulers.
+    let road_maintenance_energy_cost_per_tick =
+        (roads_count * ROAD_DECAY_AMOUNT) as f32 / ROAD_DECAY_TIME as f32 * REPAIR_COST;
+    let container_maintenance_energy_cost_per_tick =
+        (containers_count * CONTAINER_DECAY) as f32 / CONTAINER_DECAY_TIME as f32 * REPAIR_COST;
+
+    let total_energy_balance = source_energy_per_tick
+        - miners_energy_cost_per_tick
+        - haulers_energy_cost_per_tick
+        - reserver_energy_cost_per_tick
+        - road_maintenance_energy_cost_per_tick
+        - container_maintenance_energy_cost_per_tick;
+
+    debug!(
+        "Approximate energy balance for remote room {}:\n\
+    * income: +{}\n\
+    * source mining: -{}\n\
+    * haulers: -{}\n\
+    * reserver: -{}\n\
+    * road maintenance: -{}\n\
+    * container maintenance: -{}\n\
+    Total: {}",
+        room_name,
+        source_energy_per_tick,
+        miners_energy_cost_per_tick,
+        haulers_energy_cost_per_tick,
+        reserver_energy_cost_per_tick,
+        road_maintenance_energy_cost_per_tick,
+        container_maintenance_energy_cost_per_tick,
+        total_energy_balance
+    );
+
+    total_energy_balance
+}
+
 #[inline]
 pub fn spawn_intent_cost(energy_cost: u32) -> f32 {
     1.0 + energy_cost as f32 * FAST_FILLER_INTENTS_PER_ENERGY
@@ -0,0 +1,123 @@
+/// A buy order on the market for a resource the room has a surplus of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuyOrderSnapshot {
+    pub id: String,
+    /// Price in credits per unit of the resource.
+    pub price: f64,
+    pub remaining_amount: u32,
+    /// Energy needed to send 1000 units of any resource from the room to the room of the order,
+    /// as given by `calc_transaction_cost`.
+    pub transaction_cost_per_1000: u32,
+}
+
+/// A deal with a buy order selling the resource to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deal {
+    pub order_id: String,
+    pub amount: u32,
+    /// Energy spent on sending the resource.
+    pub energy_cost: u32,
+    /// Credits received minus the value of the energy spent.
+    pub net_credits: f64,
+}
+
+/// Credits received for a unit of the resource sold to the order minus the value of the energy
+/// spent on sending it.
+pub fn net_unit_price(order: &BuyOrderSnapshot, credits_per_energy: f64) -> f64 {
+    order.price - order.transaction_cost_per_1000 as f64 / 1000.0 * credits_per_energy
+}
+
+/// Energy needed to send given amount of the resource to the room of the order.
+fn transaction_energy_cost(order: &BuyOrderSnapshot, amount: u32) -> u32 {
+    (amount as u64 * order.transaction_cost_per_1000 as u64).div_ceil(1000) as u32
+}
+
+/// The deal with the buy order with the best price net of the transaction energy cost, selling
+/// as much of the surplus as the order takes and the energy available pays for.
+/// `None` if no order nets a positive amount of credits.
+pub fn best_deal(
+    orders: &[BuyOrderSnapshot],
+    surplus: u32,
+    available_energy: u32,
+    credits_per_energy: f64
+) -> Option<Deal> {
+    let order = orders
+        .iter()
+        .filter(|order| order.remaining_amount > 0 && net_unit_price(order, credits_per_energy) > 0.0)
+        .max_by(|a, b| net_unit_price(a, credits_per_energy).total_cmp(&net_unit_price(b, credits_per_energy)))?;
+
+    let affordable_amount = if order.transaction_cost_per_1000 == 0 {
+        u32::MAX
+    } else {
+        (available_energy as u64 * 1000 / order.transaction_cost_per_1000 as u64).min(u32::MAX as u64) as u32
+    };
+    let amount = surplus.min(order.remaining_amount).min(affordable_amount);
+    if amount == 0 {
+        return None;
+    }
+
+    Some(Deal {
+        order_id: order.id.clone(),
+        amount,
+        energy_cost: transaction_energy_cost(order, amount),
+        net_credits: net_unit_price(order, credits_per_energy) * amount as f64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::economy::market::{best_deal, net_unit_price, BuyOrderSnapshot};
+
+    fn order(id: &str, price: f64, remaining_amount: u32, transaction_cost_per_1000: u32) -> BuyOrderSnapshot {
+        BuyOrderSnapshot {
+            id: id.into(),
+            price,
+            remaining_amount,
+            transaction_cost_per_1000,
+        }
+    }
+
+    #[test]
+    fn test_net_unit_price() {
+        assert_eq!(net_unit_price(&order("a", 1.0, 1000, 500), 0.5), 0.75);
+        assert_eq!(net_unit_price(&order("a", 1.0, 1000, 0), 0.5), 1.0);
+    }
+
+    #[test]
+    fn test_best_deal_accounts_for_transaction_cost() {
+        let orders = vec![
+            // The highest price, but far away.
+            order("far", 1.2, 5000, 900),
+            order("near", 1.0, 2000, 100),
+            // Sold at a loss.
+            order("loss", 0.2, 5000, 600),
+        ];
+        let deal = best_deal(&orders, 3000, 10_000, 0.5).unwrap();
+        assert_eq!(deal.order_id, "near");
+        // Limited by the order.
+        assert_eq!(deal.amount, 2000);
+        assert_eq!(deal.energy_cost, 200);
+        assert!((deal.net_credits - 1900.0).abs() < 1e-6);
+
+        // With cheap energy, the far order is better and the amount is limited by the surplus.
+        let deal = best_deal(&orders, 3000, 10_000, 0.1).unwrap();
+        assert_eq!(deal.order_id, "far");
+        assert_eq!(deal.amount, 3000);
+        assert_eq!(deal.energy_cost, 2700);
+    }
+
+    #[test]
+    fn test_best_deal_limits() {
+        let orders = vec![order("a", 1.0, 5000, 333)];
+        // Limited by the energy.
+        let deal = best_deal(&orders, 5000, 100, 0.5).unwrap();
+        assert_eq!(deal.amount, 300);
+        assert!(deal.energy_cost <= 100);
+
+        assert_eq!(best_deal(&orders, 5000, 0, 0.5), None);
+        assert_eq!(best_deal(&orders, 0, 1000, 0.5), None);
+        // No order nets positive.
+        assert_eq!(best_deal(&[order("b", 0.1, 5000, 500)], 5000, 1000, 0.5), None);
+        assert_eq!(best_deal(&[], 5000, 1000, 0.5), None);
+    }
+}
@@ -2,4 +2,7 @@ pub mod cost_approximation;
 pub mod room_eco_config;
 pub mod room_eco_stats;
 pub mod update_eco_config;
-pub mod gather_eco_samples;
\ No newline at end of file
+pub mod gather_eco_samples;
+pub mod upgrade_allocation;
+pub mod market;
+pub mod resource_balancer;
@@ -0,0 +1,246 @@
+use std::cmp::{min, Reverse};
+use log::debug;
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::{ResourceType, RoomName, StructureStorage, StructureTerminal};
+use screeps::game::get_object_by_id_typed;
+use screeps::ResourceType::{Catalyst, Energy, Hydrogen, Keanium, Lemergium, Oxygen, Utrium, Zynthium};
+use screeps::StructureType::{Storage, Terminal};
+use crate::config::{MAX_BALANCING_TRANSFER_AMOUNT, MIN_BALANCED_ROOM_ENERGY, MIN_ENERGY_DONOR_ROOM_ENERGY, MINERAL_BALANCING_THRESHOLD};
+use crate::hauling::transfers::get_used_capacities_with_object;
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::kernel::sleep::sleep;
+use crate::room_maintenance::manage_terminal::{has_queued_terminal_transfer, queue_terminal_transfer, TerminalTransfer};
+use crate::room_states::room_states::for_each_owned_room;
+
+/// The number of ticks between planning the transfers.
+const RESOURCE_BALANCING_INTERVAL: u32 = 100;
+
+const BASE_MINERALS: [ResourceType; 7] = [Hydrogen, Oxygen, Utrium, Lemergium, Keanium, Zynthium, Catalyst];
+
+/// Resources of an owned room with a terminal.
+#[derive(Debug, Clone)]
+pub struct RoomStock {
+    pub room_name: RoomName,
+    /// Resources in the storage and terminal.
+    pub total: FxHashMap<ResourceType, u32>,
+    /// Resources in the terminal, i.e., ones that can be sent right away.
+    pub terminal: FxHashMap<ResourceType, u32>,
+}
+
+impl RoomStock {
+    fn total_amount(&self, resource_type: ResourceType) -> u32 {
+        self.total.get(&resource_type).copied().unwrap_or(0)
+    }
+
+    fn terminal_amount(&self, resource_type: ResourceType) -> u32 {
+        self.terminal.get(&resource_type).copied().unwrap_or(0)
+    }
+}
+
+/// Transfers moving energy from rich rooms to rooms with less than `MIN_BALANCED_ROOM_ENERGY` and
+/// base minerals from rooms with much more than the average to ones with much less.
+/// Each room sends and receives at most one transfer, as terminals have a cooldown. The amounts
+/// never make a receiving room a donor or the other way around, so that the resources do not flow
+/// back and forth. Rooms are processed in a fixed order, so the result is deterministic.
+pub fn plan_balancing_transfers(rooms: &[RoomStock]) -> Vec<TerminalTransfer> {
+    let mut rooms = rooms.iter().collect::<Vec<_>>();
+    rooms.sort_by_key(|room| room.room_name);
+
+    let mut transfers = Vec::new();
+    let mut senders = FxHashSet::default();
+    let mut receivers = FxHashSet::default();
+
+    // Energy for the poorest rooms first, from the richest rooms.
+    let mut poor_rooms = rooms
+        .iter()
+        .filter(|room| room.total_amount(Energy) < MIN_BALANCED_ROOM_ENERGY)
+        .collect::<Vec<_>>();
+    poor_rooms.sort_by_key(|room| room.total_amount(Energy));
+    let mut donors = rooms
+        .iter()
+        .filter(|room| room.total_amount(Energy) > MIN_ENERGY_DONOR_ROOM_ENERGY)
+        .collect::<Vec<_>>();
+    donors.sort_by_key(|room| Reverse(room.total_amount(Energy)));
+    for poor_room in poor_rooms {
+        let deficit = MIN_BALANCED_ROOM_ENERGY - poor_room.total_amount(Energy);
+        let transfer = donors
+            .iter()
+            .filter(|donor| !senders.contains(&donor.room_name))
+            .find_map(|donor| {
+                // Half of the energy in the terminal is enough to pay for sending the other half.
+                let amount = min(
+                    min(deficit, MAX_BALANCING_TRANSFER_AMOUNT),
+                    min(donor.total_amount(Energy) - MIN_ENERGY_DONOR_ROOM_ENERGY, donor.terminal_amount(Energy) / 2)
+                );
+                (amount > 0).then_some(TerminalTransfer {
+                    from: donor.room_name,
+                    to: poor_room.room_name,
+                    resource_type: Energy,
+                    amount,
+                })
+            });
+        if let Some(transfer) = transfer {
+            senders.insert(transfer.from);
+            receivers.insert(transfer.to);
+            transfers.push(transfer);
+        }
+    }
+
+    // Base minerals from the room with the most of them to the one with the least of them.
+    for resource_type in BASE_MINERALS {
+        let average = rooms.iter().map(|room| room.total_amount(resource_type)).sum::<u32>() / rooms.len().max(1) as u32;
+        let receiver = rooms
+            .iter()
+            .filter(|room| !receivers.contains(&room.room_name))
+            .filter(|room| room.total_amount(resource_type) + MINERAL_BALANCING_THRESHOLD < average)
+            .min_by_key(|room| room.total_amount(resource_type));
+        let donor = rooms
+            .iter()
+            .filter(|room| !senders.contains(&room.room_name))
+            .filter(|room| room.total_amount(resource_type) > average + MINERAL_BALANCING_THRESHOLD)
+            .max_by_key(|room| (room.total_amount(resource_type), Reverse(room.room_name)));
+        let (Some(receiver), Some(donor)) = (receiver, donor) else {
+            continue;
+        };
+
+        // Sending a resource costs at most as much energy as its amount.
+        let amount = min(
+            min(average - receiver.total_amount(resource_type), donor.total_amount(resource_type) - average),
+            min(min(donor.terminal_amount(resource_type), donor.terminal_amount(Energy)), MAX_BALANCING_TRANSFER_AMOUNT)
+        );
+        if amount > 0 {
+            senders.insert(donor.room_name);
+            receivers.insert(receiver.room_name);
+            transfers.push(TerminalTransfer {
+                from: donor.room_name,
+                to: receiver.room_name,
+                resource_type,
+                amount,
+            });
+        }
+    }
+
+    transfers
+}
+
+/// Periodically balances energy and base minerals between the owned rooms with terminals by
+/// queuing terminal transfers. Rooms that still have a transfer queued are left out.
+pub async fn balance_resources() {
+    loop {
+        let mut rooms = Vec::new();
+        for_each_owned_room(|room_name, room_state| {
+            let Some((_, terminal_id)) = room_state.structures_with_type::<StructureTerminal>(Terminal).next() else {
+                return;
+            };
+            if has_queued_terminal_transfer(room_name) {
+                return;
+            }
+            let Some(terminal) = get_object_by_id_typed(&terminal_id) else {
+                return;
+            };
+            let terminal_stock = get_used_capacities_with_object(&terminal, terminal_id.into(), AfterAllTransfers);
+            let mut total = room_state
+                .structures_with_type::<StructureStorage>(Storage)
+                .next()
+                .and_then(|(_, storage_id)| {
+                    let storage = get_object_by_id_typed(&storage_id)?;
+                    Some(get_used_capacities_with_object(&storage, storage_id.into(), AfterAllTransfers))
+                })
+                .unwrap_or_default();
+            for (&resource_type, &amount) in terminal_stock.iter() {
+                *total.entry(resource_type).or_default() += amount;
+            }
+            rooms.push(RoomStock {
+                room_name,
+                total,
+                terminal: terminal_stock,
+            });
+        });
+
+        let transfers = plan_balancing_transfers(&rooms);
+        debug!("Balancing resources between rooms with transfers: {:?}.", transfers);
+        for transfer in transfers {
+            queue_terminal_transfer(transfer);
+        }
+
+        sleep(RESOURCE_BALANCING_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+    use screeps::ResourceType::{Energy, Hydrogen, Oxygen};
+    use screeps::{ResourceType, RoomName};
+    use crate::economy::resource_balancer::{plan_balancing_transfers, RoomStock};
+    use crate::room_maintenance::manage_terminal::TerminalTransfer;
+
+    fn room_stock(room_name: &str, total: &[(ResourceType, u32)], terminal: &[(ResourceType, u32)]) -> RoomStock {
+        RoomStock {
+            room_name: RoomName::new(room_name).unwrap(),
+            total: FxHashMap::from_iter(total.iter().copied()),
+            terminal: FxHashMap::from_iter(terminal.iter().copied()),
+        }
+    }
+
+    fn transfer(from: &str, to: &str, resource_type: ResourceType, amount: u32) -> TerminalTransfer {
+        TerminalTransfer {
+            from: RoomName::new(from).unwrap(),
+            to: RoomName::new(to).unwrap(),
+            resource_type,
+            amount,
+        }
+    }
+
+    #[test]
+    fn test_energy_to_poor_rooms() {
+        let rooms = vec![
+            room_stock("W1N1", &[(Energy, 300_000)], &[(Energy, 20_000)]),
+            room_stock("W2N1", &[(Energy, 200_000)], &[(Energy, 20_000)]),
+            // Newly claimed.
+            room_stock("W3N1", &[(Energy, 5_000)], &[]),
+            room_stock("W4N1", &[(Energy, 45_000)], &[(Energy, 5_000)]),
+            // Neither rich nor poor.
+            room_stock("W5N1", &[(Energy, 80_000)], &[(Energy, 20_000)]),
+        ];
+        assert_eq!(plan_balancing_transfers(&rooms), vec![
+            // Limited by the terminal energy.
+            transfer("W1N1", "W3N1", Energy, 10_000),
+            // Limited by the deficit.
+            transfer("W2N1", "W4N1", Energy, 5_000),
+        ]);
+    }
+
+    #[test]
+    fn test_no_energy_without_donors() {
+        let rooms = vec![
+            room_stock("W1N1", &[(Energy, 95_000)], &[(Energy, 20_000)]),
+            room_stock("W2N1", &[(Energy, 10_000)], &[]),
+        ];
+        assert_eq!(plan_balancing_transfers(&rooms), Vec::new());
+        assert_eq!(plan_balancing_transfers(&[]), Vec::new());
+    }
+
+    #[test]
+    fn test_base_minerals_equalized() {
+        let rooms = vec![
+            room_stock("W1N1", &[(Energy, 80_000), (Hydrogen, 30_000)], &[(Energy, 20_000), (Hydrogen, 8_000)]),
+            room_stock("W2N1", &[(Energy, 80_000), (Oxygen, 24_000)], &[(Energy, 20_000), (Oxygen, 24_000)]),
+            room_stock("W3N1", &[(Energy, 80_000)], &[(Energy, 20_000)]),
+        ];
+        assert_eq!(plan_balancing_transfers(&rooms), vec![
+            // Limited by the hydrogen in the terminal. Among equally poor rooms, the order of room
+            // names decides.
+            transfer("W1N1", "W3N1", Hydrogen, 8_000),
+            // A room receiving one resource may still send another one.
+            transfer("W2N1", "W1N1", Oxygen, 8_000),
+        ]);
+
+        // Small differences are not balanced, so the minerals do not go back and forth.
+        let rooms = vec![
+            room_stock("W1N1", &[(Hydrogen, 14_000)], &[(Energy, 20_000), (Hydrogen, 14_000)]),
+            room_stock("W2N1", &[(Hydrogen, 6_000)], &[(Energy, 20_000), (Hydrogen, 6_000)]),
+        ];
+        assert_eq!(plan_balancing_transfers(&rooms), Vec::new());
+    }
+}
@@ -2,16 +2,23 @@ use std::cmp::{max, min};
 use std::fmt::Display;
 use std::ops::Add;
 use log::info;
-use screeps::{controller_downgrade, BUILD_POWER, CREEP_LIFE_TIME, CREEP_RANGED_ACTION_RANGE, ENERGY_REGEN_TIME, SOURCE_ENERGY_CAPACITY, UPGRADE_CONTROLLER_POWER};
-use screeps::Part::{Carry, Move, Work};
+use screeps::{controller_downgrade, RoomName, BUILD_POWER, CONTROLLER_MAX_UPGRADE_PER_TICK, CREEP_CLAIM_LIFE_TIME, CREEP_LIFE_TIME, CREEP_RANGED_ACTION_RANGE, ENERGY_REGEN_TIME, SOURCE_ENERGY_CAPACITY, LINK_LOSS_RATIO, SOURCE_ENERGY_NEUTRAL_CAPACITY, UPGRADE_CONTROLLER_POWER};
+use screeps::Part::{Carry, Claim, Move, Work};
 use screeps::StructureType::Storage;
 use serde::{Deserialize, Serialize};
+use crate::config::{EMERGENCY_STORAGE_ENERGY_HIGH, EMERGENCY_STORAGE_ENERGY_LOW, STEADY_STATE_STORAGE_TARGET_ENERGY};
 use crate::consts::REPAIR_COST_PER_PART;
 use crate::creeps::creep_body::CreepBody;
 use crate::creeps::creep_role::CreepRole;
 use crate::creeps::creep_role::CreepRole::{Builder, Hauler, Miner, Repairer, Upgrader};
+use crate::economy::upgrade_allocation::{max_upgrade_energy_per_tick, max_upgraders, FULL_UPGRADE_ALLOCATION};
+use crate::hauling::haul_stats::HaulStats;
+use crate::hauling::requests::HaulFlow::{Consumption, Income, Refill};
 use crate::geometry::room_xy::RoomXYUtils;
-use crate::room_states::room_state::RoomState;
+use crate::priorities::BOOTSTRAP_SPAWN_PRIORITY;
+use crate::room_maintenance::links::core_link;
+use crate::room_maintenance::reserve_remotes::MAX_RESERVER_CLAIM_PARTS;
+use crate::room_states::room_state::{RoomState, SourceData};
 use crate::u;
 use crate::utils::game_tick::game_tick;
 use crate::utils::priority::Priority;
@@ -27,6 +34,13 @@ const REPAIRER_EFFICIENCY: f32 = 0.75;
 
 const MIN_HAULERS_REQUIRED: u32 = 2;
 
+/// Multiplier of the measured income of sources to budget for in case the measurements were low.
+const MEASURED_INCOME_MARGIN: f32 = 1.1;
+
+/// Haulers spawned during an emergency cost at most this much, so that they are spawned fast
+/// even with little energy.
+const EMERGENCY_HAULER_BODY_COST: u32 = 300;
+
 /// Structure containing parameters for the room economy that decide the distribution of resources
 /// as well as composition of creeps.
 #[derive(Debug, Deserialize, Serialize)]
@@ -58,12 +72,68 @@ pub struct RoomEcoConfig {
     pub repairers_required: u32,
     /// The body of a repairer.
     pub repairer_body: CreepBody,
+
+    /// Whether essential creeps may be renewed by idle spawns. Vetoed while the room is
+    /// bootstrapping and needs all its energy to spawn the missing creeps. Creeps smaller than
+    /// the current body of their role are never renewed, so that they are replaced by bigger ones.
+    pub renewal_allowed: bool,
+
+    /// Whether the room is still growing or has reached the steady state at RCL8.
+    pub mode: EcoMode,
+}
+
+/// The phase of the room economy deciding what the surplus energy is used for.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub enum EcoMode {
+    /// The surplus energy is spent on building and upgrading the controller.
+    #[default]
+    Growth,
+    /// The room is at RCL8 with nothing to build. The controller accepts only
+    /// `CONTROLLER_MAX_UPGRADE_PER_TICK` energy per tick, so the surplus energy is kept in
+    /// the storage.
+    SteadyState,
+    /// The storage is almost empty or the controller is about to downgrade. Only the creeps needed
+    /// to mine and haul energy and keep the controller are spawned until the storage is refilled.
+    Emergency,
+}
+
+/// A remote mined by the room with the data relevant to its economy.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteEcoData {
+    pub room_name: RoomName,
+    pub number_of_sources: u32,
+    /// Sum of round-trip distances between the storage and the containers, as in `RemotePlan`.
+    pub hauling_distance: u32,
+    /// Whether the controller is reserved by the bot, doubling the capacity of the sources.
+    pub reserved: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+enum UsageCategory {
+    #[default]
+    Mining,
+    RemoteMining,
+    Building,
+    Upgrading,
+    Repairing,
+}
+
+impl Display for UsageCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsageCategory::Mining => write!(f, "mining"),
+            UsageCategory::RemoteMining => write!(f, "remote mining"),
+            UsageCategory::Building => write!(f, "building"),
+            UsageCategory::Upgrading => write!(f, "upgrading"),
+            UsageCategory::Repairing => write!(f, "repairing"),
+        }
+    }
 }
 
 // TODO Stats on spawn usage or total parts.
 #[derive(Debug, Default, Clone, Copy)]
 struct ResourceUsage {
-    category: CreepRole,
+    category: UsageCategory,
     creeps: f32,
     work_energy: f32,
     body_cost: f32,
@@ -98,7 +168,9 @@ impl Display for ResourceUsage {
     }
 }
 
-pub fn update_or_create_eco_config(room_state: &mut RoomState) {
+/// Updates the numbers and bodies of creeps required by the room, including the ones mining given
+/// remotes of the room.
+pub fn update_or_create_eco_config(room_state: &mut RoomState, remotes: &[RemoteEcoData]) {
     // ----- Computing the stats required to make any decision. -----
 
     let room_name = room_state.room_name;
@@ -108,6 +180,8 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
 
     let spawn_energy = room_state.resources.spawn_energy;
     let spawn_energy_capacity = room_state.resources.spawn_energy_capacity;
+    // `None` before the storage is built.
+    let storage_energy = room_state.structure_pos(Storage).map(|_| room_state.resources.storage_energy);
     let haulable_energy = eco_stats.haul_stats.withdrawable_storage_amount.last() + eco_stats.haul_stats.unfulfilled_withdraw_amount.last();
 
     let storage_pos = {
@@ -121,6 +195,13 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
 
     let number_of_sources = room_state.sources.len() as u32;
     let single_source_energy_income = SOURCE_ENERGY_CAPACITY / ENERGY_REGEN_TIME;
+    // The income actually extracted from the sources, once measured, to not overestimate it due to
+    // the downtime of miners.
+    let local_energy_income = room_state
+        .sources
+        .iter()
+        .map(|source_data| budgeted_income(single_source_energy_income as f32, eco_stats.measured_income(source_data.id)))
+        .sum::<f32>();
 
     // Controller data.
     let ticks_to_downgrade = u!(room_state.controller).downgrade_tick - game_tick();
@@ -147,37 +228,48 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
 
     let miner_stats = eco_stats.creep_stats(Miner);
     let mut mining_usage = ResourceUsage {
-        category: Miner,
+        category: UsageCategory::Mining,
         creeps: miner_stats.number_of_active_creeps.last() as f32 - miner_stats.number_of_idle_creeps.last() as f32,
         body_cost: miner_stats.total_body_cost.last() as f32,
         ..ResourceUsage::default()
     };
-    // info!("Sources - position, haul distance, income, body usage, hauling throughput required:");
+    // Energy mined into source links is sent to the core link next to the storage, so it does not
+    // need to be hauled, but a part of it is lost.
+    let core_link_built = core_link(room_state).is_some();
+    let link_mined = |source_data: &SourceData| core_link_built && source_data.link_id.is_some();
+    info!("Sources - position, mining mode, haul distance, income, hauling throughput required:");
     for source_data in room_state.sources.iter() {
         if let Some(total_harvest_power) = eco_stats.total_harvest_power_by_source.get(&source_data.id) {
             // TODO It might be better to expect full 10E/t from a source.
-            let income = total_harvest_power.last() as f32;
-            mining_usage.work_energy -= total_harvest_power.last() as f32;
+            let income = budgeted_income(total_harvest_power.last() as f32, eco_stats.measured_income(source_data.id));
+            let efficiency = eco_stats.income_efficiency(source_data.id, single_source_energy_income as f32);
             let haul_dist = u!(source_data.work_xy).to_pos(room_name).get_range_to(storage_pos).saturating_sub(1);
-            mining_usage.hauling_throughput += 2.0 * haul_dist as f32 * income;
-            // let max_hauling_throughput_required = 2 * haul_dist * single_source_energy_income;
-
-            // info!(
-            //     "* {} - {}t, {}/{}E/t, {:.2}E/t, {}/{}R",
-            //     source_data.xy,
-            //     haul_dist,
-            //     income,
-            //     single_source_energy_income,
-            //     body_usage,
-            //     hauling_throughput_required,
-            //     max_hauling_throughput_required
-            // );
+            let link_mining = link_mined(source_data);
+            let source_usage = source_mining_usage(income, haul_dist, link_mining);
+            mining_usage.work_energy += source_usage.work_energy;
+            mining_usage.hauling_throughput += source_usage.hauling_throughput;
+
+            info!(
+                "* {} - {}, {}t, {:.2}/{}E/t, {} efficiency, {:.2}R",
+                source_data.xy,
+                if link_mining { "link" } else { "container" },
+                haul_dist,
+                income,
+                single_source_energy_income,
+                efficiency.map_or("unknown".to_string(), |efficiency| format!("{:.0}%", efficiency * 100.0)),
+                source_usage.hauling_throughput
+            );
         }
     }
 
+    // Remote miners stand on containers and are not measured separately yet, so the income and
+    // hauling are computed from the plans of the remotes.
+    let remote_miner_body = preferred_container_miner_body(spawn_energy_capacity);
+    let remote_mining_usage = remote_mining_usage(remotes, &remote_miner_body);
+
     let builder_stats = eco_stats.creep_stats(Builder);
     let mut building_usage = ResourceUsage {
-        category: Builder,
+        category: UsageCategory::Building,
         creeps: builder_stats.number_of_active_creeps.last() as f32 - builder_stats.number_of_idle_creeps.last() as f32,
         body_cost: builder_stats.total_body_cost.last() as f32,
         ..ResourceUsage::default()
@@ -199,7 +291,7 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
 
     let upgrader_stats = eco_stats.creep_stats(Upgrader);
     let mut upgrading_usage = ResourceUsage {
-        category: Upgrader,
+        category: UsageCategory::Upgrading,
         creeps: upgrader_stats.number_of_active_creeps.last() as f32 - upgrader_stats.number_of_idle_creeps.last() as f32,
         body_cost: upgrader_stats.total_body_cost.last() as f32,
         ..ResourceUsage::default()
@@ -221,7 +313,7 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
 
     let repairer_stats = eco_stats.creep_stats(Repairer);
     let mut repairing_usage = ResourceUsage {
-        category: Repairer,
+        category: UsageCategory::Repairing,
         creeps: repairer_stats.number_of_active_creeps.last() as f32 - repairer_stats.number_of_idle_creeps.last() as f32,
         body_cost: repairer_stats.total_body_cost.last() as f32,
         ..ResourceUsage::default()
@@ -243,13 +335,43 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
         // );
     }
 
-    let total_usage = mining_usage + building_usage + upgrading_usage + repairing_usage;
+    let total_usage = mining_usage + remote_mining_usage + building_usage + upgrading_usage + repairing_usage;
 
     info!("Room {} usage stats:", room_name);
-    for usage in [mining_usage, building_usage, upgrading_usage, repairing_usage] {
+    for usage in [mining_usage, remote_mining_usage, building_usage, upgrading_usage, repairing_usage] {
         info!("* {}", usage);
     }
     info!("Total: {}", total_usage);
+    info!("Spawning for other rooms: {}E/t", eco_stats.assisted_spawn_energy.last());
+
+    // Only the income and consumption require logistics. Refilling is tracked separately since it
+    // is to be handled by dedicated fillers.
+    let measured_hauled_amount = eco_stats.haul_stats.hauled_amount(Income) + eco_stats.haul_stats.hauled_amount(Consumption);
+    let calculated_hauled_amount = [mining_usage, remote_mining_usage, building_usage, upgrading_usage, repairing_usage]
+        .iter()
+        .map(|usage| usage.work_energy.abs())
+        .sum::<f32>();
+    let measured_hauling_throughput = if calculated_hauled_amount > 0.0 {
+        // Assuming the same average haul distance as in the calculations.
+        total_usage.hauling_throughput / calculated_hauled_amount * measured_hauled_amount
+    } else {
+        0.0
+    };
+    info!(
+        "Hauled: {:.2}E/t income, {:.2}E/t consumption, {:.2}E/t refill, {:.2}R measured throughput",
+        eco_stats.haul_stats.hauled_amount(Income),
+        eco_stats.haul_stats.hauled_amount(Consumption),
+        eco_stats.haul_stats.hauled_amount(Refill),
+        measured_hauling_throughput
+    );
+    info!(
+        "Haulers: {:.2}R realized throughput per hauler, {:.0}% idle, {}E delivered, {}/{} tiles loaded/empty in the last sample",
+        eco_stats.haul_stats.realized_hauler_throughput().unwrap_or(0.0),
+        eco_stats.haul_stats.idle_ratio() * 100.0,
+        eco_stats.haul_stats.delivered_amount.last(),
+        eco_stats.haul_stats.loaded_distance.last(),
+        eco_stats.haul_stats.empty_distance.last()
+    );
 
     // TODO Compute cost of respawned creeps.
     // TODO Initially use all existing creeps. Work on increasing number to max(calculated, current).
@@ -288,13 +410,22 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
 
     // ----- Modification of the eco config. -----
 
-    // TODO Handle link mining.
     // Computing minimal and preferred miner and hauler bodies.
     let min_miner_body = preferred_miner_body(0, true);
     let min_hauler_body = preferred_hauler_body(0);
 
     let hauler_body = preferred_hauler_body(spawn_energy_capacity);
-    let miner_body = preferred_miner_body(spawn_energy_capacity, true);
+    // Miners are shared by all sources, so link miners are only used once all sources have links.
+    // Miners over source containers need to carry energy to repair them.
+    let link_mining = !room_state.sources.is_empty() && room_state.sources.iter().all(link_mined);
+    let container_mining = room_state.sources.iter().any(|source_data| source_data.container_id.is_some());
+    let miner_body = if link_mining {
+        preferred_miner_body(spawn_energy_capacity, false)
+    } else if container_mining {
+        preferred_container_miner_body(spawn_energy_capacity)
+    } else {
+        preferred_miner_body(spawn_energy_capacity, true)
+    };
 
     if room_state.eco_config.is_none() {
         // TODO Handle memory wipe from an already built up state better.
@@ -311,10 +442,26 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
             builder_body: preferred_builder_body(spawn_energy),
             repairers_required: 0,
             repairer_body: preferred_repairer_body(spawn_energy),
+            renewal_allowed: false,
+            mode: EcoMode::Growth,
         });
     }
 
     let eco_config = u!(room_state.eco_config.as_mut());
+    let previous_mode = eco_config.mode;
+    eco_config.mode = next_eco_mode(
+        previous_mode,
+        eco_mode(room_state.rcl, room_state.construction_site_queue.is_empty()),
+        storage_energy,
+        ticks_to_downgrade,
+        max_ticks_to_downgrade
+    );
+    if eco_config.mode != previous_mode {
+        info!("Room {} switched from {:?} to {:?} economy.", room_name, previous_mode, eco_config.mode);
+    }
+    if eco_config.mode == EcoMode::Emergency {
+        eco_config.hauler_body = preferred_hauler_body(min(spawn_energy_capacity, EMERGENCY_HAULER_BODY_COST));
+    }
     eco_config.hauler_body = hauler_body;
     eco_config.miner_body = miner_body;
 
@@ -378,18 +525,24 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
         eco_config.miner_spawn_priority = Priority(200);
 
         // Setting the number of miners to optimal.
-        eco_config.miners_required = single_source_energy_income.div_ceil(eco_config.miner_body.energy_harvest_power()) * number_of_sources;
+        eco_config.miners_required = single_source_energy_income.div_ceil(eco_config.miner_body.energy_harvest_power()) * number_of_sources
+            + remote_miners_required(remotes, &eco_config.miner_body);
 
         // There should always be at least two haulers.
         eco_config.haulers_required = max(MIN_HAULERS_REQUIRED, eco_config.haulers_required);
     }
+    eco_config.renewal_allowed = !bootstrapping;
 
     // Energy to spare is decided by the amount in storage as well as the average unfulfilled
     // withdraw requests.
     let unfulfilled_haul_amount_balance = eco_stats.haul_stats.unfulfilled_withdraw_amount.small_sample_avg::<i32>()
         - eco_stats.haul_stats.unfulfilled_deposit_amount.small_sample_avg::<i32>();
+    // In the steady state, the surplus energy is first used to fill the storage up to its target
+    // level.
+    // TODO Send the surplus above the target to the terminal and the market.
     // TODO Check just energy, not everything.
-    let has_energy_to_spare = unfulfilled_haul_amount_balance > MIN_AVG_ENERGY_TO_SPARE as i32;
+    let has_energy_to_spare = unfulfilled_haul_amount_balance > MIN_AVG_ENERGY_TO_SPARE as i32
+        && (eco_config.mode == EcoMode::Growth || room_state.resources.storage_energy >= STEADY_STATE_STORAGE_TARGET_ENERGY);
 
     // TODO Once everything is built, it should be kept close to fully upgraded.
     //      On RCL 5-7, it should be kept rather high, but building should also take place.
@@ -432,13 +585,12 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
          */
 
         // The calculations are used to crank up the number of haulers fast even with limited data.
-        let single_hauler_throughput = eco_config.hauler_body.store_capacity();
-        let haulers_required_for_calculated_throughput = (total_usage.hauling_throughput as u32).div_ceil(single_hauler_throughput);
         let used_haulers = hauler_stats.number_of_active_creeps.small_sample_avg::<f32>() - hauler_stats.number_of_idle_creeps.small_sample_avg::<f32>();
-        let spare_haulers = 0.5;
-        eco_config.haulers_required = max(
-            haulers_required_for_calculated_throughput,
-            (used_haulers + spare_haulers).ceil() as u32
+        eco_config.haulers_required = haulers_required(
+            required_hauling_throughput(eco_config.mode, total_usage.hauling_throughput, measured_hauling_throughput),
+            &eco_stats.haul_stats,
+            &eco_config.hauler_body,
+            used_haulers
         );
 
         // If there are construction sites, spawn builders.
@@ -470,7 +622,15 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
         // builders. However, if the controller is close to downgrading, prioritize the upgrader.
         // TODO Spawning a single upgrader should have higher priority when the controller is
         //      critical.
-        if !room_state.construction_site_queue.is_empty() && !controller_downgrade_level_critical {
+        if eco_config.mode == EcoMode::SteadyState {
+            let (upgraders_required, upgrader_body) = steady_state_upgraders(
+                spawn_energy,
+                spawn_energy_capacity,
+                controller_downgrade_level_critical
+            );
+            eco_config.upgraders_required = upgraders_required;
+            eco_config.upgrader_body = upgrader_body;
+        } else if !room_state.construction_site_queue.is_empty() && !controller_downgrade_level_critical {
             eco_config.upgraders_required = 0;
         } else {
             let upgrader_stats = eco_stats.creep_stats(Upgrader);
@@ -489,12 +649,43 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
             if eco_config.upgraders_required > 0 {
                 eco_config.upgrader_body = preferred_upgrader_body(spawn_energy);
             }
+
+            // Limiting upgrading to the share of the energy income allocated to the room unless
+            // the controller is close to downgrading.
+            let upgrade_allocation = room_state.upgrade_allocation;
+            if upgrade_allocation < FULL_UPGRADE_ALLOCATION && !controller_downgrade_level_critical {
+                // Creeps spawned for other rooms are paid from the same income.
+                let energy_income = ((local_energy_income + remote_energy_income(remotes)) as u32)
+                    .saturating_sub(eco_stats.assisted_spawn_energy.small_sample_avg::<u32>());
+                let allocated_energy = energy_income * upgrade_allocation as u32;
+                let upgrader_energy_usage = max(1, eco_config.upgrader_body.upgrade_energy_usage()) * 100;
+                let max_upgraders = allocated_energy.div_ceil(upgrader_energy_usage);
+                eco_config.upgraders_required = min(eco_config.upgraders_required, max_upgraders);
+            }
+
+            // There is no point in spawning more upgraders than needed to reach the limit of
+            // energy spent on upgrading per tick.
+            if let Some(max_upgrade_energy) = max_upgrade_energy_per_tick(room_state.rcl) {
+                let max_upgraders = max_upgraders(max_upgrade_energy, eco_config.upgrader_body.upgrade_energy_usage());
+                eco_config.upgraders_required = min(eco_config.upgraders_required, max_upgraders);
+            }
         }
         
         // TODO Include in energy calculations. Prioritize over building. Prioritize over upgrading if critical unless controller also critical.
         let single_repairer_total_repairer_hits = ((eco_config.repairer_body.repair_power() * CREEP_LIFE_TIME) as f32 * REPAIRER_EFFICIENCY) as u32;
-        let repairer_required = !room_state.triaged_repair_sites.critical.is_empty() || room_state.triaged_repair_sites.total_hits_to_repair >= single_repairer_total_repairer_hits;
-        eco_config.repairers_required = repairer_required as u32;
+        let triaged_repair_sites = &room_state.triaged_repair_sites;
+        let repairer_required = !triaged_repair_sites.critical.is_empty() || triaged_repair_sites.total_hits_to_repair >= single_repairer_total_repairer_hits;
+        // Ramparts are grown by an additional repairer only when there is energy to spare.
+        let rampart_grower_required = has_energy_to_spare && triaged_repair_sites.total_rampart_growth_hits >= single_repairer_total_repairer_hits;
+        eco_config.repairers_required = repairer_required as u32 + rampart_grower_required as u32;
+    }
+
+    if eco_config.mode == EcoMode::Emergency && !bootstrapping {
+        // Shedding everything not needed to restore the energy income and keep the controller.
+        eco_config.builders_required = 0;
+        eco_config.upgraders_required = min(eco_config.upgraders_required, 1);
+        eco_config.upgrader_body = preferred_upgrader_body(0);
+        eco_config.miner_spawn_priority = Priority(250);
     }
 
     if DEBUG {
@@ -537,7 +728,7 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
             );
         }
 
-        let energy_income = number_of_sources as f32 * single_source_energy_income as f32;
+        let energy_income = local_energy_income + remote_energy_income(remotes);
 
         let hauling_body_energy_usage = eco_config.haulers_required as f32 * eco_config.hauler_body.body_energy_usage();
         let mining_body_energy_usage = eco_config.miners_required as f32 * eco_config.miner_body.body_energy_usage();
@@ -566,6 +757,8 @@ pub fn update_or_create_eco_config(room_state: &mut RoomState) {
         info!("Construction sites: {} (total {}E needed)", room_state.construction_site_queue.len(), total_construction_site_energy_needed);
         info!("Energy usage: {:.2}E/t + {:.2}E/t = {:.2}E/t", body_energy_usage, work_energy_usage, energy_usage);
         info!("Energy balance: {:.2}E/t", energy_income - energy_usage);
+        let work_energy = building_usage.work_energy + upgrading_usage.work_energy + repairing_usage.work_energy;
+        info!("Energy ledger per 1000 ticks: {}", eco_stats.energy_ledger(work_energy));
     }
 }
 
@@ -575,6 +768,22 @@ impl RoomEcoConfig {
         self.builders_required = 0;
     }
 
+    /// Whether the replacements of creeps of given role are spawned in advance, so that they arrive
+    /// at the workplace right before the current creeps die.
+    pub fn is_prespawned(&self, role: CreepRole) -> bool {
+        matches!(role, Miner | Hauler | Upgrader)
+    }
+
+    /// The current body of given role if creeps of that role are essential for the room, i.e.,
+    /// spawned with at least the bootstrap priority.
+    pub fn essential_role_body(&self, role: CreepRole) -> Option<&CreepBody> {
+        match role {
+            Miner if self.miner_spawn_priority >= BOOTSTRAP_SPAWN_PRIORITY => Some(&self.miner_body),
+            Hauler if self.hauler_spawn_priority >= BOOTSTRAP_SPAWN_PRIORITY => Some(&self.hauler_body),
+            _ => None,
+        }
+    }
+
     /*
     pub fn new(room_state: &RoomState) -> Self {
         let eco_stats = u!(room_state.eco_stats.as_ref());
@@ -837,6 +1046,173 @@ impl RoomEcoConfig {
      */
 }
 
+/// The room is in the steady state once it reached RCL8 and there is nothing left to build.
+pub fn eco_mode(rcl: u8, construction_site_queue_empty: bool) -> EcoMode {
+    if rcl == 8 && construction_site_queue_empty {
+        EcoMode::SteadyState
+    } else {
+        EcoMode::Growth
+    }
+}
+
+/// The economy mode after the update, given the previous one and the one without an emergency.
+/// An emergency starts when the storage energy drops below `EMERGENCY_STORAGE_ENERGY_LOW` or
+/// the controller is about to downgrade and ends only once the storage energy is back above
+/// `EMERGENCY_STORAGE_ENERGY_HIGH` and the controller is safe, so that it does not switch back
+/// and forth. Rooms without a storage do not enter an emergency due to storage energy.
+pub fn next_eco_mode(
+    previous_mode: EcoMode,
+    regular_mode: EcoMode,
+    storage_energy: Option<u32>,
+    ticks_to_downgrade: u32,
+    max_ticks_to_downgrade: u32
+) -> EcoMode {
+    let downgrade_risk = ticks_to_downgrade < max_ticks_to_downgrade / 8;
+    let storage_threshold = if previous_mode == EcoMode::Emergency {
+        EMERGENCY_STORAGE_ENERGY_HIGH
+    } else {
+        EMERGENCY_STORAGE_ENERGY_LOW
+    };
+    let storage_depleted = storage_energy.is_some_and(|storage_energy| storage_energy < storage_threshold);
+    if downgrade_risk || storage_depleted {
+        EcoMode::Emergency
+    } else {
+        regular_mode
+    }
+}
+
+/// Hauling throughput the haulers are spawned for. While growing, the calculated throughput is
+/// used to spawn haulers before the measurements catch up. In the steady state, the measured one
+/// is reliable and the calculated one overestimates.
+fn required_hauling_throughput(mode: EcoMode, calculated_hauling_throughput: f32, measured_hauling_throughput: f32) -> f32 {
+    match mode {
+        EcoMode::Growth => calculated_hauling_throughput.max(measured_hauling_throughput),
+        EcoMode::SteadyState | EcoMode::Emergency => measured_hauling_throughput,
+    }
+}
+
+/// The number and body of upgraders in the steady state, just enough to reach
+/// `CONTROLLER_MAX_UPGRADE_PER_TICK`, preferably with a single upgrader. If the controller is close
+/// to downgrading, upgraders are spawned with whatever energy is available.
+pub fn steady_state_upgraders(
+    spawn_energy: u32,
+    spawn_energy_capacity: u32,
+    controller_downgrade_critical: bool
+) -> (u32, CreepBody) {
+    let upgrader_body = if controller_downgrade_critical {
+        steady_state_upgrader_body(spawn_energy)
+    } else {
+        steady_state_upgrader_body(spawn_energy_capacity)
+    };
+    let upgraders_required = max_upgraders(CONTROLLER_MAX_UPGRADE_PER_TICK, upgrader_body.upgrade_energy_usage());
+    (upgraders_required, upgrader_body)
+}
+
+/// An upgrader body using `CONTROLLER_MAX_UPGRADE_PER_TICK` energy per tick, moving at full speed
+/// on roads, if affordable.
+pub fn steady_state_upgrader_body(spawn_energy: u32) -> CreepBody {
+    if spawn_energy >= 2100 {
+        vec![(Move, 9), (Work, 15), (Carry, 3)].into()
+    } else {
+        preferred_upgrader_body(spawn_energy)
+    }
+}
+
+/// The income of a source to budget haulers and upgraders for. Once the income is measured, it is
+/// used with a margin if it is lower than the theoretical one.
+pub fn budgeted_income(theoretical_income: f32, measured_income: Option<f32>) -> f32 {
+    measured_income.map_or(theoretical_income, |measured_income| {
+        theoretical_income.min(measured_income * MEASURED_INCOME_MARGIN)
+    })
+}
+
+/// Income and hauling throughput of mining a source with given income at given distance from
+/// the storage. Link mined energy is not hauled, but the links lose a part of it.
+fn source_mining_usage(income: f32, haul_dist: u32, link_mining: bool) -> ResourceUsage {
+    let mut usage = ResourceUsage {
+        category: UsageCategory::Mining,
+        work_energy: -income,
+        ..ResourceUsage::default()
+    };
+    if link_mining {
+        usage.work_energy += income * LINK_LOSS_RATIO;
+    } else {
+        usage.hauling_throughput = 2.0 * haul_dist as f32 * income;
+    }
+    usage
+}
+
+/// Energy per tick regenerated in a single source of the remote.
+fn remote_source_energy_income(remote: &RemoteEcoData) -> f32 {
+    let capacity = if remote.reserved {
+        SOURCE_ENERGY_CAPACITY
+    } else {
+        SOURCE_ENERGY_NEUTRAL_CAPACITY
+    };
+    capacity as f32 / ENERGY_REGEN_TIME as f32
+}
+
+/// Total energy per tick regenerated in the sources of the remotes.
+fn remote_energy_income(remotes: &[RemoteEcoData]) -> f32 {
+    remotes
+        .iter()
+        .map(|remote| remote.number_of_sources as f32 * remote_source_energy_income(remote))
+        .sum()
+}
+
+/// Income, hauling throughput and body cost of mining the remotes with miners of given body and,
+/// in reserved ones, reservers. Reservers live shorter, so their body cost is scaled to the lifetime
+/// of other creeps.
+fn remote_mining_usage(remotes: &[RemoteEcoData], miner_body: &CreepBody) -> ResourceUsage {
+    let reserver_body_cost = MAX_RESERVER_CLAIM_PARTS * (Claim.cost() + Move.cost());
+    let mut usage = ResourceUsage {
+        category: UsageCategory::RemoteMining,
+        ..ResourceUsage::default()
+    };
+    for remote in remotes.iter() {
+        let source_income = remote_source_energy_income(remote);
+        let miners = remote.number_of_sources * remote_source_miners_required(remote, miner_body);
+        usage.creeps += (miners + remote.reserved as u32) as f32;
+        usage.work_energy -= remote.number_of_sources as f32 * source_income;
+        // The round-trip distance is already summed over the sources.
+        usage.hauling_throughput += remote.hauling_distance as f32 * source_income;
+        usage.body_cost += (miners * miner_body.energy_cost()) as f32;
+        if remote.reserved {
+            usage.body_cost += (reserver_body_cost * CREEP_LIFE_TIME / CREEP_CLAIM_LIFE_TIME) as f32;
+        }
+    }
+    usage
+}
+
+fn remote_source_miners_required(remote: &RemoteEcoData, miner_body: &CreepBody) -> u32 {
+    (remote_source_energy_income(remote) as u32).div_ceil(max(1, miner_body.energy_harvest_power()))
+}
+
+/// Number of miners of given body required to fully mine the sources of the remotes.
+fn remote_miners_required(remotes: &[RemoteEcoData], miner_body: &CreepBody) -> u32 {
+    remotes
+        .iter()
+        .map(|remote| remote.number_of_sources * remote_source_miners_required(remote, miner_body))
+        .sum()
+}
+
+/// Number of haulers required for given hauling throughput, but at least as many as are being used
+/// plus a spare one. The throughput of a single hauler is the one realized by the haulers in
+/// the room once there are enough samples of it, and the theoretical one of given body otherwise.
+pub fn haulers_required(hauling_throughput: f32, haul_stats: &HaulStats, hauler_body: &CreepBody, used_haulers: f32) -> u32 {
+    let theoretical_hauler_throughput = hauler_body.store_capacity() as f32;
+    let single_hauler_throughput = haul_stats
+        .realized_hauler_throughput()
+        .map_or(theoretical_hauler_throughput, |throughput| throughput.min(theoretical_hauler_throughput))
+        .max(1.0);
+    let haulers_required_for_throughput = (hauling_throughput / single_hauler_throughput).ceil() as u32;
+    let spare_haulers = 0.5;
+    max(
+        haulers_required_for_throughput,
+        (used_haulers + spare_haulers).ceil() as u32
+    )
+}
+
 pub fn preferred_hauler_body(spawn_energy: u32) -> CreepBody {
     if spawn_energy >= 550 {
         vec![(Move, 5), (Carry, 5)].into()
@@ -869,8 +1245,25 @@ pub fn preferred_drop_miner_body(spawn_energy: u32) -> CreepBody {
     }
 }
 
+/// A drop miner body with a single `Carry` part to repair the container under itself, if affordable.
+pub fn preferred_container_miner_body(spawn_energy: u32) -> CreepBody {
+    if spawn_energy >= 600 {
+        vec![(Move, 1), (Work, 5), (Carry, 1)].into()
+    } else if spawn_energy >= 450 {
+        vec![(Move, 2), (Work, 3), (Carry, 1)].into()
+    } else {
+        preferred_drop_miner_body(spawn_energy)
+    }
+}
+
+/// A miner body with enough `Work` parts to fully mine a source and a single `Carry` part to
+/// store the energy in the link.
 pub fn preferred_link_miner_body(spawn_energy: u32) -> CreepBody {
-    if spawn_energy >= 300 {
+    if spawn_energy >= 800 {
+        vec![(Move, 3), (Work, 6), (Carry, 1)].into()
+    } else if spawn_energy >= 650 {
+        vec![(Move, 2), (Work, 5), (Carry, 1)].into()
+    } else if spawn_energy >= 300 {
         vec![(Move, 1), (Work, 2), (Carry, 1)].into()
     } else {
         // Smallest possible link miner.
@@ -904,4 +1297,215 @@ pub fn preferred_repairer_body(spawn_energy: u32) -> CreepBody {
     } else {
         vec![(Move, 1), (Work, 1), (Carry, 1)].into()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Part::{Carry, Move, Work};
+    use screeps::RoomName;
+    use crate::creeps::creep_body::CreepBody;
+    use crate::config::{EMERGENCY_STORAGE_ENERGY_HIGH, EMERGENCY_STORAGE_ENERGY_LOW};
+    use crate::economy::room_eco_config::{budgeted_income, eco_mode, haulers_required, next_eco_mode, preferred_miner_body, remote_miners_required, remote_mining_usage, required_hauling_throughput, source_mining_usage, steady_state_upgraders, EcoMode, RemoteEcoData, UsageCategory};
+    use crate::hauling::haul_stats::{HaulStats, HaulerActivity};
+    use crate::utils::sampling::SMALL_SAMPLE_SIZE;
+
+    #[test]
+    fn test_haulers_required_respond_to_throughput_drop() {
+        let hauler_body: CreepBody = vec![(Move, 5), (Carry, 5)].into();
+        let mut haul_stats = HaulStats::default();
+
+        // Without samples, the theoretical throughput of 250 per hauler is used.
+        assert_eq!(haulers_required(1000.0, &haul_stats, &hauler_body, 0.0), 4);
+
+        // Two haulers carrying on average half of their capacity all the time, i.e., using
+        // the full throughput since they also need to come back empty.
+        let efficient_activity = HaulerActivity {
+            delivered_amount: 250,
+            loaded_distance: 8,
+            empty_distance: 0,
+            carried_amount_distance: 8 * 125,
+            idle_ticks: 0,
+            hauler_ticks: 8,
+        };
+        for _ in 0..SMALL_SAMPLE_SIZE {
+            haul_stats.push_hauler_activity(efficient_activity);
+        }
+        assert_eq!(haul_stats.realized_hauler_throughput(), Some(250.0));
+        assert_eq!(haulers_required(1000.0, &haul_stats, &hauler_body, 0.0), 4);
+
+        // The haulers are now stuck half of the time, e.g., in traffic. Idle ticks do not count.
+        let congested_activity = HaulerActivity {
+            delivered_amount: 125,
+            loaded_distance: 4,
+            empty_distance: 0,
+            carried_amount_distance: 4 * 125,
+            idle_ticks: 4,
+            hauler_ticks: 12,
+        };
+        for _ in 0..SMALL_SAMPLE_SIZE {
+            haul_stats.push_hauler_activity(congested_activity);
+        }
+        assert_eq!(haul_stats.realized_hauler_throughput(), Some(125.0));
+        assert_eq!(haul_stats.idle_ratio(), 1.0 / 3.0);
+        assert_eq!(haulers_required(1000.0, &haul_stats, &hauler_body, 0.0), 8);
+
+        // There are always enough haulers for the ones being used.
+        assert_eq!(haulers_required(0.0, &haul_stats, &hauler_body, 2.0), 3);
+    }
+
+    fn remote(name: &str, number_of_sources: u32, hauling_distance: u32, reserved: bool) -> RemoteEcoData {
+        RemoteEcoData {
+            room_name: RoomName::new(name).unwrap(),
+            number_of_sources,
+            hauling_distance,
+            reserved,
+        }
+    }
+
+    #[test]
+    fn test_haulers_required_with_remotes() {
+        let hauler_body: CreepBody = vec![(Move, 5), (Carry, 5)].into();
+        let miner_body: CreepBody = vec![(Move, 1), (Work, 5)].into();
+        let haul_stats = HaulStats::default();
+        // Two local sources 20 tiles away from the storage.
+        let local_hauling_throughput = 2.0 * 2.0 * 20.0 * 10.0;
+
+        let haulers = |remotes: &[RemoteEcoData]| {
+            let usage = remote_mining_usage(remotes, &miner_body);
+            haulers_required(local_hauling_throughput + usage.hauling_throughput, &haul_stats, &hauler_body, 0.0)
+        };
+
+        let no_remotes = [];
+        let remote_usage = remote_mining_usage(&no_remotes, &miner_body);
+        assert_eq!(remote_usage.category, UsageCategory::RemoteMining);
+        assert_eq!(remote_usage.hauling_throughput, 0.0);
+        assert_eq!(haulers(&no_remotes), 4);
+        assert_eq!(remote_miners_required(&no_remotes, &miner_body), 0);
+
+        let near_remote = [remote("W2N1", 1, 100, true)];
+        let remote_usage = remote_mining_usage(&near_remote, &miner_body);
+        assert_eq!(remote_usage.work_energy, -10.0);
+        assert_eq!(remote_usage.creeps, 2.0);
+        assert_eq!(haulers(&near_remote), 8);
+        assert_eq!(remote_miners_required(&near_remote, &miner_body), 1);
+
+        // The same remote farther away needs more haulers.
+        let far_remote = [remote("W2N1", 1, 200, true)];
+        assert_eq!(haulers(&far_remote), 12);
+
+        // An unreserved remote yields half of the energy, so it needs half of the hauling.
+        let three_remotes = [
+            remote("W2N1", 2, 200, true),
+            remote("W1N2", 1, 150, false),
+            remote("W0N1", 1, 100, true),
+        ];
+        let remote_usage = remote_mining_usage(&three_remotes, &miner_body);
+        assert_eq!(remote_usage.work_energy, -35.0);
+        assert_eq!(remote_usage.hauling_throughput, 3750.0);
+        assert_eq!(haulers(&three_remotes), 19);
+        assert_eq!(remote_miners_required(&three_remotes, &miner_body), 4);
+    }
+
+    #[test]
+    fn test_haulers_required_drop_with_link_mining() {
+        let hauler_body: CreepBody = vec![(Move, 5), (Carry, 5)].into();
+        let haul_stats = HaulStats::default();
+        let haulers = |link_mining: [bool; 2]| {
+            let hauling_throughput = [(10.0, 25), (10.0, 15)]
+                .into_iter()
+                .zip(link_mining)
+                .map(|((income, haul_dist), link_mining)| source_mining_usage(income, haul_dist, link_mining).hauling_throughput)
+                .sum::<f32>();
+            haulers_required(hauling_throughput, &haul_stats, &hauler_body, 0.0)
+        };
+
+        assert_eq!(haulers([false, false]), 4);
+        assert_eq!(haulers([true, false]), 2);
+        assert_eq!(haulers([true, true]), 1);
+
+        // Link mined energy is not hauled, but some of it is lost.
+        let link_usage = source_mining_usage(10.0, 25, true);
+        assert_eq!(link_usage.hauling_throughput, 0.0);
+        assert!(link_usage.work_energy > -10.0 && link_usage.work_energy < 0.0);
+
+        // Link miners need a single carry part to fill the link and enough work parts to mine out
+        // the source.
+        let link_miner_body = preferred_miner_body(800, false);
+        assert_eq!(link_miner_body.count_parts(Carry), 1);
+        assert!(link_miner_body.energy_harvest_power() >= 10);
+    }
+
+    #[test]
+    fn test_steady_state_mode_switch() {
+        assert_eq!(eco_mode(7, true), EcoMode::Growth);
+        assert_eq!(eco_mode(8, false), EcoMode::Growth);
+        assert_eq!(eco_mode(8, true), EcoMode::SteadyState);
+
+        // Haulers shrink to the measured throughput only in the steady state.
+        assert_eq!(required_hauling_throughput(EcoMode::Growth, 1000.0, 600.0), 1000.0);
+        assert_eq!(required_hauling_throughput(EcoMode::Growth, 400.0, 600.0), 600.0);
+        assert_eq!(required_hauling_throughput(EcoMode::SteadyState, 1000.0, 600.0), 600.0);
+    }
+
+    #[test]
+    fn test_steady_state_upgraders() {
+        // A single upgrader reaches the limit of 15E/t.
+        let (upgraders_required, upgrader_body) = steady_state_upgraders(300, 12900, false);
+        assert_eq!(upgraders_required, 1);
+        assert_eq!(upgrader_body.upgrade_energy_usage(), 15);
+
+        // If the controller is about to downgrade, smaller upgraders are spawned right away, but no
+        // more than needed for the limit.
+        let (upgraders_required, upgrader_body) = steady_state_upgraders(550, 12900, true);
+        assert_eq!(upgrader_body.upgrade_energy_usage(), 2);
+        assert_eq!(upgraders_required, 8);
+        let (upgraders_required, _) = steady_state_upgraders(12900, 12900, true);
+        assert_eq!(upgraders_required, 1);
+    }
+
+    #[test]
+    fn test_emergency_mode_hysteresis() {
+        let max_ticks_to_downgrade = 200_000;
+        let safe_ticks_to_downgrade = 150_000;
+        let next_mode = |previous_mode: EcoMode, storage_energy: u32| {
+            next_eco_mode(previous_mode, EcoMode::Growth, Some(storage_energy), safe_ticks_to_downgrade, max_ticks_to_downgrade)
+        };
+
+        // Storage energy draining.
+        let mut mode = EcoMode::Growth;
+        for (storage_energy, expected_mode) in [
+            (50_000, EcoMode::Growth),
+            (EMERGENCY_STORAGE_ENERGY_LOW, EcoMode::Growth),
+            (EMERGENCY_STORAGE_ENERGY_LOW - 1, EcoMode::Emergency),
+            // Refilling, but staying in the emergency until the high threshold.
+            (EMERGENCY_STORAGE_ENERGY_LOW + 5_000, EcoMode::Emergency),
+            (EMERGENCY_STORAGE_ENERGY_HIGH - 1, EcoMode::Emergency),
+            (EMERGENCY_STORAGE_ENERGY_HIGH, EcoMode::Growth),
+            // Dropping back between the thresholds does not restart the emergency.
+            (EMERGENCY_STORAGE_ENERGY_HIGH - 1, EcoMode::Growth),
+            (EMERGENCY_STORAGE_ENERGY_LOW + 5_000, EcoMode::Growth),
+        ] {
+            mode = next_mode(mode, storage_energy);
+            assert_eq!(mode, expected_mode, "storage energy {}", storage_energy);
+        }
+
+        // Without a storage, only the controller matters.
+        assert_eq!(next_eco_mode(EcoMode::Growth, EcoMode::Growth, None, safe_ticks_to_downgrade, max_ticks_to_downgrade), EcoMode::Growth);
+        assert_eq!(next_eco_mode(EcoMode::Growth, EcoMode::Growth, None, 24_999, max_ticks_to_downgrade), EcoMode::Emergency);
+        assert_eq!(next_eco_mode(EcoMode::Emergency, EcoMode::Growth, None, 25_000, max_ticks_to_downgrade), EcoMode::Growth);
+
+        // Downgrade risk overrides a full storage and the steady state.
+        assert_eq!(next_eco_mode(EcoMode::SteadyState, EcoMode::SteadyState, Some(500_000), 1_000, max_ticks_to_downgrade), EcoMode::Emergency);
+        assert_eq!(next_eco_mode(EcoMode::Emergency, EcoMode::SteadyState, Some(500_000), safe_ticks_to_downgrade, max_ticks_to_downgrade), EcoMode::SteadyState);
+    }
+
+    #[test]
+    fn test_budgeted_income() {
+        assert_eq!(budgeted_income(10.0, None), 10.0);
+        assert_eq!(budgeted_income(10.0, Some(5.0)), 5.5);
+        // Measuring more than the theoretical income, e.g., from a miner with extra work parts,
+        // does not increase the budget.
+        assert_eq!(budgeted_income(10.0, Some(9.5)), 10.0);
+        assert_eq!(budgeted_income(10.0, Some(12.0)), 10.0);
+    }
+}
@@ -1,7 +1,8 @@
 use std::cmp::max;
-use enum_iterator::all;
+use std::fmt::Display;
+use enum_iterator::{all, Sequence};
 use rustc_hash::FxHashMap;
-use screeps::{ObjectId, Source};
+use screeps::{ObjectId, Source, CREEP_LIFE_TIME, LINK_LOSS_RATIO};
 use crate::utils::avg_vector::AvgVector;
 use crate::creeps::creep_role::CreepRole;
 use crate::hauling::haul_stats::HaulStats;
@@ -9,6 +10,7 @@ use crate::spawning::spawn_pool::WId;
 use crate::{local_debug, u};
 use crate::creeps::creeps::CreepRef;
 use crate::utils::game_tick::{first_tick, game_tick};
+use crate::utils::sampling::SMALL_SAMPLE_SIZE;
 
 const DEBUG: bool = true;
 
@@ -32,6 +34,11 @@ pub struct RoomEcoStats {
     
     /// Amount of energy collected from each source in the room (barring errors in harvest intent).
     pub total_harvest_power_by_source: FxHashMap<ObjectId<Source>, AvgVector<u32>>,
+    /// Energy harvested by miners from each source since last sampling.
+    harvested_energy_since_sample: FxHashMap<ObjectId<Source>, u32>,
+    /// Energy harvested by miners from each source per tick. Unlike `total_harvest_power_by_source`,
+    /// it includes the downtime of miners and the time the source is drained.
+    pub actual_income_by_source: FxHashMap<ObjectId<Source>, AvgVector<u32>>,
     /// Amount of resources hauled in given tick.
     pub total_used_haul_capacity: AvgVector<u32>,
     /// The total carry capacity of haulers in the room.
@@ -40,6 +47,67 @@ pub struct RoomEcoStats {
     
     /// Statistics about amount of resources in haul requests in the room.
     pub haul_stats: HaulStats,
+
+    /// Energy used for upgrading since last sampling by where the upgraders got it from.
+    upgrade_energy: FxHashMap<UpgradeEnergySource, u32>,
+    /// Energy used for upgrading per tick by where the upgraders got it from, e.g., to measure
+    /// the benefit of the controller link.
+    pub upgrade_energy_by_source: FxHashMap<UpgradeEnergySource, AvgVector<u32>>,
+
+    /// Energy lost in link transfers since last sampling.
+    link_energy_loss_since_sample: u32,
+    /// Energy lost in link transfers per tick, i.e., the `LINK_LOSS_RATIO` part of the energy sent.
+    pub link_energy_loss: AvgVector<u32>,
+
+    /// Energy spent on spawning creeps for other rooms since last sampling.
+    assisted_spawn_energy_since_sample: u32,
+    /// Energy spent on spawning creeps for other rooms per tick. It is taken from the energy
+    /// allocated to the room's own needs.
+    pub assisted_spawn_energy: AvgVector<u32>,
+}
+
+/// Energy flows of the room per 1000 ticks.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct EnergyLedger {
+    /// Energy harvested from the sources.
+    pub income: f32,
+    /// Body cost of the active creeps spread over their lifetime and creeps spawned for other rooms.
+    pub spawn_costs: f32,
+    /// Energy spent on building, upgrading and repairing.
+    pub work_costs: f32,
+    /// Energy lost in link transfers.
+    pub decay_losses: f32,
+}
+
+impl EnergyLedger {
+    pub fn balance(&self) -> f32 {
+        self.income - self.spawn_costs - self.work_costs - self.decay_losses
+    }
+}
+
+impl Display for EnergyLedger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "+{:.0}E income, -{:.0}E spawning, -{:.0}E work, -{:.0}E losses, {:.0}E balance",
+            self.income,
+            self.spawn_costs,
+            self.work_costs,
+            self.decay_losses,
+            self.balance()
+        )
+    }
+}
+
+/// Where an upgrader gets its energy from.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Sequence)]
+pub enum UpgradeEnergySource {
+    /// Withdrawn from the controller link.
+    Link,
+    /// Withdrawn from the controller container.
+    Container,
+    /// Delivered by haulers.
+    Delivery,
 }
 
 #[derive(Debug, Default)]
@@ -105,6 +173,22 @@ impl RoomEcoStats {
         *self.number_of_idle_creeps.entry(role).or_default() += 1;
     }
 
+    pub fn register_upgrade_energy(&mut self, source: UpgradeEnergySource, amount: u32) {
+        *self.upgrade_energy.entry(source).or_default() += amount;
+    }
+
+    pub fn register_harvest(&mut self, source_id: ObjectId<Source>, amount: u32) {
+        *self.harvested_energy_since_sample.entry(source_id).or_default() += amount;
+    }
+
+    pub fn register_link_transfer(&mut self, amount: u32) {
+        self.link_energy_loss_since_sample += (amount as f32 * LINK_LOSS_RATIO).ceil() as u32;
+    }
+
+    pub fn register_assisted_spawn(&mut self, energy_cost: u32) {
+        self.assisted_spawn_energy_since_sample += energy_cost;
+    }
+
     pub fn push_creep_stats_samples(&mut self) {
         let mut creep_stats: FxHashMap<CreepRole, SpawnPoolStats> = FxHashMap::default();
 
@@ -150,12 +234,124 @@ impl RoomEcoStats {
             );
         }
 
+        for source in all::<UpgradeEnergySource>() {
+            self.upgrade_energy_by_source.entry(source).or_default().push(
+                self.upgrade_energy
+                    .get(&source)
+                    .map_or(0, |&amount| amount / ticks_since_last_sample)
+            );
+        }
+
+        self.push_income_samples(ticks_since_last_sample);
+        self.link_energy_loss.push(self.link_energy_loss_since_sample / ticks_since_last_sample);
+        self.assisted_spawn_energy.push(self.assisted_spawn_energy_since_sample / ticks_since_last_sample);
+
         self.number_of_idle_creeps.clear();
+        self.upgrade_energy.clear();
+        self.link_energy_loss_since_sample = 0;
+        self.assisted_spawn_energy_since_sample = 0;
         self.creep_stats_by_role_sample_tick = game_tick()
     }
 
+    fn push_income_samples(&mut self, ticks_since_last_sample: u32) {
+        for &source_id in self.harvested_energy_since_sample.keys() {
+            self.actual_income_by_source.entry(source_id).or_default();
+        }
+        for (source_id, actual_income) in self.actual_income_by_source.iter_mut() {
+            let harvested_energy = self.harvested_energy_since_sample.get(source_id).copied().unwrap_or(0);
+            // Rounded rather than truncated since it is compared with the theoretical income.
+            actual_income.push((harvested_energy as f32 / max(1, ticks_since_last_sample) as f32).round() as u32);
+        }
+        self.harvested_energy_since_sample.clear();
+    }
+
+    /// The average energy per tick harvested from the source in the recent samples. `None` until
+    /// there are enough samples.
+    pub fn measured_income(&self, source_id: ObjectId<Source>) -> Option<f32> {
+        self.actual_income_by_source
+            .get(&source_id)
+            .filter(|actual_income| actual_income.samples >= SMALL_SAMPLE_SIZE)
+            .map(|actual_income| actual_income.small_sample_avg::<f32>())
+    }
+
+    /// The ratio of the measured income of the source to the theoretical one.
+    pub fn income_efficiency(&self, source_id: ObjectId<Source>, theoretical_income: f32) -> Option<f32> {
+        let measured_income = self.measured_income(source_id)?;
+        (theoretical_income > 0.0).then(|| measured_income / theoretical_income)
+    }
+
+    /// Energy flows of the room with given energy per tick spent on work by builders, upgraders
+    /// and repairers.
+    pub fn energy_ledger(&self, work_energy: f32) -> EnergyLedger {
+        let ticks = 1000.0;
+        let income = self
+            .actual_income_by_source
+            .values()
+            .map(|actual_income| actual_income.small_sample_avg::<f32>())
+            .sum::<f32>();
+        let creep_body_costs = self
+            .creep_stats_by_role
+            .values()
+            .map(|creep_stats| creep_stats.total_body_cost.small_sample_avg::<f32>() / CREEP_LIFE_TIME as f32)
+            .sum::<f32>();
+        EnergyLedger {
+            income: income * ticks,
+            spawn_costs: (creep_body_costs + self.assisted_spawn_energy.small_sample_avg::<f32>()) * ticks,
+            work_costs: work_energy * ticks,
+            decay_losses: self.link_energy_loss.small_sample_avg::<f32>() * ticks,
+        }
+    }
+
     pub fn creep_stats(&self, role: CreepRole) -> &RoomCreepStats {
         // TODO Ensure some stats exist before calling this.
         u!(self.creep_stats_by_role.get(&role))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::ObjectId;
+    use crate::economy::room_eco_stats::RoomEcoStats;
+    use crate::utils::sampling::{SAMPLE_INTERVAL, SMALL_SAMPLE_SIZE};
+
+    #[test]
+    fn test_windowed_income_efficiency() {
+        let source_id = ObjectId::from_packed(1);
+        let other_source_id = ObjectId::from_packed(2);
+        let mut eco_stats = RoomEcoStats::default();
+
+        // Harvesting 10E/t from the first source, with the second one not mined at all.
+        let push_samples = |eco_stats: &mut RoomEcoStats, harvests: &[u32]| {
+            for &amount in harvests {
+                eco_stats.register_harvest(source_id, amount);
+            }
+            eco_stats.harvested_energy_since_sample.entry(other_source_id).or_default();
+            eco_stats.push_income_samples(SAMPLE_INTERVAL);
+        };
+
+        for _ in 0..SMALL_SAMPLE_SIZE - 1 {
+            push_samples(&mut eco_stats, &[10, 10, 10, 10]);
+        }
+        // Not enough samples yet.
+        assert_eq!(eco_stats.measured_income(source_id), None);
+        push_samples(&mut eco_stats, &[10, 10, 10, 10]);
+        assert_eq!(eco_stats.measured_income(source_id), Some(10.0));
+        assert_eq!(eco_stats.income_efficiency(source_id, 10.0), Some(1.0));
+        assert_eq!(eco_stats.income_efficiency(other_source_id, 10.0), Some(0.0));
+
+        // The miner is away half of the time. Only the recent samples count.
+        for _ in 0..SMALL_SAMPLE_SIZE / 2 {
+            push_samples(&mut eco_stats, &[10, 10]);
+        }
+        assert_eq!(eco_stats.income_efficiency(source_id, 10.0), Some(0.75));
+        for _ in 0..SMALL_SAMPLE_SIZE / 2 {
+            push_samples(&mut eco_stats, &[10, 10]);
+        }
+        assert_eq!(eco_stats.income_efficiency(source_id, 10.0), Some(0.5));
+
+        let energy_ledger = eco_stats.energy_ledger(2.0);
+        assert_eq!(energy_ledger.income, 5000.0);
+        assert_eq!(energy_ledger.work_costs, 2000.0);
+        assert_eq!(energy_ledger.balance(), 3000.0);
+    }
+}
@@ -2,6 +2,7 @@ use log::trace;
 use screeps::RoomName;
 use crate::economy::room_eco_config::update_or_create_eco_config;
 use crate::kernel::sleep::sleep;
+use crate::room_maintenance::reserve_remotes::remote_eco_data;
 use crate::room_states::room_states::with_room_state;
 use crate::utils::sampling::ticks_until_sample_tick;
 
@@ -13,8 +14,9 @@ pub async fn update_eco_config(room_name: RoomName) {
         // computed.
         sleep(ticks_until_sample_tick(0) + 1).await;
 
+        let remotes = remote_eco_data(room_name);
         with_room_state(room_name, |room_state| {
-            update_or_create_eco_config(room_state);
+            update_or_create_eco_config(room_state, &remotes);
         });
 
         sleep(ticks_until_sample_tick(0) + 1).await;
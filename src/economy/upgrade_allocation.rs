@@ -0,0 +1,288 @@
+use std::cmp::max;
+use log::debug;
+use rustc_hash::FxHashMap;
+use screeps::{controller_downgrade, RoomName, CONTROLLER_MAX_UPGRADE_PER_TICK, CREEP_LIFE_TIME};
+use screeps::StructureType::{Spawn, Storage};
+use crate::config::{MANUAL_UPGRADE_ALLOCATIONS, UPGRADE_STRATEGY};
+use crate::creeps::creep_body::CreepBody;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::kernel::sleep::sleep;
+use crate::room_states::room_state::RoomState;
+use crate::room_states::room_states::for_each_owned_room;
+use crate::u;
+use crate::utils::game_tick::game_tick;
+
+/// The number of ticks between computing the upgrade allocations.
+const UPGRADE_ALLOCATION_INTERVAL: u32 = 300;
+
+/// The allocation a room gets unless its controller is close to downgrading.
+pub const DEFAULT_MIN_UPGRADE_ALLOCATION: u8 = 10;
+pub const FULL_UPGRADE_ALLOCATION: u8 = 100;
+
+/// Storage surplus at which the efficiency of a room doubles.
+const STORAGE_SURPLUS_SCALE: f32 = 50_000.0;
+/// Haul distance from storage to the controller at which the efficiency of a room halves.
+const HAUL_DIST_SCALE: f32 = 10.0;
+
+/// The maximum energy all upgraders in a room may spend on upgrading per tick at given RCL, if
+/// limited.
+pub fn max_upgrade_energy_per_tick(rcl: u8) -> Option<u32> {
+    (rcl == 8).then_some(CONTROLLER_MAX_UPGRADE_PER_TICK)
+}
+
+/// The number of upgraders with given energy usage per tick needed to reach the limit of energy
+/// spent on upgrading per tick.
+pub fn max_upgraders(max_upgrade_energy: u32, upgrader_energy_usage: u32) -> u32 {
+    max_upgrade_energy.div_ceil(max(1, upgrader_energy_usage))
+}
+
+/// How the energy used for upgrading is distributed among owned rooms.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UpgradeStrategy {
+    /// Each room upgrades using whatever energy it has to spare.
+    Even,
+    /// Only the room with the best upgrading logistics upgrades fully, others only keep their
+    /// controllers from downgrading.
+    Concentrate,
+    /// Allocations are given in the config. Rooms not present there upgrade fully.
+    Manual,
+}
+
+/// Properties of a room deciding how efficiently it upgrades its controller.
+#[derive(Clone, Debug)]
+pub struct RoomUpgradeProfile {
+    pub room_name: RoomName,
+    pub rcl: u8,
+    /// Energy in the storage that is not needed for anything else.
+    pub storage_surplus: u32,
+    /// Distance the energy has to be hauled from the storage to the controller.
+    pub controller_haul_dist: u32,
+    /// Fraction of the spawn time not used by other creeps, from 0 to 1.
+    pub spawn_headroom: f32,
+    /// The minimum allocation in percent, e.g., required to keep the controller from downgrading.
+    pub min_allocation: u8,
+}
+
+impl RoomUpgradeProfile {
+    pub fn upgrade_efficiency(&self) -> f32 {
+        self.spawn_headroom.clamp(0.0, 1.0) * (1.0 + self.storage_surplus as f32 / STORAGE_SURPLUS_SCALE)
+            / (1.0 + self.controller_haul_dist as f32 / HAUL_DIST_SCALE)
+    }
+}
+
+/// Assigns each room the percentage of its energy income it may use for upgrading.
+pub fn allocate_upgrading(
+    profiles: &[RoomUpgradeProfile],
+    strategy: UpgradeStrategy,
+    manual_allocations: &FxHashMap<RoomName, u8>,
+) -> FxHashMap<RoomName, u8> {
+    let best_room_name = match strategy {
+        UpgradeStrategy::Concentrate => profiles
+            .iter()
+            // Upgrading at RCL 8 is capped, so concentrating on such a room would waste energy
+            // unless there are no other rooms.
+            .max_by(|a, b| {
+                (a.rcl < 8)
+                    .cmp(&(b.rcl < 8))
+                    .then(a.upgrade_efficiency().total_cmp(&b.upgrade_efficiency()))
+            })
+            .map(|profile| profile.room_name),
+        _ => None,
+    };
+
+    profiles
+        .iter()
+        .map(|profile| {
+            let allocation = match strategy {
+                UpgradeStrategy::Even => FULL_UPGRADE_ALLOCATION,
+                UpgradeStrategy::Concentrate => {
+                    if best_room_name == Some(profile.room_name) {
+                        FULL_UPGRADE_ALLOCATION
+                    } else {
+                        0
+                    }
+                }
+                UpgradeStrategy::Manual => manual_allocations
+                    .get(&profile.room_name)
+                    .copied()
+                    .unwrap_or(FULL_UPGRADE_ALLOCATION),
+            };
+            let allocation = allocation.clamp(
+                profile.min_allocation.min(FULL_UPGRADE_ALLOCATION),
+                FULL_UPGRADE_ALLOCATION
+            );
+            (profile.room_name, allocation)
+        })
+        .collect()
+}
+
+/// Periodically computes the upgrade allocation of each owned room that is then used to limit
+/// the number of its upgraders.
+pub async fn balance_upgrading() {
+    let manual_allocations = MANUAL_UPGRADE_ALLOCATIONS
+        .iter()
+        .map(|&(room_name, allocation)| (u!(RoomName::new(room_name)), allocation))
+        .collect::<FxHashMap<_, _>>();
+
+    loop {
+        let mut profiles = Vec::new();
+        for_each_owned_room(|_, room_state| {
+            if let Some(profile) = room_upgrade_profile(room_state) {
+                profiles.push(profile);
+            }
+        });
+
+        let allocations = allocate_upgrading(&profiles, UPGRADE_STRATEGY, &manual_allocations);
+        debug!("Upgrade allocations using {:?} strategy: {:?}.", UPGRADE_STRATEGY, allocations);
+
+        for_each_owned_room(|room_name, room_state| {
+            room_state.upgrade_allocation = allocations.get(&room_name).copied().unwrap_or(FULL_UPGRADE_ALLOCATION);
+        });
+
+        sleep(UPGRADE_ALLOCATION_INTERVAL).await;
+    }
+}
+
+fn room_upgrade_profile(room_state: &RoomState) -> Option<RoomUpgradeProfile> {
+    let eco_stats = room_state.eco_stats.as_ref()?;
+    let eco_config = room_state.eco_config.as_ref()?;
+    let controller = room_state.controller?;
+
+    let storage_pos = room_state
+        .structure_pos(Storage)
+        .or_else(|| room_state.planned_structure_pos(Storage))?;
+    let controller_haul_dist = controller.work_xy?.to_pos(room_state.room_name).get_range_to(storage_pos);
+
+    let number_of_spawns = room_state.structures.get(&Spawn).map_or(0, |spawns| spawns.len()) as u32;
+    let spawn_time_used = [
+        (eco_config.haulers_required, &eco_config.hauler_body),
+        (eco_config.miners_required, &eco_config.miner_body),
+        (eco_config.builders_required, &eco_config.builder_body),
+        (eco_config.repairers_required, &eco_config.repairer_body),
+    ]
+    .into_iter()
+    .map(|(number_of_creeps, body): (u32, &CreepBody)| number_of_creeps * body.spawn_duration())
+    .sum::<u32>();
+    let spawn_headroom = if number_of_spawns == 0 {
+        0.0
+    } else {
+        1.0 - spawn_time_used as f32 / (number_of_spawns * CREEP_LIFE_TIME) as f32
+    };
+
+    let ticks_to_downgrade = controller.downgrade_tick.saturating_sub(game_tick());
+    let max_ticks_to_downgrade = controller_downgrade(room_state.rcl)?;
+    let min_allocation = if ticks_to_downgrade < max_ticks_to_downgrade / 4 {
+        FULL_UPGRADE_ALLOCATION
+    } else {
+        DEFAULT_MIN_UPGRADE_ALLOCATION
+    };
+
+    Some(RoomUpgradeProfile {
+        room_name: room_state.room_name,
+        rcl: room_state.rcl,
+        storage_surplus: eco_stats.haul_stats.withdrawable_storage_amount.avg::<u32>(),
+        controller_haul_dist,
+        spawn_headroom,
+        min_allocation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+    use crate::economy::upgrade_allocation::{
+        allocate_upgrading, max_upgrade_energy_per_tick, max_upgraders, RoomUpgradeProfile, UpgradeStrategy,
+        DEFAULT_MIN_UPGRADE_ALLOCATION, FULL_UPGRADE_ALLOCATION,
+    };
+    use crate::utils::test_utils::room_name;
+
+    fn profiles() -> Vec<RoomUpgradeProfile> {
+        vec![
+            // A young room with little storage and far controller.
+            RoomUpgradeProfile {
+                room_name: room_name("W1N1"),
+                rcl: 5,
+                storage_surplus: 5_000,
+                controller_haul_dist: 25,
+                spawn_headroom: 0.4,
+                min_allocation: DEFAULT_MIN_UPGRADE_ALLOCATION,
+            },
+            // A well developed room with a lot of energy close to the controller.
+            RoomUpgradeProfile {
+                room_name: room_name("W2N1"),
+                rcl: 7,
+                storage_surplus: 200_000,
+                controller_haul_dist: 5,
+                spawn_headroom: 0.7,
+                min_allocation: DEFAULT_MIN_UPGRADE_ALLOCATION,
+            },
+            // A room with even more energy, but already at RCL 8.
+            RoomUpgradeProfile {
+                room_name: room_name("W3N1"),
+                rcl: 8,
+                storage_surplus: 500_000,
+                controller_haul_dist: 3,
+                spawn_headroom: 0.9,
+                min_allocation: DEFAULT_MIN_UPGRADE_ALLOCATION,
+            },
+            // A room about to downgrade.
+            RoomUpgradeProfile {
+                room_name: room_name("W4N1"),
+                rcl: 4,
+                storage_surplus: 0,
+                controller_haul_dist: 10,
+                spawn_headroom: 0.2,
+                min_allocation: FULL_UPGRADE_ALLOCATION,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_even_strategy() {
+        let allocations = allocate_upgrading(&profiles(), UpgradeStrategy::Even, &FxHashMap::default());
+        assert_eq!(allocations.len(), 4);
+        assert!(allocations.values().all(|&allocation| allocation == FULL_UPGRADE_ALLOCATION));
+    }
+
+    #[test]
+    fn test_concentrate_strategy() {
+        let allocations = allocate_upgrading(&profiles(), UpgradeStrategy::Concentrate, &FxHashMap::default());
+        assert_eq!(allocations[&room_name("W1N1")], DEFAULT_MIN_UPGRADE_ALLOCATION);
+        assert_eq!(allocations[&room_name("W2N1")], FULL_UPGRADE_ALLOCATION);
+        assert_eq!(allocations[&room_name("W3N1")], DEFAULT_MIN_UPGRADE_ALLOCATION);
+        // The minimum is respected.
+        assert_eq!(allocations[&room_name("W4N1")], FULL_UPGRADE_ALLOCATION);
+    }
+
+    #[test]
+    fn test_concentrate_strategy_with_only_rcl8_rooms() {
+        let profiles = profiles().into_iter().filter(|profile| profile.rcl == 8).collect::<Vec<_>>();
+        let allocations = allocate_upgrading(&profiles, UpgradeStrategy::Concentrate, &FxHashMap::default());
+        assert_eq!(allocations[&room_name("W3N1")], FULL_UPGRADE_ALLOCATION);
+    }
+
+    #[test]
+    fn test_manual_strategy() {
+        let manual_allocations = FxHashMap::from_iter([
+            (room_name("W1N1"), 50),
+            (room_name("W2N1"), 0),
+            (room_name("W4N1"), 0),
+        ]);
+        let allocations = allocate_upgrading(&profiles(), UpgradeStrategy::Manual, &manual_allocations);
+        assert_eq!(allocations[&room_name("W1N1")], 50);
+        assert_eq!(allocations[&room_name("W2N1")], DEFAULT_MIN_UPGRADE_ALLOCATION);
+        assert_eq!(allocations[&room_name("W3N1")], FULL_UPGRADE_ALLOCATION);
+        assert_eq!(allocations[&room_name("W4N1")], FULL_UPGRADE_ALLOCATION);
+    }
+
+    #[test]
+    fn test_max_upgraders_at_rcl8() {
+        assert_eq!(max_upgrade_energy_per_tick(7), None);
+        assert_eq!(max_upgrade_energy_per_tick(8), Some(15));
+
+        assert_eq!(max_upgraders(15, 6), 3);
+        assert_eq!(max_upgraders(15, 15), 1);
+        assert_eq!(max_upgraders(15, 30), 1);
+        assert_eq!(max_upgraders(15, 0), 15);
+    }
+}
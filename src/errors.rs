@@ -9,6 +9,8 @@ pub enum XiError {
     CreepPickupFailed,
     #[error("creep failed to store a resource")]
     CreepTransferFailed,
+    #[error("creep failed to store a resource since the target is full")]
+    CreepTransferTargetFull,
     #[error("creep failed to withdraw a resource")]
     CreepWithdrawFailed,
     #[error("creep failed to drop a resource")]
@@ -29,14 +31,42 @@ pub enum XiError {
     CreepRepairFailed,
     #[error("creep failed to claim a controller")]
     CreepClaimFailed,
+    #[error("creep failed to reserve a controller")]
+    CreepReserveControllerFailed,
+    #[error("creep failed to attack a controller")]
+    CreepAttackControllerFailed,
+    #[error("creep failed to sign a controller")]
+    CreepSignControllerFailed,
+    #[error("creep failed to attack")]
+    CreepAttackFailed,
+    #[error("creep failed to make a ranged attack")]
+    CreepRangedAttackFailed,
+    #[error("lab failed to boost a creep")]
+    LabBoostFailed,
+    #[error("spawn failed to recycle a creep")]
+    CreepRecycleFailed,
     #[error("object does not exist in the game")]
     ObjectDoesNotExist,
     #[error("failed to scan the room due to lack of visibility")]
     RoomVisibilityError,
     #[error("spawn request tick is in the past")]
     SpawnRequestTickInThePast,
+    #[error("no owned room can spawn the creep")]
+    NoRoomToSpawnCreep,
     #[error("path not found")]
     PathNotFound,
+    #[error("route between rooms not found")]
+    RouteNotFound,
+    #[error("failed to decode the packed terrain")]
+    PackedTerrainDecodeFailed,
+    #[error("there is no mailbox for the message at the address")]
+    MailboxNotFound,
+    #[error("the mailbox is full")]
+    MailboxFull,
+    #[error("the process or name already has a mailbox")]
+    MailboxAlreadyExists,
+    #[error("the process does not exist, possibly since it has already finished")]
+    ProcessNotFound,
 }
 
 impl XiError {
@@ -0,0 +1,147 @@
+use std::cmp::Ordering;
+use log::trace;
+use rustc_hash::FxHashMap;
+use screeps::RoomName;
+use crate::room_planning::room_planner::RoomPlanner;
+use crate::room_states::room_state::{RoomDesignation, RoomState};
+use crate::room_states::scout_rooms::room_linear_distance;
+
+/// Planners stop after this many tries if they already found at least one plan.
+const MAX_CANDIDATE_PLANNER_TRIES: u16 = 20;
+
+/// A scouted room that could be claimed, with the score of its plan.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ExpansionCandidate {
+    pub room_name: RoomName,
+    pub score: f32,
+    /// Linear distance to the closest owned room.
+    pub distance: u32,
+}
+
+/// Whether the room was scouted and can be claimed, i.e., it has a controller and sources and is
+/// not owned, reserved or occupied by anyone else.
+pub fn is_expansion_candidate(room_state: &RoomState) -> bool {
+    room_state.last_scan_tick.is_some()
+        && room_state.designation == RoomDesignation::NotOwned
+        && room_state.controller.is_some()
+        && !room_state.sources.is_empty()
+        && room_state.reservation.is_none()
+        && !room_state.hostile_structures
+}
+
+/// The candidates within given linear distance of the owned rooms that were planned successfully,
+/// the best one first. Among equally good ones, the closer one is preferred.
+pub fn rank_candidates(
+    scores: &FxHashMap<RoomName, Option<f32>>,
+    owned_room_names: &[RoomName],
+    max_range: u32
+) -> Vec<ExpansionCandidate> {
+    let mut candidates = scores
+        .iter()
+        .filter_map(|(&room_name, &score)| {
+            let distance = owned_room_names
+                .iter()
+                .map(|&owned_room_name| room_linear_distance(owned_room_name, room_name))
+                .min()?;
+            (distance <= max_range).then_some(ExpansionCandidate {
+                room_name,
+                score: score?,
+                distance,
+            })
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.distance.cmp(&b.distance))
+            .then_with(|| a.room_name.cmp(&b.room_name))
+    });
+    candidates
+}
+
+/// Makes a step in planning the candidate room. Returns the score of its best plan once the
+/// planning is finished, `None` inside if no plan was found.
+pub fn plan_candidate_step(room_name: RoomName, planner: &mut RoomPlanner) -> Option<Option<f32>> {
+    // Errors are normal when planning.
+    if let Err(err) = planner.plan_step() {
+        trace!("Failed a planning attempt of candidate {}: {}.", room_name, err);
+    }
+    let finished = !planner.is_attempt_in_progress()
+        && (planner.plans_count >= 1 && planner.tries_count >= MAX_CANDIDATE_PLANNER_TRIES || planner.is_finished());
+    finished.then(|| planner.best_plan.as_ref().map(|plan| plan.score.total_score))
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+    use screeps::ObjectId;
+    use crate::expansion::candidates::{is_expansion_candidate, rank_candidates};
+    use crate::room_states::room_state::{empty_unowned_room_state, ControllerData, ReservationData, RoomDesignation, SourceData};
+    use crate::utils::test_utils::room_name;
+
+    #[test]
+    fn test_candidate_ranking() {
+        let owned_room_names = [room_name("W1N1")];
+        let scores = FxHashMap::from_iter([
+            (room_name("W2N1"), Some(5.0)),
+            (room_name("W3N1"), Some(8.0)),
+            // Equally good but farther.
+            (room_name("W4N1"), Some(8.0)),
+            // Failed to plan.
+            (room_name("W1N2"), None),
+            // Out of range.
+            (room_name("W9N1"), Some(20.0)),
+        ]);
+
+        let candidates = rank_candidates(&scores, &owned_room_names, 4);
+        let ranked_room_names = candidates.iter().map(|candidate| candidate.room_name).collect::<Vec<_>>();
+        assert_eq!(ranked_room_names, vec![room_name("W3N1"), room_name("W4N1"), room_name("W2N1")]);
+        assert_eq!(candidates[0].distance, 2);
+
+        // Distance is to the closest owned room.
+        let candidates = rank_candidates(&scores, &[room_name("W1N1"), room_name("W8N1")], 4);
+        assert_eq!(candidates[0].room_name, room_name("W9N1"));
+        assert_eq!(candidates[0].distance, 1);
+    }
+
+    #[test]
+    fn test_expansion_candidate_filter() {
+        let mut room_state = empty_unowned_room_state();
+        assert!(!is_expansion_candidate(&room_state));
+
+        room_state.last_scan_tick = Some(1);
+        room_state.controller = Some(ControllerData {
+            id: ObjectId::from_packed(1),
+            xy: (20, 20).try_into().unwrap(),
+            work_xy: None,
+            link_xy: None,
+            link_id: None,
+            downgrade_tick: 0,
+        });
+        room_state.sources.push(SourceData {
+            id: ObjectId::from_packed(2),
+            xy: (30, 30).try_into().unwrap(),
+            work_xy: None,
+            drop_mining_xys: Vec::new(),
+            container_id: None,
+            link_xy: None,
+            link_id: None,
+        });
+        assert!(is_expansion_candidate(&room_state));
+
+        room_state.reservation = Some(ReservationData {
+            username: "enemy".into(),
+            end_tick: 1000,
+        });
+        assert!(!is_expansion_candidate(&room_state));
+        room_state.reservation = None;
+
+        room_state.hostile_structures = true;
+        assert!(!is_expansion_candidate(&room_state));
+        room_state.hostile_structures = false;
+
+        room_state.designation = RoomDesignation::Enemy;
+        assert!(!is_expansion_candidate(&room_state));
+    }
+}
@@ -0,0 +1,391 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use log::{debug, info, warn};
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::{game, RoomName, StructureSpawn, CREEP_RANGED_ACTION_RANGE};
+use serde::{Deserialize, Serialize};
+use screeps::Part::{Carry, Claim, Move, Work};
+use screeps::StructureType::Spawn;
+use crate::config::{MAX_EXPANSION_RANGE, MIN_EXPANSION_CPU_BUCKET};
+use crate::creeps::creep_body::CreepBody;
+use crate::creeps::creep_role::CreepRole::{Builder, Claimer};
+use crate::expansion::candidates::{is_expansion_candidate, plan_candidate_step, rank_candidates, ExpansionCandidate};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::kernel::kernel::{current_priority, schedule, should_finish};
+use crate::kernel::sleep::sleep;
+use crate::priorities::EXPANSION_SPAWN_PRIORITY;
+use crate::room_planning::room_planner::RoomPlanner;
+use crate::room_states::room_state::RoomDesignation;
+use crate::room_states::room_states::{for_each_owned_room, for_each_room, with_room_state};
+use crate::room_states::scout_rooms::room_linear_distance;
+use crate::spawning::remote_spawning::remote_spawn_request;
+use crate::spawning::reserved_creep::ReservedCreep;
+use crate::spawning::spawn_schedule::SpawnPromiseRef;
+use crate::travel::travel::travel;
+use crate::travel::travel_spec::TravelSpec;
+
+/// Number of builders sent to a freshly claimed room to build its first spawn.
+const PIONEERS_COUNT: usize = 2;
+
+thread_local! {
+    static EXPANSION: RefCell<Expansion> = RefCell::new(Expansion::default());
+}
+
+pub fn with_expansion<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Expansion) -> R,
+{
+    EXPANSION.with(|expansion| f(&mut expansion.borrow_mut()))
+}
+
+/// The outcome of sending a claimer to a candidate room.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClaimResult {
+    Claimed,
+    /// The claimer reached the controller, but failed to claim it, e.g., since it was reserved
+    /// or claimed in the meantime.
+    ClaimBlocked,
+    /// The claimer could not reach the controller.
+    PathBlocked,
+    /// The claimer could not be spawned. The candidate is not at fault.
+    SpawnFailed,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum ExpansionStage {
+    /// Waiting until another room can be claimed.
+    #[default]
+    Idle,
+    /// A claimer is on its way to the controller of the room.
+    Claiming(RoomName),
+    /// The room is claimed and pioneers are building its first spawn.
+    Pioneering(RoomName),
+}
+
+/// State of claiming new rooms, one at a time. Candidates that failed to be claimed are not
+/// tried again.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expansion {
+    pub stage: ExpansionStage,
+    pub failed_candidates: FxHashSet<RoomName>,
+    /// Final scores of the candidates, `None` for the ones that could not be planned.
+    pub scores: FxHashMap<RoomName, Option<f32>>,
+}
+
+impl Expansion {
+    /// Starts claiming the best candidate that did not fail before. Returns the chosen room.
+    pub fn start(&mut self, candidates: &[ExpansionCandidate]) -> Option<RoomName> {
+        if self.stage != ExpansionStage::Idle {
+            return None;
+        }
+        let room_name = candidates
+            .iter()
+            .map(|candidate| candidate.room_name)
+            .find(|room_name| !self.failed_candidates.contains(room_name))?;
+        self.stage = ExpansionStage::Claiming(room_name);
+        Some(room_name)
+    }
+
+    /// Moves on after the claimer finished. A claimed room gets pioneers, otherwise the next best
+    /// candidate is tried, unless the failure is not the fault of the room.
+    pub fn on_claim_result(&mut self, result: ClaimResult, candidates: &[ExpansionCandidate]) {
+        let ExpansionStage::Claiming(room_name) = self.stage else {
+            return;
+        };
+        match result {
+            ClaimResult::Claimed => {
+                info!("Claimed {}.", room_name);
+                self.stage = ExpansionStage::Pioneering(room_name);
+            }
+            ClaimResult::ClaimBlocked | ClaimResult::PathBlocked => {
+                warn!("Failed to claim {}: {:?}.", room_name, result);
+                self.failed_candidates.insert(room_name);
+                self.stage = ExpansionStage::Idle;
+                self.start(candidates);
+            }
+            ClaimResult::SpawnFailed => {
+                warn!("Failed to spawn a claimer for {}.", room_name);
+                self.stage = ExpansionStage::Idle;
+            }
+        }
+    }
+
+    /// Finishes the expansion once the room has a spawn or was lost in the meantime.
+    pub fn on_pioneering_update(&mut self, has_spawn: bool, owned: bool) {
+        if let ExpansionStage::Pioneering(room_name) = self.stage {
+            if !owned {
+                warn!("Lost the freshly claimed room {}.", room_name);
+                self.failed_candidates.insert(room_name);
+                self.stage = ExpansionStage::Idle;
+            } else if has_spawn {
+                info!("The first spawn in {} is built.", room_name);
+                self.stage = ExpansionStage::Idle;
+            }
+        }
+    }
+}
+
+/// Whether the GCL allows another room and there is enough CPU to spare.
+fn can_expand(owned_rooms_count: u32) -> bool {
+    game::gcl::level() > owned_rooms_count && game::cpu::bucket() >= MIN_EXPANSION_CPU_BUCKET
+}
+
+fn owned_room_names() -> Vec<RoomName> {
+    let mut owned_room_names = Vec::new();
+    for_each_owned_room(|room_name, _| owned_room_names.push(room_name));
+    owned_room_names
+}
+
+async fn wait_for_spawn(spawn_promise: SpawnPromiseRef) -> Option<ReservedCreep> {
+    while spawn_promise.borrow().is_pending() {
+        sleep(1).await;
+    }
+    spawn_promise.borrow_mut().creep.take()
+}
+
+/// Spawns a claimer in the closest owned room able to do so and claims the controller of the room.
+async fn claim(room_name: RoomName) -> ClaimResult {
+    let Some(controller_pos) = with_room_state(room_name, |room_state| {
+        room_state.controller.map(|controller| controller.xy.to_pos(room_name))
+    }).flatten() else {
+        return ClaimResult::PathBlocked;
+    };
+
+    let claimer_body = CreepBody::from(vec![(Move, 1), (Claim, 1)]);
+    let spawn_promise = match remote_spawn_request(room_name, Claimer, claimer_body, EXPANSION_SPAWN_PRIORITY) {
+        Ok((_, spawn_promise)) => spawn_promise,
+        Err(err) => {
+            err.warn(&format!("Failed to schedule a claimer for {}", room_name));
+            return ClaimResult::SpawnFailed;
+        }
+    };
+    let Some(claimer) = wait_for_spawn(spawn_promise).await else {
+        return ClaimResult::SpawnFailed;
+    };
+
+    debug!("Moving the claimer to {}.", room_name);
+    if let Err(err) = travel(&claimer.as_ref(), TravelSpec::new(controller_pos, 1)).await {
+        err.warn(&format!("Claimer failed to reach the controller in {}", room_name));
+        return ClaimResult::PathBlocked;
+    }
+
+    let Some(controller) = game::rooms().get(room_name).and_then(|room| room.controller()) else {
+        return ClaimResult::PathBlocked;
+    };
+    let claim_result = claimer.borrow_mut().claim(&controller);
+    match claim_result {
+        Ok(()) => ClaimResult::Claimed,
+        Err(err) => {
+            err.warn(&format!("Failed to claim {}", room_name));
+            ClaimResult::ClaimBlocked
+        }
+    }
+}
+
+/// Sends builders spawned in nearby rooms to the claimed room. Once there, they are released to
+/// be used by the room like its own builders.
+async fn send_pioneers(room_name: RoomName) {
+    let pioneer_body = CreepBody::from(vec![(Move, 2), (Carry, 1), (Work, 1)]);
+    let mut spawn_promises = Vec::new();
+    for _ in 0..PIONEERS_COUNT {
+        match remote_spawn_request(room_name, Builder, pioneer_body.clone(), EXPANSION_SPAWN_PRIORITY) {
+            Ok((_, spawn_promise)) => spawn_promises.push(spawn_promise),
+            Err(err) => err.warn(&format!("Failed to schedule a pioneer for {}", room_name)),
+        }
+    }
+
+    let Some(target) = with_room_state(room_name, |room_state| {
+        room_state.controller.map(|controller| controller.xy.to_pos(room_name))
+    }).flatten() else {
+        return;
+    };
+    for spawn_promise in spawn_promises {
+        schedule(
+            &format!("expansion_pioneer_{}", room_name),
+            current_priority() - 1,
+            async move {
+                if let Some(pioneer) = wait_for_spawn(spawn_promise).await {
+                    let travel_spec = TravelSpec::new(target, CREEP_RANGED_ACTION_RANGE);
+                    if let Err(err) = travel(&pioneer.as_ref(), travel_spec).await {
+                        err.warn(&format!("Pioneer failed to reach {}", room_name));
+                    }
+                    // Dropping the pioneer releases it.
+                }
+            }
+        );
+    }
+}
+
+/// Claims new rooms when the GCL allows it. Scouted rooms near owned rooms are planned to be
+/// scored and the best scored one is claimed, followed by pioneers building its first spawn. If
+/// claiming fails, the next best candidate is tried. The state of the expansion is a part of
+/// the global state, so a claim in progress is resumed after a restart.
+pub async fn expand() {
+    let mut candidate_planner: Option<(RoomName, Box<RoomPlanner>)> = None;
+    let claim_result: Rc<RefCell<Option<ClaimResult>>> = Rc::new(RefCell::new(None));
+
+    // The claimer process did not survive the restart.
+    if let ExpansionStage::Claiming(room_name) = with_expansion(|expansion| expansion.stage) {
+        info!("Resuming expansion to {}.", room_name);
+        start_claim(room_name, claim_result.clone());
+    }
+
+    loop {
+        let owned_room_names = owned_room_names();
+
+        // Scoring scouted candidates in range with a single plan step each tick.
+        let mut unscored = Vec::new();
+        with_expansion(|expansion| {
+            for_each_room(|room_name, room_state| {
+                let in_range = owned_room_names
+                    .iter()
+                    .any(|&owned_room_name| room_linear_distance(owned_room_name, room_name) <= MAX_EXPANSION_RANGE);
+                if in_range && !expansion.scores.contains_key(&room_name) && is_expansion_candidate(room_state) {
+                    unscored.push(room_name);
+                }
+            });
+        });
+        if let Some(&room_name) = unscored.first() {
+            if !should_finish() && game::cpu::bucket() >= MIN_EXPANSION_CPU_BUCKET {
+                if candidate_planner.as_ref().map(|(planned_room_name, _)| *planned_room_name) != Some(room_name) {
+                    candidate_planner = match with_room_state(room_name, |room_state| RoomPlanner::new(room_state, true)) {
+                        Some(Ok(planner)) => Some((room_name, Box::new(planner))),
+                        _ => {
+                            debug!("Failed to create a planner for candidate {}.", room_name);
+                            with_expansion(|expansion| expansion.scores.insert(room_name, None));
+                            None
+                        }
+                    };
+                }
+                if let Some((_, planner)) = candidate_planner.as_mut() {
+                    if let Some(score) = plan_candidate_step(room_name, planner) {
+                        debug!("Scored expansion candidate {}: {:?}.", room_name, score);
+                        with_expansion(|expansion| expansion.scores.insert(room_name, score));
+                        candidate_planner = None;
+                    }
+                }
+            }
+        }
+
+        let candidates =
+            with_expansion(|expansion| rank_candidates(&expansion.scores, &owned_room_names, MAX_EXPANSION_RANGE));
+
+        if let Some(result) = claim_result.take() {
+            let stage = with_expansion(|expansion| {
+                expansion.on_claim_result(result, &candidates);
+                expansion.stage
+            });
+            if let ExpansionStage::Pioneering(room_name) = stage {
+                with_room_state(room_name, |room_state| room_state.designation = RoomDesignation::Owned);
+                schedule(&format!("expansion_pioneers_{}", room_name), current_priority() - 1, send_pioneers(room_name));
+            } else if let ExpansionStage::Claiming(room_name) = stage {
+                info!("Retrying expansion with {}.", room_name);
+                start_claim(room_name, claim_result.clone());
+            }
+        }
+
+        match with_expansion(|expansion| expansion.stage) {
+            ExpansionStage::Idle => {
+                // Scores are final once computed, so the best of the already scored candidates is
+                // claimed without waiting for the rest to be scored.
+                if can_expand(owned_room_names.len() as u32) {
+                    if let Some(room_name) = with_expansion(|expansion| expansion.start(&candidates)) {
+                        info!("Expanding to {}.", room_name);
+                        start_claim(room_name, claim_result.clone());
+                    }
+                }
+            }
+            ExpansionStage::Claiming(_) => {}
+            ExpansionStage::Pioneering(room_name) => {
+                let (has_spawn, owned) = with_room_state(room_name, |room_state| {
+                    (
+                        room_state.structures_with_type::<StructureSpawn>(Spawn).next().is_some(),
+                        room_state.designation == RoomDesignation::Owned
+                    )
+                }).unwrap_or((false, false));
+                with_expansion(|expansion| expansion.on_pioneering_update(has_spawn, owned));
+            }
+        }
+
+        sleep(1).await;
+    }
+}
+
+/// Runs the claim in a separate process, storing its result for the expansion process.
+fn start_claim(room_name: RoomName, claim_result: Rc<RefCell<Option<ClaimResult>>>) {
+    schedule(
+        &format!("expansion_claim_{}", room_name),
+        current_priority() - 1,
+        async move {
+            let result = claim(room_name).await;
+            claim_result.replace(Some(result));
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expansion::candidates::ExpansionCandidate;
+    use crate::expansion::expand::{ClaimResult, Expansion, ExpansionStage};
+    use crate::utils::test_utils::room_name;
+
+    fn candidates() -> Vec<ExpansionCandidate> {
+        ["W3N1", "W1N3", "W4N4"]
+            .into_iter()
+            .enumerate()
+            .map(|(ix, name)| ExpansionCandidate {
+                room_name: room_name(name),
+                score: 10.0 - ix as f32,
+                distance: 2,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_successful_expansion() {
+        let mut expansion = Expansion::default();
+        assert_eq!(expansion.start(&[]), None);
+        assert_eq!(expansion.start(&candidates()), Some(room_name("W3N1")));
+        assert_eq!(expansion.stage, ExpansionStage::Claiming(room_name("W3N1")));
+        // Only one expansion at a time.
+        assert_eq!(expansion.start(&candidates()), None);
+
+        expansion.on_claim_result(ClaimResult::Claimed, &candidates());
+        assert_eq!(expansion.stage, ExpansionStage::Pioneering(room_name("W3N1")));
+        expansion.on_pioneering_update(false, true);
+        assert_eq!(expansion.stage, ExpansionStage::Pioneering(room_name("W3N1")));
+        expansion.on_pioneering_update(true, true);
+        assert_eq!(expansion.stage, ExpansionStage::Idle);
+    }
+
+    #[test]
+    fn test_failed_claims_retried_with_next_candidate() {
+        let mut expansion = Expansion::default();
+        expansion.start(&candidates());
+
+        expansion.on_claim_result(ClaimResult::PathBlocked, &candidates());
+        assert_eq!(expansion.stage, ExpansionStage::Claiming(room_name("W1N3")));
+        expansion.on_claim_result(ClaimResult::ClaimBlocked, &candidates());
+        assert_eq!(expansion.stage, ExpansionStage::Claiming(room_name("W4N4")));
+
+        // A failed spawn does not count against the candidate.
+        expansion.on_claim_result(ClaimResult::SpawnFailed, &candidates());
+        assert_eq!(expansion.stage, ExpansionStage::Idle);
+        assert_eq!(expansion.start(&candidates()), Some(room_name("W4N4")));
+
+        // No candidates left.
+        expansion.on_claim_result(ClaimResult::ClaimBlocked, &candidates());
+        assert_eq!(expansion.stage, ExpansionStage::Idle);
+        assert_eq!(expansion.start(&candidates()), None);
+    }
+
+    #[test]
+    fn test_claimed_room_lost_while_pioneering() {
+        let mut expansion = Expansion::default();
+        expansion.start(&candidates());
+        expansion.on_claim_result(ClaimResult::Claimed, &candidates());
+        expansion.on_pioneering_update(false, false);
+        assert_eq!(expansion.stage, ExpansionStage::Idle);
+        assert_eq!(expansion.start(&candidates()), Some(room_name("W1N3")));
+    }
+}
@@ -0,0 +1,30 @@
+use log::{info, warn};
+use screeps::Position;
+use crate::room_planning::room_planner::RoomPlanner;
+use crate::room_states::room_states::with_room_state;
+
+/// Replans the room with the center of its core forced to be at the position of the flag.
+/// The current plan, if any, is kept until the new one is ready.
+pub async fn anchor_room_plan(pos: Position) {
+    let room_name = pos.room_name();
+    let xy = pos.xy();
+    let result = with_room_state(room_name, |room_state| {
+        let previous_anchor = room_state.planner_anchor.replace(xy);
+        match RoomPlanner::new(room_state, true) {
+            Ok(planner) => {
+                room_state.planner = Some(Box::new(planner));
+                Ok(())
+            }
+            Err(err) => {
+                room_state.planner_anchor = previous_anchor;
+                Err(err)
+            }
+        }
+    });
+
+    match result {
+        Some(Ok(())) => info!("Replanning room {} with the core anchored at {}.", room_name, xy),
+        Some(Err(err)) => warn!("Cannot anchor the core of room {} at {}: {}.", room_name, xy, err),
+        None => warn!("Cannot anchor the core of room {} without its state.", room_name),
+    }
+}
@@ -3,11 +3,15 @@ use log::info;
 use rustc_hash::FxHashMap;
 use screeps::game::flags;
 use screeps::HasPosition;
+use crate::flags::anchor_room_plan::anchor_room_plan;
 use crate::flags::claim_room::claim_room;
 use crate::flags::forced_build::forced_build;
+use crate::flags::order_room_lifecycle::order_room_lifecycle;
+use crate::flags::show_room_plan::{plan_flag_max_rcl, show_room_plan};
 use crate::geometry::position_utils::PositionUtils;
 use crate::kernel::kernel::{current_priority, schedule};
 use crate::kernel::sleep::sleep;
+use crate::room_states::room_lifecycle::RoomLifecycleEvent::{ActivationOrdered, EvacuationOrdered};
 
 pub async fn execute_flag_orders() {
     let mut active_flags = FxHashMap::default();
@@ -38,6 +42,35 @@ pub async fn execute_flag_orders() {
                         forced_build(flag_pos)
                     );
                     e.insert(process_handle);
+                } else if flag_name.starts_with("evacuate") || flag_name.starts_with("activate") {
+                    let room_name = flag.pos().room_name();
+                    let event = if flag_name.starts_with("evacuate") {
+                        EvacuationOrdered
+                    } else {
+                        ActivationOrdered
+                    };
+                    let process_handle = schedule(
+                        &format!("order_room_lifecycle_{}", room_name),
+                        current_priority() - 1,
+                        order_room_lifecycle(room_name, event)
+                    );
+                    e.insert(process_handle);
+                } else if flag_name.starts_with("anchor") {
+                    let flag_pos = flag.pos();
+                    let process_handle = schedule(
+                        &format!("anchor_room_plan_{}", flag_pos.room_name()),
+                        current_priority() - 1,
+                        anchor_room_plan(flag_pos)
+                    );
+                    e.insert(process_handle);
+                } else if flag_name.starts_with("plan") {
+                    let room_name = flag.pos().room_name();
+                    let process_handle = schedule(
+                        &format!("show_room_plan_{}", room_name),
+                        current_priority() - 1,
+                        show_room_plan(flag_name.clone(), room_name, plan_flag_max_rcl(flag_name))
+                    );
+                    e.insert(process_handle);
                 }
             }
         }
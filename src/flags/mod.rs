@@ -1,3 +1,6 @@
 pub mod flag_orders;
 pub mod claim_room;
-pub mod forced_build;
\ No newline at end of file
+pub mod forced_build;
+pub mod order_room_lifecycle;
+pub mod anchor_room_plan;
+pub mod show_room_plan;
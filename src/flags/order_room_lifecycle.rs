@@ -0,0 +1,12 @@
+use log::{debug, warn};
+use screeps::RoomName;
+use crate::room_states::room_lifecycle::{apply_room_lifecycle_event, RoomLifecycleEvent};
+use crate::room_states::room_states::with_room_state;
+
+/// Changes the lifecycle of an owned room, e.g., to start evacuating it before unclaiming it.
+pub async fn order_room_lifecycle(room_name: RoomName, event: RoomLifecycleEvent) {
+    debug!("Ordering {:?} in room {}.", event, room_name);
+    if with_room_state(room_name, |room_state| apply_room_lifecycle_event(room_state, event)).is_none() {
+        warn!("Cannot order {:?} in room {} without its state.", event, room_name);
+    }
+}
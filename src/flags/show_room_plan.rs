@@ -0,0 +1,34 @@
+use log::debug;
+use screeps::game::flags;
+use screeps::RoomName;
+use crate::cpu_management::cpu_level;
+use crate::cpu_management::CpuFeature::Visualizations;
+use crate::kernel::sleep::sleep;
+use crate::room_states::room_states::with_room_state;
+use crate::visualization::room_visualization::visualize_plan;
+
+/// The maximum RCL of the structures shown for a flag named `plan`, optionally followed by the RCL,
+/// e.g., `plan4`. All structures are shown when no RCL is given.
+pub fn plan_flag_max_rcl(flag_name: &str) -> u8 {
+    flag_name
+        .strip_prefix("plan")
+        .map(|suffix| suffix.chars().take_while(|c| c.is_ascii_digit()).collect::<String>())
+        .and_then(|digits| digits.parse::<u8>().ok())
+        .map_or(8, |rcl| rcl.clamp(1, 8))
+}
+
+/// Shows the plan of the room with the structures up to the given RCL for as long as the flag exists.
+pub async fn show_room_plan(flag_name: String, room_name: RoomName, max_rcl: u8) {
+    debug!("Showing the plan of room {} up to RCL {}.", room_name, max_rcl);
+    while flags().get(flag_name.clone()).is_some() {
+        if cpu_level().enables(Visualizations) {
+            with_room_state(room_name, |room_state| {
+                if let Some(plan) = room_state.plan.as_ref() {
+                    visualize_plan(room_name, plan, max_rcl);
+                }
+            });
+        }
+        sleep(1).await;
+    }
+    debug!("Stopped showing the plan of room {}.", room_name);
+}
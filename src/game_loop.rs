@@ -1,22 +1,32 @@
+use std::cell::Cell;
 use js_sys::Date;
 use crate::config::{FIRST_MEMORY_SAVE_TICK, LOG_LEVEL, MEMORY_SAVE_INTERVAL};
 use crate::construction::place_construction_sites::place_construction_sites;
+use crate::cpu_management::manage_cpu;
+use crate::construction::road_usage::decay_road_usage;
 use crate::utils::game_tick::{first_tick, game_tick};
-use crate::global_state::{load_global_state, save_global_state};
+use crate::global_state::{load_global_state, request_global_state, save_global_state};
 use crate::room_maintenance::maintenance::maintain_rooms;
 use crate::flags::flag_orders::execute_flag_orders;
-use crate::priorities::{CLEANUP_CREEPS_PRIORITY, PLACING_CONSTRUCTION_SITES_PRIORITY, MOVE_CREEPS_PRIORITY, ROOM_MAINTENANCE_PRIORITY, ROOM_PLANNING_PRIORITY, ROOM_SCANNING_PRIORITY, VISUALIZATIONS_PRIORITY, DEFEND_ROOMS_PRIORITY};
+use crate::priorities::{CLEANUP_CREEPS_PRIORITY, PLACING_CONSTRUCTION_SITES_PRIORITY, MOVE_CREEPS_PRIORITY, ROOM_MAINTENANCE_PRIORITY, ROOM_PLANNING_PRIORITY, ROOM_SCANNING_PRIORITY, VISUALIZATIONS_PRIORITY, DEFEND_ROOMS_PRIORITY, BALANCE_UPGRADING_PRIORITY, BALANCE_RESOURCES_PRIORITY, SCOUTING_PRIORITY, EXPANSION_PRIORITY};
 use crate::room_planning::plan_rooms::plan_rooms;
 use crate::room_states::scan_rooms::scan_rooms;
+use crate::room_states::scout_rooms::scout_rooms;
 use crate::visualization::show_visualizations::show_visualizations;
 use log::info;
 use screeps::game;
 use crate::creeps::creeps::cleanup_creeps;
-use crate::defense::defend_rooms;
+use crate::defense::defend_rooms::defend_rooms;
+use crate::economy::upgrade_allocation::balance_upgrading;
+use crate::economy::resource_balancer::balance_resources;
+use crate::expansion::expand::expand;
 use crate::kernel::kernel::{run_processes, schedule, wake_up_sleeping_processes};
+use crate::kernel::last_call::{is_after_last_call, register_last_call_flush};
 use crate::kernel::sleep::sleep;
+use crate::kernel::supervisor::{supervise, RestartPolicy};
 use crate::logging::init_logging;
-use crate::travel::traffic::move_creeps;
+use crate::profiler::end_profiler_tick;
+use crate::travel::traffic::{issue_pending_move_intents, move_creeps};
 use crate::utils::priority::Priority;
 
 pub fn setup() {
@@ -54,15 +64,21 @@ pub fn setup() {
         }
     });
 
-    load_global_state();
+    request_global_state();
 
     schedule("scan_rooms", ROOM_SCANNING_PRIORITY, scan_rooms());
     schedule("plan_rooms", ROOM_PLANNING_PRIORITY, plan_rooms());
-    schedule("cleanup_creeps", CLEANUP_CREEPS_PRIORITY, cleanup_creeps());
-    schedule(
+    supervise("cleanup_creeps", CLEANUP_CREEPS_PRIORITY, cleanup_creeps, RestartPolicy::Always);
+    supervise(
         "place_construction_sites",
         PLACING_CONSTRUCTION_SITES_PRIORITY,
-        place_construction_sites(),
+        place_construction_sites,
+        RestartPolicy::Always,
+    );
+    schedule(
+        "decay_road_usage",
+        PLACING_CONSTRUCTION_SITES_PRIORITY,
+        decay_road_usage(),
     );
     schedule(
         "maintain_rooms",
@@ -80,11 +96,32 @@ pub fn setup() {
         DEFEND_ROOMS_PRIORITY,
         defend_rooms(),
     );
+    schedule(
+        "balance_upgrading",
+        BALANCE_UPGRADING_PRIORITY,
+        balance_upgrading(),
+    );
+    schedule(
+        "resource_balancer",
+        BALANCE_RESOURCES_PRIORITY,
+        balance_resources(),
+    );
+    schedule(
+        "scouting",
+        SCOUTING_PRIORITY,
+        scout_rooms(),
+    );
+    schedule(
+        "expansion",
+        EXPANSION_PRIORITY,
+        expand(),
+    );
     schedule(
         "move_creeps",
         MOVE_CREEPS_PRIORITY,
         move_creeps()
     );
+    register_last_call_flush("issue_pending_move_intents", MOVE_CREEPS_PRIORITY, issue_pending_move_intents);
     schedule(
         "show_visualizations",
         VISUALIZATIONS_PRIORITY,
@@ -92,6 +129,13 @@ pub fn setup() {
     );
 }
 
+thread_local! {
+    /// Whether the global state was loaded after the restart.
+    static GLOBAL_STATE_LOADED: Cell<bool> = const { Cell::new(false) };
+    /// Whether the global state is to be saved at the end of the tick.
+    static MEMORY_SAVE_PENDING: Cell<bool> = const { Cell::new(false) };
+}
+
 // pub static mut S_PLANNER: Option<RoomPlanner> = None;
 
 pub fn game_loop() {
@@ -116,10 +160,29 @@ pub fn game_loop() {
         info!("Initialization used {}CPU.", game::cpu::get_used());
     }
 
+    // The segments with the global state are available only in the tick after requesting them, so
+    // the processes wait for them since they would work on an empty state otherwise.
+    if !GLOBAL_STATE_LOADED.with(|loaded| loaded.get()) {
+        if !load_global_state() {
+            info!("Waiting for the segments with the global state.");
+            return;
+        }
+        GLOBAL_STATE_LOADED.with(|loaded| loaded.set(true));
+    }
+
     wake_up_sleeping_processes();
     run_processes();
 
+    manage_cpu();
+
+    end_profiler_tick(game_tick());
+
     if ticks_since_restart >= FIRST_MEMORY_SAVE_TICK && ticks_since_restart % MEMORY_SAVE_INTERVAL == 0 {
+        MEMORY_SAVE_PENDING.with(|pending| pending.set(true));
+    }
+
+    // Saving the whole state is expensive, so after the last call it is postponed to the next tick.
+    if !is_after_last_call() && MEMORY_SAVE_PENDING.with(|pending| pending.replace(false)) {
         save_global_state();
     }
 
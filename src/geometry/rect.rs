@@ -152,6 +152,9 @@ impl Rect {
         Rect::new((left, top).into(), (right, bottom).into())
     }
 
+    /// Returns the point symmetric to given one with respect to the center of the rectangle.
+    /// The mirror of a point inside the rectangle is always inside it. The mirror of a point outside
+    /// of the rectangle may lie outside of the room, in which case `OutOfBoundsError` is returned.
     pub fn mirror_xy(self, xy: RoomXY) -> Result<RoomXY, OutOfBoundsError> {
         let offset = xy.sub(self.top_left);
         self.bottom_right.try_add_diff((-offset.0, -offset.1))
@@ -266,6 +269,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mirror_xy() {
+        let rect = Rect::new_unordered((40, 10).try_into().unwrap(), (49, 13).try_into().unwrap());
+
+        assert_eq!(rect.mirror_xy((40, 10).try_into().unwrap()).ok(), Some((49, 13).try_into().unwrap()));
+        assert_eq!(rect.mirror_xy((42, 12).try_into().unwrap()).ok(), Some((47, 11).try_into().unwrap()));
+        assert_eq!(rect.mirror_xy((39, 11).try_into().unwrap()).ok(), None);
+        assert_eq!(rect.mirror_xy((0, 11).try_into().unwrap()).ok(), None);
+        assert_eq!(rect.mirror_xy((45, 49).try_into().unwrap()).ok(), None);
+        assert_eq!(rect.mirror_xy((45, 20).try_into().unwrap()).ok(), Some((44, 3).try_into().unwrap()));
+    }
+
     #[test]
     fn test_boundary() {
         let rect1 = Rect::new_unordered((0, 0).try_into().unwrap(), (0, 0).try_into().unwrap());
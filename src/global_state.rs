@@ -1,103 +1,195 @@
+pub mod binary_format;
+pub mod persistence;
+
+use crate::expansion::expand::{with_expansion, Expansion};
+use crate::global_state::persistence::{load_from_segments, request_segments, save_to_segments, LoadedSegments};
+use crate::logging::{with_log_levels, LogLevels};
+use crate::profiler::{with_profiler_aggregates, ProfilerAggregates};
+use crate::room_planning::plan::Plan;
+use crate::room_states::chunk_graphs::{with_chunk_graphs, ChunkGraphs};
 use crate::room_states::room_states::{with_room_states, RoomStates};
 use js_sys::JsString;
-use log::{error, info, trace};
-use screeps::{raw_memory, MEMORY_SIZE_LIMIT};
+use log::{error, info, trace, warn};
+use screeps::raw_memory;
 use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, FromInto, PickFirst};
 
 /// References to parts of the global state to avoid copying them.
 #[derive(Serialize)]
 struct GlobalStateSer<'a> {
-    #[serde(default)]
     room_states: &'a RoomStates,
+    chunk_graphs: &'a ChunkGraphs,
+    log_levels: &'a LogLevels,
+    profiler_aggregates: &'a ProfilerAggregates,
+    expansion: &'a Expansion,
 }
 
-type OldRoomStates = RoomStates;
-
-/// A structure holding parts of the global state.
-/// Serialization of each part combines `PickFirst` and `FromInto` so that a migration may be written after its format
-/// change. The migration consists of copying the structure with the old format to the type marking old version of given
-/// part and implementing `From` to convert it to the new version. After the migration has been applied, the type should
-/// be reverted back to the current one.
-#[serde_as]
+/// A structure holding parts of the global state, in the same order as in `GlobalStateSer`.
+/// The binary format decodes the fields strictly in order, so any change of the fields here or in
+/// any persisted type requires bumping `PERSISTENCE_VERSION` and adding a migration.
 #[derive(Deserialize)]
 struct GlobalStateDe {
-    #[serde_as(as = "PickFirst<(_, FromInto<OldRoomStates>)>")]
-    #[serde(default)]
     room_states: RoomStates,
+    chunk_graphs: ChunkGraphs,
+    log_levels: LogLevels,
+    profiler_aggregates: ProfilerAggregates,
+    expansion: Expansion,
+}
+
+/// Requests the segments with the global state so that it can be loaded in the next tick.
+pub fn request_global_state() {
+    request_segments();
 }
 
-/// Saves the serialized global state into Memory.
+/// Saves the serialized global state into the segments.
 pub fn save_global_state() {
-    match serialize_global_state() {
-        Ok(serialized_global_state) => {
-            // TODO Keep in mind that base32768 is an option to increase the capacity of memory almost 2x.
-            let len = serialized_global_state.len() as u32;
-            raw_memory::set(&JsString::from(serialized_global_state));
+    let result = with_room_states(|room_states| {
+        let plans_size = room_states
+            .values()
+            .filter_map(|room_state| room_state.plan.as_ref())
+            .map(Plan::approximate_serialized_size)
+            .sum::<usize>();
+        with_chunk_graphs(|chunk_graphs| {
+            with_log_levels(|log_levels| {
+                with_profiler_aggregates(|profiler_aggregates| {
+                    with_expansion(|expansion| {
+                        save_to_segments(&GlobalStateSer {
+                            room_states,
+                            chunk_graphs,
+                            log_levels,
+                            profiler_aggregates,
+                            expansion,
+                        })
+                    })
+                })
+            })
+        })
+        .map(|len| (len, plans_size))
+    });
+    match result {
+        Ok((len, plans_size)) => {
+            // The segments hold the binary format encoded in base64.
             trace!(
-                "Serialized the global state. Using {:.1}kB ({}%) of the Memory limit.",
+                "Serialized the global state. Using {:.1}kB of segments, about {:.1}kB of which are room plans.",
                 (len as f32) / 1024.0,
-                len / MEMORY_SIZE_LIMIT
+                (plans_size as f32) * 4.0 / 3.0 / 1024.0
             );
+            // The global state used to be saved in Memory, which is no longer needed.
+            if raw_memory::get().length() > 2 {
+                raw_memory::set(&JsString::from("{}"));
+            }
         }
         Err(e) => {
-            error!("Failed to serialize global state: {:?}.", e);
+            error!("Failed to serialize global state: {}.", e);
         }
     }
 }
 
-/// Serializes the global state into a string.
-fn serialize_global_state() -> Result<String, serde_json::Error> {
-    with_room_states(|room_states| {
-        let global_state = GlobalStateSer { room_states };
-        serde_json::to_string(&global_state)
-    })
-}
+/// Loads the global state from the segments once they are available. The global state that fails
+/// to load, e.g., due to a version without a migration, is discarded. The global state saved in
+/// Memory before is not loaded, since it predates the persisted format.
+/// Returns whether the global state is ready.
+pub fn load_global_state() -> bool {
+    if cfg!(feature = "memory_wipe") {
+        info!("Wiping the memory.");
+        return true;
+    }
 
-/// Loads and deserializes the global state from Memory.
-pub fn load_global_state() {
-    // TODO Wiping memory when there is a flag memory_wipe.
-    // TODO Also, serializing this memory after wipe.
-    #[cfg(feature = "memory_wipe")]
-    let raw_memory_str = "{}";
-    #[cfg(feature = "memory_wipe")]
-    info!("Wiping the memory.");
-    #[cfg(not(feature = "memory_wipe"))]
-    let raw_memory_str = raw_memory::get().as_string().unwrap();
-    #[cfg(not(feature = "memory_wipe"))]
-    info!("Loading the global state.");
-    
-    match deserialize_global_state(&raw_memory_str) {
-        Ok(()) => {
-            trace!("Deserialized the global state.");
+    match load_from_segments::<GlobalStateDe>() {
+        Ok(LoadedSegments::Unavailable) => {
+            return false;
+        }
+        Ok(LoadedSegments::Empty) => {
+            info!("No saved global state.");
+        }
+        Ok(LoadedSegments::Loaded(global_state)) => {
+            info!("Loaded the global state.");
+            set_global_state(global_state);
         }
         Err(e) => {
-            error!("Failed to deserialize global state: {:?}.", e);
+            warn!("Discarding the saved global state: {}.", e);
         }
     }
+    true
 }
 
-/// Deserializes the global state from a string.
-fn deserialize_global_state(raw_memory_str: &str) -> Result<(), serde_json::Error> {
-    let deserialized_global_state: GlobalStateDe = serde_json::from_str(raw_memory_str)?;
+fn set_global_state(global_state: GlobalStateDe) {
+    let GlobalStateDe {
+        room_states: room_states_de,
+        chunk_graphs: chunk_graphs_de,
+        log_levels: log_levels_de,
+        profiler_aggregates: profiler_aggregates_de,
+        expansion: expansion_de,
+    } = global_state;
     with_room_states(move |room_states| {
-        let GlobalStateDe {
-            room_states: room_states_de,
-        } = deserialized_global_state;
-        {
-            *room_states = room_states_de;
-        }
+        *room_states = room_states_de;
+    });
+    with_chunk_graphs(move |chunk_graphs| {
+        *chunk_graphs = chunk_graphs_de;
+    });
+    with_log_levels(move |log_levels| {
+        *log_levels = log_levels_de;
+    });
+    with_profiler_aggregates(move |profiler_aggregates| {
+        *profiler_aggregates = profiler_aggregates_de;
+    });
+    with_expansion(move |expansion| {
+        *expansion = expansion_de;
     });
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::global_state::{deserialize_global_state, serialize_global_state};
+    use crate::algorithms::chunk_graph::chunk_graph;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::expansion::expand::{Expansion, ExpansionStage};
+    use crate::global_state::{GlobalStateDe, GlobalStateSer};
+    use crate::logging::LogLevels;
+    use crate::profiler::{ProfilerAggregates, SpanTree};
+    use log::LevelFilter::{Debug, Info};
+    use crate::global_state::persistence::{decode_segments, encode_segments, PERSISTENCE_VERSION};
+    use crate::room_states::chunk_graphs::ChunkGraphs;
+    use crate::room_states::room_states::test_room_states;
+    use screeps::RoomName;
 
     #[test]
     fn serialize_and_deserialize_global_state() {
-        let serialized_global_state = serialize_global_state().unwrap();
-        deserialize_global_state(&serialized_global_state).unwrap();
+        let room_states = test_room_states();
+        let mut chunk_graphs = ChunkGraphs::default();
+        chunk_graphs.insert(RoomName::new("W1N1").unwrap(), chunk_graph(&RoomMatrix::new(0), 5));
+        let mut log_levels = LogLevels::new(Info);
+        log_levels.set("xi::room_planning", Debug);
+        let mut span_tree = SpanTree::default();
+        span_tree.enter("room_planning", 1.0);
+        span_tree.exit(3.5);
+        let mut profiler_aggregates = ProfilerAggregates::default();
+        profiler_aggregates.record_tick(100, span_tree.spans(), 1000);
+        let mut expansion = Expansion {
+            stage: ExpansionStage::Claiming(RoomName::new("W2N1").unwrap()),
+            ..Expansion::default()
+        };
+        expansion.failed_candidates.insert(RoomName::new("W3N1").unwrap());
+        expansion.scores.insert(RoomName::new("W2N1").unwrap(), Some(12.5));
+        expansion.scores.insert(RoomName::new("W4N1").unwrap(), None);
+        let global_state_ser = GlobalStateSer {
+            room_states: &room_states,
+            chunk_graphs: &chunk_graphs,
+            log_levels: &log_levels,
+            profiler_aggregates: &profiler_aggregates,
+            expansion: &expansion,
+        };
+        let segments = encode_segments(&global_state_ser, PERSISTENCE_VERSION, 10).unwrap();
+        let global_state: GlobalStateDe =
+            decode_segments(|i| segments.get(i).cloned(), PERSISTENCE_VERSION, &[]).unwrap();
+        assert_eq!(
+            global_state.room_states.keys().collect::<Vec<_>>(),
+            room_states.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            global_state.chunk_graphs.values().map(|chunks| chunks.graph.node_count()).collect::<Vec<_>>(),
+            chunk_graphs.values().map(|chunks| chunks.graph.node_count()).collect::<Vec<_>>()
+        );
+        assert_eq!(global_state.log_levels, log_levels);
+        assert_eq!(global_state.profiler_aggregates, profiler_aggregates);
+        assert_eq!(global_state.expansion, expansion);
     }
 }
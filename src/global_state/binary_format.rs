@@ -0,0 +1,852 @@
+//! A compact binary serde format. Unsigned integers are encoded as LEB128 varints and signed ones
+//! are zigzag-encoded first, so small values take a single byte. Structs are encoded as their
+//! fields in order without names and enum variants as their indexes. The format is not
+//! self-describing, so the data can only be decoded into the same types it was encoded from.
+use std::fmt::Display;
+use serde::de::{DeserializeSeed, IntoDeserializer, Visitor};
+use serde::{de, ser, Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum BinaryFormatError {
+    #[error("{0}")]
+    Custom(String),
+    #[error("unexpected end of the input")]
+    UnexpectedEnd,
+    #[error("{0} bytes left in the input after decoding")]
+    TrailingBytes(usize),
+    #[error("varint does not fit in 64 bits")]
+    VarintOverflow,
+    #[error("integer out of range of its type")]
+    IntegerOutOfRange,
+    #[error("invalid {0}")]
+    InvalidValue(&'static str),
+    #[error("sequences and maps must have a known length")]
+    UnknownLength,
+    #[error("the format is not self-describing")]
+    NotSelfDescribing,
+}
+
+impl ser::Error for BinaryFormatError {
+    fn custom<T: Display>(msg: T) -> Self {
+        BinaryFormatError::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for BinaryFormatError {
+    fn custom<T: Display>(msg: T) -> Self {
+        BinaryFormatError::Custom(msg.to_string())
+    }
+}
+
+/// Encodes the value in the binary format.
+pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>, BinaryFormatError>
+where
+    T: Serialize + ?Sized,
+{
+    let mut encoder = Encoder { output: Vec::new() };
+    value.serialize(&mut encoder)?;
+    Ok(encoder.output)
+}
+
+/// Decodes a value from the binary format. All of the input must be used.
+pub fn from_bytes<'de, T>(bytes: &'de [u8]) -> Result<T, BinaryFormatError>
+where
+    T: Deserialize<'de>,
+{
+    let mut decoder = Decoder { input: bytes };
+    let value = T::deserialize(&mut decoder)?;
+    if decoder.input.is_empty() {
+        Ok(value)
+    } else {
+        Err(BinaryFormatError::TrailingBytes(decoder.input.len()))
+    }
+}
+
+pub fn write_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+/// Reads a varint from the beginning of the input and advances it.
+pub fn read_varint(input: &mut &[u8]) -> Result<u64, BinaryFormatError> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first().ok_or(BinaryFormatError::UnexpectedEnd)?;
+        *input = rest;
+        // Only the lowest bit of the tenth byte fits in 64 bits.
+        if shift == 63 && byte > 1 {
+            return Err(BinaryFormatError::VarintOverflow);
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+struct Encoder {
+    output: Vec<u8>,
+}
+
+impl Encoder {
+    fn write_len(&mut self, len: Option<usize>) -> Result<(), BinaryFormatError> {
+        let len = len.ok_or(BinaryFormatError::UnknownLength)?;
+        write_varint(&mut self.output, len as u64);
+        Ok(())
+    }
+}
+
+impl ser::Serializer for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), BinaryFormatError> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), BinaryFormatError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), BinaryFormatError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), BinaryFormatError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), BinaryFormatError> {
+        write_varint(&mut self.output, zigzag_encode(v));
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), BinaryFormatError> {
+        // Bytes are common, e.g., in bitfields, and a varint would take two bytes for half of them.
+        self.output.push(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), BinaryFormatError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), BinaryFormatError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), BinaryFormatError> {
+        write_varint(&mut self.output, v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), BinaryFormatError> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), BinaryFormatError> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), BinaryFormatError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), BinaryFormatError> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), BinaryFormatError> {
+        write_varint(&mut self.output, v.len() as u64);
+        self.output.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), BinaryFormatError> {
+        self.output.push(0);
+        Ok(())
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.output.push(1);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str
+    ) -> Result<(), BinaryFormatError> {
+        self.serialize_u32(variant_index)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T
+    ) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        write_varint(&mut self.output, variant_index as u64);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, BinaryFormatError> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, BinaryFormatError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, BinaryFormatError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize
+    ) -> Result<Self, BinaryFormatError> {
+        write_varint(&mut self.output, variant_index as u64);
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self, BinaryFormatError> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, BinaryFormatError> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize
+    ) -> Result<Self, BinaryFormatError> {
+        write_varint(&mut self.output, variant_index as u64);
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut Encoder {
+    type Ok = ();
+    type Error = BinaryFormatError;
+
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<(), BinaryFormatError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+}
+
+struct Decoder<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> Decoder<'de> {
+    fn read_byte(&mut self) -> Result<u8, BinaryFormatError> {
+        let (&byte, rest) = self.input.split_first().ok_or(BinaryFormatError::UnexpectedEnd)?;
+        self.input = rest;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'de [u8], BinaryFormatError> {
+        if self.input.len() < len {
+            return Err(BinaryFormatError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, BinaryFormatError> {
+        read_varint(&mut self.input)
+    }
+
+    fn read_unsigned<T>(&mut self) -> Result<T, BinaryFormatError>
+    where
+        T: TryFrom<u64>,
+    {
+        T::try_from(self.read_varint()?).map_err(|_| BinaryFormatError::IntegerOutOfRange)
+    }
+
+    fn read_signed<T>(&mut self) -> Result<T, BinaryFormatError>
+    where
+        T: TryFrom<i64>,
+    {
+        T::try_from(zigzag_decode(self.read_varint()?)).map_err(|_| BinaryFormatError::IntegerOutOfRange)
+    }
+
+    fn read_len(&mut self) -> Result<usize, BinaryFormatError> {
+        self.read_unsigned()
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Decoder<'de> {
+    type Error = BinaryFormatError;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        Err(BinaryFormatError::NotSelfDescribing)
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.read_byte()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(BinaryFormatError::InvalidValue("bool")),
+        }
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i8(self.read_signed()?)
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i16(self.read_signed()?)
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i32(self.read_signed()?)
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i64(self.read_signed()?)
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_u8(self.read_byte()?)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_u16(self.read_unsigned()?)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_u32(self.read_unsigned()?)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_u64(self.read_varint()?)
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        let bytes = self.read_bytes(4)?;
+        visitor.visit_f32(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        visitor.visit_f64(f64::from_le_bytes(bytes))
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        let c = char::from_u32(self.read_unsigned()?).ok_or(BinaryFormatError::InvalidValue("char"))?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_len()?;
+        let s = std::str::from_utf8(self.read_bytes(len)?).map_err(|_| BinaryFormatError::InvalidValue("UTF-8 string"))?;
+        visitor.visit_borrowed_str(s)
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_len()?;
+        visitor.visit_borrowed_bytes(self.read_bytes(len)?)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.read_byte()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            _ => Err(BinaryFormatError::InvalidValue("option")),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_len()?;
+        visitor.visit_seq(Elements { decoder: self, remaining: len })
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Elements { decoder: self, remaining: len })
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V
+    ) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Elements { decoder: self, remaining: len })
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_len()?;
+        visitor.visit_map(Elements { decoder: self, remaining: len })
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Elements { decoder: self, remaining: fields.len() })
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        Err(BinaryFormatError::NotSelfDescribing)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Access to a known number of consecutive elements of a sequence, tuple, struct or map.
+struct Elements<'a, 'de> {
+    decoder: &'a mut Decoder<'de>,
+    remaining: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = BinaryFormatError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, BinaryFormatError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.decoder).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Elements<'_, 'de> {
+    type Error = BinaryFormatError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, BinaryFormatError>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.decoder).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut *self.decoder)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Decoder<'de> {
+    type Error = BinaryFormatError;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), BinaryFormatError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant_index: u32 = self.read_unsigned()?;
+        let variant = seed.deserialize(variant_index.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Decoder<'de> {
+    type Error = BinaryFormatError;
+
+    fn unit_variant(self) -> Result<(), BinaryFormatError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, BinaryFormatError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Elements { decoder: self, remaining: len })
+    }
+
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value, BinaryFormatError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Elements { decoder: self, remaining: fields.len() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use serde::{Deserialize, Serialize};
+    use crate::global_state::binary_format::{from_bytes, read_varint, to_bytes, write_varint, BinaryFormatError};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Empty,
+        Circle(f32),
+        Segment(i16, i16),
+        Rect { width: u32, height: u32 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Sample {
+        name: String,
+        small: u8,
+        large: u64,
+        negative: i32,
+        flag: bool,
+        maybe: Option<char>,
+        shapes: Vec<Shape>,
+        map: BTreeMap<u16, (u8, String)>,
+        precise: f64,
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            let mut input = bytes.as_slice();
+            assert_eq!(read_varint(&mut input), Ok(value));
+            assert!(input.is_empty());
+        }
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, 127);
+        assert_eq!(bytes.len(), 1);
+        assert_eq!(read_varint(&mut [0xff; 10].as_slice()), Err(BinaryFormatError::VarintOverflow));
+        assert_eq!(read_varint(&mut [0x80].as_slice()), Err(BinaryFormatError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sample = Sample {
+            name: "ξ".into(),
+            small: 200,
+            large: 1 << 40,
+            negative: -70_000,
+            flag: true,
+            maybe: Some('x'),
+            shapes: vec![Shape::Empty, Shape::Circle(1.5), Shape::Segment(-1, 1), Shape::Rect { width: 3, height: 4 }],
+            map: BTreeMap::from([(1, (2, "a".into())), (500, (0, String::new()))]),
+            precise: -0.1,
+        };
+        let bytes = to_bytes(&sample).unwrap();
+        assert_eq!(from_bytes::<Sample>(&bytes).unwrap(), sample);
+
+        assert_eq!(from_bytes::<Sample>(&bytes[..bytes.len() - 1]).unwrap_err(), BinaryFormatError::UnexpectedEnd);
+        let mut extended_bytes = bytes.clone();
+        extended_bytes.push(0);
+        assert_eq!(from_bytes::<Sample>(&extended_bytes).unwrap_err(), BinaryFormatError::TrailingBytes(1));
+    }
+}
@@ -0,0 +1,245 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use screeps::raw_memory;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+use crate::config::GLOBAL_STATE_SEGMENTS;
+use crate::global_state::binary_format::{from_bytes, to_bytes, BinaryFormatError};
+
+/// Version of the format of the persisted data. The binary format is not self-describing, so it must
+/// be bumped on every change of the persisted types, including adding or removing a field, along
+/// with adding a migration from the previous version to `MIGRATIONS`.
+pub const PERSISTENCE_VERSION: u32 = 1;
+
+/// Converts the data encoded in the binary format from one version to the next one by decoding it
+/// into frozen copies of the old types, converting them and encoding the result. The copies must not
+/// reference the current persisted types, since those may change later.
+pub type Migration = fn(Vec<u8>) -> Result<Vec<u8>, PersistenceError>;
+
+/// Migrations of the persisted data along with the versions they convert from.
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+/// The beginning of the first segment, followed by the version and the number of segments.
+const HEADER_PREFIX: &str = "xi";
+/// The maximum length of the contents of a single segment.
+const MAX_SEGMENT_LENGTH: usize = 100 * 1024;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum PersistenceError {
+    #[error("the data does not fit in {0} segments")]
+    TooLarge(usize),
+    #[error("invalid header of the first segment")]
+    InvalidHeader,
+    #[error("segment {0} is missing")]
+    MissingSegment(usize),
+    #[error("no migration from version {0} to version {1}")]
+    UnsupportedVersion(u32, u32),
+    #[error("invalid base64 encoding")]
+    InvalidEncoding,
+    #[error("invalid binary data: {0}")]
+    BinaryFormat(#[from] BinaryFormatError),
+}
+
+/// The result of loading the persisted data.
+#[derive(Debug)]
+pub enum LoadedSegments<T> {
+    /// The segments are not active yet.
+    Unavailable,
+    /// Nothing was saved in the segments.
+    Empty,
+    Loaded(T),
+}
+
+/// Encodes the value into the contents of consecutive segments. The first one starts with a header
+/// with the version and the number of segments, followed by the value in the binary format encoded
+/// in base64 and split between the segments.
+pub fn encode_segments<T>(value: &T, version: u32, max_segments: usize) -> Result<Vec<String>, PersistenceError>
+where
+    T: Serialize,
+{
+    // TODO Keep in mind that base32768 is an option to fit almost 2x more in the segments.
+    let data = STANDARD.encode(to_bytes(value)?);
+    let header_len = header(version, 0).len();
+    let segments_count = (header_len + data.len()).div_ceil(MAX_SEGMENT_LENGTH);
+    if segments_count > max_segments {
+        return Err(PersistenceError::TooLarge(max_segments));
+    }
+
+    let contents = header(version, segments_count) + &data;
+    // Both the header and base64 are ASCII, so the chunks are valid strings.
+    Ok(contents
+        .as_bytes()
+        .chunks(MAX_SEGMENT_LENGTH)
+        .map(|chunk| chunk.iter().map(|&byte| byte as char).collect())
+        .collect())
+}
+
+/// The header with the number of segments padded so that its length does not depend on it.
+fn header(version: u32, segments_count: usize) -> String {
+    format!("{}{};{:02};", HEADER_PREFIX, version, segments_count)
+}
+
+/// Decodes the value from the contents of consecutive segments given by `segment`, applying the
+/// migrations if it was saved with an older version.
+pub fn decode_segments<T, F>(segment: F, version: u32, migrations: &[(u32, Migration)]) -> Result<T, PersistenceError>
+where
+    T: DeserializeOwned,
+    F: Fn(usize) -> Option<String>,
+{
+    let first_segment = segment(0).ok_or(PersistenceError::MissingSegment(0))?;
+    let mut header_parts = first_segment
+        .strip_prefix(HEADER_PREFIX)
+        .ok_or(PersistenceError::InvalidHeader)?
+        .splitn(3, ';');
+    let mut parse_header_part = || -> Result<usize, PersistenceError> {
+        header_parts
+            .next()
+            .and_then(|part| part.parse().ok())
+            .ok_or(PersistenceError::InvalidHeader)
+    };
+    let saved_version = parse_header_part()? as u32;
+    let segments_count = parse_header_part()?;
+    let mut data = header_parts.next().ok_or(PersistenceError::InvalidHeader)?.to_string();
+    for i in 1..segments_count {
+        data.push_str(&segment(i).ok_or(PersistenceError::MissingSegment(i))?);
+    }
+
+    let mut bytes = STANDARD.decode(data).map_err(|_| PersistenceError::InvalidEncoding)?;
+    if saved_version > version {
+        return Err(PersistenceError::UnsupportedVersion(saved_version, version));
+    }
+    for data_version in saved_version..version {
+        let migration = migrations
+            .iter()
+            .find_map(|&(from_version, migration)| (from_version == data_version).then_some(migration))
+            .ok_or(PersistenceError::UnsupportedVersion(saved_version, version))?;
+        bytes = migration(bytes)?;
+    }
+    Ok(from_bytes(&bytes)?)
+}
+
+/// Requests the segments with the persisted data to be available in the next tick.
+pub fn request_segments() {
+    raw_memory::set_active_segments(&GLOBAL_STATE_SEGMENTS);
+}
+
+/// Saves the value into the segments. Returns the total length of their contents.
+pub fn save_to_segments<T>(value: &T) -> Result<usize, PersistenceError>
+where
+    T: Serialize,
+{
+    let contents = encode_segments(value, PERSISTENCE_VERSION, GLOBAL_STATE_SEGMENTS.len())?;
+    let len = contents.iter().map(|segment_contents| segment_contents.len()).sum();
+    let segments = raw_memory::segments();
+    for (&segment_id, segment_contents) in GLOBAL_STATE_SEGMENTS.iter().zip(contents) {
+        segments.set(segment_id, segment_contents);
+    }
+    Ok(len)
+}
+
+/// Loads the value from the segments if they were activated by `request_segments`.
+pub fn load_from_segments<T>() -> Result<LoadedSegments<T>, PersistenceError>
+where
+    T: DeserializeOwned,
+{
+    let segments = raw_memory::segments();
+    match segments.get(GLOBAL_STATE_SEGMENTS[0]) {
+        None => Ok(LoadedSegments::Unavailable),
+        Some(first_segment) if first_segment.is_empty() => Ok(LoadedSegments::Empty),
+        Some(_) => {
+            let value = decode_segments(
+                |i| GLOBAL_STATE_SEGMENTS.get(i).and_then(|&segment_id| segments.get(segment_id)),
+                PERSISTENCE_VERSION,
+                MIGRATIONS
+            )?;
+            Ok(LoadedSegments::Loaded(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use crate::global_state::binary_format::{from_bytes, to_bytes};
+    use crate::global_state::persistence::{decode_segments, encode_segments, Migration, PersistenceError, MAX_SEGMENT_LENGTH};
+    use crate::room_planning::plan::Plan;
+    use crate::room_planning::room_planner::RoomPlanner;
+    use crate::room_planning::room_planner::tests::test_room_state;
+
+    fn decode<T>(segments: &[String], version: u32, migrations: &[(u32, Migration)]) -> Result<T, PersistenceError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        decode_segments(|i| segments.get(i).cloned(), version, migrations)
+    }
+
+    #[test]
+    fn test_plan_round_trip_within_size_budget() {
+        let room_state = test_room_state();
+        let mut planner = RoomPlanner::new(&room_state, true).unwrap();
+        let plan = (0..10).find_map(|_| planner.plan().ok()).unwrap();
+
+        let segments = encode_segments(&plan, 1, 10).unwrap();
+        assert_eq!(segments.len(), 1);
+        // The same plan takes about 17kB in JSON.
+        assert!(segments[0].len() < 2 * 1024, "the plan takes {} bytes", segments[0].len());
+
+        let decoded_plan: Plan = decode(&segments, 1, &[]).unwrap();
+        assert_eq!(decoded_plan.tiles.data, plan.tiles.data);
+        assert_eq!(decoded_plan.score, plan.score);
+        assert_eq!(decoded_plan.main_ramparts, plan.main_ramparts);
+        assert_eq!(decoded_plan.demolition_list, plan.demolition_list);
+    }
+
+    #[test]
+    fn test_large_data_split_between_segments() {
+        let data = (0..150_000u32).collect::<Vec<_>>();
+        let segments = encode_segments(&data, 1, 10).unwrap();
+        assert!(segments.len() > 1);
+        assert!(segments.iter().all(|segment| segment.len() <= MAX_SEGMENT_LENGTH));
+        assert_eq!(decode::<Vec<u32>>(&segments, 1, &[]).unwrap(), data);
+
+        assert_eq!(decode::<Vec<u32>>(&segments[..1], 1, &[]), Err(PersistenceError::MissingSegment(1)));
+        assert_eq!(encode_segments(&data, 1, 1), Err(PersistenceError::TooLarge(1)));
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct StateV1 {
+        energy: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct StateV2 {
+        energy: u32,
+        minerals: u32,
+    }
+
+    fn migrate_v1_to_v2(bytes: Vec<u8>) -> Result<Vec<u8>, PersistenceError> {
+        let state: StateV1 = from_bytes(&bytes)?;
+        Ok(to_bytes(&StateV2 {
+            energy: state.energy,
+            minerals: 0,
+        })?)
+    }
+
+    #[test]
+    fn test_version_bump() {
+        let segments = encode_segments(&StateV1 { energy: 1000 }, 1, 10).unwrap();
+        let migrations: [(u32, Migration); 1] = [(1, migrate_v1_to_v2)];
+        assert_eq!(
+            decode::<StateV2>(&segments, 2, &migrations),
+            Ok(StateV2 {
+                energy: 1000,
+                minerals: 0
+            })
+        );
+
+        // Without a migration, or when saved by a newer version, the data is discarded.
+        assert_eq!(decode::<StateV2>(&segments, 2, &[]), Err(PersistenceError::UnsupportedVersion(1, 2)));
+        let segments = encode_segments(&StateV2 { energy: 1000, minerals: 10 }, 3, 10).unwrap();
+        assert_eq!(decode::<StateV2>(&segments, 2, &migrations), Err(PersistenceError::UnsupportedVersion(3, 2)));
+
+        assert_eq!(decode::<StateV2>(&["{}".to_string()], 2, &migrations), Err(PersistenceError::InvalidHeader));
+    }
+}
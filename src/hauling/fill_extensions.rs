@@ -0,0 +1,178 @@
+use std::cmp::min;
+use std::iter::once;
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::{ResourceType, RoomName, RoomXY};
+use screeps::StructureType::Road;
+use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::shortest_path_by_distance_matrix::{closest_in_circle_by_matrix, shortest_path_by_distance_matrix};
+use crate::consts::UNREACHABLE_COST;
+use crate::creeps::actions::transfer_when_able;
+use crate::creeps::creeps::CreepRef;
+use crate::errors::XiError;
+use crate::geometry::rect::room_rect;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::haul_resources::hauler_travel_spec;
+use crate::hauling::requests::ReservedHaulRequest;
+use crate::hauling::transfers::get_free_capacity_unchecked;
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::room_states::room_states::with_room_state;
+use crate::travel::travel::travel;
+use crate::{local_debug, u};
+
+const DEBUG: bool = true;
+
+/// Fills the extensions in the room with the energy reserved in the request, visiting them along
+/// the planned roads in the order given by `extension_fill_order`. Finishes when the reserved
+/// energy is deposited or there are no more unfilled extensions. The unused reservation is released
+/// when the request is dropped.
+pub async fn fill_extensions(creep_ref: &CreepRef, room_name: RoomName, request: &mut ReservedHaulRequest) -> Result<(), XiError> {
+    let (road_xys, unfilled_extensions) = u!(with_room_state(room_name, |room_state| {
+        let road_xys = room_state
+            .plan
+            .as_ref()
+            .map(|plan| plan.tiles.find_structure_xys(Road))
+            .unwrap_or_default();
+        let unfilled_extensions = room_state
+            .extensions
+            .iter()
+            .filter(|extension| extension.missing_energy > 0)
+            .map(|extension| (extension.xy, extension.id))
+            .collect::<FxHashMap<_, _>>();
+        (road_xys, unfilled_extensions)
+    }));
+
+    let start_xy = creep_ref.borrow().travel_state.pos.xy();
+    let unfilled_xys = unfilled_extensions.keys().copied().collect::<Vec<_>>();
+
+    for xy in extension_fill_order(road_xys.into_iter(), start_xy, &unfilled_xys) {
+        if request.amount == 0 {
+            break;
+        }
+
+        let id = unfilled_extensions[&xy];
+        // The extension may have been filled by someone else in the meantime.
+        let missing_energy = get_free_capacity_unchecked(id.into(), Some(ResourceType::Energy), AfterAllTransfers).unwrap_or(0);
+        let carried_energy = creep_ref.borrow_mut().used_capacity(Some(ResourceType::Energy), AfterAllTransfers)?;
+        let amount = min(min(missing_energy, carried_energy), request.amount);
+        if amount == 0 {
+            if carried_energy == 0 {
+                break;
+            }
+            continue;
+        }
+
+        travel(creep_ref, hauler_travel_spec(xy.to_pos(room_name))).await?;
+        local_debug!("{} filling extension at {} with {} energy.", creep_ref.borrow().name, xy, amount);
+        match transfer_when_able(creep_ref, id.into(), ResourceType::Energy, amount, false).await {
+            // Filled by someone else in the same tick. There are other extensions to fill.
+            Err(XiError::CreepTransferTargetFull) => continue,
+            result => result?,
+        }
+        request.complete_partially(amount);
+    }
+
+    Ok(())
+}
+
+/// Computes the order in which to fill extensions at given positions when travelling along given
+/// roads from the start. Repeatedly heads to the closest extension by the roads while filling all
+/// extensions next to the tiles on the way. Extensions that cannot be reached by the roads go last,
+/// ordered by their range from the start.
+pub fn extension_fill_order<R>(road_xys: R, start: RoomXY, extension_xys: &[RoomXY]) -> Vec<RoomXY>
+where
+    R: Iterator<Item = RoomXY>,
+{
+    let road_xys = road_xys.collect::<FxHashSet<_>>();
+    let obstacles = room_rect()
+        .iter()
+        .filter(|xy| !road_xys.contains(xy))
+        .collect::<Vec<_>>();
+
+    let mut remaining_xys = extension_xys.to_vec();
+    let mut order = Vec::new();
+    let mut current = start;
+
+    while !remaining_xys.is_empty() {
+        let dm = distance_matrix(obstacles.iter().copied(), once(current));
+        let (stop_xy, dist) = u!(remaining_xys
+            .iter()
+            .map(|&xy| closest_in_circle_by_matrix(&dm, xy, 1))
+            .min_by_key(|&(_, dist)| dist));
+        if dist >= UNREACHABLE_COST {
+            break;
+        }
+
+        let mut path = shortest_path_by_distance_matrix(&dm, stop_xy, 0);
+        path.reverse();
+        for path_xy in path {
+            remaining_xys.retain(|&xy| {
+                let next_to_path = xy.dist(path_xy) <= 1;
+                if next_to_path {
+                    order.push(xy);
+                }
+                !next_to_path
+            });
+        }
+
+        current = stop_xy;
+    }
+
+    remaining_xys.sort_by_key(|&xy| xy.dist(start));
+    order.extend(remaining_xys);
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::RoomXY;
+    use crate::hauling::fill_extensions::extension_fill_order;
+
+    #[test]
+    fn test_extension_fill_order_along_road_loop() {
+        let xy = |x: u8, y: u8| -> RoomXY { (x, y).try_into().unwrap() };
+
+        // A square road loop with corners at (10, 10) and (20, 20).
+        let mut road_xys = Vec::new();
+        for i in 10..=20 {
+            road_xys.extend([xy(i, 10), xy(20, i), xy(i, 20), xy(10, i)]);
+        }
+
+        // Ten extensions outside of the loop, in the clockwise order.
+        let loop_extension_xys = [
+            xy(12, 9),
+            xy(16, 9),
+            xy(21, 12),
+            xy(21, 16),
+            xy(21, 19),
+            xy(18, 21),
+            xy(14, 21),
+            xy(9, 18),
+            xy(9, 15),
+            xy(9, 11),
+        ];
+        // Shuffling them so that the input order does not matter.
+        let mut extension_xys = loop_extension_xys.to_vec();
+        extension_xys.reverse();
+        extension_xys.rotate_left(3);
+
+        let order = extension_fill_order(road_xys.into_iter(), xy(11, 10), &extension_xys);
+        assert_eq!(order.len(), loop_extension_xys.len());
+        // The extensions are visited monotonically along the loop in one direction or the other.
+        let expected_clockwise = loop_extension_xys.to_vec();
+        let mut expected_counterclockwise = loop_extension_xys.to_vec();
+        expected_counterclockwise[1..].reverse();
+        assert!(
+            order == expected_clockwise || order == expected_counterclockwise,
+            "Unexpected order {:?}.",
+            order
+        );
+    }
+
+    #[test]
+    fn test_unreachable_extensions_filled_last() {
+        let xy = |x: u8, y: u8| -> RoomXY { (x, y).try_into().unwrap() };
+        let road_xys = (10..=20).map(|x| xy(x, 10));
+        let order = extension_fill_order(road_xys, xy(10, 10), &[xy(30, 30), xy(15, 11), xy(25, 25), xy(19, 9)]);
+        assert_eq!(order, vec![xy(15, 11), xy(19, 9), xy(25, 25), xy(30, 30)]);
+    }
+}
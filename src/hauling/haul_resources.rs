@@ -1,38 +1,57 @@
 use std::cell::{Cell, RefCell};
+use std::iter::once;
 use std::rc::Rc;
 use crate::creeps::creeps::CreepRef;
 use crate::errors::XiError;
 use crate::kernel::sleep::sleep;
 use crate::priorities::HAULER_SPAWN_PRIORITY;
 use crate::room_states::room_states::with_room_state;
+use crate::travel::park::park;
 use crate::travel::travel::travel;
 use crate::u;
 use log::{debug, warn};
 use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::StructureType::Storage;
-use screeps::{Creep, ObjectId, Position, RoomName};
+use screeps::{game, Creep, ObjectId, Position, ResourceType, RoomName};
 use crate::creeps::actions::{pickup_when_able, transfer_when_able, withdraw_when_able};
 use crate::creeps::creep_body::CreepBody;
 use crate::creeps::creep_role::CreepRole::Hauler;
-use crate::hauling::requests::HaulRequestTargetKind::PickupTarget;
-use crate::hauling::requests::with_haul_requests;
-use crate::hauling::reserving_requests::{find_haul_requests, ReservedRequests};
-use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::hauling::fill_extensions::fill_extensions;
+use crate::hauling::requests::HaulRequestTargetKind::{ExtensionsTarget, PickupTarget};
+use crate::hauling::hauler_routes::{set_hauler_route, with_hauler_routes, HaulerRoute};
+use crate::hauling::requests::{with_haul_requests, ReservedHaulRequest};
+use crate::hauling::scheduling_hauls::sweep_haul_requests;
+use crate::hauling::store_anywhere_or_drop::store_anywhere_or_drop;
+use crate::hauling::reserving_requests::{assign_haul_requests, find_haul_requests, HaulerData, ReservedRequests};
+use crate::hauling::transfers::get_free_capacity_unchecked;
+use crate::hauling::haul_stats::register_hauler_tick;
+use crate::hauling::transfers::TransferStage::{AfterAllTransfers, BeforeAnyTransfers};
 use crate::kernel::wait_until_some::wait_until_some;
 use crate::spawning::preferred_spawn::best_spawns;
 use crate::spawning::spawn_pool::{SpawnPool, SpawnPoolOptions};
 use crate::spawning::spawn_schedule::SpawnRequest;
 use crate::travel::travel_spec::TravelSpec;
 use crate::utils::priority::Priority;
+use crate::utils::game_tick::game_tick;
+use crate::utils::get_object_by_id::erased_object_by_id;
 use crate::utils::result_utils::ResultUtils;
 use crate::utils::sampling::is_sample_tick;
 
 const DEBUG: bool = true;
 
+/// Number of ticks between dropping expired haul requests and ones with targets that no longer exist.
+const HAUL_REQUESTS_SWEEP_INTERVAL: u32 = 10;
+
+/// Number of ticks between assigning haul requests to all idle haulers at once.
+const HAUL_ASSIGNMENT_INTERVAL: u32 = 3;
+
 #[derive(Debug)]
 struct HaulerStats {
     carry_capacity: u32,
     used_capacity: Rc<Cell<u32>>,
+    /// Position and the amount carried in the previous tick to measure the distance traveled.
+    last_pos: Option<Position>,
+    last_carried_amount: u32,
 }
 
 /// Execute hauling of resources of haulers assigned to given room.
@@ -54,6 +73,7 @@ pub async fn haul_resources(room_name: RoomName) {
             priority: HAULER_SPAWN_PRIORITY,
             preferred_spawns,
             tick: (0, 0),
+            home_room: None,
         }
     }));
 
@@ -62,6 +82,9 @@ pub async fn haul_resources(room_name: RoomName) {
     
     // A map of hauler capacities and non-idle capacities.
     let hauler_stats: Rc<RefCell<FxHashMap<ObjectId<Creep>, HaulerStats>>> = Rc::new(RefCell::new(FxHashMap::default()));
+    // Haulers waiting for haul requests and the requests assigned to them in the last assignment.
+    let idle_haulers: Rc<RefCell<FxHashMap<ObjectId<Creep>, HaulerData>>> = Rc::new(RefCell::new(FxHashMap::default()));
+    let assignments: Rc<RefCell<FxHashMap<ObjectId<Creep>, Option<ReservedRequests>>>> = Rc::new(RefCell::new(FxHashMap::default()));
     
     loop {
         let (haulers_required, hauler_body, hauler_spawn_priority) = wait_until_some(|| with_room_state(room_name, |room_state| {
@@ -76,25 +99,12 @@ pub async fn haul_resources(room_name: RoomName) {
         spawn_pool.base_spawn_request.body = hauler_body;
         spawn_pool.base_spawn_request.priority = hauler_spawn_priority;
         
-        /* TODO This should not be needed. For now, let it break.
-        with_haul_requests(room_name, |haul_requests| {
-            haul_requests.withdraw_requests.retain(|_, request| {
-                if request.borrow().kind != PickupRequest {
-                    // TODO Not checking every single withdraw request as change in structures can
-                    //      handle the rest.
-                    true
-                } else if erased_object_by_id(request.borrow().target).is_err() {
-                    // Setting the request to not require any more resources.
-                    // This combined with removing it from the map is exactly what cancelling
-                    // request does.
-                    request.borrow_mut().amount = 0;
-                    false
-                } else {
-                    true
-                }
+        if game_tick().is_multiple_of(HAUL_REQUESTS_SWEEP_INTERVAL) {
+            // Targets in rooms that are not visible cannot be checked.
+            sweep_haul_requests(room_name, |request| {
+                game::rooms().get(request.pos.room_name()).is_none() || erased_object_by_id(&request.target).is_ok()
             });
-        });
-        */
+        }
         
         with_haul_requests(room_name, |haul_requests| {
             debug!("Available withdraw requests:");
@@ -114,8 +124,14 @@ pub async fn haul_resources(room_name: RoomName) {
             hauler_stats.borrow_mut().insert(creep_id, HaulerStats {
                 carry_capacity,
                 used_capacity: used_capacity.clone(),
+                last_pos: None,
+                last_carried_amount: 0,
             });
+            let idle_haulers = idle_haulers.clone();
+            let assignments = assignments.clone();
             async move {
+                let mut reassign_immediately = false;
+                let mut idle_ticks = 0;
                 loop {
                     let store = u!(creep_ref.borrow_mut().used_capacities(AfterAllTransfers));
                     let pos = creep_ref.borrow_mut().travel_state.pos;
@@ -126,30 +142,70 @@ pub async fn haul_resources(room_name: RoomName) {
                         creep_ref.borrow().name
                     );
 
-                    let reserved_requests = find_haul_requests(
-                        room_name,
-                        &store,
-                        pos,
-                        carry_capacity,
-                        ttl
-                    );
+                    let reserved_requests = if reassign_immediately {
+                        // Not waiting for the next assignment to find a new target for the load.
+                        reassign_immediately = false;
+                        find_haul_requests(
+                            room_name,
+                            &store,
+                            pos,
+                            carry_capacity,
+                            ttl
+                        )
+                    } else {
+                        idle_haulers.borrow_mut().insert(creep_id, HaulerData {
+                            store: store.clone(),
+                            pos,
+                            capacity: carry_capacity,
+                            ttl,
+                        });
+                        wait_until_some(|| assignments.borrow_mut().remove(&creep_id)).await
+                    };
 
                     if let Some(reserved_requests) = reserved_requests {
-                        let result = fulfill_requests(&creep_ref, reserved_requests, used_capacity.clone()).await;
+                        idle_ticks = 0;
+                        let result = fulfill_requests(&creep_ref, room_name, reserved_requests, used_capacity.clone()).await;
                         used_capacity.set(0);
+                        set_hauler_route(room_name, &creep_ref.borrow().name, Vec::new());
 
-                        if let Err(e) = result {
-                            debug!("Error when hauling: {:?}.", e);
-                            sleep(1).await;
+                        match result {
+                            Err(XiError::CreepTransferTargetFull) => {
+                                // The target was filled by someone else. The hauler still has its
+                                // load and immediately looks for another place to deposit it.
+                                debug!(
+                                    "{} found its deposit target full and is looking for another one.",
+                                    creep_ref.borrow().name
+                                );
+                                reassign_immediately = true;
+                            }
+                            Err(e) => {
+                                debug!("Error when hauling: {:?}.", e);
+                                sleep(1).await;
+                            }
+                            Ok(()) => (),
                         }
+                    } else if store.keys().any(|&resource_type| resource_type != ResourceType::Energy) {
+                        // Leftovers other than energy cannot wait for a request as energy can, so
+                        // they are put in the storage.
+                        debug!(
+                            "{} has no request for its load and is storing it.",
+                            creep_ref.borrow().name
+                        );
+                        store_anywhere_or_drop(&creep_ref, room_name)
+                            .await
+                            .warn_if_err("Error while storing leftover resources");
                     } else {
-                        // There is nothing to haul. The creep is idle.
+                        // There is nothing to haul. The creep is idle and gets out of the way
+                        // if it stays so.
+                        idle_ticks += 1;
                         with_room_state(room_name, |room_state| {
                             if let Some(eco_stats) = room_state.eco_stats.as_mut() {
                                 eco_stats.register_idle_creep(Hauler, &creep_ref);
                             }
+                            if idle_ticks > 1 {
+                                park(&creep_ref, room_state);
+                            }
                         });
-                        sleep(1).await;
                     }
                 }
             }
@@ -159,24 +215,48 @@ pub async fn haul_resources(room_name: RoomName) {
         let mut total_carry_capacity = 0;
         
         let mut alive_creeps_id = FxHashSet::default();
+        let mut alive_creep_names = FxHashSet::default();
 
         spawn_pool.for_each_creep(|creep_ref| {
-            // TODO Update eco_stats.hauled_resources and eco_stats.total_haul_capacity.
-            // Maybe keep a map hauler -> used capacity and use this used capacity for that when not idle?
-            
             // The creep may be dead.
             let maybe_creep_id = creep_ref.borrow_mut().screeps_id();
             if let Ok(creep_id) = maybe_creep_id {
                 alive_creeps_id.insert(creep_id);
+                alive_creep_names.insert(creep_ref.borrow().name.clone());
                 let mut borrowed_hauler_stats = hauler_stats.borrow_mut();
                 // `with_spawned_creeps` has already run, so the creep's record is initialized.
                 let hauler_stats = u!(borrowed_hauler_stats.get_mut(&creep_id));
                 total_carry_capacity += hauler_stats.carry_capacity;
                 total_used_capacity += hauler_stats.used_capacity.get();
+
+                let pos = creep_ref.borrow().travel_state.pos;
+                let carried_amount = creep_ref.borrow_mut().used_capacity(None, BeforeAnyTransfers).unwrap_or(0);
+                let distance = hauler_stats.last_pos.map_or(0, |last_pos| last_pos.get_range_to(pos));
+                // The distance to the current position was traveled with what was carried before.
+                register_hauler_tick(
+                    room_name,
+                    distance,
+                    hauler_stats.last_carried_amount,
+                    idle_haulers.borrow().contains_key(&creep_id)
+                );
+                hauler_stats.last_pos = Some(pos);
+                hauler_stats.last_carried_amount = carried_amount;
             }
         });
         
         hauler_stats.borrow_mut().retain(|creep_id, _| alive_creeps_id.contains(&creep_id));
+        idle_haulers.borrow_mut().retain(|creep_id, _| alive_creeps_id.contains(creep_id));
+        // Dropping the requests assigned to dead haulers releases their reservations.
+        assignments.borrow_mut().retain(|creep_id, _| alive_creeps_id.contains(creep_id));
+        with_hauler_routes(room_name, |routes| routes.retain(|creep_name, _| alive_creep_names.contains(creep_name)));
+
+        if game_tick().is_multiple_of(HAUL_ASSIGNMENT_INTERVAL) && !idle_haulers.borrow().is_empty() {
+            // Assigning all idle haulers at once so that the closest requests are not taken by
+            // whichever hauler asks first.
+            let (creep_ids, haulers): (Vec<_>, Vec<_>) = idle_haulers.borrow_mut().drain().unzip();
+            let reserved_requests = assign_haul_requests(room_name, &haulers);
+            assignments.borrow_mut().extend(creep_ids.into_iter().zip(reserved_requests));
+        }
 
         with_room_state(room_name, |room_state| {
             if let Some(eco_stats) = room_state.eco_stats.as_mut() {
@@ -198,12 +278,24 @@ pub async fn haul_resources(room_name: RoomName) {
     }
 }
 
-/// First completes all withdraw requests and then all deposit requests. Registers `used_capacity`
-/// when performing the deposit request.
+/// First completes all withdraw requests and then all deposit requests, each in the order they were
+/// chosen. Registers `used_capacity` when performing the deposit requests. The withdraw leg is skipped when there are no withdraw
+/// requests, i.e., when the hauler is already carrying the resources.
+/// When the deposit target turns out to be full, the reservation is released and
+/// `XiError::CreepTransferTargetFull` is returned so that the hauler can be reassigned with its
+/// load.
 // TODO Still register it in the last tick.
-async fn fulfill_requests(creep_ref: &CreepRef, mut reserved_requests: ReservedRequests, used_capacity: Rc<Cell<u32>>) -> Result<(), XiError> {
-    // TODO This only works for singleton withdraw and store requests.
-    if let Some(mut withdraw_request) = reserved_requests.withdraw_requests.pop() {
+async fn fulfill_requests(
+    creep_ref: &CreepRef,
+    room_name: RoomName,
+    mut reserved_requests: ReservedRequests,
+    used_capacity: Rc<Cell<u32>>
+) -> Result<(), XiError> {
+    reserved_requests.withdraw_requests.reverse();
+    reserved_requests.deposit_requests.reverse();
+
+    while let Some(mut withdraw_request) = reserved_requests.withdraw_requests.pop() {
+        set_hauler_route(room_name, &creep_ref.borrow().name, remaining_route(&withdraw_request, &reserved_requests));
         let withdraw_travel_spec = hauler_travel_spec(withdraw_request.request.borrow().pos);
 
         let result: Result<(), XiError> = async {
@@ -239,12 +331,18 @@ async fn fulfill_requests(creep_ref: &CreepRef, mut reserved_requests: ReservedR
         }
     }
 
-    if let Some(mut store_request) = reserved_requests.deposit_requests.pop() {
+    while let Some(mut store_request) = reserved_requests.deposit_requests.pop() {
+        set_hauler_route(room_name, &creep_ref.borrow().name, remaining_route(&store_request, &reserved_requests));
         let store_travel_spec = hauler_travel_spec(store_request.request.borrow().pos);
 
         used_capacity.set(creep_ref.borrow_mut().used_capacity(None, AfterAllTransfers)?);
 
         let result = async {
+            if store_request.request.borrow().target_kind == ExtensionsTarget {
+                let room_name = store_request.request.borrow().pos.room_name();
+                return fill_extensions(creep_ref, room_name, &mut store_request).await;
+            }
+
             // Creep may die on the way.
             travel(creep_ref, store_travel_spec).await?;
             let target = store_request.request.borrow().target;
@@ -262,8 +360,22 @@ async fn fulfill_requests(creep_ref: &CreepRef, mut reserved_requests: ReservedR
             Ok(())
         }.await;
         
-        if result.is_err() {
-            reserved_requests.deposit_requests.push(store_request);
+        match result {
+            Err(XiError::CreepTransferTargetFull) => {
+                // Another hauler or process has filled the target in the meantime. Releasing the
+                // reservation and correcting the request's amount to what actually fits in there.
+                let free_capacity = get_free_capacity_unchecked(
+                    store_request.request.borrow().target,
+                    Some(store_request.request.borrow().resource_type),
+                    AfterAllTransfers
+                ).unwrap_or(0);
+                store_request.release_target_full(free_capacity);
+                return result;
+            }
+            Err(_) => {
+                reserved_requests.deposit_requests.push(store_request);
+            }
+            Ok(()) => (),
         }
         
         match result {
@@ -279,12 +391,33 @@ async fn fulfill_requests(creep_ref: &CreepRef, mut reserved_requests: ReservedR
             }
             _ => (),
         }
+
+        if result.is_err() {
+            // The remaining requests are released when dropped.
+            break;
+        }
     }
 
     Ok(())
 }
 
-fn hauler_travel_spec(target: Position) -> TravelSpec {
+/// Positions of the targets of the current request followed by the remaining ones, in the order they
+/// are fulfilled in `fulfill_requests`.
+fn remaining_route(
+    current_request: &ReservedHaulRequest,
+    reserved_requests: &ReservedRequests
+) -> HaulerRoute {
+    once(current_request)
+        .chain(reserved_requests.withdraw_requests.iter().rev())
+        .chain(reserved_requests.deposit_requests.iter().rev())
+        .map(|reserved_request| {
+            let request = reserved_request.request.borrow();
+            (request.kind, request.pos)
+        })
+        .collect()
+}
+
+pub(super) fn hauler_travel_spec(target: Position) -> TravelSpec {
     TravelSpec {
         target,
         range: 1,
@@ -1,6 +1,80 @@
+use std::cell::RefCell;
+use enum_iterator::all;
+use rustc_hash::FxHashMap;
 use screeps::RoomName;
 use crate::utils::avg_vector::AvgVector;
-use crate::hauling::requests::{with_haul_requests, HaulRequestKind, HaulRequestTargetKind};
+use crate::hauling::requests::{with_haul_requests, HaulFlow, HaulRequestKind, HaulRequestTargetKind};
+use crate::utils::sampling::{SAMPLE_INTERVAL, SMALL_SAMPLE_SIZE};
+
+/// The realized hauler throughput is only used once there are this many samples of it.
+const MIN_HAULER_ACTIVITY_SAMPLES: usize = SMALL_SAMPLE_SIZE;
+
+thread_local! {
+    /// Amounts hauled since the last sample by the haulers of each room, per flow.
+    static HAULED_AMOUNTS: RefCell<FxHashMap<RoomName, FxHashMap<HaulFlow, u32>>> = RefCell::new(FxHashMap::default());
+    /// Activity of the haulers of each room since the last sample.
+    static HAULER_ACTIVITIES: RefCell<FxHashMap<RoomName, HaulerActivity>> = RefCell::new(FxHashMap::default());
+}
+
+/// What the haulers of a room were doing, summed over the haulers and ticks.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct HaulerActivity {
+    /// Amount of resources deposited by the haulers.
+    pub delivered_amount: u32,
+    /// Tiles traveled while carrying something.
+    pub loaded_distance: u32,
+    /// Tiles traveled while empty.
+    pub empty_distance: u32,
+    /// Tiles traveled times the amount of resources carried while traveling them.
+    pub carried_amount_distance: u32,
+    /// Ticks in which the haulers were waiting for a haul request.
+    pub idle_ticks: u32,
+    /// Ticks in which the haulers were alive, idle or not.
+    pub hauler_ticks: u32,
+}
+
+/// Registers the amount transferred when completing a haul request of given flow.
+pub fn register_hauled_amount(room_name: RoomName, flow: HaulFlow, amount: u32) {
+    HAULED_AMOUNTS.with(|hauled_amounts| {
+        *hauled_amounts
+            .borrow_mut()
+            .entry(room_name)
+            .or_default()
+            .entry(flow)
+            .or_default() += amount;
+    });
+}
+
+/// Registers a tick of a hauler's life, in which it moved given distance carrying given amount of
+/// resources or was idle.
+pub fn register_hauler_tick(room_name: RoomName, distance: u32, carried_amount: u32, idle: bool) {
+    with_hauler_activity(room_name, |activity| {
+        if carried_amount > 0 {
+            activity.loaded_distance += distance;
+        } else {
+            activity.empty_distance += distance;
+        }
+        activity.carried_amount_distance += distance * carried_amount;
+        activity.idle_ticks += idle as u32;
+        activity.hauler_ticks += 1;
+    });
+}
+
+/// Registers the amount deposited by a hauler.
+pub fn register_delivered_amount(room_name: RoomName, amount: u32) {
+    with_hauler_activity(room_name, |activity| {
+        activity.delivered_amount += amount;
+    });
+}
+
+fn with_hauler_activity<F>(room_name: RoomName, f: F)
+where
+    F: FnOnce(&mut HaulerActivity),
+{
+    HAULER_ACTIVITIES.with(|activities| {
+        f(activities.borrow_mut().entry(room_name).or_default());
+    });
+}
 
 #[derive(Debug, Default)]
 pub struct HaulStats {
@@ -12,6 +86,22 @@ pub struct HaulStats {
     pub withdrawable_storage_amount: AvgVector<u32>,
     /// Total amount of free space in the storages in the room.
     pub depositable_storage_amount: AvgVector<u32>,
+    /// Amount of resources hauled per tick, per flow. Each haul counts once for withdrawing and
+    /// once for depositing, e.g., hauling from a source to the storage counts as both income and
+    /// balancing.
+    pub hauled_amount_by_flow: FxHashMap<HaulFlow, AvgVector<u32>>,
+    /// Amount of resources deposited by the haulers per sample.
+    pub delivered_amount: AvgVector<u32>,
+    /// Tiles traveled by the haulers while carrying something per sample.
+    pub loaded_distance: AvgVector<u32>,
+    /// Tiles traveled by the haulers while empty per sample.
+    pub empty_distance: AvgVector<u32>,
+    /// Tiles traveled by the haulers times the amount carried per sample.
+    pub carried_amount_distance: AvgVector<u32>,
+    /// Ticks the haulers were idle per sample.
+    pub idle_ticks: AvgVector<u32>,
+    /// Ticks the haulers were alive per sample.
+    pub hauler_ticks: AvgVector<u32>,
 }
 
 impl HaulStats {
@@ -29,5 +119,95 @@ impl HaulStats {
             self.withdrawable_storage_amount.push(amounts[0][1]);
             self.depositable_storage_amount.push(amounts[1][1]);
         });
+
+        self.add_hauled_amounts_sample(room_name);
+        self.add_hauler_activity_sample(room_name);
+    }
+
+    fn add_hauler_activity_sample(&mut self, room_name: RoomName) {
+        let activity = HAULER_ACTIVITIES.with(|activities| {
+            activities.borrow_mut().remove(&room_name).unwrap_or_default()
+        });
+        self.push_hauler_activity(activity);
+    }
+
+    pub fn push_hauler_activity(&mut self, activity: HaulerActivity) {
+        self.delivered_amount.push(activity.delivered_amount);
+        self.loaded_distance.push(activity.loaded_distance);
+        self.empty_distance.push(activity.empty_distance);
+        self.carried_amount_distance.push(activity.carried_amount_distance);
+        self.idle_ticks.push(activity.idle_ticks);
+        self.hauler_ticks.push(activity.hauler_ticks);
+    }
+
+    /// Fraction of the recent ticks in which the haulers were idle.
+    pub fn idle_ratio(&self) -> f32 {
+        if self.hauler_ticks.small_sample_sum == 0 {
+            0.0
+        } else {
+            self.idle_ticks.small_sample_sum as f32 / self.hauler_ticks.small_sample_sum as f32
+        }
+    }
+
+    /// Hauling throughput recently realized by a single non-idle hauler, in the same units as
+    /// the calculated hauling throughput, i.e., the amount of resources times the distance of
+    /// the round trip per tick. It accounts for partial loads, obstacles and fatigue.
+    /// `None` until there are enough samples.
+    pub fn realized_hauler_throughput(&self) -> Option<f32> {
+        let busy_ticks = self.hauler_ticks.small_sample_sum - self.idle_ticks.small_sample_sum;
+        (self.hauler_ticks.samples >= MIN_HAULER_ACTIVITY_SAMPLES && busy_ticks > 0).then(|| {
+            // Each resource carried over a distance also requires the hauler to come back empty.
+            2.0 * self.carried_amount_distance.small_sample_sum as f32 / busy_ticks as f32
+        })
+    }
+
+    fn add_hauled_amounts_sample(&mut self, room_name: RoomName) {
+        let hauled_amounts = HAULED_AMOUNTS.with(|hauled_amounts| {
+            hauled_amounts.borrow_mut().remove(&room_name).unwrap_or_default()
+        });
+        for flow in all::<HaulFlow>() {
+            self.hauled_amount_by_flow.entry(flow).or_default().push(
+                hauled_amounts
+                    .get(&flow)
+                    .map_or(0, |&amount| amount / SAMPLE_INTERVAL)
+            );
+        }
+    }
+
+    /// Average amount of resources hauled per tick in given flow.
+    pub fn hauled_amount(&self, flow: HaulFlow) -> f32 {
+        self.hauled_amount_by_flow
+            .get(&flow)
+            .map_or(0.0, |amounts| amounts.small_sample_avg::<f32>())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hauling::haul_stats::{register_hauled_amount, HaulStats};
+    use crate::hauling::requests::HaulFlow::{Balancing, Consumption, Income, Loot, Refill};
+    use crate::room_states::room_state::test_empty_unowned_room_name;
+    use crate::utils::sampling::SAMPLE_INTERVAL;
+
+    #[test]
+    fn test_hauled_amounts_aggregated_per_flow() {
+        let room_name = test_empty_unowned_room_name();
+        let mut haul_stats = HaulStats::default();
+
+        register_hauled_amount(room_name, Income, 50 * SAMPLE_INTERVAL);
+        register_hauled_amount(room_name, Income, 30 * SAMPLE_INTERVAL);
+        register_hauled_amount(room_name, Consumption, 20 * SAMPLE_INTERVAL);
+        register_hauled_amount(room_name, Refill, 10 * SAMPLE_INTERVAL);
+        haul_stats.add_hauled_amounts_sample(room_name);
+
+        assert_eq!(haul_stats.hauled_amount_by_flow[&Income].last(), 80);
+        assert_eq!(haul_stats.hauled_amount_by_flow[&Consumption].last(), 20);
+        assert_eq!(haul_stats.hauled_amount_by_flow[&Refill].last(), 10);
+        assert_eq!(haul_stats.hauled_amount_by_flow[&Loot].last(), 0);
+        assert_eq!(haul_stats.hauled_amount_by_flow[&Balancing].last(), 0);
+
+        // The amounts are reset after each sample.
+        haul_stats.add_hauled_amounts_sample(room_name);
+        assert_eq!(haul_stats.hauled_amount_by_flow[&Income].last(), 0);
+    }
+}
@@ -0,0 +1,32 @@
+use std::cell::RefCell;
+use rustc_hash::FxHashMap;
+use screeps::{Position, RoomName};
+use crate::hauling::requests::HaulRequestKind;
+
+/// The kinds and positions of the remaining targets of a hauler, withdraw ones followed by deposit
+/// ones.
+pub type HaulerRoute = Vec<(HaulRequestKind, Position)>;
+
+thread_local! {
+    /// Routes of the haulers by their names, by the rooms of their hauling processes. Used only for
+    /// visualization.
+    static HAULER_ROUTES: RefCell<FxHashMap<RoomName, FxHashMap<String, HaulerRoute>>> = RefCell::new(FxHashMap::default());
+}
+
+pub fn with_hauler_routes<F, R>(room_name: RoomName, f: F) -> R
+where
+    F: FnOnce(&mut FxHashMap<String, HaulerRoute>) -> R,
+{
+    HAULER_ROUTES.with(|routes| f(routes.borrow_mut().entry(room_name).or_default()))
+}
+
+/// Sets the remaining targets of the hauler, replacing the previous ones. An empty route removes it.
+pub fn set_hauler_route(room_name: RoomName, creep_name: &str, route: HaulerRoute) {
+    with_hauler_routes(room_name, |routes| {
+        if route.is_empty() {
+            routes.remove(creep_name);
+        } else {
+            routes.insert(creep_name.to_string(), route);
+        }
+    });
+}
@@ -4,4 +4,6 @@ pub mod store_anywhere_or_drop;
 mod reserving_requests;
 pub mod requests;
 pub mod transfers;
-pub mod haul_stats;
\ No newline at end of file
+pub mod haul_stats;
+pub mod hauler_routes;
+mod fill_extensions;
\ No newline at end of file
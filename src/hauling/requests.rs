@@ -6,9 +6,13 @@ use log::trace;
 use rustc_hash::FxHashMap;
 use screeps::{ObjectId, Position, RawObjectId, ResourceType, RoomName};
 use crate::utils::priority::Priority;
+use crate::kernel::broadcast::Broadcast;
 use crate::hauling::scheduling_hauls::cancel_haul_request;
+use crate::hauling::haul_stats::{register_delivered_amount, register_hauled_amount};
 use crate::a;
+use enum_iterator::Sequence;
 use HaulRequestKind::*;
+use HaulRequestTargetKind::*;
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
 pub enum HaulRequestKind {
@@ -29,6 +33,46 @@ pub enum HaulRequestTargetKind {
     /// A regular immovable room object, e.g., structure, tombstone.
     /// It can also be a permanent storage when the storage is low on some resource.
     RegularTarget,
+    /// All extensions in the room, filled one after another along a route. The target is one of
+    /// them.
+    ExtensionsTarget,
+}
+
+/// The purpose of the resources moved by a haul request, used to tell genuine logistics demand
+/// apart from moving resources around within the room.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Sequence)]
+pub enum HaulFlow {
+    /// Resources entering the logistics, e.g., mined energy picked up next to a source.
+    Income,
+    /// Refilling spawns, extensions and towers.
+    Refill,
+    /// Delivering resources to where they are used up, e.g., upgraders or builders.
+    Consumption,
+    /// Resources picked up from tombstones, ruins or abandoned piles.
+    Loot,
+    /// Moving resources into or out of permanent storage.
+    Balancing,
+}
+
+impl HaulFlow {
+    /// The flow of a request unless specified otherwise when creating it.
+    pub fn default_for(kind: HaulRequestKind, target_kind: HaulRequestTargetKind) -> Self {
+        match (kind, target_kind) {
+            (_, StorageTarget) => HaulFlow::Balancing,
+            (WithdrawRequest, _) => HaulFlow::Income,
+            (DepositRequest, CreepTarget) => HaulFlow::Consumption,
+            (DepositRequest, _) => HaulFlow::Refill,
+        }
+    }
+}
+
+/// The reason a haul request was dropped by the hauling system rather than by its creator.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HaulRequestOutcome {
+    /// The expiry tick of the request has passed.
+    Expired,
+    /// The target of the request no longer exists.
+    TargetNotFound,
 }
 
 #[derive(Default)]
@@ -37,6 +81,16 @@ pub struct RoomHaulRequests {
     pub deposit_requests: FxHashMap<HaulRequestId, HaulRequestRef>,
 }
 
+impl RoomHaulRequests {
+    /// Withdraw or deposit requests, depending on the kind.
+    pub fn requests(&self, kind: HaulRequestKind) -> &FxHashMap<HaulRequestId, HaulRequestRef> {
+        match kind {
+            WithdrawRequest => &self.withdraw_requests,
+            DepositRequest => &self.deposit_requests,
+        }
+    }
+}
+
 /// There can be only one haul request per withdrawal/deposit, per object, per resource type.
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct HaulRequestId(RawObjectId, ResourceType);
@@ -51,12 +105,15 @@ impl Display for HaulRequestId {
 #[derive(Debug)]
 pub struct HaulRequest {
     pub kind: HaulRequestKind,
-    /// Name of the room responsible for providing the hauler.
+    /// Name of the home room of the request, responsible for providing the hauler. It is the room
+    /// of the target unless the target is outside of owned rooms, e.g., in a remote.
     pub room_name: RoomName,
     pub target: RawObjectId,
     pub target_kind: HaulRequestTargetKind,
     pub limited_transfer: bool,
     pub resource_type: ResourceType,
+    /// What the hauled resources are for, used in the stats.
+    pub flow: HaulFlow,
     /// Best effort information on the position of the target.
     /// May change if the target is moving (e.g., creep).
     pub pos: Position,
@@ -72,6 +129,10 @@ pub struct HaulRequest {
     /// The amount that is reserved to be withdrawn or deposited.
     /// May exceed `amount` if the `amount` has decreased.
     pub reserved_amount: u32,
+    /// The tick from which the request is dropped. `None` if it does not expire.
+    pub expiry_tick: Option<u32>,
+    /// Broadcast of the outcome when the request is dropped by the hauling system.
+    pub completion: Broadcast<HaulRequestOutcome>,
 }
 
 /// Haul request identifier that cancels the request on drop.
@@ -79,6 +140,8 @@ pub struct HaulRequest {
 pub struct HaulRequestHandle {
     pub request: HaulRequestRef,
     pub droppable: bool,
+    /// Broadcast of the outcome when the request is dropped by the hauling system.
+    pub completion: Broadcast<HaulRequestOutcome>,
 }
 
 #[derive(Debug)]
@@ -93,7 +156,7 @@ thread_local! {
     static HAUL_REQUESTS: RefCell<FxHashMap<RoomName, RoomHaulRequests>> = RefCell::new(FxHashMap::default());
 }
 
-pub(super) fn with_haul_requests<F, R>(room_name: RoomName, f: F) -> R
+pub fn with_haul_requests<F, R>(room_name: RoomName, f: F) -> R
 where
     F: FnOnce(&mut RoomHaulRequests) -> R,
 {
@@ -106,6 +169,18 @@ where
     })
 }
 
+impl HaulRequestHandle {
+    /// The outcome of the request if it was dropped by the hauling system since the last check. The handle of a
+    /// dropped request no longer cancels it on drop, so that it does not cancel a newer request for the same target.
+    pub fn check_dropped(&mut self) -> Option<HaulRequestOutcome> {
+        let outcome = self.completion.check();
+        if outcome.is_some() {
+            self.droppable = false;
+        }
+        outcome
+    }
+}
+
 impl Drop for HaulRequestHandle {
     fn drop(&mut self) {
         if self.droppable {
@@ -170,11 +245,14 @@ impl HaulRequest {
             limited_transfer,
             pos,
             resource_type,
+            flow: HaulFlow::default_for(kind, target_kind),
             amount: 0,
             change: 0,
             max_amount: u32::MAX,
             priority: Priority(100),
             reserved_amount: 0,
+            expiry_tick: None,
+            completion: Broadcast::default(),
         }
     }
     
@@ -182,6 +260,14 @@ impl HaulRequest {
         HaulRequestId(self.target, self.resource_type)
     }
 
+    /// Number of rooms between the home room of the request and the room of its target.
+    pub fn home_room_dist(&self) -> u32 {
+        let target_room_name = self.pos.room_name();
+        let dx = self.room_name.x_coord().abs_diff(target_room_name.x_coord());
+        let dy = self.room_name.y_coord().abs_diff(target_room_name.y_coord());
+        max(dx, dy)
+    }
+
     pub fn unreserved_amount(&self) -> i32 {
         self.amount as i32 - self.reserved_amount as i32
     }
@@ -222,7 +308,507 @@ impl ReservedHaulRequest {
         let mut borrowed_request = self.request.borrow_mut();
         borrowed_request.amount -= self.amount;
         borrowed_request.reserved_amount -= self.amount;
+        register_hauled_amount(borrowed_request.room_name, borrowed_request.flow, self.amount);
+        if borrowed_request.kind == DepositRequest {
+            register_delivered_amount(borrowed_request.room_name, self.amount);
+        }
         // Preventing the drop from changing anything.
         self.amount = 0;
     }
-}
\ No newline at end of file
+
+    /// Completes a part of the reserved amount, e.g., after depositing into one of many targets
+    /// covered by the request. The rest stays reserved.
+    pub fn complete_partially(&mut self, amount: u32) {
+        let amount = min(amount, self.amount);
+        let mut borrowed_request = self.request.borrow_mut();
+        // The amount may have been already updated by the creator of the request in the meantime.
+        borrowed_request.amount = borrowed_request.amount.saturating_sub(amount);
+        borrowed_request.reserved_amount -= amount;
+        register_hauled_amount(borrowed_request.room_name, borrowed_request.flow, amount);
+        if borrowed_request.kind == DepositRequest {
+            register_delivered_amount(borrowed_request.room_name, amount);
+        }
+        self.amount -= amount;
+    }
+
+    /// Releases the reservation of a deposit request whose target turned out to be full, e.g.,
+    /// because another hauler got there first. The amount of the request is corrected to the
+    /// actual free capacity of the target so that it is not assigned to other haulers beyond what
+    /// fits in there.
+    pub fn release_target_full(self, free_capacity: u32) {
+        let mut borrowed_request = self.request.borrow_mut();
+        a!(borrowed_request.kind == DepositRequest);
+        trace!(
+            "Correcting the amount of the request {} to the free capacity {} of the target.",
+            borrowed_request,
+            free_capacity
+        );
+        borrowed_request.amount = min(borrowed_request.amount, free_capacity);
+        // The reservation itself is released on drop.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use rustc_hash::FxHashMap;
+    use screeps::{ObjectId, Position, RawObjectId, ResourceType, RoomName, StructureContainer, StructureExtension, Resource, StructureLab, StructureSpawn, StructureStorage};
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::hauling::requests::{with_haul_requests, HaulFlow, HaulRequest, HaulRequestHandle, HaulRequestOutcome};
+    use crate::hauling::requests::HaulRequestKind::{DepositRequest, WithdrawRequest};
+    use crate::hauling::requests::HaulRequestTargetKind::{CreepTarget, PickupTarget, RegularTarget, StorageTarget};
+    use crate::hauling::reserving_requests::{assign_haul_requests, find_haul_requests, HaulerData, ReservedRequests};
+    use crate::hauling::scheduling_hauls::{schedule_haul, sweep_haul_requests};
+    use crate::room_states::room_state::test_empty_unowned_room_name;
+    use crate::u;
+    use crate::utils::game_tick::game_tick;
+    use crate::utils::priority::Priority;
+
+    fn schedule_test_deposit_request<T>(target: ObjectId<T>, x: u8, y: u8, amount: u32) -> HaulRequestHandle {
+        let room_name = test_empty_unowned_room_name();
+        let mut request = HaulRequest::new(
+            DepositRequest,
+            room_name,
+            ResourceType::Energy,
+            target,
+            RegularTarget,
+            false,
+            Position::new_from_raw(x, y, room_name)
+        );
+        request.amount = amount;
+        schedule_haul(request, None)
+    }
+
+    fn find_for_loaded_hauler(amount: u32, x: u8, y: u8) -> Option<ReservedRequests> {
+        let store = FxHashMap::from_iter([(ResourceType::Energy, amount)]);
+        find_haul_requests(
+            test_empty_unowned_room_name(),
+            &store,
+            Position::new_from_raw(x, y, test_empty_unowned_room_name()),
+            amount,
+            1000
+        )
+    }
+
+    #[test]
+    fn test_two_reservations_against_full_target() {
+        let container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(1).into();
+        let container_request = schedule_test_deposit_request(container_id, 10, 10, 1000);
+
+        // Two haulers reserve depositing into the same container.
+        let mut first_reserved = find_for_loaded_hauler(500, 12, 12).unwrap();
+        let mut second_reserved = find_for_loaded_hauler(500, 8, 8).unwrap();
+        assert!(first_reserved.withdraw_requests.is_empty());
+        assert!(second_reserved.withdraw_requests.is_empty());
+        assert_eq!(container_request.request.borrow().reserved_amount, 1000);
+
+        // The first one delivers its load, but then the container is filled by something else.
+        u!(first_reserved.deposit_requests.first_mut()).complete();
+        drop(first_reserved);
+        assert_eq!(container_request.request.borrow().amount, 500);
+        assert_eq!(container_request.request.borrow().reserved_amount, 500);
+
+        // The second one gets ERR_FULL and releases its reservation.
+        u!(second_reserved.deposit_requests.pop()).release_target_full(0);
+        assert_eq!(container_request.request.borrow().amount, 0);
+        assert_eq!(container_request.request.borrow().reserved_amount, 0);
+
+        // The container is no longer assigned to anyone.
+        assert!(find_for_loaded_hauler(500, 8, 8).is_none());
+
+        // The loaded hauler can be assigned to another deposit request straight away, without
+        // a withdraw leg.
+        let spawn_id: ObjectId<StructureSpawn> = RawObjectId::from_packed(2).into();
+        let spawn_request = schedule_test_deposit_request(spawn_id, 20, 20, 300);
+        let reassigned = find_for_loaded_hauler(500, 8, 8).unwrap();
+        assert!(reassigned.withdraw_requests.is_empty());
+        assert_eq!(reassigned.deposit_requests.len(), 1);
+        assert_eq!(reassigned.deposit_requests[0].amount, 300);
+        assert_eq!(spawn_request.request.borrow().reserved_amount, 300);
+    }
+
+    #[test]
+    fn test_release_corrects_amount_to_free_capacity() {
+        let container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(3).into();
+        let container_request = schedule_test_deposit_request(container_id, 10, 10, 1000);
+
+        let first_reserved = find_for_loaded_hauler(500, 12, 12).unwrap();
+        let mut second_reserved = find_for_loaded_hauler(500, 8, 8).unwrap();
+
+        // The container shrunk to 300 free capacity before the second hauler arrived while
+        // the first one is still on its way.
+        u!(second_reserved.deposit_requests.pop()).release_target_full(300);
+        assert_eq!(container_request.request.borrow().amount, 300);
+        // The first hauler's reservation exceeds the corrected amount, so no one else is assigned.
+        assert_eq!(container_request.request.borrow().reserved_amount, 500);
+        assert!(find_for_loaded_hauler(500, 8, 8).is_none());
+
+        // After the first hauler releases its reservation too, the corrected amount is open again,
+        // but only up to the actual free capacity.
+        drop(first_reserved);
+        let reassigned = find_for_loaded_hauler(500, 8, 8).unwrap();
+        assert_eq!(reassigned.deposit_requests[0].amount, 300);
+    }
+
+    #[test]
+    fn test_default_haul_flows() {
+        assert_eq!(HaulFlow::default_for(WithdrawRequest, PickupTarget), HaulFlow::Income);
+        assert_eq!(HaulFlow::default_for(WithdrawRequest, RegularTarget), HaulFlow::Income);
+        assert_eq!(HaulFlow::default_for(WithdrawRequest, StorageTarget), HaulFlow::Balancing);
+        assert_eq!(HaulFlow::default_for(DepositRequest, StorageTarget), HaulFlow::Balancing);
+        assert_eq!(HaulFlow::default_for(DepositRequest, CreepTarget), HaulFlow::Consumption);
+        assert_eq!(HaulFlow::default_for(DepositRequest, RegularTarget), HaulFlow::Refill);
+
+        let request = HaulRequest::new(
+            DepositRequest,
+            test_empty_unowned_room_name(),
+            ResourceType::Energy,
+            ObjectId::<StructureSpawn>::from(RawObjectId::from_packed(4)),
+            RegularTarget,
+            false,
+            Position::new_from_raw(10, 10, test_empty_unowned_room_name())
+        );
+        assert_eq!(request.flow, HaulFlow::Refill);
+    }
+
+    #[test]
+    fn test_replacing_request_keeps_one_entry() {
+        let room_name = test_empty_unowned_room_name();
+        let container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(8).into();
+        let original_handle = schedule_test_deposit_request(container_id, 10, 10, 1000);
+        let original_request = original_handle.request.clone();
+
+        let mut replacement = HaulRequest::new(
+            DepositRequest,
+            room_name,
+            ResourceType::Energy,
+            container_id,
+            RegularTarget,
+            false,
+            Position::new_from_raw(10, 10, room_name)
+        );
+        replacement.amount = 400;
+        replacement.priority = Priority(150);
+        let replacement_handle = schedule_haul(replacement, Some(original_handle));
+
+        with_haul_requests(room_name, |haul_requests| {
+            assert_eq!(haul_requests.deposit_requests.len(), 1);
+            let request = u!(haul_requests.deposit_requests.values().next()).borrow();
+            assert_eq!(request.amount, 400);
+            assert_eq!(request.priority, Priority(150));
+        });
+        // The request is updated in place.
+        assert!(Rc::ptr_eq(&replacement_handle.request, &original_request));
+
+        drop(replacement_handle);
+        with_haul_requests(room_name, |haul_requests| {
+            assert!(haul_requests.deposit_requests.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_replacing_request_with_another_kind() {
+        let room_name = test_empty_unowned_room_name();
+        let container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(9).into();
+        let original_handle = schedule_test_deposit_request(container_id, 10, 10, 1000);
+        let original_request = original_handle.request.clone();
+
+        let mut replacement = HaulRequest::new(
+            WithdrawRequest,
+            room_name,
+            ResourceType::Energy,
+            container_id,
+            RegularTarget,
+            false,
+            Position::new_from_raw(10, 10, room_name)
+        );
+        replacement.amount = 300;
+        let replacement_handle = schedule_haul(replacement, Some(original_handle));
+
+        // The replaced request is cancelled instead of being left behind.
+        assert_eq!(original_request.borrow().amount, 0);
+        with_haul_requests(room_name, |haul_requests| {
+            assert!(haul_requests.deposit_requests.is_empty());
+            assert_eq!(haul_requests.withdraw_requests.len(), 1);
+            let request = u!(haul_requests.withdraw_requests.values().next()).borrow();
+            assert_eq!(request.amount, 300);
+        });
+        assert_eq!(replacement_handle.request.borrow().amount, 300);
+    }
+
+    #[test]
+    fn test_sweep_drops_expired_requests() {
+        let room_name = test_empty_unowned_room_name();
+        let expired_id: ObjectId<StructureContainer> = RawObjectId::from_packed(5).into();
+        let mut expired_request = schedule_test_deposit_request(expired_id, 10, 10, 1000);
+        expired_request.request.borrow_mut().expiry_tick = Some(game_tick());
+        let missing_id: ObjectId<StructureContainer> = RawObjectId::from_packed(6).into();
+        let mut missing_request = schedule_test_deposit_request(missing_id, 20, 20, 1000);
+        let kept_id: ObjectId<StructureContainer> = RawObjectId::from_packed(7).into();
+        let mut kept_request = schedule_test_deposit_request(kept_id, 30, 30, 1000);
+        kept_request.request.borrow_mut().expiry_tick = Some(game_tick() + 100);
+
+        sweep_haul_requests(room_name, |request| request.target != RawObjectId::from(missing_id));
+
+        assert_eq!(expired_request.request.borrow().amount, 0);
+        assert_eq!(expired_request.completion.check(), Some(HaulRequestOutcome::Expired));
+        assert_eq!(missing_request.request.borrow().amount, 0);
+        assert_eq!(missing_request.completion.check(), Some(HaulRequestOutcome::TargetNotFound));
+        assert_eq!(kept_request.request.borrow().amount, 1000);
+        assert_eq!(kept_request.completion.check(), None);
+
+        // The dropped requests are no longer assigned to haulers.
+        let reserved = find_for_loaded_hauler(500, 10, 10).unwrap();
+        assert_eq!(reserved.deposit_requests.len(), 1);
+        assert_eq!(reserved.deposit_requests[0].request.borrow().target, RawObjectId::from(kept_id));
+    }
+
+    #[test]
+    fn test_haul_requests_matched_by_resource_type() {
+        let room_name = test_empty_unowned_room_name();
+        let storage_id: ObjectId<StructureStorage> = RawObjectId::from_packed(8).into();
+        let mut keanium_request = HaulRequest::new(
+            WithdrawRequest,
+            room_name,
+            ResourceType::Keanium,
+            storage_id,
+            StorageTarget,
+            false,
+            Position::new_from_raw(25, 25, room_name)
+        );
+        keanium_request.amount = 3000;
+        let _keanium_handle = schedule_haul(keanium_request, None);
+
+        // The extension is closer, but there is no energy to fill it with.
+        let extension_id: ObjectId<StructureExtension> = RawObjectId::from_packed(9).into();
+        let extension_request = schedule_test_deposit_request(extension_id, 26, 26, 50);
+        let lab_id: ObjectId<StructureLab> = RawObjectId::from_packed(10).into();
+        let mut lab_request = HaulRequest::new(
+            DepositRequest,
+            room_name,
+            ResourceType::Keanium,
+            lab_id,
+            RegularTarget,
+            false,
+            Position::new_from_raw(35, 35, room_name)
+        );
+        lab_request.amount = 2000;
+        let lab_handle = schedule_haul(lab_request, None);
+
+        let reserved = find_haul_requests(room_name, &FxHashMap::default(), Position::new_from_raw(25, 26, room_name), 500, 1000).unwrap();
+        assert_eq!(reserved.withdraw_requests.len(), 1);
+        assert_eq!(reserved.withdraw_requests[0].request.borrow().resource_type, ResourceType::Keanium);
+        // Exactly as much as needed is withdrawn when it is not energy.
+        assert_eq!(reserved.withdraw_requests[0].amount, 500);
+        assert_eq!(reserved.deposit_requests.len(), 1);
+        assert_eq!(reserved.deposit_requests[0].request.borrow().target, lab_handle.request.borrow().target);
+        assert_eq!(extension_request.request.borrow().reserved_amount, 0);
+
+        // A hauler carrying Keanium is not sent to the extension either.
+        let store = FxHashMap::from_iter([(ResourceType::Keanium, 500)]);
+        let reserved = find_haul_requests(room_name, &store, Position::new_from_raw(26, 27, room_name), 500, 1000).unwrap();
+        assert!(reserved.withdraw_requests.is_empty());
+        assert_eq!(reserved.deposit_requests[0].request.borrow().resource_type, ResourceType::Keanium);
+        assert_eq!(extension_request.request.borrow().reserved_amount, 0);
+    }
+
+    fn schedule_test_withdraw_request<T>(target: ObjectId<T>, x: u8, y: u8, amount: u32) -> HaulRequestHandle {
+        let room_name = test_empty_unowned_room_name();
+        let mut request = HaulRequest::new(
+            WithdrawRequest,
+            room_name,
+            ResourceType::Energy,
+            target,
+            RegularTarget,
+            false,
+            Position::new_from_raw(x, y, room_name)
+        );
+        request.amount = amount;
+        schedule_haul(request, None)
+    }
+
+    fn find_for_empty_hauler(capacity: u32, x: u8, y: u8) -> Option<ReservedRequests> {
+        find_haul_requests(
+            test_empty_unowned_room_name(),
+            &FxHashMap::default(),
+            Position::new_from_raw(x, y, test_empty_unowned_room_name()),
+            capacity,
+            1000
+        )
+    }
+
+    #[test]
+    fn test_partial_withdraw_reservation() {
+        let container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(11).into();
+        let container_request = schedule_test_withdraw_request(container_id, 10, 10, 400);
+
+        let first_reserved = find_for_empty_hauler(300, 12, 12).unwrap();
+        assert_eq!(first_reserved.withdraw_requests[0].amount, 300);
+        let second_reserved = find_for_empty_hauler(300, 8, 8).unwrap();
+        assert_eq!(second_reserved.withdraw_requests[0].amount, 100);
+        assert_eq!(container_request.request.borrow().reserved_amount, 400);
+        assert!(find_for_empty_hauler(300, 8, 8).is_none());
+
+        // The second hauler dies and its process is killed, releasing the reservation.
+        drop(second_reserved);
+        assert_eq!(container_request.request.borrow().reserved_amount, 300);
+
+        // A remainder that is too small compared to the capacity is not worth sending another hauler.
+        assert!(find_for_empty_hauler(1000, 8, 8).is_none());
+        let third_reserved = find_for_empty_hauler(300, 8, 8).unwrap();
+        assert_eq!(third_reserved.withdraw_requests[0].amount, 100);
+        drop(first_reserved);
+        assert_eq!(container_request.request.borrow().reserved_amount, 100);
+    }
+
+    #[test]
+    fn test_chained_pickups() {
+        let room_name = test_empty_unowned_room_name();
+        let pile_xys = [(12, 12), (15, 12), (18, 13), (40, 40)];
+        let pile_handles = pile_xys
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| {
+                let mut request = HaulRequest::new(
+                    WithdrawRequest,
+                    room_name,
+                    ResourceType::Energy,
+                    ObjectId::<Resource>::from(RawObjectId::from_packed(20 + i as u128)),
+                    PickupTarget,
+                    false,
+                    Position::new_from_raw(x, y, room_name)
+                );
+                request.amount = 100;
+                schedule_haul(request, None)
+            })
+            .collect::<Vec<_>>();
+
+        let reserved = find_for_empty_hauler(300, 10, 10).unwrap();
+        let stops = reserved
+            .withdraw_requests
+            .iter()
+            .map(|request| (request.request.borrow().target, request.amount))
+            .collect::<Vec<_>>();
+        let expected_stops = pile_handles[..3]
+            .iter()
+            .map(|handle| (handle.request.borrow().target, 100))
+            .collect::<Vec<_>>();
+        assert_eq!(stops, expected_stops);
+        assert!(reserved.deposit_requests.is_empty());
+        // The far pile is left for another hauler.
+        assert_eq!(pile_handles[3].request.borrow().reserved_amount, 0);
+    }
+
+    #[test]
+    fn test_global_assignment_shorter_than_greedy() {
+        let room_name = test_empty_unowned_room_name();
+        // Five groups of two haulers and three withdraw requests each, far enough from one another.
+        // In each group, the first hauler greedily takes the request closest to both of them,
+        // sending the second one further away.
+        let mut request_handles = Vec::new();
+        let mut haulers = Vec::new();
+        for group in 0..5u8 {
+            let y = 5 + 8 * group;
+            for (i, x) in [9, 12, 40].into_iter().enumerate() {
+                let id: ObjectId<StructureContainer> = RawObjectId::from_packed(100 + 3 * group as u128 + i as u128).into();
+                request_handles.push(schedule_test_withdraw_request(id, x, y, 300));
+            }
+            for x in [10, 8] {
+                haulers.push(HaulerData {
+                    store: FxHashMap::default(),
+                    pos: Position::new_from_raw(x, y, room_name),
+                    capacity: 300,
+                    ttl: 1000,
+                });
+            }
+        }
+
+        let total_dist = |hauler_requests: &[Option<ReservedRequests>]| {
+            haulers
+                .iter()
+                .zip(hauler_requests.iter())
+                .map(|(hauler, reserved)| {
+                    let reserved = u!(reserved.as_ref());
+                    reserved.withdraw_requests[0].request.borrow().pos.get_range_to(hauler.pos)
+                })
+                .sum::<u32>()
+        };
+
+        let greedy_requests = haulers
+            .iter()
+            .map(|hauler| find_haul_requests(room_name, &hauler.store, hauler.pos, hauler.capacity, hauler.ttl))
+            .collect::<Vec<_>>();
+        let greedy_dist = total_dist(&greedy_requests);
+        drop(greedy_requests);
+        assert!(request_handles.iter().all(|handle| handle.request.borrow().reserved_amount == 0));
+
+        let assigned_requests = assign_haul_requests(room_name, &haulers);
+        let assigned_dist = total_dist(&assigned_requests);
+        assert_eq!(greedy_dist, 25);
+        assert_eq!(assigned_dist, 15);
+
+        // Each request is assigned to at most one hauler and the far ones are left alone.
+        for (i, handle) in request_handles.iter().enumerate() {
+            let expected_reserved_amount = if i % 3 == 2 { 0 } else { 300 };
+            assert_eq!(handle.request.borrow().reserved_amount, expected_reserved_amount);
+        }
+    }
+
+    #[test]
+    fn test_remote_requests_assigned_to_home_room_haulers() {
+        let home_room_name = test_empty_unowned_room_name();
+        let remote_room_name = RoomName::new("W2N1").unwrap();
+        let unrelated_room_name = RoomName::new("W5N5").unwrap();
+        let hauler_at = |pos: Position| HaulerData {
+            store: FxHashMap::default(),
+            pos,
+            capacity: 300,
+            ttl: 1000,
+        };
+
+        // A container next to a source in a remote, closer to the hauler than the local one.
+        let remote_container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(200).into();
+        let mut remote_request = HaulRequest::new(
+            WithdrawRequest,
+            home_room_name,
+            ResourceType::Energy,
+            remote_container_id,
+            RegularTarget,
+            false,
+            Position::new_from_raw(45, 10, remote_room_name)
+        );
+        remote_request.amount = 1000;
+        let remote_handle = schedule_haul(remote_request, None);
+        assert_eq!(remote_handle.request.borrow().home_room_dist(), 1);
+        let local_container_id: ObjectId<StructureContainer> = RawObjectId::from_packed(201).into();
+        let local_handle = schedule_test_withdraw_request(local_container_id, 40, 10, 300);
+
+        // Haulers of unrelated rooms are not assigned the request.
+        let unrelated_hauler = hauler_at(Position::new_from_raw(45, 10, unrelated_room_name));
+        let unrelated_assigned = assign_haul_requests(unrelated_room_name, &[unrelated_hauler]);
+        assert!(unrelated_assigned[0].is_none());
+        let remote_hauler = hauler_at(Position::new_from_raw(45, 10, remote_room_name));
+        assert!(assign_haul_requests(remote_room_name, &[remote_hauler])[0].is_none());
+
+        // Local requests are preferred, but the remote one is assigned to the home room's hauler
+        // when there is nothing better to do.
+        let home_haulers = [
+            hauler_at(Position::new_from_raw(5, 10, home_room_name)),
+            hauler_at(Position::new_from_raw(5, 10, home_room_name)),
+        ];
+        let assigned = assign_haul_requests(home_room_name, &home_haulers);
+        let targets = assigned
+            .iter()
+            .map(|reserved| u!(reserved.as_ref()).withdraw_requests[0].request.borrow().target)
+            .collect::<Vec<_>>();
+        assert!(targets.contains(&RawObjectId::from(local_container_id)));
+        assert!(targets.contains(&RawObjectId::from(remote_container_id)));
+        assert_eq!(local_handle.request.borrow().reserved_amount, 300);
+        assert_eq!(remote_handle.request.borrow().reserved_amount, 300);
+
+        // With a single hauler, the local request is preferred even though it is further away.
+        drop(assigned);
+        let assigned = assign_haul_requests(home_room_name, &[hauler_at(Position::new_from_raw(2, 10, home_room_name))]);
+        assert_eq!(u!(assigned[0].as_ref()).withdraw_requests[0].request.borrow().target, RawObjectId::from(local_container_id));
+    }
+}
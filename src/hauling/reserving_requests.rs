@@ -4,7 +4,10 @@ use rustc_hash::FxHashMap;
 use screeps::{Position, ResourceType, RoomName};
 use crate::{local_debug, u};
 use crate::geometry::position_utils::PositionUtils;
-use crate::hauling::requests::{with_haul_requests, ReservedHaulRequest};
+use crate::config::HAUL_CHAIN_MAX_DETOUR;
+use crate::algorithms::min_cost_weighted_matching::min_cost_weighted_matching;
+use crate::hauling::requests::{with_haul_requests, HaulRequestId, HaulRequestKind, ReservedHaulRequest, RoomHaulRequests};
+use crate::hauling::requests::HaulRequestKind::{DepositRequest, WithdrawRequest};
 use crate::hauling::requests::HaulRequestTargetKind::StorageTarget;
 
 const DEBUG: bool = true;
@@ -14,6 +17,21 @@ const MIN_DECAYING_AMOUNT: u32 = 100;
 
 const CREEP_LOW_TTL: u32 = 100;
 
+/// Not sending another hauler to a withdraw request that is already partially reserved when what is
+/// left is under this fraction of the hauler's capacity.
+const MIN_PARTIALLY_RESERVED_WITHDRAW_FRACTION: u32 = 4;
+
+/// Cost of a single point of priority in terms of distance when assigning requests to haulers.
+/// Exceeds any distance within a room so that priorities are respected first.
+const PRIORITY_DISTANCE: u32 = 100;
+
+/// Cost of leaving a hauler idle, higher than the cost of any request.
+const IDLE_COST: u32 = (u8::MAX as u32 + 1) * PRIORITY_DISTANCE;
+
+/// Decrease of priority of a request per room between its home room and its target when assigning
+/// requests to haulers.
+const INTER_ROOM_PRIORITY_DISCOUNT: u8 = 10;
+
 /// A structure containing active requests to first withdraw and then store resources.
 /// When dropped, the remaining requests are rescheduled.
 /// The contents of the requests may change on the way. Specifically, the amount and position
@@ -108,201 +126,504 @@ pub fn find_haul_requests(
             );
         }
 
-        let mut withdraw_requests = Vec::new();
-        let mut deposit_requests = Vec::new();
+        let hauler = HaulerData {
+            store: creep_store.clone(),
+            pos: creep_pos,
+            capacity: creep_capacity,
+            ttl: creep_ttl,
+        };
 
-        if let Some(&first_resource_type) = creep_store.keys().next() {
+        let job = if !creep_store.is_empty() {
             // Filled creep store. In this case, the creep seeks to deposit its resources somewhere.
+            deposit_candidates(haul_requests, &hauler)
+                .into_iter()
+                .max_by_key(|&(_, depositable_amount, is_storage, dist)| (is_storage, Reverse(dist), depositable_amount))
+                .map(|(request_id, depositable_amount, is_storage, _)| HaulJob::Deposit(request_id, depositable_amount, is_storage))
+        } else {
+            // Empty creep store. In this case, the creep seeks to withdraw resources from somewhere.
+            // It will not withdraw from a storage unless there is a non-storage deposit request.
+            // but only if either there is somewhere to put them or they must be withdrawn to not be
+            // lost (i.e., are not in storage).
+            withdraw_candidates(haul_requests, &hauler)
+                .into_iter()
+                .max_by_key(|&(_, withdrawable_amount, dist)| (Reverse(dist), withdrawable_amount))
+                .map(|(request_id, withdrawable_amount, _)| HaulJob::Withdraw(request_id, withdrawable_amount))
+                .or_else(|| {
+                    // If there is no non-storage withdraw request, try to find a deposit request and
+                    // a withdraw request from storage.
+                    storage_withdraw_and_deposit_candidates(haul_requests, &hauler)
+                        .into_iter()
+                        .max_by_key(|&(_, _, withdrawn_amount, deposited_amount, total_dist)| (Reverse(total_dist), deposited_amount, withdrawn_amount))
+                        .map(|(withdraw_request_id, deposit_request_id, withdrawn_amount, deposited_amount, _)| {
+                            HaulJob::StorageWithdrawAndDeposit(withdraw_request_id, deposit_request_id, withdrawn_amount, deposited_amount)
+                        })
+                })
+        };
+
+        job.and_then(|job| reserve_job(haul_requests, job, &hauler))
+    })
+}
+
+/// Data of a hauler relevant to assigning haul requests to it.
+#[derive(Clone, Debug)]
+pub struct HaulerData {
+    pub store: FxHashMap<ResourceType, u32>,
+    pub pos: Position,
+    pub capacity: u32,
+    pub ttl: u32,
+}
+
+/// The primary requests a hauler was chosen to fulfill, before adding nearby requests to the trip.
+#[derive(Copy, Clone, Debug)]
+enum HaulJob {
+    Deposit(HaulRequestId, u32, bool),
+    Withdraw(HaulRequestId, u32),
+    StorageWithdrawAndDeposit(HaulRequestId, HaulRequestId, u32, u32),
+}
+
+impl HaulJob {
+    /// The request that the job is meant to fulfill, shared among haulers when assigning requests.
+    fn demand(&self) -> (HaulRequestKind, HaulRequestId) {
+        match *self {
+            HaulJob::Deposit(id, _, _) => (DepositRequest, id),
+            HaulJob::Withdraw(id, _) => (WithdrawRequest, id),
+            HaulJob::StorageWithdrawAndDeposit(_, id, _, _) => (DepositRequest, id),
+        }
+    }
+}
+
+/// Deposit requests that a creep with a filled store can fulfill along with the depositable amount,
+/// whether it is a storage and the distance.
+fn deposit_candidates(haul_requests: &RoomHaulRequests, hauler: &HaulerData) -> Vec<(HaulRequestId, u32, bool, u32)> {
+    let Some(&first_resource_type) = hauler.store.keys().next() else {
+        return Vec::new();
+    };
+
+    // First trying to find a non-storage deposit request that can be fulfilled with
+    // what is already carried. For example, to deposit the picked up energy from
+    // drop mining.
+    // If this fails, trying to find a deposit request to storage, but only if the creep
+    // has a non-energy resource or is low on TTL.
+    let storage_possible = hauler.ttl < CREEP_LOW_TTL || first_resource_type != ResourceType::Energy || hauler.store.len() >= 2;
+
+    haul_requests
+        .deposit_requests
+        .iter()
+        .filter_map(|(&id, request)| {
+            let borrowed_request = request.borrow();
+            let is_storage = borrowed_request.target_kind == StorageTarget;
+            if !storage_possible && is_storage {
+                return None;
+            }
+            let carried_amount = if let Some(&amount) = hauler.store.get(&borrowed_request.resource_type) {
+                amount
+            } else {
+                return None;
+            };
+            let depositable_amount = min(carried_amount as i32, borrowed_request.unreserved_amount());
+            if depositable_amount <= 0 {
+                return None;
+            }
+            // TODO Reward requests with higher amount.
+            // TODO Penalize requests that would not be completely fulfilled unless
+            //      the request itself is already over capacity.
+            // TODO Penalize requests such that fulfilling possible amount would not changew
+            //      the number of full capacities to withdraw them.
+            // TODO Also include all possible requests available when standing on one of
+            //      neighboring tiles (e.g., a group of up to 6 more extensions).
+            Some((id, depositable_amount as u32, is_storage, borrowed_request.pos.get_range_to(hauler.pos)))
+        })
+        .collect()
+}
 
-            // First trying to find a non-storage deposit request that can be fulfilled with
-            // what is already carried. For example, to deposit the picked up energy from
-            // drop mining.
-            // If this fails, trying to find a deposit request to storage, but only if the creep
-            // has a non-energy resource or is low on TTL.
-            let storage_possible = creep_ttl < CREEP_LOW_TTL || first_resource_type != ResourceType::Energy || creep_store.len() >= 2;
+/// Non-storage withdraw requests that fill up an empty creep or are not increasing in amount, along
+/// with the withdrawn amount and the distance.
+fn withdraw_candidates(haul_requests: &RoomHaulRequests, hauler: &HaulerData) -> Vec<(HaulRequestId, u32, u32)> {
+    haul_requests
+        .withdraw_requests
+        .iter()
+        .filter_map(|(&id, request)| {
+            let borrowed_request = request.borrow();
+            if borrowed_request.target_kind == StorageTarget {
+                return None;
+            }
+            let withdrawable_amount = min(hauler.capacity as i32, borrowed_request.unreserved_amount());
+            if withdrawable_amount <= 0 {
+                return None;
+            }
+            if borrowed_request.reserved_amount > 0
+                && borrowed_request.change <= 0
+                && (withdrawable_amount as u32) < hauler.capacity / MIN_PARTIALLY_RESERVED_WITHDRAW_FRACTION {
+                // Another hauler is already on its way and only scraps would be left.
+                return None;
+            }
+            let mut withdrawn_amount = withdrawable_amount as u32;
+            let dist = borrowed_request.pos.get_range_to(hauler.pos);
+            if borrowed_request.change > 0 {
+                // Not undertaking increasing requests that do not (yet) fill the creep.
+                // TODO Take actual speed into consideration.
+                if borrowed_request.predicted_unreserved_amount(dist) < hauler.capacity {
+                    return None;
+                }
+                // Reserving withdrawal of full capacity (or max amount) even though it is
+                // not available yet so that other creeps won't come to pick small scraps.
+                withdrawn_amount = min(borrowed_request.max_amount, hauler.capacity);
+            } else if borrowed_request.change < 0 {
+                // Not undertaking decaying requests that will leave too small of a pile
+                // upon arrival.
+                // TODO Take actual speed into consideration.
+                if borrowed_request.predicted_unreserved_amount(dist) < MIN_DECAYING_AMOUNT {
+                    return None;
+                }
+            }
+            // TODO Reward requests with higher amount.
+            // TODO Ignore too small requests from loose piles and let them decay.
+            // TODO Reward decaying requests if deciding to pick them up.
+            // TODO Also include all possible requests available when standing on one of
+            //      neighboring tiles.
+            Some((id, withdrawn_amount, dist))
+        })
+        .collect()
+}
 
-            let deposit_request_data = haul_requests
-                .deposit_requests
+/// Pairs of a storage withdraw request and a non-storage deposit request of the same resource for an
+/// empty creep, each deposit request with its closest storage withdraw request, along with
+/// the withdrawn and deposited amounts and the total distance.
+fn storage_withdraw_and_deposit_candidates(
+    haul_requests: &RoomHaulRequests,
+    hauler: &HaulerData
+) -> Vec<(HaulRequestId, HaulRequestId, u32, u32, u32)> {
+    let eligible_storage_withdraw_request_data = haul_requests
+        .withdraw_requests
+        .iter()
+        .filter_map(|(&id, request)| {
+            let borrowed_request = request.borrow();
+            // Non-storage requests were already processed.
+            if borrowed_request.target_kind != StorageTarget {
+                return None;
+            }
+            let withdrawable_amount = min(hauler.capacity as i32, borrowed_request.unreserved_amount());
+            if withdrawable_amount <= 0 {
+                return None;
+            }
+            Some((id, withdrawable_amount as u32, borrowed_request.resource_type, borrowed_request.pos, borrowed_request.pos.get_range_to(hauler.pos)))
+        })
+        .collect::<Vec<_>>();
+
+    haul_requests
+        .deposit_requests
+        .iter()
+        .filter_map(|(&deposit_request_id, request)| {
+            let borrowed_request = request.borrow();
+            if borrowed_request.target_kind == StorageTarget {
+                return None;
+            }
+            let max_depositable_amount = min(hauler.capacity as i32, borrowed_request.unreserved_amount());
+            if max_depositable_amount <= 0 {
+                return None;
+            }
+            eligible_storage_withdraw_request_data
                 .iter()
-                .filter_map(|(&id, request)| {
-                    let borrowed_request = request.borrow();
-                    let is_storage = borrowed_request.target_kind == StorageTarget;
-                    if !storage_possible && is_storage {
+                .filter_map(|&(withdraw_request_id, withdrawable_amount, resource_type, withdraw_pos, withdraw_dist)| {
+                    if borrowed_request.resource_type != resource_type {
                         return None;
                     }
-                    let carried_amount = if let Some(&amount) = creep_store.get(&borrowed_request.resource_type) {
-                        amount
+
+                    let deposited_amount = min(max_depositable_amount as u32, withdrawable_amount);
+                    // When it is energy, withdrawing as much as possible. When
+                    // something else, withdrawing exactly as much as is needed.
+                    let withdrawn_amount = if borrowed_request.resource_type == ResourceType::Energy {
+                        withdrawable_amount
                     } else {
-                        return None;
+                        deposited_amount
                     };
-                    let depositable_amount = min(carried_amount as i32, borrowed_request.unreserved_amount());
-                    if depositable_amount <= 0 {
-                        return None;
-                    }
-                    // TODO Reward requests with higher amount.
-                    // TODO Penalize requests that would not be completely fulfilled unless
-                    //      the request itself is already over capacity.
-                    // TODO Penalize requests such that fulfilling possible amount would not changew
-                    //      the number of full capacities to withdraw them.
-                    // TODO Also include all possible requests available when standing on one of
-                    //      neighboring tiles (e.g., a group of up to 6 more extensions).
-                    Some((id, depositable_amount as u32, is_storage, borrowed_request.pos.get_range_to(creep_pos)))
+                    let total_dist = withdraw_dist + withdraw_pos.get_range_to(borrowed_request.pos);
+
+                    Some((withdraw_request_id, withdrawn_amount, deposited_amount, total_dist))
+                })
+                .min_by_key(|&(_, withdrawn_amount, deposited_amount, total_dist)| (total_dist, Reverse(deposited_amount), Reverse(withdrawn_amount)))
+                .map(|(withdraw_request_id, withdrawn_amount, deposited_amount, total_dist)| {
+                    (withdraw_request_id, deposit_request_id, withdrawn_amount, deposited_amount, total_dist)
                 })
-                .max_by_key(|&(_, depositable_amount, is_storage, dist)| (is_storage, Reverse(dist), depositable_amount));
+        })
+        .collect()
+}
+
+/// All jobs a hauler can currently do along with the distance it needs to travel to perform them.
+fn job_candidates(haul_requests: &RoomHaulRequests, hauler: &HaulerData) -> Vec<(HaulJob, u32)> {
+    if !hauler.store.is_empty() {
+        deposit_candidates(haul_requests, hauler)
+            .into_iter()
+            .map(|(id, amount, is_storage, dist)| (HaulJob::Deposit(id, amount, is_storage), dist))
+            .collect()
+    } else {
+        let withdraw_jobs = withdraw_candidates(haul_requests, hauler)
+            .into_iter()
+            .map(|(id, amount, dist)| (HaulJob::Withdraw(id, amount), dist))
+            .collect::<Vec<_>>();
+        if !withdraw_jobs.is_empty() {
+            return withdraw_jobs;
+        }
+        // As when assigning a single hauler, withdrawing from the storage is only considered when
+        // there is no other withdraw request.
+        storage_withdraw_and_deposit_candidates(haul_requests, hauler)
+            .into_iter()
+            .map(|(withdraw_id, deposit_id, withdrawn_amount, deposited_amount, total_dist)| {
+                (HaulJob::StorageWithdrawAndDeposit(withdraw_id, deposit_id, withdrawn_amount, deposited_amount), total_dist)
+            })
+            .collect()
+    }
+}
+
+/// Reserves the requests of the job along with nearby requests that can be fulfilled in the same trip.
+fn reserve_job(haul_requests: &RoomHaulRequests, job: HaulJob, hauler: &HaulerData) -> Option<ReservedRequests> {
+    let mut withdraw_requests = Vec::new();
+    let mut deposit_requests = Vec::new();
 
-            if let Some((request_id, depositable_amount, _, _)) = deposit_request_data {
-                local_debug!("Found deposit request {} for {}.", request_id, depositable_amount);
-                deposit_requests.push((request_id, depositable_amount));
+    match job {
+        HaulJob::Deposit(request_id, depositable_amount, is_storage) => {
+            local_debug!("Found deposit request {} for {}.", request_id, depositable_amount);
+            deposit_requests.push((request_id, depositable_amount));
+            if !is_storage {
+                chain_deposit_requests(haul_requests, &mut deposit_requests, hauler.store.clone());
             }
-        } else {
-            // Empty creep store. In this case, the creep seeks to withdraw resources from somewhere.
-            // It will not withdraw from a storage unless there is a non-storage deposit request.
-            // but only if either there is somewhere to put them or they must be withdrawn to not be
-            // lost (i.e., are not in storage).
+        }
+        HaulJob::Withdraw(request_id, withdrawable_amount) => {
+            local_debug!("Found withdraw request {} for {}.", request_id, withdrawable_amount);
+            withdraw_requests.push((request_id, withdrawable_amount));
+            chain_withdraw_requests(haul_requests, &mut withdraw_requests, hauler.capacity);
+        }
+        HaulJob::StorageWithdrawAndDeposit(withdraw_request_id, deposit_request_id, withdrawn_amount, deposited_amount) => {
+            // TODO Maybe not always take full creep capacity of minerals?
+            local_debug!(
+                "Found withdraw request {} for {} and deposit request {} for {}.",
+                withdraw_request_id,
+                withdrawn_amount,
+                deposit_request_id,
+                deposited_amount
+            );
+            withdraw_requests.push((withdraw_request_id, withdrawn_amount));
+            deposit_requests.push((deposit_request_id, deposited_amount));
+            let resource_type = u!(haul_requests.withdraw_requests.get(&withdraw_request_id)).borrow().resource_type;
+            let withdrawn_store = FxHashMap::from_iter([(resource_type, withdrawn_amount)]);
+            chain_deposit_requests(haul_requests, &mut deposit_requests, withdrawn_store);
+        }
+    }
 
-            // First trying to find a non-storage withdraw request that fills up the creep or is not
-            // increasing in amount.
-            let withdraw_request_data = haul_requests
-                .withdraw_requests
-                .iter()
-                .filter_map(|(&id, request)| {
-                    let borrowed_request = request.borrow();
-                    if borrowed_request.target_kind == StorageTarget {
-                        return None;
-                    }
-                    let withdrawable_amount = min(creep_capacity as i32, borrowed_request.unreserved_amount());
-                    if withdrawable_amount <= 0 {
-                        return None;
-                    }
-                    let mut withdrawn_amount = withdrawable_amount as u32;
-                    let dist = borrowed_request.pos.get_range_to(creep_pos);
-                    if borrowed_request.change > 0 {
-                        // Not undertaking increasing requests that do not (yet) fill the creep.
-                        // TODO Take actual speed into consideration.
-                        if borrowed_request.predicted_unreserved_amount(dist) < creep_capacity {
-                            return None;
-                        }
-                        // Reserving withdrawal of full capacity (or max amount) even though it is
-                        // not available yet so that other creeps won't come to pick small scraps.
-                        withdrawn_amount = min(borrowed_request.max_amount, creep_capacity);
-                    } else if borrowed_request.change < 0 {
-                        // Not undertaking decaying requests that will leave too small of a pile
-                        // upon arrival.
-                        // TODO Take actual speed into consideration.
-                        if borrowed_request.predicted_unreserved_amount(dist) < MIN_DECAYING_AMOUNT {
-                            return None;
-                        }
+    (!withdraw_requests.is_empty() || !deposit_requests.is_empty()).then(|| {
+        let reserved_withdraw_requests = withdraw_requests
+            .into_iter()
+            .map(|(withdraw_request_id, amount)| {
+                ReservedHaulRequest::new(
+                    u!(haul_requests.withdraw_requests.get(&withdraw_request_id)).clone(),
+                    amount
+                )
+            })
+            .collect();
+
+        let reserved_deposit_requests = deposit_requests
+            .into_iter()
+            .map(|(deposit_request_id, amount)| {
+                ReservedHaulRequest::new(
+                    u!(haul_requests.deposit_requests.get(&deposit_request_id)).clone(),
+                    amount
+                )
+            })
+            .collect();
+
+        ReservedRequests {
+            withdraw_requests: reserved_withdraw_requests,
+            deposit_requests: reserved_deposit_requests,
+        }
+    })
+}
+
+/// Assigns haul requests of given home room to all given haulers at once, minimizing the total
+/// distance travelled to their first stop while preferring requests with higher priority, instead
+/// of each hauler greedily taking the closest request. Requests with targets outside of the home
+/// room, e.g., in remotes, have their priority discounted by the distance between the rooms.
+/// Haulers that are not assigned anything get `None`.
+pub fn assign_haul_requests(room_name: RoomName, haulers: &[HaulerData]) -> Vec<Option<ReservedRequests>> {
+    with_haul_requests(room_name, |haul_requests| {
+        let haulers_candidates = haulers
+            .iter()
+            .map(|hauler| job_candidates(haul_requests, hauler))
+            .collect::<Vec<_>>();
+
+        // Each request can be fulfilled by as many haulers as needed to cover its unreserved amount,
+        // each taking a separate slot.
+        let min_capacity = haulers.iter().map(|hauler| hauler.capacity).min().unwrap_or(1).max(1);
+        let mut slots = FxHashMap::default();
+        let mut requests_slots_count = 0;
+        for (job, _) in haulers_candidates.iter().flatten() {
+            let (kind, id) = job.demand();
+            slots.entry((kind, id)).or_insert_with(|| {
+                let unreserved_amount = u!(haul_requests.requests(kind).get(&id)).borrow().unreserved_amount().max(1) as u32;
+                let slots_count = min(unreserved_amount.div_ceil(min_capacity) as usize, haulers.len());
+                requests_slots_count += slots_count;
+                (requests_slots_count - slots_count, slots_count)
+            });
+        }
+
+        // The costs are dominated by the priority, then by the distance. Each hauler may also stay
+        // idle, which is more costly than doing anything.
+        let costs = haulers_candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidates)| {
+                let mut hauler_costs = FxHashMap::default();
+                for &(job, dist) in candidates.iter() {
+                    let (kind, id) = job.demand();
+                    let priority = {
+                        let request = u!(haul_requests.requests(kind).get(&id)).borrow();
+                        let discount = INTER_ROOM_PRIORITY_DISCOUNT.saturating_mul(min(request.home_room_dist(), u8::MAX as u32) as u8);
+                        request.priority.saturating_sub(discount)
+                    };
+                    let (first_slot, slots_count) = slots[&job.demand()];
+                    for slot in first_slot..first_slot + slots_count {
+                        let cost = (u8::MAX - priority.0) as u32 * PRIORITY_DISTANCE + dist + (slot - first_slot) as u32;
+                        let slot_cost = hauler_costs.entry(slot).or_insert(cost);
+                        *slot_cost = min(*slot_cost, cost);
                     }
-                    // TODO Reward requests with higher amount.
-                    // TODO Ignore too small requests from loose piles and let them decay.
-                    // TODO Reward decaying requests if deciding to pick them up.
-                    // TODO Also include all possible requests available when standing on one of
-                    //      neighboring tiles.
-                    Some((id, withdrawn_amount, dist))
-                })
-                .max_by_key(|&(_, withdrawable_amount, dist)| (Reverse(dist), withdrawable_amount));
+                }
+                hauler_costs.insert(requests_slots_count + i, IDLE_COST);
+                hauler_costs.into_iter().collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
 
-            if let Some((request_id, withdrawable_amount, _)) = withdraw_request_data {
-                local_debug!("Found withdraw request {} for {}.", request_id, withdrawable_amount);
-                withdraw_requests.push((request_id, withdrawable_amount));
-            } else {
-                // If there is no non-storage withdraw request, try to find a deposit request and
-                // a withdraw request from storage.
-                let eligible_storage_withdraw_request_data = haul_requests
-                    .withdraw_requests
-                    .iter()
-                    .filter_map(|(&id, request)| {
-                        let borrowed_request = request.borrow();
-                        // Non-storage requests were already processed.
-                        if borrowed_request.target_kind != StorageTarget {
-                            return None;
-                        }
-                        let withdrawable_amount = min(creep_capacity as i32, borrowed_request.unreserved_amount());
-                        if withdrawable_amount <= 0 {
-                            return None;
-                        }
-                        Some((id, withdrawable_amount as u32, borrowed_request.resource_type, borrowed_request.pos, borrowed_request.pos.get_range_to(creep_pos)))
-                    })
-                    .collect::<Vec<_>>();
+        // There is always the idle slot, so all haulers can be matched.
+        let (hauler_slots, _) = u!(min_cost_weighted_matching(&costs));
 
-                let withdraw_and_deposit_request_data = haul_requests
-                    .deposit_requests
+        // Reserving the requests in the order of the costs so that haulers with better options
+        // reserve first.
+        let mut assigned_jobs = hauler_slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &slot)| {
+                haulers_candidates[i]
                     .iter()
-                    .filter_map(|(&deposit_request_id, request)| {
-                        let borrowed_request = request.borrow();
-                        if borrowed_request.target_kind == StorageTarget {
-                            return None;
-                        }
-                        let max_depositable_amount = min(creep_capacity as i32, borrowed_request.unreserved_amount());
-                        if max_depositable_amount <= 0 {
-                            return None;
-                        }
-                        eligible_storage_withdraw_request_data
-                            .iter()
-                            .filter_map(|&(withdraw_request_id, withdrawable_amount, resource_type, withdraw_pos, withdraw_dist)| {
-                                if borrowed_request.resource_type != resource_type {
-                                    return None;
-                                }
-
-                                let deposited_amount = min(max_depositable_amount as u32, withdrawable_amount);
-                                // When it is energy, withdrawing as much as possible. When
-                                // something else, withdrawing exactly as much as is needed.
-                                let withdrawn_amount = if borrowed_request.resource_type == ResourceType::Energy {
-                                    withdrawable_amount
-                                } else {
-                                    deposited_amount
-                                };
-                                let total_dist = withdraw_dist + withdraw_pos.get_range_to(borrowed_request.pos);
-
-                                Some((withdraw_request_id, withdrawn_amount, deposited_amount, total_dist))
-                            })
-                            .min_by_key(|&(_, withdrawn_amount, deposited_amount, total_dist)| (total_dist, Reverse(deposited_amount), Reverse(withdrawn_amount)))
-                            .map(|(withdraw_request_id, withdrawn_amount, deposited_amount, total_dist)| {
-                                (withdraw_request_id, deposit_request_id, withdrawn_amount, deposited_amount, total_dist)
-                            })
+                    .filter(|(job, _)| {
+                        let (first_slot, slots_count) = slots[&job.demand()];
+                        first_slot <= slot && slot < first_slot + slots_count
                     })
-                    .max_by_key(|&(_, _, withdrawn_amount, deposited_amount, total_dist)| (Reverse(total_dist), deposited_amount));
-
-                if let Some((withdraw_request_id, deposit_request_id, withdrawn_amount, deposited_amount, _)) = withdraw_and_deposit_request_data {
-                    // TODO Maybe not always take full creep capacity of minerals?
-                    local_debug!(
-                        "Found withdraw request {} for {} and deposit request {} for {}.",
-                        withdraw_request_id,
-                        withdrawn_amount,
-                        deposit_request_id,
-                        deposited_amount
-                    );
-                    withdraw_requests.push((withdraw_request_id, withdrawn_amount));
-                    deposit_requests.push((deposit_request_id, deposited_amount));
-                }
+                    .min_by_key(|&&(_, dist)| dist)
+                    .map(|&(job, _)| (i, job, u!(costs[i].iter().find(|&&(j, _)| j == slot)).1))
+            })
+            .collect::<Vec<_>>();
+        assigned_jobs.sort_by_key(|&(_, _, cost)| cost);
+
+        let mut result = haulers.iter().map(|_| None).collect::<Vec<_>>();
+        for (i, job, _) in assigned_jobs {
+            // The job's amounts may have changed due to reservations of other haulers.
+            let updated_job = job_candidates(haul_requests, &haulers[i])
+                .into_iter()
+                .map(|(updated_job, _)| updated_job)
+                .find(|updated_job| updated_job.demand() == job.demand());
+            if let Some(updated_job) = updated_job {
+                result[i] = reserve_job(haul_requests, updated_job, &haulers[i]);
             }
         }
+        result
+    })
+}
 
-        (!withdraw_requests.is_empty() || !deposit_requests.is_empty()).then(|| {
-            let reserved_withdraw_requests = withdraw_requests
-                .into_iter()
-                .map(|(withdraw_request_id, amount)| {
-                    ReservedHaulRequest::new(
-                        u!(haul_requests.withdraw_requests.get(&withdraw_request_id)).clone(),
-                        amount
-                    )
-                })
-                .collect();
+/// Greedily appends withdraw requests of the same resource as the first one, each within
+/// `HAUL_CHAIN_MAX_DETOUR` from the previous one, until the creep would be full.
+fn chain_withdraw_requests(
+    haul_requests: &RoomHaulRequests,
+    withdraw_requests: &mut Vec<(HaulRequestId, u32)>,
+    creep_capacity: u32
+) {
+    let (resource_type, mut last_pos) = {
+        let first_request = u!(haul_requests.withdraw_requests.get(&withdraw_requests[0].0)).borrow();
+        (first_request.resource_type, first_request.pos)
+    };
+    let mut withdrawn_amount = withdraw_requests.iter().map(|&(_, amount)| amount).sum::<u32>();
 
-            let reserved_deposit_requests = deposit_requests
-                .into_iter()
-                .map(|(deposit_request_id, amount)| {
-                    ReservedHaulRequest::new(
-                        u!(haul_requests.deposit_requests.get(&deposit_request_id)).clone(),
-                        amount
-                    )
-                })
-                .collect();
+    while withdrawn_amount < creep_capacity {
+        let next_request_data = haul_requests
+            .withdraw_requests
+            .iter()
+            .filter_map(|(&id, request)| {
+                if withdraw_requests.iter().any(|&(chosen_id, _)| chosen_id == id) {
+                    return None;
+                }
+                let borrowed_request = request.borrow();
+                // Increasing requests are only worth it when they fill the creep by themselves.
+                if borrowed_request.target_kind == StorageTarget
+                    || borrowed_request.resource_type != resource_type
+                    || borrowed_request.change > 0 {
+                    return None;
+                }
+                let dist = borrowed_request.pos.get_range_to(last_pos);
+                if dist > HAUL_CHAIN_MAX_DETOUR {
+                    return None;
+                }
+                if borrowed_request.change < 0 && borrowed_request.predicted_unreserved_amount(dist) < MIN_DECAYING_AMOUNT {
+                    return None;
+                }
+                let amount = min((creep_capacity - withdrawn_amount) as i32, borrowed_request.unreserved_amount());
+                (amount > 0).then_some((id, amount as u32, dist, borrowed_request.pos))
+            })
+            .min_by_key(|&(_, amount, dist, _)| (dist, Reverse(amount)));
+
+        let Some((id, amount, _, pos)) = next_request_data else {
+            break;
+        };
+        local_debug!("Chaining withdraw request {} for {}.", id, amount);
+        withdraw_requests.push((id, amount));
+        withdrawn_amount += amount;
+        last_pos = pos;
+    }
+}
+
+/// Greedily appends non-storage deposit requests of the resources left in the creep after the
+/// already chosen ones, each within `HAUL_CHAIN_MAX_DETOUR` from the previous one.
+fn chain_deposit_requests(
+    haul_requests: &RoomHaulRequests,
+    deposit_requests: &mut Vec<(HaulRequestId, u32)>,
+    mut creep_store: FxHashMap<ResourceType, u32>
+) {
+    let mut last_pos = None;
+    for &(id, amount) in deposit_requests.iter() {
+        let request = u!(haul_requests.deposit_requests.get(&id)).borrow();
+        if let Some(carried_amount) = creep_store.get_mut(&request.resource_type) {
+            *carried_amount = carried_amount.saturating_sub(amount);
+        }
+        last_pos = Some(request.pos);
+    }
+    let Some(mut last_pos) = last_pos else {
+        return;
+    };
 
-            Some(ReservedRequests {
-                withdraw_requests: reserved_withdraw_requests,
-                deposit_requests: reserved_deposit_requests,
+    loop {
+        let next_request_data = haul_requests
+            .deposit_requests
+            .iter()
+            .filter_map(|(&id, request)| {
+                if deposit_requests.iter().any(|&(chosen_id, _)| chosen_id == id) {
+                    return None;
+                }
+                let borrowed_request = request.borrow();
+                if borrowed_request.target_kind == StorageTarget {
+                    return None;
+                }
+                let carried_amount = creep_store.get(&borrowed_request.resource_type).copied().unwrap_or(0);
+                let dist = borrowed_request.pos.get_range_to(last_pos);
+                if dist > HAUL_CHAIN_MAX_DETOUR {
+                    return None;
+                }
+                let amount = min(carried_amount as i32, borrowed_request.unreserved_amount());
+                (amount > 0).then_some((id, amount as u32, dist, borrowed_request.resource_type, borrowed_request.pos))
             })
-        })
-    }).flatten()
-}
\ No newline at end of file
+            .min_by_key(|&(_, amount, dist, _, _)| (dist, Reverse(amount)));
+
+        let Some((id, amount, _, resource_type, pos)) = next_request_data else {
+            break;
+        };
+        local_debug!("Chaining deposit request {} for {}.", id, amount);
+        deposit_requests.push((id, amount));
+        *u!(creep_store.get_mut(&resource_type)) -= amount;
+        last_pos = pos;
+    }
+}
@@ -6,8 +6,14 @@ use crate::hauling::requests::{
     HaulRequestHandle,
     HaulRequestRef
 };
-use crate::hauling::requests::HaulRequestKind::DepositRequest;
+use crate::hauling::requests::HaulRequestKind::{DepositRequest, WithdrawRequest};
+use crate::hauling::requests::HaulRequestTargetKind::PickupTarget;
+use crate::hauling::requests::HaulRequestOutcome::{Expired, TargetNotFound};
+use crate::utils::game_tick::game_tick;
+use screeps::{HasId, HasPosition, Resource, RoomName};
 use crate::local_debug;
+use crate::utils::priority::Priority;
+use crate::utils::resource_decay::decay_per_tick;
 
 const DEBUG: bool = true;
 
@@ -21,7 +27,16 @@ pub fn schedule_haul(mut request: HaulRequest, mut replaced_haul_request_handle:
     let mut previous_id = None;
     if let Some(mut replaced_haul_request_handle) = replaced_haul_request_handle.take() {
         replaced_haul_request_handle.droppable = false;
-        previous_id = Some(replaced_haul_request_handle.request.borrow().id());
+        let previous_request_ref = replaced_haul_request_handle.request.clone();
+        let previous_request = previous_request_ref.borrow();
+        if previous_request.room_name == request.room_name && previous_request.kind == request.kind {
+            previous_id = Some(previous_request.id());
+        } else {
+            // The previous request is in another container, so it cannot be updated in place and
+            // would otherwise be left there without anyone to cancel it.
+            drop(previous_request);
+            cancel_haul_request(previous_request_ref);
+        }
     }
     
     let request_ref = with_haul_requests(request.room_name, |haul_requests| {
@@ -36,6 +51,8 @@ pub fn schedule_haul(mut request: HaulRequest, mut replaced_haul_request_handle:
             // The IDs may be different, e.g., if the previous resource pile expired.
             if let Some(previous_request) = container.remove(&previous_id) {
                 request.reserved_amount = previous_request.borrow().reserved_amount;
+                // Keeping the broadcast so that the creator keeps being notified.
+                request.completion = previous_request.borrow().completion.clone_primed();
                 // This is where the request is updated for everyone.
                 previous_request.replace(request);
                 request_ref = previous_request;
@@ -49,12 +66,37 @@ pub fn schedule_haul(mut request: HaulRequest, mut replaced_haul_request_handle:
         request_ref
     });
     
+    let completion = request_ref.borrow().completion.clone_primed();
     HaulRequestHandle {
         request: request_ref,
         droppable: true,
+        completion,
     }
 }
 
+/// Schedules picking up the whole resource pile, taking its decay into account.
+pub fn schedule_pickup(
+    room_name: RoomName,
+    resource: &Resource,
+    priority: Priority,
+    replaced_haul_request_handle: Option<HaulRequestHandle>
+) -> HaulRequestHandle {
+    let amount = resource.amount();
+    let mut pickup_request = HaulRequest::new(
+        WithdrawRequest,
+        room_name,
+        resource.resource_type(),
+        resource.id(),
+        PickupTarget,
+        false,
+        resource.pos()
+    );
+    pickup_request.amount = amount;
+    pickup_request.change = -(decay_per_tick(amount) as i32);
+    pickup_request.priority = priority;
+    schedule_haul(pickup_request, replaced_haul_request_handle)
+}
+
 pub fn cancel_haul_request(request: HaulRequestRef) {
     let mut borrowed_request = request.borrow_mut();
     local_debug!(
@@ -79,4 +121,58 @@ pub fn cancel_haul_request(request: HaulRequestRef) {
             },
         }
     });
-}
\ No newline at end of file
+}
+
+/// Cancels all haul requests in given room that do not satisfy the predicate.
+pub fn retain_haul_requests<F>(room_name: RoomName, mut f: F)
+where
+    F: FnMut(&HaulRequest) -> bool,
+{
+    with_haul_requests(room_name, |haul_requests| {
+        for requests in [&mut haul_requests.withdraw_requests, &mut haul_requests.deposit_requests] {
+            requests.retain(|_, request| {
+                let mut borrowed_request = request.borrow_mut();
+                let retained = f(&borrowed_request);
+                if !retained {
+                    local_debug!("Cancelling {:?} request {}.", borrowed_request.kind, borrowed_request);
+                    // Setting the request to not require any more resources.
+                    borrowed_request.amount = 0;
+                }
+                retained
+            });
+        }
+    });
+}
+
+/// Cancels haul requests in given room that expired or whose target no longer exists and broadcasts
+/// the outcome to their creators.
+pub fn sweep_haul_requests<F>(room_name: RoomName, mut target_exists: F)
+where
+    F: FnMut(&HaulRequest) -> bool,
+{
+    let mut dropped_requests = Vec::new();
+
+    with_haul_requests(room_name, |haul_requests| {
+        for requests in [&mut haul_requests.withdraw_requests, &mut haul_requests.deposit_requests] {
+            requests.retain(|_, request| {
+                let mut borrowed_request = request.borrow_mut();
+                let outcome = if borrowed_request.expiry_tick.is_some_and(|tick| game_tick() >= tick) {
+                    Expired
+                } else if !target_exists(&borrowed_request) {
+                    TargetNotFound
+                } else {
+                    return true;
+                };
+                local_debug!("Dropping {:?} request {} ({:?}).", borrowed_request.kind, borrowed_request, outcome);
+                // Setting the request to not require any more resources.
+                borrowed_request.amount = 0;
+                dropped_requests.push((borrowed_request.completion.clone_same(), outcome));
+                false
+            });
+        }
+    });
+
+    for (completion, outcome) in dropped_requests {
+        completion.broadcast(outcome);
+    }
+}
@@ -1,19 +1,44 @@
-use crate::creeps::actions::drop_when_able;
+use screeps::{RoomName, StructureStorage};
+use screeps::StructureType::Storage;
+use crate::creeps::actions::{drop_when_able, transfer_when_able};
 use crate::creeps::creeps::CreepRef;
 use crate::errors::XiError;
+use crate::geometry::room_xy::RoomXYUtils;
 use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::room_states::room_states::with_room_state;
+use crate::travel::travel::travel;
+use crate::travel::travel_spec::TravelSpec;
+use crate::utils::priority::Priority;
 
-/// Stores everything it contains anywhere or drops if there is no storage available.
-pub async fn store_anywhere_or_drop(creep_ref: &CreepRef) -> Result<(), XiError> {
-    let store = creep_ref.borrow_mut().used_capacities(AfterAllTransfers)?;
+/// Stores everything it contains in the storage of given room or drops it if there is no storage
+/// available or the storage is full.
+pub async fn store_anywhere_or_drop(creep_ref: &CreepRef, room_name: RoomName) -> Result<(), XiError> {
+    let storage = with_room_state(room_name, |room_state| {
+        room_state.structures_with_type::<StructureStorage>(Storage).next()
+    }).flatten();
 
-    for (resource_type, amount) in store.into_iter() {
-        drop_when_able(
-            creep_ref,
-            resource_type,
-            amount
-        ).await?;
+    if let Some((storage_xy, storage_id)) = storage {
+        let travel_spec = TravelSpec {
+            target: storage_xy.to_pos(room_name),
+            range: 1,
+            progress_priority: Priority(200),
+            target_rect_priority: Priority(200),
+        };
+        travel(creep_ref, travel_spec).await?;
+
+        let store = creep_ref.borrow_mut().used_capacities(AfterAllTransfers)?;
+        for (resource_type, amount) in store.into_iter() {
+            match transfer_when_able(creep_ref, storage_id.into(), resource_type, amount, false).await {
+                Err(XiError::CreepTransferTargetFull) => drop_when_able(creep_ref, resource_type, amount).await?,
+                result => result?,
+            }
+        }
+    } else {
+        let store = creep_ref.borrow_mut().used_capacities(AfterAllTransfers)?;
+        for (resource_type, amount) in store.into_iter() {
+            drop_when_able(creep_ref, resource_type, amount).await?;
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
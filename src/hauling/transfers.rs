@@ -6,7 +6,9 @@ use screeps::game::get_object_by_id_typed;
 use wasm_bindgen::JsCast;
 use crate::errors::XiError;
 use crate::hauling::transfers::TransferStage::*;
+use crate::utils::get_object_by_id::erased_object_by_id;
 use crate::utils::single_tick_cache::SingleTickCache;
+use crate::utils::unchecked_has_store::UncheckedHasStore;
 
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 struct ResourceTransfers {
@@ -79,6 +81,12 @@ where
     Ok(get_used_capacity_with_object(&object, object_id.into(), resource_type, transfer_stage))
 }
 
+/// Same as `get_used_capacity`, but for an object of unknown type that is assumed to have a store.
+pub fn get_used_capacity_unchecked(object_id: RawObjectId, resource_type: Option<ResourceType>, transfer_stage: TransferStage) -> Result<u32, XiError> {
+    let object = erased_object_by_id(&object_id)?;
+    Ok(get_used_capacity_with_object(&UncheckedHasStore(&object), object_id, resource_type, transfer_stage))
+}
+
 pub fn get_used_capacity_with_object<T>(object: &T, object_id: RawObjectId, resource_type: Option<ResourceType>, transfer_stage: TransferStage) -> u32
 where
     T: ?Sized + HasStore,
@@ -164,6 +172,12 @@ where
     Ok(get_free_capacity_with_object(&object, object_id.into(), resource_type, transfer_stage))
 }
 
+/// Same as `get_free_capacity`, but for an object of unknown type that is assumed to have a store.
+pub fn get_free_capacity_unchecked(object_id: RawObjectId, resource_type: Option<ResourceType>, transfer_stage: TransferStage) -> Result<u32, XiError> {
+    let object = erased_object_by_id(&object_id)?;
+    Ok(get_free_capacity_with_object(&UncheckedHasStore(&object), object_id, resource_type, transfer_stage))
+}
+
 pub fn get_free_capacity_with_object<T>(object: &T, object_id: RawObjectId, resource_type: Option<ResourceType>, transfer_stage: TransferStage) -> u32
 where
     T: ?Sized + HasStore,
@@ -1,5 +1,5 @@
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
@@ -13,9 +13,20 @@ use crate::kernel::kernel::{move_current_process_to_waiting_for_condition, signa
 pub struct Broadcast<T> {
     cid: CId,
     value: Rc<RefCell<Option<(T, u32)>>>,
+    /// Number of broadcasts so far, shared among all clones.
+    broadcasts_count: Rc<Cell<u32>>,
     last_try_tick: u32,
 }
 
+/// A future resolving to the value of the next broadcast after its creation.
+#[derive(Debug)]
+pub struct BroadcastWait<T> {
+    cid: CId,
+    value: Rc<RefCell<Option<(T, u32)>>>,
+    broadcasts_count: Rc<Cell<u32>>,
+    initial_broadcasts_count: u32,
+}
+
 impl<T> Default for Broadcast<T> {
     fn default() -> Self {
         let cid = CId::new();
@@ -23,6 +34,7 @@ impl<T> Default for Broadcast<T> {
         Broadcast {
             cid,
             value: Rc::new(RefCell::new(None)),
+            broadcasts_count: Rc::new(Cell::new(0)),
             last_try_tick: 0,
         }
     }
@@ -33,16 +45,45 @@ where
     T: Clone,
 {
     /// Wakes up all processes waiting on the broadcast.
+    /// Only the last value broadcast in a tick is received by processes woken up by it.
     pub fn broadcast(&self, value: T) {
         self.value.replace(Some((value, game_tick())));
+        self.broadcasts_count.set(self.broadcasts_count.get() + 1);
         signal_condition(self.cid);
     }
 
+    /// Waits for the next broadcast, regardless of the primed state, and returns its value.
+    pub fn wait(&self) -> BroadcastWait<T> {
+        BroadcastWait {
+            cid: self.cid,
+            value: self.value.clone(),
+            broadcasts_count: self.broadcasts_count.clone(),
+            initial_broadcasts_count: self.broadcasts_count.get(),
+        }
+    }
+
+    /// Waits for the next broadcast with a value satisfying the predicate and returns the value.
+    pub fn wait_for<P>(&self, predicate: P) -> impl Future<Output = T>
+    where
+        P: Fn(&T) -> bool,
+    {
+        let broadcast = self.clone_same();
+        async move {
+            loop {
+                let value = broadcast.wait().await;
+                if predicate(&value) {
+                    return value;
+                }
+            }
+        }
+    }
+
     /// Clone with same primed state.
     pub fn clone_same(&self) -> Self {
         Broadcast {
             cid: self.cid,
             value: self.value.clone(),
+            broadcasts_count: self.broadcasts_count.clone(),
             last_try_tick: self.last_try_tick,
         }
     }
@@ -52,6 +93,7 @@ where
         Broadcast {
             cid: self.cid,
             value: self.value.clone(),
+            broadcasts_count: self.broadcasts_count.clone(),
             last_try_tick: 0,
         }
     }
@@ -61,10 +103,17 @@ where
         Broadcast {
             cid: self.cid,
             value: self.value.clone(),
+            broadcasts_count: self.broadcasts_count.clone(),
             last_try_tick: game_tick(),
         }
     }
 
+    /// Number of broadcasts so far, shared among all clones. Can be used as a generation counter
+    /// of the broadcast value.
+    pub fn broadcasts_count(&self) -> u32 {
+        self.broadcasts_count.get()
+    }
+
     pub fn reset(&self) {
         self.value.replace(None);
     }
@@ -115,4 +164,24 @@ where
         self.last_try_tick = game_tick();
         result
     }
-}
\ No newline at end of file
+}
+
+impl<T> Future for BroadcastWait<T>
+where
+    T: Clone,
+{
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.broadcasts_count.get() > self.initial_broadcasts_count {
+            if let Some((value, _)) = self.value.borrow().as_ref() {
+                trace!("Broadcast wait ready.");
+                return Poll::Ready(value.clone());
+            }
+        }
+
+        trace!("Broadcast wait pending.");
+        move_current_process_to_waiting_for_condition(self.cid);
+        Poll::Pending
+    }
+}
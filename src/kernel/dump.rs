@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use crate::kernel::kernel::{process_tree, render_process_tree};
+use crate::kernel::process::{ProcessInfo, ProcessState};
+use crate::utils::priority::Priority;
+
+/// Full state of the kernel in a human-readable form, to be printed in the console.
+pub fn dump() -> String {
+    render_dump(&process_tree())
+}
+
+/// Renders the process tree, numbers of active processes by priority, numbers of sleeping processes
+/// by the tick they wake up in and what the waiting processes wait for.
+pub fn render_dump(processes: &[ProcessInfo]) -> String {
+    let mut active_counts = BTreeMap::<Priority, usize>::new();
+    let mut sleeping_counts = BTreeMap::<u32, usize>::new();
+    let mut waiting_edges = Vec::new();
+    for info in processes.iter() {
+        match info.state {
+            ProcessState::Active => *active_counts.entry(info.priority).or_default() += 1,
+            ProcessState::Sleeping(wake_up_tick) => *sleeping_counts.entry(wake_up_tick).or_default() += 1,
+            ProcessState::Awaiting(pid) => waiting_edges.push(format!("{}-{} -> {}", info.pid, info.name, pid)),
+            ProcessState::WaitingForCondition(cid) => {
+                waiting_edges.push(format!("{}-{} -> {}", info.pid, info.name, cid))
+            }
+        }
+    }
+
+    let mut lines = vec![format!("Processes ({}):", processes.len())];
+    lines.extend(render_process_tree(processes).lines().map(|line| format!("  {}", line)));
+    lines.push("Active by priority:".to_string());
+    // Higher priorities first, in the order they are run.
+    lines.extend(
+        active_counts
+            .iter()
+            .rev()
+            .map(|(priority, count)| format!("  {}: {}", priority, count)),
+    );
+    lines.push("Sleeping by wake up tick:".to_string());
+    lines.extend(
+        sleeping_counts
+            .iter()
+            .map(|(wake_up_tick, count)| format!("  {}: {}", wake_up_tick, count)),
+    );
+    lines.push("Waiting:".to_string());
+    lines.extend(waiting_edges.into_iter().map(|edge| format!("  {}", edge)));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kernel::condition::CId;
+    use crate::kernel::dump::render_dump;
+    use crate::kernel::process::{PId, ProcessInfo, ProcessState};
+    use crate::utils::priority::Priority;
+
+    #[test]
+    fn test_render_dump() {
+        let process = |pid, parent_pid, name: &str, priority, state| ProcessInfo {
+            pid,
+            parent_pid,
+            name: name.to_string(),
+            priority: Priority(priority),
+            state,
+            cpu_used: None,
+        };
+        let [root, a, b, c, d] = [(); 5].map(|_| PId::new());
+        let cid = CId::new();
+        let processes = [
+            process(root, None, "root", 200, ProcessState::Awaiting(a)),
+            process(a, Some(root), "a", 100, ProcessState::Active),
+            process(b, Some(root), "b", 150, ProcessState::Active),
+            process(c, None, "c", 100, ProcessState::Sleeping(20)),
+            process(d, Some(c), "d", 100, ProcessState::WaitingForCondition(cid)),
+        ];
+
+        let dump = render_dump(&processes);
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "Processes (5):");
+        assert!(lines[1].starts_with(&format!("  {}-root", root)));
+        assert!(lines[2].starts_with(&format!("    {}-a", a)));
+        assert!(lines[5].starts_with(&format!("    {}-d", d)));
+        assert_eq!(
+            &lines[6..],
+            &[
+                "Active by priority:".to_string(),
+                format!("  {}: 1", Priority(150)),
+                format!("  {}: 1", Priority(100)),
+                "Sleeping by wake up tick:".to_string(),
+                "  20: 1".to_string(),
+                "Waiting:".to_string(),
+                format!("  {}-root -> {}", root, a),
+                format!("  {}-d -> {}", d, cid),
+            ]
+        );
+
+        assert_eq!(
+            render_dump(&[]),
+            "Processes (0):\nActive by priority:\nSleeping by wake up tick:\nWaiting:"
+        );
+    }
+}
@@ -1,19 +1,28 @@
 use crate::utils::game_tick::game_tick;
 use crate::utils::cold::cold;
+use crate::config::RERAISE_PROCESS_PANICS;
 use crate::utils::multi_map_utils::{MultiMapUtils, OrderedMultiMapUtils};
 use crate::{a, local_debug, u};
 use log::{error, trace};
 use parking_lot::lock_api::MappedMutexGuard;
 use parking_lot::{Mutex, MutexGuard, RawMutex};
 use rustc_hash::{FxHashMap, FxHashSet};
-use screeps::game;
-use std::collections::BTreeMap;
-use std::future::Future;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use futures::future::Either;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
 use std::task::Poll;
-use crate::kernel::condition::CId;
-use crate::kernel::process::{PId, Process, WrappedProcessMeta};
+use crate::kernel::condition::{CId, Condition};
+use crate::kernel::last_call::{is_last_call, run_last_call};
+use crate::kernel::mailbox::remove_mailbox;
+use crate::kernel::process::{PId, Process, ProcessInfo, ProcessState, WrappedProcessMeta};
 use crate::kernel::process_handle::ProcessHandle;
 use crate::kernel::runnable::Runnable;
+use crate::errors::XiError;
+use crate::errors::XiError::ProcessNotFound;
+use crate::kernel::sleep::sleep;
 use crate::utils::priority::Priority;
 
 const DEBUG: bool = false;
@@ -22,8 +31,8 @@ const DEBUG: bool = false;
 /// from one thread.
 #[derive(Debug)]
 struct Kernel {
-    /// Map from priorities to processes.
-    active_processes_by_priorities: BTreeMap<Priority, Vec<Box<dyn Runnable>>>,
+    /// Map from priorities to processes. Processes with the same priority are run in FIFO order.
+    active_processes_by_priorities: BTreeMap<Priority, VecDeque<Box<dyn Runnable>>>,
     /// Processes that are sleeping until the tick in the key.
     sleeping_processes: BTreeMap<u32, Vec<Box<dyn Runnable>>>,
     /// Processes that are awaiting completion of another process with PID in the key.
@@ -32,6 +41,10 @@ struct Kernel {
     condition_processes: FxHashMap<CId, Vec<Box<dyn Runnable>>>,
     /// Processes by PID.
     meta_by_pid: FxHashMap<PId, WrappedProcessMeta>,
+    /// Scoped children of processes with PID in the key.
+    scoped_children: FxHashMap<PId, Vec<ScopedChild>>,
+    /// CPU used when the current process started running.
+    current_process_start_cpu: f64,
 
     current_process_meta: Option<WrappedProcessMeta>,
 }
@@ -50,12 +63,26 @@ impl Kernel {
             awaiting_processes: FxHashMap::default(),
             condition_processes: FxHashMap::default(),
             meta_by_pid: FxHashMap::default(),
+            scoped_children: FxHashMap::default(),
+            current_process_start_cpu: 0.0,
 
             current_process_meta: None,
         }
     }
 }
 
+/// A child process that is killed when its parent finishes, along with a function setting its result when killed.
+struct ScopedChild {
+    pid: PId,
+    set_default_result: Box<dyn FnOnce()>,
+}
+
+impl Debug for ScopedChild {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ScopedChild({})", self.pid)
+    }
+}
+
 /// Schedules a future to run asynchronously. It will not run right away, but instead be enqueued.
 /// Returns `ProcessHandle` which can be awaited and returns the value returned by the scheduled process.
 /// If called outside of a process, the result should be manually dropped using `std::mem::drop`.
@@ -69,6 +96,7 @@ where
     let pid = PId::new();
     let parent_pid = kern.current_process_meta.as_ref().map(|meta| meta.borrow().pid);
     let process = Process::new(name.into(), pid, parent_pid, priority, future);
+    process.meta.borrow_mut().cpu_budget = kern.current_process_meta.as_ref().and_then(|meta| meta.borrow().cpu_budget);
 
     let result = process.result.clone();
 
@@ -81,26 +109,204 @@ where
     ProcessHandle::new(pid, result)
 }
 
+/// Schedules a future to run asynchronously as a child of the current process that is killed with the default
+/// result when the current process finishes or is killed. Must be called from within a process.
+pub fn schedule_scoped<F, T>(name: &str, priority: Priority, future: F) -> ProcessHandle<T>
+where
+    F: Future<Output = T> + 'static,
+    T: Default + 'static,
+{
+    schedule_scoped_with(name, priority, future, T::default)
+}
+
+/// Schedules a future to run asynchronously as a child of the current process that is killed with the result
+/// of `default_result` when the current process finishes or is killed. Must be called from within a process.
+pub fn schedule_scoped_with<F, T, G>(name: &str, priority: Priority, future: F, default_result: G) -> ProcessHandle<T>
+where
+    F: Future<Output = T> + 'static,
+    T: 'static,
+    G: FnOnce() -> T + 'static,
+{
+    let process_handle = schedule(name, priority, future);
+
+    let mut kern = kernel();
+    let meta = u!(kern.meta_by_pid.get(&process_handle.pid)).clone();
+    let mut meta = meta.borrow_mut();
+    if let Some(parent_pid) = meta.parent_pid {
+        meta.scoped = true;
+        let result = process_handle.result.clone();
+        kern.scoped_children.push_or_insert(
+            parent_pid,
+            ScopedChild {
+                pid: process_handle.pid,
+                set_default_result: Box::new(move || {
+                    result.replace(Some(default_result()));
+                }),
+            },
+        );
+    } else {
+        error!("Tried to schedule scoped {} while there is no current process.", meta);
+    }
+
+    process_handle
+}
+
 /// Kills the process. Can be mildly expensive under some circumstances.
-/// Only a process that has not finished or returned yet may be killed.
-pub fn kill<T>(process_handle: ProcessHandle<T>, result: T) {
+/// Returns `ProcessNotFound` error without changing the result if the process has already finished or was killed.
+pub fn kill<T>(process_handle: ProcessHandle<T>, result: T) -> Result<(), XiError> {
     local_debug!("Killing {}.", process_handle.pid);
 
+    if !process_exists(process_handle.pid) {
+        return Err(ProcessNotFound);
+    }
+
     process_handle.result.replace(Some(result));
 
     kill_without_result_or_cleanup(process_handle.pid);
 
     cleanup_process(process_handle.pid);
+
+    Ok(())
+}
+
+/// Awaits completion of the process for at most given number of ticks. Returns the result of the process if it
+/// finished in time. Otherwise, the process is killed and `None` is returned.
+/// Since a process cannot be sleeping and awaiting at the same time, a separate process with the same priority as the
+/// current one sleeps for given number of ticks and then kills the awaited process, waking up the current one.
+pub async fn timeout<T>(ticks: u32, process_handle: ProcessHandle<T>) -> Option<T>
+where
+    T: 'static,
+{
+    let pid = process_handle.pid;
+    let watchdog_handle = schedule("timeout", current_priority(), async move {
+        sleep(ticks).await;
+        if process_exists(pid) {
+            local_debug!("Process {} timed out.", pid);
+            kill_without_result_or_cleanup(pid);
+            cleanup_process(pid);
+        }
+    });
+
+    let result = poll_fn(|_| {
+        if let Some(result) = process_handle.try_result() {
+            Poll::Ready(Some(result))
+        } else if process_handle.is_finished() {
+            Poll::Ready(None)
+        } else {
+            move_current_process_to_awaiting(pid);
+            Poll::Pending
+        }
+    })
+    .await;
+
+    // The watchdog has already finished if it killed the awaited process.
+    kill(watchdog_handle, ()).ok();
+
+    result
+}
+
+/// Awaits completion of the first of two processes and returns its result.
+/// If `kill_loser` is set, the other process is killed without a result and its handle must not be awaited.
+/// Otherwise, it keeps running and its handle can still be awaited.
+pub async fn select2<A, B>(a: ProcessHandle<A>, b: ProcessHandle<B>, kill_loser: bool) -> Either<A, B>
+where
+    A: Clone + 'static,
+    B: Clone + 'static,
+{
+    let a_result = a.result.clone();
+    let b_result = b.result.clone();
+    let (a_pid, b_pid) = (a.pid, b.pid);
+    let winner = first_finished(vec![(a_pid, erase_result(a)), (b_pid, erase_result(b))], kill_loser).await;
+    if winner == 0 {
+        Either::Left(u!(a_result.borrow().clone()))
+    } else {
+        Either::Right(u!(b_result.borrow().clone()))
+    }
+}
+
+/// Awaits completion of the first of given processes and returns its index and result.
+/// If `kill_losers` is set, the other processes are killed without a result and their handles must not be awaited.
+/// Otherwise, they keep running and their handles can still be awaited.
+pub async fn select_all<T>(process_handles: Vec<ProcessHandle<T>>, kill_losers: bool) -> (usize, T)
+where
+    T: Clone + 'static,
+{
+    let results = process_handles
+        .iter()
+        .map(|process_handle| process_handle.result.clone())
+        .collect::<Vec<_>>();
+    let process_handles = process_handles
+        .into_iter()
+        .map(|process_handle| (process_handle.pid, erase_result(process_handle)))
+        .collect();
+    let winner = first_finished(process_handles, kill_losers).await;
+    let result = u!(results[winner].borrow().clone());
+    (winner, result)
+}
+
+/// A future finishing together with a process, without returning its result.
+type ProcessCompletion = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Converts the handle to a future finishing together with the process, keeping the result in the handle.
+fn erase_result<T>(process_handle: ProcessHandle<T>) -> ProcessCompletion
+where
+    T: Clone + 'static,
+{
+    Box::pin(async move {
+        process_handle.await;
+    })
+}
+
+/// Awaits completion of the first of the processes with given PIDs and returns its index.
+/// Since a process can await only one other process, for each awaited process there is a separate process with the
+/// same priority as the current one awaiting it and signalling a condition the current process waits on.
+async fn first_finished(process_handles: Vec<(PId, ProcessCompletion)>, kill_losers: bool) -> usize {
+    a!(!process_handles.is_empty());
+
+    let condition = Condition::<usize>::default();
+    let mut pids = Vec::new();
+    let mut selecting_process_handles = Vec::new();
+    for (i, (pid, process_future)) in process_handles.into_iter().enumerate() {
+        let condition = condition.clone();
+        pids.push(pid);
+        selecting_process_handles.push(schedule("select", current_priority(), async move {
+            process_future.await;
+            condition.signal(i);
+        }));
+    }
+
+    let winner = condition.await;
+
+    // The selecting processes of processes that already finished have already finished as well.
+    for selecting_process_handle in selecting_process_handles {
+        kill(selecting_process_handle, ()).ok();
+    }
+
+    if kill_losers {
+        for (i, pid) in pids.into_iter().enumerate() {
+            if i != winner && process_exists(pid) {
+                local_debug!("Killing {} that lost the select.", pid);
+                kill_without_result_or_cleanup(pid);
+                cleanup_process(pid);
+            }
+        }
+    }
+
+    winner
 }
 
 /// Kills the process with all its children. Can be mildly expensive under some circumstances.
-/// Only a process that has not finished or returned yet may be killed.
+/// Returns `ProcessNotFound` error if the process has already finished or was killed.
 /// Furthermore, there must not exist any process awaiting completion of the process' children except for the process
 /// or its children themselves.
 // TODO Processes whose parents are already finished but given process is an ancestors will not be killed.
-pub fn kill_tree<T>(process_handle: ProcessHandle<T>, result: T) {
+pub fn kill_tree<T>(process_handle: ProcessHandle<T>, result: T) -> Result<(), XiError> {
     local_debug!("Killing tree of {}.", process_handle.pid);
 
+    if !process_exists(process_handle.pid) {
+        return Err(ProcessNotFound);
+    }
+
     let mut killed_pids = FxHashSet::default();
     let mut awaiting_pids = FxHashSet::default();
     {
@@ -135,85 +341,159 @@ pub fn kill_tree<T>(process_handle: ProcessHandle<T>, result: T) {
     // There should be no process awaiting any killed processes except for the killed ones.
     a!(awaiting_pids.is_empty());
 
-    kill(process_handle, result);
+    kill(process_handle, result)
 }
 
 fn kill_without_result_or_cleanup(pid: PId) {
     let mut kern = kernel();
     // None indicates the process has finished already.
-    if let Some(removed_meta) = kern.meta_by_pid.remove(&pid) {
+    if let Some(removed_meta) = remove_meta(&mut kern, pid) {
         local_debug!("Removing meta of process {}.", pid);
         let meta = removed_meta.borrow();
-        let process = if let Some(wake_up_tick) = meta.wake_up_tick {
+        let maybe_process = if let Some(wake_up_tick) = meta.wake_up_tick {
             drop(meta);
             local_debug!("Process {} was awaiting tick {}.", pid, wake_up_tick);
-            let vec_with_process = u!(kern.sleeping_processes.get_mut(&wake_up_tick));
-            let process = u!(vec_with_process
-                .extract_if(|process| process.borrow_meta().pid == pid).next()
-            );
-            if vec_with_process.is_empty() {
+            let (maybe_process, now_empty) = extract_process(kern.sleeping_processes.get_mut(&wake_up_tick), pid);
+            if now_empty {
                 kern.sleeping_processes.remove(&wake_up_tick);
             }
-            process
+            maybe_process
         } else if let Some(awaited_pid) = meta.awaited_pid {
             drop(meta);
             local_debug!("Process {} was awaiting {}.", pid, awaited_pid);
-            let vec_with_process = u!(kern.awaiting_processes.get_mut(&awaited_pid));
-            let process = u!(vec_with_process
-                .extract_if(|process| process.borrow_meta().pid == pid).next()
-            );
-            if vec_with_process.is_empty() {
+            let (maybe_process, now_empty) = extract_process(kern.awaiting_processes.get_mut(&awaited_pid), pid);
+            if now_empty {
                 kern.awaiting_processes.remove(&awaited_pid);
             }
-            process
+            maybe_process
         } else if let Some(awaited_cid) = meta.awaited_cid {
             drop(meta);
             local_debug!("Process {} was awaiting condition {}.", pid, awaited_cid);
-            let vec_with_process = u!(kern.condition_processes.get_mut(&awaited_cid));
-            let process = u!(vec_with_process
-                .extract_if(|process| process.borrow_meta().pid == pid).next()
-            );
-            if vec_with_process.is_empty() {
+            let (maybe_process, now_empty) = extract_process(kern.condition_processes.get_mut(&awaited_cid), pid);
+            if now_empty {
                 kern.condition_processes.remove(&awaited_cid);
             }
-            process
+            maybe_process
         } else {
             let priority = meta.priority;
             drop(meta);
             local_debug!("Process {} was not awaiting anything.", pid);
-            // Fail on unwrap means that the process was neither awaiting anything nor active,
-            // which should never happen.
-            let vec_with_process = u!(kern.active_processes_by_priorities.get_mut(&priority));
-            let process = u!(vec_with_process
-                .extract_if(|process| process.borrow_meta().pid == pid).next()
-            );
-            if vec_with_process.is_empty() {
+            let processes = kern.active_processes_by_priorities.get_mut(&priority);
+            let maybe_process = processes.and_then(|processes| {
+                let index = processes.iter().position(|process| process.borrow_meta().pid == pid)?;
+                processes.remove(index)
+            });
+            if kern
+                .active_processes_by_priorities
+                .get(&priority)
+                .is_some_and(|processes| processes.is_empty())
+            {
                 kern.active_processes_by_priorities.remove(&priority);
             }
-            process
+            maybe_process
         };
 
         // Dropping the kernel since the process is about to be dropped, along with structures that
         // kill other processes on drop.
         drop(kern);
 
-        trace!("Killed {}.", process);
+        if let Some(process) = maybe_process {
+            trace!("Killed {}.", process);
+        } else {
+            // This means that the process was neither awaiting anything nor active, which should never happen.
+            error!("Process {} to kill was not found where its meta indicated.", pid);
+        }
     } else {
         local_debug!("Meta of process {} was already removed.", pid);
     }
 }
 
+/// Removes the process with given PID from the vector, if both exist. Also returns whether the vector is empty
+/// afterwards.
+fn extract_process(
+    maybe_processes: Option<&mut Vec<Box<dyn Runnable>>>,
+    pid: PId,
+) -> (Option<Box<dyn Runnable>>, bool) {
+    match maybe_processes {
+        Some(processes) => {
+            let maybe_process = processes.extract_if(.., |process| process.borrow_meta().pid == pid).next();
+            (maybe_process, processes.is_empty())
+        }
+        None => (None, false),
+    }
+}
+
 /// Runs all processes in the queue. Should be preceded by waking up all sleeping processes that should wake up this
 /// tick and waking up all processes waiting for travel to finish.
 pub fn run_processes() {
+    run_processes_with_cpu_usage(cpu_usage);
+}
+
+/// Runs processes until the queue is empty or the CPU limit is imminent, in which case the remaining processes
+/// are left in the queue for the next tick and the last call flushes are run instead.
+/// `cpu_usage` returns the used CPU and the CPU limit of the tick.
+fn run_processes_with_cpu_usage<F>(cpu_usage: F)
+where
+    F: Fn() -> (f64, f64),
+{
     while let Some((_, mut process)) = { (|| kernel().active_processes_by_priorities.pop_from_last())() } {
+        let (used_cpu, cpu_limit) = cpu_usage();
+        if is_last_call(used_cpu, cpu_limit) {
+            // Putting the process back at the front of the queue so that it runs first in the next tick.
+            let priority = process.borrow_meta().priority;
+            kernel().active_processes_by_priorities.entry(priority).or_default().push_front(process);
+            let skipped_processes = kernel()
+                .active_processes_by_priorities
+                .values()
+                .rev()
+                .flatten()
+                .map(|process| process.to_string())
+                .collect::<Vec<_>>();
+            run_last_call(used_cpu, &skipped_processes);
+            break;
+        }
+
         trace!("Running {}.", process);
 
         let pid = process.borrow_meta().pid;
 
-        kernel().current_process_meta = Some(process.clone_meta());
+        {
+            let mut kern = kernel();
+            kern.current_process_meta = Some(process.clone_meta());
+            kern.current_process_start_cpu = current_cpu_used();
+        }
+
+        // The process is not used after a panic other than to be dropped, so the kernel stays consistent. However,
+        // any state shared with other processes may be left in an inconsistent state.
+        // The builds deployed to the game cannot unwind. The Node.js version of the MMO servers predates WASM
+        // exception handling, so they are built with `panic_immediate_abort` for the MVP target, and the release
+        // profile aborts as well. There, a panic traps the WASM VM, the JS loop catches the error and the VM is
+        // restarted in the next tick. Only native builds, e.g., tests, recover here.
+        let poll_result = catch_unwind(AssertUnwindSafe(|| process.poll()));
+
+        let process_cpu_used = current_cpu_used() - kernel().current_process_start_cpu;
+        process.borrow_meta().add_cpu_used(process_cpu_used);
+
+        let poll_result = match poll_result {
+            Ok(poll_result) => poll_result,
+            Err(payload) => {
+                if RERAISE_PROCESS_PANICS {
+                    resume_unwind(payload);
+                }
+
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                error!("{} panicked: {}.", process, message);
+                kernel().current_process_meta = None;
+                cleanup_process(pid);
+                continue;
+            }
+        };
 
-        match process.poll() {
+        match poll_result {
             Poll::Ready(()) => {
                 trace!("{} finished.", process);
                 cleanup_process(pid);
@@ -248,25 +528,39 @@ pub fn run_processes() {
 pub fn wake_up_sleeping_processes() {
     let mut kern = kernel();
 
+    // Waking up processes in the order of their wake up ticks until reaching one still in the future.
     while let Some(first_entry) = kern.sleeping_processes.first_entry() {
-        if game_tick() <= *first_entry.key() {
-            for process in first_entry.remove() {
-                process.borrow_meta().wake_up_tick = None;
-                enqueue_process(&mut kern, process);
-            }
-            continue;
+        if game_tick() < *first_entry.key() {
+            break;
+        }
+
+        for process in first_entry.remove() {
+            process.borrow_meta().wake_up_tick = None;
+            enqueue_process(&mut kern, process);
         }
     }
 }
 
 pub(super) fn move_current_process_to_awaiting(awaited_process_pid: PId) {
-    if let Some(meta) = kernel().current_process_meta.as_ref() {
+    let kern = kernel();
+    // A finished process with its result set never gets here since awaiting it resolves immediately.
+    if !kern.meta_by_pid.contains_key(&awaited_process_pid) {
+        error!(
+            "Tried to await {} which has already finished without a result. The current process will never wake up.",
+            awaited_process_pid
+        );
+    }
+    if let Some(meta) = kern.current_process_meta.as_ref() {
         meta.borrow_mut().awaited_pid = Some(awaited_process_pid);
     } else {
         error!("Tried await completion of a process while there is no current process.")
     }
 }
 
+pub(super) fn process_exists(pid: PId) -> bool {
+    kernel().meta_by_pid.contains_key(&pid)
+}
+
 pub(super) fn move_current_process_to_sleeping(wake_up_tick: u32) {
     if let Some(meta) = kernel().current_process_meta.as_ref() {
         meta.borrow_mut().wake_up_tick = Some(wake_up_tick);
@@ -309,7 +603,20 @@ fn cleanup_process(pid: PId) {
     }
 
     // The meta may be not present in `meta_by_pid` anymore if the process was killed.
-    kern.meta_by_pid.remove(&pid);
+    remove_meta(&mut kern, pid);
+    let maybe_scoped_children = kern.scoped_children.remove(&pid);
+
+    drop(kern);
+    remove_mailbox(pid);
+
+    if let Some(scoped_children) = maybe_scoped_children {
+        for scoped_child in scoped_children {
+            local_debug!("Killing scoped {} of finished {}.", scoped_child.pid, pid);
+            (scoped_child.set_default_result)();
+            kill_without_result_or_cleanup(scoped_child.pid);
+            cleanup_process(scoped_child.pid);
+        }
+    }
 
     // TODO Implement in kill somewhere cleanup of conditions no process is awaiting.
     // let meta_ref = meta.borrow();
@@ -333,16 +640,186 @@ fn cleanup_process(pid: PId) {
     // }
 }
 
+/// Enqueues the process at the back of the queue of processes with its priority.
+/// Removes the meta of the process, also removing it from scoped children of its parent.
+fn remove_meta(kern: &mut MappedMutexGuard<RawMutex, Kernel>, pid: PId) -> Option<WrappedProcessMeta> {
+    let meta = kern.meta_by_pid.remove(&pid)?;
+
+    let borrowed_meta = meta.borrow();
+    if let Some(parent_pid) = borrowed_meta.parent_pid.filter(|_| borrowed_meta.scoped) {
+        if let Some(siblings) = kern.scoped_children.get_mut(&parent_pid) {
+            siblings.retain(|sibling| sibling.pid != pid);
+            if siblings.is_empty() {
+                kern.scoped_children.remove(&parent_pid);
+            }
+        }
+    }
+    drop(borrowed_meta);
+
+    Some(meta)
+}
+
 fn enqueue_process(kern: &mut MappedMutexGuard<RawMutex, Kernel>, process: Box<dyn Runnable>) {
     let priority = process.borrow_meta().priority;
     kern.active_processes_by_priorities.push_or_insert(priority, process);
 }
 
+#[cfg(not(test))]
+fn cpu_usage() -> (f64, f64) {
+    (screeps::game::cpu::get_used(), screeps::game::cpu::tick_limit())
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Mocked CPU used in the current tick.
+    static TEST_CPU_USED: std::cell::Cell<f64> = const { std::cell::Cell::new(0.0) };
+}
+
+#[cfg(test)]
+fn cpu_usage() -> (f64, f64) {
+    (TEST_CPU_USED.with(|cpu_used| cpu_used.get()), f64::INFINITY)
+}
+
+/// CPU used so far in the tick, used for accounting CPU of processes.
+fn current_cpu_used() -> f64 {
+    cpu_usage().0
+}
+
+/// Sets the CPU budget of the process and all its descendants. Once a process uses that much CPU in a tick,
+/// `should_finish` returns true in it. Processes scheduled later inherit the budget of their parent.
+pub fn set_process_budget(pid: PId, cpu: f64) -> Result<(), XiError> {
+    let kern = kernel();
+    if !kern.meta_by_pid.contains_key(&pid) {
+        return Err(ProcessNotFound);
+    }
+
+    let mut processes_children: FxHashMap<PId, Vec<PId>> = FxHashMap::default();
+    for (&child_pid, meta) in kern.meta_by_pid.iter() {
+        if let Some(parent_pid) = meta.borrow().parent_pid {
+            processes_children.push_or_insert(parent_pid, child_pid);
+        }
+    }
+
+    let mut queue = vec![pid];
+    while let Some(pid) = queue.pop() {
+        if let Some(meta) = kern.meta_by_pid.get(&pid) {
+            meta.borrow_mut().cpu_budget = Some(cpu);
+        }
+        queue.extend(processes_children.remove(&pid).unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+/// Information about all existing processes, ordered by PID.
+pub fn process_tree() -> Vec<ProcessInfo> {
+    let kern = kernel();
+
+    let process_info = |process: &dyn Runnable, state: ProcessState| {
+        let meta = process.borrow_meta();
+        ProcessInfo {
+            pid: meta.pid,
+            parent_pid: meta.parent_pid,
+            name: meta.name.clone(),
+            priority: meta.priority,
+            state,
+            cpu_used: meta.recent_cpu_used(),
+        }
+    };
+
+    let mut result = Vec::new();
+    for processes in kern.active_processes_by_priorities.values() {
+        result.extend(
+            processes
+                .iter()
+                .map(|process| process_info(process.as_ref(), ProcessState::Active)),
+        );
+    }
+    for (&wake_up_tick, processes) in kern.sleeping_processes.iter() {
+        result.extend(
+            processes
+                .iter()
+                .map(|process| process_info(process.as_ref(), ProcessState::Sleeping(wake_up_tick))),
+        );
+    }
+    for (&awaited_pid, processes) in kern.awaiting_processes.iter() {
+        result.extend(
+            processes
+                .iter()
+                .map(|process| process_info(process.as_ref(), ProcessState::Awaiting(awaited_pid))),
+        );
+    }
+    for (&awaited_cid, processes) in kern.condition_processes.iter() {
+        result.extend(
+            processes
+                .iter()
+                .map(|process| process_info(process.as_ref(), ProcessState::WaitingForCondition(awaited_cid))),
+        );
+    }
+    // The currently running process is not in any of the collections.
+    if let Some(meta) = kern.current_process_meta.as_ref() {
+        let meta = meta.borrow();
+        result.push(ProcessInfo {
+            pid: meta.pid,
+            parent_pid: meta.parent_pid,
+            name: meta.name.clone(),
+            priority: meta.priority,
+            state: ProcessState::Active,
+            cpu_used: meta.recent_cpu_used(),
+        });
+    }
+
+    result.sort_by_key(|info| info.pid);
+    result
+}
+
+/// Renders the processes as a tree with each child indented below its parent. Processes whose parent has already
+/// finished are shown at the top level.
+pub fn render_process_tree(processes: &[ProcessInfo]) -> String {
+    let pids = processes.iter().map(|info| info.pid).collect::<FxHashSet<_>>();
+    let mut children = FxHashMap::default();
+    let mut roots = Vec::new();
+    for info in processes.iter() {
+        match info.parent_pid {
+            Some(parent_pid) if pids.contains(&parent_pid) => children.push_or_insert(parent_pid, info),
+            _ => roots.push(info),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut stack = roots.into_iter().rev().map(|info| (info, 0)).collect::<Vec<_>>();
+    while let Some((info, depth)) = stack.pop() {
+        lines.push(format!(
+            "{}{}-{} ({}) {}",
+            "  ".repeat(depth),
+            info.pid,
+            info.name,
+            info.priority,
+            info.state
+        ));
+        if let Some(info_children) = children.get(&info.pid) {
+            stack.extend(info_children.iter().rev().map(|&child| (child, depth + 1)));
+        }
+    }
+    lines.join("\n")
+}
+
 /// Function to be called to check if the process should finish execution for the tick to fit in its CPU time
-/// constraints. Should be called regularly from long-running processes.
+/// constraints or the CPU budget of the current process. Should be called regularly from long-running processes.
 pub fn should_finish() -> bool {
     // TODO Make this less naive and based on statistics and process parameters.
-    game::cpu::get_used() >= 0.8 * game::cpu::tick_limit()
+    let (used_cpu, cpu_limit) = cpu_usage();
+    if used_cpu >= 0.8 * cpu_limit {
+        return true;
+    }
+
+    let kern = kernel();
+    kern.current_process_meta.as_ref().is_some_and(|meta| {
+        let meta = meta.borrow();
+        meta.cpu_budget.is_some_and(|cpu_budget| {
+            meta.cpu_used_this_tick() + used_cpu - kern.current_process_start_cpu >= cpu_budget
+        })
+    })
 }
 
 /// Borrows metadata of the currently active process. The borrowed reference must be dropped before the next await.
@@ -384,16 +861,24 @@ fn kernel() -> MappedMutexGuard<'static, RawMutex, Kernel> {
 
 #[cfg(test)]
 mod tests {
-    use std::cell::Cell;
-    use crate::utils::game_tick::inc_game_tick;
+    use std::cell::{Cell, RefCell};
+    use crate::utils::game_tick::{game_tick, inc_game_tick};
     use crate::logging::init_logging;
     use log::LevelFilter::Trace;
     use std::sync::Mutex;
     use log::debug;
     use crate::kernel::broadcast::Broadcast;
     use crate::kernel::condition::Condition;
-    use crate::kernel::kernel::{current_process_wrapped_meta, kill, run_processes, schedule, wake_up_sleeping_processes, Kernel, KERNEL};
+    use crate::kernel::kernel::{TEST_CPU_USED, current_process_wrapped_meta, kernel, kill, kill_tree, process_tree, render_process_tree, run_processes, run_processes_with_cpu_usage, schedule, schedule_scoped, schedule_scoped_with, select2, select_all, set_process_budget, should_finish, timeout, wake_up_sleeping_processes, Kernel, KERNEL};
+    use crate::kernel::last_call::register_last_call_flush;
+    use crate::kernel::mailbox::{send, Mailbox, MailboxOverflow};
+    use crate::errors::XiError;
+    use crate::kernel::process::ProcessState;
+    use futures::future::Either;
+    use crate::u;
+    use std::rc::Rc;
     use crate::kernel::sleep::sleep;
+    use crate::kernel::supervisor::{supervise, RestartPolicy};
     use crate::utils::priority::Priority;
 
     /// Reinitializes the kernel.
@@ -471,6 +956,57 @@ mod tests {
         assert_eq!(get_test_counter(), 4);
     }
 
+    async fn sleep_and_return(ticks: u32, result: u8) -> u8 {
+        if ticks > 0 {
+            sleep(ticks).await;
+        }
+        result
+    }
+
+    async fn collect_ready_results() {
+        let mut children = vec![
+            schedule("sleep_and_return", Priority(100), sleep_and_return(0, 1)),
+            schedule("sleep_and_return", Priority(100), sleep_and_return(1, 2)),
+            schedule("sleep_and_return", Priority(100), sleep_and_return(3, 4)),
+        ];
+
+        while !children.is_empty() {
+            children.retain(|child| {
+                if let Some(result) = child.try_result() {
+                    assert!(child.is_finished());
+                    assert!(child.try_result().is_none());
+                    add_to_test_counter(result);
+                    false
+                } else {
+                    assert!(!child.is_finished());
+                    true
+                }
+            });
+            sleep(1).await;
+        }
+    }
+
+    #[test]
+    fn test_try_result() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("collect_ready_results", Priority(100), collect_ready_results());
+        run_processes();
+        assert_eq!(get_test_counter(), 0);
+
+        let mut counters = Vec::new();
+        for _ in 0..4 {
+            inc_game_tick();
+            wake_up_sleeping_processes();
+            run_processes();
+            counters.push(get_test_counter());
+        }
+        assert_eq!(counters, vec![1, 3, 7, 7]);
+    }
+
     async fn do_stuff_and_sleep_and_stuff() {
         add_to_test_counter(1);
         sleep(2).await;
@@ -507,6 +1043,70 @@ mod tests {
         assert_eq!(get_test_counter(), 2);
     }
 
+    async fn do_stuff_and_sleep_for(ticks: u32) {
+        add_to_test_counter(1);
+        sleep(ticks).await;
+        add_to_test_counter(1);
+    }
+
+    fn sleeping_and_active_processes_count() -> (usize, usize) {
+        let kern = kernel();
+        (
+            kern.sleeping_processes.values().map(|processes| processes.len()).sum(),
+            kern.active_processes_by_priorities.values().map(|processes| processes.len()).sum(),
+        )
+    }
+
+    #[test]
+    fn test_sleep_does_not_wake_up_early() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("do_stuff_and_sleep_for", Priority(100), do_stuff_and_sleep_for(3));
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 1);
+        assert_eq!(sleeping_and_active_processes_count(), (1, 0));
+
+        for _ in 0..2 {
+            inc_game_tick();
+            wake_up_sleeping_processes();
+            assert_eq!(sleeping_and_active_processes_count(), (1, 0));
+            run_processes();
+            assert_eq!(get_test_counter(), 1);
+        }
+
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        assert_eq!(sleeping_and_active_processes_count(), (0, 1));
+        run_processes();
+        assert_eq!(get_test_counter(), 2);
+    }
+
+    #[test]
+    fn test_wake_up_stops_at_process_sleeping_until_future() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("do_stuff_and_sleep_for", Priority(100), do_stuff_and_sleep_for(1));
+        schedule("do_stuff_and_sleep_for", Priority(100), do_stuff_and_sleep_for(5));
+        run_processes();
+        assert_eq!(get_test_counter(), 2);
+
+        // Skipping past the wake up tick of the first process, but not the second one.
+        for _ in 0..3 {
+            inc_game_tick();
+        }
+        wake_up_sleeping_processes();
+        assert_eq!(sleeping_and_active_processes_count(), (1, 1));
+        run_processes();
+        assert_eq!(get_test_counter(), 3);
+    }
+
     async fn await_sleeping() {
         add_to_test_counter(1);
         schedule("do_stuff_and_sleep_and_stuff", Priority(100), do_stuff_and_sleep_and_stuff()).await;
@@ -564,6 +1164,132 @@ mod tests {
         assert_eq!(get_test_counter(), 2);
     }
 
+    #[test]
+    fn test_last_call_skips_remaining_processes() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        let flushed = Rc::new(Cell::new(false));
+        let flushed_clone = flushed.clone();
+        register_last_call_flush("test_flush", Priority(100), move || flushed_clone.set(true));
+        schedule("add_one", Priority(100), async { add_to_test_counter(1) });
+        schedule("add_two", Priority(50), async { add_to_test_counter(2) });
+
+        // The CPU usage crosses the threshold after the first process.
+        let cpu_readings = [(10.0, 100.0), (96.0, 100.0)];
+        let reading = Cell::new(0);
+        run_processes_with_cpu_usage(|| {
+            reading.set(reading.get() + 1);
+            cpu_readings[reading.get() - 1]
+        });
+        assert_eq!(get_test_counter(), 1);
+        assert!(flushed.get());
+
+        // The skipped process runs in the next tick.
+        flushed.set(false);
+        run_processes();
+        assert_eq!(get_test_counter(), 3);
+        assert!(!flushed.get());
+    }
+
+    async fn receive_from_mailbox(name: &'static str, capacity: usize, overflow: MailboxOverflow) {
+        let mailbox = u!(Mailbox::<u8>::new(Some(name), capacity, overflow));
+        loop {
+            let message = mailbox.recv().await;
+            add_to_test_counter(message);
+        }
+    }
+
+    #[test]
+    fn test_mailbox_send_before_recv() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("send_to_self", Priority(100), async {
+            let mailbox = u!(Mailbox::<u8>::new(None, 10, MailboxOverflow::Reject));
+            let pid = current_process_wrapped_meta().borrow().pid;
+            u!(send(pid, 2u8));
+            u!(send(pid, 3u8));
+            add_to_test_counter(mailbox.recv().await);
+            add_to_test_counter(mailbox.recv().await);
+        });
+        run_processes();
+        assert_eq!(get_test_counter(), 5);
+    }
+
+    #[test]
+    fn test_mailbox_recv_before_send() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        drop(schedule("receiver", Priority(100), receive_from_mailbox("receiver", 10, MailboxOverflow::Reject)));
+        run_processes();
+        assert_eq!(get_test_counter(), 0);
+        u!(send("receiver", 3u8));
+        assert_eq!(get_test_counter(), 0);
+        run_processes();
+        assert_eq!(get_test_counter(), 3);
+        // Messages of other types are not accepted.
+        assert!(matches!(send("receiver", 3u32), Err(XiError::MailboxNotFound)));
+    }
+
+    #[test]
+    fn test_mailbox_capacity_overflow() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        drop(schedule("dropping", Priority(100), receive_from_mailbox("dropping", 2, MailboxOverflow::DropOldest)));
+        drop(schedule("rejecting", Priority(100), receive_from_mailbox("rejecting", 2, MailboxOverflow::Reject)));
+        run_processes();
+
+        u!(send("dropping", 1u8));
+        u!(send("dropping", 2u8));
+        u!(send("dropping", 4u8));
+        run_processes();
+        assert_eq!(get_test_counter(), 6);
+
+        u!(send("rejecting", 10u8));
+        u!(send("rejecting", 20u8));
+        assert!(matches!(send("rejecting", 40u8), Err(XiError::MailboxFull)));
+        run_processes();
+        assert_eq!(get_test_counter(), 36);
+    }
+
+    #[test]
+    fn test_mailbox_cleanup_after_owner_dies() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        let receiver = schedule("receiver", Priority(100), receive_from_mailbox("receiver", 10, MailboxOverflow::Reject));
+        let pid = receiver.pid;
+        drop(schedule("finishing", Priority(100), async {
+            u!(Mailbox::<u8>::new(Some("finishing"), 10, MailboxOverflow::Reject)).try_recv();
+        }));
+        run_processes();
+        assert!(matches!(send("finishing", 1u8), Err(XiError::MailboxNotFound)));
+
+        u!(kill(receiver, ()));
+        assert!(matches!(send("receiver", 1u8), Err(XiError::MailboxNotFound)));
+        assert!(matches!(send(pid, 1u8), Err(XiError::MailboxNotFound)));
+
+        // The name can be reused by another process.
+        drop(schedule("receiver", Priority(100), receive_from_mailbox("receiver", 10, MailboxOverflow::Reject)));
+        run_processes();
+        u!(send("receiver", 1u8));
+        run_processes();
+        assert_eq!(get_test_counter(), 1);
+    }
+
     #[test]
     fn test_closure() {
         let three = 3u8;
@@ -625,7 +1351,7 @@ mod tests {
             sleep(1).await;
             let ph = process_handle.clone();
             schedule("kill", Priority(100), async {
-                kill(ph, 10);
+                u!(kill(ph, 10));
             });
             let result = process_handle.await;
             add_to_test_counter(result);
@@ -645,6 +1371,311 @@ mod tests {
         assert_eq!(get_test_counter(), 11);
     }
 
+    #[test]
+    fn test_timeout() {
+        let await_with_timeout = async {
+            let process_handle = schedule("sleep_and_return", Priority(100), sleep_and_return(10, 10));
+            let pid = process_handle.pid;
+            let result = timeout(2, process_handle).await;
+            assert!(result.is_none());
+            assert!(!kernel().meta_by_pid.contains_key(&pid));
+            add_to_test_counter(1);
+
+            let process_handle = schedule("sleep_and_return", Priority(100), sleep_and_return(1, 10));
+            let result = timeout(2, process_handle).await;
+            add_to_test_counter(u!(result));
+        };
+
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("await_with_timeout", Priority(100), await_with_timeout);
+        run_processes();
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 0);
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 1);
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 11);
+        // Only the processes sleeping for 10 ticks remained and got killed, so nothing is sleeping anymore.
+        assert!(kernel().sleeping_processes.is_empty());
+        assert!(kernel().meta_by_pid.is_empty());
+    }
+
+    #[test]
+    fn test_supervise_max_restarts() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        let supervisor_handle = supervise(
+            "increment",
+            Priority(100),
+            || async {
+                add_to_test_counter(1);
+            },
+            RestartPolicy::MaxRestarts(3, 100),
+        );
+
+        for _ in 0..20 {
+            run_processes();
+            inc_game_tick();
+            wake_up_sleeping_processes();
+        }
+
+        // The initial run and three restarts.
+        assert_eq!(get_test_counter(), 4);
+        assert!(supervisor_handle.is_finished());
+    }
+
+    #[test]
+    fn test_kill_finished_process() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        let process_handle = schedule("do_stuff", Priority(100), do_stuff());
+        run_processes();
+        assert_eq!(get_test_counter(), 1);
+        assert!(process_handle.is_finished());
+
+        let result = process_handle.result.clone();
+        assert!(matches!(kill(process_handle.clone(), 10), Err(XiError::ProcessNotFound)));
+        assert!(matches!(kill_tree(process_handle, 10), Err(XiError::ProcessNotFound)));
+        // The result of the finished process is not overwritten.
+        assert_eq!(*result.borrow(), Some(1));
+    }
+
+    #[test]
+    fn test_process_tree() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        let parent_handle = schedule("parent", Priority(100), async {
+            schedule("child", Priority(50), do_stuff_and_sleep_for(5)).await;
+        });
+        run_processes();
+
+        let processes = process_tree();
+        assert_eq!(processes.len(), 2);
+        let parent = &processes[0];
+        let child = &processes[1];
+        assert_eq!(parent.pid, parent_handle.pid);
+        assert_eq!(parent.name, "parent");
+        assert_eq!(parent.parent_pid, None);
+        assert_eq!(parent.state, ProcessState::Awaiting(child.pid));
+        assert_eq!(child.name, "child");
+        assert_eq!(child.parent_pid, Some(parent.pid));
+        assert_eq!(child.priority, Priority(50));
+        assert_eq!(child.state, ProcessState::Sleeping(game_tick() + 5));
+
+        let rendered = render_process_tree(&processes);
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("{}-parent", parent.pid)));
+        assert!(lines[1].starts_with(&format!("  {}-child", child.pid)));
+    }
+
+    #[test]
+    fn test_same_priority_fifo_order() {
+        let lock = TEST_MUTEX.lock();
+
+        init_logging(Trace);
+        reset_kernel();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        for id in 1..=3 {
+            let order = order.clone();
+            schedule("append_id", Priority(100), async move {
+                order.borrow_mut().push(id);
+                sleep(1).await;
+                order.borrow_mut().push(id);
+            });
+        }
+
+        run_processes();
+        assert_eq!(*order.borrow(), vec![1, 2, 3]);
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(*order.borrow(), vec![1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_process_panic() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("panic", Priority(100), async {
+            panic!("test panic");
+        });
+        schedule("do_stuff", Priority(100), do_stuff());
+        run_processes();
+        assert_eq!(get_test_counter(), 1);
+
+        let kern = kernel();
+        assert!(kern.meta_by_pid.is_empty());
+        assert!(kern.active_processes_by_priorities.is_empty());
+        assert!(kern.current_process_meta.is_none());
+    }
+
+    #[test]
+    fn test_scoped_children_killed_with_parent() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        let parent_handle = schedule("parent", Priority(100), async {
+            let loop_forever = || async {
+                loop {
+                    sleep(1).await;
+                }
+            };
+            let first_child = schedule_scoped("first_child", Priority(50), loop_forever());
+            let second_child = schedule_scoped_with("second_child", Priority(50), loop_forever(), || ());
+            add_to_test_counter(1);
+            (first_child, second_child)
+        });
+        run_processes();
+        assert_eq!(get_test_counter(), 1);
+
+        let (first_child, second_child) = u!(parent_handle.try_result());
+        let kern = kernel();
+        assert!(!kern.meta_by_pid.contains_key(&parent_handle.pid));
+        assert!(!kern.meta_by_pid.contains_key(&first_child.pid));
+        assert!(!kern.meta_by_pid.contains_key(&second_child.pid));
+        assert!(kern.sleeping_processes.is_empty());
+        assert!(kern.scoped_children.is_empty());
+        drop(kern);
+        assert_eq!(first_child.try_result(), Some(()));
+        assert_eq!(second_child.try_result(), Some(()));
+    }
+
+    #[test]
+    fn test_select2() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("select", Priority(100), async {
+            let short = schedule("short", Priority(100), sleep_and_return(1, 1));
+            let long = schedule("long", Priority(100), sleep_and_return(3, 10));
+            match select2(long.clone(), short, false).await {
+                Either::Left(_) => add_to_test_counter(100),
+                Either::Right(result) => add_to_test_counter(result),
+            }
+            add_to_test_counter(long.await);
+        });
+        run_processes();
+        let mut counters = Vec::new();
+        for _ in 0..3 {
+            inc_game_tick();
+            wake_up_sleeping_processes();
+            run_processes();
+            counters.push(get_test_counter());
+        }
+        assert_eq!(counters, vec![1, 1, 11]);
+        assert!(kernel().meta_by_pid.is_empty());
+    }
+
+    #[test]
+    fn test_select_all_killing_losers() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("select", Priority(100), async {
+            let process_handles = vec![
+                schedule("long", Priority(100), sleep_and_return(3, 10)),
+                schedule("short", Priority(100), sleep_and_return(1, 1)),
+                schedule("long", Priority(100), sleep_and_return(5, 20)),
+            ];
+            let (winner, result) = select_all(process_handles, true).await;
+            add_to_test_counter(winner as u8 + result);
+        });
+        run_processes();
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 2);
+        let kern = kernel();
+        assert!(kern.meta_by_pid.is_empty());
+        assert!(kern.sleeping_processes.is_empty());
+    }
+
+    #[test]
+    fn test_awaiting_finished_process() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        let child_handle = schedule("do_stuff", Priority(100), do_stuff());
+        run_processes();
+        assert_eq!(get_test_counter(), 1);
+        assert!(child_handle.is_finished());
+
+        let parent_handle = schedule("parent", Priority(100), async move {
+            let result = child_handle.await;
+            add_to_test_counter(result);
+        });
+        run_processes();
+        assert_eq!(get_test_counter(), 2);
+        assert!(parent_handle.is_finished());
+        assert!(kernel().awaiting_processes.is_empty());
+    }
+
+    fn add_test_cpu_used(cpu: f64) {
+        TEST_CPU_USED.with(|cpu_used| cpu_used.set(cpu_used.get() + cpu));
+    }
+
+    #[test]
+    fn test_process_cpu_budget() {
+        let lock = TEST_MUTEX.lock();
+
+        init_logging(Trace);
+        reset_kernel();
+        let results = Rc::new(RefCell::new(Vec::new()));
+        for (name, cpu_budget) in [("limited", 5.0), ("unlimited", 100.0)] {
+            let results = results.clone();
+            let process_handle = schedule(name, Priority(100), async move {
+                loop {
+                    for _ in 0..2 {
+                        add_test_cpu_used(3.0);
+                        results.borrow_mut().push((name, should_finish()));
+                    }
+                    sleep(1).await;
+                }
+            });
+            u!(set_process_budget(process_handle.pid, cpu_budget));
+        }
+
+        run_processes();
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+
+        let expected_tick_results = [("limited", false), ("limited", true), ("unlimited", false), ("unlimited", false)];
+        assert_eq!(*results.borrow(), expected_tick_results.repeat(2));
+        TEST_CPU_USED.with(|cpu_used| cpu_used.set(0.0));
+    }
+
     #[test]
     fn test_two_processes_waiting_for_one() {
         let lock = TEST_MUTEX.lock();
@@ -712,6 +1743,63 @@ mod tests {
         assert_eq!(get_test_counter(), 84);
     }
 
+    #[test]
+    fn test_broadcast_wait_multiple_waiters() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("waker", Priority(100), async {
+            let broadcast = Broadcast::<u8>::default();
+            // A broadcast before waiting is not received.
+            broadcast.broadcast(100);
+            for _ in 0..2 {
+                let wait = broadcast.wait();
+                schedule("waiter", Priority(99), async move {
+                    add_to_test_counter(wait.await);
+                });
+            }
+            sleep(1).await;
+            broadcast.broadcast(21);
+        });
+        run_processes();
+        assert_eq!(get_test_counter(), 0);
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 42);
+    }
+
+    #[test]
+    fn test_broadcast_wait_for() {
+        let lock = TEST_MUTEX.lock();
+
+        set_test_counter(0);
+        init_logging(Trace);
+        reset_kernel();
+        schedule("waker", Priority(100), async {
+            let broadcast = Broadcast::<u8>::default();
+            let wait_for_even = broadcast.wait_for(|value| value % 2 == 0);
+            schedule("waiter", Priority(99), async move {
+                add_to_test_counter(wait_for_even.await);
+            });
+            sleep(1).await;
+            broadcast.broadcast(3);
+            sleep(1).await;
+            broadcast.broadcast(4);
+        });
+        run_processes();
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 0);
+        inc_game_tick();
+        wake_up_sleeping_processes();
+        run_processes();
+        assert_eq!(get_test_counter(), 4);
+    }
+
     #[test]
     fn test_broadcast() {
         let lock = TEST_MUTEX.lock();
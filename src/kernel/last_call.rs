@@ -0,0 +1,103 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use log::warn;
+use crate::config::LAST_CALL_CPU_FRACTION;
+use crate::utils::game_tick::game_tick;
+use crate::utils::priority::Priority;
+
+/// A closure to run when the CPU limit is imminent, e.g., to issue intents already decided on.
+struct LastCallFlush {
+    name: &'static str,
+    priority: Priority,
+    flush: Box<dyn Fn()>,
+}
+
+thread_local! {
+    static LAST_CALL_FLUSHES: RefCell<Vec<LastCallFlush>> = const { RefCell::new(Vec::new()) };
+    static LAST_CALL_TICK: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// Registers a closure to be run in the last call of a tick. Closures with higher priority run
+/// first. They should be cheap since they run when there is almost no CPU left.
+pub fn register_last_call_flush<F>(name: &'static str, priority: Priority, flush: F)
+where
+    F: Fn() + 'static,
+{
+    LAST_CALL_FLUSHES.with(|flushes| {
+        flushes.borrow_mut().push(LastCallFlush {
+            name,
+            priority,
+            flush: Box::new(flush),
+        });
+    });
+}
+
+/// Whether the used CPU is so close to the limit that no more processes should run in this tick.
+pub fn is_last_call(used_cpu: f64, cpu_limit: f64) -> bool {
+    used_cpu >= LAST_CALL_CPU_FRACTION * cpu_limit
+}
+
+/// Whether the last call happened in the current tick.
+pub fn is_after_last_call() -> bool {
+    LAST_CALL_TICK.with(|tick| tick.get() == Some(game_tick()))
+}
+
+/// Runs all registered flushes in the order of their priorities and reports the skipped processes.
+/// Returns the names of the flushes in the order they were run.
+pub fn run_last_call(used_cpu: f64, skipped_processes: &[String]) -> Vec<&'static str> {
+    LAST_CALL_TICK.with(|tick| tick.set(Some(game_tick())));
+
+    let flushed = LAST_CALL_FLUSHES.with(|flushes| {
+        let flushes = flushes.borrow();
+        let mut ordered_flushes = flushes.iter().collect::<Vec<_>>();
+        ordered_flushes.sort_by_key(|flush| Reverse(flush.priority));
+        ordered_flushes
+            .into_iter()
+            .map(|flush| {
+                (flush.flush)();
+                flush.name
+            })
+            .collect::<Vec<_>>()
+    });
+
+    warn!(
+        "Last call at {:.1} CPU. Skipped {} processes: {}. Flushed: {}.",
+        used_cpu,
+        skipped_processes.len(),
+        skipped_processes.join(", "),
+        flushed.join(", ")
+    );
+
+    flushed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::kernel::last_call::{is_after_last_call, is_last_call, register_last_call_flush, run_last_call};
+    use crate::utils::priority::Priority;
+
+    #[test]
+    fn test_is_last_call() {
+        assert!(!is_last_call(0.0, 100.0));
+        assert!(!is_last_call(94.9, 100.0));
+        assert!(is_last_call(95.0, 100.0));
+        assert!(is_last_call(120.0, 100.0));
+        assert!(!is_last_call(450.0, 500.0));
+    }
+
+    #[test]
+    fn test_last_call_flush_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for (name, priority) in [("low", Priority(10)), ("high", Priority(200)), ("medium", Priority(100))] {
+            let log = log.clone();
+            register_last_call_flush(name, priority, move || log.borrow_mut().push(name));
+        }
+
+        let flushed = run_last_call(99.0, &["skipped_process".to_string()]);
+        assert_eq!(flushed, vec!["high", "medium", "low"]);
+        assert_eq!(*log.borrow(), vec!["high", "medium", "low"]);
+        assert!(is_after_last_call());
+    }
+}
@@ -0,0 +1,192 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use log::trace;
+use rustc_hash::FxHashMap;
+use crate::errors::XiError;
+use crate::kernel::condition::CId;
+use crate::kernel::kernel::{current_process_wrapped_meta, move_current_process_to_waiting_for_condition, signal_condition};
+use crate::kernel::process::PId;
+
+/// Where to send a message, either the PID of the process owning the mailbox or its name.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MailboxAddress {
+    Pid(PId),
+    Name(String),
+}
+
+impl From<PId> for MailboxAddress {
+    fn from(pid: PId) -> Self {
+        MailboxAddress::Pid(pid)
+    }
+}
+
+impl From<&str> for MailboxAddress {
+    fn from(name: &str) -> Self {
+        MailboxAddress::Name(name.into())
+    }
+}
+
+/// What happens when sending a message to a full mailbox.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MailboxOverflow {
+    /// The oldest message is dropped to make space for the new one.
+    DropOldest,
+    /// The new message is rejected with `XiError::MailboxFull`.
+    Reject,
+}
+
+#[derive(Debug)]
+struct MailboxQueue<T> {
+    cid: CId,
+    messages: VecDeque<T>,
+    capacity: usize,
+    overflow: MailboxOverflow,
+}
+
+thread_local! {
+    /// Queues of all mailboxes by the PID of the owner, each of them being `Rc<RefCell<MailboxQueue<T>>>`.
+    static MAILBOXES: RefCell<FxHashMap<PId, Rc<dyn Any>>> = RefCell::new(FxHashMap::default());
+    /// PIDs of the owners of named mailboxes.
+    static MAILBOX_NAMES: RefCell<FxHashMap<String, PId>> = RefCell::new(FxHashMap::default());
+}
+
+/// A queue of messages of type `T` sent to a single process. There can be only one mailbox per
+/// process. It is unregistered when dropped or when the owner process ends.
+#[derive(Debug)]
+pub struct Mailbox<T> {
+    pid: PId,
+    queue: Rc<RefCell<MailboxQueue<T>>>,
+}
+
+impl<T> Mailbox<T>
+where
+    T: 'static,
+{
+    /// Creates a mailbox of the current process, optionally also addressable by a name.
+    pub fn new(name: Option<&str>, capacity: usize, overflow: MailboxOverflow) -> Result<Self, XiError> {
+        let pid = current_process_wrapped_meta().borrow().pid;
+
+        let name_taken = name.is_some_and(|name| MAILBOX_NAMES.with(|names| names.borrow().contains_key(name)));
+        if name_taken || MAILBOXES.with(|mailboxes| mailboxes.borrow().contains_key(&pid)) {
+            return Err(XiError::MailboxAlreadyExists);
+        }
+
+        let queue = Rc::new(RefCell::new(MailboxQueue {
+            cid: CId::new(),
+            messages: VecDeque::new(),
+            capacity,
+            overflow,
+        }));
+        MAILBOXES.with(|mailboxes| mailboxes.borrow_mut().insert(pid, queue.clone()));
+        if let Some(name) = name {
+            MAILBOX_NAMES.with(|names| names.borrow_mut().insert(name.into(), pid));
+        }
+
+        Ok(Mailbox { pid, queue })
+    }
+
+    /// Waits until there is a message in the mailbox and returns the oldest one.
+    pub fn recv(&self) -> Recv<'_, T> {
+        Recv { mailbox: self }
+    }
+
+    /// Returns the oldest message in the mailbox, if any, without waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.borrow_mut().messages.pop_front()
+    }
+}
+
+impl<T> Drop for Mailbox<T> {
+    fn drop(&mut self) {
+        // The mailbox may have been already removed when the owner process ended.
+        let queue_ptr = Rc::as_ptr(&self.queue) as *const ();
+        let registered = MAILBOXES.with(|mailboxes| {
+            mailboxes
+                .borrow()
+                .get(&self.pid)
+                .is_some_and(|queue| Rc::as_ptr(queue) as *const () == queue_ptr)
+        });
+        if registered {
+            remove_mailbox(self.pid);
+        }
+    }
+}
+
+/// Sends a message to the mailbox at given address. Fails when there is no mailbox for messages
+/// of this type at the address or the mailbox is full and rejects new messages.
+pub fn send<A, T>(address: A, message: T) -> Result<(), XiError>
+where
+    A: Into<MailboxAddress>,
+    T: 'static,
+{
+    let pid = match address.into() {
+        MailboxAddress::Pid(pid) => pid,
+        MailboxAddress::Name(name) => {
+            MAILBOX_NAMES.with(|names| names.borrow().get(&name).copied()).ok_or(XiError::MailboxNotFound)?
+        }
+    };
+
+    let queue = MAILBOXES
+        .with(|mailboxes| mailboxes.borrow().get(&pid).cloned())
+        .and_then(|queue| queue.downcast::<RefCell<MailboxQueue<T>>>().ok())
+        .ok_or(XiError::MailboxNotFound)?;
+
+    let cid = {
+        let mut queue = queue.borrow_mut();
+        if queue.messages.len() >= queue.capacity {
+            match queue.overflow {
+                MailboxOverflow::DropOldest => {
+                    trace!("Dropping the oldest message in the mailbox of {}.", pid);
+                    queue.messages.pop_front();
+                }
+                MailboxOverflow::Reject => {
+                    return Err(XiError::MailboxFull);
+                }
+            }
+        }
+        queue.messages.push_back(message);
+        queue.cid
+    };
+
+    signal_condition(cid);
+
+    Ok(())
+}
+
+/// Removes the mailbox of the process, e.g., after it has ended.
+pub(super) fn remove_mailbox(pid: PId) {
+    if MAILBOXES.with(|mailboxes| mailboxes.borrow_mut().remove(&pid)).is_some() {
+        MAILBOX_NAMES.with(|names| names.borrow_mut().retain(|_, &mut owner_pid| owner_pid != pid));
+    }
+}
+
+/// Future returned by `Mailbox::recv`.
+pub struct Recv<'a, T> {
+    mailbox: &'a Mailbox<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut queue = self.mailbox.queue.borrow_mut();
+        match queue.messages.pop_front() {
+            Some(message) => {
+                trace!("Message received.");
+                Poll::Ready(message)
+            }
+            None => {
+                trace!("Waiting for a message.");
+                let cid = queue.cid;
+                drop(queue);
+                move_current_process_to_waiting_for_condition(cid);
+                Poll::Pending
+            }
+        }
+    }
+}
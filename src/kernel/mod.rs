@@ -4,5 +4,9 @@ pub mod process;
 pub mod process_handle;
 pub mod runnable;
 pub mod sleep;
+pub mod supervisor;
 pub mod wait_until_some;
-pub mod kernel;
\ No newline at end of file
+pub mod kernel;
+pub mod last_call;
+pub mod mailbox;
+pub mod dump;
\ No newline at end of file
@@ -8,6 +8,7 @@ use std::rc::Rc;
 use std::sync::Arc;
 use std::task::{Context, Poll, Wake, Waker};
 use crate::kernel::condition::CId;
+use crate::utils::game_tick::game_tick;
 use crate::utils::priority::Priority;
 use crate::utils::uid::UId;
 
@@ -19,11 +20,44 @@ pub struct ProcessMeta {
     pub name: String,
     pub pid: PId,
     pub parent_pid: Option<PId>,
+    /// Whether the process is killed when its parent finishes.
+    pub scoped: bool,
     pub priority: Priority,
     pub creeps: Vec<String>,
     pub wake_up_tick: Option<u32>,
     pub awaited_pid: Option<PId>,
     pub awaited_cid: Option<CId>,
+    /// Maximum CPU the process may use in a tick before `should_finish` returns true.
+    pub cpu_budget: Option<f64>,
+    /// CPU used by the process in the tick `cpu_used_tick`.
+    pub cpu_used: f64,
+    pub cpu_used_tick: u32,
+}
+
+impl ProcessMeta {
+    /// Registers CPU used by the process in the current tick.
+    pub fn add_cpu_used(&mut self, cpu: f64) {
+        if self.cpu_used_tick != game_tick() {
+            self.cpu_used_tick = game_tick();
+            self.cpu_used = 0.0;
+        }
+        self.cpu_used += cpu;
+    }
+
+    /// CPU used by the process in the current tick.
+    pub fn cpu_used_this_tick(&self) -> f64 {
+        if self.cpu_used_tick == game_tick() {
+            self.cpu_used
+        } else {
+            0.0
+        }
+    }
+
+    /// CPU used by the process in the current tick or, if it has not run in it yet, in the previous
+    /// one. `None` if it has not run in either of them.
+    pub fn recent_cpu_used(&self) -> Option<f64> {
+        (self.cpu_used_tick != 0 && self.cpu_used_tick + 1 >= game_tick()).then_some(self.cpu_used)
+    }
 }
 
 impl Display for ProcessMeta {
@@ -38,6 +72,38 @@ impl Display for ProcessMeta {
 
 pub type WrappedProcessMeta = Rc<RefCell<ProcessMeta>>;
 
+/// State of a process derived from the kernel collection it is in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ProcessState {
+    Active,
+    Sleeping(u32),
+    Awaiting(PId),
+    WaitingForCondition(CId),
+}
+
+impl Display for ProcessState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessState::Active => write!(f, "active"),
+            ProcessState::Sleeping(wake_up_tick) => write!(f, "sleeping until {}", wake_up_tick),
+            ProcessState::Awaiting(pid) => write!(f, "awaiting {}", pid),
+            ProcessState::WaitingForCondition(cid) => write!(f, "waiting for {}", cid),
+        }
+    }
+}
+
+/// Information about a process for introspection.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: PId,
+    pub parent_pid: Option<PId>,
+    pub name: String,
+    pub priority: Priority,
+    pub state: ProcessState,
+    /// CPU used by the process as given by `ProcessMeta::recent_cpu_used`.
+    pub cpu_used: Option<f64>,
+}
+
 pub(super) struct Process<T> {
     pub meta: WrappedProcessMeta,
     pub result: Rc<RefCell<Option<T>>>,
@@ -59,11 +125,15 @@ impl<T> Process<T> {
             name,
             pid,
             parent_pid,
+            scoped: false,
             priority,
             creeps: Vec::new(),
             wake_up_tick: None,
             awaited_pid: None,
             awaited_cid: None,
+            cpu_budget: None,
+            cpu_used: 0.0,
+            cpu_used_tick: 0,
         };
         let wrapped_meta = Rc::new(RefCell::new(meta));
 
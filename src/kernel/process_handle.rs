@@ -1,4 +1,4 @@
-use crate::kernel::kernel::move_current_process_to_awaiting;
+use crate::kernel::kernel::{move_current_process_to_awaiting, process_exists};
 use crate::kernel::process::PId;
 use derive_more::Constructor;
 use std::cell::RefCell;
@@ -15,6 +15,19 @@ pub struct ProcessHandle<T> {
     pub(super) result: Rc<RefCell<Option<T>>>,
 }
 
+impl<T> ProcessHandle<T> {
+    /// Takes the result of the process out of the handle if it has finished, without suspending the current
+    /// process. Returns `None` if the process has not finished yet or the result was already taken.
+    pub fn try_result(&self) -> Option<T> {
+        self.result.borrow_mut().take()
+    }
+
+    /// Checks whether the process has finished, i.e., it is no longer known to the kernel.
+    pub fn is_finished(&self) -> bool {
+        !process_exists(self.pid)
+    }
+}
+
 impl<T> Future for ProcessHandle<T>
 where
     T: Clone,
@@ -0,0 +1,73 @@
+use crate::kernel::kernel::schedule;
+use crate::kernel::process_handle::ProcessHandle;
+use crate::kernel::sleep::sleep;
+use crate::utils::game_tick::game_tick;
+use crate::utils::priority::Priority;
+use log::{error, warn};
+use std::collections::VecDeque;
+use std::future::Future;
+
+/// Maximum number of ticks to wait before restarting a supervised process.
+const MAX_RESTART_BACKOFF: u32 = 64;
+
+/// Policy of restarting a supervised process after it returns or is killed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RestartPolicy {
+    /// Always restart the process.
+    Always,
+    /// Restart the process at most given number of times within given number of ticks, then give up.
+    MaxRestarts(u32, u32),
+    /// Never restart the process, only log its termination.
+    Never,
+}
+
+/// Schedules a process created by the factory and reschedules it according to the restart policy whenever it
+/// terminates. Consecutive restarts are delayed by exponential backoff, starting at one tick and reset once the
+/// process runs for at least `MAX_RESTART_BACKOFF` ticks.
+/// Returns the handle of the supervisor process, which finishes when the process is no longer to be restarted.
+pub fn supervise<G, F>(name: &'static str, priority: Priority, factory: G, policy: RestartPolicy) -> ProcessHandle<()>
+where
+    G: Fn() -> F + 'static,
+    F: Future<Output = ()> + 'static,
+{
+    schedule("supervisor", priority, async move {
+        let mut restart_ticks = VecDeque::new();
+        let mut backoff = 1;
+
+        loop {
+            let start_tick = game_tick();
+            schedule(name, priority, factory()).await;
+            warn!("Supervised process {} terminated after {} ticks.", name, game_tick() - start_tick);
+
+            if game_tick() - start_tick >= MAX_RESTART_BACKOFF {
+                backoff = 1;
+            }
+
+            match policy {
+                RestartPolicy::Always => {}
+                RestartPolicy::MaxRestarts(max_restarts, window_ticks) => {
+                    while restart_ticks
+                        .front()
+                        .is_some_and(|&tick| tick + window_ticks <= game_tick())
+                    {
+                        restart_ticks.pop_front();
+                    }
+
+                    if restart_ticks.len() as u32 >= max_restarts {
+                        error!(
+                            "Supervised process {} terminated after {} restarts within {} ticks. Giving up.",
+                            name, max_restarts, window_ticks
+                        );
+                        break;
+                    }
+                }
+                RestartPolicy::Never => break,
+            }
+
+            sleep(backoff).await;
+            backoff = (2 * backoff).min(MAX_RESTART_BACKOFF);
+            restart_ticks.push_back(game_tick());
+        }
+    })
+}
+
@@ -0,0 +1,85 @@
+use log::{debug, warn};
+use screeps::{Boost, Part, ResourceType, StructureLab, LAB_BOOST_ENERGY, LAB_BOOST_MINERAL};
+use screeps::game::get_object_by_id_typed;
+use screeps::StructureType::Lab;
+use crate::creeps::creeps::CreepRef;
+use crate::errors::XiError;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::transfers::get_used_capacities_with_object;
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::hauling::transfers::register_transfer;
+use crate::kernel::sleep::sleep;
+use crate::room_states::room_states::with_room_state;
+use crate::travel::travel::move_to_cached;
+
+/// The body part boosted by the compound.
+fn boosted_part(boost: Boost) -> Part {
+    match boost {
+        Boost::Harvest(_) | Boost::BuildAndRepair(_) | Boost::Dismantle(_) | Boost::UpgradeController(_) => Part::Work,
+        Boost::Attack(_) => Part::Attack,
+        Boost::RangedAttack(_) => Part::RangedAttack,
+        Boost::Heal(_) => Part::Heal,
+        Boost::Carry(_) => Part::Carry,
+        Boost::Move(_) => Part::Move,
+        Boost::Tough(_) => Part::Tough,
+    }
+}
+
+/// Boosts all parts of a freshly spawned creep with given compounds, one after another, by moving
+/// it next to the labs in its home room containing them. Compounds that no lab has enough of,
+/// together with the energy needed, are skipped, as boosting is optional.
+pub async fn boost_creep(creep_ref: &CreepRef, compounds: &[ResourceType]) -> Result<(), XiError> {
+    for &compound in compounds {
+        let Some(boost) = compound.boost() else {
+            warn!("Tried to boost a creep with {}, which is not a boost.", compound);
+            continue;
+        };
+
+        let (home_room, parts) = {
+            let creep = creep_ref.borrow();
+            (creep.home_room, creep.body.count_parts(boosted_part(boost)) as u32)
+        };
+        let Some(room_name) = home_room else {
+            return Ok(());
+        };
+        if parts == 0 {
+            continue;
+        }
+
+        let labs = with_room_state(room_name, |room_state| {
+            room_state.structures_with_type::<StructureLab>(Lab).collect::<Vec<_>>()
+        }).unwrap_or_default();
+        // The amounts are checked only once since the labs do not lose minerals other than by
+        // boosting or withdrawing.
+        let lab = labs.into_iter().find(|&(_, id)| {
+            get_object_by_id_typed(&id).is_some_and(|lab| {
+                let used_capacities = get_used_capacities_with_object(&lab, id.into(), AfterAllTransfers);
+                used_capacities.get(&compound).is_some_and(|&amount| amount >= parts * LAB_BOOST_MINERAL)
+                    && used_capacities.get(&ResourceType::Energy).is_some_and(|&amount| amount >= parts * LAB_BOOST_ENERGY)
+            })
+        });
+        let Some((lab_xy, lab_id)) = lab else {
+            debug!("No lab in {} has enough {} to boost {}.", room_name, compound, creep_ref.borrow().name);
+            continue;
+        };
+
+        move_to_cached(creep_ref, lab_xy.to_pos(room_name), 1).await?;
+
+        let lab = get_object_by_id_typed(&lab_id).ok_or(XiError::ObjectDoesNotExist)?;
+        let result = {
+            let mut creep = creep_ref.borrow_mut();
+            lab.boost_creep(creep.screeps_obj()?, None)
+        };
+        if result.is_ok() {
+            register_transfer(lab_id.into(), compound, -((parts * LAB_BOOST_MINERAL) as i32));
+            register_transfer(lab_id.into(), ResourceType::Energy, -((parts * LAB_BOOST_ENERGY) as i32));
+        } else {
+            return Err(XiError::LabBoostFailed);
+        }
+
+        // The boost is visible in the next tick.
+        sleep(1).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,3 @@
+pub mod reactions;
+pub mod run_labs;
+pub mod boost_creep;
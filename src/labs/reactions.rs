@@ -0,0 +1,199 @@
+use std::cmp::min;
+use rustc_hash::FxHashMap;
+use screeps::{ResourceType, RoomXY};
+use crate::geometry::room_xy::RoomXYUtils;
+
+/// Maximum range between an output lab and both input labs for the reaction to run.
+const LAB_REACTION_RANGE: u8 = 2;
+
+/// A reaction producing `amount` of `output` from the same amount of each of the inputs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Reaction {
+    pub inputs: [ResourceType; 2],
+    pub output: ResourceType,
+    pub amount: u32,
+}
+
+/// The reactions needed to have `amount` of the target compound, in the order they have to be
+/// run, with the inputs of each reaction either in the stock or produced by the earlier ones.
+/// Resources in the stock are used up first. Empty if there is enough of the target already.
+/// `None` if there is not enough of the base minerals.
+pub fn reaction_chain(target: ResourceType, amount: u32, stock: &FxHashMap<ResourceType, u32>) -> Option<Vec<Reaction>> {
+    let mut remaining_stock = stock.clone();
+    let mut chain = Vec::new();
+    add_reactions(target, amount, &mut remaining_stock, &mut chain).then_some(chain)
+}
+
+fn add_reactions(
+    resource_type: ResourceType,
+    amount: u32,
+    stock: &mut FxHashMap<ResourceType, u32>,
+    chain: &mut Vec<Reaction>
+) -> bool {
+    let available = stock.get(&resource_type).copied().unwrap_or(0);
+    let used = min(available, amount);
+    if used > 0 {
+        stock.insert(resource_type, available - used);
+    }
+    let missing = amount - used;
+    if missing == 0 {
+        return true;
+    }
+
+    let Some(inputs) = resource_type.reaction_components() else {
+        return false;
+    };
+    for input in inputs {
+        if !add_reactions(input, missing, stock, chain) {
+            return false;
+        }
+    }
+    chain.push(Reaction {
+        inputs,
+        output: resource_type,
+        amount: missing,
+    });
+    true
+}
+
+/// Roles of the labs of the room.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LabAssignment {
+    /// Labs holding the inputs of the reaction.
+    pub inputs: [RoomXY; 2],
+    /// Labs running the reaction, all within range of both inputs.
+    pub outputs: Vec<RoomXY>,
+    /// Output lab reserved for holding the boost compound and energy for boosting creeps.
+    /// Only assigned when there is more than one output lab.
+    pub boost: Option<RoomXY>,
+}
+
+/// Assigns the pair of labs that the most other labs are in range of as inputs and these other
+/// labs as outputs. In the labs stamp, these are the two inner labs. `None` if no other lab is
+/// in range of any pair.
+pub fn assign_labs(lab_xys: &[RoomXY]) -> Option<LabAssignment> {
+    let mut lab_xys = lab_xys.to_vec();
+    lab_xys.sort();
+
+    let in_range_of_both = |first: RoomXY, second: RoomXY| {
+        lab_xys
+            .iter()
+            .copied()
+            .filter(move |&xy| {
+                xy != first && xy != second && xy.dist(first) <= LAB_REACTION_RANGE && xy.dist(second) <= LAB_REACTION_RANGE
+            })
+    };
+
+    let mut best_inputs = None;
+    let mut best_outputs_count = 0;
+    for (i, &first) in lab_xys.iter().enumerate() {
+        for &second in lab_xys[i + 1..].iter() {
+            let outputs_count = in_range_of_both(first, second).count();
+            if outputs_count > best_outputs_count {
+                best_inputs = Some([first, second]);
+                best_outputs_count = outputs_count;
+            }
+        }
+    }
+
+    let inputs = best_inputs?;
+    let mut outputs = in_range_of_both(inputs[0], inputs[1]).collect::<Vec<_>>();
+    let boost = if outputs.len() > 1 { outputs.pop() } else { None };
+    Some(LabAssignment {
+        inputs,
+        outputs,
+        boost,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashMap;
+    use screeps::ResourceType::*;
+    use screeps::RoomXY;
+    use screeps::StructureType::Lab;
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::labs::reactions::{assign_labs, reaction_chain, Reaction};
+    use crate::room_planning::stamps::labs_stamp;
+
+    #[test]
+    fn test_reaction_chain_from_base_minerals() {
+        let stock = FxHashMap::from_iter([
+            (Utrium, 1000),
+            (Lemergium, 1000),
+            (Zynthium, 1000),
+            (Keanium, 1000),
+            (Hydrogen, 2000),
+            (Oxygen, 1000),
+            (Catalyst, 1000),
+        ]);
+        let chain = reaction_chain(CatalyzedGhodiumAcid, 500, &stock).unwrap();
+        let outputs = chain.iter().map(|reaction| reaction.output).collect::<Vec<_>>();
+        assert_eq!(outputs, vec![UtriumLemergite, ZynthiumKeanite, Ghodium, GhodiumHydride, Hydroxide, GhodiumAcid, CatalyzedGhodiumAcid]);
+        assert_eq!(chain[6], Reaction {
+            inputs: [GhodiumAcid, Catalyst],
+            output: CatalyzedGhodiumAcid,
+            amount: 500,
+        });
+        assert!(chain.iter().all(|reaction| reaction.amount == 500));
+    }
+
+    #[test]
+    fn test_reaction_chain_uses_stock() {
+        let stock = FxHashMap::from_iter([
+            (CatalyzedGhodiumAcid, 200),
+            (GhodiumHydride, 100),
+            (Ghodium, 1000),
+            (Hydrogen, 1000),
+            (Hydroxide, 300),
+            (Catalyst, 300),
+        ]);
+        let chain = reaction_chain(CatalyzedGhodiumAcid, 500, &stock).unwrap();
+        assert_eq!(chain, vec![
+            Reaction { inputs: [Ghodium, Hydrogen], output: GhodiumHydride, amount: 200 },
+            Reaction { inputs: [GhodiumHydride, Hydroxide], output: GhodiumAcid, amount: 300 },
+            Reaction { inputs: [GhodiumAcid, Catalyst], output: CatalyzedGhodiumAcid, amount: 300 },
+        ]);
+
+        // Nothing to do when there is enough of the target.
+        assert_eq!(reaction_chain(CatalyzedGhodiumAcid, 200, &stock), Some(Vec::new()));
+        // Not enough catalyst.
+        assert_eq!(reaction_chain(CatalyzedGhodiumAcid, 600, &stock), None);
+    }
+
+    #[test]
+    fn test_stamp_labs_assignment() {
+        let offset = (20, 30);
+        let lab_xys = labs_stamp()
+            .iter()
+            .filter(|(_, tile)| tile.iter().any(|structure_type| structure_type == Lab))
+            .map(|(xy, _)| RoomXY::try_from((xy.x.u8() + offset.0, xy.y.u8() + offset.1)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lab_xys.len(), 10);
+
+        let assignment = assign_labs(&lab_xys).unwrap();
+        let inputs = assignment.inputs;
+        // The two inner labs.
+        for xy in [(22, 31), (21, 32)] {
+            assert!(inputs.contains(&RoomXY::try_from(xy).unwrap()));
+        }
+        assert_eq!(assignment.outputs.len(), 7);
+        assert!(assignment.boost.is_some());
+        for xy in assignment.outputs.iter().chain(assignment.boost.iter()) {
+            assert!(!inputs.contains(xy));
+        }
+    }
+
+    #[test]
+    fn test_few_labs_assignment() {
+        let xys = [(10, 10), (11, 10), (12, 12)].map(|xy| RoomXY::try_from(xy).unwrap());
+        let assignment = assign_labs(&xys).unwrap();
+        assert_eq!(assignment.inputs, [xys[0], xys[1]]);
+        assert_eq!(assignment.outputs, vec![xys[2]]);
+        assert_eq!(assignment.boost, None);
+
+        // Too far apart.
+        let xys = [(10, 10), (11, 10), (14, 14)].map(|xy| RoomXY::try_from(xy).unwrap());
+        assert_eq!(assign_labs(&xys), None);
+    }
+}
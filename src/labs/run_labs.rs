@@ -0,0 +1,293 @@
+use std::cmp::min;
+use rustc_hash::FxHashMap;
+use screeps::{ObjectId, RawObjectId, ResourceType, RoomName, RoomXY, StructureLab, StructureStorage, StructureTerminal, LAB_MINERAL_CAPACITY, LAB_REACTION_AMOUNT};
+use screeps::game::get_object_by_id_typed;
+use screeps::StructureType::{Lab, Storage, Terminal};
+use crate::config::{TARGET_BOOST_COMPOUND_AMOUNT, UPGRADER_BOOST_COMPOUND};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::requests::{HaulRequest, HaulRequestHandle};
+use crate::hauling::requests::HaulRequestKind::{DepositRequest, WithdrawRequest};
+use crate::hauling::requests::HaulRequestTargetKind::{RegularTarget, StorageTarget};
+use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::{get_used_capacities_with_object, register_transfer};
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::kernel::wait_until_some::wait_until_some;
+use crate::labs::reactions::{assign_labs, reaction_chain, LabAssignment, Reaction};
+use crate::local_debug;
+use crate::room_maintenance::fill_structures_with_energy::schedule_missing_energy_deposit;
+use crate::room_states::room_states::with_room_state;
+use crate::room_states::utils::loop_until_structures_change;
+use crate::utils::priority::Priority;
+use crate::utils::result_utils::ResultUtils;
+
+const DEBUG: bool = true;
+
+/// Reaction products are withdrawn from the output labs once there is at least this much of them.
+const MIN_LAB_WITHDRAW_AMOUNT: u32 = 500;
+const LAB_HAUL_PRIORITY: Priority = Priority(50);
+
+/// A haul needed to bring the contents of a lab to the desired ones.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum LabHaul {
+    Withdraw(ResourceType, u32),
+    Deposit(ResourceType, u32),
+}
+
+/// The mineral and its amount each lab should contain. Input labs hold the inputs of the current
+/// reaction, output labs are emptied of its products and the boost lab holds the boost compound.
+fn desired_lab_contents(assignment: &LabAssignment, reaction: Option<&Reaction>) -> FxHashMap<RoomXY, Option<(ResourceType, u32)>> {
+    let mut contents = FxHashMap::default();
+    for (i, &xy) in assignment.inputs.iter().enumerate() {
+        contents.insert(xy, reaction.map(|reaction| (reaction.inputs[i], min(reaction.amount, LAB_MINERAL_CAPACITY))));
+    }
+    for &xy in assignment.outputs.iter() {
+        contents.insert(xy, reaction.map(|reaction| (reaction.output, 0)));
+    }
+    if let Some(xy) = assignment.boost {
+        contents.insert(xy, Some((UPGRADER_BOOST_COMPOUND, LAB_MINERAL_CAPACITY)));
+    }
+    contents
+}
+
+/// The haul to bring the mineral in a lab to the desired one, limited by the amount available in
+/// the storage and terminal. Minerals other than the desired one are withdrawn entirely.
+fn lab_haul(current: Option<(ResourceType, u32)>, desired: Option<(ResourceType, u32)>, available: u32) -> Option<LabHaul> {
+    match (current, desired) {
+        (Some((resource_type, amount)), Some((desired_resource_type, desired_amount))) if resource_type == desired_resource_type => {
+            if amount >= desired_amount + MIN_LAB_WITHDRAW_AMOUNT {
+                Some(LabHaul::Withdraw(resource_type, amount - desired_amount))
+            } else {
+                let missing_amount = min(desired_amount.saturating_sub(amount), available);
+                (missing_amount > 0).then_some(LabHaul::Deposit(resource_type, missing_amount))
+            }
+        }
+        (Some((resource_type, amount)), _) => Some(LabHaul::Withdraw(resource_type, amount)),
+        (None, Some((resource_type, desired_amount))) => {
+            let missing_amount = min(desired_amount, available);
+            (missing_amount > 0).then_some(LabHaul::Deposit(resource_type, missing_amount))
+        }
+        (None, None) => None,
+    }
+}
+
+/// The mineral in the lab after all transfers this tick, if any.
+fn lab_mineral(lab: &StructureLab, id: ObjectId<StructureLab>) -> Option<(ResourceType, u32)> {
+    get_used_capacities_with_object(lab, id.into(), AfterAllTransfers)
+        .into_iter()
+        .find(|&(resource_type, amount)| resource_type != ResourceType::Energy && amount > 0)
+}
+
+/// Produces the upgrader boost compound in the labs of the room, running one reaction of its
+/// chain at a time. Minerals are hauled between the storage or terminal and the labs as needed,
+/// and the boost lab is kept filled with the compound and energy for boosting creeps.
+pub async fn run_labs(room_name: RoomName) {
+    loop {
+        let (labs, assignment, storage_id, terminal_id) = wait_until_some(|| {
+            with_room_state(room_name, |room_state| {
+                let labs = room_state.structures_with_type::<StructureLab>(Lab).collect::<FxHashMap<_, _>>();
+                let assignment = assign_labs(&labs.keys().copied().collect::<Vec<_>>())?;
+                let (_, storage_id) = room_state.structures_with_type::<StructureStorage>(Storage).next()?;
+                let terminal_id = room_state.structures_with_type::<StructureTerminal>(Terminal).next();
+                Some((labs, assignment, storage_id, terminal_id))
+            }).flatten()
+        }).await;
+
+        let mut lab_requests: FxHashMap<RoomXY, HaulRequestHandle> = FxHashMap::default();
+        let mut energy_requests: FxHashMap<RoomXY, HaulRequestHandle> = FxHashMap::default();
+        let mut terminal_requests: FxHashMap<ResourceType, HaulRequestHandle> = FxHashMap::default();
+
+        loop_until_structures_change(room_name, 1, || {
+            let Some(storage) = get_object_by_id_typed(&storage_id) else {
+                return true;
+            };
+            let storage_stock = get_used_capacities_with_object(&storage, storage_id.into(), AfterAllTransfers);
+            let terminal = terminal_id.and_then(|(xy, id)| Some((xy, id, get_object_by_id_typed(&id)?)));
+            let terminal_stock = terminal
+                .as_ref()
+                .map(|(_, id, terminal)| get_used_capacities_with_object(terminal, (*id).into(), AfterAllTransfers))
+                .unwrap_or_default();
+            let lab_objects = labs
+                .iter()
+                .filter_map(|(&xy, &id)| Some((xy, (id, get_object_by_id_typed(&id)?))))
+                .collect::<FxHashMap<_, _>>();
+            let lab_minerals = lab_objects
+                .iter()
+                .map(|(&xy, (id, lab))| (xy, lab_mineral(lab, *id)))
+                .collect::<FxHashMap<_, _>>();
+
+            let mut stock = storage_stock.clone();
+            for (&resource_type, &amount) in terminal_stock.iter() {
+                *stock.entry(resource_type).or_default() += amount;
+            }
+            for &(resource_type, amount) in lab_minerals.values().flatten() {
+                *stock.entry(resource_type).or_default() += amount;
+            }
+
+            // The first reaction of the chain only needs resources already in the room.
+            let reaction = reaction_chain(UPGRADER_BOOST_COMPOUND, TARGET_BOOST_COMPOUND_AMOUNT, &stock)
+                .and_then(|chain| chain.first().copied());
+            local_debug!("Current reaction in {}: {:?}.", room_name, reaction);
+
+            let desired_contents = desired_lab_contents(&assignment, reaction.as_ref());
+            for (&xy, &desired) in desired_contents.iter() {
+                let Some(&(id, _)) = lab_objects.get(&xy) else {
+                    continue;
+                };
+                let current = lab_minerals.get(&xy).copied().flatten();
+                let available = |resource_type| {
+                    storage_stock.get(&resource_type).copied().unwrap_or(0)
+                        + terminal_stock.get(&resource_type).copied().unwrap_or(0)
+                };
+                let haul = lab_haul(current, desired, desired.map_or(0, |(resource_type, _)| available(resource_type)));
+                let replaced_request = lab_requests.remove(&xy);
+                let Some(haul) = haul else {
+                    continue;
+                };
+
+                local_debug!("Scheduling {:?} for the lab at {} in {}.", haul, xy, room_name);
+                let (kind, resource_type, amount) = match haul {
+                    LabHaul::Withdraw(resource_type, amount) => (WithdrawRequest, resource_type, amount),
+                    LabHaul::Deposit(resource_type, amount) => (DepositRequest, resource_type, amount),
+                };
+                let mut request = HaulRequest::new(kind, room_name, resource_type, id, RegularTarget, false, xy.to_pos(room_name));
+                request.amount = amount;
+                request.priority = LAB_HAUL_PRIORITY;
+                lab_requests.insert(xy, schedule_haul(request, replaced_request));
+
+                // Minerals missing from the storage are taken from the terminal.
+                let missing_in_storage = amount.saturating_sub(storage_stock.get(&resource_type).copied().unwrap_or(0));
+                if kind == DepositRequest && missing_in_storage > 0 {
+                    if let Some((terminal_xy, terminal_id, _)) = terminal.as_ref() {
+                        let mut withdraw_request = HaulRequest::new(
+                            WithdrawRequest,
+                            room_name,
+                            resource_type,
+                            *terminal_id,
+                            StorageTarget,
+                            false,
+                            terminal_xy.to_pos(room_name)
+                        );
+                        withdraw_request.amount = missing_in_storage;
+                        withdraw_request.priority = LAB_HAUL_PRIORITY;
+                        let replaced_request = terminal_requests.remove(&resource_type);
+                        terminal_requests.insert(resource_type, schedule_haul(withdraw_request, replaced_request));
+                    }
+                }
+            }
+
+            // Boosting uses energy as well.
+            if let Some(boost_xy) = assignment.boost {
+                if let Some(&(id, _)) = lab_objects.get(&boost_xy) {
+                    let replaced_request = energy_requests.remove(&boost_xy);
+                    if let Some(handle) = schedule_missing_energy_deposit(
+                        room_name,
+                        RawObjectId::from(id).into(),
+                        boost_xy.to_pos(room_name),
+                        replaced_request
+                    ) {
+                        energy_requests.insert(boost_xy, handle);
+                    }
+                }
+            }
+
+            if let Some(reaction) = reaction {
+                run_reaction(&assignment, &lab_objects, &reaction);
+            }
+
+            true
+        }).await;
+    }
+}
+
+/// Runs the reaction in all output labs that are off cooldown, as long as the input labs have
+/// enough of the inputs.
+fn run_reaction(
+    assignment: &LabAssignment,
+    lab_objects: &FxHashMap<RoomXY, (ObjectId<StructureLab>, StructureLab)>,
+    reaction: &Reaction
+) {
+    let [Some((input1_id, input1)), Some((input2_id, input2))] = assignment.inputs.map(|xy| lab_objects.get(&xy)) else {
+        return;
+    };
+
+    for xy in assignment.outputs.iter() {
+        let Some((output_id, output)) = lab_objects.get(xy) else {
+            continue;
+        };
+        if output.cooldown() > 0 {
+            continue;
+        }
+
+        let inputs_ready = [(input1_id, input1), (input2_id, input2)]
+            .iter()
+            .zip(reaction.inputs)
+            .all(|(&(&id, lab), resource_type)| {
+                lab_mineral(lab, id).is_some_and(|mineral| mineral.0 == resource_type && mineral.1 >= LAB_REACTION_AMOUNT)
+            });
+        let output_free = match lab_mineral(output, *output_id) {
+            Some((resource_type, amount)) => resource_type == reaction.output && amount + LAB_REACTION_AMOUNT <= LAB_MINERAL_CAPACITY,
+            None => true,
+        };
+        if !inputs_ready || !output_free {
+            continue;
+        }
+
+        let result = output.run_reaction(input1, input2);
+        if result.is_ok() {
+            register_transfer((*input1_id).into(), reaction.inputs[0], -(LAB_REACTION_AMOUNT as i32));
+            register_transfer((*input2_id).into(), reaction.inputs[1], -(LAB_REACTION_AMOUNT as i32));
+            register_transfer((*output_id).into(), reaction.output, LAB_REACTION_AMOUNT as i32);
+        }
+        result.warn_if_err("Failed to run a reaction");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::ResourceType::{Catalyst, CatalyzedGhodiumAcid, GhodiumAcid, Hydroxide, Keanium};
+    use screeps::{RoomXY, LAB_MINERAL_CAPACITY};
+    use crate::labs::reactions::{LabAssignment, Reaction};
+    use crate::labs::run_labs::{desired_lab_contents, lab_haul, LabHaul};
+
+    #[test]
+    fn test_lab_hauls() {
+        // Filling an input lab up to the amount needed by the reaction.
+        assert_eq!(lab_haul(None, Some((Hydroxide, 1000)), 5000), Some(LabHaul::Deposit(Hydroxide, 1000)));
+        assert_eq!(lab_haul(Some((Hydroxide, 800)), Some((Hydroxide, 1000)), 5000), Some(LabHaul::Deposit(Hydroxide, 200)));
+        assert_eq!(lab_haul(Some((Hydroxide, 800)), Some((Hydroxide, 1000)), 50), Some(LabHaul::Deposit(Hydroxide, 50)));
+        assert_eq!(lab_haul(Some((Hydroxide, 1000)), Some((Hydroxide, 1000)), 5000), None);
+        assert_eq!(lab_haul(None, Some((Hydroxide, 1000)), 0), None);
+        // Emptying a lab of a different mineral.
+        assert_eq!(lab_haul(Some((Keanium, 300)), Some((Hydroxide, 1000)), 5000), Some(LabHaul::Withdraw(Keanium, 300)));
+        assert_eq!(lab_haul(Some((Keanium, 300)), None, 5000), Some(LabHaul::Withdraw(Keanium, 300)));
+        // Products are withdrawn from the output labs in batches.
+        assert_eq!(lab_haul(Some((GhodiumAcid, 100)), Some((GhodiumAcid, 0)), 0), None);
+        assert_eq!(lab_haul(Some((GhodiumAcid, 600)), Some((GhodiumAcid, 0)), 0), Some(LabHaul::Withdraw(GhodiumAcid, 600)));
+    }
+
+    #[test]
+    fn test_desired_lab_contents() {
+        let xy = |x, y| RoomXY::try_from((x, y)).unwrap();
+        let assignment = LabAssignment {
+            inputs: [xy(10, 10), xy(11, 11)],
+            outputs: vec![xy(12, 12)],
+            boost: Some(xy(9, 9)),
+        };
+        let reaction = Reaction {
+            inputs: [GhodiumAcid, Catalyst],
+            output: CatalyzedGhodiumAcid,
+            amount: 5000,
+        };
+        let contents = desired_lab_contents(&assignment, Some(&reaction));
+        assert_eq!(contents[&xy(10, 10)], Some((GhodiumAcid, LAB_MINERAL_CAPACITY)));
+        assert_eq!(contents[&xy(11, 11)], Some((Catalyst, LAB_MINERAL_CAPACITY)));
+        assert_eq!(contents[&xy(12, 12)], Some((CatalyzedGhodiumAcid, 0)));
+        assert_eq!(contents[&xy(9, 9)], Some((CatalyzedGhodiumAcid, LAB_MINERAL_CAPACITY)));
+
+        // Without a reaction, only the boost lab is kept filled.
+        let contents = desired_lab_contents(&assignment, None);
+        assert_eq!(contents[&xy(10, 10)], None);
+        assert_eq!(contents[&xy(12, 12)], None);
+        assert_eq!(contents[&xy(9, 9)], Some((CatalyzedGhodiumAcid, LAB_MINERAL_CAPACITY)));
+    }
+}
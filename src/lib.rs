@@ -8,10 +8,14 @@
 #![allow(clippy::comparison_chain)]
 
 use js_sys::JsString;
+use screeps::{ResourceType, RoomName};
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+use crate::room_maintenance::manage_terminal::{queue_terminal_transfer, TerminalTransfer};
 
 mod algorithms;
 mod config;
+mod cpu_management;
 mod construction;
 mod consts;
 mod fresh_number;
@@ -36,6 +40,8 @@ mod room_maintenance;
 mod travel;
 mod defense;
 mod flags;
+mod expansion;
+mod labs;
 
 // `wasm_bindgen` to expose the function to JS.
 #[wasm_bindgen]
@@ -49,7 +55,117 @@ pub fn game_loop() {
     game_loop::game_loop();
 }
 
+/// Sets the log level of modules with given path prefix, e.g., `set_log_level("xi::room_planning",
+/// "debug")`, or the default level for an empty prefix. An empty level removes the prefix.
+#[wasm_bindgen]
+pub fn set_log_level(prefix: String, level: String) -> JsString {
+    if level.is_empty() {
+        logging::with_log_levels(|log_levels| log_levels.remove(&prefix));
+        return format!("Removed the log level of {:?}.", prefix).into();
+    }
+    match level.parse::<log::LevelFilter>() {
+        Ok(level) => {
+            logging::with_log_levels(|log_levels| log_levels.set(&prefix, level));
+            format!("Set the log level of {:?} to {}.", prefix, level).into()
+        }
+        Err(_) => "Invalid log level, expected one of off, error, warn, info, debug or trace.".into(),
+    }
+}
+
 #[wasm_bindgen(js_name = take_log)]
 pub fn take_log() -> JsString {
     logging::take_log().join("\n").into()
 }
+
+/// Takes the log like `take_log`, keeping only the lines with at least the given level, e.g.,
+/// `take_log_filtered("warn")`.
+#[wasm_bindgen]
+pub fn take_log_filtered(min_level: String) -> JsString {
+    match min_level.parse::<log::LevelFilter>() {
+        Ok(min_level) => logging::take_log_filtered(min_level).join("\n").into(),
+        Err(_) => "Invalid log level, expected one of off, error, warn, info, debug or trace.".into(),
+    }
+}
+
+#[wasm_bindgen]
+pub fn ps() -> JsString {
+    kernel::kernel::render_process_tree(&kernel::kernel::process_tree()).into()
+}
+
+/// Shows the spans measured by the profiler in the previous tick and their CPU usage statistics over
+/// the last ticks.
+#[wasm_bindgen]
+pub fn profile() -> JsString {
+    profiler::report().into()
+}
+
+/// Dumps the process tree, active processes by priority, sleeping processes by wake up tick and
+/// what waiting processes wait for.
+#[wasm_bindgen]
+pub fn dump() -> JsString {
+    kernel::dump::dump().into()
+}
+
+/// Queues sending a resource from the terminal of an owned room to another room, e.g.,
+/// `send_resources("W1N1", "W2N1", "energy", 10000)`.
+#[wasm_bindgen]
+pub fn send_resources(from: String, to: String, resource_type: String, amount: u32) -> JsString {
+    let (Ok(from), Ok(to)) = (RoomName::new(&from), RoomName::new(&to)) else {
+        return "Invalid room name.".into();
+    };
+    let Some(resource_type) = ResourceType::from_js_value(&JsValue::from_str(&resource_type)) else {
+        return "Invalid resource type.".into();
+    };
+    queue_terminal_transfer(TerminalTransfer {
+        from,
+        to,
+        resource_type,
+        amount,
+    });
+    format!("Queued sending {} {} from {} to {}.", amount, resource_type, from, to).into()
+}
+
+/// Shows the heatmap of the matrix with given name in the rooms the planner registers it for, e.g.,
+/// `show_heatmap("interior_dm")`, `show_heatmap("exits_dm")` or `show_heatmap("tower_damage")`.
+/// An empty name hides it.
+#[wasm_bindgen]
+pub fn show_heatmap(name: String) -> JsString {
+    if name.is_empty() {
+        visualization::heatmap::select_heatmap(None);
+        "Hid the heatmap.".into()
+    } else {
+        let text = format!("Showing the {} heatmap once it is registered.", name);
+        visualization::heatmap::select_heatmap(Some(name));
+        text.into()
+    }
+}
+
+/// Prints the terrain, controller, sources and mineral of a scanned room in the format of the offline planning
+/// fixtures, e.g., `room_fixture("W1N1")`.
+#[wasm_bindgen]
+pub fn room_fixture(room_name: String) -> JsString {
+    let Ok(room_name) = RoomName::new(&room_name) else {
+        return "Invalid room name.".into();
+    };
+    let fixture = room_states::room_states::with_room_state(room_name, |room_state| {
+        room_planning::room_planner::PlannerInput::from_room_state(room_state).map(|input| input.to_fixture())
+    });
+    match fixture {
+        Some(Ok(fixture)) => fixture.into(),
+        Some(Err(err)) => format!("Room {} cannot be planned: {}.", room_name, err).into(),
+        None => format!("Room {} was not scanned.", room_name).into(),
+    }
+}
+
+/// Sets the seed of the random numbers used, e.g., by the next started room planner, to replay planning with the seed
+/// from the logs, e.g., `set_seed("1234567890")`.
+#[wasm_bindgen]
+pub fn set_seed(seed: String) -> JsString {
+    match seed.parse::<u64>() {
+        Ok(seed) => {
+            utils::random::set_seed(seed);
+            format!("Set the random seed to {}.", seed).into()
+        }
+        Err(_) => "Invalid seed.".into(),
+    }
+}
@@ -1,30 +1,157 @@
 use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use log::{Level, LevelFilter};
 use log::LevelFilter::*;
+use serde::{Deserialize, Serialize};
+use crate::config::{LOG_BUFFER_MAX_BYTES, LOG_BUFFER_MAX_LINES, LOG_LEVEL};
 use crate::utils::game_tick::game_tick;
 
 thread_local! {
-    static LOG: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    static LOG: RefCell<LogBuffer> = RefCell::new(LogBuffer::new(LOG_BUFFER_MAX_LINES, LOG_BUFFER_MAX_BYTES));
+    static LOG_LEVELS: RefCell<LogLevels> = RefCell::new(LogLevels::new(LOG_LEVEL));
+}
+
+/// Log levels by module path prefixes, e.g., `xi::room_planning` for the room planner and its
+/// submodules. Records are logged with the level of the longest prefix of their module path. The
+/// empty prefix holds the default level.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LogLevels {
+    levels: BTreeMap<String, LevelFilter>,
+}
+
+impl LogLevels {
+    pub fn new(default_level: LevelFilter) -> Self {
+        LogLevels {
+            levels: BTreeMap::from([(String::new(), default_level)]),
+        }
+    }
+
+    /// Sets the level of the module path prefix, or the default level for the empty prefix.
+    pub fn set(&mut self, prefix: &str, level: LevelFilter) {
+        self.levels.insert(prefix.to_string(), level);
+    }
+
+    /// Removes the level of the module path prefix so that it is logged with the level of a shorter
+    /// one. The default level cannot be removed.
+    pub fn remove(&mut self, prefix: &str) {
+        if !prefix.is_empty() {
+            self.levels.remove(prefix);
+        }
+    }
+
+    /// The level of the longest registered prefix of the module path.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.levels
+            .iter()
+            .filter(|(prefix, _)| {
+                prefix.is_empty()
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(LOG_LEVEL, |(_, &level)| level)
+    }
+
+    pub fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        LogLevels::new(LOG_LEVEL)
+    }
+}
+
+pub fn with_log_levels<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut LogLevels) -> R,
+{
+    LOG_LEVELS.with(|log_levels| f(&mut log_levels.borrow_mut()))
+}
+
+/// Log lines waiting to be taken, limited in the number of lines and their total length so that the
+/// buffer does not grow without bound when they are not taken.
+#[derive(Debug)]
+pub struct LogBuffer {
+    lines: VecDeque<(Level, String)>,
+    bytes: usize,
+    dropped: usize,
+    max_lines: usize,
+    max_bytes: usize,
+}
+
+impl LogBuffer {
+    pub fn new(max_lines: usize, max_bytes: usize) -> Self {
+        LogBuffer {
+            lines: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+            max_lines,
+            max_bytes,
+        }
+    }
+
+    /// Adds the line, dropping the oldest lines if the buffer exceeds its limits.
+    pub fn push(&mut self, level: Level, line: String) {
+        self.bytes += line.len();
+        self.lines.push_back((level, line));
+        while self.lines.len() > self.max_lines || self.bytes > self.max_bytes {
+            let Some((_, dropped_line)) = self.lines.pop_front() else {
+                break;
+            };
+            self.bytes -= dropped_line.len();
+            self.dropped += 1;
+        }
+    }
+
+    /// Takes the lines with at least the given level, in the order they were logged, clearing the
+    /// buffer. If any lines were dropped, they are preceded by a line with the number of them.
+    pub fn take(&mut self, min_level: LevelFilter) -> Vec<String> {
+        let mut result = Vec::new();
+        if self.dropped > 0 {
+            result.push(format!("… {} lines dropped", self.dropped));
+        }
+        result.extend(
+            self.lines
+                .drain(..)
+                .filter(|&(level, _)| level <= min_level)
+                .map(|(_, line)| line),
+        );
+        self.bytes = 0;
+        self.dropped = 0;
+        result
+    }
 }
 
 pub fn take_log() -> Vec<String> {
-    LOG.with(|log| {
-        log.replace(Vec::new())
-    })
+    take_log_filtered(Trace)
+}
+
+/// Takes the log lines, keeping only the ones with at least the given level, e.g., `Warn` for only
+/// warnings and errors.
+pub fn take_log_filtered(min_level: LevelFilter) -> Vec<String> {
+    LOG.with(|log| log.borrow_mut().take(min_level))
 }
 
 struct JsLog;
 struct JsNotify;
 
 impl log::Log for JsLog {
-    fn enabled(&self, _: &log::Metadata<'_>) -> bool {
-        true
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        with_log_levels(|log_levels| log_levels.enabled(metadata))
     }
 
     fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
         #[cfg(not(test))]
         #[cfg(not(feature = "separate_messages"))]
         LOG.with(|log| {
-            log.borrow_mut().push(format!("{}", record.args()));
+            log.borrow_mut().push(record.level(), format!("{}", record.args()));
         });
         #[cfg(not(test))]
         #[cfg(feature = "separate_messages")]
@@ -52,7 +179,8 @@ impl log::Log for JsNotify {
 #[cfg(test)]
 static LOGGING_INITIALIZED: std::sync::Mutex<bool> = std::sync::Mutex::new(false);
 
-pub fn init_logging(verbosity: log::LevelFilter) {
+/// Sets up logging with the given default level, see `LogLevels`.
+pub fn init_logging(verbosity: LevelFilter) {
     #[cfg(test)]
     {
         let mut lock = LOGGING_INITIALIZED.lock().unwrap();
@@ -64,8 +192,11 @@ pub fn init_logging(verbosity: log::LevelFilter) {
         *lock = true;
     }
 
+    with_log_levels(|log_levels| log_levels.set("", verbosity));
+
+    // The records are filtered by `JsLog` according to the log levels which may change at runtime.
     fern::Dispatch::new()
-        .level(verbosity)
+        .level(Trace)
         .format(|out, message, record| {
             #[cfg(not(test))]
             let postfix = "</span>";
@@ -109,7 +240,7 @@ pub fn init_logging(verbosity: log::LevelFilter) {
                     postfix
                 ))
             } else {
-                out.finish(format_args!("{}", message))
+                out.finish(format_args!("{}: {}", record.target(), message))
             }
         })
         .chain(Box::new(JsLog) as Box<dyn log::Log>)
@@ -125,3 +256,73 @@ pub fn init_logging(verbosity: log::LevelFilter) {
         .apply()
         .expect("Failed to set up logging. init_logging should only be called once per WASM VM instance.");
 }
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use log::LevelFilter::{Debug, Info, Trace, Warn};
+    use crate::logging::{LogBuffer, LogLevels};
+
+    fn enabled(log_levels: &LogLevels, target: &str, level: Level) -> bool {
+        log_levels.enabled(&log::Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn test_log_levels_by_module_prefix() {
+        let mut log_levels = LogLevels::new(Info);
+        log_levels.set("xi::room_planning", Debug);
+        log_levels.set("xi::hauling", Warn);
+
+        assert!(enabled(&log_levels, "xi::room_planning::room_planner", Level::Debug));
+        assert!(!enabled(&log_levels, "xi::room_planning::room_planner", Level::Trace));
+        assert!(enabled(&log_levels, "xi::room_planning", Level::Debug));
+        assert!(enabled(&log_levels, "xi::hauling::haul_resources", Level::Warn));
+        assert!(!enabled(&log_levels, "xi::hauling::haul_resources", Level::Info));
+        // Prefixes only match whole module names.
+        assert!(!enabled(&log_levels, "xi::hauling_stats", Level::Debug));
+        assert!(enabled(&log_levels, "xi::hauling_stats", Level::Info));
+        assert!(!enabled(&log_levels, "xi::kernel::kernel", Level::Debug));
+
+        // The longest prefix takes precedence.
+        log_levels.set("xi::room_planning::room_planner", Trace);
+        assert!(enabled(&log_levels, "xi::room_planning::room_planner", Level::Trace));
+        assert!(!enabled(&log_levels, "xi::room_planning::plan", Level::Trace));
+
+        log_levels.remove("xi::hauling");
+        assert!(enabled(&log_levels, "xi::hauling::haul_resources", Level::Info));
+        log_levels.remove("");
+        log_levels.set("", Warn);
+        assert!(!enabled(&log_levels, "xi::kernel::kernel", Level::Info));
+        assert_eq!(log_levels.level("xi::room_planning::plan"), Debug);
+    }
+
+    #[test]
+    fn test_log_buffer_drops_oldest_lines() {
+        let mut log_buffer = LogBuffer::new(3, 1000);
+        for i in 0..5 {
+            log_buffer.push(Level::Info, format!("line {}", i));
+        }
+        assert_eq!(log_buffer.take(Trace), vec!["… 2 lines dropped", "line 2", "line 3", "line 4"]);
+        assert!(log_buffer.take(Trace).is_empty());
+
+        // The total length of lines is limited too.
+        let mut log_buffer = LogBuffer::new(100, 20);
+        log_buffer.push(Level::Info, "a".repeat(10));
+        log_buffer.push(Level::Info, "b".repeat(10));
+        log_buffer.push(Level::Info, "c".repeat(5));
+        assert_eq!(log_buffer.take(Trace), vec!["… 1 lines dropped".to_string(), "b".repeat(10), "c".repeat(5)]);
+        log_buffer.push(Level::Info, "d".repeat(30));
+        assert_eq!(log_buffer.take(Trace), vec!["… 1 lines dropped"]);
+    }
+
+    #[test]
+    fn test_log_buffer_filtered_by_level() {
+        let mut log_buffer = LogBuffer::new(3, 1000);
+        log_buffer.push(Level::Debug, "debug 0".to_string());
+        log_buffer.push(Level::Warn, "warn 1".to_string());
+        log_buffer.push(Level::Info, "info 2".to_string());
+        log_buffer.push(Level::Error, "error 3".to_string());
+        assert_eq!(log_buffer.take(Warn), vec!["… 1 lines dropped", "warn 1", "error 3"]);
+        assert!(log_buffer.take(Trace).is_empty());
+    }
+}
@@ -10,10 +10,23 @@ pub const PLACING_CONSTRUCTION_SITES_PRIORITY: Priority = Priority(100);
 pub const CREEP_REGISTRATION_PRIORITY: Priority = Priority(220);
 pub const ROOM_MAINTENANCE_PRIORITY: Priority = Priority(200);
 pub const DEFEND_ROOMS_PRIORITY: Priority = Priority(180);
+pub const BALANCE_UPGRADING_PRIORITY: Priority = Priority(90);
+pub const BALANCE_RESOURCES_PRIORITY: Priority = Priority(85);
+pub const SCOUTING_PRIORITY: Priority = Priority(70);
+pub const EXPANSION_PRIORITY: Priority = Priority(65);
 pub const MOVE_CREEPS_PRIORITY: Priority = Priority(50);
 pub const SPAWNING_CREEPS_PRIORITY: Priority = Priority(40);
 pub const VISUALIZATIONS_PRIORITY: Priority = Priority(10);
 
+/// Defenders are spawned before anything else while the room is under attack.
+pub const DEFENDER_SPAWN_PRIORITY: Priority = Priority(220);
 pub const MINER_SPAWN_PRIORITY: Priority = Priority(200);
 pub const HAULER_SPAWN_PRIORITY: Priority = Priority(200);
-pub const UPGRADER_SPAWN_PRIORITY: Priority = Priority(100);
\ No newline at end of file
+pub const UPGRADER_SPAWN_PRIORITY: Priority = Priority(100);
+pub const INVADER_CORE_ATTACKER_SPAWN_PRIORITY: Priority = Priority(120);
+pub const REMOTE_RESERVER_SPAWN_PRIORITY: Priority = Priority(110);
+pub const SCOUT_SPAWN_PRIORITY: Priority = Priority(60);
+/// Claimers and pioneers of a new room.
+pub const EXPANSION_SPAWN_PRIORITY: Priority = Priority(90);
+/// The minimum priority of creeps that may be spawned while the room is being rebuilt.
+pub const BOOTSTRAP_SPAWN_PRIORITY: Priority = Priority(200);
\ No newline at end of file
@@ -1,29 +1,481 @@
-#[cfg(not(test))]
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use log::debug;
 #[cfg(not(test))]
 use screeps::game;
+use serde::{Deserialize, Serialize};
+use crate::config::PROFILER_WINDOW_TICKS;
+
+thread_local! {
+    /// Spans measured in the current tick.
+    static SPAN_TREE: RefCell<SpanTree> = RefCell::new(SpanTree::default());
+    /// Spans measured in the previous tick.
+    static LAST_TICK_SPAN_TREE: RefCell<SpanTree> = RefCell::new(SpanTree::default());
+    static PROFILER_AGGREGATES: RefCell<ProfilerAggregates> = RefCell::new(ProfilerAggregates::default());
+}
 
 #[cfg(not(test))]
+fn cpu_used() -> f64 {
+    game::cpu::get_used()
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Mocked CPU used in the current tick.
+    static TEST_CPU_USED: std::cell::Cell<f64> = const { std::cell::Cell::new(0.0) };
+}
+
+#[cfg(test)]
+fn cpu_used() -> f64 {
+    TEST_CPU_USED.with(|cpu_used| cpu_used.get())
+}
+
+/// Measures the CPU used by `f` as a span with given name, nested in the spans of the
+/// `measure_time` calls it is called from.
 pub fn measure_time<F, R>(name: &str, f: F) -> R
 where
     F: FnOnce() -> R,
 {
-    let start = game::cpu::get_used();
+    SPAN_TREE.with(|span_tree| span_tree.borrow_mut().enter(name, cpu_used()));
     let result = f();
-    let end = game::cpu::get_used();
-    // TODO stack
+    let cpu = SPAN_TREE.with(|span_tree| span_tree.borrow_mut().exit(cpu_used()));
     debug!(
         "<span style=\"color: #6666bb\">{} completed in {}ms.</span>",
         name,
-        end - start
+        cpu
     );
     result
 }
 
-#[cfg(test)]
-pub fn measure_time<F, R>(name: &str, f: F) -> R
-    where
-        F: FnOnce() -> R,
+/// Finishes measuring the spans of the current tick, adding them to the aggregates. Spans that are
+/// still open are discarded.
+pub fn end_profiler_tick(tick: u32) {
+    let span_tree = SPAN_TREE.with(|span_tree| span_tree.take());
+    PROFILER_AGGREGATES.with(|aggregates| {
+        aggregates
+            .borrow_mut()
+            .record_tick(tick, span_tree.spans(), PROFILER_WINDOW_TICKS)
+    });
+    LAST_TICK_SPAN_TREE.with(|last_tick_span_tree| last_tick_span_tree.replace(span_tree));
+}
+
+pub fn with_profiler_aggregates<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProfilerAggregates) -> R,
 {
-    f()
-}
\ No newline at end of file
+    PROFILER_AGGREGATES.with(|aggregates| f(&mut aggregates.borrow_mut()))
+}
+
+/// The spans of the previous tick as a tree followed by the statistics of spans over the rolling
+/// window, the most expensive ones first.
+pub fn report() -> String {
+    let span_tree = LAST_TICK_SPAN_TREE.with(|last_tick_span_tree| last_tick_span_tree.borrow().render());
+    let (window_start, summaries) =
+        with_profiler_aggregates(|aggregates| (aggregates.window_start(), aggregates.summaries()));
+    render_report(&span_tree, window_start, &summaries)
+}
+
+fn render_report(span_tree: &str, window_start: u32, summaries: &[SpanSummary]) -> String {
+    let mut lines = vec!["Last tick:".to_string()];
+    lines.extend(span_tree.lines().map(|line| format!("  {}", line)));
+    lines.push(format!("Since tick {}:", window_start));
+    lines.extend(summaries.iter().map(|summary| {
+        format!(
+            "  {}: total {:.1}, mean {:.2}, ema {:.2}, p50 <= {:.2}, p95 <= {:.2}, max {:.2} in {} ticks",
+            summary.name,
+            summary.total,
+            summary.mean,
+            summary.ema,
+            summary.p50,
+            summary.p95,
+            summary.max,
+            summary.ticks
+        )
+    }));
+    lines.join("\n")
+}
+
+/// A named span measured in a tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub name: String,
+    /// Index of the span this one is nested in.
+    pub parent: Option<usize>,
+    pub depth: usize,
+    /// CPU used within the span, including the spans nested in it.
+    pub cpu: f64,
+    /// CPU used within the span outside of the spans nested in it.
+    pub self_cpu: f64,
+    start: f64,
+}
+
+/// Spans measured in a tick, in the order they were entered.
+#[derive(Debug, Clone, Default)]
+pub struct SpanTree {
+    spans: Vec<Span>,
+    /// Indexes of the spans that were entered, but not exited yet, the innermost one last.
+    open: Vec<usize>,
+}
+
+impl SpanTree {
+    pub fn enter(&mut self, name: &str, cpu_used: f64) {
+        self.open.push(self.spans.len());
+        self.spans.push(Span {
+            name: name.to_string(),
+            parent: self.open.iter().rev().nth(1).copied(),
+            depth: self.open.len() - 1,
+            cpu: 0.0,
+            self_cpu: 0.0,
+            start: cpu_used,
+        });
+    }
+
+    /// Exits the innermost open span. Returns the CPU used within it.
+    pub fn exit(&mut self, cpu_used: f64) -> f64 {
+        let Some(i) = self.open.pop() else {
+            return 0.0;
+        };
+        let span = &mut self.spans[i];
+        span.cpu = cpu_used - span.start;
+        span.self_cpu += span.cpu;
+        let cpu = span.cpu;
+        if let Some(parent) = span.parent {
+            self.spans[parent].self_cpu -= cpu;
+        }
+        cpu
+    }
+
+    /// The spans that were exited, each after its parent.
+    pub fn spans(&self) -> &[Span] {
+        if let Some(&first_open) = self.open.first() {
+            &self.spans[..first_open]
+        } else {
+            &self.spans
+        }
+    }
+
+    /// Renders the spans with each one indented below its parent.
+    pub fn render(&self) -> String {
+        self.spans()
+            .iter()
+            .map(|span| {
+                format!(
+                    "{}{} {:.2} (self {:.2})",
+                    "  ".repeat(span.depth),
+                    span.name,
+                    span.cpu,
+                    span.self_cpu
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Statistics of the CPU used by spans with a name per tick they were measured in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanSummary {
+    pub name: String,
+    pub ticks: u32,
+    pub total: f64,
+    pub mean: f64,
+    /// Exponential moving average over the ticks the span was measured in.
+    pub ema: f64,
+    /// Upper bound of the median, estimated from the buckets.
+    pub p50: f64,
+    /// Upper bound of the 95th percentile, estimated from the buckets.
+    pub p95: f64,
+    pub max: f64,
+}
+
+/// Upper bounds of the CPU used by a span in a tick counted in each bucket, except for the last
+/// bucket which counts everything above the last bound.
+const CPU_BUCKET_BOUNDS: [f32; 15] = [
+    0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0,
+];
+const CPU_BUCKETS_COUNT: usize = CPU_BUCKET_BOUNDS.len() + 1;
+/// Weight of the CPU used in the latest tick in the exponential moving average.
+const EMA_ALPHA: f32 = 0.05;
+
+/// Aggregated CPU used per tick by spans with a name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct SpanAggregate {
+    ticks: u32,
+    total: f64,
+    max: f32,
+    /// Exponential moving average, not reset with the window.
+    ema: f32,
+    /// Number of ticks with the CPU used in each bucket.
+    buckets: [u32; CPU_BUCKETS_COUNT],
+}
+
+impl SpanAggregate {
+    fn record(&mut self, cpu: f32) {
+        self.ema = if self.ticks == 0 && self.ema == 0.0 {
+            cpu
+        } else {
+            self.ema + EMA_ALPHA * (cpu - self.ema)
+        };
+        self.ticks += 1;
+        self.total += cpu as f64;
+        self.max = self.max.max(cpu);
+        self.buckets[CPU_BUCKET_BOUNDS.partition_point(|&bound| bound < cpu)] += 1;
+    }
+
+    fn reset_window(&mut self) {
+        *self = SpanAggregate {
+            ema: self.ema,
+            ..SpanAggregate::default()
+        };
+    }
+
+    /// The upper bound of the bucket with the nearest-rank percentile, limited by the maximum.
+    fn percentile(&self, p: f64) -> f64 {
+        let rank = ((p / 100.0 * self.ticks as f64).ceil() as u32).max(1);
+        let mut count = 0;
+        for (i, &bucket_count) in self.buckets.iter().enumerate() {
+            count += bucket_count;
+            if count >= rank {
+                return CPU_BUCKET_BOUNDS.get(i).map_or(self.max, |&bound| bound.min(self.max)) as f64;
+            }
+        }
+        self.max as f64
+    }
+}
+
+/// CPU used by spans with each name aggregated over a window of ticks. The window is restarted
+/// every `window` ticks given to `record_tick`. It is persisted in the global state, so changing
+/// it or `SpanAggregate`, including the number of buckets, requires bumping `PERSISTENCE_VERSION`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfilerAggregates {
+    window_start: u32,
+    spans: BTreeMap<String, SpanAggregate>,
+}
+
+impl ProfilerAggregates {
+    /// Adds the total CPU used by spans with each name in the tick, first restarting the window
+    /// if it already lasted `window` ticks. Spans nested in a span with the same name are not
+    /// counted again.
+    pub fn record_tick(&mut self, tick: u32, spans: &[Span], window: u32) {
+        if tick >= self.window_start + window {
+            // Spans not measured in the whole previous window are forgotten.
+            self.spans.retain(|_, aggregate| aggregate.ticks > 0);
+            for aggregate in self.spans.values_mut() {
+                aggregate.reset_window();
+            }
+            self.window_start = tick;
+        }
+
+        let mut tick_cpu = BTreeMap::<&str, f64>::new();
+        for span in spans.iter() {
+            let mut ancestor = span.parent;
+            let mut nested_in_same_name = false;
+            while let Some(i) = ancestor {
+                if spans[i].name == span.name {
+                    nested_in_same_name = true;
+                    break;
+                }
+                ancestor = spans[i].parent;
+            }
+            if !nested_in_same_name {
+                *tick_cpu.entry(span.name.as_str()).or_default() += span.cpu;
+            }
+        }
+
+        for (name, cpu) in tick_cpu.into_iter() {
+            self.spans.entry(name.to_string()).or_default().record(cpu as f32);
+        }
+    }
+
+    /// The first tick of the current window.
+    pub fn window_start(&self) -> u32 {
+        self.window_start
+    }
+
+    /// Statistics of the spans measured in the current window, ordered by the total CPU used,
+    /// the highest first.
+    pub fn summaries(&self) -> Vec<SpanSummary> {
+        let mut result = self
+            .spans
+            .iter()
+            .filter(|(_, aggregate)| aggregate.ticks > 0)
+            .map(|(name, aggregate)| SpanSummary {
+                name: name.clone(),
+                ticks: aggregate.ticks,
+                total: aggregate.total,
+                mean: aggregate.total / aggregate.ticks as f64,
+                ema: aggregate.ema as f64,
+                p50: aggregate.percentile(50.0),
+                p95: aggregate.percentile(95.0),
+                max: aggregate.max as f64,
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| b.total.total_cmp(&a.total));
+        result
+    }
+}
+
+/// A profiler that can be injected into code which should also be runnable outside of the game.
+pub trait Profiler {
+    fn measure_time(&self, name: &str, f: &mut dyn FnMut());
+}
+
+/// Profiler measuring the game CPU usage using `measure_time`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GameProfiler;
+
+impl Profiler for GameProfiler {
+    fn measure_time(&self, name: &str, f: &mut dyn FnMut()) {
+        measure_time(name, f)
+    }
+}
+
+/// Profiler that does not measure anything.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoOpProfiler;
+
+impl Profiler for NoOpProfiler {
+    fn measure_time(&self, _name: &str, f: &mut dyn FnMut()) {
+        f()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::global_state::binary_format::to_bytes;
+    use crate::profiler::{end_profiler_tick, measure_time, report, ProfilerAggregates, SpanAggregate, SpanTree, TEST_CPU_USED};
+
+    fn use_cpu(cpu: f64) {
+        TEST_CPU_USED.with(|cpu_used| cpu_used.set(cpu_used.get() + cpu));
+    }
+
+    #[test]
+    fn test_nested_spans_attribution() {
+        TEST_CPU_USED.with(|cpu_used| cpu_used.set(0.0));
+        measure_time("place_towers", || {
+            use_cpu(1.0);
+            measure_time("greedy", || use_cpu(2.0));
+            measure_time("genetic", || {
+                use_cpu(0.5);
+                measure_time("sorting", || use_cpu(3.0));
+            });
+        });
+        measure_time("other", || use_cpu(0.25));
+        end_profiler_tick(10);
+
+        let report = report();
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(
+            &lines[..6],
+            &[
+                "Last tick:",
+                "  place_towers 6.50 (self 1.00)",
+                "    greedy 2.00 (self 2.00)",
+                "    genetic 3.50 (self 0.50)",
+                "      sorting 3.00 (self 3.00)",
+                "  other 0.25 (self 0.25)",
+            ]
+        );
+        assert!(lines[7].starts_with("  place_towers: total 6.5, mean 6.50"));
+        assert!(lines[8].starts_with("  genetic: total 3.5"));
+        assert!(lines[11].starts_with("  other: total ") && lines[11].contains(" mean 0.25, "));
+    }
+
+    #[test]
+    fn test_open_spans_are_not_recorded() {
+        let mut span_tree = SpanTree::default();
+        span_tree.enter("outer", 1.0);
+        span_tree.enter("inner", 2.0);
+        assert_eq!(span_tree.exit(4.0), 2.0);
+        assert!(span_tree.spans().is_empty());
+        assert_eq!(span_tree.exit(5.0), 4.0);
+        assert_eq!(span_tree.spans().len(), 2);
+        assert_eq!(span_tree.exit(6.0), 0.0);
+    }
+
+    #[test]
+    fn test_windowed_aggregation() {
+        let mut aggregates = ProfilerAggregates::default();
+        for tick in 1..=19 {
+            let mut span_tree = SpanTree::default();
+            span_tree.enter("loop", 0.0);
+            // A span nested in one with the same name is counted only once.
+            span_tree.enter("loop", 0.0);
+            span_tree.exit(tick as f64 / 2.0);
+            span_tree.exit(tick as f64);
+            if tick % 2 == 0 {
+                span_tree.enter("even", 10.0);
+                span_tree.exit(11.0);
+            }
+            aggregates.record_tick(tick, span_tree.spans(), 10);
+        }
+
+        // The window was restarted in tick 10.
+        assert_eq!(aggregates.window_start(), 10);
+        let summaries = aggregates.summaries();
+        assert_eq!(summaries.len(), 2);
+        let loop_summary = &summaries[0];
+        assert_eq!(loop_summary.name, "loop");
+        assert_eq!(loop_summary.ticks, 10);
+        assert_eq!(loop_summary.total, 145.0);
+        assert_eq!(loop_summary.mean, 14.5);
+        // Ticks 10 and 11 to 19 are in different buckets.
+        assert_eq!(loop_summary.p50, 19.0);
+        assert_eq!(loop_summary.p95, 19.0);
+        assert_eq!(loop_summary.max, 19.0);
+        // The moving average includes the ticks from before the window.
+        assert!(loop_summary.ema > 1.0 && loop_summary.ema < 19.0);
+        assert_eq!(summaries[1].name, "even");
+        assert_eq!(summaries[1].ticks, 5);
+        assert_eq!(summaries[1].total, 5.0);
+        assert_eq!(summaries[1].ema, 1.0);
+        assert_eq!(summaries[1].p50, 1.0);
+
+        // Spans not measured within the window are not summarized and then forgotten.
+        aggregates.record_tick(20, &[], 10);
+        assert!(aggregates.summaries().is_empty());
+        aggregates.record_tick(30, &[], 10);
+        assert_eq!(aggregates, ProfilerAggregates {
+            window_start: 30,
+            ..ProfilerAggregates::default()
+        });
+    }
+
+    #[test]
+    fn test_percentile_estimate() {
+        let mut aggregate = SpanAggregate::default();
+        for cpu in 1..=100 {
+            aggregate.record(cpu as f32);
+        }
+        assert_eq!(aggregate.percentile(0.0), 1.0);
+        // The median of 50 is in the (20, 50] bucket.
+        assert_eq!(aggregate.percentile(50.0), 50.0);
+        // The 95th percentile of 95 is in the (50, 100] bucket.
+        assert_eq!(aggregate.percentile(95.0), 100.0);
+        aggregate.record(1000.0);
+        assert_eq!(aggregate.percentile(100.0), 1000.0);
+
+        // The estimate does not exceed the maximum.
+        let mut aggregate = SpanAggregate::default();
+        aggregate.record(60.0);
+        assert_eq!(aggregate.percentile(50.0), 60.0);
+    }
+
+    /// The aggregates are a part of the persisted global state, so this test failing means that
+    /// `PERSISTENCE_VERSION` needs to be bumped along with adding a migration.
+    #[test]
+    fn test_persisted_layout() {
+        let mut span_tree = SpanTree::default();
+        span_tree.enter("loop", 0.0);
+        span_tree.exit(1.5);
+        let mut aggregates = ProfilerAggregates::default();
+        aggregates.record_tick(7, span_tree.spans(), 1000);
+        assert_eq!(
+            to_bytes(&aggregates).unwrap(),
+            [
+                0, 1, 4, 108, 111, 111, 112, 1, 0, 0, 0, 0, 0, 0, 248, 63, 0, 0, 192, 63, 0, 0, 192, 63, 0, 0, 0, 0, 0,
+                0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::cmp::min;
+use log::debug;
+use rustc_hash::FxHashMap;
+use screeps::{RoomName, StructureStorage, StructureTerminal};
+use screeps::game::get_object_by_id_typed;
+use screeps::StructureType::{Storage, Terminal};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::requests::HaulRequest;
+use crate::hauling::requests::HaulFlow::Balancing;
+use crate::hauling::requests::HaulRequestKind::{DepositRequest, WithdrawRequest};
+use crate::hauling::requests::HaulRequestTargetKind::{RegularTarget, StorageTarget};
+use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::{get_free_capacity_with_object, get_used_capacities_with_object};
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::kernel::wait_until_some::wait_until_some;
+use crate::room_states::room_states::with_room_state;
+use crate::room_states::utils::loop_until_structures_change;
+use crate::u;
+use crate::utils::priority::Priority;
+
+/// Moves all resources from the storage to the terminal, from where they can be sent out before
+/// the room is abandoned.
+pub async fn evacuate_room(room_name: RoomName) {
+    loop {
+        let (storage_xy, storage_id, terminal_xy, terminal_id) = wait_until_some(|| {
+            with_room_state(room_name, |room_state| {
+                let (storage_xy, storage_id) = room_state.structures_with_type::<StructureStorage>(Storage).next()?;
+                let (terminal_xy, terminal_id) = room_state.structures_with_type::<StructureTerminal>(Terminal).next()?;
+                Some((storage_xy, storage_id, terminal_xy, terminal_id))
+            }).flatten()
+        }).await;
+
+        let storage_pos = storage_xy.to_pos(room_name);
+        let terminal_pos = terminal_xy.to_pos(room_name);
+
+        let mut withdraw_requests = FxHashMap::default();
+        let mut deposit_requests = FxHashMap::default();
+
+        loop_until_structures_change(room_name, 1, || {
+            let storage = u!(get_object_by_id_typed(&storage_id));
+            let terminal = u!(get_object_by_id_typed(&terminal_id));
+            let used_capacities = get_used_capacities_with_object(&storage, storage_id.into(), AfterAllTransfers);
+            let mut free_capacity = get_free_capacity_with_object(&terminal, terminal_id.into(), None, AfterAllTransfers);
+
+            // Resources that are no longer in the storage do not need to be moved.
+            withdraw_requests.retain(|resource_type, _| used_capacities.contains_key(resource_type));
+            deposit_requests.retain(|resource_type, _| used_capacities.contains_key(resource_type));
+
+            for (&resource_type, &used_capacity) in used_capacities.iter() {
+                let amount = min(used_capacity, free_capacity);
+                if amount == 0 {
+                    // There is no room left in the terminal for this resource.
+                    withdraw_requests.remove(&resource_type);
+                    deposit_requests.remove(&resource_type);
+                    continue;
+                }
+                free_capacity -= amount;
+                debug!("Evacuating {amount} {resource_type} from the storage to the terminal in {room_name}.");
+
+                // The previous requests are replaced by these ones.
+                let mut withdraw_request = HaulRequest::new(
+                    WithdrawRequest,
+                    room_name,
+                    resource_type,
+                    storage_id,
+                    StorageTarget,
+                    false,
+                    storage_pos
+                );
+                withdraw_request.amount = amount;
+                withdraw_request.priority = Priority(150);
+                let previous_withdraw_request = withdraw_requests.remove(&resource_type);
+                withdraw_requests.insert(resource_type, schedule_haul(withdraw_request, previous_withdraw_request));
+
+                let mut deposit_request = HaulRequest::new(
+                    DepositRequest,
+                    room_name,
+                    resource_type,
+                    terminal_id,
+                    RegularTarget,
+                    false,
+                    terminal_pos
+                );
+                deposit_request.amount = amount;
+                deposit_request.priority = Priority(150);
+                deposit_request.flow = Balancing;
+                let previous_deposit_request = deposit_requests.remove(&resource_type);
+                deposit_requests.insert(resource_type, schedule_haul(deposit_request, previous_deposit_request));
+            }
+
+            true
+        }).await;
+    }
+}
@@ -2,33 +2,42 @@ use log::debug;
 use rustc_hash::FxHashMap;
 use crate::room_states::room_states::with_room_state;
 use screeps::{ObjectId, Position, RawObjectId, ResourceType, RoomName, RoomXY, Structure};
-use screeps::StructureType::{Extension, Spawn, Tower};
+use screeps::StructureType::{Spawn, Tower};
 use crate::geometry::room_xy::RoomXYUtils;
 use crate::hauling::requests::{HaulRequest, HaulRequestHandle};
 use crate::hauling::requests::HaulRequestKind::DepositRequest;
-use crate::hauling::requests::HaulRequestTargetKind::RegularTarget;
+use crate::hauling::requests::HaulRequestTargetKind::{ExtensionsTarget, RegularTarget};
 use crate::hauling::scheduling_hauls::schedule_haul;
 use crate::hauling::transfers::get_free_capacity_with_object;
 use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::room_states::room_state::ExtensionData;
 use crate::room_states::utils::loop_until_structures_change;
 use crate::utils::get_object_by_id::structure_object_by_id;
 use crate::utils::priority::Priority;
 
-/// Keeps spawns filled by requesting haulers to fill them.
+/// Keeps spawns, extensions and towers filled by requesting haulers to fill them.
+/// All extensions share a single request fulfilled by filling them along a route.
 pub async fn fill_structures_with_energy(room_name: RoomName) {
     loop {
         // TODO Maybe don't drop all store requests on change, just the ones that changed?
         let mut deposit_request_handles: FxHashMap<_, _> = FxHashMap::default();
+        let mut extensions_deposit_request_handle = None;
         
         loop_until_structures_change(room_name, 4, || {
             with_room_state(room_name, |room_state| {
-                for structure_type in [Spawn, Extension, Tower] {
+                for structure_type in [Spawn, Tower] {
                     schedule_missing_energy_deposit_for_structure_type(
                         room_name,
                         room_state.structures.get(&structure_type),
                         &mut deposit_request_handles
                     );
                 }
+
+                extensions_deposit_request_handle = schedule_missing_extensions_energy_deposit(
+                    room_name,
+                    &room_state.extensions,
+                    extensions_deposit_request_handle.take()
+                );
             });
 
             true
@@ -84,3 +93,29 @@ pub fn schedule_missing_energy_deposit(
         None
     }
 }
+
+/// Schedules depositing the energy missing in all extensions, as of the last scan of the room, as
+/// a single request.
+pub fn schedule_missing_extensions_energy_deposit(
+    room_name: RoomName,
+    extensions: &[ExtensionData],
+    replaced_request_handle: Option<HaulRequestHandle>
+) -> Option<HaulRequestHandle> {
+    let missing_energy = extensions.iter().map(|extension| extension.missing_energy).sum::<u32>();
+    // The request needs a target, so one of the unfilled extensions is used.
+    let first_unfilled_extension = extensions.iter().find(|extension| extension.missing_energy > 0)?;
+
+    debug!("Scheduling haul of missing {missing_energy} energy for extensions in {room_name}.");
+    let mut deposit_request = HaulRequest::new(
+        DepositRequest,
+        room_name,
+        ResourceType::Energy,
+        first_unfilled_extension.id,
+        ExtensionsTarget,
+        false,
+        first_unfilled_extension.xy.to_pos(room_name)
+    );
+    deposit_request.amount = missing_energy;
+    deposit_request.priority = Priority(100);
+    Some(schedule_haul(deposit_request, replaced_request_handle))
+}
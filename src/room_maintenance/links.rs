@@ -0,0 +1,312 @@
+use std::cmp::min;
+use screeps::{ObjectId, ResourceType, RoomName, RoomXY, StructureLink, LINK_LOSS_RATIO};
+use screeps::game::get_object_by_id_typed;
+use screeps::StructureType::Link;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::requests::{HaulRequest, HaulRequestHandle};
+use crate::hauling::requests::HaulRequestKind::WithdrawRequest;
+use crate::hauling::requests::HaulRequestTargetKind::RegularTarget;
+use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::{get_free_capacity_with_object, get_used_capacity_with_object, register_transfer};
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::kernel::wait_until_some::wait_until_some;
+use crate::local_debug;
+use crate::room_states::room_state::RoomState;
+use crate::room_states::room_states::with_room_state;
+use crate::room_states::utils::loop_until_structures_change;
+use crate::utils::priority::Priority;
+use crate::utils::result_utils::ResultUtils;
+
+const DEBUG: bool = true;
+
+/// Source links send their energy only once they have at least this much, so that the cooldown is
+/// not wasted on small amounts.
+const MIN_SOURCE_LINK_TRANSFER: u32 = 400;
+/// The controller link is refilled from the core link when it has less energy than this.
+const CONTROLLER_LINK_REFILL_THRESHOLD: u32 = 400;
+/// Energy in the core link not needed by the controller link is hauled away once there is at least
+/// this much of it.
+const MIN_CORE_LINK_SURPLUS: u32 = 200;
+
+/// The state of a link in the current tick.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LinkState {
+    pub id: ObjectId<StructureLink>,
+    pub energy: u32,
+    pub free_capacity: u32,
+    pub cooldown: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LinkTransfer {
+    pub from: ObjectId<StructureLink>,
+    pub to: ObjectId<StructureLink>,
+    pub amount: u32,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LinkPlan {
+    pub transfers: Vec<LinkTransfer>,
+    /// Energy left in the core link that the controller link does not need.
+    pub core_link_surplus: u32,
+}
+
+/// Links in the room by their purpose.
+struct RoomLinks {
+    source_links: Vec<ObjectId<StructureLink>>,
+    core_link: Option<(RoomXY, ObjectId<StructureLink>)>,
+    controller_link: Option<ObjectId<StructureLink>>,
+}
+
+/// Moves energy from the source links to the core link and from the core link to the controller
+/// link. Energy mined into links does not need to be carried by haulers, only the surplus in
+/// the core link is hauled away, which is a short trip to the storage.
+pub async fn operate_links(room_name: RoomName) {
+    loop {
+        let room_links = wait_until_some(|| with_room_state(room_name, |room_state| room_links(room_state)).flatten()).await;
+        let mut core_link_withdraw_request = None;
+
+        loop_until_structures_change(room_name, 1, || {
+            let source_links = room_links
+                .source_links
+                .iter()
+                .filter_map(|&id| link_state(id))
+                .collect::<Vec<_>>();
+            let core_link = room_links.core_link.and_then(|(_, id)| link_state(id));
+            let controller_link = room_links.controller_link.and_then(link_state);
+
+            let plan = plan_link_transfers(&source_links, core_link, controller_link);
+            for transfer in plan.transfers.iter() {
+                send_energy(room_name, transfer);
+            }
+
+            core_link_withdraw_request = room_links.core_link.and_then(|(xy, id)| {
+                (plan.core_link_surplus >= MIN_CORE_LINK_SURPLUS).then(|| {
+                    schedule_core_link_withdraw(
+                        room_name,
+                        xy,
+                        id,
+                        plan.core_link_surplus,
+                        core_link_withdraw_request.take()
+                    )
+                })
+            });
+
+            true
+        }).await;
+    }
+}
+
+/// Decides which links send energy where in the current tick. The source links send to the core
+/// link or, if there is none, to the controller link. The core link refills the controller link when
+/// it is low on energy.
+pub fn plan_link_transfers(
+    source_links: &[LinkState],
+    core_link: Option<LinkState>,
+    controller_link: Option<LinkState>,
+) -> LinkPlan {
+    let mut plan = LinkPlan::default();
+    let mut core_link = core_link;
+    let mut controller_link = controller_link;
+
+    let controller_link_needs_energy = |controller_link: &LinkState| {
+        controller_link.energy < CONTROLLER_LINK_REFILL_THRESHOLD
+    };
+
+    if let (Some(core_link), Some(controller_link)) = (core_link.as_mut(), controller_link.as_mut()) {
+        if core_link.cooldown == 0 && controller_link_needs_energy(controller_link) {
+            let amount = min(core_link.energy, controller_link.free_capacity);
+            if amount > 0 {
+                plan.transfers.push(LinkTransfer {
+                    from: core_link.id,
+                    to: controller_link.id,
+                    amount,
+                });
+                core_link.energy -= amount;
+                controller_link.free_capacity -= amount;
+                controller_link.energy += received_energy(amount);
+            }
+        }
+    }
+
+    if let Some(receiver) = core_link.as_mut().or(controller_link.as_mut()) {
+        for source_link in source_links.iter() {
+            if source_link.cooldown > 0 || source_link.energy < MIN_SOURCE_LINK_TRANSFER {
+                continue;
+            }
+            let amount = min(source_link.energy, receiver.free_capacity);
+            if amount >= MIN_SOURCE_LINK_TRANSFER {
+                plan.transfers.push(LinkTransfer {
+                    from: source_link.id,
+                    to: receiver.id,
+                    amount,
+                });
+                receiver.free_capacity -= amount;
+            }
+        }
+    }
+
+    if let Some(core_link) = core_link {
+        if !controller_link.as_ref().is_some_and(controller_link_needs_energy) {
+            plan.core_link_surplus = core_link.energy;
+        }
+    }
+
+    plan
+}
+
+/// Energy that arrives at the target of a link transfer of given amount.
+fn received_energy(amount: u32) -> u32 {
+    amount - (amount as f32 * LINK_LOSS_RATIO).ceil() as u32
+}
+
+/// Finds the built source, core and controller links. The core link is the one not planned next to
+/// a source or the controller.
+fn room_links(room_state: &RoomState) -> Option<RoomLinks> {
+    let controller_data = room_state.controller?;
+    let source_links = room_state
+        .sources
+        .iter()
+        .filter_map(|source_data| source_data.link_id)
+        .collect::<Vec<_>>();
+    Some(RoomLinks {
+        source_links,
+        core_link: core_link(room_state),
+        controller_link: controller_data.link_id,
+    })
+}
+
+/// The built link that is not planned next to a source or the controller, receiving the energy
+/// from source links next to the storage.
+pub fn core_link(room_state: &RoomState) -> Option<(RoomXY, ObjectId<StructureLink>)> {
+    let controller_link_xy = room_state.controller.and_then(|controller_data| controller_data.link_xy);
+    room_state
+        .structures_with_type::<StructureLink>(Link)
+        .find(|&(xy, _)| {
+            Some(xy) != controller_link_xy
+                && room_state.sources.iter().all(|source_data| Some(xy) != source_data.link_xy)
+        })
+}
+
+fn link_state(id: ObjectId<StructureLink>) -> Option<LinkState> {
+    let link = get_object_by_id_typed(&id)?;
+    Some(LinkState {
+        id,
+        energy: get_used_capacity_with_object(&link, id.into(), Some(ResourceType::Energy), AfterAllTransfers),
+        free_capacity: get_free_capacity_with_object(&link, id.into(), Some(ResourceType::Energy), AfterAllTransfers),
+        cooldown: link.cooldown(),
+    })
+}
+
+fn send_energy(room_name: RoomName, transfer: &LinkTransfer) {
+    let (Some(from), Some(to)) = (get_object_by_id_typed(&transfer.from), get_object_by_id_typed(&transfer.to)) else {
+        return;
+    };
+    local_debug!("Sending {} energy from link {} to {}.", transfer.amount, transfer.from, transfer.to);
+    let result = from.transfer_energy(&to, Some(transfer.amount));
+    if result.is_ok() {
+        register_transfer(transfer.from.into(), ResourceType::Energy, -(transfer.amount as i32));
+        register_transfer(transfer.to.into(), ResourceType::Energy, received_energy(transfer.amount) as i32);
+        with_room_state(room_name, |room_state| {
+            if let Some(eco_stats) = room_state.eco_stats.as_mut() {
+                eco_stats.register_link_transfer(transfer.amount);
+            }
+        });
+    }
+    result.warn_if_err("Failed to send energy through a link");
+}
+
+fn schedule_core_link_withdraw(
+    room_name: RoomName,
+    xy: RoomXY,
+    id: ObjectId<StructureLink>,
+    amount: u32,
+    replaced_request_handle: Option<HaulRequestHandle>
+) -> HaulRequestHandle {
+    local_debug!("Scheduling haul of surplus {amount} energy from the core link in {room_name}.");
+    let mut withdraw_request = HaulRequest::new(
+        WithdrawRequest,
+        room_name,
+        ResourceType::Energy,
+        id,
+        RegularTarget,
+        false,
+        xy.to_pos(room_name)
+    );
+    withdraw_request.amount = amount;
+    withdraw_request.priority = Priority(100);
+    schedule_haul(withdraw_request, replaced_request_handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{ObjectId, RawObjectId, StructureLink, LINK_CAPACITY};
+    use crate::room_maintenance::links::{plan_link_transfers, LinkState, LinkTransfer};
+
+    fn test_link(packed_id: u128, energy: u32, cooldown: u32) -> LinkState {
+        LinkState {
+            id: ObjectId::<StructureLink>::from(RawObjectId::from_packed(packed_id)),
+            energy,
+            free_capacity: LINK_CAPACITY - energy,
+            cooldown,
+        }
+    }
+
+    #[test]
+    fn test_source_links_send_to_core_link() {
+        let full_source_link = test_link(1, 800, 0);
+        let half_source_link = test_link(2, 300, 0);
+        let core_link = test_link(3, 0, 0);
+        let controller_link = test_link(4, 700, 0);
+
+        let plan = plan_link_transfers(&[full_source_link, half_source_link], Some(core_link), Some(controller_link));
+        assert_eq!(plan.transfers, vec![LinkTransfer {
+            from: full_source_link.id,
+            to: core_link.id,
+            amount: 800,
+        }]);
+        // The energy arrives only in the next tick.
+        assert_eq!(plan.core_link_surplus, 0);
+
+        // The second source link has to wait until there is enough space in the core link.
+        let full_core_link = test_link(3, 600, 0);
+        let plan = plan_link_transfers(&[test_link(2, 500, 0)], Some(full_core_link), Some(controller_link));
+        assert!(plan.transfers.is_empty());
+        assert_eq!(plan.core_link_surplus, 600);
+    }
+
+    #[test]
+    fn test_core_link_refills_controller_link() {
+        let source_link = test_link(1, 800, 0);
+        let core_link = test_link(3, 500, 0);
+        let controller_link = test_link(4, 100, 0);
+
+        let plan = plan_link_transfers(&[source_link], Some(core_link), Some(controller_link));
+        // The space freed in the core link can only be used in the next tick.
+        assert_eq!(plan.transfers, vec![LinkTransfer {
+            from: core_link.id,
+            to: controller_link.id,
+            amount: 500,
+        }]);
+        assert_eq!(plan.core_link_surplus, 0);
+
+        // Links on cooldown do not send anything and the energy is kept for the controller link.
+        let plan = plan_link_transfers(&[test_link(1, 800, 3)], Some(test_link(3, 500, 2)), Some(controller_link));
+        assert!(plan.transfers.is_empty());
+        assert_eq!(plan.core_link_surplus, 0);
+    }
+
+    #[test]
+    fn test_source_links_send_to_controller_link_without_core_link() {
+        let source_link = test_link(1, 600, 0);
+        let controller_link = test_link(4, 100, 0);
+
+        let plan = plan_link_transfers(&[source_link], None, Some(controller_link));
+        assert_eq!(plan.transfers, vec![LinkTransfer {
+            from: source_link.id,
+            to: controller_link.id,
+            amount: 600,
+        }]);
+        assert_eq!(plan.core_link_surplus, 0);
+    }
+}
@@ -0,0 +1,256 @@
+use std::cmp::min;
+use std::iter::once;
+use rustc_hash::FxHashMap;
+use screeps::{game, ObjectId, RawObjectId, ResourceType, RoomName, RoomObject};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::requests::HaulFlow::Loot;
+use crate::hauling::requests::{HaulRequest, HaulRequestHandle};
+use crate::hauling::requests::HaulRequestKind::WithdrawRequest;
+use crate::hauling::requests::HaulRequestTargetKind::RegularTarget;
+use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::kernel::sleep::sleep;
+use crate::local_debug;
+use crate::room_states::room_state::{RemainsData, RoomState};
+use crate::room_states::room_states::with_room_state;
+use crate::utils::game_tick::game_tick;
+use crate::utils::priority::Priority;
+
+const DEBUG: bool = true;
+
+/// Number of ticks between updates of the requests to loot tombstones and ruins.
+const LOOT_INTERVAL: u32 = 5;
+
+/// Priority of looting remains that are far from decaying and have little resources in them.
+const LOOT_PRIORITY: Priority = Priority(100);
+/// Remains decaying in fewer ticks than this have their priority increased, up to
+/// `MAX_URGENCY_BONUS` right before they decay.
+const URGENT_DECAY_TICKS: u32 = 200;
+const MAX_URGENCY_BONUS: u32 = 30;
+/// Amounts of a resource up to this one have their priority increased proportionally, up to
+/// `MAX_ABUNDANCE_BONUS`.
+const ABUNDANT_LOOT_AMOUNT: u32 = 1000;
+const MAX_ABUNDANCE_BONUS: u32 = 20;
+
+/// Withdraw requests for the resources in the remains, by the remains and resource type.
+type LootRequests = FxHashMap<(RawObjectId, ResourceType), HaulRequestHandle>;
+
+/// Requests hauling resources out of tombstones and ruins in the room and its remotes before they
+/// decay.
+pub async fn loot_remains(room_name: RoomName) {
+    let mut loot_requests: FxHashMap<RoomName, LootRequests> = FxHashMap::default();
+
+    loop {
+        // Remotes with hauling paused due to an invader core are not looted.
+        let looted_room_names = with_room_state(room_name, |room_state| {
+            once(room_name)
+                .chain(
+                    room_state
+                        .remote_plans
+                        .keys()
+                        .copied()
+                        .filter(|remote_name| !room_state.paused_remotes.contains(remote_name))
+                )
+                .collect::<Vec<_>>()
+        }).unwrap_or_default();
+        // Dropping the requests in rooms no longer looted cancels them.
+        loot_requests.retain(|looted_room_name, _| looted_room_names.contains(looted_room_name));
+        // Forgetting the requests dropped by the hauling system, e.g., when the remains decayed while the room was
+        // not visible, so that they are scheduled anew if the remains are still there.
+        for room_loot_requests in loot_requests.values_mut() {
+            forget_dropped_loot_requests(room_loot_requests);
+        }
+
+        for looted_room_name in looted_room_names {
+            // The remains are only known in visible rooms. Requests in other rooms expire when the
+            // remains decay.
+            if game::rooms().get(looted_room_name).is_none() {
+                continue;
+            }
+            with_room_state(looted_room_name, |looted_room_state| {
+                update_loot_requests(
+                    room_name,
+                    looted_room_state,
+                    loot_requests.entry(looted_room_name).or_default()
+                );
+            });
+        }
+
+        sleep(LOOT_INTERVAL).await;
+    }
+}
+
+/// Schedules withdraw requests for the resources in the remains in given room, replacing
+/// the previous ones, and cancels the requests for the remains and resources that are gone.
+pub fn update_loot_requests(home_room_name: RoomName, room_state: &RoomState, loot_requests: &mut LootRequests) {
+    let mut updated_loot_requests = FxHashMap::default();
+
+    for remains in room_state.remains.iter() {
+        for (&resource_type, &amount) in remains.store.iter() {
+            let key = (remains.id, resource_type);
+            let handle = schedule_withdraw_from_remains(
+                home_room_name,
+                room_state.room_name,
+                remains,
+                resource_type,
+                amount,
+                loot_requests.remove(&key)
+            );
+            updated_loot_requests.insert(key, handle);
+        }
+    }
+
+    // The requests that were not replaced are cancelled when dropped.
+    *loot_requests = updated_loot_requests;
+}
+
+/// Removes the requests that were dropped by the hauling system.
+pub fn forget_dropped_loot_requests(loot_requests: &mut LootRequests) {
+    loot_requests.retain(|&(remains_id, resource_type), handle| match handle.check_dropped() {
+        Some(outcome) => {
+            local_debug!("Loot request of {resource_type} from {remains_id} was dropped ({outcome:?}).");
+            false
+        }
+        None => true,
+    });
+}
+
+/// Schedules withdrawing given amount of a resource from a tombstone or ruin, expiring when it
+/// decays.
+pub fn schedule_withdraw_from_remains(
+    home_room_name: RoomName,
+    room_name: RoomName,
+    remains: &RemainsData,
+    resource_type: ResourceType,
+    amount: u32,
+    replaced_request_handle: Option<HaulRequestHandle>
+) -> HaulRequestHandle {
+    local_debug!("Scheduling loot of {amount} {resource_type} from {} in {room_name}.", remains.id);
+    let mut withdraw_request = HaulRequest::new(
+        WithdrawRequest,
+        home_room_name,
+        resource_type,
+        ObjectId::<RoomObject>::from(remains.id),
+        RegularTarget,
+        false,
+        remains.xy.to_pos(room_name)
+    );
+    withdraw_request.amount = amount;
+    withdraw_request.priority = loot_priority(remains.decay_tick.saturating_sub(game_tick()), amount);
+    withdraw_request.flow = Loot;
+    withdraw_request.expiry_tick = Some(remains.decay_tick);
+    schedule_haul(withdraw_request, replaced_request_handle)
+}
+
+/// Priority of looting given amount of a resource from remains decaying in given number of ticks.
+/// Remains about to decay and larger amounts are looted first.
+pub fn loot_priority(ticks_to_decay: u32, amount: u32) -> Priority {
+    let urgency_bonus = (URGENT_DECAY_TICKS - min(ticks_to_decay, URGENT_DECAY_TICKS)) * MAX_URGENCY_BONUS / URGENT_DECAY_TICKS;
+    let abundance_bonus = min(amount, ABUNDANT_LOOT_AMOUNT) * MAX_ABUNDANCE_BONUS / ABUNDANT_LOOT_AMOUNT;
+    LOOT_PRIORITY.saturating_add((urgency_bonus + abundance_bonus) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use rustc_hash::FxHashMap;
+    use screeps::{RawObjectId, ResourceType};
+    use crate::hauling::requests::HaulFlow;
+    use crate::hauling::scheduling_hauls::sweep_haul_requests;
+    use crate::room_maintenance::loot_remains::{forget_dropped_loot_requests, loot_priority, update_loot_requests};
+    use crate::room_states::room_state::{empty_unowned_room_state, RemainsData};
+    use crate::u;
+    use crate::utils::game_tick::game_tick;
+    use crate::utils::priority::Priority;
+
+    #[test]
+    fn test_loot_priority() {
+        assert_eq!(loot_priority(1000, 0), Priority(100));
+        assert_eq!(loot_priority(200, 0), Priority(100));
+        assert_eq!(loot_priority(100, 0), Priority(115));
+        assert_eq!(loot_priority(0, 0), Priority(130));
+        assert_eq!(loot_priority(1000, 500), Priority(110));
+        assert_eq!(loot_priority(0, 5000), Priority(150));
+    }
+
+    #[test]
+    fn test_loot_requests() {
+        let mut room_state = empty_unowned_room_state();
+        let tombstone_id = RawObjectId::from_packed(1);
+        let ruin_id = RawObjectId::from_packed(2);
+        room_state.remains = vec![
+            RemainsData {
+                id: tombstone_id,
+                xy: (10, 10).try_into().unwrap(),
+                decay_tick: game_tick() + 20,
+                store: FxHashMap::from_iter([(ResourceType::Energy, 200)]),
+            },
+            RemainsData {
+                id: ruin_id,
+                xy: (20, 20).try_into().unwrap(),
+                decay_tick: game_tick() + 400,
+                store: FxHashMap::from_iter([(ResourceType::Energy, 1000), (ResourceType::Keanium, 500)]),
+            },
+        ];
+
+        let mut loot_requests = FxHashMap::default();
+        update_loot_requests(room_state.room_name, &room_state, &mut loot_requests);
+        assert_eq!(loot_requests.len(), 3);
+
+        let tombstone_request = u!(loot_requests.get(&(tombstone_id, ResourceType::Energy))).request.clone();
+        assert_eq!(tombstone_request.borrow().amount, 200);
+        assert_eq!(tombstone_request.borrow().priority, Priority(131));
+        assert_eq!(tombstone_request.borrow().expiry_tick, Some(game_tick() + 20));
+        assert_eq!(tombstone_request.borrow().flow, HaulFlow::Loot);
+        let ruin_energy_request = u!(loot_requests.get(&(ruin_id, ResourceType::Energy))).request.clone();
+        assert_eq!(ruin_energy_request.borrow().amount, 1000);
+        assert_eq!(ruin_energy_request.borrow().priority, Priority(120));
+        let ruin_keanium_request = u!(loot_requests.get(&(ruin_id, ResourceType::Keanium))).request.clone();
+        assert_eq!(ruin_keanium_request.borrow().amount, 500);
+        assert_eq!(ruin_keanium_request.borrow().priority, Priority(110));
+
+        // The tombstone disappears and the ruin has been partially looted.
+        room_state.remains.remove(0);
+        room_state.remains[0].store.remove(&ResourceType::Keanium);
+        *u!(room_state.remains[0].store.get_mut(&ResourceType::Energy)) = 600;
+        update_loot_requests(room_state.room_name, &room_state, &mut loot_requests);
+        assert_eq!(loot_requests.len(), 1);
+        assert_eq!(tombstone_request.borrow().amount, 0);
+        assert_eq!(ruin_keanium_request.borrow().amount, 0);
+        // The request is updated in place so that the haulers that reserved it keep it.
+        let updated_ruin_energy_request = &u!(loot_requests.get(&(ruin_id, ResourceType::Energy))).request;
+        assert!(Rc::ptr_eq(updated_ruin_energy_request, &ruin_energy_request));
+        assert_eq!(ruin_energy_request.borrow().amount, 600);
+    }
+    #[test]
+    fn test_forget_dropped_loot_requests() {
+        let mut room_state = empty_unowned_room_state();
+        let tombstone_id = RawObjectId::from_packed(3);
+        let ruin_id = RawObjectId::from_packed(4);
+        room_state.remains = vec![
+            RemainsData {
+                id: tombstone_id,
+                xy: (10, 10).try_into().unwrap(),
+                decay_tick: game_tick(),
+                store: FxHashMap::from_iter([(ResourceType::Energy, 200)]),
+            },
+            RemainsData {
+                id: ruin_id,
+                xy: (20, 20).try_into().unwrap(),
+                decay_tick: game_tick() + 400,
+                store: FxHashMap::from_iter([(ResourceType::Energy, 1000)]),
+            },
+        ];
+
+        let mut loot_requests = FxHashMap::default();
+        update_loot_requests(room_state.room_name, &room_state, &mut loot_requests);
+        assert_eq!(loot_requests.len(), 2);
+
+        // The tombstone decays and its request expires.
+        sweep_haul_requests(room_state.room_name, |_| true);
+        forget_dropped_loot_requests(&mut loot_requests);
+        assert_eq!(loot_requests.len(), 1);
+        assert!(loot_requests.contains_key(&(ruin_id, ResourceType::Energy)));
+        let ruin_request = u!(loot_requests.get(&(ruin_id, ResourceType::Energy))).request.clone();
+        assert_eq!(ruin_request.borrow().amount, 1000);
+    }
+}
@@ -1,29 +1,63 @@
 use crate::kernel::sleep::sleep;
-use crate::kernel::kernel::{current_priority, kill_tree, schedule};
+use crate::kernel::kernel::{current_priority, kill_tree, schedule, set_process_budget};
 use crate::priorities::SPAWNING_CREEPS_PRIORITY;
 use crate::room_states::room_states::with_room_state;
 use log::{debug, info};
 use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::{game, RoomName};
 use crate::construction::build_structures::build_structures;
+use crate::cpu_management::{cpu_level, CpuLevel};
+use crate::cpu_management::CpuFeature::NonEssentialRoomProcesses;
+use crate::construction::demolish_hostile_structures::demolish_hostile_structures;
 use crate::construction::repair_structures::repair_structures;
 use crate::construction::triage_repair_sites::triage_repair_sites;
+use crate::config::OWNED_ROOMS_CPU_FRACTION;
 use crate::consts::FAR_FUTURE;
 use crate::economy::gather_eco_samples::gather_eco_samples;
 use crate::economy::update_eco_config::update_eco_config;
 use crate::room_maintenance::fill_structures_with_energy::fill_structures_with_energy;
 use crate::hauling::haul_resources::haul_resources;
 use crate::room_maintenance::manage_storage::manage_storage;
+use crate::room_maintenance::manage_terminal::manage_terminal;
+use crate::room_maintenance::links::operate_links;
+use crate::labs::run_labs::run_labs;
+use crate::room_maintenance::loot_remains::loot_remains;
+use crate::room_maintenance::reserve_remotes::reserve_remotes;
 use crate::room_maintenance::mine_sources::mine_sources;
+use crate::spawning::renew_creeps::renew_creeps;
+use crate::towers::operate_towers;
+use crate::defense::defend_ramparts::defend_ramparts;
+use crate::defense::invader_cores::handle_invader_cores;
 use crate::spawning::spawn_room_creeps::{spawn_room_creeps, update_spawn_list};
 use crate::u;
 use crate::room_maintenance::upgrade_controller::upgrade_controller;
+use crate::room_maintenance::evacuate_room::evacuate_room;
+use crate::room_states::room_lifecycle::RoomLifecycle::Evacuating;
+
+/// Which processes maintain a room.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum MaintenanceMode {
+    Full,
+    /// Only spawning, defense and hauling while the CPU is critically low.
+    EssentialOnly,
+    Evacuating,
+}
 
 /// Each tick, schedule or kill processes to maintain a room.
 pub async fn maintain_rooms() {
     let mut room_processes = FxHashMap::default();
+    // Rooms stay in the essential-only mode until the CPU level is back to normal so that their
+    // processes are not restarted repeatedly.
+    let mut essential_only = false;
 
     loop {
+        let level = cpu_level();
+        if !level.enables(NonEssentialRoomProcesses) {
+            essential_only = true;
+        } else if level >= CpuLevel::Normal {
+            essential_only = false;
+        }
+
         // Checking which rooms were lost by comparing them with the current information contained
         // keys of `room_processes`.
         let mut lost_rooms = room_processes.keys().cloned().collect::<FxHashSet<_>>();
@@ -32,36 +66,75 @@ pub async fn maintain_rooms() {
             lost_rooms.remove(&room_name);
 
             // Only maintaining rooms that have a plan are maintained.
-            // Finding out if the room has a plan.
-            let has_plan = with_room_state(room_name, |room_state| {
-                room_state.plan.is_some()
-            }).unwrap_or(false);
+            // Finding out if the room has a plan and whether it is being evacuated.
+            let (has_plan, evacuating) = with_room_state(room_name, |room_state| {
+                (room_state.plan.is_some(), room_state.lifecycle == Evacuating)
+            }).unwrap_or((false, false));
             
             if has_plan {
+                let mode = if evacuating {
+                    MaintenanceMode::Evacuating
+                } else if essential_only {
+                    MaintenanceMode::EssentialOnly
+                } else {
+                    MaintenanceMode::Full
+                };
+
+                // Replacing all processes of the room when it starts or stops being evacuated or
+                // maintained only by the essential processes.
+                if room_processes.get(&room_name).is_some_and(|&(process_mode, _)| process_mode != mode) {
+                    let (_, room_process) = u!(room_processes.remove(&room_name));
+                    if let Err(err) = kill_tree(room_process, ()) {
+                        err.warn(&format!("Failed to kill the maintenance process of room {}", room_name));
+                    }
+                }
+
                 room_processes.entry(room_name).or_insert_with(|| {
                     // Schedule the room maintenance process to run later so that it can be killed
                     // before it runs in the tick the room is lost.
-                    schedule(
-                        &format!("maintain_room_{}", room_name),
-                        current_priority() - 1,
-                        maintain_room(room_name),
-                    )
+                    let room_process = if mode == MaintenanceMode::Evacuating {
+                        schedule(
+                            &format!("maintain_evacuated_room_{}", room_name),
+                            current_priority() - 1,
+                            maintain_evacuated_room(room_name),
+                        )
+                    } else {
+                        schedule(
+                            &format!("maintain_room_{}", room_name),
+                            current_priority() - 1,
+                            maintain_room(room_name, mode == MaintenanceMode::EssentialOnly),
+                        )
+                    };
+                    (mode, room_process)
                 });
             }
         }
 
         for room_name in lost_rooms.into_iter() {
-            let room_process = u!(room_processes.remove(&room_name));
+            let (_, room_process) = u!(room_processes.remove(&room_name));
             info!("Lost room {}.", room_name);
-            kill_tree(room_process, ());
+            if let Err(err) = kill_tree(room_process, ()) {
+                err.warn(&format!("Failed to kill the maintenance process of lost room {}", room_name));
+            }
             // TODO Release other room resources, reallocate creeps.
         }
 
+        if !room_processes.is_empty() {
+            let room_cpu_budget = OWNED_ROOMS_CPU_FRACTION * game::cpu::tick_limit() / room_processes.len() as f64;
+            for (_, room_process) in room_processes.values() {
+                if let Err(err) = set_process_budget(room_process.pid, room_cpu_budget) {
+                    err.warn("Failed to set the CPU budget of a room maintenance process");
+                }
+            }
+        }
+
         sleep(1).await;
     }
 }
 
-async fn maintain_room(room_name: RoomName) {
+/// Schedules the processes maintaining the room. With `essential_only`, only the ones spawning
+/// creeps, defending the room and hauling resources are scheduled.
+async fn maintain_room(room_name: RoomName, essential_only: bool) {
     with_room_state(room_name, |room_state| {
         let structures_broadcast = room_state.structures_broadcast.clone_primed();
     
@@ -88,12 +161,14 @@ async fn maintain_room(room_name: RoomName) {
             fill_structures_with_energy(room_name)
         );
 
-        // Schedule mining sources inside the room.
-        schedule(
-            &format!("mine_sources_{}", room_name),
-            current_priority() - 1,
-            mine_sources(room_name)
-        );
+        if !essential_only {
+            // Schedule mining sources inside the room.
+            schedule(
+                &format!("mine_sources_{}", room_name),
+                current_priority() - 1,
+                mine_sources(room_name)
+            );
+        }
 
         // Handle scheduled hauls and control haulers.
         schedule(
@@ -109,20 +184,81 @@ async fn maintain_room(room_name: RoomName) {
             manage_storage(room_name)
         );
         
+        if !essential_only {
+            // Keep the terminal stocked, send queued transfers and sell the surplus on the market.
+            schedule(
+                &format!("manage_terminal_{}", room_name),
+                current_priority() - 1,
+                manage_terminal(room_name)
+            );
+
+            // Send energy from the source links to the core and controller links.
+            schedule(
+                &format!("operate_links_{}", room_name),
+                current_priority() - 1,
+                operate_links(room_name)
+            );
+
+            // Produce the boost compounds in the labs and keep them ready for boosting creeps.
+            schedule(
+                &format!("run_labs_{}", room_name),
+                current_priority() - 1,
+                run_labs(room_name)
+            );
+        }
+
+        // Attack hostiles, heal creeps and repair critical structures with towers.
+        // Should run before the incidents are observed in `defend_rooms`.
         schedule(
-            &format!("gather_eco_samples_{}", room_name),
-            current_priority() - 10,
-            gather_eco_samples(room_name)
+            &format!("operate_towers_{}", room_name),
+            current_priority() - 1,
+            operate_towers(room_name)
         );
 
-        // Update stats and decide on resource distribution within the room.
-        // This should happen after everything else.
+        // Spawn defenders and keep them on the ramparts closest to the hostiles.
         schedule(
-            &format!("update_eco_config_{}", room_name),
-            current_priority() - 11,
-            update_eco_config(room_name)
+            &format!("defend_ramparts_{}", room_name),
+            current_priority() - 1,
+            defend_ramparts(room_name)
+        );
+
+        // Pause hauling in remotes with invader cores and send attackers against them.
+        schedule(
+            &format!("handle_invader_cores_{}", room_name),
+            current_priority() - 1,
+            handle_invader_cores(room_name)
         );
 
+        if !essential_only {
+            // Keep the controllers of profitable remotes reserved.
+            schedule(
+                &format!("reserve_remotes_{}", room_name),
+                current_priority() - 1,
+                reserve_remotes(room_name)
+            );
+
+            // Loot tombstones and ruins in the room and its remotes.
+            schedule(
+                &format!("loot_remains_{}", room_name),
+                current_priority() - 1,
+                loot_remains(room_name)
+            );
+
+            schedule(
+                &format!("gather_eco_samples_{}", room_name),
+                current_priority() - 10,
+                gather_eco_samples(room_name)
+            );
+
+            // Update stats and decide on resource distribution within the room.
+            // This should happen after everything else.
+            schedule(
+                &format!("update_eco_config_{}", room_name),
+                current_priority() - 11,
+                update_eco_config(room_name)
+            );
+        }
+
         // Spawning creeps is scheduled to run later to react to spawning requests.
         schedule(
             &format!("spawn_creeps_{}", room_name),
@@ -135,39 +271,73 @@ async fn maintain_room(room_name: RoomName) {
             },
         );
 
-        // Upgrade the controller, spawn upgraders and schedule hauling of the energy.
+        // Renew essential creeps next to idle spawns.
         schedule(
-            &format!("upgrade_controller_{}", room_name),
-            current_priority() - 1,
-            upgrade_controller(room_name)
+            &format!("renew_creeps_{}", room_name),
+            SPAWNING_CREEPS_PRIORITY,
+            renew_creeps(room_name)
         );
 
-        // Build structures in the room and spawn builders.
-        schedule(
-            &format!("build_structures_{}", room_name),
-            current_priority() - 1,
-            build_structures(room_name)
-        );
-        
-        // Order structures to be repaired in the room.
-        // TODO Shouldn't this be more global?
-        schedule(
-            &format!("select_repair_sites_{}", room_name),
-            current_priority() - 1,
-            triage_repair_sites(room_name)
-        );
-        
-        // Repair structures in the room and spawn repairers.
-        // Should run after selecting the repair sites.
-        schedule(
-            &format!("repair_structures_{}", room_name),
-            current_priority() - 2,
-            repair_structures(room_name)
-        );
+        if !essential_only {
+            // Upgrade the controller, spawn upgraders and schedule hauling of the energy.
+            schedule(
+                &format!("upgrade_controller_{}", room_name),
+                current_priority() - 1,
+                upgrade_controller(room_name)
+            );
+
+            // Build structures in the room and spawn builders.
+            schedule(
+                &format!("build_structures_{}", room_name),
+                current_priority() - 1,
+                build_structures(room_name)
+            );
+
+            // Destroy structures left by the previous owner of the room.
+            schedule(
+                &format!("demolish_hostile_structures_{}", room_name),
+                current_priority() - 1,
+                demolish_hostile_structures(room_name)
+            );
+
+            // Order structures to be repaired in the room.
+            // TODO Shouldn't this be more global?
+            schedule(
+                &format!("select_repair_sites_{}", room_name),
+                current_priority() - 1,
+                triage_repair_sites(room_name)
+            );
+
+            // Repair structures in the room and spawn repairers.
+            // Should run after selecting the repair sites.
+            schedule(
+                &format!("repair_structures_{}", room_name),
+                current_priority() - 2,
+                repair_structures(room_name)
+            );
+        }
     });
 
     debug!("Finished setting up maintenance of room {}.", room_name);
     // The process has done its job, now it is waiting for the whole tree to be killed when
     // the room is lost.
     sleep(FAR_FUTURE).await;
-}
\ No newline at end of file
+}
+
+/// Only hauls the resources out of the storage without spawning any creeps or spending resources.
+async fn maintain_evacuated_room(room_name: RoomName) {
+    schedule(
+        &format!("haul_resources_{}", room_name),
+        current_priority() - 1,
+        haul_resources(room_name)
+    );
+
+    schedule(
+        &format!("evacuate_room_{}", room_name),
+        current_priority() - 1,
+        evacuate_room(room_name)
+    );
+
+    debug!("Finished setting up evacuation of room {}.", room_name);
+    sleep(FAR_FUTURE).await;
+}
@@ -1,5 +1,6 @@
 use log::debug;
-use rustc_hash::FxHashMap;
+use std::iter::once;
+use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::{ResourceType, RoomName, StructureStorage, STORAGE_CAPACITY};
 use screeps::game::get_object_by_id_typed;
 use screeps::StructureType::Storage;
@@ -38,35 +39,49 @@ pub async fn manage_storage(room_name: RoomName) {
             let free_capacity = get_free_capacity_with_object(&obj, storage_id.into(), None, AfterAllTransfers);
             let used_capacities = get_used_capacities_with_object(&obj, storage_id.into(), AfterAllTransfers);
             
-            // TODO Not only energy, but anything. But do not allow conflicts when close to full.
-            
-            debug!("Scheduling haul of depositable {free_capacity} energy for storage in {room_name}.");
-            let previous_deposit_request = deposit_requests.remove(&ResourceType::Energy);
-            // The previous deposit request is replaced by this one.
-            let mut deposit_request = HaulRequest::new(
-                DepositRequest,
-                room_name,
-                ResourceType::Energy,
-                storage_id,
-                StorageTarget,
-                false,
-                storage_pos
-            );
-            deposit_request.amount = free_capacity;
-            deposit_request.priority = Priority(100);
-            deposit_requests.insert(
-                ResourceType::Energy,
-                schedule_haul(deposit_request, previous_deposit_request)
-            );
-            
-            let previous_withdraw_request = withdraw_requests.remove(&ResourceType::Energy);
-            if let Some(&used_capacity) = used_capacities.get(&ResourceType::Energy) {
-                debug!("Scheduling haul of withdrawable {used_capacity} energy for storage in {room_name}.");
+            // Resources that are no longer in the storage cannot be withdrawn.
+            withdraw_requests.retain(|resource_type, _| used_capacities.contains_key(resource_type));
+            deposit_requests.retain(|resource_type, _| {
+                *resource_type == ResourceType::Energy || used_capacities.contains_key(resource_type)
+            });
+
+            // Energy can always be deposited. Other resources are deposited into the storage by
+            // haulers with leftovers even if there is no request yet.
+            // TODO Do not allow conflicts when close to full.
+            let depositable_resource_types = used_capacities
+                .keys()
+                .copied()
+                .chain(once(ResourceType::Energy))
+                .collect::<FxHashSet<_>>();
+            for resource_type in depositable_resource_types {
+                debug!("Scheduling haul of depositable {free_capacity} {resource_type} for storage in {room_name}.");
+                let previous_deposit_request = deposit_requests.remove(&resource_type);
+                // The previous deposit request is replaced by this one.
+                let mut deposit_request = HaulRequest::new(
+                    DepositRequest,
+                    room_name,
+                    resource_type,
+                    storage_id,
+                    StorageTarget,
+                    false,
+                    storage_pos
+                );
+                deposit_request.amount = free_capacity;
+                deposit_request.priority = Priority(100);
+                deposit_requests.insert(
+                    resource_type,
+                    schedule_haul(deposit_request, previous_deposit_request)
+                );
+            }
+
+            for (&resource_type, &used_capacity) in used_capacities.iter() {
+                debug!("Scheduling haul of withdrawable {used_capacity} {resource_type} for storage in {room_name}.");
+                let previous_withdraw_request = withdraw_requests.remove(&resource_type);
                 // The previous withdraw request is replaced by this one.
                 let mut withdraw_request = HaulRequest::new(
                     WithdrawRequest,
                     room_name,
-                    ResourceType::Energy,
+                    resource_type,
                     storage_id,
                     StorageTarget,
                     false,
@@ -75,7 +90,7 @@ pub async fn manage_storage(room_name: RoomName) {
                 withdraw_request.amount = used_capacity;
                 withdraw_request.priority = Priority(100);
                 withdraw_requests.insert(
-                    ResourceType::Energy,
+                    resource_type,
                     schedule_haul(withdraw_request, previous_withdraw_request)
                 );
             }
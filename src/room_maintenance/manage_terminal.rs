@@ -0,0 +1,250 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use js_sys::JsString;
+use log::{info, warn};
+use rustc_hash::FxHashMap;
+use screeps::{game, MarketResourceType, OrderType, RawObjectId, ResourceType, RoomName, StructureStorage, StructureTerminal};
+use screeps::game::get_object_by_id_typed;
+use screeps::local::LodashFilter;
+use screeps::StructureType::{Storage, Terminal};
+use crate::config::{CREDITS_PER_ENERGY, TERMINAL_ENERGY_BUFFER, TERMINAL_RESOURCE_CAP};
+use crate::economy::market::{best_deal, BuyOrderSnapshot};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::requests::HaulFlow::Balancing;
+use crate::hauling::requests::HaulRequest;
+use crate::hauling::requests::HaulRequestKind::DepositRequest;
+use crate::hauling::requests::HaulRequestTargetKind::RegularTarget;
+use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::{get_used_capacities_with_object, register_transfer};
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::kernel::wait_until_some::wait_until_some;
+use crate::local_debug;
+use crate::room_states::room_states::with_room_state;
+use crate::room_states::utils::loop_until_structures_change;
+use crate::utils::game_tick::game_tick;
+use crate::utils::priority::Priority;
+use crate::utils::result_utils::ResultUtils;
+
+const DEBUG: bool = true;
+
+/// Terminals are available from this RCL.
+const MIN_TERMINAL_RCL: u8 = 6;
+/// Market orders are checked at most once per this many ticks since it is CPU-intensive.
+const MARKET_CHECK_INTERVAL: u32 = 100;
+
+/// A transfer of a resource between terminals requested from the console.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TerminalTransfer {
+    pub from: RoomName,
+    pub to: RoomName,
+    pub resource_type: ResourceType,
+    pub amount: u32,
+}
+
+thread_local! {
+    /// Transfers waiting for the terminal of their source room, sent in order.
+    static TERMINAL_TRANSFERS: RefCell<VecDeque<TerminalTransfer>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Queues a transfer of a resource from the terminal of one owned room to another room. It is sent
+/// once the terminal is off cooldown, before selling anything.
+pub fn queue_terminal_transfer(transfer: TerminalTransfer) {
+    info!("Queued sending {} {} from {} to {}.", transfer.amount, transfer.resource_type, transfer.from, transfer.to);
+    TERMINAL_TRANSFERS.with(|transfers| transfers.borrow_mut().push_back(transfer));
+}
+
+/// Whether a transfer from the room is waiting to be sent.
+pub fn has_queued_terminal_transfer(room_name: RoomName) -> bool {
+    next_terminal_transfer(room_name).is_some()
+}
+
+fn next_terminal_transfer(room_name: RoomName) -> Option<TerminalTransfer> {
+    TERMINAL_TRANSFERS.with(|transfers| transfers.borrow().iter().find(|transfer| transfer.from == room_name).copied())
+}
+
+fn remove_terminal_transfer(transfer: TerminalTransfer) {
+    TERMINAL_TRANSFERS.with(|transfers| {
+        let mut transfers = transfers.borrow_mut();
+        if let Some(i) = transfers.iter().position(|&queued_transfer| queued_transfer == transfer) {
+            transfers.remove(i);
+        }
+    });
+}
+
+/// Keeps the terminal of an RCL6+ room stocked with energy and the surplus of minerals, sends
+/// queued transfers and sells the surplus on the market.
+pub async fn manage_terminal(room_name: RoomName) {
+    loop {
+        let (terminal_xy, terminal_id, storage_id) = wait_until_some(|| {
+            with_room_state(room_name, |room_state| {
+                if room_state.rcl < MIN_TERMINAL_RCL {
+                    return None;
+                }
+                let (terminal_xy, terminal_id) = room_state.structures_with_type::<StructureTerminal>(Terminal).next()?;
+                let storage_id = room_state
+                    .structures_with_type::<StructureStorage>(Storage)
+                    .next()
+                    .map(|(_, id)| id);
+                Some((terminal_xy, terminal_id, storage_id))
+            }).flatten()
+        }).await;
+
+        let terminal_pos = terminal_xy.to_pos(room_name);
+
+        let mut deposit_requests = FxHashMap::default();
+        let mut next_market_check_tick = game_tick();
+
+        loop_until_structures_change(room_name, 1, || {
+            let Some(terminal) = get_object_by_id_typed(&terminal_id) else {
+                return true;
+            };
+            let terminal_stock = get_used_capacities_with_object(&terminal, terminal_id.into(), AfterAllTransfers);
+            let storage_stock = storage_id
+                .and_then(|id| Some(get_used_capacities_with_object(&get_object_by_id_typed(&id)?, id.into(), AfterAllTransfers)))
+                .unwrap_or_default();
+            let terminal_amount = |resource_type| terminal_stock.get(&resource_type).copied().unwrap_or(0);
+
+            // The energy buffer and the surplus of other resources are brought from the storage.
+            let mut missing_amounts = FxHashMap::default();
+            missing_amounts.insert(ResourceType::Energy, TERMINAL_ENERGY_BUFFER.saturating_sub(terminal_amount(ResourceType::Energy)));
+            for (&resource_type, &storage_amount) in storage_stock.iter() {
+                if resource_type != ResourceType::Energy {
+                    let surplus = (storage_amount + terminal_amount(resource_type)).saturating_sub(TERMINAL_RESOURCE_CAP);
+                    missing_amounts.insert(resource_type, surplus.saturating_sub(terminal_amount(resource_type)).min(storage_amount));
+                }
+            }
+
+            deposit_requests.retain(|resource_type, _| missing_amounts.get(resource_type).is_some_and(|&amount| amount > 0));
+            for (&resource_type, &amount) in missing_amounts.iter() {
+                if amount == 0 {
+                    continue;
+                }
+                local_debug!("Scheduling haul of {amount} {resource_type} to the terminal in {room_name}.");
+                // The previous request is replaced by this one.
+                let mut deposit_request = HaulRequest::new(
+                    DepositRequest,
+                    room_name,
+                    resource_type,
+                    terminal_id,
+                    RegularTarget,
+                    false,
+                    terminal_pos
+                );
+                deposit_request.amount = amount;
+                deposit_request.priority = Priority(50);
+                deposit_request.flow = Balancing;
+                let previous_deposit_request = deposit_requests.remove(&resource_type);
+                deposit_requests.insert(resource_type, schedule_haul(deposit_request, previous_deposit_request));
+            }
+
+            if terminal.cooldown() > 0 {
+                return true;
+            }
+
+            if let Some(transfer) = next_terminal_transfer(room_name) {
+                send_transfer(&terminal, terminal_id.into(), transfer, terminal_amount(transfer.resource_type), terminal_amount(ResourceType::Energy));
+                return true;
+            }
+
+            if game_tick() >= next_market_check_tick {
+                next_market_check_tick = game_tick() + MARKET_CHECK_INTERVAL;
+                sell_surplus(room_name, terminal_id.into(), &terminal_stock, &storage_stock);
+            }
+
+            true
+        }).await;
+    }
+}
+
+fn send_transfer(
+    terminal: &StructureTerminal,
+    terminal_id: RawObjectId,
+    transfer: TerminalTransfer,
+    available_amount: u32,
+    available_energy: u32
+) {
+    let energy_cost = game::market::calc_transaction_cost(
+        transfer.amount,
+        &JsString::from(transfer.from.to_string()),
+        &JsString::from(transfer.to.to_string())
+    );
+    let needed_energy = if transfer.resource_type == ResourceType::Energy {
+        transfer.amount + energy_cost
+    } else {
+        energy_cost
+    };
+    if available_amount < transfer.amount || available_energy < needed_energy {
+        warn!(
+            "Dropping the transfer of {} {} from {} to {} since the terminal does not have enough of it or the energy to send it.",
+            transfer.amount, transfer.resource_type, transfer.from, transfer.to
+        );
+        remove_terminal_transfer(transfer);
+        return;
+    }
+
+    let result = terminal.send(transfer.resource_type, transfer.amount, transfer.to, None);
+    if result.is_ok() {
+        info!("Sent {} {} from {} to {}.", transfer.amount, transfer.resource_type, transfer.from, transfer.to);
+        register_transfer(terminal_id, transfer.resource_type, -(transfer.amount as i32));
+        register_transfer(terminal_id, ResourceType::Energy, -(energy_cost as i32));
+    }
+    result.warn_if_err("Failed to send resources from the terminal");
+    remove_terminal_transfer(transfer);
+}
+
+/// Sells the surplus of one resource in the terminal to the best buy order on the market, if
+/// any of them nets a positive amount of credits.
+fn sell_surplus(
+    room_name: RoomName,
+    terminal_id: RawObjectId,
+    terminal_stock: &FxHashMap<ResourceType, u32>,
+    storage_stock: &FxHashMap<ResourceType, u32>
+) {
+    let available_energy = terminal_stock.get(&ResourceType::Energy).copied().unwrap_or(0);
+    let room_name_str = JsString::from(room_name.to_string());
+
+    for (&resource_type, &terminal_amount) in terminal_stock.iter() {
+        if resource_type == ResourceType::Energy {
+            continue;
+        }
+        let total_amount = terminal_amount + storage_stock.get(&resource_type).copied().unwrap_or(0);
+        let surplus = total_amount.saturating_sub(TERMINAL_RESOURCE_CAP).min(terminal_amount);
+        if surplus == 0 {
+            continue;
+        }
+
+        let filter = LodashFilter::new();
+        filter.resource_type(MarketResourceType::Resource(resource_type));
+        let orders = game::market::get_all_orders(Some(&filter))
+            .into_iter()
+            .filter(|order| order.order_type() == OrderType::Buy)
+            .filter_map(|order| {
+                let order_room_name = order.room_name()?;
+                Some(BuyOrderSnapshot {
+                    id: order.id().into(),
+                    price: order.price(),
+                    remaining_amount: order.remaining_amount(),
+                    transaction_cost_per_1000: game::market::calc_transaction_cost(1000, &room_name_str, &order_room_name),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let Some(deal) = best_deal(&orders, surplus, available_energy, CREDITS_PER_ENERGY) else {
+            local_debug!("No profitable buy order for {} {} in {}.", surplus, resource_type, room_name);
+            continue;
+        };
+
+        let result = game::market::deal(&JsString::from(deal.order_id.as_str()), deal.amount, Some(room_name));
+        if result.is_ok() {
+            info!(
+                "Sold {} {} from {} for {:.1} credits net of the energy cost.",
+                deal.amount, resource_type, room_name, deal.net_credits
+            );
+            register_transfer(terminal_id, resource_type, -(deal.amount as i32));
+            register_transfer(terminal_id, ResourceType::Energy, -(deal.energy_cost as i32));
+        }
+        result.warn_if_err("Failed to sell resources on the market");
+        // The terminal is on cooldown after the deal.
+        break;
+    }
+}
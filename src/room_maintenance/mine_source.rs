@@ -10,13 +10,15 @@ use crate::utils::result_utils::ResultUtils;
 use log::{debug, warn};
 use screeps::game::get_object_by_id_typed;
 use screeps::look::ENERGY;
-use screeps::{HasId, ResourceType, RoomName};
+use screeps::{HasHits, HasId, ObjectId, ResourceType, RoomName, RoomXY, StructureContainer};
 use crate::consts::FAR_FUTURE;
 use crate::geometry::room_xy::RoomXYUtils;
-use crate::hauling::requests::HaulRequest;
+use crate::hauling::requests::{HaulRequest, HaulRequestHandle};
 use crate::hauling::requests::HaulRequestKind::WithdrawRequest;
-use crate::hauling::requests::HaulRequestTargetKind::PickupTarget;
+use crate::hauling::requests::HaulRequestTargetKind::{PickupTarget, RegularTarget};
 use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::get_used_capacity_with_object;
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
 use crate::kernel::wait_until_some::wait_until_some;
 use crate::room_states::utils::run_future_until_structures_change;
 use crate::spawning::preferred_spawn::best_spawns;
@@ -30,7 +32,7 @@ use crate::utils::resource_decay::decay_per_tick;
 const DEBUG: bool = true;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum MiningKind {
+pub enum MiningKind {
     DropMining,
     ContainerMining,
     LinkMining,
@@ -63,14 +65,15 @@ pub async fn mine_source(room_name: RoomName, source_ix: usize, initial_miners:
                 priority: MINER_SPAWN_PRIORITY,
                 preferred_spawns,
                 tick: (0, 0),
+                home_room: None,
             };
 
             (base_spawn_request, source_data)
         }));
         
         let mining_kind = match (source_data.link_id, source_data.container_id) {
-            (Some(_), None) => MiningKind::LinkMining,
-            (_, Some(_)) => MiningKind::ContainerMining,
+            (Some(_), _) => MiningKind::LinkMining,
+            (None, Some(_)) => MiningKind::ContainerMining,
             (None, None) => MiningKind::DropMining,
         };
         
@@ -97,6 +100,8 @@ pub async fn mine_source(room_name: RoomName, source_ix: usize, initial_miners:
         let mut spawn_pool = SpawnPool::new(room_name, base_spawn_request, spawn_pool_options);
 
         run_future_until_structures_change(room_name, async move {
+            let mut container_withdraw_request = None;
+
             loop {
                 let (source_miners_required, miner_body, miner_spawn_priority) = wait_until_some(|| with_room_state(room_name, |room_state| {
                     room_state
@@ -115,7 +120,12 @@ pub async fn mine_source(room_name: RoomName, source_ix: usize, initial_miners:
                             (source_miners_required, config.miner_body.clone(), config.miner_spawn_priority)
                         })
                 }).flatten()).await;
-                spawn_pool.target_number_of_creeps = min(source_data.drop_mining_xys.len() as u32, source_miners_required);
+                spawn_pool.target_number_of_creeps = if mining_kind == MiningKind::DropMining {
+                    min(source_data.drop_mining_xys.len() as u32, source_miners_required)
+                } else {
+                    // There is only one work position when not drop mining.
+                    min(1, source_miners_required)
+                };
                 spawn_pool.base_spawn_request.body = miner_body;
                 spawn_pool.base_spawn_request.priority = miner_spawn_priority;
                 
@@ -131,6 +141,21 @@ pub async fn mine_source(room_name: RoomName, source_ix: usize, initial_miners:
                             .push(total_harvest_power);
                     }
                 });
+
+                // The energy in the container is hauled away by a standing request instead of
+                // picking up the energy dropped by the miners.
+                container_withdraw_request = source_data.container_id.and_then(|container_id| {
+                    let container = get_object_by_id_typed(&container_id)?;
+                    let amount = get_used_capacity_with_object(&container, container_id.into(), Some(ResourceType::Energy), AfterAllTransfers);
+                    Some(schedule_container_withdraw(
+                        room_name,
+                        u!(source_data.work_xy),
+                        container_id,
+                        amount,
+                        total_harvest_power,
+                        container_withdraw_request.take()
+                    ))
+                });
                 
                 // Keeping a miner or multiple miners spawned and mining.
                 spawn_pool.with_spawned_creeps(|creep_ref| {
@@ -140,6 +165,8 @@ pub async fn mine_source(room_name: RoomName, source_ix: usize, initial_miners:
                         
                         let miner = creep_ref.as_ref();
                         let energy_income = creep_ref.borrow().body.energy_harvest_power();
+                        let store_capacity = creep_ref.borrow().body.store_capacity();
+                        let repair_power = creep_ref.borrow().body.repair_power();
 
                         // Moving towards the location.
                         while let Err(err) = travel(&creep_ref, travel_spec.clone()).await {
@@ -159,61 +186,111 @@ pub async fn mine_source(room_name: RoomName, source_ix: usize, initial_miners:
                                 mining_kind,
                                 source_data.id
                             );
-                            
+
                             let source = u!(get_object_by_id_typed(&source_data.id));
-                            if source.energy() > 0 {
-                                creep_ref.borrow_mut()
-                                    .harvest(&source)
-                                    .warn_if_err("Failed to mine the source");
-                                sleep(1).await;
-                            } else if creep_ref.borrow_mut().ticks_to_live() < source.ticks_to_regeneration().unwrap_or(FAR_FUTURE) {
-                                // If the miner does not exist by the time source regenerates, kill it.
-                                debug!("Miner {} has insufficient ticks to live. Killing it.", miner.borrow().name);
-                                creep_ref.borrow_mut().suicide().warn_if_err("Failed to kill the miner.");
-                                // TODO Store the energy first.
-                                break;
-                            } else {
-                                // The source is exhausted for now, so sleeping until it is regenerated.
-                                // TODO eco_stats.register_idle_creep(Miner);
-                                sleep(source.ticks_to_regeneration().unwrap_or(1)).await;
-                                continue;
-                            }
+                            let container = source_data
+                                .container_id
+                                .and_then(|id| get_object_by_id_typed(&id).map(|container| (id, container)));
+                            let miner_state = MinerState {
+                                source_energy: source.energy(),
+                                carried_energy: creep_ref
+                                    .borrow_mut()
+                                    .used_capacity(Some(ResourceType::Energy), AfterAllTransfers)
+                                    .unwrap_or(0),
+                                store_capacity,
+                                energy_income,
+                                repair_power,
+                                container: container.as_ref().map(|(id, container)| ContainerState {
+                                    hits: container.hits(),
+                                    hits_max: container.hits_max(),
+                                    energy: get_used_capacity_with_object(container, (*id).into(), Some(ResourceType::Energy), AfterAllTransfers),
+                                }),
+                            };
 
-                            // Transporting the energy in a way depending on room plan.
-                            match mining_kind {
-                                MiningKind::DropMining => {
-                                    let creep_pos = creep_ref.borrow_mut().travel_state.pos;
-                                    if let Some(dropped_energy) = u!(creep_pos.look_for(ENERGY)).first() {
-                                        let amount = dropped_energy.amount();
-                                        let mut new_pickup_request = HaulRequest::new(
-                                            WithdrawRequest,
-                                            room_name,
-                                            ResourceType::Energy,
-                                            dropped_energy.id(),
-                                            PickupTarget,
-                                            false,
-                                            creep_pos
-                                        );
-                                        new_pickup_request.amount = amount;
-                                        let decay = decay_per_tick(amount);
-                                        new_pickup_request.change = energy_income as i32 - decay as i32;
-                                        new_pickup_request.priority = Priority(100);
-    
-                                        // Ordering a hauler to get dropped energy, updating the existing request.
-                                        pickup_request = Some(schedule_haul(new_pickup_request, pickup_request.take()));
+                            match miner_action(mining_kind, &miner_state) {
+                                MinerAction::Harvest { store_in_link } => {
+                                    let harvest_result = creep_ref.borrow_mut().harvest(&source);
+                                    match harvest_result {
+                                        Ok(()) => {
+                                            let harvest_power = creep_ref.borrow().body.energy_harvest_power();
+                                            let amount = min(harvest_power, miner_state.source_energy);
+                                            with_room_state(room_name, |room_state| {
+                                                if let Some(eco_stats) = room_state.eco_stats.as_mut() {
+                                                    eco_stats.register_harvest(source_data.id, amount);
+                                                }
+                                            });
+                                        }
+                                        Err(err) => err.warn("Failed to mine the source"),
+                                    }
+
+                                    if store_in_link {
+                                        let link_id = u!(source_data.link_id);
+                                        if let Some(link) = get_object_by_id_typed(&link_id) {
+                                            creep_ref
+                                                .borrow_mut()
+                                                .transfer(link_id, &link, ResourceType::Energy, miner_state.carried_energy, false)
+                                                .warn_if_err("Failed to store the energy in the link");
+                                        }
+                                    }
+
+                                    // The energy ends up in the container if there is one. Otherwise,
+                                    // a hauler is ordered to pick up the dropped energy, updating
+                                    // the existing request. Miners without a store drop the energy
+                                    // even next to a link.
+                                    if container.is_none() && (mining_kind == MiningKind::DropMining || store_capacity == 0) {
+                                        let creep_pos = creep_ref.borrow_mut().travel_state.pos;
+                                        if let Some(dropped_energy) = u!(creep_pos.look_for(ENERGY)).first() {
+                                            let amount = dropped_energy.amount();
+                                            let mut new_pickup_request = HaulRequest::new(
+                                                WithdrawRequest,
+                                                room_name,
+                                                ResourceType::Energy,
+                                                dropped_energy.id(),
+                                                PickupTarget,
+                                                false,
+                                                creep_pos
+                                            );
+                                            new_pickup_request.amount = amount;
+                                            let decay = decay_per_tick(amount);
+                                            new_pickup_request.change = energy_income as i32 - decay as i32;
+                                            new_pickup_request.priority = Priority(100);
+
+                                            pickup_request = Some(schedule_haul(new_pickup_request, pickup_request.take()));
+                                        }
                                     }
                                 }
-                                MiningKind::ContainerMining => {
-                                    let container_id = u!(source_data.container_id);
-                                    // TODO
-                                    // Ordering a hauler to get energy from the container.
+                                MinerAction::WithdrawFromContainer(amount) => {
+                                    let (container_id, container) = u!(container.as_ref());
+                                    creep_ref
+                                        .borrow_mut()
+                                        .withdraw(*container_id, container, ResourceType::Energy, amount, false)
+                                        .warn_if_err("Failed to take energy from the container");
                                 }
-                                MiningKind::LinkMining => {
-                                    let link_id = u!(source_data.link_id);
-                                    // TODO
-                                    // Storing the energy into the link and sending it if the next batch would not fit.
+                                MinerAction::RepairContainer => {
+                                    let (_, container) = u!(container.as_ref());
+                                    creep_ref
+                                        .borrow_mut()
+                                        .repair(container)
+                                        .warn_if_err("Failed to repair the container");
+                                }
+                                MinerAction::Wait => {
+                                    let ticks_to_regeneration = source.ticks_to_regeneration().unwrap_or(FAR_FUTURE);
+                                    if creep_ref.borrow_mut().ticks_to_live() < ticks_to_regeneration {
+                                        // If the miner does not exist by the time source regenerates, kill it.
+                                        debug!("Miner {} has insufficient ticks to live. Killing it.", miner.borrow().name);
+                                        creep_ref.borrow_mut().suicide().warn_if_err("Failed to kill the miner.");
+                                        // TODO Store the energy first.
+                                        break;
+                                    }
+                                    // The source is exhausted for now and there is nothing to repair,
+                                    // so sleeping until it is regenerated.
+                                    // TODO eco_stats.register_idle_creep(Miner);
+                                    sleep(ticks_to_regeneration).await;
+                                    continue;
                                 }
                             }
+
+                            sleep(1).await;
                         }
                     }
                 });
@@ -222,4 +299,183 @@ pub async fn mine_source(room_name: RoomName, source_ix: usize, initial_miners:
             }
         }).await;
     }
-}
\ No newline at end of file
+}
+
+/// The state of a miner at its work position and of the container under it in the current tick.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MinerState {
+    pub source_energy: u32,
+    pub carried_energy: u32,
+    pub store_capacity: u32,
+    pub energy_income: u32,
+    pub repair_power: u32,
+    pub container: Option<ContainerState>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ContainerState {
+    pub hits: u32,
+    pub hits_max: u32,
+    pub energy: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MinerAction {
+    /// Harvesting the source and, if set, storing the carried energy in the link in the same tick.
+    Harvest { store_in_link: bool },
+    /// Taking given amount of energy from the container to repair it.
+    WithdrawFromContainer(u32),
+    RepairContainer,
+    /// Waiting for the source to regenerate.
+    Wait,
+}
+
+/// Decides what the miner does in the current tick. It harvests while the source has energy,
+/// storing it in the link before the next batch would not fit. The energy not stored in the link
+/// falls into the container or onto the ground. While the source is exhausted, a miner with a store
+/// repairs the container under itself using the energy from the container.
+pub fn miner_action(mining_kind: MiningKind, state: &MinerState) -> MinerAction {
+    if state.source_energy > 0 {
+        let store_in_link = mining_kind == MiningKind::LinkMining
+            && state.store_capacity > 0
+            && state.carried_energy > 0
+            && state.carried_energy + state.energy_income > state.store_capacity;
+        return MinerAction::Harvest { store_in_link };
+    }
+
+    if let Some(container) = state.container {
+        // Repairing only once a whole tick of repair would not be wasted.
+        let needs_repair = state.repair_power > 0 && container.hits + state.repair_power <= container.hits_max;
+        if needs_repair && state.store_capacity > 0 {
+            if state.carried_energy > 0 {
+                return MinerAction::RepairContainer;
+            } else if container.energy > 0 {
+                return MinerAction::WithdrawFromContainer(min(container.energy, state.store_capacity));
+            }
+        }
+    }
+
+    MinerAction::Wait
+}
+
+fn schedule_container_withdraw(
+    room_name: RoomName,
+    xy: RoomXY,
+    id: ObjectId<StructureContainer>,
+    amount: u32,
+    energy_income: u32,
+    replaced_request_handle: Option<HaulRequestHandle>
+) -> HaulRequestHandle {
+    let mut withdraw_request = HaulRequest::new(
+        WithdrawRequest,
+        room_name,
+        ResourceType::Energy,
+        id,
+        RegularTarget,
+        false,
+        xy.to_pos(room_name)
+    );
+    withdraw_request.amount = amount;
+    withdraw_request.change = energy_income as i32;
+    withdraw_request.priority = Priority(100);
+    schedule_haul(withdraw_request, replaced_request_handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::room_maintenance::mine_source::{miner_action, ContainerState, MinerAction, MinerState, MiningKind};
+
+    fn link_miner_state(source_energy: u32, carried_energy: u32) -> MinerState {
+        // Two Work parts and one Carry part.
+        MinerState {
+            source_energy,
+            carried_energy,
+            store_capacity: 50,
+            energy_income: 4,
+            repair_power: 200,
+            container: None,
+        }
+    }
+
+    #[test]
+    fn test_link_miner_stores_energy_before_store_fills() {
+        let state = link_miner_state(3000, 44);
+        assert_eq!(miner_action(MiningKind::LinkMining, &state), MinerAction::Harvest { store_in_link: false });
+        let state = link_miner_state(3000, 48);
+        assert_eq!(miner_action(MiningKind::LinkMining, &state), MinerAction::Harvest { store_in_link: true });
+        // Without a link, the energy overflows into the container or onto the ground.
+        assert_eq!(miner_action(MiningKind::ContainerMining, &state), MinerAction::Harvest { store_in_link: false });
+        // Miners without a store have nothing to put into the link.
+        let state = MinerState {
+            store_capacity: 0,
+            carried_energy: 0,
+            ..state
+        };
+        assert_eq!(miner_action(MiningKind::LinkMining, &state), MinerAction::Harvest { store_in_link: false });
+    }
+
+    #[test]
+    fn test_miner_repairs_container_while_source_exhausted() {
+        let damaged_container = ContainerState {
+            hits: 200000,
+            hits_max: 250000,
+            energy: 30,
+        };
+        let state = MinerState {
+            container: Some(damaged_container),
+            ..link_miner_state(0, 0)
+        };
+        // Taking the energy from the container first.
+        assert_eq!(miner_action(MiningKind::ContainerMining, &state), MinerAction::WithdrawFromContainer(30));
+        let state = MinerState {
+            carried_energy: 30,
+            ..state
+        };
+        assert_eq!(miner_action(MiningKind::ContainerMining, &state), MinerAction::RepairContainer);
+
+        // Back to harvesting once the source regenerates.
+        let state = MinerState {
+            source_energy: 3000,
+            ..state
+        };
+        assert_eq!(miner_action(MiningKind::ContainerMining, &state), MinerAction::Harvest { store_in_link: false });
+    }
+
+    #[test]
+    fn test_miner_waits_when_nothing_to_repair() {
+        let container = ContainerState {
+            hits: 249900,
+            hits_max: 250000,
+            energy: 500,
+        };
+        // A repair would be partially wasted.
+        let state = MinerState {
+            container: Some(container),
+            ..link_miner_state(0, 10)
+        };
+        assert_eq!(miner_action(MiningKind::ContainerMining, &state), MinerAction::Wait);
+
+        // No energy to repair with.
+        let state = MinerState {
+            container: Some(ContainerState {
+                hits: 100000,
+                energy: 0,
+                ..container
+            }),
+            ..link_miner_state(0, 0)
+        };
+        assert_eq!(miner_action(MiningKind::ContainerMining, &state), MinerAction::Wait);
+
+        // Drop miners cannot carry the energy to repair.
+        let state = MinerState {
+            store_capacity: 0,
+            container: Some(ContainerState {
+                hits: 100000,
+                ..container
+            }),
+            ..link_miner_state(0, 0)
+        };
+        assert_eq!(miner_action(MiningKind::ContainerMining, &state), MinerAction::Wait);
+        assert_eq!(miner_action(MiningKind::DropMining, &link_miner_state(0, 0)), MinerAction::Wait);
+    }
+}
@@ -12,7 +12,7 @@ use crate::kernel::kernel::{current_priority, schedule};
 use crate::kernel::sleep::sleep;
 use crate::room_maintenance::mine_source::mine_source;
 use crate::room_states::room_states::with_room_state;
-use crate::spawning::reserved_creep::find_unassigned_creep;
+use crate::spawning::reserved_creep::{find_unassigned_creep, CreepRequirements};
 use crate::u;
 use crate::utils::multi_map_utils::{MultiMapUtils, OrderedMultiMapUtils};
 
@@ -26,8 +26,8 @@ pub async fn mine_sources(room_name: RoomName) {
     // `SUFFICIENT_WORK_PARTS` available to it.
     let mut miners_and_dists = Vec::new();
     while let Some(reserved_creep) = find_unassigned_creep(
-        room_name,
         Miner,
+        &CreepRequirements::any_in_room(room_name),
         None
     ) {
         let mut dists = u!(with_room_state(room_name, |room_state| {
@@ -52,7 +52,7 @@ pub async fn mine_sources(room_name: RoomName) {
     // source. This is to ensure that the full-sized creeps are used first.
     let mut initial_miners = FxHashMap::default();
     let mut total_work_parts: FxHashMap<ObjectId<Source>, u32> = FxHashMap::default();
-    let mut miners_by_min_dist = BTreeMap::default();
+    let mut miners_by_min_dist: BTreeMap<_, Vec<_>> = BTreeMap::default();
     for (reserved_creep, work_parts, dists) in miners_and_dists.into_iter() {
         let min_dist = u!(dists.last()).1;
         miners_by_min_dist.push_or_insert((Reverse(work_parts), min_dist), (reserved_creep, dists));
@@ -1,6 +1,11 @@
 pub mod maintenance;
-mod fill_structures_with_energy;
+pub mod fill_structures_with_energy;
 mod mine_source;
 mod upgrade_controller;
 mod mine_sources;
-mod manage_storage;
\ No newline at end of file
+mod manage_storage;
+pub mod manage_terminal;
+mod evacuate_room;
+mod loot_remains;
+pub mod links;
+pub mod reserve_remotes;
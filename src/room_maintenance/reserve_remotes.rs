@@ -0,0 +1,280 @@
+use std::cmp::min;
+use log::{debug, info};
+use rustc_hash::FxHashMap;
+use screeps::{game, RoomName};
+use screeps::Part::{Claim, Move};
+use crate::creeps::creep_body::CreepBody;
+use crate::creeps::creep_role::CreepRole::Claimer;
+use crate::economy::room_eco_config::RemoteEcoData;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::kernel::kernel::{current_priority, kill_tree, schedule};
+use crate::kernel::process_handle::ProcessHandle;
+use crate::kernel::sleep::sleep;
+use crate::priorities::REMOTE_RESERVER_SPAWN_PRIORITY;
+use crate::room_planning::remote_planner::RemotePlan;
+use crate::room_states::room_state::ReservationData;
+use crate::room_states::room_states::with_room_state;
+use crate::spawning::spawn_pool::{SpawnPool, SpawnPoolOptions};
+use crate::spawning::spawn_schedule::generic_base_spawn_request;
+use crate::travel::travel::move_to_cached;
+use crate::travel::travel_spec::TravelSpec;
+use crate::u;
+use crate::utils::game_tick::game_tick;
+use crate::utils::result_utils::ResultUtils;
+
+/// A new reserver is sent when the reservation has fewer ticks left than this.
+const RESERVATION_RENEWAL_TICKS: u32 = 2000;
+/// Reservers have at most this many claim parts.
+pub const MAX_RESERVER_CLAIM_PARTS: u32 = 2;
+/// A reserver with a single claim part only keeps the reservation at the same level, so it is
+/// only sent when at least this many ticks of the reservation are left when it arrives.
+const MIN_SINGLE_CLAIM_RESERVATION_TICKS: u32 = 1000;
+
+/// What a reserver does with the controller of the remote.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ControllerAction {
+    Reserve,
+    /// Decrease the reservation of another player until it is gone and the controller can be
+    /// reserved.
+    Attack,
+}
+
+/// Whether a reserver should be on its way to the remote, i.e., whether it is not reserved by
+/// the bot or its reservation is running out.
+pub fn needs_reserver(reservation: Option<&ReservationData>, username: &str, tick: u32) -> bool {
+    match reservation {
+        Some(reservation) if reservation.username == username => {
+            reservation.end_tick.saturating_sub(tick) < RESERVATION_RENEWAL_TICKS
+        }
+        _ => true,
+    }
+}
+
+pub fn controller_action(reservation: Option<&ReservationData>, username: &str) -> ControllerAction {
+    match reservation {
+        Some(reservation) if reservation.username != username => ControllerAction::Attack,
+        _ => ControllerAction::Reserve,
+    }
+}
+
+/// Number of claim parts of a reserver that arrives after given number of travel ticks to
+/// a controller with reservation of the bot ending in given number of ticks. Two parts are
+/// needed to increase the reservation, one is enough to keep it while it is still high.
+/// `None` if the room cannot afford a reserver.
+pub fn reserver_claim_parts(reservation_ticks: u32, travel_ticks: u32, spawn_energy_capacity: u32) -> Option<u32> {
+    let affordable_parts = min(spawn_energy_capacity / (Claim.cost() + Move.cost()), MAX_RESERVER_CLAIM_PARTS);
+    if affordable_parts == 0 {
+        return None;
+    }
+    let reservation_on_arrival = reservation_ticks.saturating_sub(travel_ticks);
+    let needed_parts = if reservation_on_arrival >= MIN_SINGLE_CLAIM_RESERVATION_TICKS { 1 } else { 2 };
+    Some(min(needed_parts, affordable_parts))
+}
+
+/// Remotes are reserved only when mining them with a reserver, as assumed in
+/// `remote_energy_balance`, yields energy.
+pub fn is_reservation_profitable(remote_plan: &RemotePlan) -> bool {
+    !remote_plan.containers.is_empty() && remote_plan.energy_balance > 0.0
+}
+
+/// Approximate number of ticks to travel from the storage to the remote along the roads.
+fn remote_travel_ticks(remote_plan: &RemotePlan) -> u32 {
+    remote_plan.hauling_distance / (2 * remote_plan.containers.len() as u32).max(1)
+}
+
+/// Economy data of the remotes of the room that are mined, i.e., profitable and not paused due to
+/// an invader core.
+pub fn remote_eco_data(room_name: RoomName) -> Vec<RemoteEcoData> {
+    let Some((username, active_remotes)) = with_room_state(room_name, |room_state| {
+        let active_remotes = room_state
+            .remote_plans
+            .iter()
+            .filter(|&(remote_name, remote_plan)| {
+                is_reservation_profitable(remote_plan) && !room_state.paused_remotes.contains(remote_name)
+            })
+            .map(|(&remote_name, remote_plan)| (remote_name, remote_plan.containers.len() as u32, remote_plan.hauling_distance))
+            .collect::<Vec<_>>();
+        (room_state.owner.clone(), active_remotes)
+    }) else {
+        return Vec::new();
+    };
+
+    active_remotes
+        .into_iter()
+        .map(|(remote_name, number_of_sources, hauling_distance)| {
+            let reservation = with_room_state(remote_name, |remote_state| remote_state.reservation.clone()).flatten();
+            RemoteEcoData {
+                room_name: remote_name,
+                number_of_sources,
+                hauling_distance,
+                reserved: reservation.is_some_and(|reservation| {
+                    reservation.username == username && reservation.end_tick > game_tick()
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Keeps a `remote_reservation` process running for each profitable remote of the room that is
+/// not paused due to an invader core.
+pub async fn reserve_remotes(room_name: RoomName) {
+    let mut remote_processes: FxHashMap<RoomName, ProcessHandle<()>> = FxHashMap::default();
+
+    loop {
+        let active_remotes = with_room_state(room_name, |room_state| {
+            room_state
+                .remote_plans
+                .iter()
+                .filter(|&(remote_name, remote_plan)| {
+                    is_reservation_profitable(remote_plan) && !room_state.paused_remotes.contains(remote_name)
+                })
+                .map(|(&remote_name, remote_plan)| (remote_name, remote_travel_ticks(remote_plan)))
+                .collect::<FxHashMap<_, _>>()
+        }).unwrap_or_default();
+
+        remote_processes.retain(|remote_name, remote_process| {
+            if active_remotes.contains_key(remote_name) {
+                return true;
+            }
+            debug!("Stopping reservation of {} from {}.", remote_name, room_name);
+            if let Err(err) = kill_tree(remote_process.clone(), ()) {
+                err.warn(&format!("Failed to kill the reservation process of {}", remote_name));
+            }
+            false
+        });
+
+        for (&remote_name, &travel_ticks) in active_remotes.iter() {
+            remote_processes.entry(remote_name).or_insert_with(|| {
+                info!("Reserving {} from {}.", remote_name, room_name);
+                schedule(
+                    &format!("remote_reservation_{}", remote_name),
+                    current_priority() - 1,
+                    remote_reservation(room_name, remote_name, travel_ticks)
+                )
+            });
+        }
+
+        sleep(1).await;
+    }
+}
+
+/// Sends reservers from the room to the controller of the remote whenever its reservation runs
+/// low. The reservers reserve the controller or attack it if it is reserved by someone else.
+async fn remote_reservation(room_name: RoomName, remote_name: RoomName, travel_ticks: u32) {
+    let mut reserver_pool: Option<SpawnPool> = None;
+
+    loop {
+        let Some(controller_xy) = with_room_state(remote_name, |remote_state| {
+            remote_state.controller.map(|controller| controller.xy)
+        }).flatten() else {
+            sleep(10).await;
+            continue;
+        };
+        let controller_pos = controller_xy.to_pos(remote_name);
+        let (username, spawn_energy_capacity) = with_room_state(room_name, |room_state| {
+            (room_state.owner.clone(), room_state.resources.spawn_energy_capacity)
+        }).unwrap_or_default();
+        let reservation = with_room_state(remote_name, |remote_state| remote_state.reservation.clone()).flatten();
+
+        let own_reservation_ticks = reservation
+            .as_ref()
+            .filter(|reservation| reservation.username == username)
+            .map_or(0, |reservation| reservation.end_tick.saturating_sub(game_tick()));
+        let claim_parts = reserver_claim_parts(own_reservation_ticks, travel_ticks, spawn_energy_capacity);
+
+        if let Some(claim_parts) = claim_parts {
+            let reserver_pool = reserver_pool.get_or_insert_with(|| {
+                let base_spawn_request = u!(with_room_state(room_name, |room_state| {
+                    let mut base_spawn_request = generic_base_spawn_request(room_state, Claimer);
+                    base_spawn_request.priority = REMOTE_RESERVER_SPAWN_PRIORITY;
+                    base_spawn_request
+                }));
+                let travel_spec = TravelSpec::new(controller_pos, 1);
+                SpawnPool::new(room_name, base_spawn_request, SpawnPoolOptions::default().travel_spec(Some(travel_spec)))
+            });
+            reserver_pool.target_number_of_creeps = needs_reserver(reservation.as_ref(), &username, game_tick()) as u32;
+            reserver_pool.base_spawn_request.body = CreepBody::from(vec![(Move, claim_parts as u8), (Claim, claim_parts as u8)]);
+
+            reserver_pool.with_spawned_creeps(|creep_ref| {
+                let username = username.clone();
+                async move {
+                    loop {
+                        if let Err(err) = move_to_cached(&creep_ref, controller_pos, 1).await {
+                            err.warn(&format!("Reserver could not reach the controller in {}", remote_name));
+                            sleep(1).await;
+                            continue;
+                        }
+
+                        let controller = game::rooms().get(remote_name).and_then(|room| room.controller());
+                        if let Some(controller) = controller {
+                            let reservation = with_room_state(remote_name, |remote_state| {
+                                remote_state.reservation.clone()
+                            }).flatten();
+                            let mut creep = creep_ref.borrow_mut();
+                            match controller_action(reservation.as_ref(), &username) {
+                                ControllerAction::Reserve => creep
+                                    .reserve_controller(&controller)
+                                    .warn_if_err(&format!("Failed to reserve the controller in {}", remote_name)),
+                                ControllerAction::Attack => creep
+                                    .attack_controller(&controller)
+                                    .warn_if_err(&format!("Failed to attack the controller in {}", remote_name)),
+                            }
+                        }
+                        sleep(1).await;
+                    }
+                }
+            });
+        } else {
+            // Dropping the spawn pool releases the reservers.
+            reserver_pool = None;
+        }
+
+        sleep(1).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::room_maintenance::reserve_remotes::{controller_action, needs_reserver, reserver_claim_parts, ControllerAction};
+    use crate::room_states::room_state::ReservationData;
+
+    fn reservation(username: &str, end_tick: u32) -> ReservationData {
+        ReservationData {
+            username: username.into(),
+            end_tick,
+        }
+    }
+
+    #[test]
+    fn test_reserver_trigger_thresholds() {
+        let tick = 10_000;
+        assert!(needs_reserver(None, "xi", tick));
+        assert!(!needs_reserver(Some(&reservation("xi", tick + 4000)), "xi", tick));
+        assert!(!needs_reserver(Some(&reservation("xi", tick + 2000)), "xi", tick));
+        assert!(needs_reserver(Some(&reservation("xi", tick + 1999)), "xi", tick));
+        // Expired reservation that was not scanned since.
+        assert!(needs_reserver(Some(&reservation("xi", tick - 5)), "xi", tick));
+        // Someone else's reservation is always to be attacked.
+        assert!(needs_reserver(Some(&reservation("enemy", tick + 4000)), "xi", tick));
+    }
+
+    #[test]
+    fn test_claim_vs_attack() {
+        assert_eq!(controller_action(None, "xi"), ControllerAction::Reserve);
+        assert_eq!(controller_action(Some(&reservation("xi", 100)), "xi"), ControllerAction::Reserve);
+        assert_eq!(controller_action(Some(&reservation("enemy", 100)), "xi"), ControllerAction::Attack);
+        assert_eq!(controller_action(Some(&reservation("Invader", 100)), "xi"), ControllerAction::Attack);
+    }
+
+    #[test]
+    fn test_reserver_claim_parts() {
+        // An unreserved controller needs two parts to build up the reservation.
+        assert_eq!(reserver_claim_parts(0, 50, 1300), Some(2));
+        // One is enough to keep a high reservation, but not for a far remote.
+        assert_eq!(reserver_claim_parts(1900, 50, 1300), Some(1));
+        assert_eq!(reserver_claim_parts(1900, 950, 1300), Some(2));
+        // Limited by energy.
+        assert_eq!(reserver_claim_parts(0, 50, 800), Some(1));
+        assert_eq!(reserver_claim_parts(0, 50, 550), None);
+    }
+}
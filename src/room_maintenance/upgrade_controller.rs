@@ -1,17 +1,34 @@
-use log::warn;
-use screeps::{ResourceType, RoomName, CREEP_RANGED_ACTION_RANGE};
+use std::cell::{Cell, RefCell};
+use std::cmp::min;
+use std::rc::Rc;
+use log::{debug, warn};
+use rustc_hash::FxHashSet;
+use screeps::{controller_downgrade, HasId, HasPosition, ObjectId, Position, RawObjectId, ResourceType, RoomName, RoomXY, StructureContainer, StructureController, StructureLink, CREEP_RANGED_ACTION_RANGE};
 use screeps::game::get_object_by_id_typed;
+use screeps::StructureType::{Container, Link};
+use screeps::Terrain::Wall;
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::config::{CONTROLLER_SIGN, UPGRADER_BOOST_COMPOUND};
+use crate::creeps::actions::withdraw_when_able;
 use crate::creeps::creep_body::CreepBody;
 use crate::creeps::creep_role::CreepRole::Upgrader;
+use crate::creeps::creeps::CreepRef;
+use crate::economy::room_eco_stats::UpgradeEnergySource;
+use crate::economy::upgrade_allocation::max_upgrade_energy_per_tick;
+use crate::economy::room_eco_stats::UpgradeEnergySource::{Container as ContainerSource, Delivery, Link as LinkSource};
 use crate::geometry::room_xy::RoomXYUtils;
 use crate::hauling::requests::HaulRequest;
 use crate::hauling::requests::HaulRequestKind::DepositRequest;
 use crate::hauling::requests::HaulRequestTargetKind::CreepTarget;
 use crate::hauling::scheduling_hauls::schedule_haul;
+use crate::hauling::transfers::get_used_capacity_unchecked;
 use crate::hauling::transfers::TransferStage::AfterAllTransfers;
 use crate::kernel::sleep::sleep;
+use crate::labs::boost_creep::boost_creep;
 use crate::kernel::wait_until_some::wait_until_some;
 use crate::priorities::UPGRADER_SPAWN_PRIORITY;
+use crate::room_maintenance::links::core_link;
+use crate::room_states::room_state::RoomState;
 use crate::room_states::room_states::with_room_state;
 use crate::spawning::preferred_spawn::best_spawns;
 use crate::spawning::spawn_pool::{SpawnPool, SpawnPoolOptions};
@@ -19,11 +36,180 @@ use crate::spawning::spawn_schedule::SpawnRequest;
 use crate::travel::travel::travel;
 use crate::travel::travel_spec::TravelSpec;
 use crate::u;
+use crate::utils::game_tick::game_tick;
 use crate::utils::priority::Priority;
 use crate::utils::result_utils::ResultUtils;
 
+/// Upgraders wait at most this many ticks for the empty controller link to be refilled before
+/// asking haulers to deliver energy, and only while the links feeding it have energy to send.
+const MAX_LINK_WAIT_TICKS: u32 = 5;
+
+/// The state of the built controller link.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ControllerLinkState {
+    pub energy: u32,
+    /// The number of ticks the link has been empty for.
+    pub empty_ticks: u32,
+    /// Whether the links that send energy to the controller link have any.
+    pub refill_expected: bool,
+}
+
+/// What an upgrader should do in the current tick.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UpgraderEnergyPlan {
+    /// Where to get more energy from. `None` if the upgrader is full or waiting for the controller
+    /// link to be refilled.
+    pub refill: Option<UpgradeEnergySource>,
+    /// Whether to upgrade the controller.
+    pub upgrade: bool,
+}
+
+/// Decides where the upgrader gets its energy from, preferring the controller link, then
+/// the controller container and only then delivery by haulers. The delivery is only requested when
+/// there is no controller link, no energy is expected to be sent to it or it has been empty for
+/// `MAX_LINK_WAIT_TICKS`.
+/// During energy famine, i.e., when neither the link nor the container have any energy, the
+/// upgrader also spends whatever it carries, even if not enough to use all its Work parts, but
+/// only if the controller's downgrade timer is below half.
+pub fn plan_upgrader_energy(
+    link: Option<ControllerLinkState>,
+    container_energy: u32,
+    carried_energy: u32,
+    free_capacity: u32,
+    upgrade_energy_consumption: u32,
+    ticks_to_downgrade: u32,
+    max_ticks_to_downgrade: u32,
+) -> UpgraderEnergyPlan {
+    let link_energy = link.map_or(0, |link| link.energy);
+    let refill = if free_capacity == 0 {
+        None
+    } else if link_energy > 0 {
+        Some(LinkSource)
+    } else if container_energy > 0 {
+        Some(ContainerSource)
+    } else if link.is_some_and(|link| link.refill_expected && link.empty_ticks < MAX_LINK_WAIT_TICKS) {
+        None
+    } else {
+        Some(Delivery)
+    };
+
+    let energy_famine = link_energy == 0 && container_energy == 0;
+    let trickle_upgrade = energy_famine && carried_energy > 0 && ticks_to_downgrade < max_ticks_to_downgrade / 2;
+    let upgrade = carried_energy >= upgrade_energy_consumption && carried_energy > 0 || trickle_upgrade;
+
+    UpgraderEnergyPlan {
+        refill,
+        upgrade,
+    }
+}
+
+/// Energy an upgrader spends on upgrading in the current tick, given the energy other upgraders
+/// already spent in it and the limit, if any. Upgrading always uses all Work parts, so the upgrader
+/// either spends all of `upgrade_energy` if it fits within the limit or does not upgrade at all.
+pub fn throttled_upgrade_energy(upgrade_energy: u32, used_upgrade_energy: u32, max_upgrade_energy: Option<u32>) -> u32 {
+    match max_upgrade_energy {
+        Some(max_upgrade_energy) if used_upgrade_energy + upgrade_energy > max_upgrade_energy => 0,
+        _ => upgrade_energy,
+    }
+}
+
+type ControllerEnergyStructures = (
+    Option<(RoomXY, ObjectId<StructureLink>)>,
+    Option<(RoomXY, ObjectId<StructureContainer>)>
+);
+
+/// Links that send energy to the controller link, i.e., the core link or, if there is none, the
+/// source links.
+fn controller_link_feeders(room_state: &RoomState) -> Vec<ObjectId<StructureLink>> {
+    match core_link(room_state) {
+        Some((_, id)) => vec![id],
+        None => room_state
+            .sources
+            .iter()
+            .filter_map(|source_data| source_data.link_id)
+            .collect(),
+    }
+}
+
+/// Controller link and container if they are built.
+fn controller_energy_structures(room_state: &RoomState, work_xy: RoomXY) -> ControllerEnergyStructures {
+    let link = room_state.plan.as_ref().and_then(|plan| {
+        room_state
+            .structures_with_type::<StructureLink>(Link)
+            .find(|&(xy, _)| xy == plan.controller.link_xy)
+    });
+    let container = room_state
+        .structures_with_type::<StructureContainer>(Container)
+        .filter(|&(xy, _)| xy.dist(work_xy) <= 1)
+        .min_by_key(|&(xy, _)| xy.dist(work_xy));
+    (link, container)
+}
+
+/// Tiles where upgraders park, i.e., the controller work position and the passable tiles around it
+/// in range of the controller. The work position goes first and planned roads last, so that
+/// the upgraders block the traffic the least.
+fn upgrader_parking_xys(room_state: &RoomState, controller_xy: RoomXY, work_xy: RoomXY) -> Vec<RoomXY> {
+    let Some(plan) = room_state.plan.as_ref() else {
+        return vec![work_xy];
+    };
+    let mut parking_xys = work_xy
+        .around()
+        .filter(|&xy| {
+            let tile = plan.tiles.get(xy);
+            xy.dist(controller_xy) <= CREEP_RANGED_ACTION_RANGE
+                && room_state.terrain.get(xy) != Wall
+                && tile.is_passable(true)
+                && !tile.reserved()
+        })
+        .collect::<Vec<_>>();
+    parking_xys.sort_by_key(|&xy| plan.tiles.get(xy).structures().road());
+    parking_xys.insert(0, work_xy);
+    parking_xys
+}
+
+/// The right of an upgrader to park on a tile, so that upgraders do not compete for the same one.
+/// Released on drop, e.g., when the upgrader dies.
+struct ParkingClaim(Rc<RefCell<FxHashSet<RoomXY>>>, RoomXY);
+
+impl ParkingClaim {
+    fn try_new(parked_xys: &Rc<RefCell<FxHashSet<RoomXY>>>, parking_xys: &[RoomXY]) -> Option<Self> {
+        let mut borrowed_parked_xys = parked_xys.borrow_mut();
+        let xy = parking_xys.iter().copied().find(|xy| !borrowed_parked_xys.contains(xy))?;
+        borrowed_parked_xys.insert(xy);
+        Some(ParkingClaim(parked_xys.clone(), xy))
+    }
+}
+
+impl Drop for ParkingClaim {
+    fn drop(&mut self) {
+        self.0.borrow_mut().remove(&self.1);
+    }
+}
+
+fn needs_sign(controller: &StructureController, owner: &str) -> bool {
+    controller
+        .sign()
+        .is_none_or(|sign| sign.username() != owner || sign.text() != CONTROLLER_SIGN)
+}
+
+/// The right of an upgrader to sign the controller, so that only one does it at a time.
+/// Released on drop, e.g., when the upgrader dies.
+struct SignClaim(Rc<Cell<bool>>);
+
+impl SignClaim {
+    fn try_new(signing: &Rc<Cell<bool>>) -> Option<Self> {
+        (!signing.replace(true)).then(|| SignClaim(signing.clone()))
+    }
+}
+
+impl Drop for SignClaim {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
 pub async fn upgrade_controller(room_name: RoomName) {
-    let (base_spawn_request, controller_id, work_pos, controller_pos) = u!(with_room_state(room_name, |room_state| {
+    let (base_spawn_request, controller_id, work_xy, controller_pos, parking_xys) = u!(with_room_state(room_name, |room_state| {
         let controller_data = u!(room_state.controller);
         let work_xy = u!(controller_data.work_xy);
 
@@ -36,17 +222,16 @@ pub async fn upgrade_controller(room_name: RoomName) {
             priority: UPGRADER_SPAWN_PRIORITY,
             preferred_spawns,
             tick: (0, 0),
+            home_room: None,
         };
 
-        (base_spawn_request, controller_data.id, work_xy.to_pos(room_name), controller_data.xy.to_pos(room_name))
+        let parking_xys = upgrader_parking_xys(room_state, controller_data.xy, work_xy);
+
+        (base_spawn_request, controller_data.id, work_xy, controller_data.xy.to_pos(room_name), parking_xys)
     }));
 
-    // Travel spec for the upgrader. Will not change unless structures change.
-    // TODO When link is present - around the link.
-    //      Otherwise - around or on the container unless it is too far.
-    //      It is okay to be next to container on low RCL.
-    //      When under siege, don't be on unprotected places.
-    //let travel_spec = TravelSpec::new(work_pos, 1);
+    // Travel spec for the upgrader before it parks or if there are no free parking tiles.
+    // TODO When under siege, don't be on unprotected places.
     let travel_spec = TravelSpec::new(controller_pos, CREEP_RANGED_ACTION_RANGE);
 
     // TODO Handle prioritizing energy for the upgrading - always upgrade enough to prevent
@@ -56,6 +241,13 @@ pub async fn upgrade_controller(room_name: RoomName) {
         .include_all_unassigned(true);
     let mut spawn_pool = SpawnPool::new(room_name, base_spawn_request, spawn_pool_options);
 
+    // Whether one of the upgraders is already signing the controller.
+    let signing = Rc::new(Cell::new(false));
+    // Parking tiles taken by the upgraders.
+    let parked_xys = Rc::new(RefCell::new(FxHashSet::default()));
+    // The tick and the energy all upgraders spent on upgrading in it.
+    let upgrade_energy_spent = Rc::new(Cell::new((0, 0)));
+
     loop {
         let (upgraders_required, upgrader_body) = wait_until_some(|| with_room_state(room_name, |room_state| {
             room_state
@@ -67,14 +259,28 @@ pub async fn upgrade_controller(room_name: RoomName) {
         }).flatten()).await;
         spawn_pool.target_number_of_creeps = upgraders_required;
         spawn_pool.base_spawn_request.body = upgrader_body;
-        
+
         spawn_pool.with_spawned_creeps(|creep_ref| {
-            let travel_spec = travel_spec.clone();
+            let signing = signing.clone();
+            let parked_xys = parked_xys.clone();
+            let parking_xys = parking_xys.clone();
+            let upgrade_energy_spent = upgrade_energy_spent.clone();
+            let parking_claim = ParkingClaim::try_new(&parked_xys, &parking_xys);
+            let travel_spec = match parking_claim.as_ref() {
+                Some(ParkingClaim(_, xy)) => TravelSpec::new(xy.to_pos(room_name), 0),
+                None => travel_spec.clone(),
+            };
             async move {
+                let _parking_claim = parking_claim;
                 let capacity = u!(creep_ref.borrow_mut().carry_capacity());
                 let creep_id = u!(creep_ref.borrow_mut().screeps_id());
                 let upgrade_energy_consumption = creep_ref.borrow_mut().upgrade_energy_consumption();
 
+                // Boosting is optional, so the upgrader works unboosted if it fails.
+                if let Err(err) = boost_creep(&creep_ref, &[UPGRADER_BOOST_COMPOUND]).await {
+                    warn!("Upgrader could not be boosted: {err}.");
+                }
+
                 // TODO A way to await travel and ignore errors forever since there isn't anything
                 //      that can be done outside of suicide. Similarly with other creeps.
                 if let Err(err) = travel(&creep_ref, travel_spec.clone()).await {
@@ -85,52 +291,270 @@ pub async fn upgrade_controller(room_name: RoomName) {
                 }
 
                 let mut store_request = None;
+                // The source of the energy the upgrader is currently using.
+                let mut energy_source = Delivery;
+                // The number of ticks the controller link has been empty for.
+                let mut link_empty_ticks = 0;
 
                 loop {
                     // This can only fail if the creep died, but then this process would be killed.
                     let current_energy = u!(creep_ref.borrow_mut().used_capacity(Some(ResourceType::Energy), AfterAllTransfers));
-                    if current_energy < capacity {
-                        with_room_state(room_name, |room_state| {
-                            if let Some(eco_stats) = room_state.eco_stats.as_mut() {
-                                eco_stats.register_idle_creep(Upgrader, &creep_ref);
-                            }
-                        });
-
-                        // TODO Use a container.
-                        // TODO Use link.
-                        let mut new_store_request = HaulRequest::new(
-                            DepositRequest,
-                            room_name,
-                            ResourceType::Energy,
-                            creep_id,
-                            CreepTarget,
-                            false,
-                            creep_ref.borrow().travel_state.pos
-                        );
-                        new_store_request.amount = capacity;
-                        new_store_request.priority = Priority(40);
-                        new_store_request.change = upgrade_energy_consumption as i32;
-                        new_store_request.max_amount = capacity;
-
-                        store_request = Some(schedule_haul(new_store_request, store_request.take()));
-                    } else {
-                        store_request = None;
-                    }
+                    let free_capacity = u!(creep_ref.borrow_mut().free_capacity(AfterAllTransfers));
+
+                    let (link, container, link_feeders, ticks_to_downgrade, max_ticks_to_downgrade, max_upgrade_energy, owner) = u!(with_room_state(room_name, |room_state| {
+                        let (link, container) = controller_energy_structures(room_state, work_xy);
+                        let controller_data = u!(room_state.controller);
+                        (
+                            link,
+                            container,
+                            controller_link_feeders(room_state),
+                            controller_data.downgrade_tick.saturating_sub(game_tick()),
+                            controller_downgrade(room_state.rcl).unwrap_or(0),
+                            max_upgrade_energy_per_tick(room_state.rcl),
+                            room_state.owner.clone()
+                        )
+                    }));
+                    let link_energy = link.map_or(0, |(_, id)| {
+                        get_used_capacity_unchecked(id.into(), Some(ResourceType::Energy), AfterAllTransfers).unwrap_or(0)
+                    });
+                    let container_energy = container.map_or(0, |(_, id)| {
+                        get_used_capacity_unchecked(id.into(), Some(ResourceType::Energy), AfterAllTransfers).unwrap_or(0)
+                    });
+
+                    link_empty_ticks = if link_energy == 0 { link_empty_ticks + 1 } else { 0 };
+                    let refill_expected = link_feeders.iter().any(|&id| {
+                        get_used_capacity_unchecked(id.into(), Some(ResourceType::Energy), AfterAllTransfers).unwrap_or(0) > 0
+                    });
+
+                    let plan = plan_upgrader_energy(
+                        link.map(|_| ControllerLinkState {
+                            energy: link_energy,
+                            empty_ticks: link_empty_ticks,
+                            refill_expected,
+                        }),
+                        container_energy,
+                        current_energy,
+                        free_capacity,
+                        upgrade_energy_consumption,
+                        ticks_to_downgrade,
+                        max_ticks_to_downgrade
+                    );
+
+                    // Respecting the limit of energy spent on upgrading per tick shared by all
+                    // upgraders.
+                    let (spent_tick, spent_energy) = upgrade_energy_spent.get();
+                    let spent_energy = if spent_tick == game_tick() { spent_energy } else { 0 };
+                    let upgrade_energy = throttled_upgrade_energy(
+                        min(current_energy, upgrade_energy_consumption),
+                        spent_energy,
+                        max_upgrade_energy
+                    );
 
                     // TODO Does this current_energy work or does it need to be one before transfers?
-                    if current_energy >= upgrade_energy_consumption {
+                    if plan.upgrade && upgrade_energy > 0 {
                         let controller = u!(get_object_by_id_typed(&controller_id));
-                        creep_ref
+                        let upgrade_result = creep_ref
                             .borrow_mut()
-                            .upgrade_controller(&controller)
-                            .warn_if_err("Failed to upgrade the controller");
+                            .upgrade_controller(&controller);
+                        if upgrade_result.is_ok() {
+                            upgrade_energy_spent.set((game_tick(), spent_energy + upgrade_energy));
+                            with_room_state(room_name, |room_state| {
+                                if let Some(eco_stats) = room_state.eco_stats.as_mut() {
+                                    eco_stats.register_upgrade_energy(energy_source, upgrade_energy);
+                                }
+                            });
+                        }
+                        upgrade_result.warn_if_err("Failed to upgrade the controller");
+
+                        if needs_sign(&controller, &owner) {
+                            if let Some(_sign_claim) = SignClaim::try_new(&signing) {
+                                sign(&creep_ref, &controller).await;
+                                store_request = None;
+                                continue;
+                            }
+                        }
+                    }
+
+                    match plan.refill {
+                        Some(Delivery) => {
+                            with_room_state(room_name, |room_state| {
+                                if let Some(eco_stats) = room_state.eco_stats.as_mut() {
+                                    eco_stats.register_idle_creep(Upgrader, &creep_ref);
+                                }
+                            });
+
+                            let mut new_store_request = HaulRequest::new(
+                                DepositRequest,
+                                room_name,
+                                ResourceType::Energy,
+                                creep_id,
+                                CreepTarget,
+                                false,
+                                creep_ref.borrow().travel_state.pos
+                            );
+                            new_store_request.amount = capacity;
+                            new_store_request.priority = Priority(40);
+                            new_store_request.change = upgrade_energy_consumption as i32;
+                            new_store_request.max_amount = capacity;
+
+                            store_request = Some(schedule_haul(new_store_request, store_request.take()));
+                            energy_source = Delivery;
+                        }
+                        Some(source) => {
+                            store_request = None;
+                            let (xy, id, available_energy) = match source {
+                                LinkSource => {
+                                    let (xy, id) = u!(link);
+                                    (xy, id.into(), link_energy)
+                                }
+                                _ => {
+                                    let (xy, id) = u!(container);
+                                    (xy, id.into(), container_energy)
+                                }
+                            };
+                            if refill(&creep_ref, xy.to_pos(room_name), id, min(free_capacity, available_energy)).await {
+                                energy_source = source;
+                            }
+                            // Going back to the controller if the energy source is out of range.
+                            if let Err(err) = travel(&creep_ref, travel_spec.clone()).await {
+                                warn!("Upgrader could not return to the controller: {err}.");
+                            }
+                        }
+                        None => {
+                            store_request = None;
+                        }
                     }
 
                     sleep(1).await;
                 }
             }
         });
-        
+
         sleep(1).await;
     }
-}
\ No newline at end of file
+}
+
+/// Withdraws energy from the controller link or container. Returns whether successful.
+async fn refill(creep_ref: &CreepRef, pos: Position, id: RawObjectId, amount: u32) -> bool {
+    if let Err(err) = travel(creep_ref, TravelSpec::new(pos, 1)).await {
+        warn!("Upgrader could not reach the energy source: {err}.");
+        return false;
+    }
+
+    let result = withdraw_when_able(creep_ref, id, ResourceType::Energy, amount, true).await;
+    result.warn_if_err("Upgrader failed to withdraw energy");
+    result.is_ok()
+}
+
+/// Signs the controller with `CONTROLLER_SIGN` and goes back.
+async fn sign(creep_ref: &CreepRef, controller: &StructureController) {
+    let controller_pos = controller.pos();
+    if let Err(err) = travel(creep_ref, TravelSpec::new(controller_pos, 1)).await {
+        warn!("Upgrader could not reach the controller to sign it: {err}.");
+        return;
+    }
+
+    debug!("Signing the controller in {}.", controller_pos.room_name());
+    // The controller object is from an earlier tick, so it needs to be fetched again.
+    if let Some(controller) = get_object_by_id_typed(&controller.id()) {
+        creep_ref
+            .borrow_mut()
+            .sign_controller(&controller, CONTROLLER_SIGN)
+            .warn_if_err("Failed to sign the controller");
+    }
+    sleep(1).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::economy::room_eco_stats::UpgradeEnergySource::{Container, Delivery, Link};
+    use crate::room_maintenance::upgrade_controller::{plan_upgrader_energy, throttled_upgrade_energy, ControllerLinkState, UpgraderEnergyPlan, MAX_LINK_WAIT_TICKS};
+
+    const MAX_TICKS_TO_DOWNGRADE: u32 = 20_000;
+
+    fn link(energy: u32, empty_ticks: u32, refill_expected: bool) -> Option<ControllerLinkState> {
+        Some(ControllerLinkState {
+            energy,
+            empty_ticks,
+            refill_expected,
+        })
+    }
+
+    #[test]
+    fn test_link_preferred_over_container() {
+        let plan = plan_upgrader_energy(link(400, 0, false), 1000, 0, 100, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Link), upgrade: false });
+    }
+
+    #[test]
+    fn test_container_preferred_over_delivery() {
+        let plan = plan_upgrader_energy(None, 1000, 50, 50, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Container), upgrade: true });
+    }
+
+    #[test]
+    fn test_delivery_without_link_or_container() {
+        let plan = plan_upgrader_energy(None, 0, 5, 95, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Delivery), upgrade: true });
+    }
+
+    #[test]
+    fn test_no_refill_when_full() {
+        let plan = plan_upgrader_energy(link(400, 0, false), 1000, 100, 0, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: None, upgrade: true });
+    }
+
+    #[test]
+    fn test_trickle_upgrade_during_famine() {
+        // Not enough energy for a full upgrade, but the downgrade timer is below half.
+        let plan = plan_upgrader_energy(None, 0, 3, 97, 5, 9_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Delivery), upgrade: true });
+
+        // The downgrade timer is above half.
+        let plan = plan_upgrader_energy(None, 0, 3, 97, 5, 11_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Delivery), upgrade: false });
+
+        // Nothing to spend.
+        let plan = plan_upgrader_energy(None, 0, 0, 100, 5, 9_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Delivery), upgrade: false });
+    }
+
+    #[test]
+    fn test_no_trickle_upgrade_when_energy_available() {
+        // The upgrader should rather refill from the container than trickle.
+        let plan = plan_upgrader_energy(None, 500, 3, 97, 5, 9_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Container), upgrade: false });
+    }
+
+    #[test]
+    fn test_waiting_for_empty_link_before_delivery() {
+        // The link has just been emptied and the links feeding it have energy to send.
+        let plan = plan_upgrader_energy(link(0, 1, true), 0, 5, 95, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: None, upgrade: true });
+
+        // No energy is going to arrive.
+        let plan = plan_upgrader_energy(link(0, 1, false), 0, 5, 95, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Delivery), upgrade: true });
+
+        // The link has been empty for too long.
+        let plan = plan_upgrader_energy(link(0, MAX_LINK_WAIT_TICKS, true), 0, 5, 95, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Delivery), upgrade: true });
+
+        // The container is used while waiting for the link.
+        let plan = plan_upgrader_energy(link(0, 1, true), 500, 5, 95, 5, 15_000, MAX_TICKS_TO_DOWNGRADE);
+        assert_eq!(plan, UpgraderEnergyPlan { refill: Some(Container), upgrade: true });
+    }
+
+    #[test]
+    fn test_upgrade_throttling_at_rcl8() {
+        // No limit below RCL 8.
+        assert_eq!(throttled_upgrade_energy(20, 100, None), 20);
+        // Upgraders with 6 Work parts each, in order.
+        assert_eq!(throttled_upgrade_energy(6, 0, Some(15)), 6);
+        assert_eq!(throttled_upgrade_energy(6, 6, Some(15)), 6);
+        // The third one would exceed the limit with all of its Work parts, so it does not upgrade.
+        assert_eq!(throttled_upgrade_energy(6, 12, Some(15)), 0);
+        assert_eq!(throttled_upgrade_energy(3, 12, Some(15)), 3);
+        assert_eq!(throttled_upgrade_energy(6, 15, Some(15)), 0);
+    }
+}
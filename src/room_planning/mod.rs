@@ -4,4 +4,8 @@ pub mod plan_rooms;
 pub mod planned_tile;
 pub mod stamps;
 pub mod room_planner;
-mod blueprint;
\ No newline at end of file
+pub mod remote_planner;
+pub mod tower_damage_field;
+mod blueprint;
+#[cfg(all(test, feature = "offline"))]
+mod offline_planning;
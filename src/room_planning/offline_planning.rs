@@ -0,0 +1,284 @@
+//! Planning of fixture rooms outside of the game, serving as a regression benchmark of the planner.
+//! Run with `cargo test --features offline -- --nocapture` to see the scores and timings.
+//! The current fixtures are generated cave-like rooms standing in for real ones and are yet to be replaced with real rooms
+//! dumped with the `room_fixture` console command. A dump can be pasted into a fixture file as it is, which is checked by
+//! `test_fixture_round_trip`.
+
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::room_planning::plan::Plan;
+use crate::room_planning::room_planner::{PlannerConfig, PlannerInput, PlannerProgress, RoomPlanner, RoomPlannerError};
+use crate::room_states::packed_terrain::PackedTerrain;
+use crate::room_states::room_state::StructuresMap;
+use crate::profiler::NoOpProfiler;
+use crate::u;
+use log::{LevelFilter, Log, Metadata, Record};
+use screeps::{RoomName, RoomXY, StructureType};
+use std::rc::Rc;
+use std::cmp::max;
+use std::time::{Duration, Instant};
+
+const FIXTURES: [(&str, &str); 3] = [
+    ("W1N1", include_str!("fixtures/W1N1.txt")),
+    ("W2N5", include_str!("fixtures/W2N5.txt")),
+    ("W7N3", include_str!("fixtures/W7N3.txt")),
+];
+
+const SEED: u64 = 1337;
+
+/// Logger printing the records of at least given level to the standard output, used by the planner in place of the game
+/// logger.
+struct PrintLogger(LevelFilter);
+
+impl Log for PrintLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.0
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            println!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static PRINT_LOGGER: PrintLogger = PrintLogger(LevelFilter::Info);
+
+fn parse_xy(s: &str) -> RoomXY {
+    let mut coords = s.split_whitespace().map(|c| u!(c.parse::<u8>()));
+    u!((u!(coords.next()), u!(coords.next())).try_into())
+}
+
+/// Parses a fixture consisting of `key: value` lines with the controller, sources, mineral and base64 terrain.
+fn parse_fixture(room_name: &str, contents: &str) -> PlannerInput {
+    let mut terrain = None;
+    let mut controller_xy = None;
+    let mut source_xys = Vec::new();
+    let mut mineral_xy = None;
+
+    for line in contents.lines() {
+        let (key, value) = u!(line.split_once(':'));
+        match key.trim() {
+            "terrain" => terrain = Some(u!(PackedTerrain::from_base64(value))),
+            "controller" => controller_xy = Some(parse_xy(value)),
+            "sources" => source_xys = value.split(',').map(parse_xy).collect(),
+            "mineral" => mineral_xy = Some(parse_xy(value)),
+            _ => panic!("Unknown fixture key {}.", key),
+        }
+    }
+
+    PlannerInput {
+        room_name: u!(RoomName::new(room_name)),
+        terrain: u!(terrain),
+        controller_xy: u!(controller_xy),
+        source_xys,
+        mineral_xy,
+        existing_structures: StructuresMap::default(),
+    }
+}
+
+/// Plans the room the same way as `plan_rooms` does in the game.
+fn plan_fixture(input: PlannerInput, seed: u64) -> Option<Plan> {
+    let config = PlannerConfig {
+        fast_mode: true,
+        ..PlannerConfig::default()
+    };
+    plan_fixture_with_config(input, config, seed, &PRINT_LOGGER)
+}
+
+/// Plans the room with given configuration, passing the planner's logs to the logger.
+fn plan_fixture_with_config(input: PlannerInput, config: PlannerConfig, seed: u64, logger: &'static dyn Log) -> Option<Plan> {
+    // The logger filters the records by itself.
+    log::set_max_level(LevelFilter::Trace);
+    let mut planner = u!(RoomPlanner::from_input(
+        input,
+        config,
+        seed,
+        Rc::new(NoOpProfiler),
+        logger
+    ));
+    while !(planner.plans_count >= 1 && planner.tries_count >= 20 || planner.is_finished()) {
+        planner.plan().ok();
+    }
+    planner.best_plan
+}
+
+#[test]
+fn test_plan_fixture_rooms_deterministically() {
+    for (room_name, contents) in FIXTURES {
+        let input = parse_fixture(room_name, contents);
+
+        let start = Instant::now();
+        let plan = plan_fixture(input.clone(), SEED);
+        let duration = start.elapsed();
+
+        let plan = plan.unwrap_or_else(|| panic!("Failed to plan room {}.", room_name));
+        println!("{}: {:?} in {:.2}s.", room_name, plan.score, duration.as_secs_f64());
+
+        let other_plan = u!(plan_fixture(input, SEED));
+        assert_eq!(plan.score, other_plan.score);
+        assert!(plan.tiles.iter().eq(other_plan.tiles.iter()));
+    }
+}
+
+#[test]
+fn test_fixture_round_trip() {
+    for (room_name, contents) in FIXTURES {
+        let input = parse_fixture(room_name, contents);
+        assert_eq!(input.to_fixture(), contents.trim_end());
+    }
+}
+
+/// Finds the center of the core, which is the container diagonally two tiles away from the storage.
+fn plan_core_center(plan: &Plan) -> RoomXY {
+    let structure_xy = |structure_type: StructureType| {
+        plan.tiles
+            .iter()
+            .filter_map(move |(xy, tile)| (StructureType::try_from(tile.structures().main()).ok() == Some(structure_type)).then_some(xy))
+    };
+    let storage_xy = u!(structure_xy(StructureType::Storage).next());
+    u!(structure_xy(StructureType::Container).find(|xy| {
+        xy.x.u8().abs_diff(storage_xy.x.u8()) == 2 && xy.y.u8().abs_diff(storage_xy.y.u8()) == 2
+    }))
+}
+
+#[test]
+fn test_plan_fixture_room_with_forced_core_center() {
+    let (room_name, contents) = FIXTURES[0];
+    let input = parse_fixture(room_name, contents);
+    let core_center = plan_core_center(&u!(plan_fixture(input.clone(), SEED)));
+
+    let config = PlannerConfig {
+        fast_mode: true,
+        forced_core_center: Some(core_center),
+    };
+    let plan = u!(plan_fixture_with_config(input, config, SEED, &PRINT_LOGGER));
+    assert_eq!(plan_core_center(&plan), core_center);
+}
+
+#[test]
+fn test_forced_core_center_at_room_edge_fails() {
+    let (room_name, contents) = FIXTURES[0];
+    let input = parse_fixture(room_name, contents);
+
+    let config = PlannerConfig {
+        fast_mode: true,
+        forced_core_center: Some(u!((1, 25).try_into())),
+    };
+    let result = RoomPlanner::from_input(
+        input,
+        config,
+        SEED,
+        Rc::new(NoOpProfiler),
+        &PRINT_LOGGER
+    );
+    let Err(err) = result else {
+        panic!("Planning with the core at the room edge should fail.");
+    };
+    assert!(matches!(
+        err.downcast_ref::<RoomPlannerError>(),
+        Some(RoomPlannerError::StructurePlacementFailure)
+    ));
+}
+
+#[test]
+fn test_plan_fixture_rooms_in_interleaved_steps() {
+    let config = PlannerConfig {
+        fast_mode: true,
+        ..PlannerConfig::default()
+    };
+    let new_planner = |(room_name, contents): (&str, &str)| {
+        u!(RoomPlanner::from_input(
+            parse_fixture(room_name, contents),
+            config.clone(),
+            SEED,
+            Rc::new(NoOpProfiler),
+            &PRINT_LOGGER
+        ))
+    };
+
+    // Planning two rooms at once, one step at a time, as if spread over multiple ticks.
+    let mut planners = FIXTURES[..2].iter().copied().map(new_planner).collect::<Vec<_>>();
+    let mut steps = 0;
+    while planners.iter().any(|planner| {
+        planner.is_attempt_in_progress() || !(planner.plans_count >= 1 && planner.tries_count >= 20 || planner.is_finished())
+    }) {
+        for planner in planners.iter_mut() {
+            if planner.is_attempt_in_progress() || !(planner.plans_count >= 1 && planner.tries_count >= 20 || planner.is_finished()) {
+                if let Ok(PlannerProgress::Finished(plan)) = planner.plan_step() {
+                    assert_eq!(planner.best_plan.as_ref().map(|best_plan| best_plan.score.total_score >= plan.score.total_score), Some(true));
+                }
+            }
+        }
+        steps += 1;
+    }
+    assert!(steps > planners.iter().map(|planner| planner.tries_count as usize).max().unwrap_or(0));
+
+    for ((room_name, contents), planner) in FIXTURES[..2].iter().copied().zip(planners) {
+        let stepped_plan = u!(planner.best_plan);
+        let plan = u!(plan_fixture(parse_fixture(room_name, contents), SEED));
+        assert_eq!(stepped_plan.score, plan.score);
+        assert!(stepped_plan.tiles.iter().eq(plan.tiles.iter()));
+    }
+}
+
+/// The towers are placed by a genetic algorithm, so their positions depend on the random numbers. With a fixed seed, the
+/// solution must stay the same so that a plan can be replayed from the seed in a bug report.
+#[test]
+fn test_tower_placement_is_pinned_for_seed() {
+    let (room_name, contents) = FIXTURES[0];
+    let plan = u!(plan_fixture(parse_fixture(room_name, contents), SEED));
+    let tower_xys = plan
+        .tiles
+        .iter()
+        .filter_map(|(xy, tile)| (StructureType::try_from(tile.structures().main()).ok() == Some(StructureType::Tower)).then_some(xy))
+        .collect::<Vec<_>>();
+    let expected_tower_xys = [(26, 15), (27, 15), (28, 15), (34, 35), (35, 35), (36, 35)]
+        .into_iter()
+        .map(|xy| u!(xy.try_into()))
+        .collect::<Vec<RoomXY>>();
+    assert_eq!(tower_xys, expected_tower_xys);
+}
+
+/// Each step of planning should take only a small part of a whole plan attempt, so that planning can be spread over
+/// ticks with a small CPU budget per tick.
+#[test]
+fn test_plan_step_cost_on_largest_fixture() {
+    let (room_name, contents) = u!(FIXTURES
+        .into_iter()
+        .max_by_key(|&(room_name, contents)| parse_fixture(room_name, contents).terrain.not_walls().count()));
+    let mut planner = u!(RoomPlanner::from_input(
+        parse_fixture(room_name, contents),
+        PlannerConfig {
+            fast_mode: true,
+            ..PlannerConfig::default()
+        },
+        SEED,
+        Rc::new(NoOpProfiler),
+        &PRINT_LOGGER
+    ));
+
+    let mut max_step_duration = Duration::ZERO;
+    let mut max_attempt_duration = Duration::ZERO;
+    let mut attempt_duration = Duration::ZERO;
+    while planner.is_attempt_in_progress() || !(planner.plans_count >= 1 && planner.tries_count >= 20 || planner.is_finished()) {
+        let start = Instant::now();
+        planner.plan_step().ok();
+        let step_duration = start.elapsed();
+        max_step_duration = max(max_step_duration, step_duration);
+        attempt_duration += step_duration;
+        if !planner.is_attempt_in_progress() {
+            max_attempt_duration = max(max_attempt_duration, attempt_duration);
+            attempt_duration = Duration::ZERO;
+        }
+    }
+
+    println!(
+        "{}: the most expensive step took {:.2}ms and the most expensive attempt {:.2}ms.",
+        room_name,
+        max_step_duration.as_secs_f64() * 1000.0,
+        max_attempt_duration.as_secs_f64() * 1000.0
+    );
+    assert!(max_step_duration * 3 <= max_attempt_duration);
+}
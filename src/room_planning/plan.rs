@@ -1,18 +1,41 @@
+use crate::algorithms::matrix_common::MatrixCommon;
 use crate::algorithms::room_matrix::RoomMatrix;
 use crate::room_planning::planned_tile::PlannedTile;
 use derive_more::Constructor;
-use screeps::RoomXY;
+use screeps::{Direction, RoomXY, StructureType};
 use std::cmp::Ordering;
 use std::fmt::Debug;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize, Clone, Constructor)]
 pub struct Plan {
+    #[serde(with = "crate::algorithms::packed_room_matrix")]
     pub tiles: RoomMatrix<PlannedTile>,
     pub controller: PlannedControllerData,
     pub sources: Vec<PlannedSourceData>,
-    pub mineral: PlannedMineralData,
+    pub mineral: Option<PlannedMineralData>,
     pub score: PlanScore,
+    /// Structures that were present in the room when planning and conflict with the plan.
+    pub demolition_list: Vec<(StructureType, RoomXY)>,
+    /// Ramparts separating the inside of the base from the outside.
+    pub main_ramparts: Vec<RoomXY>,
+}
+
+impl Plan {
+    /// Approximate size of the plan in bytes when persisted in the binary format, computed without
+    /// serializing it.
+    pub fn approximate_serialized_size(&self) -> usize {
+        let tile_runs = 1 + self.tiles.data.windows(2).filter(|tiles| tiles[0] != tiles[1]).count();
+        let xys_count = 2
+            + 3 * self.sources.len()
+            + self.mineral.map_or(0, |_| 1)
+            + self.demolition_list.len()
+            + self.main_ramparts.len();
+        // Runs of tiles take one or two bytes for the length and two for the tile, positions take
+        // two bytes and structure types one. The rest are lengths of sequences, the marker of
+        // the mineral and four floats of the score.
+        3 * tile_runs + 2 * xys_count + self.demolition_list.len() + 6 + 1 + 16
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
@@ -55,3 +78,54 @@ impl Ord for PlanScore {
         self.partial_cmp(other).unwrap_or(Ordering::Equal)
     }
 }
+
+/// Directions from the spawn at given position to the planned roads around it that are not reserved,
+/// e.g., for creeps filling the extensions. Creeps spawned in these directions do not block
+/// the tiles around the spawn.
+pub fn open_directions(spawn_xy: RoomXY, planned_tiles: &RoomMatrix<PlannedTile>) -> Vec<Direction> {
+    [
+        Direction::Top,
+        Direction::TopRight,
+        Direction::Right,
+        Direction::BottomRight,
+        Direction::Bottom,
+        Direction::BottomLeft,
+        Direction::Left,
+        Direction::TopLeft,
+    ]
+    .into_iter()
+    .filter(|&direction| {
+        spawn_xy.checked_add_direction(direction).is_some_and(|xy| {
+            let tile = planned_tiles.get(xy);
+            tile.structures().road() && !tile.reserved()
+        })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Direction::{Bottom, BottomLeft, BottomRight, Left, Right, TopLeft, TopRight};
+    use screeps::RoomXY;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::room_planning::plan::open_directions;
+    use crate::room_planning::planned_tile::PlannedTile;
+    use crate::room_planning::stamps::core_stamp;
+
+    #[test]
+    fn test_open_directions_of_spawns_in_core() {
+        let xy = |x: u8, y: u8| -> RoomXY { (x, y).try_into().unwrap() };
+
+        let mut core = core_stamp();
+        core.translate((20, 20)).unwrap();
+        let mut planned_tiles = RoomMatrix::new(PlannedTile::default());
+        planned_tiles.merge_structures(&core).unwrap();
+
+        // The side of the spawn towards the storage has the factory and the reserved tile of
+        // the fast filler, so only the outer road remains.
+        assert_eq!(open_directions(xy(21, 23), &planned_tiles), vec![BottomLeft, Left, TopLeft]);
+        assert_eq!(open_directions(xy(25, 23), &planned_tiles), vec![TopRight, Right, BottomRight]);
+        // The spawn at the bottom has extensions on its sides and the fast filler above.
+        assert_eq!(open_directions(xy(23, 25), &planned_tiles), vec![BottomRight, Bottom, BottomLeft]);
+    }
+}
@@ -1,15 +1,23 @@
 use crate::algorithms::matrix_common::MatrixCommon;
+use crate::config::ROOM_PLANNING_TICK_CPU;
+use crate::cpu_management::cpu_level;
+use crate::cpu_management::CpuFeature::RoomPlanning;
 use crate::utils::game_tick::first_tick;
 use crate::kernel::kernel::should_finish;
 use crate::kernel::sleep::{sleep, sleep_until};
-use crate::room_states::room_states::for_each_owned_room;
+use crate::room_states::room_states::{for_each_owned_room, with_room_states};
 use crate::utils::multi_map_utils::MultiMapUtils;
 use crate::{a, log_err, u};
 use log::{debug, error, trace};
-use screeps::{game, StructureType};
-use screeps::StructureType::{Container, Rampart, Road};
+use screeps::{game, RoomXY, StructureType};
+use screeps::StructureType::{Container, Rampart, Road, Storage};
+use rustc_hash::FxHashMap;
+use crate::algorithms::distance_matrix::distance_matrix;
+use crate::room_planning::plan::Plan;
+use crate::room_states::packed_terrain::PackedTerrain;
 use crate::room_planning::room_planner::{RoomPlanner, MIN_RAMPART_RCL};
-use crate::room_states::room_state::{RoomState, StructuresMap};
+use crate::room_planning::remote_planner::plan_remote;
+use crate::room_states::room_state::{RoomDesignation, RoomState, StructuresMap};
 
 pub const MIN_CONTAINER_RCL: u8 = 3;
 
@@ -23,19 +31,52 @@ pub async fn plan_rooms() {
     // TODO Should run as long as it needs during the planning of the first room.
 
     sleep_until(first_tick() + 5).await;
+
+    // The most CPU used by a single planning step so far, reserved for the next step in the same tick.
+    let mut max_step_cpu = 0.0f64;
     
     loop {
+        let start_cpu = game::cpu::get_used();
+        let tick_cpu_exceeded =
+            |reserved_cpu: f64| game::cpu::get_used() - start_cpu + reserved_cpu >= ROOM_PLANNING_TICK_CPU;
+        // The first step in the tick is always allowed, so that the planning progresses even if a step is expensive.
+        let mut reserved_cpu = 0.0;
+        let mut planning_in_progress = false;
+
         // Iterating over all scanned and owned rooms.
         for_each_owned_room(|room_name, room_state| {
-            if game::cpu::tick_limit() - game::cpu::get_used() < MIN_PLAN_ROOMS_CPU {
+            if !cpu_level().enables(RoomPlanning)
+                || game::cpu::tick_limit() - game::cpu::get_used() < MIN_PLAN_ROOMS_CPU
+                || tick_cpu_exceeded(reserved_cpu)
+            {
+                planning_in_progress |= room_state.plan.is_none() || room_state.planner.is_some();
                 return;
             }
 
-            // Creating the room plan if there isn't one.
-            if room_state.plan.is_none() {
+            // Restoring the planner persisted before a global reset.
+            if let Some(checkpoint) = room_state.planner_checkpoint.take() {
+                match RoomPlanner::from_checkpoint(room_state, checkpoint) {
+                    Ok(planner) => {
+                        debug!("Restored the planner of room {}.", room_name);
+                        room_state.planner = Some(Box::new(planner));
+                    }
+                    err => {
+                        log_err!(err);
+                    }
+                }
+            }
+
+            // Creating the room plan if there isn't one or continuing replanning, e.g., with a new anchor.
+            if room_state.plan.is_none() || room_state.planner.is_some() {
                 // Creating the planner. It should not fail unless it is a bug.
                 if room_state.planner.is_none() {
-                    match RoomPlanner::new(room_state, true) {
+                    // Keeping structures already in the room where possible, e.g., after claiming an abandoned base.
+                    let existing_structures = room_state
+                        .structures
+                        .iter()
+                        .map(|(&structure_type, xys)| (structure_type, xys.keys().copied().collect()))
+                        .collect::<StructuresMap>();
+                    match RoomPlanner::new_with_existing(room_state, true, &existing_structures) {
                         Ok(planner) => {
                             room_state.planner = Some(Box::new(planner));
                         }
@@ -47,14 +88,20 @@ pub async fn plan_rooms() {
 
                 if let Some(planner) = room_state.planner.as_mut() {
                     loop {
-                        // Errors are normal when planning.
-                        let result = planner.plan();
+                        // Errors are normal when planning. Planning is done in steps to spread it over
+                        // multiple ticks.
+                        let step_start_cpu = game::cpu::get_used();
+                        let result = planner.plan_step();
+                        max_step_cpu = max_step_cpu.max(game::cpu::get_used() - step_start_cpu);
+                        reserved_cpu = max_step_cpu;
                         if let Err(err) = result {
                             trace!("Failed to create a plan for room {}: {}.", room_name, err);
                         }
 
                         // TODO Finishing planning should depend on used CPU more than on the number of tries.
-                        if planner.plans_count >= 1 && planner.tries_count >= 20 || planner.is_finished() {
+                        if !planner.is_attempt_in_progress()
+                            && (planner.plans_count >= 1 && planner.tries_count >= 20 || planner.is_finished())
+                        {
                             if planner.best_plan.is_none() {
                                 error!("Failed to create a plan for room {}.", room_name);
                                 // Resetting the planner.
@@ -68,7 +115,8 @@ pub async fn plan_rooms() {
                                 plan_current_rcl_structures(room_state);
                             }
                             break;
-                        } else if should_finish() {
+                        } else if should_finish() || tick_cpu_exceeded(reserved_cpu) {
+                            planning_in_progress = true;
                             break;
                         }
                     }
@@ -79,11 +127,65 @@ pub async fn plan_rooms() {
             }
         });
 
-        // Running only once per few ticks.
-        sleep(10).await;
+        plan_remote_rooms();
+
+        if planning_in_progress {
+            // Continuing the planning in the next tick.
+            sleep(1).await;
+        } else {
+            // Running only once per few ticks.
+            sleep(10).await;
+        }
     }
 }
 
+/// Plans mining of unowned rooms with sources adjacent to owned rooms that already have a plan. The remote room must
+/// be visible so that its terrain is up to date. Each remote is planned once per owned room.
+fn plan_remote_rooms() {
+    with_room_states(|room_states| {
+        let mut remote_plans = Vec::new();
+
+        for (&room_name, room_state) in room_states.iter() {
+            let Some(plan) = room_state.plan.as_ref() else {
+                continue;
+            };
+            if room_state.designation != RoomDesignation::Owned {
+                continue;
+            }
+
+            for offset in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let Some(remote_name) = room_name.checked_add(offset) else {
+                    continue;
+                };
+                if room_state.remote_plans.contains_key(&remote_name) || game::rooms().get(remote_name).is_none() {
+                    continue;
+                }
+
+                if let Some(remote_state) = room_states.get(&remote_name) {
+                    if remote_state.designation == RoomDesignation::NotOwned && !remote_state.sources.is_empty() {
+                        match plan_remote(room_state, plan, remote_state) {
+                            Ok(remote_plan) => {
+                                debug!(
+                                    "Planned remote {} of room {} with energy balance {}.",
+                                    remote_name, room_name, remote_plan.energy_balance
+                                );
+                                remote_plans.push((room_name, remote_name, remote_plan));
+                            }
+                            Err(err) => {
+                                trace!("Failed to plan remote {} of room {}: {}.", remote_name, room_name, err);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for (room_name, remote_name, remote_plan) in remote_plans {
+            u!(room_states.get_mut(&room_name)).remote_plans.insert(remote_name, remote_plan);
+        }
+    });
+}
+
 /// Creates a map of structures to be built for given RCL.
 pub fn plan_current_rcl_structures(room_state: &mut RoomState) {
     debug!(
@@ -128,5 +230,26 @@ pub fn plan_current_rcl_structures(room_state: &mut RoomState) {
         structures_map
     };
 
+    room_state.current_rcl_build_order = build_order(plan, &room_state.terrain, &structures_map);
     room_state.current_rcl_structures = structures_map;
 }
+
+/// Orders positions of structures of each type by the minimum RCL of their tile in the plan and then by the distance
+/// to the storage, so that the earliest and closest ones are built first.
+pub fn build_order(
+    plan: &Plan,
+    terrain: &PackedTerrain,
+    structures_map: &StructuresMap,
+) -> FxHashMap<StructureType, Vec<RoomXY>> {
+    let storage_xys = plan.tiles.find_structure_xys(Storage);
+    let storage_dm = distance_matrix(terrain.walls(), storage_xys.into_iter());
+
+    structures_map
+        .iter()
+        .map(|(&structure_type, xys)| {
+            let mut ordered_xys = xys.iter().copied().collect::<Vec<_>>();
+            ordered_xys.sort_by_key(|&xy| (plan.tiles.get(xy).min_rcl(), storage_dm.get(xy), xy));
+            (structure_type, ordered_xys)
+        })
+        .collect()
+}
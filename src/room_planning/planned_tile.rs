@@ -245,3 +245,15 @@ impl<'de> Deserialize<'de> for PlannedTile {
         Ok(PlannedTile::from_bytes(bytes))
     }
 }
+
+impl From<PlannedTile> for u16 {
+    fn from(tile: PlannedTile) -> Self {
+        u16::from_le_bytes(tile.into_bytes())
+    }
+}
+
+impl From<u16> for PlannedTile {
+    fn from(packed: u16) -> Self {
+        PlannedTile::from_bytes(packed.to_le_bytes())
+    }
+}
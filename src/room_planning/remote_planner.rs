@@ -0,0 +1,237 @@
+use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::shortest_path_by_distance_matrix::shortest_path_by_distance_matrix;
+use crate::consts::UNREACHABLE_COST;
+use crate::economy::cost_approximation::remote_energy_balance;
+use crate::room_planning::plan::{Plan, PlannedSourceData};
+use crate::room_planning::room_planner::RoomPlannerError;
+use crate::room_planning::room_planner::RoomPlannerError::{
+    NotAdjacentRoom, ResourceNotFound, StructurePlacementFailure, UnreachableResource,
+};
+use crate::room_states::room_state::RoomState;
+use rustc_hash::FxHashSet;
+use screeps::StructureType::Storage;
+use screeps::Terrain::Wall;
+use screeps::{RoomName, RoomXY, ROOM_SIZE};
+use serde::{Deserialize, Serialize};
+use std::iter::once;
+
+/// Plan of mining sources in a room adjacent to an owned room and hauling the energy to its storage.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RemotePlan {
+    /// Roads from the storage of the owned room to the containers in the remote room that are not already in the
+    /// owned room's plan.
+    pub roads: Vec<(RoomName, RoomXY)>,
+    /// Sources in the remote room. The container is placed on the work tile. Since remotes do not have links,
+    /// `link_xy` is the same as `work_xy`.
+    pub containers: Vec<PlannedSourceData>,
+    /// Sum of round-trip distances between the storage and the containers.
+    pub hauling_distance: u32,
+    /// Approximate energy per tick gained from mining the remote.
+    pub energy_balance: f32,
+}
+
+/// Plans mining of the remote room adjacent to the owned room with given plan. Each source gets a container next
+/// to it, connected by roads to the storage along the shortest route through exits shared by both rooms.
+pub fn plan_remote(owned_state: &RoomState, plan: &Plan, remote_state: &RoomState) -> Result<RemotePlan, RoomPlannerError> {
+    let exit_pairs = shared_exits(owned_state, remote_state)?;
+    let storage_xy = *plan
+        .tiles
+        .find_structure_xys(Storage)
+        .first()
+        .ok_or(StructurePlacementFailure)?;
+    if remote_state.sources.is_empty() {
+        Err(ResourceNotFound)?;
+    }
+
+    let owned_obstacles = plan
+        .tiles
+        .iter()
+        .filter_map(|(xy, tile)| (!tile.is_passable(true)).then_some(xy))
+        .chain(owned_state.terrain.walls());
+    let owned_dm = distance_matrix(owned_obstacles, once(storage_xy));
+
+    let remote_obstacles = remote_state
+        .terrain
+        .walls()
+        .chain(remote_state.sources.iter().map(|source| source.xy))
+        .chain(remote_state.controller.iter().map(|controller| controller.xy))
+        .chain(remote_state.mineral.iter().map(|mineral| mineral.xy))
+        .collect::<Vec<_>>();
+
+    let mut roads = Vec::new();
+    let mut roads_set = FxHashSet::default();
+    let mut containers = Vec::new();
+    let mut round_trip_distances = Vec::new();
+
+    for source in remote_state.sources.iter() {
+        let source_dm = distance_matrix(remote_obstacles.iter().copied(), once(source.xy));
+
+        // The route through the exit with the smallest total distance, with moving between rooms taking one tick.
+        let (owned_exit_xy, remote_exit_xy, dist) = exit_pairs
+            .iter()
+            .copied()
+            .filter_map(|(owned_exit_xy, remote_exit_xy)| {
+                let owned_dist = owned_dm.get(owned_exit_xy);
+                let remote_dist = source_dm.get(remote_exit_xy);
+                (owned_dist < UNREACHABLE_COST && remote_dist < UNREACHABLE_COST)
+                    .then_some((owned_exit_xy, remote_exit_xy, owned_dist as u32 + remote_dist as u32 - 1))
+            })
+            .min_by_key(|&(_, _, dist)| dist)
+            .ok_or(UnreachableResource)?;
+
+        let mut remote_path = shortest_path_by_distance_matrix(&source_dm, remote_exit_xy, 1);
+        let work_xy = remote_path.pop().ok_or(UnreachableResource)?;
+        let owned_path = shortest_path_by_distance_matrix(&owned_dm, owned_exit_xy, 1)
+            .into_iter()
+            .filter(|&xy| !plan.tiles.get(xy).structures().road());
+
+        for road in owned_path
+            .map(|xy| (owned_state.room_name, xy))
+            .chain(remote_path.into_iter().map(|xy| (remote_state.room_name, xy)))
+        {
+            if roads_set.insert(road) {
+                roads.push(road);
+            }
+        }
+
+        containers.push(PlannedSourceData {
+            source_xy: source.xy,
+            work_xy,
+            link_xy: work_xy,
+        });
+        round_trip_distances.push(2 * dist);
+    }
+
+    let energy_balance = remote_energy_balance(
+        remote_state.room_name,
+        &round_trip_distances,
+        roads.len() as u32,
+        containers.len() as u32,
+    );
+
+    Ok(RemotePlan {
+        roads,
+        containers,
+        hauling_distance: round_trip_distances.iter().sum(),
+        energy_balance,
+    })
+}
+
+/// Pairs of exit tiles connecting the two rooms, the first one in the owned room and the second one in the remote
+/// room.
+fn shared_exits(owned_state: &RoomState, remote_state: &RoomState) -> Result<Vec<(RoomXY, RoomXY)>, RoomPlannerError> {
+    let last = ROOM_SIZE - 1;
+    let offset = remote_state.room_name - owned_state.room_name;
+    if !matches!(offset, (1, 0) | (-1, 0) | (0, 1) | (0, -1)) {
+        return Err(NotAdjacentRoom);
+    }
+
+    let exit_pairs = (1..last)
+        .filter_map(|i| {
+            let (owned_exit, remote_exit) = match offset {
+                (1, 0) => ((last, i), (0, i)),
+                (-1, 0) => ((0, i), (last, i)),
+                (0, 1) => ((i, last), (i, 0)),
+                _ => ((i, 0), (i, last)),
+            };
+            let owned_exit_xy = RoomXY::try_from(owned_exit).ok()?;
+            let remote_exit_xy = RoomXY::try_from(remote_exit).ok()?;
+            (owned_state.terrain.get(owned_exit_xy) != Wall && remote_state.terrain.get(remote_exit_xy) != Wall)
+                .then_some((owned_exit_xy, remote_exit_xy))
+        })
+        .collect::<Vec<_>>();
+
+    if exit_pairs.is_empty() {
+        Err(UnreachableResource)
+    } else {
+        Ok(exit_pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::room_planning::plan::{Plan, PlanScore, PlannedControllerData};
+    use crate::room_planning::planned_tile::PlannedTile;
+    use crate::room_planning::remote_planner::plan_remote;
+    use crate::room_planning::room_planner::RoomPlannerError;
+    use crate::room_states::room_state::{RoomState, SourceData};
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use screeps::StructureType::Storage;
+    use screeps::Terrain::Wall;
+    use screeps::{ObjectId, RoomName, RoomXY, ROOM_SIZE};
+
+    fn test_owned_room_state_and_plan() -> (RoomState, Plan) {
+        let room_state = RoomState::new(RoomName::new("W2N2").unwrap());
+        let mut tiles = RoomMatrix::default();
+        tiles.set((25, 25).try_into().unwrap(), PlannedTile::from(Storage));
+        let plan = Plan::new(
+            tiles,
+            PlannedControllerData::default(),
+            Vec::new(),
+            None,
+            PlanScore::default(),
+            Vec::new(),
+            Vec::new(),
+        );
+        (room_state, plan)
+    }
+
+    fn test_remote_room_state(room_name: &str) -> RoomState {
+        let mut room_state = RoomState::new(RoomName::new(room_name).unwrap());
+        room_state.sources = vec![SourceData::new(
+            ObjectId::from_packed(2010),
+            (20, 10).try_into().unwrap(),
+            None,
+            Vec::new(),
+            None,
+            None,
+            None,
+        )];
+        room_state
+    }
+
+    #[test]
+    fn test_plan_remote_through_shared_exit() {
+        let (mut owned_state, plan) = test_owned_room_state_and_plan();
+        // The remote is to the east. Only the exit at y=10 is open in both rooms.
+        let mut remote_state = test_remote_room_state("W1N2");
+        for y in 0..ROOM_SIZE {
+            if y != 10 {
+                owned_state.terrain.set((ROOM_SIZE - 1, y).try_into().unwrap(), Wall);
+            }
+        }
+
+        let remote_plan = plan_remote(&owned_state, &plan, &remote_state).unwrap();
+
+        let owned_exit_xy: RoomXY = (ROOM_SIZE - 1, 10).try_into().unwrap();
+        let remote_exit_xy: RoomXY = (0, 10).try_into().unwrap();
+        assert!(remote_plan.roads.contains(&(owned_state.room_name, owned_exit_xy)));
+        assert!(remote_plan.roads.contains(&(remote_state.room_name, remote_exit_xy)));
+        assert_eq!(remote_plan.containers.len(), 1);
+        let work_xy = remote_plan.containers[0].work_xy;
+        assert_eq!(work_xy.x.u8(), 19);
+        assert!(!remote_plan.roads.contains(&(remote_state.room_name, work_xy)));
+        // 24 tiles from the storage to the exit and 19 from the other side of the exit to the container, both ways.
+        assert_eq!(remote_plan.hauling_distance, 2 * (24 + 19));
+        assert!(remote_plan.energy_balance > 0.0);
+
+        // Closing the exit on the remote side.
+        remote_state.terrain.set(remote_exit_xy, Wall);
+        assert_eq!(
+            plan_remote(&owned_state, &plan, &remote_state).err(),
+            Some(RoomPlannerError::UnreachableResource)
+        );
+    }
+
+    #[test]
+    fn test_plan_remote_requires_adjacent_room() {
+        let (owned_state, plan) = test_owned_room_state_and_plan();
+        let remote_state = test_remote_room_state("W0N2");
+        assert_eq!(
+            plan_remote(&owned_state, &plan, &remote_state).err(),
+            Some(RoomPlannerError::NotAdjacentRoom)
+        );
+    }
+}
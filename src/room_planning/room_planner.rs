@@ -1,11 +1,11 @@
 use crate::algorithms::binary_search::upper_bound_by_key;
 use crate::algorithms::chunk_graph::{chunk_graph, ChunkGraph, ChunkId};
-use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::distance_matrix::{bounded_distance_matrix, distance_matrix, distance_to_targets, distance_until};
 use crate::algorithms::distance_transform::{distance_transform_from_obstacles, l1_distance_transform_from_obstacles};
 use crate::algorithms::grid_min_cut::grid_min_cut;
 use crate::algorithms::interior_matrix::interior_matrix;
 use crate::algorithms::matrix_common::MatrixCommon;
-use crate::algorithms::minimal_shortest_paths_tree::{minimal_shortest_paths_tree, PathSpec};
+use crate::algorithms::minimal_shortest_paths_tree::{MinimalShortestPathsTree, PathSpec};
 use crate::algorithms::room_matrix::RoomMatrix;
 use crate::algorithms::room_matrix_slice::RoomMatrixSlice;
 use crate::algorithms::shortest_path_by_distance_matrix::{distance_by_matrix, shortest_path_by_distance_matrix};
@@ -14,31 +14,37 @@ use crate::consts::{OBSTACLE_COST, UNREACHABLE_COST};
 use crate::economy::cost_approximation::energy_balance_and_cpu_cost;
 use crate::geometry::rect::{ball, bounding_rect, room_rect, Rect};
 use crate::geometry::room_xy::RoomXYUtils;
-use crate::profiler::measure_time;
-use crate::utils::random::random;
+use crate::profiler::{GameProfiler, Profiler};
+use crate::utils::random::{seed_for_room, RandomSource, SeededRandom};
+use crate::visualization::heatmap::{heatmap_selected, register_heatmap, Palette};
 use crate::room_planning::packed_tile_structures::MainStructureType;
 use crate::room_planning::plan::{Plan, PlanScore, PlannedControllerData, PlannedMineralData, PlannedSourceData};
 use crate::room_planning::planned_tile::{BasePart, PlannedTile};
 use crate::room_planning::stamps::{core_stamp, labs_stamp};
+use crate::room_planning::tower_damage_field::TowerDamageField;
 use crate::room_states::packed_terrain::PackedTerrain;
-use crate::room_states::room_state::RoomState;
+use crate::room_states::room_state::{RoomState, StructuresMap};
 use crate::towers::tower_attack_power;
 use crate::u;
 use derive_more::Constructor;
-use log::{debug, error};
+use log::{debug, error, info, Log};
 use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
 use screeps::StructureType::{
     Container,
     Extension,
     Extractor,
+    Factory,
     Lab,
     Link,
     Nuker,
     Observer,
+    PowerSpawn,
     Rampart,
     Road,
     Spawn,
     Storage,
+    Terminal,
     Tower,
 };
 use screeps::Terrain::{Plain, Swamp, Wall};
@@ -56,6 +62,7 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Debug, Formatter};
 use std::iter::{empty, once};
+use std::rc::Rc;
 use num_traits::clamp;
 use thiserror::Error;
 
@@ -76,15 +83,25 @@ const GROWTH_RAMPART_COST: u8 = 4;
 const GROWN_STRUCTURE_REMOVAL_COST: u8 = 8;
 const SAFE_DIST: u8 = 6;
 const RAMPART_TO_PLAINS_ROAD_MAINTENANCE_COST: u8 = 30;
+const TOWER_GENERATIONS: u8 = 8;
+/// The number of shortest path areas or paths of roads to the main ramparts found in a single planning step.
+const RAMPART_ROAD_PATHS_PER_STEP: usize = 10;
+/// Bonuses subtracted from the scaled weighted sum of distances to resources of core centers that keep existing
+/// structures in the core.
+const EXISTING_STORAGE_CORE_BONUS: u8 = 100;
+const EXISTING_SPAWN_CORE_BONUS: u8 = 40;
+const EXISTING_TOWER_CORE_BONUS: u8 = 20;
 
 #[derive(Error, Debug, Eq, PartialEq)]
 pub enum RoomPlannerError {
     #[error("controller not found")]
     ControllerNotFound,
-    #[error("at least one source or mineral not found")]
+    #[error("no sources found")]
     ResourceNotFound,
     #[error("one of sources, the mineral or the controller is unreachable")]
     UnreachableResource,
+    #[error("the remote room is not adjacent to the owned room")]
+    NotAdjacentRoom,
     #[error("unable to find positions for all required structures")]
     StructurePlacementFailure,
     #[error("failed to connect some points with roads")]
@@ -115,21 +132,161 @@ struct RoadParameters {
     base_part: BasePart,
 }
 
+/// Stage of a single attempt at creating a plan. The attempt may be spread over multiple calls of
+/// `RoomPlanner::plan_step`, each performing one stage or a bounded part of it.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+enum PlanStage {
+    /// Selecting the next combination of the core and labs positions and rotations.
+    #[default]
+    SelectStamps,
+    /// Connecting resources with roads and placing their containers and links.
+    ConnectResources,
+    /// Growing preliminary extensions, towers, nuker and observer, only used to compute the preliminary main ramparts.
+    GrowPreliminaryStructures,
+    /// Computing preliminary main ramparts around the preliminary structures and then discarding them.
+    PreliminaryRamparts,
+    /// Growing the extensions.
+    GrowExtensions,
+    /// Finding candidate placements of towers with a few different approaches.
+    FindTowerCandidates,
+    /// Improving the candidate placements of towers with given generation of the genetic algorithm.
+    EvolveTowers(u8),
+    /// Placing the best reachable candidate towers and regrowing extensions.
+    PlaceTowers,
+    /// Placing main ramparts.
+    PlaceRamparts,
+    /// Placing roads on main ramparts and starting to connect them with roads.
+    PlaceRampartRoads,
+    /// Continuing to connect main ramparts with roads, then placing the roads and regrowing extensions.
+    ConnectRampartRoads,
+    /// Placing the remaining structures.
+    PlaceRemaining,
+    /// Assigning minimum RCL to structures and scoring the plan.
+    Finalize,
+}
+
+/// Result of a single step of planning.
+#[derive(Debug)]
+pub enum PlannerProgress {
+    /// The current plan attempt is not finished yet.
+    InProgress,
+    /// The current plan attempt has finished with given plan.
+    Finished(Box<Plan>),
+}
+
+/// Candidate placements of towers carried between the stages of tower placement.
+struct TowerCandidates {
+    valid_tiles_matrix: RoomMatrix<bool>,
+    damage_field: TowerDamageField,
+    solutions: Vec<[RoomXY; 6]>,
+    /// Population of the genetic algorithm, empty in fast mode.
+    population: Vec<[RoomXY; 6]>,
+}
+
+/// Roads being connected to some points over multiple stages.
+struct RoadsConnection {
+    paths_tree: MinimalShortestPathsTree,
+    roads_parameters: Vec<RoadParameters>,
+}
+
+/// Parameters of the planning independent of the room.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlannerConfig {
+    /// Whether to skip the more expensive parts of planning, e.g., trying all labs placements.
+    pub fast_mode: bool,
+    /// Center of the core to use instead of searching for one, e.g., when set by the user.
+    pub forced_core_center: Option<RoomXY>,
+}
+
+/// Everything about the room that the planner needs to know.
+#[derive(Clone, Debug)]
+pub struct PlannerInput {
+    pub room_name: RoomName,
+    pub terrain: PackedTerrain,
+    pub controller_xy: RoomXY,
+    pub source_xys: Vec<RoomXY>,
+    pub mineral_xy: Option<RoomXY>,
+    /// Structures already present in the room that the plan should keep where possible.
+    pub existing_structures: StructuresMap,
+}
+
+impl PlannerInput {
+    /// Formats the input as a fixture of the offline planning, consisting of `key: value` lines with
+    /// the controller, sources, mineral and base64 terrain.
+    pub fn to_fixture(&self) -> String {
+        let format_xy = |xy: RoomXY| format!("{} {}", xy.x.u8(), xy.y.u8());
+        let mut lines = vec![
+            format!("controller: {}", format_xy(self.controller_xy)),
+            format!(
+                "sources: {}",
+                self.source_xys.iter().copied().map(format_xy).collect::<Vec<_>>().join(", ")
+            ),
+        ];
+        if let Some(mineral_xy) = self.mineral_xy {
+            lines.push(format!("mineral: {}", format_xy(mineral_xy)));
+        }
+        lines.push(format!("terrain: {}", self.terrain.to_base64()));
+        lines.join("\n")
+    }
+
+    pub fn from_room_state(state: &RoomState) -> Result<Self, RoomPlannerError> {
+        // Preliminary checks of the room.
+        let controller_xy = state.controller.ok_or(ControllerNotFound)?.xy;
+        let source_xys = (!state.sources.is_empty())
+            .then_some(state.sources.iter().map(|source| source.xy).collect::<Vec<_>>())
+            .ok_or(ResourceNotFound)?;
+        let mineral_xy = state.mineral.map(|mineral| mineral.xy);
+
+        Ok(PlannerInput {
+            room_name: state.room_name,
+            terrain: state.terrain,
+            controller_xy,
+            source_xys,
+            mineral_xy,
+            existing_structures: StructuresMap::default(),
+        })
+    }
+}
+
+/// Progress of the planner that can be persisted, e.g., to continue planning after a global reset.
+/// The rest of the planner state is recomputed from the room when restoring it. A plan attempt that was in progress
+/// is retried from the beginning.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlannerCheckpoint {
+    config: PlannerConfig,
+    tries_count: u16,
+    plans_count: u16,
+    retry_current_stamps: bool,
+    core_centers_stack: Vec<RoomXY>,
+    core_rotations_stack: Vec<u8>,
+    labs_dists_stack: Vec<u8>,
+    labs_top_left_corners_stack: Vec<RoomXY>,
+    labs_rotations_stack: Vec<u8>,
+    best_plan: Option<Plan>,
+    existing_structures: StructuresMap,
+    seed: u64,
+}
+
 pub struct RoomPlanner {
-    fast_mode: bool,
+    config: PlannerConfig,
+    seed: u64,
+    rng: SeededRandom,
+    profiler: Rc<dyn Profiler>,
+    logger: &'static dyn Log,
     pub tries_count: u16,
     pub plans_count: u16,
 
     room_name: RoomName,
     controller_xy: RoomXY,
     source_xys: Vec<RoomXY>,
-    mineral_xy: RoomXY,
+    mineral_xy: Option<RoomXY>,
     terrain: PackedTerrain,
+    existing_structures: StructuresMap,
 
     walls: Vec<RoomXY>,
     controller_dm: RoomMatrix<u8>,
     source_dms: Vec<RoomMatrix<u8>>,
-    mineral_dm: RoomMatrix<u8>,
+    mineral_dm: Option<RoomMatrix<u8>>,
     exits_dm: RoomMatrix<u8>,
     exit_rampart_distances: RoomMatrix<u8>,
     dt: RoomMatrix<u8>,
@@ -137,6 +294,7 @@ pub struct RoomPlanner {
     chunks: ChunkGraph,
     enclosures: FxHashMap<ChunkId, (ChunkId, bool)>,
 
+    stage: PlanStage,
     core_centers_stack: Vec<RoomXY>,
     core_rotations_stack: Vec<u8>,
     labs_dists_stack: Vec<u8>,
@@ -152,64 +310,118 @@ pub struct RoomPlanner {
     main_ramparts: Vec<RoomXY>,
     interior_dm: RoomMatrix<u8>,
     min_tower_damage: u16,
+    // Cache per plan attempt, carried between its stages.
+    preliminary_tiles: Option<RoomMatrix<PlannedTile>>,
+    tower_candidates: Option<TowerCandidates>,
+    rampart_roads: Option<RoadsConnection>,
 
     // Output.
     planned_tiles: RoomMatrix<PlannedTile>,
     planned_sources: Vec<PlannedSourceData>,
     planned_controller: PlannedControllerData,
-    planned_mineral: PlannedMineralData,
+    planned_mineral: Option<PlannedMineralData>,
 
     pub best_plan: Option<Plan>,
 }
 
+/// The seed of the random source of the planner of the room. The seed is logged so that the planning can be replayed
+/// after setting it with `set_seed`.
+fn room_seed(room_name: RoomName) -> u64 {
+    let seed = seed_for_room(room_name);
+    info!("Planning room {} with seed {}.", room_name, seed);
+    seed
+}
+
 impl RoomPlanner {
     // TODO Option to plan remotes used outside of shard3 or when there is enough space.
     pub fn new(state: &RoomState, fast_mode: bool) -> Result<RoomPlanner, Box<dyn Error>> {
-        // Preliminary checks of the room.
-        let controller_xy = state.controller.ok_or(ControllerNotFound)?.xy;
-        let source_xys = (!state.sources.is_empty())
-            .then_some(state.sources.iter().map(|source| source.xy).collect::<Vec<_>>())
-            .ok_or(ResourceNotFound)?;
-        let mineral_xy = state.mineral.ok_or(ResourceNotFound)?.xy;
+        Self::new_with_existing(state, fast_mode, &StructuresMap::default())
+    }
+
+    /// Creates the planner for a room with given structures already present, e.g., after claiming an abandoned base.
+    /// The plan prefers to keep them and lists the ones that conflict with it in `Plan::demolition_list`.
+    pub fn new_with_existing(
+        state: &RoomState,
+        fast_mode: bool,
+        existing: &StructuresMap,
+    ) -> Result<RoomPlanner, Box<dyn Error>> {
+        let mut input = PlannerInput::from_room_state(state)?;
+        input.existing_structures = existing.clone();
+        Self::from_input(
+            input,
+            PlannerConfig {
+                fast_mode,
+                forced_core_center: state.planner_anchor,
+            },
+            room_seed(state.room_name),
+            Rc::new(GameProfiler),
+            log::logger(),
+        )
+    }
+
+    /// Creates the planner without any dependence on the game state, e.g., to plan rooms offline.
+    pub fn from_input(
+        input: PlannerInput,
+        config: PlannerConfig,
+        seed: u64,
+        profiler: Rc<dyn Profiler>,
+        logger: &'static dyn Log,
+    ) -> Result<RoomPlanner, Box<dyn Error>> {
+        let PlannerInput {
+            room_name,
+            terrain,
+            controller_xy,
+            source_xys,
+            mineral_xy,
+            existing_structures,
+        } = input;
 
         // Finding distances from various room features and initializing data structures.
-        let walls = state.terrain.walls().collect::<Vec<_>>();
+        let walls = terrain.walls().collect::<Vec<_>>();
         let controller_dm = distance_matrix(walls.iter().copied(), once(controller_xy));
         let source_dms = source_xys
             .iter()
             .copied()
             .map(|source_xy| distance_matrix(walls.iter().copied(), once(source_xy)))
             .collect::<Vec<_>>();
-        let mineral_dm = distance_matrix(walls.iter().copied(), once(mineral_xy));
+        let mineral_dm = mineral_xy.map(|mineral_xy| distance_matrix(walls.iter().copied(), once(mineral_xy)));
         let exits = room_rect()
             .boundary()
-            .filter_map(|xy| (state.terrain.get(xy) != Wall).then_some(xy))
+            .filter_map(|xy| (terrain.get(xy) != Wall).then_some(xy))
             .collect::<Vec<_>>();
         let exits_dm = distance_matrix(walls.iter().copied(), exits.iter().copied());
-        let exit_rampart_distances = distance_matrix(
+        register_heatmap(room_name, "exits_dm", &exits_dm, Palette::Heat);
+        // Only distances up to 6 are ever checked, anything further is treated the same.
+        let exit_rampart_distances = bounded_distance_matrix(
             empty(),
             exits_dm.iter().filter_map(|(xy, dist)| (dist <= 1).then_some(xy)),
+            6,
         );
         // Distance transform in maximum metric.
         let dt = distance_transform_from_obstacles(walls.iter().copied(), 1);
         // Distance transform in l1 metric.
         let dt_l1 = l1_distance_transform_from_obstacles(walls.iter().copied(), 1);
         // Chunk graph.
-        let walls_matrix = state.terrain.to_obstacle_matrix(0);
+        let walls_matrix = terrain.to_obstacle_matrix(0);
         let chunks = chunk_graph(&walls_matrix, CHUNK_RADIUS);
         let enclosures = chunks.enclosures();
 
         let mut room_planner = RoomPlanner {
-            fast_mode,
+            config,
+            seed,
+            rng: SeededRandom::new(seed),
+            profiler,
+            logger,
             tries_count: 0,
             plans_count: 0,
 
-            room_name: state.room_name,
+            room_name,
             controller_xy,
             source_xys,
             mineral_xy,
 
-            terrain: state.terrain,
+            terrain,
+            existing_structures,
             walls,
             controller_dm,
             source_dms,
@@ -221,6 +433,7 @@ impl RoomPlanner {
             chunks,
             enclosures,
 
+            stage: PlanStage::default(),
             core_centers_stack: Vec::new(),
             core_rotations_stack: Vec::new(),
             labs_dists_stack: Vec::new(),
@@ -235,11 +448,14 @@ impl RoomPlanner {
             main_ramparts: Vec::new(),
             interior_dm: RoomMatrix::new(ROOM_SIZE),
             min_tower_damage: 0,
+            preliminary_tiles: None,
+            tower_candidates: None,
+            rampart_roads: None,
 
             planned_tiles: RoomMatrix::default(),
             planned_sources: Vec::new(),
             planned_controller: PlannedControllerData::default(),
-            planned_mineral: PlannedMineralData::default(),
+            planned_mineral: None,
 
             best_plan: None,
         };
@@ -249,6 +465,77 @@ impl RoomPlanner {
         Ok(room_planner)
     }
 
+    /// Restores the planner from a checkpoint created by `RoomPlanner::checkpoint`.
+    pub fn from_checkpoint(state: &RoomState, checkpoint: PlannerCheckpoint) -> Result<RoomPlanner, Box<dyn Error>> {
+        info!("Resuming planning of room {} with seed {}.", state.room_name, checkpoint.seed);
+        Self::from_input_and_checkpoint(
+            PlannerInput::from_room_state(state)?,
+            checkpoint,
+            Rc::new(GameProfiler),
+            log::logger(),
+        )
+    }
+
+    /// Restores the planner from a checkpoint without any dependence on the game state. The random source is reseeded
+    /// with the seed of the checkpointed planner.
+    pub fn from_input_and_checkpoint(
+        input: PlannerInput,
+        checkpoint: PlannerCheckpoint,
+        profiler: Rc<dyn Profiler>,
+        logger: &'static dyn Log,
+    ) -> Result<RoomPlanner, Box<dyn Error>> {
+        let mut input = input;
+        input.existing_structures = checkpoint.existing_structures;
+        let mut planner = Self::from_input(input, checkpoint.config, checkpoint.seed, profiler, logger)?;
+
+        planner.tries_count = checkpoint.tries_count;
+        planner.plans_count = checkpoint.plans_count;
+        planner.core_centers_stack = checkpoint.core_centers_stack;
+        planner.core_rotations_stack = checkpoint.core_rotations_stack;
+        planner.labs_dists_stack = checkpoint.labs_dists_stack;
+        planner.labs_top_left_corners_stack = checkpoint.labs_top_left_corners_stack;
+        planner.labs_rotations_stack = checkpoint.labs_rotations_stack;
+        planner.best_plan = checkpoint.best_plan;
+
+        // Checkpoints made before the first try used to have a placeholder core center on top of the stack. It can
+        // never be a valid core center, being at the room edge.
+        if planner.core_rotations_stack.is_empty() && planner.core_centers_stack.last() == Some(&RoomXY::default()) {
+            planner.core_centers_stack.pop();
+        }
+
+        // The core is only recomputed when the core center or rotation changes, so it needs to be restored if some
+        // core was already selected.
+        if !planner.core_rotations_stack.is_empty() {
+            planner.init_core();
+        }
+
+        if checkpoint.retry_current_stamps {
+            // Duplicating the current labs rotation so that the next attempt uses the same stamps.
+            let labs_rotation = planner.current_labs_rotation();
+            planner.labs_rotations_stack.push(labs_rotation);
+        }
+
+        Ok(planner)
+    }
+
+    /// Creates a checkpoint of the planner from which it can be restored.
+    pub fn checkpoint(&self) -> PlannerCheckpoint {
+        PlannerCheckpoint {
+            config: self.config.clone(),
+            tries_count: self.tries_count,
+            plans_count: self.plans_count,
+            retry_current_stamps: self.is_attempt_in_progress(),
+            core_centers_stack: self.core_centers_stack.clone(),
+            core_rotations_stack: self.core_rotations_stack.clone(),
+            labs_dists_stack: self.labs_dists_stack.clone(),
+            labs_top_left_corners_stack: self.labs_top_left_corners_stack.clone(),
+            labs_rotations_stack: self.labs_rotations_stack.clone(),
+            best_plan: self.best_plan.clone(),
+            existing_structures: self.existing_structures.clone(),
+            seed: self.seed,
+        }
+    }
+
     /// Creates the room plan.
     /// A good place for the core is one that balances the following:
     /// - the number of ramparts required to protect the base,
@@ -256,32 +543,174 @@ impl RoomPlanner {
     /// - distance from the nearest spawn to sources, controller and mineral,
     /// - distance between ramparts to maximize minimal tower damage right outside of ramparts.
     pub fn plan(&mut self) -> Result<Plan, Box<dyn Error>> {
+        loop {
+            if let PlannerProgress::Finished(plan) = self.plan_step()? {
+                return Ok(*plan);
+            }
+        }
+    }
+
+    /// Performs a single stage, or a bounded part of a longer stage, of the current plan attempt, starting a new
+    /// attempt if there is none, so that planning can be spread over multiple ticks. Returns the plan when the attempt is finished. On error, the attempt is
+    /// abandoned and the next call starts a new one.
+    pub fn plan_step(&mut self) -> Result<PlannerProgress, Box<dyn Error>> {
+        // Taking the stage resets it in case of an error.
+        let next_stage = match std::mem::take(&mut self.stage) {
+            PlanStage::SelectStamps => {
+                self.select_stamps()?;
+                PlanStage::ConnectResources
+            }
+            PlanStage::ConnectResources => {
+                self.connect_resources()?;
+                PlanStage::GrowPreliminaryStructures
+            }
+            PlanStage::GrowPreliminaryStructures => {
+                // Preliminary growth of places for extensions, towers, nuker, observer. These will be used to
+                // compute preliminary main rampart positions and then discarded.
+                self.preliminary_tiles = Some(self.planned_tiles.clone());
+                self.grow_reachable_structures(Extension, 68, self.storage_xy)?;
+                PlanStage::PreliminaryRamparts
+            }
+            PlanStage::PreliminaryRamparts => {
+                // This sets the `main_ramparts` attribute.
+                let result = self.place_main_ramparts();
+                self.planned_tiles = u!(self.preliminary_tiles.take());
+                result?;
+                PlanStage::GrowExtensions
+            }
+            PlanStage::GrowExtensions => {
+                // Growing the extensions plus a spot for the nuker
+                self.grow_reachable_structures(Extension, 61, self.storage_xy)?;
+
+                debug!(logger: self.logger, "After initial grow\n{:?}", self);
+                PlanStage::FindTowerCandidates
+            }
+            PlanStage::FindTowerCandidates => {
+                self.find_tower_candidates()?;
+                if self.config.fast_mode {
+                    PlanStage::PlaceTowers
+                } else {
+                    PlanStage::EvolveTowers(0)
+                }
+            }
+            PlanStage::EvolveTowers(generation) => {
+                self.evolve_towers(generation);
+                if generation + 1 < TOWER_GENERATIONS {
+                    PlanStage::EvolveTowers(generation + 1)
+                } else {
+                    PlanStage::PlaceTowers
+                }
+            }
+            PlanStage::PlaceTowers => {
+                // Placing towers and roads to these towers.
+                self.place_towers()?;
+                // Regrowing extensions that were removed when placing the roads.
+                self.grow_reachable_structures(Extension, 61, self.storage_xy)?;
+
+                debug!(logger: self.logger, "After towers and regrow\n{:?}", self);
+                PlanStage::PlaceRamparts
+            }
+            PlanStage::PlaceRamparts => {
+                self.place_main_ramparts()?;
+                PlanStage::PlaceRampartRoads
+            }
+            PlanStage::PlaceRampartRoads => {
+                self.place_rampart_roads()?;
+                PlanStage::ConnectRampartRoads
+            }
+            PlanStage::ConnectRampartRoads => {
+                if self.connect_rampart_roads()? {
+                    // Regrowing extensions removed when placing the roads.
+                    self.grow_reachable_structures(Extension, 61, self.storage_xy)?;
+
+                    debug!(logger: self.logger, "After rampart roads and regrow\n{:?}", self);
+                    PlanStage::PlaceRemaining
+                } else {
+                    PlanStage::ConnectRampartRoads
+                }
+            }
+            PlanStage::PlaceRemaining => {
+                // Placing the observer in a free space, preferably at a `SAFE_DIST` from outside.
+                self.place_observer()?;
+
+                // Replacing one of the extensions with a nuker, preferably one at a `SAFE_DIST` from outside.
+                self.place_nuker()?;
+
+                // Moving links into a position not reachable from outside if possible.
+                self.optimize_links()?;
+
+                // Adding ramparts on everything near outside that needs protection.
+                self.place_extra_ramparts()?;
+
+                // TODO Make a few iterations that improve existing plan. For example grow but try to keep further away
+                //      from existing ramparts.
+                PlanStage::Finalize
+            }
+            PlanStage::Finalize => {
+                let plan = self.finalize_plan()?;
+
+                if self.config.fast_mode {
+                    // Try only the first successful attempt at placing labs in fast mode.
+                    self.labs_rotations_stack.clear();
+                    self.labs_top_left_corners_stack.clear();
+                    self.labs_dists_stack.clear();
+                }
+
+                self.plans_count += 1;
+
+                return Ok(PlannerProgress::Finished(Box::new(plan)));
+            }
+        };
+
+        self.stage = next_stage;
+        Ok(PlannerProgress::InProgress)
+    }
+
+    /// Whether a plan attempt was started and is neither finished nor abandoned yet.
+    pub fn is_attempt_in_progress(&self) -> bool {
+        self.stage != PlanStage::SelectStamps
+    }
+
+    /// Selects the next combination of stamps to try and places them.
+    fn select_stamps(&mut self) -> Result<(), Box<dyn Error>> {
         self.tries_count += 1;
 
-        self.labs_rotations_stack.pop();
-        if self.labs_rotations_stack.is_empty() {
-            self.labs_top_left_corners_stack.pop();
-            if self.labs_top_left_corners_stack.is_empty() {
-                self.labs_dists_stack.pop();
-                if self.labs_dists_stack.is_empty() {
-                    self.core_rotations_stack.pop();
-                    if self.core_rotations_stack.is_empty() {
-                        self.core_centers_stack.pop();
-                        if self.core_centers_stack.is_empty() {
-                            Err(PlanGenerationFinished)?;
-                        }
+        if self.core_rotations_stack.is_empty() {
+            // The first try uses the best core center, so there is nothing to pop yet.
+            if self.core_centers_stack.is_empty() {
+                Err(PlanGenerationFinished)?;
+            }
+            self.init_core_rotations_stack();
+            self.init_labs_dists_stack();
+            self.init_labs_top_left_corners_stack()?;
+            self.init_labs_rotations_stack();
+        } else {
+            self.labs_rotations_stack.pop();
+            if self.labs_rotations_stack.is_empty() {
+                self.labs_top_left_corners_stack.pop();
+                if self.labs_top_left_corners_stack.is_empty() {
+                    self.labs_dists_stack.pop();
+                    if self.labs_dists_stack.is_empty() {
+                        self.core_rotations_stack.pop();
+                        if self.core_rotations_stack.is_empty() {
+                            self.core_centers_stack.pop();
+                            if self.core_centers_stack.is_empty() {
+                                Err(PlanGenerationFinished)?;
+                            }
 
-                        self.init_core_rotations_stack();
+                            self.init_core_rotations_stack();
+                        }
+                        self.init_labs_dists_stack();
                     }
-                    self.init_labs_dists_stack();
+                    self.init_labs_top_left_corners_stack()?;
                 }
-                self.init_labs_top_left_corners_stack()?;
+                self.init_labs_rotations_stack();
             }
-            self.init_labs_rotations_stack();
         }
         self.init_planned_tiles()?;
 
         debug!(
+            logger: self.logger,
             "Processing core {}/R{} and labs {}/R{} at dist {}.",
             self.current_core_center(),
             self.current_core_rotation(),
@@ -290,38 +719,25 @@ impl RoomPlanner {
             self.current_labs_dist(),
         );
 
-        let plan = self.plan_from_stamps()?;
-
-        if self.fast_mode {
-            // Try only the first successful attempt at placing labs in fast mode.
-            self.labs_rotations_stack.clear();
-            self.labs_top_left_corners_stack.clear();
-            self.labs_dists_stack.clear();
-        }
-
-        self.plans_count += 1;
-
-        Ok(plan)
+        Ok(())
     }
 
     pub fn is_finished(&self) -> bool {
-        self.core_centers_stack.is_empty()
+        !self.is_attempt_in_progress()
+            && (self.core_centers_stack.is_empty()
             || self.core_centers_stack.len() == 1
             && self.core_rotations_stack.len() == 1
             && self.labs_dists_stack.len() == 1
             && self.labs_top_left_corners_stack.len() == 1
-            && self.labs_rotations_stack.len() == 1
+            && self.labs_rotations_stack.len() == 1)
     }
 
     pub fn init_core_centers(&mut self) -> Result<(), Box<dyn Error>> {
         // TODO Perform theoretical calculations on good weights, include mineral in them.
         let resources_dist_sum = {
             let mut preliminary_sum = RoomMatrix::new(0.0f32);
-            let resource_dms_and_weights = [
-                (&self.controller_dm, CONTROLLER_DIST_WEIGHT),
-                (&self.mineral_dm, MINERAL_DIST_WEIGHT),
-            ]
-                .into_iter()
+            let resource_dms_and_weights = once((&self.controller_dm, CONTROLLER_DIST_WEIGHT))
+                .chain(self.mineral_dm.iter().map(|dm| (dm, MINERAL_DIST_WEIGHT)))
                 .chain(self.source_dms.iter().map(|dm| (dm, SOURCE_DIST_WEIGHT)));
             for (dm, weight) in resource_dms_and_weights {
                 preliminary_sum.update(|xy, value| {
@@ -339,12 +755,39 @@ impl RoomPlanner {
                     .fold(1.0, |acc, (_, v)| if v != f32::INFINITY && v > acc { v } else { acc });
             preliminary_sum.map(|xy, value| {
                 if value.is_finite() {
-                    (value / max_finite_value * 250.0).round() as u8
+                    let scaled_value = (value / max_finite_value * 250.0).round() as u8;
+                    scaled_value.saturating_sub(self.existing_structures_core_bonus(xy))
                 } else {
                     OBSTACLE_COST
                 }
             })
         };
+        if let Some(forced_xy) = self.config.forced_core_center {
+            self.init_forced_core_center(&resources_dist_sum, forced_xy)
+        } else {
+            self.init_searched_core_centers(&resources_dist_sum)
+        }
+    }
+
+    /// Uses only the forced core center, as long as the core can fit there.
+    fn init_forced_core_center(
+        &mut self,
+        resources_dist_sum: &RoomMatrix<u8>,
+        forced_xy: RoomXY,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.exit_rampart_distances.get(forced_xy) < 6
+            || resources_dist_sum.get(forced_xy) == OBSTACLE_COST
+            || !self.core_fits(&self.dt, forced_xy)
+        {
+            Err(StructurePlacementFailure)?
+        }
+        debug!(logger: self.logger, "Using the forced core center {}.", forced_xy);
+        self.core_centers_stack = vec![forced_xy];
+        Ok(())
+    }
+
+    /// Selects the core centers closest to the resources, with the best one on top of the stack.
+    fn init_searched_core_centers(&mut self, resources_dist_sum: &RoomMatrix<u8>) -> Result<(), Box<dyn Error>> {
         // Finding only resource centers where the core can fit.
         let mut resource_centers = resources_dist_sum
             .iter()
@@ -368,7 +811,7 @@ impl RoomPlanner {
             ),
             resource_centers.len(),
         );
-        debug!("Found {} valid core centers.", resource_centers.len());
+        debug!(logger: self.logger, "Found {} valid core centers.", resource_centers.len());
         self.core_centers_stack = resource_centers
             .iter()
             .copied()
@@ -376,12 +819,13 @@ impl RoomPlanner {
             .map(|(xy, _)| xy)
             .collect();
         debug!(
+            logger: self.logger,
             "Remaining {} core centers within percentile {} of weighted sum of distances to resources.",
             self.core_centers_stack.len(),
             RESOURCES_DIST_PERCENTILE_CUTOFF
         );
 
-        if self.fast_mode {
+        if self.config.fast_mode {
             let mut used_chunks = FxHashSet::default();
             self.core_centers_stack = self
                 .core_centers_stack
@@ -399,6 +843,7 @@ impl RoomPlanner {
                 .collect::<Vec<_>>();
 
             debug!(
+                logger: self.logger,
                 "Remaining {} core centers after selecting one per chunk.",
                 self.core_centers_stack.len()
             );
@@ -406,12 +851,27 @@ impl RoomPlanner {
 
         self.core_centers_stack.reverse();
 
-        // Temporary value to be removed at the beginning.
-        self.core_centers_stack.push((0, 0).try_into().unwrap());
-
         Ok(())
     }
 
+    /// Bonus for placing the core center at given tile, higher the more existing structures the core keeps.
+    fn existing_structures_core_bonus(&self, core_center: RoomXY) -> u8 {
+        let existing_count = |structure_type: StructureType, max_dist: u8| {
+            self.existing_structures
+                .get(&structure_type)
+                .map_or(0, |xys| xys.iter().filter(|xy| xy.dist(core_center) <= max_dist).count() as u8)
+        };
+
+        // The storage is in one of the corners of the inner core, so it is kept only when diagonal to the center.
+        let storage_kept = self.existing_structures.get(&Storage).is_some_and(|xys| {
+            ball(core_center, 2).corners().iter().any(|corner| xys.contains(corner))
+        });
+
+        (storage_kept as u8 * EXISTING_STORAGE_CORE_BONUS)
+            .saturating_add(existing_count(Spawn, 3).saturating_mul(EXISTING_SPAWN_CORE_BONUS))
+            .saturating_add(existing_count(Tower, 3).saturating_mul(EXISTING_TOWER_CORE_BONUS))
+    }
+
     #[inline]
     fn core_fits(&self, dt: &RoomMatrix<u8>, xy: RoomXY) -> bool {
         let center_dt_dist = dt.get(xy);
@@ -430,7 +890,10 @@ impl RoomPlanner {
     }
 
     fn init_core_rotations_stack(&mut self) {
-        if self.fast_mode {
+        if let Some(storage_rotation) = self.existing_storage_core_rotation() {
+            // Keeping the existing storage.
+            self.core_rotations_stack = vec![storage_rotation];
+        } else if self.config.fast_mode {
             // Try only the rotation where the storage is in a spacious place.
             let core_center = self.current_core_center();
             let inner_core_rect = ball(core_center, 2);
@@ -447,7 +910,29 @@ impl RoomPlanner {
         }
     }
 
+    /// Rotation of the core at the current core center that places the storage on an existing one, if there is any.
+    fn existing_storage_core_rotation(&self) -> Option<u8> {
+        let existing_storages = self.existing_structures.get(&Storage)?;
+        ball(self.current_core_center(), 2)
+            .corners()
+            .into_iter()
+            .position(|corner| existing_storages.contains(&corner))
+            .map(|i| i as u8)
+    }
+
     fn init_labs_dists_stack(&mut self) {
+        self.init_core();
+
+        if self.config.fast_mode {
+            self.labs_dists_stack = (1..FAST_MODE_LABS_DIST).collect();
+        } else {
+            self.labs_dists_stack = (1..MAX_LABS_DIST).collect();
+        }
+        self.labs_dists_stack.reverse();
+    }
+
+    /// Places the core stamp at the current core center with the current rotation.
+    fn init_core(&mut self) {
         self.core = core_stamp();
         let core_center = self.current_core_center();
         u!(self.core.translate(core_center.sub(self.core.rect.center())));
@@ -464,13 +949,6 @@ impl RoomPlanner {
         for (xy, _) in self.terrain.iter() {
             self.checkerboard.set(xy, (grid_bit + xy.x.u8() + xy.y.u8()) % 2);
         }
-
-        if self.fast_mode {
-            self.labs_dists_stack = (1..FAST_MODE_LABS_DIST).collect();
-        } else {
-            self.labs_dists_stack = (1..MAX_LABS_DIST).collect();
-        }
-        self.labs_dists_stack.reverse();
     }
 
     fn init_labs_top_left_corners_stack(&mut self) -> Result<(), RoomPlannerError> {
@@ -535,11 +1013,9 @@ impl RoomPlanner {
         // . L L .
         let core_center = self.current_core_center();
         unsafe {
-            // Note that once the first dt_l1 below passes, adding the diff is correct.
-            self.dt_l1.get(labs_rect.top_left.add_diff((1, 1))) >= 2
-                && self.dt_l1.get(labs_rect.top_left.add_diff((1, 2))) >= 2
-                && self.dt_l1.get(labs_rect.top_left.add_diff((2, 1))) >= 2
-                && self.dt_l1.get(labs_rect.top_left.add_diff((2, 2))) >= 2
+            // The labs rect is 4x4, so its inner 2x2 rect is valid.
+            let inner_rect = Rect::unchecked_new(labs_rect.top_left.add_diff((1, 1)), labs_rect.top_left.add_diff((2, 2)));
+            self.dt_l1.slice(inner_rect).iter().all(|(_, dist)| dist >= 2)
                 && labs_rect.corners().iter().copied().all(|xy| {
                 self.exit_rampart_distances.get(xy) >= 4
                     && (core_center.dist(xy) >= 4
@@ -552,7 +1028,7 @@ impl RoomPlanner {
     }
 
     fn init_labs_rotations_stack(&mut self) {
-        if self.fast_mode {
+        if self.config.fast_mode {
             // In fast mode, only use the lab rotation where its road corner is the closest to the storage.
             let top_left = self.current_labs_top_left_corner();
             let labs_rect = u!(Rect::new(top_left, unsafe { top_left.add_diff((3, 3)) }));
@@ -583,7 +1059,7 @@ impl RoomPlanner {
         Ok(())
     }
 
-    fn plan_from_stamps(&mut self) -> Result<Plan, Box<dyn Error>> {
+    fn connect_resources(&mut self) -> Result<(), Box<dyn Error>> {
         // First attempt in which good places to grow towards are not known.
         self.interior_dm = RoomMatrix::new(ROOM_SIZE);
 
@@ -616,9 +1092,9 @@ impl RoomPlanner {
                 true,
                 BasePart::Interior,
             )))
-            .chain(once(RoadParameters::new(
+            .chain(self.mineral_xy.map(|mineral_xy| RoadParameters::new(
                 vec![self.storage_xy],
-                self.mineral_xy,
+                mineral_xy,
                 1,
                 1,
                 2.0,
@@ -638,10 +1114,13 @@ impl RoomPlanner {
             self.planned_tiles.reserve(work_xy);
         }
 
+        // Work tiles of sources come after the ones of the labs, controller and mineral, if there is one.
+        let sources_work_xys_offset = if self.mineral_xy.is_some() { 3 } else { 2 };
+
         // Adding links.
         self.planned_sources = Vec::new();
         for (i, source_xy) in self.source_xys.clone().into_iter().enumerate() {
-            let work_xy = work_xys[3 + i];
+            let work_xy = work_xys[sources_work_xys_offset + i];
             let link_xy = self.place_resource_storage(work_xy, BasePart::Protected, true, false)?;
             self.planned_sources.push(PlannedSourceData {
                 source_xy,
@@ -657,60 +1136,22 @@ impl RoomPlanner {
         }
 
         // Adding mineral mining container and the extractor.
-        {
+        self.planned_mineral = None;
+        if let Some(mineral_xy) = self.mineral_xy {
             let work_xy = work_xys[2];
             self.place_resource_storage(work_xy, BasePart::Outside, false, false)?;
-            self.planned_mineral = PlannedMineralData { work_xy };
+            self.planned_mineral = Some(PlannedMineralData { work_xy });
             self.planned_tiles
-                .merge_structure(self.mineral_xy, Extractor, BasePart::Outside, false)?;
+                .merge_structure(mineral_xy, Extractor, BasePart::Outside, false)?;
         }
 
         // Making sure that the controller can be actively protected.
         self.add_controller_protection();
 
-        self.dry_run(|planner| -> Result<(), Box<dyn Error>> {
-            // Preliminary growth of places for extensions, towers, nuker, observer. These will be used to compute
-            // preliminary main rampart positions and then discarded.
-            planner.grow_reachable_structures(Extension, 68, planner.storage_xy)?;
-            // This sets the `main_ramparts` attribute.
-            planner.place_main_ramparts()?;
-            Ok(())
-        })?;
-
-        // Growing the extensions plus a spot for the nuker
-        self.grow_reachable_structures(Extension, 61, self.storage_xy)?;
-
-        debug!("After initial grow\n{:?}", self);
-
-        // Placing towers and roads to these towers.
-        self.place_towers()?;
-        // Regrowing extensions that were removed when placing the roads.
-        self.grow_reachable_structures(Extension, 61, self.storage_xy)?;
-
-        debug!("After towers and regrow\n{:?}", self);
-
-        // Placing main ramparts, roads to them and regrowing extensions removed when placing the roads.
-        self.place_main_ramparts()?;
-        self.place_rampart_roads()?;
-        self.grow_reachable_structures(Extension, 61, self.storage_xy)?;
-
-        debug!("After rampart roads and regrow\n{:?}", self);
-
-        // Placing the observer in a free space, preferably at a `SAFE_DIST` from outside.
-        self.place_observer()?;
-
-        // Replacing one of the extensions with a nuker, preferably one at a `SAFE_DIST` from outside.
-        self.place_nuker()?;
-
-        // Moving links into a position not reachable from outside if possible.
-        self.optimize_links()?;
-
-        // Adding ramparts on everything near outside that needs protection.
-        self.place_extra_ramparts()?;
-
-        // TODO Make a few iterations that improve existing plan. For example grow but try to keep further away from
-        //      existing ramparts.
+        Ok(())
+    }
 
+    fn finalize_plan(&mut self) -> Result<Plan, Box<dyn Error>> {
         // Assigning the minimum RCL for buildings to be built.
         self.assign_min_rcl()?;
 
@@ -729,9 +1170,11 @@ impl RoomPlanner {
             self.planned_sources.clone(),
             self.planned_mineral,
             score,
+            self.demolition_list(),
+            self.main_ramparts.clone(),
         );
 
-        debug!("Successfully created a new plan with score {:?}.", score);
+        debug!(logger: self.logger, "Successfully created a new plan with score {:?}.", score);
         if self
             .best_plan
             .as_ref()
@@ -744,6 +1187,25 @@ impl RoomPlanner {
         Ok(plan)
     }
 
+    /// Existing structures that conflict with the planned ones and need to be removed.
+    fn demolition_list(&self) -> Vec<(StructureType, RoomXY)> {
+        let mut demolition_list = self
+            .existing_structures
+            .iter()
+            .flat_map(|(&structure_type, xys)| xys.iter().map(move |&xy| (structure_type, xy)))
+            .filter(|&(structure_type, xy)| {
+                let tile = self.planned_tiles.get(xy);
+                match structure_type {
+                    Rampart => false,
+                    Road => !tile.is_passable(true),
+                    _ => !tile.is_empty() && !tile.iter().any(|planned_type| planned_type == structure_type),
+                }
+            })
+            .collect::<Vec<_>>();
+        demolition_list.sort_by_key(|&(_, xy)| xy);
+        demolition_list
+    }
+
     #[inline]
     fn closest_labs_road(&self) -> RoomXY {
         let mut lab_roads = self
@@ -761,7 +1223,21 @@ impl RoomPlanner {
         sqrt_target_scaling: bool,
         dist_tolerance: u8,
     ) -> Result<Vec<RoomXY>, Box<dyn Error>> {
+        let mut paths_tree = self.roads_paths_tree(roads_parameters, sqrt_target_scaling, dist_tolerance);
+        paths_tree.advance(usize::MAX).ok_or(RoadConnectionFailure)?;
+        Ok(self.place_roads(paths_tree.into_paths(), roads_parameters))
+    }
+
+    /// Prepares finding paths of roads from `connect_with_roads`, to be advanced until all paths are found.
+    fn roads_paths_tree(
+        &self,
+        roads_parameters: &[RoadParameters],
+        sqrt_target_scaling: bool,
+        dist_tolerance: u8,
+    ) -> MinimalShortestPathsTree {
         let mut cost_matrix = self.terrain.to_cost_matrix(1);
+        let no_roads = FxHashSet::default();
+        let existing_roads = self.existing_structures.get(&Road).unwrap_or(&no_roads);
         for (xy, tile) in self.planned_tiles.iter() {
             if self.interior_dm.get(xy) == 0 {
                 cost_matrix.set(xy, obstacle_cost());
@@ -776,7 +1252,7 @@ impl RoomPlanner {
                     } else {
                         cost_matrix.set(xy, obstacle_cost());
                     }
-                } else if tile.structures().road() {
+                } else if tile.structures().road() || existing_roads.contains(&xy) {
                     cost_matrix.set(xy, 0);
                 }
             }
@@ -787,10 +1263,10 @@ impl RoomPlanner {
             .exits_dm
             .map(|xy, dist| (255 - dist).saturating_add(2 * self.checkerboard.get(xy)));
 
-        let paths = minimal_shortest_paths_tree(
-            &cost_matrix,
-            &preference_matrix,
-            &roads_parameters
+        MinimalShortestPathsTree::new(
+            cost_matrix,
+            preference_matrix,
+            roads_parameters
                 .iter()
                 .map(|params| PathSpec {
                     sources: params.start_xys.clone(),
@@ -803,8 +1279,10 @@ impl RoomPlanner {
             sqrt_target_scaling,
             dist_tolerance,
         )
-            .ok_or(RoadConnectionFailure)?;
+    }
 
+    /// Places roads along the found paths. Returns the last tile of each path.
+    fn place_roads(&mut self, paths: Vec<Vec<RoomXY>>, roads_parameters: &[RoadParameters]) -> Vec<RoomXY> {
         for (path, params) in paths.iter().zip(roads_parameters) {
             // The first tile is source and is skipped. The last tile is skipped and reserved.
             for &xy in &path[1..path.len() - params.skipped_roads as usize] {
@@ -812,7 +1290,7 @@ impl RoomPlanner {
             }
         }
 
-        Ok(paths.into_iter().map(|path| path[path.len() - 1]).collect())
+        paths.into_iter().map(|path| path[path.len() - 1]).collect()
     }
 
     fn place_resource_storage(
@@ -874,7 +1352,7 @@ impl RoomPlanner {
                         .planned_tiles
                         .iter()
                         .filter_map(|(xy, tile)| (tile.base_part() >= BasePart::Connected).then_some(xy));
-                    let connection_dm = distance_matrix(self.walls.iter().copied(), connected);
+                    let (connection_dm, _) = distance_until(self.walls.iter().copied(), connected, once(near_controller_xy));
                     for xy in shortest_path_by_distance_matrix(&connection_dm, near_controller_xy, 1) {
                         self.planned_tiles.upgrade_base_part(xy, BasePart::Connected);
                     }
@@ -1040,7 +1518,8 @@ impl RoomPlanner {
         Ok(())
     }
 
-    fn place_towers(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Finds candidate placements of towers with a few different approaches, to be chosen from in `place_towers`.
+    fn find_tower_candidates(&mut self) -> Result<(), Box<dyn Error>> {
         let obstacles = self
             .planned_tiles
             .iter()
@@ -1080,21 +1559,31 @@ impl RoomPlanner {
             .into_iter()
             .collect::<Vec<_>>();
 
+        let profiler = self.profiler.clone();
+
+        let mut tower_damage_field = None;
+        profiler.measure_time("tower damage field", &mut || {
+            tower_damage_field = Some(TowerDamageField::new(
+                valid_tiles.iter().copied(),
+                outside_of_main_ramparts.clone(),
+            ));
+        });
+        let tower_damage_field = u!(tower_damage_field);
+
         let mut solutions = Vec::new();
 
         // We try a few approaches and select the best.
 
         // The first approach may sometimes fail and is finding the solution from pairs whose center is exactly the
         // rectangle's center.
-        measure_time("symmetric pairs tower placement", || {
+        profiler.measure_time("symmetric pairs tower placement", &mut || {
             // Top-left center or the exact center depending on parity of width/height.
-            let mut pair_top_xys = valid_tiles
-                .iter()
-                .copied()
+            // Only the tiles within the rampart bounding rectangle have a pair within it.
+            let mut pair_top_xys = valid_tiles_matrix
+                .slice(rect)
+                .find_xy(true)
                 .filter_map(|xy| {
                     if xy.y <= rect_center.y {
-                        // Mirroring can fail if the rampart bounding rectangle is small, e.g., due to not having ramparts on 2-3 sides due
-                        // to favorable terrain.
                         if let Ok(mirror_xy) = rect.mirror_xy(xy) {
                             if valid_tiles_matrix.get(mirror_xy) {
                                 // It is better if the towers are not close to the border, as it decreases the average strength.
@@ -1165,6 +1654,7 @@ impl RoomPlanner {
                 }
 
                 debug!(
+                    logger: self.logger,
                     "Best symmetric pairs {:?}.",
                     pair_top_xys
                         .iter()
@@ -1178,8 +1668,9 @@ impl RoomPlanner {
 
             for xys in solutions.iter() {
                 debug!(
+                    logger: self.logger,
                     "Symmetric pairs min damage: {}.",
-                    Self::min_tower_damage(xys, &outside_of_main_ramparts)
+                    tower_damage_field.min_damage(xys)
                 );
             }
         });
@@ -1192,8 +1683,9 @@ impl RoomPlanner {
                     if let Ok(solution) = xys.try_into() {
                         solutions.push(solution);
                         debug!(
+                            logger: planner.logger,
                             "Growth min damage: {}.",
-                            Self::min_tower_damage(&solution, &outside_of_main_ramparts)
+                            tower_damage_field.min_damage(&solution)
                         );
                     }
                 }
@@ -1201,17 +1693,17 @@ impl RoomPlanner {
         };
 
         // Second approach is growing the towers near storage.
-        measure_time("grown near storage tower placement", || {
+        profiler.measure_time("grown near storage tower placement", &mut || {
             grow(storage_xy);
         });
 
         // Third approach is growing the towers near rectangle's center.
-        measure_time("grown near center tower placement", || {
+        profiler.measure_time("grown near center tower placement", &mut || {
             grow(rect_center);
         });
 
         // Fourth approach is finding more or less evenly spread towers near ramparts.
-        measure_time("near ramparts tower placement", || {
+        profiler.measure_time("near ramparts tower placement", &mut || {
             let near_ramparts = main_ramparts_dt
                 .iter()
                 .filter_map(|(xy, dist)| {
@@ -1231,7 +1723,7 @@ impl RoomPlanner {
                         let mut solution_vec: Vec<RoomXY> = Vec::new();
                         // A total of 24 tries to find at least 6 points sufficiently far away.
                         for i in 0..30 {
-                            let xy = near_ramparts[(random() * near_ramparts.len() as f64) as usize];
+                            let xy = near_ramparts[(self.rng.random() * near_ramparts.len() as f64) as usize];
                             if solution_vec
                                 .iter()
                                 .copied()
@@ -1247,8 +1739,9 @@ impl RoomPlanner {
                         if solution_vec.len() == 6 {
                             let solution = u!(solution_vec.try_into());
                             debug!(
+                                logger: self.logger,
                                 "Near ramparts min damage: {}.",
-                                Self::min_tower_damage(&solution, &outside_of_main_ramparts)
+                                tower_damage_field.min_damage(&solution)
                             );
                             solutions.push(solution);
                             break;
@@ -1259,7 +1752,7 @@ impl RoomPlanner {
         });
 
         // Fifth approach is a greedy one.
-        measure_time("greedy tower placement", || {
+        profiler.measure_time("greedy tower placement", &mut || {
             let mut solution_vec = Vec::new();
             let mut current_damages = outside_of_main_ramparts.iter().map(|_| 0u16).collect::<Vec<_>>();
             for _ in 0..6 {
@@ -1290,116 +1783,148 @@ impl RoomPlanner {
             if solution_vec.len() == 6 {
                 let solution = u!(solution_vec.try_into());
                 debug!(
+                    logger: self.logger,
                     "Greedy min damage: {}.",
-                    Self::min_tower_damage(&solution, &outside_of_main_ramparts)
+                    tower_damage_field.min_damage(&solution)
                 );
                 solutions.push(solution);
             }
         });
 
-        // Sixth approach is genetic algorithm that tries to improve on top of what previous algorithms spewed out.
-        if !self.fast_mode {
-            measure_time("genetic algorithm tower placement", || {
-                // let mut population = Vec::new();
-                let mut population = solutions.clone();
-                for _ in 0..100 {
-                    let mut xys = [RoomXY::default(); 6];
-                    for i in 0..6 {
-                        loop {
-                            let xy = valid_tiles[(random() * valid_tiles.len() as f64) as usize];
-                            if (0..i).all(|j| xys[j] != xy) {
-                                xys[i] = xy;
-                                break;
-                            }
+        // Sixth approach is genetic algorithm that tries to improve on top of what previous algorithms spewed out. It is
+        // run one generation per stage in `evolve_towers`.
+        let mut population = Vec::new();
+        if !self.config.fast_mode {
+            population = solutions.clone();
+            for _ in 0..100 {
+                let mut xys = [RoomXY::default(); 6];
+                for i in 0..6 {
+                    loop {
+                        let xy = valid_tiles[(self.rng.random() * valid_tiles.len() as f64) as usize];
+                        if (0..i).all(|j| xys[j] != xy) {
+                            xys[i] = xy;
+                            break;
                         }
                     }
-                    population.push(xys);
                 }
+                population.push(xys);
+            }
+        }
 
-                for generation in 0..8 {
-                    measure_time("sorting", || {
-                        // TODO This is by far the most costly part of the algorithm.
-                        //      This should be improved by computing only for points which dominate other points.
-                        //      If not possible, skip half or more points.
-                        population
-                            .sort_by_key(|xys| Reverse(RoomPlanner::min_tower_damage(xys, &outside_of_main_ramparts)));
-                    });
-                    let mut new_population = Vec::new();
+        self.tower_candidates = Some(TowerCandidates {
+            valid_tiles_matrix,
+            damage_field: tower_damage_field,
+            solutions,
+            population,
+        });
 
-                    // Preserve the best.
-                    for i in 0..min(population.len(), 25) {
-                        new_population.push(population[i]);
-                    }
+        Ok(())
+    }
+
+    /// Runs a single generation of the genetic algorithm on the candidate placements of towers.
+    fn evolve_towers(&mut self, generation: u8) {
+        let profiler = self.profiler.clone();
+        let TowerCandidates {
+            valid_tiles_matrix,
+            damage_field: tower_damage_field,
+            population,
+            ..
+        } = u!(self.tower_candidates.as_mut());
+
+        profiler.measure_time("genetic algorithm tower placement", &mut || {
+            profiler.measure_time("sorting", &mut || {
+                // TODO This is still the most costly part of the algorithm.
+                //      This could be improved by computing only for points which dominate other points.
+                population.sort_by_cached_key(|xys| Reverse(tower_damage_field.min_damage(xys)));
+            });
+            let mut new_population = Vec::new();
+
+            // Preserve the best.
+            for i in 0..min(population.len(), 25) {
+                new_population.push(population[i]);
+            }
+
+            if generation % 2 == 1 {
+                profiler.measure_time("crossing", &mut || {
+                    // Cross the best, each with each.
+                    for i in 0..min(population.len(), 13) {
+                        for j in 0..min(population.len(), i) {
+                            let mut xys = population[i];
 
-                    if generation % 2 == 1 {
-                        measure_time("crossing", || {
-                            // Cross the best, each with each.
-                            for i in 0..min(population.len(), 13) {
-                                for j in 0..min(population.len(), i) {
-                                    let mut xys = population[i];
-
-                                    for k in 0..xys.len() {
-                                        if random() > 0.5 {
-                                            xys[k] = population[j][k];
-                                        }
-                                    }
-
-                                    if (0..6).all(|k| (0..k).all(|l| xys[l] != xys[k])) {
-                                        new_population.push(xys);
-                                    }
+                            for k in 0..xys.len() {
+                                if self.rng.random() > 0.5 {
+                                    xys[k] = population[j][k];
                                 }
                             }
-                        });
-                    } else {
-                        measure_time("mutating", || {
-                            // Mutate the best.
-                            for i in 0..min(population.len(), 25) {
-                                // 2.5 mutations on average, more mutations for better ones.
-                                for _ in 0..3 {
-                                    let mut xys = population[i];
-
-                                    for _ in 0..4 {
-                                        let j = (random() * 6.0) as usize;
-                                        let j_value = xys[j];
-
-                                        let new_j_value = (0..5)
-                                            .map(|_| ((random() * 4.0) as i8 + 1, (random() * 4.0) as i8 + 1))
-                                            .find_map(|offset| {
-                                                j_value.try_add_diff(offset).ok().and_then(|xy| {
-                                                    (valid_tiles_matrix.get(xy) && !xys.contains(&xy)).then_some(xy)
-                                                })
-                                            });
-
-                                        if let Some(xy) = new_j_value {
-                                            xys[j] = xy;
-                                        }
-                                    }
-
-                                    new_population.push(xys);
+
+                            if (0..6).all(|k| (0..k).all(|l| xys[l] != xys[k])) {
+                                new_population.push(xys);
+                            }
+                        }
+                    }
+                });
+            } else {
+                profiler.measure_time("mutating", &mut || {
+                    // Mutate the best.
+                    for i in 0..min(population.len(), 25) {
+                        // 2.5 mutations on average, more mutations for better ones.
+                        for _ in 0..3 {
+                            let mut xys = population[i];
+
+                            for _ in 0..4 {
+                                let j = (self.rng.random() * 6.0) as usize;
+                                let j_value = xys[j];
+
+                                let new_j_value = (0..5)
+                                    .map(|_| {
+                                        (
+                                            (self.rng.random() * 4.0) as i8 + 1,
+                                            (self.rng.random() * 4.0) as i8 + 1,
+                                        )
+                                    })
+                                    .find_map(|offset| {
+                                        j_value.try_add_diff(offset).ok().and_then(|xy| {
+                                            (valid_tiles_matrix.get(xy) && !xys.contains(&xy)).then_some(xy)
+                                        })
+                                    });
+
+                                if let Some(xy) = new_j_value {
+                                    xys[j] = xy;
                                 }
                             }
-                        });
+
+                            new_population.push(xys);
+                        }
                     }
+                });
+            }
 
-                    population = new_population
-                        .into_iter()
-                        .collect::<FxHashSet<_>>()
-                        .into_iter()
-                        .collect::<Vec<_>>();
+            *population = new_population
+                .into_iter()
+                .collect::<FxHashSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
 
-                    let best_damage = u!(population
-                        .iter()
-                        .copied()
-                        .map(|xys| (RoomPlanner::min_tower_damage(&xys, &outside_of_main_ramparts)))
-                        .max());
-                    debug!("Generation {} best damage {}", generation, best_damage);
-                }
-            });
-        }
+            let best_damage = u!(population
+                .iter()
+                .copied()
+                .map(|xys| tower_damage_field.min_damage(&xys))
+                .max());
+            debug!(logger: self.logger, "Generation {} best damage {}", generation, best_damage);
+        });
+    }
+
+    /// Places the best of the candidate towers that are reachable from the storage, along with roads to them.
+    fn place_towers(&mut self) -> Result<(), Box<dyn Error>> {
+        let TowerCandidates {
+            damage_field: tower_damage_field,
+            solutions,
+            ..
+        } = u!(self.tower_candidates.take());
 
         let mut scored_solutions = solutions
             .into_iter()
-            .map(|xys| (xys, Self::min_tower_damage(&xys, &outside_of_main_ramparts)))
+            .map(|xys| (xys, tower_damage_field.min_damage(&xys)))
             .collect::<Vec<_>>();
         scored_solutions.sort_by_key(|&(_, score)| score);
 
@@ -1409,15 +1934,31 @@ impl RoomPlanner {
                 .iter()
                 .filter_map(|(xy, dist)| (dist <= 1 || !self.planned_tiles.get(xy).is_passable(true)).then_some(xy))
                 .chain(solution.iter().copied());
-            let storage_dm = distance_matrix(obstacles, once(self.storage_xy));
-
-            if solution
-                .iter()
-                .all(|&xy| xy.around().any(|near| storage_dm.get(near) < unreachable_cost()))
-            {
-                debug!("Chosen towers with minimum damage {}: {:?}.", min_damage, solution);
+            // Only need to know whether the tiles next to the towers are reachable, so the search can stop early.
+            let near_towers = solution.iter().flat_map(|&xy| xy.around()).collect::<Vec<_>>();
+            let near_towers_dists = distance_to_targets(obstacles, once(self.storage_xy), &near_towers);
+            let mut offset = 0;
+
+            if solution.iter().all(|&xy| {
+                let near_count = xy.around().count();
+                let tower_near_dists = &near_towers_dists[offset..offset + near_count];
+                offset += near_count;
+                tower_near_dists.iter().any(|&dist| dist < unreachable_cost())
+            }) {
+                debug!(logger: self.logger, "Chosen towers with minimum damage {}: {:?}.", min_damage, solution);
                 self.min_tower_damage = min_damage;
 
+                if heatmap_selected("tower_damage") {
+                    let tower_damage = RoomMatrix::new(0u16).map(|xy, _| {
+                        if self.terrain.get(xy) == Wall {
+                            obstacle_cost::<u16>()
+                        } else {
+                            solution.iter().map(|&tower_xy| tower_attack_power(xy.dist(tower_xy))).sum()
+                        }
+                    });
+                    register_heatmap(self.room_name, "tower_damage", &tower_damage, Palette::Heat);
+                }
+
                 for xy in solution.iter().copied() {
                     self.planned_tiles
                         .replace_structure(xy, Tower, BasePart::Interior, false);
@@ -1444,14 +1985,6 @@ impl RoomPlanner {
         Err(StructurePlacementFailure.into())
     }
 
-    fn min_tower_damage(xys: &[RoomXY; 6], outside_of_main_ramparts: &[RoomXY]) -> u16 {
-        u!(outside_of_main_ramparts
-            .iter()
-            .copied()
-            .map(|xy| xys.iter().map(|&tower_xy| tower_attack_power(xy.dist(tower_xy))).sum())
-            .min())
-    }
-
     /// Uses min-cut to place ramparts around the base and outside according to `BasePart` definition.
     fn place_main_ramparts(&mut self) -> Result<(), Box<dyn Error>> {
         let interior_base_parts_dm = distance_matrix(
@@ -1491,12 +2024,14 @@ impl RoomPlanner {
             interior.iter().filter_map(|(xy, interior)| (!interior).then_some(xy)),
         )
             .map(|xy, dist| if self.terrain.get(xy) == Wall { 0 } else { dist });
+        register_heatmap(self.room_name, "interior_dm", &self.interior_dm, Palette::Heat);
 
-        debug!("Placed the main ramparts.");
+        debug!(logger: self.logger, "Placed the main ramparts.");
 
         Ok(())
     }
 
+    /// Places roads on the main ramparts and prepares connecting them with roads in `connect_rampart_roads`.
     fn place_rampart_roads(&mut self) -> Result<(), Box<dyn Error>> {
         // Placing roads on the ramparts first so that the cost of going through it is only the extra distance.
         for &xy in self.main_ramparts.iter() {
@@ -1504,17 +2039,36 @@ impl RoomPlanner {
         }
 
         // TODO does not always protect
-        self.connect_with_roads(
-            &self
-                .main_ramparts
-                .iter()
-                .map(|&xy| {
-                    RoadParameters::new(vec![self.storage_xy], xy, 0, 0, 0.5, false, BasePart::ProtectedIfInside)
-                })
-                .collect::<Vec<_>>(),
-            true,
-            1,
-        )?;
+        let roads_parameters = self
+            .main_ramparts
+            .iter()
+            .map(|&xy| RoadParameters::new(vec![self.storage_xy], xy, 0, 0, 0.5, false, BasePart::ProtectedIfInside))
+            .collect::<Vec<_>>();
+        let paths_tree = self.roads_paths_tree(&roads_parameters, true, 1);
+        self.rampart_roads = Some(RoadsConnection {
+            paths_tree,
+            roads_parameters,
+        });
+
+        Ok(())
+    }
+
+    /// Continues connecting the main ramparts with roads. Returns whether the roads were placed.
+    fn connect_rampart_roads(&mut self) -> Result<bool, Box<dyn Error>> {
+        let rampart_roads = u!(self.rampart_roads.as_mut());
+        if !rampart_roads
+            .paths_tree
+            .advance(RAMPART_ROAD_PATHS_PER_STEP)
+            .ok_or(RoadConnectionFailure)?
+        {
+            return Ok(false);
+        }
+
+        let RoadsConnection {
+            paths_tree,
+            roads_parameters,
+        } = u!(self.rampart_roads.take());
+        self.place_roads(paths_tree.into_paths(), &roads_parameters);
 
         // let obstacles = self
         //     .planned_tiles
@@ -1562,9 +2116,9 @@ impl RoomPlanner {
         //     }
         // }
 
-        debug!("Placed rampart roads.");
+        debug!(logger: self.logger, "Placed rampart roads.");
 
-        Ok(())
+        Ok(true)
     }
 
     fn place_observer(&mut self) -> Result<(), Box<dyn Error>> {
@@ -1586,7 +2140,7 @@ impl RoomPlanner {
             if let Some(xy) = observer_xy {
                 self.planned_tiles
                     .merge_structure(xy, Observer, BasePart::Interior, false)?;
-                debug!("Placed observer {} tiles from the outside.", self.interior_dm.get(xy));
+                debug!(logger: self.logger, "Placed observer {} tiles from the outside.", self.interior_dm.get(xy));
                 return Ok(());
             }
         }
@@ -1613,7 +2167,7 @@ impl RoomPlanner {
             if let Some(xy) = nuker_xy {
                 self.planned_tiles
                     .replace_structure(xy, Nuker, BasePart::Interior, false);
-                debug!("Placed nuker {} tiles from the outside.", self.interior_dm.get(xy));
+                debug!(logger: self.logger, "Placed nuker {} tiles from the outside.", self.interior_dm.get(xy));
                 return Ok(());
             }
         }
@@ -1650,6 +2204,7 @@ impl RoomPlanner {
 
     fn place_extra_ramparts(&mut self) -> Result<(), Box<dyn Error>> {
         debug!(
+            logger: self.logger,
             "Base parts:\n{}",
             self.planned_tiles.map(|xy, tile| { tile.base_part() as u8 })
         );
@@ -1658,7 +2213,7 @@ impl RoomPlanner {
             // Checking if ramparts are okay.
             let base_part = self.planned_tiles.get(xy).base_part();
             if (base_part == BasePart::Interior || base_part == BasePart::Connected) && interior_dist == 0 {
-                debug!("fail at {}, {:?}\n{}", xy, self.planned_tiles.get(xy), self.interior_dm);
+                debug!(logger: self.logger, "fail at {}, {:?}\n{}", xy, self.planned_tiles.get(xy), self.interior_dm);
                 Err(RampartPlacementFailure)?;
             }
 
@@ -1673,7 +2228,7 @@ impl RoomPlanner {
             }
         }
 
-        debug!("Placed extra ramparts.");
+        debug!(logger: self.logger, "Placed extra ramparts.");
 
         Ok(())
     }
@@ -1741,7 +2296,7 @@ impl RoomPlanner {
             .map(|xy| distance_by_matrix(&dm, xy, 2))
             .collect::<Vec<_>>();
 
-        let mineral_distance = distance_by_matrix(&dm, self.mineral_xy, 2);
+        let mineral_distance = self.mineral_xy.map(|xy| distance_by_matrix(&dm, xy, 2));
 
         let controller_distance = distance_by_matrix(&dm, self.controller_xy, 4);
 
@@ -1775,7 +2330,7 @@ impl RoomPlanner {
             // Towers build order is ordered by the distance from the storage.
             let mut tower_xys = self.planned_tiles.find_structure_xys(Tower);
             if tower_xys.len() != Tower.controller_structures(8) as usize {
-                error!("Wrong number of towers generated: {}.", tower_xys.len());
+                error!(logger: self.logger, "Wrong number of towers generated: {}.", tower_xys.len());
                 Err(StructurePlacementFailure)?;
             }
             tower_xys.sort_by_key(|&xy| distance_by_matrix(&storage_road_dm, xy, 1));
@@ -1786,7 +2341,7 @@ impl RoomPlanner {
             // First are built two central labs, then others, beginning with the closest one.
             let mut lab_xys = self.planned_tiles.find_structure_xys(Lab);
             if lab_xys.len() != Lab.controller_structures(8) as usize {
-                error!("Wrong number of labs generated: {}.", lab_xys.len());
+                error!(logger: self.logger, "Wrong number of labs generated: {}.", lab_xys.len());
                 Err(StructurePlacementFailure)?;
             }
             let labs_inner_rect = unsafe {
@@ -1829,7 +2384,7 @@ impl RoomPlanner {
             // the storage.
             let mut extension_xys = self.planned_tiles.find_structure_xys(Extension);
             if extension_xys.len() != Extension.controller_structures(8) as usize {
-                error!("Wrong number of extensions generated: {}.", extension_xys.len());
+                error!(logger: self.logger, "Wrong number of extensions generated: {}.", extension_xys.len());
                 Err(StructurePlacementFailure)?;
             }
             extension_xys.sort_by_key(|&xy| {
@@ -1842,11 +2397,21 @@ impl RoomPlanner {
             self.assign_min_rcl_from_ordering(Extension, extension_xys);
         }
 
+        // The terminal, factory and power spawn are part of the core stamp.
+        for structure_type in [Terminal, Factory, PowerSpawn] {
+            let xys = self.planned_tiles.find_structure_xys(structure_type);
+            if xys.len() != structure_type.controller_structures(8) as usize {
+                error!(logger: self.logger, "Wrong number of {:?} generated: {}.", structure_type, xys.len());
+                Err(StructurePlacementFailure)?;
+            }
+            self.assign_min_rcl_from_ordering(structure_type, xys);
+        }
+
         {
             // Nuker.
             let nuker_xys = self.planned_tiles.find_structure_xys(Nuker);
             if nuker_xys.len() != Nuker.controller_structures(8) as usize {
-                error!("Wrong number of nukers generated: {}.", nuker_xys.len());
+                error!(logger: self.logger, "Wrong number of nukers generated: {}.", nuker_xys.len());
                 Err(StructurePlacementFailure)?;
             }
             self.assign_min_rcl_from_ordering(Nuker, nuker_xys);
@@ -1856,22 +2421,23 @@ impl RoomPlanner {
             // Observer.
             let observer_xys = self.planned_tiles.find_structure_xys(Observer);
             if observer_xys.len() != Observer.controller_structures(8) as usize {
-                error!("Wrong number of observers generated: {}.", observer_xys.len());
+                error!(logger: self.logger, "Wrong number of observers generated: {}.", observer_xys.len());
                 Err(StructurePlacementFailure)?;
             }
             self.assign_min_rcl_from_ordering(Observer, observer_xys);
         }
 
-        {
+        if let Some(planned_mineral) = self.planned_mineral {
             // Mineral container.
-            self.planned_tiles.set_min_rcl(self.planned_mineral.work_xy, 6);
+            self.planned_tiles.set_min_rcl(planned_mineral.work_xy, 6);
         }
 
         {
             // Extractor.
             let extractor_xys = self.planned_tiles.find_structure_xys(Extractor);
-            if extractor_xys.len() != Extractor.controller_structures(8) as usize {
-                error!("Wrong number of extractors generated: {}.", extractor_xys.len());
+            let expected_extractors_count = self.mineral_xy.map_or(0, |_| Extractor.controller_structures(8));
+            if extractor_xys.len() != expected_extractors_count as usize {
+                error!(logger: self.logger, "Wrong number of extractors generated: {}.", extractor_xys.len());
                 Err(StructurePlacementFailure)?;
             }
             self.assign_min_rcl_from_ordering(Extractor, extractor_xys);
@@ -1928,7 +2494,7 @@ impl RoomPlanner {
                 }) {
                     // TODO It should prefer lower-RCL paths to reduce the number of false positives.
                     let path = shortest_path_by_distance_matrix(&storage_road_dm, xy, 1);
-                    debug!("Pathed a RCL {} road of length {} from {}.", min_rcl, path.len(), xy);
+                    debug!(logger: self.logger, "Pathed a RCL {} road of length {} from {}.", min_rcl, path.len(), xy);
                     for xy in path {
                         let prev_min_rcl = self.planned_tiles.get(xy).min_rcl();
                         if prev_min_rcl == 0 || prev_min_rcl > min_rcl {
@@ -2016,15 +2582,19 @@ impl Debug for RoomPlanner {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use screeps::ResourceType::Keanium;
     use screeps::Terrain::Wall;
-    use screeps::{ObjectId, RoomName, ROOM_SIZE};
-    use crate::room_planning::room_planner::RoomPlanner;
-    use crate::room_states::room_state::{ControllerData, MineralData, RoomState, SourceData};
-
-    #[test]
-    fn test_generate_some_plan() {
+    use screeps::{ObjectId, RoomName, RoomXY, StructureType, ROOM_SIZE};
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::geometry::rect::Rect;
+    use crate::global_state::binary_format::to_bytes;
+    use crate::room_planning::plan::Plan;
+    use crate::room_planning::room_planner::{PlannerCheckpoint, RoomPlanner};
+    use crate::room_states::room_state::{ControllerData, MineralData, RoomState, SourceData, StructuresMap};
+
+    pub(crate) fn test_room_state() -> RoomState {
         let mut room_state = RoomState::new(RoomName::new("W3N3").unwrap());
         room_state.sources = vec![
             SourceData::new(ObjectId::from_packed(1010), (10, 10).try_into().unwrap(), None, Vec::new(), None, None, None),
@@ -2040,6 +2610,7 @@ mod tests {
             (30, 10).try_into().unwrap(),
             None,
             None,
+            None,
             0
         ));
         room_state.terrain.set((0, 0).try_into().unwrap(), Wall);
@@ -2053,6 +2624,12 @@ mod tests {
         room_state.terrain.set((30, 10).try_into().unwrap(), Wall);
         room_state.terrain.set((30, 30).try_into().unwrap(), Wall);
 
+        room_state
+    }
+
+    #[test]
+    fn test_generate_some_plan() {
+        let room_state = test_room_state();
         let mut planner = RoomPlanner::new(&room_state, true).unwrap();
 
         for i in 0..10 {
@@ -2063,4 +2640,149 @@ mod tests {
 
         panic!("Planner did not manage to produce a plan within 10 tries.");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_plan_serialization() {
+        let room_state = test_room_state();
+        let mut planner = RoomPlanner::new(&room_state, true).unwrap();
+        let plan = (0..10).find_map(|_| planner.plan().ok()).unwrap();
+
+        let serialized_plan = serde_json::to_string(&plan).unwrap();
+        assert!(serialized_plan.len() < 6 * 1024, "the plan takes {} bytes", serialized_plan.len());
+        let deserialized_plan: Plan = serde_json::from_str(&serialized_plan).unwrap();
+        assert_eq!(deserialized_plan.tiles.data, plan.tiles.data);
+
+        let binary_size = to_bytes(&plan).unwrap().len();
+        assert!(
+            plan.approximate_serialized_size().abs_diff(binary_size) <= binary_size / 10,
+            "approximated {} bytes instead of {}",
+            plan.approximate_serialized_size(),
+            binary_size
+        );
+    }
+
+    #[test]
+    fn test_plan_contains_terminal_factory_and_power_spawn() {
+        let room_state = test_room_state();
+        let mut planner = RoomPlanner::new(&room_state, true).unwrap();
+
+        for _ in 0..10 {
+            if let Ok(plan) = planner.plan() {
+                for (structure_type, min_rcl) in [
+                    (StructureType::Terminal, 6),
+                    (StructureType::Factory, 7),
+                    (StructureType::PowerSpawn, 8),
+                ] {
+                    let xys = plan.tiles.find_structure_xys(structure_type);
+                    assert_eq!(xys.len(), 1);
+                    assert_eq!(plan.tiles.get(xys[0]).min_rcl(), min_rcl);
+                }
+
+                let formatted_plan = format!("{:?}", planner);
+                assert!(formatted_plan.contains('Y'));
+                assert!(formatted_plan.contains('F'));
+                assert!(formatted_plan.contains('P'));
+                return;
+            }
+        }
+
+        panic!("Planner did not manage to produce a plan within 10 tries.");
+    }
+
+    #[test]
+    fn test_generate_plan_without_mineral() {
+        let mut room_state = test_room_state();
+        room_state.mineral = None;
+        let mut planner = RoomPlanner::new(&room_state, true).unwrap();
+
+        for _ in 0..10 {
+            if let Ok(plan) = planner.plan() {
+                assert!(plan.mineral.is_none());
+                assert!(plan.tiles.find_structure_xys(StructureType::Extractor).is_empty());
+                return;
+            }
+        }
+
+        panic!("Planner did not manage to produce a plan within 10 tries.");
+    }
+
+    #[test]
+    fn test_plan_keeps_existing_storage() {
+        let room_state = test_room_state();
+        let storage_xy: RoomXY = (22, 24).try_into().unwrap();
+        let mut existing = StructuresMap::default();
+        existing.insert(StructureType::Storage, [storage_xy].into_iter().collect());
+        let mut planner = RoomPlanner::new_with_existing(&room_state, true, &existing).unwrap();
+
+        for _ in 0..10 {
+            if let Ok(plan) = planner.plan() {
+                assert_eq!(plan.tiles.find_structure_xys(StructureType::Storage), vec![storage_xy]);
+                assert!(plan.demolition_list.is_empty());
+                return;
+            }
+        }
+
+        panic!("Planner did not manage to produce a plan within 10 tries.");
+    }
+
+    #[test]
+    fn test_place_towers_with_ramparts_on_room_edge() {
+        let room_state = test_room_state();
+        let mut planner = RoomPlanner::new(&room_state, true).unwrap();
+
+        // The ramparts are all on the right edge of the room, so mirroring any interior tile with
+        // respect to their bounding rectangle lands outside of the room.
+        planner.main_ramparts = (15..=35).map(|y| (ROOM_SIZE - 1, y).try_into().unwrap()).collect();
+        let interior = Rect::new_unordered((35, 15).try_into().unwrap(), (ROOM_SIZE - 2, 35).try_into().unwrap());
+        planner.interior_dm = RoomMatrix::new(0);
+        for xy in interior.iter() {
+            let dist_to_outside = [xy.x.u8() - 34, ROOM_SIZE - xy.x.u8(), xy.y.u8() - 14, 36 - xy.y.u8()];
+            planner.interior_dm.set(xy, dist_to_outside.into_iter().min().unwrap());
+        }
+        planner.storage_xy = (40, 25).try_into().unwrap();
+        planner.planned_tiles = RoomMatrix::default();
+
+        planner.find_tower_candidates().unwrap();
+        planner.place_towers().unwrap();
+        assert_eq!(planner.planned_tiles.find_structure_xys(StructureType::Tower).len(), 6);
+    }
+
+    #[test]
+    fn test_restore_planner_from_checkpoint() {
+        let room_state = test_room_state();
+        let mut planner = RoomPlanner::new(&room_state, false).unwrap();
+
+        // Stopping in the middle of the third attempt.
+        while planner.tries_count < 3 || !planner.is_attempt_in_progress() {
+            planner.plan_step().ok();
+        }
+
+        let serialized_checkpoint = serde_json::to_string(&planner.checkpoint()).unwrap();
+        let checkpoint: PlannerCheckpoint = serde_json::from_str(&serialized_checkpoint).unwrap();
+        let mut restored_planner = RoomPlanner::from_checkpoint(&room_state, checkpoint).unwrap();
+
+        assert_eq!(restored_planner.seed, planner.seed);
+        assert_eq!(restored_planner.tries_count, planner.tries_count);
+        assert_eq!(restored_planner.plans_count, planner.plans_count);
+        assert_eq!(
+            restored_planner.best_plan.as_ref().map(|plan| plan.score),
+            planner.best_plan.as_ref().map(|plan| plan.score)
+        );
+
+        // The interrupted attempt is retried with the same stamps.
+        assert!(!restored_planner.is_attempt_in_progress());
+        restored_planner.plan_step().ok();
+        assert_eq!(restored_planner.tries_count, planner.tries_count + 1);
+        assert_eq!(restored_planner.core_centers_stack, planner.core_centers_stack);
+        assert_eq!(restored_planner.core_rotations_stack, planner.core_rotations_stack);
+        assert_eq!(restored_planner.labs_dists_stack, planner.labs_dists_stack);
+        assert_eq!(restored_planner.labs_top_left_corners_stack, planner.labs_top_left_corners_stack);
+        assert_eq!(restored_planner.labs_rotations_stack, planner.labs_rotations_stack);
+        assert_eq!(restored_planner.storage_xy, planner.storage_xy);
+
+        // Planning continues instead of restarting.
+        restored_planner.plan().ok();
+        assert!(restored_planner.tries_count > planner.tries_count);
+        assert!(restored_planner.core_centers_stack.len() <= planner.core_centers_stack.len());
+    }
+}
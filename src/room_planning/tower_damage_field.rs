@@ -0,0 +1,102 @@
+use screeps::RoomXY;
+use crate::consts::ROOM_AREA;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::towers::tower_attack_power;
+
+/// The lowest damage a tower deals, at `TOWER_FALLOFF_RANGE` or further.
+const MIN_TOWER_ATTACK_POWER: u16 = 150;
+
+/// Damage dealt by towers on the given tiles to each of the target tiles, precomputed so that the
+/// minimum damage of a placement of towers is only a sum of lookups.
+#[derive(Debug)]
+pub struct TowerDamageField {
+    targets: Vec<RoomXY>,
+    /// Damage to each target indexed by the index of the tower tile. Empty for tiles that were not
+    /// given as tower tiles.
+    damages: Vec<Box<[u16]>>,
+}
+
+impl TowerDamageField {
+    pub fn new(tower_xys: impl IntoIterator<Item = RoomXY>, targets: Vec<RoomXY>) -> Self {
+        let mut damages = vec![Box::default(); ROOM_AREA];
+        for tower_xy in tower_xys {
+            damages[tower_xy.to_index()] = targets
+                .iter()
+                .map(|&target_xy| tower_attack_power(tower_xy.dist(target_xy)))
+                .collect();
+        }
+        TowerDamageField { targets, damages }
+    }
+
+    /// The minimum over the targets of the total damage dealt by the towers. Towers on tiles outside
+    /// of the field are computed directly.
+    pub fn min_damage(&self, xys: &[RoomXY; 6]) -> u16 {
+        let rows = xys.map(|xy| &self.damages[xy.to_index()]);
+        if rows.iter().any(|row| row.len() != self.targets.len()) {
+            return min_tower_damage(xys, &self.targets);
+        }
+
+        let floor = xys.len() as u16 * MIN_TOWER_ATTACK_POWER;
+        let mut result = u16::MAX;
+        for i in 0..self.targets.len() {
+            let damage = rows.iter().map(|row| row[i]).sum();
+            if damage < result {
+                result = damage;
+                // No target may take less damage than this.
+                if result == floor {
+                    break;
+                }
+            }
+        }
+        result
+    }
+}
+
+/// The minimum over the targets of the total damage dealt by the towers, computed directly.
+pub fn min_tower_damage(xys: &[RoomXY; 6], targets: &[RoomXY]) -> u16 {
+    targets
+        .iter()
+        .map(|&xy| xys.iter().map(|&tower_xy| tower_attack_power(xy.dist(tower_xy))).sum())
+        .min()
+        .unwrap_or(u16::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::RoomXY;
+    use crate::room_planning::tower_damage_field::{min_tower_damage, TowerDamageField, MIN_TOWER_ATTACK_POWER};
+    use crate::towers::tower_attack_power;
+    use crate::utils::random::{RandomSource, SeededRandom};
+
+    fn random_xy(rng: &mut SeededRandom) -> RoomXY {
+        ((rng.random() * 50.0) as u8, (rng.random() * 50.0) as u8).try_into().unwrap()
+    }
+
+    #[test]
+    fn test_min_damage_equals_direct_computation() {
+        let mut rng = SeededRandom::new(7);
+        let tower_xys = (0..60).map(|_| random_xy(&mut rng)).collect::<Vec<_>>();
+        let targets = (0..150).map(|_| random_xy(&mut rng)).collect::<Vec<_>>();
+        let field = TowerDamageField::new(tower_xys.iter().copied(), targets.clone());
+
+        for _ in 0..200 {
+            let xys = [(); 6].map(|_| tower_xys[(rng.random() * tower_xys.len() as f64) as usize]);
+            assert_eq!(field.min_damage(&xys), min_tower_damage(&xys, &targets));
+        }
+
+        // Towers outside of the field.
+        let xys = [(); 6].map(|_| random_xy(&mut rng));
+        assert_eq!(field.min_damage(&xys), min_tower_damage(&xys, &targets));
+    }
+
+    #[test]
+    fn test_min_damage_at_the_floor() {
+        assert_eq!(tower_attack_power(u8::MAX), MIN_TOWER_ATTACK_POWER);
+
+        let xys = [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)].map(|xy| xy.try_into().unwrap());
+        let targets = [(49, 49), (1, 2), (30, 30)].map(|xy| xy.try_into().unwrap()).to_vec();
+        let field = TowerDamageField::new(xys, targets.clone());
+        assert_eq!(field.min_damage(&xys), 6 * MIN_TOWER_ATTACK_POWER);
+        assert_eq!(min_tower_damage(&xys, &targets), 6 * MIN_TOWER_ATTACK_POWER);
+    }
+}
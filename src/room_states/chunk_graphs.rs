@@ -0,0 +1,85 @@
+use crate::algorithms::chunk_graph::{chunk_graph, ChunkGraph};
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::consts::OBSTACLE_COST;
+use rustc_hash::FxHashMap;
+use screeps::RoomName;
+use std::cell::RefCell;
+use std::ops::DerefMut;
+
+/// The maximum number of changed tiles for which the cached chunk graph is updated. With more
+/// changes, it is computed from scratch, since the old chunk centers may no longer fit the room.
+const MAX_INCREMENTAL_CHANGES: usize = 100;
+
+pub type ChunkGraphs = FxHashMap<RoomName, ChunkGraph>;
+
+thread_local! {
+    static CHUNK_GRAPHS: RefCell<ChunkGraphs> = RefCell::new(FxHashMap::default());
+}
+
+pub fn with_chunk_graphs<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ChunkGraphs) -> R,
+{
+    CHUNK_GRAPHS.with(|chunk_graphs| f(chunk_graphs.borrow_mut().deref_mut()))
+}
+
+/// Runs the function on the chunk graph of the room with given obstacles matrix. The cached chunk
+/// graph is updated incrementally when only a few obstacles changed since it was computed.
+pub fn with_chunk_graph<F, R>(room_name: RoomName, obstacles: &RoomMatrix<u8>, chunk_radius: u8, f: F) -> R
+where
+    F: FnOnce(&ChunkGraph) -> R,
+{
+    with_chunk_graphs(|chunk_graphs| {
+        let changed_tiles = chunk_graphs
+            .get(&room_name)
+            .filter(|chunks| chunks.chunk_radius == chunk_radius)
+            .map(|chunks| {
+                obstacles
+                    .iter()
+                    .filter(|&(xy, value)| value != chunks.obstacles().get(xy))
+                    .map(|(xy, value)| (xy, value == OBSTACLE_COST))
+                    .collect::<Vec<_>>()
+            });
+
+        match changed_tiles {
+            Some(changed_tiles) if changed_tiles.len() <= MAX_INCREMENTAL_CHANGES => {
+                let chunks = chunk_graphs.get_mut(&room_name).unwrap();
+                if !changed_tiles.is_empty() {
+                    chunks.update(&changed_tiles);
+                }
+                f(chunks)
+            }
+            _ => f(chunk_graphs
+                .entry(room_name)
+                .insert_entry(chunk_graph(obstacles, chunk_radius))
+                .get()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::consts::OBSTACLE_COST;
+    use crate::room_states::chunk_graphs::{with_chunk_graph, with_chunk_graphs};
+    use screeps::RoomName;
+
+    #[test]
+    fn test_chunk_graph_cache() {
+        let room_name = RoomName::new("W1N1").unwrap();
+        let mut obstacles = RoomMatrix::new(0);
+        let centers = with_chunk_graph(room_name, &obstacles, 5, |chunks| chunks.graph.node_weights().copied().collect::<Vec<_>>());
+
+        // Covering one of the centers removes its chunk while keeping the others.
+        obstacles.set(centers[0], OBSTACLE_COST);
+        let updated_centers = with_chunk_graph(room_name, &obstacles, 5, |chunks| chunks.graph.node_weights().copied().collect::<Vec<_>>());
+        assert_eq!(updated_centers, centers[1..]);
+
+        // A different chunk radius requires computing the chunk graph from scratch.
+        let chunk_radius = with_chunk_graph(room_name, &obstacles, 4, |chunks| chunks.chunk_radius);
+        assert_eq!(chunk_radius, 4);
+        assert_eq!(with_chunk_graphs(|chunk_graphs| chunk_graphs.len()), 1);
+    }
+}
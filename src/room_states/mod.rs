@@ -1,7 +1,10 @@
+pub mod chunk_graphs;
 pub mod packed_terrain;
 pub mod room_states;
 pub mod scan_room;
 pub mod scan_rooms;
+pub mod scout_rooms;
 pub mod utils;
 pub mod room_state;
-pub mod conversion;
\ No newline at end of file
+pub mod conversion;
+pub mod room_lifecycle;
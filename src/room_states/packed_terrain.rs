@@ -6,6 +6,10 @@ use screeps::Terrain::{Plain, Swamp, Wall};
 use screeps::{RoomTerrain, RoomXY, Terrain, ROOM_SIZE};
 use std::fmt::{Display, Formatter};
 use crate::algorithms::weighted_distance_matrix::obstacle_cost;
+use crate::errors::XiError;
+use crate::errors::XiError::PackedTerrainDecodeFailed;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 
 pub const PACKED_TERRAIN_DATA_SIZE: usize = ROOM_AREA / 4;
 
@@ -39,6 +43,17 @@ impl PackedTerrain {
         self.data[index / 4] |= (terrain as u8) << offset;
     }
 
+    /// Encodes the terrain in base64, e.g., to be saved as a test fixture.
+    pub fn to_base64(self) -> String {
+        STANDARD.encode(self.data)
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, XiError> {
+        let decoded = STANDARD.decode(encoded.trim()).map_err(|_| PackedTerrainDecodeFailed)?;
+        let data = decoded.try_into().map_err(|_| PackedTerrainDecodeFailed)?;
+        Ok(PackedTerrain { data })
+    }
+
     pub fn walls(&self) -> impl Iterator<Item = RoomXY> + '_ {
         self.iter().filter_map(|(xy, t)| (t == Wall).then_some(xy))
     }
@@ -182,6 +197,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_base64_round_trip() {
+        let mut terrain = PackedTerrain::new();
+        terrain.set((0, 0).try_into().unwrap(), Wall);
+        terrain.set((25, 13).try_into().unwrap(), Swamp);
+        terrain.set((ROOM_SIZE - 1, ROOM_SIZE - 1).try_into().unwrap(), Wall);
+        let decoded = PackedTerrain::from_base64(&terrain.to_base64()).unwrap();
+        assert_eq!(decoded.data, terrain.data);
+        assert!(PackedTerrain::from_base64("AAAA").is_err());
+    }
+
     #[test]
     fn test_iter() {
         let mut terrain = PackedTerrain::new();
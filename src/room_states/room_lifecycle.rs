@@ -0,0 +1,184 @@
+use log::info;
+use rustc_hash::FxHashMap;
+use screeps::{RawObjectId, StructureType};
+use serde::{Deserialize, Serialize};
+use crate::hauling::requests::HaulRequestKind;
+use crate::hauling::requests::HaulRequestKind::{DepositRequest, WithdrawRequest};
+use crate::hauling::scheduling_hauls::retain_haul_requests;
+use crate::priorities::BOOTSTRAP_SPAWN_PRIORITY;
+use crate::room_states::room_state::RoomState;
+use crate::utils::priority::Priority;
+use RoomLifecycle::*;
+use RoomLifecycleEvent::*;
+
+/// The stage of life of an owned room, deciding what the room may spend its resources on.
+#[derive(Deserialize, Serialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RoomLifecycle {
+    /// The room is maintained normally.
+    #[default]
+    Active,
+    /// The room is about to be abandoned. Its resources are moved to the terminal to be sent out.
+    Evacuating,
+    /// All spawns were lost. Only the most important creeps and structures are allowed.
+    Rebuilding,
+}
+
+/// An event that may change the lifecycle of a room.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RoomLifecycleEvent {
+    EvacuationOrdered,
+    ActivationOrdered,
+    AllSpawnsLost,
+    SpawnRebuilt,
+}
+
+/// Allowed transitions between the lifecycle states. Events not listed here are ignored.
+const TRANSITIONS: [(RoomLifecycle, RoomLifecycleEvent, RoomLifecycle); 5] = [
+    (Active, EvacuationOrdered, Evacuating),
+    (Active, AllSpawnsLost, Rebuilding),
+    (Rebuilding, EvacuationOrdered, Evacuating),
+    (Rebuilding, SpawnRebuilt, Active),
+    (Evacuating, ActivationOrdered, Active),
+];
+
+/// Structures that may be built while rebuilding the room.
+const CRITICAL_STRUCTURES: [StructureType; 3] = [
+    StructureType::Spawn,
+    StructureType::Extension,
+    StructureType::Tower,
+];
+
+/// Haul requests allowed while evacuating the room, i.e., ones moving resources from the storage
+/// to the terminal.
+const EVACUATION_HAULS: [(HaulRequestKind, StructureType); 2] = [
+    (WithdrawRequest, StructureType::Storage),
+    (DepositRequest, StructureType::Terminal),
+];
+
+impl RoomLifecycle {
+    /// The state after given event or `None` if the event does not change the state.
+    pub fn transition(self, event: RoomLifecycleEvent) -> Option<RoomLifecycle> {
+        TRANSITIONS
+            .iter()
+            .find(|&&(from, transition_event, _)| from == self && transition_event == event)
+            .map(|&(_, _, to)| to)
+    }
+
+    /// Whether a creep with given spawn priority may be spawned in the room.
+    pub fn allows_spawn(self, priority: Priority) -> bool {
+        match self {
+            Active => true,
+            Evacuating => false,
+            Rebuilding => priority >= BOOTSTRAP_SPAWN_PRIORITY,
+        }
+    }
+
+    /// Whether a structure of given type may be built in the room.
+    pub fn allows_construction(self, structure_type: StructureType) -> bool {
+        match self {
+            Active => true,
+            Evacuating => false,
+            Rebuilding => CRITICAL_STRUCTURES.contains(&structure_type),
+        }
+    }
+
+    /// Whether a haul request of given kind may target a structure of given type in the room.
+    /// `None` stands for a target that is not a structure, e.g., a creep or a resource pile.
+    pub fn allows_haul(self, kind: HaulRequestKind, structure_type: Option<StructureType>) -> bool {
+        match self {
+            Active | Rebuilding => true,
+            Evacuating => structure_type.is_some_and(|structure_type| EVACUATION_HAULS.contains(&(kind, structure_type))),
+        }
+    }
+}
+
+/// Applies given event to the lifecycle of the room, logging the change if there is any.
+pub fn apply_room_lifecycle_event(room_state: &mut RoomState, event: RoomLifecycleEvent) {
+    if let Some(lifecycle) = room_state.lifecycle.transition(event) {
+        info!(
+            "Room {} changed its lifecycle from {:?} to {:?} after {:?}.",
+            room_state.room_name, room_state.lifecycle, lifecycle, event
+        );
+        room_state.lifecycle = lifecycle;
+    }
+}
+
+/// Removes the construction sites from the queue and haul requests of the room that are not
+/// allowed in its current lifecycle state.
+pub fn enforce_room_lifecycle(room_state: &mut RoomState) {
+    let lifecycle = room_state.lifecycle;
+    if lifecycle == Active {
+        return;
+    }
+
+    room_state
+        .construction_site_queue
+        .retain(|cs| lifecycle.allows_construction(cs.structure_type));
+
+    let structure_types = room_state
+        .structures
+        .iter()
+        .flat_map(|(&structure_type, structures)| {
+            structures
+                .values()
+                .map(move |&id| (RawObjectId::from(id), structure_type))
+        })
+        .collect::<FxHashMap<_, _>>();
+
+    retain_haul_requests(room_state.room_name, |request| {
+        lifecycle.allows_haul(request.kind, structure_types.get(&request.target).copied())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::StructureType;
+    use crate::hauling::requests::HaulRequestKind::{DepositRequest, WithdrawRequest};
+    use crate::priorities::BOOTSTRAP_SPAWN_PRIORITY;
+    use crate::room_states::room_lifecycle::RoomLifecycle::{Active, Evacuating, Rebuilding};
+    use crate::room_states::room_lifecycle::RoomLifecycleEvent::{ActivationOrdered, AllSpawnsLost, EvacuationOrdered, SpawnRebuilt};
+    use crate::utils::priority::Priority;
+
+    #[test]
+    fn test_transitions() {
+        assert_eq!(Active.transition(AllSpawnsLost), Some(Rebuilding));
+        assert_eq!(Rebuilding.transition(SpawnRebuilt), Some(Active));
+        assert_eq!(Active.transition(EvacuationOrdered), Some(Evacuating));
+        assert_eq!(Rebuilding.transition(EvacuationOrdered), Some(Evacuating));
+        assert_eq!(Evacuating.transition(ActivationOrdered), Some(Active));
+
+        // Losing spawns or rebuilding them does not stop the evacuation.
+        assert_eq!(Evacuating.transition(AllSpawnsLost), None);
+        assert_eq!(Evacuating.transition(SpawnRebuilt), None);
+        assert_eq!(Active.transition(SpawnRebuilt), None);
+        assert_eq!(Active.transition(ActivationOrdered), None);
+    }
+
+    #[test]
+    fn test_allowed_spawns() {
+        let low_priority = Priority(BOOTSTRAP_SPAWN_PRIORITY.0 - 1);
+        assert!(Active.allows_spawn(low_priority));
+        assert!(Rebuilding.allows_spawn(BOOTSTRAP_SPAWN_PRIORITY));
+        assert!(!Rebuilding.allows_spawn(low_priority));
+        assert!(!Evacuating.allows_spawn(BOOTSTRAP_SPAWN_PRIORITY));
+    }
+
+    #[test]
+    fn test_allowed_construction() {
+        assert!(Active.allows_construction(StructureType::Road));
+        assert!(Rebuilding.allows_construction(StructureType::Spawn));
+        assert!(!Rebuilding.allows_construction(StructureType::Road));
+        assert!(!Evacuating.allows_construction(StructureType::Spawn));
+    }
+
+    #[test]
+    fn test_allowed_hauls() {
+        assert!(Active.allows_haul(DepositRequest, None));
+        assert!(Rebuilding.allows_haul(WithdrawRequest, Some(StructureType::Container)));
+        assert!(Evacuating.allows_haul(WithdrawRequest, Some(StructureType::Storage)));
+        assert!(Evacuating.allows_haul(DepositRequest, Some(StructureType::Terminal)));
+        assert!(!Evacuating.allows_haul(DepositRequest, Some(StructureType::Storage)));
+        assert!(!Evacuating.allows_haul(WithdrawRequest, Some(StructureType::Terminal)));
+        assert!(!Evacuating.allows_haul(DepositRequest, None));
+    }
+}
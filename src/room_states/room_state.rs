@@ -1,6 +1,7 @@
+use std::collections::VecDeque;
 use std::iter::{Flatten, Map};
 use std::option::IntoIter;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use derive_more::Constructor;
 use screeps::{
     game,
@@ -15,6 +16,8 @@ use screeps::{
     Structure,
     StructureContainer,
     StructureController,
+    StructureExtension,
+    StructureInvaderCore,
     StructureLink,
     StructureType,
     Terrain,
@@ -27,15 +30,20 @@ use js_sys::{Object, Reflect};
 use crate::algorithms::matrix_common::MatrixCommon;
 use crate::algorithms::room_matrix::RoomMatrix;
 use crate::construction::place_construction_sites::ConstructionSiteData;
+use crate::economy::upgrade_allocation::FULL_UPGRADE_ALLOCATION;
+use crate::room_states::room_lifecycle::RoomLifecycle;
 use crate::construction::triage_repair_sites::{StructureToRepair, TriagedRepairSites};
 use crate::creeps::creeps::CreepRef;
+use crate::defense::incidents::IncidentSummary;
+use crate::defense::threat::ThreatReport;
 use crate::economy::room_eco_config::RoomEcoConfig;
 use crate::economy::room_eco_stats::RoomEcoStats;
 use crate::geometry::room_xy::RoomXYUtils;
 use crate::kernel::broadcast::Broadcast;
 use crate::room_planning::packed_tile_structures::PackedTileStructures;
 use crate::room_planning::plan::Plan;
-use crate::room_planning::room_planner::RoomPlanner;
+use crate::room_planning::remote_planner::RemotePlan;
+use crate::room_planning::room_planner::{PlannerCheckpoint, RoomPlanner};
 use crate::room_states::packed_terrain::PackedTerrain;
 use crate::travel::surface::Surface;
 use crate::u;
@@ -60,10 +68,23 @@ pub struct RoomState {
     #[serde(skip)]
     pub structures_matrix: RoomMatrix<PackedTileStructures>,
     pub plan: Option<Plan>,
-    #[serde(skip)]
+    /// The planner is persisted as a checkpoint, which is then restored by `plan_rooms`.
+    #[serde(rename = "planner_checkpoint", serialize_with = "serialize_planner", skip_deserializing)]
     pub planner: Option<Box<RoomPlanner>>,
+    /// Checkpoint of the planner loaded from the memory, to be restored into `planner`.
+    #[serde(skip_serializing)]
+    pub planner_checkpoint: Option<PlannerCheckpoint>,
     /// Structures to be built at current RCL.
     pub current_rcl_structures: StructuresMap,
+    /// Positions of structures from `current_rcl_structures` of each type in the order they should be built.
+    pub current_rcl_build_order: FxHashMap<StructureType, Vec<RoomXY>>,
+    /// Structures from `current_rcl_structures` that were destroyed recently along with the tick it was noticed,
+    /// to be rebuilt before new structures.
+    #[serde(skip)]
+    pub recently_destroyed: Vec<(StructureType, RoomXY, u32)>,
+    /// Number of steps of creeps on each tile, decaying over time. Used to decide which planned roads to build.
+    #[serde(skip)]
+    pub road_usage: RoomMatrix<u16>,
     #[serde(skip)]
     pub extra_construction_sites: Vec<ConstructionSiteData>,
     #[serde(skip)]
@@ -78,7 +99,7 @@ pub struct RoomState {
     // pub outer_extensions: Option<Vec<Extension>>,
     /// Broadcast signalled each time the set of structures in the room changes.
     #[serde(skip)]
-    pub structures_broadcast: Broadcast<()>,
+    pub structures_broadcast: Broadcast<StructuresChange>,
     #[serde(skip)]
     pub resources: RoomResources,
     #[serde(skip)]
@@ -87,6 +108,56 @@ pub struct RoomState {
     pub eco_stats: Option<RoomEcoStats>,
     #[serde(skip)]
     pub eco_config: Option<RoomEcoConfig>,
+    /// Summaries of the most recent incidents in the room, the last one being the newest.
+    pub recent_incidents: VecDeque<IncidentSummary>,
+    /// Percentage of the energy income the room may use for upgrading the controller.
+    #[serde(skip, default = "full_upgrade_allocation")]
+    pub upgrade_allocation: u8,
+    pub lifecycle: RoomLifecycle,
+    /// Center of the core the room plan is forced to use, set by the user with a flag.
+    pub planner_anchor: Option<RoomXY>,
+    /// Plans of mining adjacent rooms, computed once the room has a plan.
+    pub remote_plans: FxHashMap<RoomName, RemotePlan>,
+    /// Tombstones and ruins with resources in them.
+    #[serde(skip)]
+    pub remains: Vec<RemainsData>,
+    /// Extensions in the room along with the energy missing in them, ordered by their position.
+    #[serde(skip)]
+    pub extensions: Vec<ExtensionData>,
+    /// Number of towers that attacked hostiles in the room in the current tick.
+    #[serde(skip)]
+    pub tower_attacks: u32,
+    /// The latest assessment of the threat posed by hostiles in the room.
+    #[serde(skip)]
+    pub threat_report: ThreatReport,
+    /// Broadcast signalled each time the threat report changes.
+    #[serde(skip)]
+    pub threat_broadcast: Broadcast<ThreatReport>,
+    /// The tick in which the bot last activated safe mode in the room.
+    pub safe_mode_activation_tick: Option<u32>,
+    /// The invader core in the room as of the last scan.
+    #[serde(skip)]
+    pub invader_core: Option<InvaderCoreData>,
+    /// Remotes of the room where hauling is paused due to an invader core in them.
+    #[serde(skip)]
+    pub paused_remotes: FxHashSet<RoomName>,
+    /// The reservation of the controller as of the last scan.
+    pub reservation: Option<ReservationData>,
+    /// Whether there were structures owned by other players or invaders in the room as of the last scan.
+    pub hostile_structures: bool,
+    /// The tick in which the room was last scanned, `None` if it never was.
+    pub last_scan_tick: Option<u32>,
+}
+
+fn serialize_planner<S>(planner: &Option<Box<RoomPlanner>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    planner.as_ref().map(|planner| planner.checkpoint()).serialize(serializer)
+}
+
+fn full_upgrade_allocation() -> u8 {
+    FULL_UPGRADE_ALLOCATION
 }
 
 #[derive(Deserialize, Serialize, Copy, Clone, Eq, PartialEq, Debug)]
@@ -95,6 +166,7 @@ pub enum RoomDesignation {
     NotOwned,
     Enemy,
     Invader,
+    SourceKeeper,
     Portal,
     Highway
 }
@@ -105,6 +177,7 @@ pub struct ControllerData {
     pub xy: RoomXY,
     pub work_xy: Option<RoomXY>,
     pub link_xy: Option<RoomXY>,
+    pub link_id: Option<ObjectId<StructureLink>>,
     pub downgrade_tick: u32,
 }
 
@@ -128,8 +201,59 @@ pub struct MineralData {
     pub mineral_type: ResourceType,
 }
 
+/// A tombstone or ruin with resources that can be looted before it decays.
+#[derive(Clone, Debug)]
+pub struct RemainsData {
+    pub id: RawObjectId,
+    pub xy: RoomXY,
+    /// The tick in which the remains decay along with the resources in them.
+    pub decay_tick: u32,
+    pub store: FxHashMap<ResourceType, u32>,
+}
+
+/// Reservation of a controller by a player or invaders.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ReservationData {
+    pub username: String,
+    /// The tick in which the reservation ends unless renewed.
+    pub end_tick: u32,
+}
+
+/// An invader core, reserving the room at level 0 or being a stronghold at higher levels.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct InvaderCoreData {
+    pub id: ObjectId<StructureInvaderCore>,
+    pub xy: RoomXY,
+    pub level: u8,
+    /// The tick in which the core collapses on its own. `None` if it is not collapsing yet, e.g.,
+    /// while it is still being deployed.
+    pub collapse_tick: Option<u32>,
+}
+
+impl InvaderCoreData {
+    /// Whether the core is still there in given tick, assuming it is not destroyed before that.
+    pub fn is_present(&self, tick: u32) -> bool {
+        self.collapse_tick.is_none_or(|collapse_tick| collapse_tick > tick)
+    }
+}
+
+/// An extension and the energy missing in it as of the last scan.
+#[derive(Copy, Clone, Debug)]
+pub struct ExtensionData {
+    pub id: ObjectId<StructureExtension>,
+    pub xy: RoomXY,
+    pub missing_energy: u32,
+}
+
 pub type StructuresMap = FxHashMap<StructureType, FxHashSet<RoomXY>>;
 
+/// Structures added and removed in a room between two scans, ordered by structure type and position.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StructuresChange {
+    pub added: Vec<(StructureType, RoomXY)>,
+    pub removed: Vec<(StructureType, RoomXY)>,
+}
+
 #[derive(Default, Clone, Debug)]
 pub struct RoomResources {
     pub spawn_energy: u32,
@@ -181,10 +305,14 @@ impl RoomState {
             sources: Vec::new(),
             mineral: None,
             current_rcl_structures: FxHashMap::default(),
+            current_rcl_build_order: FxHashMap::default(),
+            recently_destroyed: Vec::new(),
+            road_usage: RoomMatrix::default(),
             structures: FxHashMap::default(),
             structures_matrix: RoomMatrix::default(),
             plan: None,
             planner: None,
+            planner_checkpoint: None,
             extra_construction_sites: Vec::new(),
             construction_site_queue: Vec::new(),
             structures_to_repair: FxHashMap::default(),
@@ -194,6 +322,22 @@ impl RoomState {
             essential_creeps: None,
             eco_stats: None,
             eco_config: None,
+            recent_incidents: VecDeque::new(),
+            upgrade_allocation: FULL_UPGRADE_ALLOCATION,
+            lifecycle: RoomLifecycle::Active,
+            planner_anchor: None,
+            remote_plans: FxHashMap::default(),
+            remains: Vec::new(),
+            extensions: Vec::new(),
+            tower_attacks: 0,
+            threat_report: ThreatReport::default(),
+            threat_broadcast: Broadcast::default(),
+            safe_mode_activation_tick: None,
+            invader_core: None,
+            paused_remotes: FxHashSet::default(),
+            reservation: None,
+            hostile_structures: false,
+            last_scan_tick: None,
         }
     }
 
@@ -3,6 +3,7 @@ use screeps::RoomName;
 use std::cell::RefCell;
 use std::ops::DerefMut;
 use crate::room_states::room_state::{RoomDesignation, RoomState};
+use crate::utils::game_tick::game_tick;
 #[cfg(test)]
 use crate::room_states::room_state::empty_unowned_room_state;
 
@@ -26,6 +27,27 @@ where
     ROOM_STATES.with(|states| states.borrow_mut().get_mut(&room_name).map(f))
 }
 
+/// Runs the function on the state of the room if the room was scanned at least once.
+pub fn with_scouted_room<F, R>(room_name: RoomName, f: F) -> Option<R>
+where
+    F: FnOnce(&RoomState) -> R,
+{
+    ROOM_STATES.with(|states| {
+        states
+            .borrow()
+            .get(&room_name)
+            .filter(|room_state| room_state.last_scan_tick.is_some())
+            .map(f)
+    })
+}
+
+/// Whether the room was never scanned or the last scan is older than given number of ticks.
+pub fn needs_scan(room_name: RoomName, max_age: u32) -> bool {
+    with_scouted_room(room_name, |room_state| room_state.last_scan_tick)
+        .flatten()
+        .is_none_or(|last_scan_tick| game_tick().saturating_sub(last_scan_tick) > max_age)
+}
+
 pub fn map_and_replace_room_state<F, R>(room_name: RoomName, mut f: F) -> R
 where
     F: FnMut(&mut RoomState) -> R,
@@ -73,4 +95,26 @@ pub fn test_room_states() -> RoomStates {
     ];
     
     room_states.into_iter().map(|room_state| (room_state.room_name, room_state)).collect()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use screeps::RoomName;
+    use crate::room_states::room_state::RoomState;
+    use crate::room_states::room_states::{needs_scan, with_room_states, with_scouted_room};
+    use crate::utils::game_tick::game_tick;
+
+    #[test]
+    fn test_needs_scan() {
+        let room_name = RoomName::new("W5N3").unwrap();
+        assert!(needs_scan(room_name, 100));
+
+        // A room state that exists, but was never scanned.
+        with_room_states(|room_states| room_states.insert(room_name, RoomState::new(room_name)));
+        assert!(needs_scan(room_name, 100));
+        assert_eq!(with_scouted_room(room_name, |room_state| room_state.room_name), None);
+
+        with_room_states(|room_states| room_states.get_mut(&room_name).unwrap().last_scan_tick = Some(game_tick()));
+        assert!(!needs_scan(room_name, 0));
+        assert_eq!(with_scouted_room(room_name, |room_state| room_state.room_name), Some(room_name));
+    }
+}
@@ -2,19 +2,29 @@ use log::debug;
 use crate::room_states::room_states::map_and_replace_room_state;
 use crate::{local_debug, u};
 use rustc_hash::FxHashMap;
-use screeps::{find, game, HasId, HasPosition, Mineral, ObjectId, OwnedStructureProperties, Position, ResourceType, RoomName, Source, StructureController};
+use js_sys::Reflect;
+use screeps::{find, game, HasId, HasPosition, HasStore, Mineral, NaturalEffectType, ObjectId, OwnedStructureProperties, Position, RawObjectId, ResourceType, RoomName, RoomObjectProperties, RoomXY, Source, Structure, StructureController, StructureInvaderCore, StructureObject, StructureType};
 use screeps::ResourceType::Energy;
 use screeps::Terrain::Wall;
+use screeps::StructureType::{Container, Link, Spawn};
 use crate::construction::triage_repair_sites::StructureToRepair;
 use crate::economy::room_eco_stats::RoomEcoStats;
 use crate::errors::XiError;
 use crate::geometry::room_xy::RoomXYUtils;
-use crate::room_states::room_state::{ControllerData, MineralData, RoomDesignation, RoomResources, RoomState, SourceData};
+use crate::hauling::transfers::{get_free_capacity_with_object, get_used_capacities_with_object};
+use crate::hauling::transfers::TransferStage::AfterAllTransfers;
+use crate::room_states::room_lifecycle::apply_room_lifecycle_event;
+use crate::room_states::room_lifecycle::RoomLifecycleEvent::{AllSpawnsLost, SpawnRebuilt};
+use crate::room_states::room_state::{ControllerData, ExtensionData, InvaderCoreData, MineralData, RemainsData, ReservationData, RoomDesignation, RoomResources, RoomState, SourceData, StructuresChange};
+use crate::travel::route::is_source_keeper_room;
 use crate::utils::game_tick::game_tick;
 use crate::utils::multi_map_utils::MultiMapUtils;
 
 const DEBUG: bool = true;
 
+/// Number of ticks a destroyed structure is prioritized when rebuilding.
+const RECENTLY_DESTROYED_TICKS: u32 = 1500;
+
 /// Updates the state of given room, i.e., records the terrain, structures, resources and other
 /// data. Fails if the room is not visible.
 pub fn scan_room(room_name: RoomName, force_update: bool) -> Result<(), XiError> {
@@ -29,30 +39,42 @@ pub fn update_room_state_from_scan(room_name: RoomName, force_update: bool, stat
         Some(room) => room,
         None => Err(XiError::RoomVisibilityError)?,
     };
-    if let Some(controller) = room.controller() {
+    let structure_objs = room.find(find::STRUCTURES, None);
+    let controller = room.controller();
+    let features = RoomFeatures {
+        controller: controller.is_some(),
+        my_controller: controller.as_ref().is_some_and(|controller| controller.my()),
+        foreign_controller: controller.as_ref().is_some_and(|controller| !controller.my() && controller.owner().is_some()),
+        portal: structure_objs.iter().any(|structure_obj| matches!(structure_obj, StructureObject::StructurePortal(_))),
+        invader_core: structure_objs.iter().any(|structure_obj| matches!(structure_obj, StructureObject::StructureInvaderCore(_))),
+    };
+    state.designation = classify_room(room_name, features);
+    state.last_scan_tick = Some(game_tick());
+    state.reservation = None;
+    if let Some(controller) = controller {
         state.rcl = controller.level();
         let id: ObjectId<StructureController> = controller.id();
         let pos: Position = controller.pos();
         let mut work_xy = None;
-        let link_xy = None; // TODO This requires information if the link and core have been constructed.
         if let Some(owner) = controller.owner() {
             state.owner = owner.username();
-            if controller.my() {
-                state.designation = RoomDesignation::Owned;
-                
-                if let Some(plan) = state.plan.as_ref() {
-                    // TODO How about not at RCL8? Is it the same work_xy?
-                    work_xy = Some(plan.controller.work_xy);
-                }
-            } else {
-                state.designation = RoomDesignation::NotOwned;
+        }
+        if controller.my() {
+            if let Some(plan) = state.plan.as_ref() {
+                // TODO How about not at RCL8? Is it the same work_xy?
+                work_xy = Some(plan.controller.work_xy);
             }
         }
+        state.reservation = controller.reservation().map(|reservation| ReservationData {
+            username: reservation.username(),
+            end_tick: game_tick() + reservation.ticks_to_end(),
+        });
         state.controller = Some(ControllerData {
             id,
             xy: pos.xy(),
             work_xy,
-            link_xy,
+            link_xy: None,
+            link_id: None,
             downgrade_tick: game_tick() + controller.ticks_to_downgrade().unwrap_or(0)
         });
     };
@@ -72,7 +94,6 @@ pub fn update_room_state_from_scan(room_name: RoomName, force_update: bool, stat
         let drop_mining_xys = (state.designation == RoomDesignation::Owned).then(|| {
             xy.around().filter(|&xy| state.terrain.get(xy) != Wall).collect()
         }).unwrap_or_default();
-        // TODO container_id, link_xy, link_id
         state.sources.push(SourceData {
             id,
             xy,
@@ -93,15 +114,48 @@ pub fn update_room_state_from_scan(room_name: RoomName, force_update: bool, stat
             mineral_type,
         });
     }
+    state.remains = room
+        .find(find::TOMBSTONES, None)
+        .into_iter()
+        .filter_map(|tombstone| remains_data(&tombstone, tombstone.id().into(), tombstone.ticks_to_decay()))
+        .chain(
+            room.find(find::RUINS, None)
+                .into_iter()
+                .filter_map(|ruin| remains_data(&ruin, ruin.id().into(), ruin.ticks_to_decay()))
+        )
+        .collect();
     let mut structures = FxHashMap::default();
     state.structures_to_repair.clear();
+    state.extensions.clear();
+    state.invader_core = None;
+    state.hostile_structures = false;
     let mut structures_changed = force_update;
     // Note that it also finds the controller and other such structures.
-    for structure in room.find(find::STRUCTURES, None) {
-        let structure = structure.as_structure();
+    for structure_obj in structure_objs {
+        let structure = structure_obj.as_structure();
         let structure_type = structure.structure_type();
         let xy = structure.pos().xy();
         let id = structure.id();
+
+        if let StructureObject::StructureExtension(extension) = &structure_obj {
+            let extension_id = extension.id();
+            state.extensions.push(ExtensionData {
+                id: extension_id,
+                xy,
+                missing_energy: get_free_capacity_with_object(extension, extension_id.into(), Some(Energy), AfterAllTransfers),
+            });
+        }
+        if let StructureObject::StructureInvaderCore(invader_core) = &structure_obj {
+            state.invader_core = Some(InvaderCoreData {
+                id: invader_core.id(),
+                xy,
+                level: invader_core.level(),
+                collapse_tick: collapse_timer(invader_core).map(|ticks| game_tick() + ticks),
+            });
+        }
+        if is_hostile_structure(&structure_obj) {
+            state.hostile_structures = true;
+        }
         structures
             .entry(structure_type)
             .or_insert_with(FxHashMap::default)
@@ -130,6 +184,7 @@ pub fn update_room_state_from_scan(room_name: RoomName, force_update: bool, stat
             structures_changed = true;
         }
     }
+    state.extensions.sort_by_key(|extension| extension.xy);
     if !structures_changed {
         for (structure_type, state_xys) in state.structures.iter() {
             if let Some(xys) = structures.get(structure_type) {
@@ -145,17 +200,35 @@ pub fn update_room_state_from_scan(room_name: RoomName, force_update: bool, stat
     }
     if structures_changed {
         debug!("Structures in room {room_name} changed.");
+        let had_spawns = state.structures.get(&Spawn).is_some_and(|xys| !xys.is_empty());
+        let has_spawns = structures.get(&Spawn).is_some_and(|xys| !xys.is_empty());
+        let structures_change = structures_change(&state.structures, &structures);
         state.structures = structures;
 
+        if state.designation == RoomDesignation::Owned {
+            update_recently_destroyed(state, &structures_change);
+        }
+
+        if state.designation == RoomDesignation::Owned {
+            if had_spawns && !has_spawns {
+                apply_room_lifecycle_event(state, AllSpawnsLost);
+            } else if !had_spawns && has_spawns {
+                apply_room_lifecycle_event(state, SpawnRebuilt);
+            }
+        }
+
         // TODO Fast filler data.
 
         state.update_structures_matrix();
 
         // Informing waiting processes that the structure changed.
-        state.structures_broadcast.broadcast(());
+        state.structures_broadcast.broadcast(structures_change);
     }
     
     if state.designation == RoomDesignation::Owned {
+        update_links(state);
+        update_source_containers(state);
+
         state.resources = RoomResources {
             spawn_energy: room.energy_available(),
             spawn_energy_capacity: room.energy_capacity_available(),
@@ -172,3 +245,207 @@ pub fn update_room_state_from_scan(room_name: RoomName, force_update: bool, stat
     
     Ok(())
 }
+
+/// Features of a room seen in a scan that determine its designation.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RoomFeatures {
+    pub controller: bool,
+    pub my_controller: bool,
+    /// Whether the controller is owned by another player.
+    pub foreign_controller: bool,
+    pub portal: bool,
+    pub invader_core: bool,
+}
+
+/// The designation of a room with given name and features. Ownership of the controller takes
+/// precedence, followed by invader cores, portals and source keepers. The remaining rooms without
+/// a controller, i.e., highways and sector centers, are designated as highways.
+pub fn classify_room(room_name: RoomName, features: RoomFeatures) -> RoomDesignation {
+    if features.my_controller {
+        RoomDesignation::Owned
+    } else if features.foreign_controller {
+        RoomDesignation::Enemy
+    } else if features.invader_core {
+        RoomDesignation::Invader
+    } else if features.portal {
+        RoomDesignation::Portal
+    } else if is_source_keeper_room(room_name) {
+        RoomDesignation::SourceKeeper
+    } else if !features.controller {
+        RoomDesignation::Highway
+    } else {
+        RoomDesignation::NotOwned
+    }
+}
+
+/// Whether the structure is owned by another player or invaders. Controllers and keeper lairs are
+/// not counted, as they are already reflected in the designation of the room.
+fn is_hostile_structure(structure_obj: &StructureObject) -> bool {
+    if matches!(structure_obj, StructureObject::StructureController(_) | StructureObject::StructureKeeperLair(_)) {
+        return false;
+    }
+    structure_obj
+        .as_owned()
+        .is_some_and(|owned_structure| !owned_structure.my() && owned_structure.owner().is_some())
+}
+
+/// Data of a tombstone or ruin if there are any resources left in it.
+fn remains_data<T>(remains: &T, id: RawObjectId, ticks_to_decay: u32) -> Option<RemainsData>
+where
+    T: HasStore + HasPosition,
+{
+    let mut store = get_used_capacities_with_object(remains, id, AfterAllTransfers);
+    store.retain(|_, amount| *amount > 0);
+    (!store.is_empty()).then(|| RemainsData {
+        id,
+        xy: remains.pos().xy(),
+        decay_tick: game_tick() + ticks_to_decay,
+        store,
+    })
+}
+
+/// Number of ticks until the invader core collapses on its own, if it has the collapse timer effect.
+fn collapse_timer(invader_core: &StructureInvaderCore) -> Option<u32> {
+    // The bindings of `Effect::ticks_remaining` have a wrong return type, so the effects are read
+    // directly.
+    invader_core.effects_raw()?.iter().find_map(|effect| {
+        let effect_type = Reflect::get(&effect, &"effect".into()).ok()?.as_f64()? as u32;
+        if effect_type != NaturalEffectType::CollapseTimer as u32 {
+            return None;
+        }
+        Reflect::get(&effect, &"ticksRemaining".into()).ok()?.as_f64().map(|ticks| ticks as u32)
+    })
+}
+
+/// Structures present only in the new or only in the old structures.
+fn structures_change(
+    old_structures: &FxHashMap<StructureType, FxHashMap<RoomXY, ObjectId<Structure>>>,
+    new_structures: &FxHashMap<StructureType, FxHashMap<RoomXY, ObjectId<Structure>>>,
+) -> StructuresChange {
+    let difference = |structures: &FxHashMap<StructureType, FxHashMap<RoomXY, ObjectId<Structure>>>,
+                      other_structures: &FxHashMap<StructureType, FxHashMap<RoomXY, ObjectId<Structure>>>| {
+        let mut result = structures
+            .iter()
+            .flat_map(|(&structure_type, xys)| {
+                let other_xys = other_structures.get(&structure_type);
+                xys.keys()
+                    .filter(move |xy| !other_xys.is_some_and(|other_xys| other_xys.contains_key(xy)))
+                    .map(move |&xy| (structure_type, xy))
+            })
+            .collect::<Vec<_>>();
+        result.sort_by_key(|&(structure_type, xy)| (structure_type as u32, xy));
+        result
+    };
+
+    StructuresChange {
+        added: difference(new_structures, old_structures),
+        removed: difference(old_structures, new_structures),
+    }
+}
+
+/// Registers planned structures that were removed as recently destroyed and forgets ones that were rebuilt or
+/// destroyed long ago.
+fn update_recently_destroyed(state: &mut RoomState, structures_change: &StructuresChange) {
+    for &(structure_type, xy) in structures_change.removed.iter() {
+        if state
+            .current_rcl_structures
+            .get(&structure_type)
+            .is_some_and(|xys| xys.contains(&xy))
+        {
+            debug!("{:?} in {} at {} was destroyed.", structure_type, state.room_name, xy);
+            state.recently_destroyed.push((structure_type, xy, game_tick()));
+        }
+    }
+
+    let structures = &state.structures;
+    state.recently_destroyed.retain(|&(structure_type, xy, tick)| {
+        tick + RECENTLY_DESTROYED_TICKS > game_tick()
+            && !structures
+                .get(&structure_type)
+                .is_some_and(|xys| xys.contains_key(&xy))
+    });
+}
+
+/// Records the IDs of the containers built at the work positions of the sources.
+fn update_source_containers(state: &mut RoomState) {
+    let containers = state.structures.get(&Container);
+    for source_data in state.sources.iter_mut() {
+        source_data.container_id = source_data
+            .work_xy
+            .and_then(|xy| containers.and_then(|containers| containers.get(&xy)))
+            .map(|&id| RawObjectId::from(id).into());
+    }
+}
+
+/// Records the planned positions of the source and controller links and their IDs if they are
+/// built.
+fn update_links(state: &mut RoomState) {
+    let Some(plan) = state.plan.as_ref() else {
+        return;
+    };
+    let links = state.structures.get(&Link);
+    let link_id_at = |xy: RoomXY| {
+        links
+            .and_then(|links| links.get(&xy))
+            .map(|&id| RawObjectId::from(id).into())
+    };
+
+    for source_data in state.sources.iter_mut() {
+        source_data.link_xy = plan
+            .sources
+            .iter()
+            .find(|planned_source| planned_source.source_xy == source_data.xy)
+            .map(|planned_source| planned_source.link_xy);
+        source_data.link_id = source_data.link_xy.and_then(link_id_at);
+    }
+
+    if let Some(controller_data) = state.controller.as_mut() {
+        controller_data.link_xy = Some(plan.controller.link_xy);
+        controller_data.link_id = link_id_at(plan.controller.link_xy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::RoomName;
+    use crate::room_states::room_state::RoomDesignation;
+    use crate::room_states::scan_room::{classify_room, RoomFeatures};
+
+    fn classify(room_name: &str, features: RoomFeatures) -> RoomDesignation {
+        classify_room(RoomName::new(room_name).unwrap(), features)
+    }
+
+    #[test]
+    fn test_classify_room() {
+        let unowned = RoomFeatures {
+            controller: true,
+            ..RoomFeatures::default()
+        };
+        assert_eq!(classify("W1N1", unowned), RoomDesignation::NotOwned);
+        assert_eq!(classify("W1N1", RoomFeatures { my_controller: true, ..unowned }), RoomDesignation::Owned);
+        assert_eq!(classify("W1N1", RoomFeatures { foreign_controller: true, ..unowned }), RoomDesignation::Enemy);
+        // A remote reserved by invaders.
+        assert_eq!(classify("W1N1", RoomFeatures { invader_core: true, ..unowned }), RoomDesignation::Invader);
+
+        let no_controller = RoomFeatures::default();
+        assert_eq!(classify("W10N3", no_controller), RoomDesignation::Highway);
+        assert_eq!(classify("E0S0", no_controller), RoomDesignation::Highway);
+        assert_eq!(classify("E20N10", RoomFeatures { portal: true, ..no_controller }), RoomDesignation::Portal);
+        // Sector centers have no controller and possibly a portal.
+        assert_eq!(classify("W5N5", no_controller), RoomDesignation::Highway);
+        assert_eq!(classify("E15S25", RoomFeatures { portal: true, ..no_controller }), RoomDesignation::Portal);
+    }
+
+    #[test]
+    fn test_classify_source_keeper_room() {
+        let no_controller = RoomFeatures::default();
+        for room_name in ["W4N4", "W6N5", "E4S6", "E16S14", "W35N46"] {
+            assert_eq!(classify(room_name, no_controller), RoomDesignation::SourceKeeper, "{}", room_name);
+        }
+        for room_name in ["W3N4", "E7S5", "W5N5", "E15S15", "W4N10"] {
+            assert_ne!(classify(room_name, no_controller), RoomDesignation::SourceKeeper, "{}", room_name);
+        }
+        // A stronghold in a source keeper room.
+        assert_eq!(classify("W4N4", RoomFeatures { invader_core: true, ..no_controller }), RoomDesignation::Invader);
+    }
+}
@@ -3,7 +3,8 @@ use screeps::game;
 use crate::log_err;
 use crate::kernel::sleep::sleep;
 use crate::room_states::room_state::RoomDesignation;
-use crate::room_states::room_states::for_each_room;
+use crate::room_states::room_lifecycle::enforce_room_lifecycle;
+use crate::room_states::room_states::{for_each_owned_room, for_each_room};
 use crate::room_states::scan_room::scan_room;
 
 /// Scans visible rooms.
@@ -21,12 +22,19 @@ pub async fn scan_rooms() {
             first_scan = false;
         }
         
+        // Owned rooms are always visible, so an invisible room formerly owned was lost. Other
+        // designations are kept to be used, e.g., in routing, while the room is not visible.
         for_each_room(|room_name, room_state| {
-            if !visible_room_names.contains(&room_name) {
+            if !visible_room_names.contains(&room_name) && room_state.designation == RoomDesignation::Owned {
                 room_state.designation = RoomDesignation::NotOwned;
             }
         });
 
+        // Making sure nothing is spent on what is not allowed in the current lifecycle of the room.
+        for_each_owned_room(|_, room_state| {
+            enforce_room_lifecycle(room_state);
+        });
+
         // TODO A proper scan only once per few ticks or when it is somehow requested (e.g., by a scout). However, some
         //      preliminary scan should always happen to detect ownership change.
         sleep(1).await;
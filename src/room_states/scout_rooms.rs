@@ -0,0 +1,316 @@
+use std::cmp::max;
+use log::info;
+use rustc_hash::FxHashSet;
+use screeps::{game, RoomName, RoomXY, StructureObserver, StructureSpawn, OBSERVER_RANGE};
+use screeps::Part::Move;
+use screeps::StructureType::{Observer, Spawn};
+use crate::cpu_management::cpu_level;
+use crate::cpu_management::CpuFeature::Scouting;
+use crate::creeps::creep_role::CreepRole::Scout;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::kernel::sleep::sleep;
+use crate::local_debug;
+use crate::priorities::SCOUT_SPAWN_PRIORITY;
+use crate::room_states::room_states::{for_each_owned_room, with_room_state, with_scouted_room};
+use crate::spawning::spawn_pool::{SpawnPool, SpawnPoolOptions};
+use crate::spawning::spawn_schedule::generic_base_spawn_request;
+use crate::travel::travel::travel;
+use crate::travel::travel_spec::TravelSpec;
+use crate::utils::game_tick::game_tick;
+use crate::utils::result_utils::ResultUtils;
+
+const DEBUG: bool = true;
+
+/// Rooms within this linear distance of owned rooms are kept visible from time to time.
+const SCOUTING_RANGE: u32 = 3;
+/// Rooms last scanned more than this many ticks ago are scouted again.
+const MAX_SCAN_AGE: u32 = 1500;
+/// Range of the scout to the center of the room it is visiting. Anywhere inside the room is fine
+/// as the whole room becomes visible.
+const SCOUT_RANGE_TO_CENTER: u8 = 20;
+
+/// Distance between the rooms in rooms, counting diagonal steps as one, like
+/// `game::map::get_room_linear_distance` without wrapping around.
+pub fn room_linear_distance(room_name: RoomName, other_room_name: RoomName) -> u32 {
+    max(
+        room_name.x_coord().abs_diff(other_room_name.x_coord()),
+        room_name.y_coord().abs_diff(other_room_name.y_coord())
+    )
+}
+
+/// Rooms within given linear distance of any of the owned rooms, except the owned rooms
+/// themselves.
+pub fn scouting_targets(owned_room_names: &[RoomName], range: u32) -> FxHashSet<RoomName> {
+    let range = range as i32;
+    let mut targets = FxHashSet::default();
+    for &owned_room_name in owned_room_names.iter() {
+        for dy in -range..=range {
+            for dx in -range..=range {
+                if let Some(room_name) = owned_room_name.checked_add((dx, dy)) {
+                    targets.insert(room_name);
+                }
+            }
+        }
+    }
+    for owned_room_name in owned_room_names.iter() {
+        targets.remove(owned_room_name);
+    }
+    targets
+}
+
+/// Rooms never scanned or scanned more than `max_age` ticks ago, the stalest ones first.
+pub fn stale_rooms<F>(room_names: impl IntoIterator<Item = RoomName>, last_scan_tick: F, tick: u32, max_age: u32) -> Vec<RoomName>
+where
+    F: Fn(RoomName) -> Option<u32>,
+{
+    let mut stale_rooms = room_names
+        .into_iter()
+        .map(|room_name| (last_scan_tick(room_name), room_name))
+        .filter(|&(last_scan_tick, _)| {
+            last_scan_tick.is_none_or(|last_scan_tick| tick.saturating_sub(last_scan_tick) > max_age)
+        })
+        .collect::<Vec<_>>();
+    // `None` goes before any tick.
+    stale_rooms.sort();
+    stale_rooms.into_iter().map(|(_, room_name)| room_name).collect()
+}
+
+/// Order in which a scout starting in given room visits the rooms, always going to the nearest
+/// one not yet visited next. Ties are broken by the order of the rooms, i.e., the staleness.
+pub fn scouting_tour(start_room_name: RoomName, room_names: &[RoomName]) -> Vec<RoomName> {
+    let mut remaining = room_names.to_vec();
+    let mut tour = Vec::with_capacity(remaining.len());
+    let mut current_room_name = start_room_name;
+    while !remaining.is_empty() {
+        let (ix, _) = remaining
+            .iter()
+            .enumerate()
+            .min_by_key(|&(ix, &room_name)| (room_linear_distance(current_room_name, room_name), ix))
+            .unwrap();
+        current_room_name = remaining.remove(ix);
+        tour.push(current_room_name);
+    }
+    tour
+}
+
+/// Assigns to each observer, identified by its room, the stalest room within its range not
+/// assigned to another observer. Returns the assignments and the rooms out of range of all
+/// observers, which are left for scouts.
+pub fn assign_observers(
+    stale_room_names: &[RoomName],
+    observer_room_names: &[RoomName]
+) -> (Vec<(RoomName, RoomName)>, Vec<RoomName>) {
+    let in_range = |observer_room_name: RoomName, room_name: RoomName| {
+        room_linear_distance(observer_room_name, room_name) <= OBSERVER_RANGE
+    };
+
+    let mut observed = Vec::new();
+    let mut assigned = FxHashSet::default();
+    for &observer_room_name in observer_room_names.iter() {
+        let target = stale_room_names
+            .iter()
+            .find(|&&room_name| !assigned.contains(&room_name) && in_range(observer_room_name, room_name));
+        if let Some(&room_name) = target {
+            assigned.insert(room_name);
+            observed.push((observer_room_name, room_name));
+        }
+    }
+
+    let scouted = stale_room_names
+        .iter()
+        .copied()
+        .filter(|&room_name| {
+            !observer_room_names.iter().any(|&observer_room_name| in_range(observer_room_name, room_name))
+        })
+        .collect();
+
+    (observed, scouted)
+}
+
+fn owned_room_names() -> Vec<RoomName> {
+    let mut owned_room_names = Vec::new();
+    for_each_owned_room(|room_name, _| owned_room_names.push(room_name));
+    owned_room_names
+}
+
+fn observer_room_names() -> Vec<RoomName> {
+    let mut observer_room_names = Vec::new();
+    for_each_owned_room(|room_name, room_state| {
+        if room_state.structures_with_type::<StructureObserver>(Observer).next().is_some() {
+            observer_room_names.push(room_name);
+        }
+    });
+    observer_room_names
+}
+
+/// Rooms near owned rooms that need a scan, the stalest ones first.
+fn stale_target_rooms() -> Vec<RoomName> {
+    stale_rooms(
+        scouting_targets(&owned_room_names(), SCOUTING_RANGE),
+        |room_name| with_scouted_room(room_name, |room_state| room_state.last_scan_tick).flatten(),
+        game_tick(),
+        MAX_SCAN_AGE
+    )
+}
+
+/// Rooms to be visited by scouts, i.e., stale rooms out of range of observers.
+fn rooms_to_scout() -> Vec<RoomName> {
+    assign_observers(&stale_target_rooms(), &observer_room_names()).1
+}
+
+/// The owned room with spawns closest to given room.
+fn closest_spawning_room(room_name: RoomName) -> Option<RoomName> {
+    let mut closest = None;
+    for_each_owned_room(|owned_room_name, room_state| {
+        if room_state.structures_with_type::<StructureSpawn>(Spawn).next().is_some() {
+            let dist = room_linear_distance(owned_room_name, room_name);
+            if closest.is_none_or(|(_, closest_dist)| dist < closest_dist) {
+                closest = Some((owned_room_name, dist));
+            }
+        }
+    });
+    closest.map(|(owned_room_name, _)| owned_room_name)
+}
+
+/// Keeps the rooms near owned rooms visible so that they are scanned from time to time. Rooms in
+/// range of observers are observed, one per observer each tick. A single scout walks through
+/// the remaining stale rooms, each time going to the nearest one.
+pub async fn scout_rooms() {
+    let mut scout_pool: Option<SpawnPool> = None;
+
+    loop {
+        if !cpu_level().enables(Scouting) {
+            // Dropping the spawn pool releases the scout.
+            scout_pool = None;
+            sleep(1).await;
+            continue;
+        }
+
+        let (observed, scouted) = assign_observers(&stale_target_rooms(), &observer_room_names());
+
+        for (observer_room_name, room_name) in observed {
+            let observer_id = with_room_state(observer_room_name, |room_state| {
+                room_state.structures_with_type::<StructureObserver>(Observer).next().map(|(_, id)| id)
+            }).flatten();
+            if let Some(observer) = observer_id.and_then(|id| game::get_object_by_id_typed(&id)) {
+                local_debug!("Observing {} from {}.", room_name, observer_room_name);
+                observer
+                    .observe_room(room_name)
+                    .warn_if_err(&format!("Failed to observe {} from {}", room_name, observer_room_name));
+            }
+        }
+
+        if scouted.is_empty() {
+            // Dropping the spawn pool releases the scout.
+            scout_pool = None;
+        } else if scout_pool.is_none() {
+            if let Some(spawn_room_name) = closest_spawning_room(scouted[0]) {
+                info!("Scouting {} rooms from {}.", scouted.len(), spawn_room_name);
+                let base_spawn_request = with_room_state(spawn_room_name, |room_state| {
+                    let mut base_spawn_request = generic_base_spawn_request(room_state, Scout);
+                    base_spawn_request.body = vec![(Move, 1)].into();
+                    base_spawn_request.priority = SCOUT_SPAWN_PRIORITY;
+                    base_spawn_request
+                });
+                scout_pool = base_spawn_request.map(|base_spawn_request| {
+                    SpawnPool::new(spawn_room_name, base_spawn_request, SpawnPoolOptions::default())
+                });
+            }
+        }
+
+        if let Some(scout_pool) = scout_pool.as_mut() {
+            scout_pool.with_spawned_creeps(|creep_ref| async move {
+                // Rooms the scout failed to reach, skipped until there is nothing else to scout.
+                let mut unreachable = FxHashSet::default();
+                loop {
+                    let creep_room_name = creep_ref.borrow().travel_state.pos.room_name();
+                    let mut rooms_to_scout = rooms_to_scout();
+                    if rooms_to_scout.iter().all(|room_name| unreachable.contains(room_name)) {
+                        unreachable.clear();
+                    }
+                    rooms_to_scout.retain(|room_name| !unreachable.contains(room_name));
+                    let Some(&next_room_name) = scouting_tour(creep_room_name, &rooms_to_scout).first() else {
+                        sleep(1).await;
+                        continue;
+                    };
+
+                    local_debug!("Scout {} going to {}.", creep_ref.borrow().name, next_room_name);
+                    let room_center = unsafe { RoomXY::unchecked_new(25, 25) }.to_pos(next_room_name);
+                    if let Err(err) = travel(&creep_ref, TravelSpec::new(room_center, SCOUT_RANGE_TO_CENTER)).await {
+                        err.warn(&format!("Scout could not reach {}", next_room_name));
+                        unreachable.insert(next_room_name);
+                        sleep(1).await;
+                    }
+                }
+            });
+        }
+
+        sleep(1).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::RoomName;
+    use crate::room_states::scout_rooms::{assign_observers, room_linear_distance, scouting_targets, scouting_tour, stale_rooms};
+    use crate::utils::test_utils::room_name;
+
+    #[test]
+    fn test_scouting_targets() {
+        let targets = scouting_targets(&[room_name("W0N0")], 1);
+        assert_eq!(targets.len(), 8);
+        assert!(!targets.contains(&room_name("W0N0")));
+        // Crossing from W to E and N to S.
+        assert!(targets.contains(&room_name("E0S0")));
+        assert!(targets.contains(&room_name("W1N1")));
+        assert!(!targets.contains(&room_name("W2N0")));
+
+        // Overlapping ranges of two owned rooms, neither of which is a target.
+        let targets = scouting_targets(&[room_name("W1N1"), room_name("W2N1")], 1);
+        assert_eq!(targets.len(), 10);
+        assert!(!targets.contains(&room_name("W2N1")));
+        assert!(targets.iter().all(|&target| {
+            room_linear_distance(target, room_name("W1N1")) <= 1 || room_linear_distance(target, room_name("W2N1")) <= 1
+        }));
+
+        assert_eq!(scouting_targets(&[room_name("W1N1")], 3).len(), 48);
+    }
+
+    #[test]
+    fn test_stale_room_ordering() {
+        let last_scan_tick = |name: RoomName| {
+            if name == room_name("W2N1") {
+                Some(1000)
+            } else if name == room_name("W3N1") {
+                Some(100)
+            } else if name == room_name("W4N1") {
+                Some(1900)
+            } else {
+                None
+            }
+        };
+        let rooms = ["W2N1", "W3N1", "W4N1", "W5N1"].map(room_name);
+        let stale = stale_rooms(rooms, last_scan_tick, 2000, 500);
+        // Never scanned first, then the oldest scans. Recently scanned rooms are not stale.
+        assert_eq!(stale, vec![room_name("W5N1"), room_name("W3N1"), room_name("W2N1")]);
+
+        // The tour goes to the nearest room each time.
+        let tour = scouting_tour(room_name("W1N1"), &stale);
+        assert_eq!(tour, vec![room_name("W2N1"), room_name("W3N1"), room_name("W5N1")]);
+        // Among equally distant rooms, the stalest one is visited first.
+        let tour = scouting_tour(room_name("W1N1"), &[room_name("W1N2"), room_name("W1N0"), room_name("W1N3")]);
+        assert_eq!(tour, vec![room_name("W1N2"), room_name("W1N3"), room_name("W1N0")]);
+    }
+
+    #[test]
+    fn test_observers_preferred_in_range() {
+        let stale = ["W5N1", "W20N1", "W3N1", "W2N1"].map(room_name);
+        let (observed, scouted) = assign_observers(&stale, &[room_name("W1N1")]);
+        assert_eq!(observed, vec![(room_name("W1N1"), room_name("W5N1"))]);
+        assert_eq!(scouted, vec![room_name("W20N1")]);
+
+        let (observed, scouted) = assign_observers(&stale, &[]);
+        assert!(observed.is_empty());
+        assert_eq!(scouted, stale.to_vec());
+    }
+}
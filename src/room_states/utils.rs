@@ -46,5 +46,6 @@ where
     }
     
     trace!("Structures changed. Killing the process {}.", handle.pid);
-    kill(handle, ());
+    // The process may have already finished on its own.
+    kill(handle, ()).ok();
 }
\ No newline at end of file
@@ -3,4 +3,7 @@ pub mod spawn_schedule;
 pub mod spawn_room_creeps;
 pub mod scheduling_creeps;
 pub mod reserved_creep;
-pub mod preferred_spawn;
\ No newline at end of file
+pub mod preferred_spawn;
+pub mod renew_creeps;
+pub mod recycle_creeps;
+pub mod remote_spawning;
\ No newline at end of file
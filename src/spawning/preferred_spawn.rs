@@ -1,6 +1,7 @@
 use screeps::{Direction, ObjectId, Position, RawObjectId, RoomXY, StructureSpawn};
 use screeps::StructureType::Spawn;
 use crate::geometry::room_xy::RoomXYUtils;
+use crate::room_planning::plan::open_directions;
 use crate::room_states::room_state::RoomState;
 
 #[derive(Debug, Clone)]
@@ -26,7 +27,7 @@ pub fn best_spawns(room_state: &RoomState, target_xy: Option<RoomXY>) -> Vec<Pre
                     target_xy.get_range_to(xy),
                     PreferredSpawn {
                         id: RawObjectId::from(id).into(),
-                        directions: Vec::new(),
+                        directions: spawn_directions(room_state, xy, Some(target_xy)),
                         extra_cost: 0,
                         pos: xy.to_pos(room_state.room_name),
                     },
@@ -45,11 +46,29 @@ pub fn best_spawns(room_state: &RoomState, target_xy: Option<RoomXY>) -> Vec<Pre
             .flat_map(|xys| {
                 xys.iter().map(|(&xy, &id)| PreferredSpawn {
                     id: RawObjectId::from(id).into(),
-                    directions: Vec::new(),
+                    directions: spawn_directions(room_state, xy, None),
                     extra_cost: 0,
                     pos: xy.to_pos(room_state.room_name),
                 })
             })
             .collect()
     }
+}
+
+/// Directions in which creeps should leave the spawn, towards the planned roads that are not
+/// reserved and, if given, ordered by the range to the target. Empty if there is no plan or no such
+/// road, in which case any direction is allowed.
+fn spawn_directions(room_state: &RoomState, spawn_xy: RoomXY, target_xy: Option<RoomXY>) -> Vec<Direction> {
+    let Some(plan) = room_state.plan.as_ref() else {
+        return Vec::new();
+    };
+    let mut directions = open_directions(spawn_xy, &plan.tiles);
+    if let Some(target_xy) = target_xy {
+        directions.sort_by_key(|&direction| {
+            spawn_xy
+                .checked_add_direction(direction)
+                .map_or(u8::MAX, |xy| xy.get_range_to(target_xy))
+        });
+    }
+    directions
 }
\ No newline at end of file
@@ -0,0 +1,168 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::{HasId, ObjectId, Position, RawObjectId, RoomName, StructureSpawn};
+use screeps::look::RESOURCES;
+use screeps::Part::Move;
+use screeps::StructureType::{Road, Spawn};
+use crate::algorithms::distance_matrix::distance_matrix;
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::consts::UNREACHABLE_COST;
+use crate::creeps::actions::recycle;
+use crate::creeps::creep_body::CreepBody;
+use crate::creeps::creeps::CreepRef;
+use crate::geometry::rect::room_rect;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::hauling::requests::HaulRequestHandle;
+use crate::hauling::scheduling_hauls::schedule_pickup;
+use crate::kernel::sleep::sleep;
+use crate::local_debug;
+use crate::room_states::room_states::with_room_state;
+use crate::spawning::reserved_creep::ReservedCreep;
+use crate::travel::surface::Surface;
+use crate::utils::priority::Priority;
+
+const DEBUG: bool = true;
+
+/// Creeps head to a spawn to be recycled when they have fewer ticks to live than the travel time
+/// to the spawn plus this margin.
+const RECYCLE_MARGIN_TICKS: u32 = 10;
+/// Creeps with more ticks to live are not checked, as no trip to a spawn takes this long.
+const MAX_RECYCLE_TTL: u32 = 200;
+/// Priority of picking up the resources dropped by a recycled creep.
+const RECYCLED_RESOURCES_PICKUP_PRIORITY: Priority = Priority(120);
+
+/// Returns the nearest spawn in the room if the creep has few enough ticks to live that it should
+/// stop working and travel to the spawn to be recycled.
+pub fn recycle_spawn(room_name: RoomName, creep_ref: &CreepRef) -> Option<ObjectId<StructureSpawn>> {
+    let (creep_pos, ticks_to_live, body) = {
+        let mut creep = creep_ref.borrow_mut();
+        (creep.travel_state.pos, creep.ticks_to_live(), creep.body.clone())
+    };
+    if ticks_to_live == 0 || ticks_to_live > MAX_RECYCLE_TTL {
+        return None;
+    }
+
+    with_room_state(room_name, |room_state| {
+        let (spawn_xy, spawn_id) = room_state
+            .structures_with_type::<StructureSpawn>(Spawn)
+            .min_by_key(|&(xy, _)| creep_pos.get_range_to(xy.to_pos(room_name)))?;
+        let range = creep_pos.get_range_to(spawn_xy.to_pos(room_name));
+
+        let road_distance = room_state
+            .plan
+            .as_ref()
+            .filter(|_| creep_pos.room_name() == room_name)
+            .and_then(|plan| {
+                let road_xys = plan.tiles.find_structure_xys(Road).into_iter().collect::<FxHashSet<_>>();
+                let obstacles = room_rect().iter().filter(|xy| !road_xys.contains(xy));
+                let dm = distance_matrix(obstacles, [spawn_xy].into_iter());
+                let dist = dm.get(creep_pos.xy());
+                (dist < UNREACHABLE_COST).then_some(dist as u32)
+            });
+
+        let threshold = recycle_ttl_threshold(&body, road_distance, range)?;
+        (ticks_to_live < threshold).then_some(spawn_id)
+    }).flatten()
+}
+
+/// Number of ticks to live below which a creep with given body needs to head to a spawn to be
+/// recycled before it dies. The travel time is computed from the distance to the spawn along
+/// the roads if the creep is on the road network or from the range to it otherwise. Creeps unable
+/// to move are not recycled.
+pub fn recycle_ttl_threshold(body: &CreepBody, road_distance: Option<u32>, range: u32) -> Option<u32> {
+    if body.count_parts(Move) == 0 {
+        return None;
+    }
+    // The creep needs to reach a tile next to the spawn.
+    let travel_ticks = match road_distance {
+        Some(road_distance) => road_distance.saturating_sub(1) * body.ticks_per_tile(Surface::Road) as u32,
+        None => range.saturating_sub(1) * body.ticks_per_tile(Surface::Plain) as u32,
+    };
+    Some(travel_ticks + RECYCLE_MARGIN_TICKS)
+}
+
+/// Has the creep travel to the spawn and be recycled there, then requests picking up the resources
+/// it dropped until they are gone. Resources ending up in a tombstone are looted with other
+/// remains.
+pub async fn recycle_creep(room_name: RoomName, reserved_creep: ReservedCreep, spawn_id: ObjectId<StructureSpawn>) {
+    let creep_ref = reserved_creep.as_ref();
+    local_debug!("Recycling {} in spawn {} in {}.", creep_ref.borrow().name, spawn_id, room_name);
+    let recycle_pos = match recycle(&creep_ref, spawn_id).await {
+        Ok(pos) => pos,
+        Err(e) => {
+            e.warn(&format!("Failed to recycle {}", creep_ref.borrow().name));
+            return;
+        }
+    };
+
+    // The resources appear in the next tick, when the creep is already gone.
+    sleep(1).await;
+    drop(reserved_creep);
+    schedule_recycled_resources_pickup(room_name, recycle_pos).await;
+}
+
+async fn schedule_recycled_resources_pickup(room_name: RoomName, pos: Position) {
+    let mut pickup_requests: FxHashMap<RawObjectId, HaulRequestHandle> = FxHashMap::default();
+    loop {
+        let Ok(resources) = pos.look_for(RESOURCES) else {
+            return;
+        };
+        if resources.is_empty() {
+            return;
+        }
+
+        // The requests dropped by the hauling system are scheduled anew instead of being replaced.
+        pickup_requests.retain(|_, handle| handle.check_dropped().is_none());
+
+        let mut updated_pickup_requests = FxHashMap::default();
+        for resource in resources.iter() {
+            let id = RawObjectId::from(resource.id());
+            let handle = schedule_pickup(
+                room_name,
+                resource,
+                RECYCLED_RESOURCES_PICKUP_PRIORITY,
+                pickup_requests.remove(&id)
+            );
+            updated_pickup_requests.insert(id, handle);
+        }
+        // The requests that were not replaced are cancelled when dropped.
+        pickup_requests = updated_pickup_requests;
+
+        sleep(1).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Part::{Carry, Move, Work};
+    use crate::creeps::creep_body::CreepBody;
+    use crate::spawning::recycle_creeps::{recycle_ttl_threshold, RECYCLE_MARGIN_TICKS};
+
+    #[test]
+    fn test_recycle_ttl_threshold_for_fast_creep() {
+        let hauler_body = CreepBody::from(vec![(Move, 5), (Carry, 5)]);
+        // Next to the spawn.
+        assert_eq!(recycle_ttl_threshold(&hauler_body, Some(1), 1), Some(RECYCLE_MARGIN_TICKS));
+        // One tile per tick both on and off the roads.
+        assert_eq!(recycle_ttl_threshold(&hauler_body, Some(21), 15), Some(20 + RECYCLE_MARGIN_TICKS));
+        assert_eq!(recycle_ttl_threshold(&hauler_body, None, 15), Some(14 + RECYCLE_MARGIN_TICKS));
+    }
+
+    #[test]
+    fn test_recycle_ttl_threshold_for_slow_creep() {
+        // Three ticks per tile on roads and five off them.
+        let miner_body = CreepBody::from(vec![(Move, 1), (Work, 5)]);
+        assert_eq!(recycle_ttl_threshold(&miner_body, Some(21), 15), Some(60 + RECYCLE_MARGIN_TICKS));
+        assert_eq!(recycle_ttl_threshold(&miner_body, None, 15), Some(70 + RECYCLE_MARGIN_TICKS));
+
+        // Five ticks per tile on roads and ten off them.
+        let heavy_body = CreepBody::from(vec![(Move, 1), (Work, 10)]);
+        assert_eq!(recycle_ttl_threshold(&heavy_body, Some(11), 11), Some(50 + RECYCLE_MARGIN_TICKS));
+        assert_eq!(recycle_ttl_threshold(&heavy_body, None, 11), Some(100 + RECYCLE_MARGIN_TICKS));
+    }
+
+    #[test]
+    fn test_immobile_creeps_not_recycled() {
+        let body = CreepBody::from(vec![(Work, 5)]);
+        assert_eq!(recycle_ttl_threshold(&body, Some(5), 5), None);
+    }
+}
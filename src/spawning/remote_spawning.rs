@@ -0,0 +1,158 @@
+use std::cmp::max;
+use screeps::{game, RoomName, StructureSpawn};
+use screeps::StructureType::Spawn;
+use crate::creeps::creep_body::CreepBody;
+use crate::creeps::creep_role::CreepRole;
+use crate::errors::XiError;
+use crate::errors::XiError::NoRoomToSpawnCreep;
+use crate::local_debug;
+use crate::room_states::room_states::{for_each_owned_room, with_room_state};
+use crate::spawning::scheduling_creeps::schedule_creep;
+use crate::spawning::spawn_schedule::{generic_base_spawn_request, with_spawn_schedule, SpawnPromiseRef};
+use crate::utils::game_tick::game_tick;
+use crate::utils::priority::Priority;
+
+const DEBUG: bool = true;
+
+/// Rooms farther away than this are not asked to spawn creeps for another room.
+const MAX_ASSISTING_ROOM_DISTANCE: u32 = 10;
+/// Rooms whose spawns have more ticks of queued spawning per spawn than this, including the new
+/// creep, have no spare spawn capacity.
+const MAX_ASSISTING_SPAWN_LOAD: u32 = 300;
+/// The creep must start spawning within this many ticks.
+const REMOTE_SPAWN_TIMEOUT: u32 = 400;
+
+/// An owned room that could spawn a creep for another room.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AssistingRoomCandidate {
+    pub room_name: RoomName,
+    /// The distance to the target room in rooms.
+    pub distance: u32,
+    pub number_of_spawns: u32,
+    pub spawn_energy_capacity: u32,
+    /// Ticks needed to finish the creeps being spawned and waiting for a spawn in the room.
+    pub queued_spawn_ticks: u32,
+}
+
+impl AssistingRoomCandidate {
+    fn spawn_load(&self) -> u32 {
+        self.queued_spawn_ticks / max(1, self.number_of_spawns)
+    }
+}
+
+/// Schedules spawning a creep for the target room, e.g., a freshly claimed one without spawns, in
+/// the closest owned room with spare spawn capacity. The creep belongs to the target room once
+/// spawned. Returns the room spawning the creep, needed to cancel the spawn, and the promise.
+pub fn remote_spawn_request(
+    target_room_name: RoomName,
+    role: CreepRole,
+    body: CreepBody,
+    priority: Priority
+) -> Result<(RoomName, SpawnPromiseRef), XiError> {
+    let mut candidates = Vec::new();
+    for_each_owned_room(|room_name, room_state| {
+        candidates.push(AssistingRoomCandidate {
+            room_name,
+            distance: game::map::get_room_linear_distance(room_name, target_room_name, false),
+            number_of_spawns: room_state.structures_with_type::<StructureSpawn>(Spawn).count() as u32,
+            spawn_energy_capacity: room_state.resources.spawn_energy_capacity,
+            queued_spawn_ticks: with_spawn_schedule(room_name, |room_spawn_schedule| room_spawn_schedule.queued_spawn_ticks()),
+        });
+    });
+
+    let assisting_room_name = choose_assisting_room(&candidates, &body).ok_or(NoRoomToSpawnCreep)?;
+    local_debug!("Spawning {} for {} in {}.", role, target_room_name, assisting_room_name);
+
+    let mut spawn_request = with_room_state(assisting_room_name, |room_state| {
+        generic_base_spawn_request(room_state, role)
+    }).ok_or(NoRoomToSpawnCreep)?;
+    spawn_request.body = body;
+    spawn_request.priority = priority;
+    spawn_request.tick = (game_tick(), game_tick() + REMOTE_SPAWN_TIMEOUT);
+    spawn_request.home_room = Some(target_room_name);
+
+    let spawn_promise = schedule_creep(assisting_room_name, spawn_request)?;
+    Ok((assisting_room_name, spawn_promise))
+}
+
+/// Chooses the closest room able to spawn the body without overloading its spawns. Among equally
+/// close rooms, the one with the least loaded spawns is chosen.
+pub fn choose_assisting_room(candidates: &[AssistingRoomCandidate], body: &CreepBody) -> Option<RoomName> {
+    candidates
+        .iter()
+        .filter(|candidate| {
+            candidate.number_of_spawns > 0
+                && candidate.distance <= MAX_ASSISTING_ROOM_DISTANCE
+                && candidate.spawn_energy_capacity >= body.energy_cost()
+                && candidate.spawn_load() + body.spawn_duration() <= MAX_ASSISTING_SPAWN_LOAD
+        })
+        .min_by_key(|candidate| (candidate.distance, candidate.spawn_load()))
+        .map(|candidate| candidate.room_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Part::{Claim, Move};
+    use screeps::RoomName;
+    use crate::creeps::creep_body::CreepBody;
+    use crate::spawning::remote_spawning::{choose_assisting_room, AssistingRoomCandidate};
+    use crate::u;
+
+    fn candidate(room_name: &str, distance: u32, queued_spawn_ticks: u32) -> AssistingRoomCandidate {
+        AssistingRoomCandidate {
+            room_name: u!(RoomName::new(room_name)),
+            distance,
+            number_of_spawns: 1,
+            spawn_energy_capacity: 800,
+            queued_spawn_ticks,
+        }
+    }
+
+    #[test]
+    fn test_closest_room_with_spare_capacity_chosen() {
+        let claimer_body = CreepBody::from(vec![(Move, 1), (Claim, 1)]);
+        let near = candidate("W1N1", 1, 0);
+        let middle = candidate("W3N1", 3, 100);
+        let far = candidate("W6N1", 6, 0);
+
+        assert_eq!(choose_assisting_room(&[far, middle, near], &claimer_body), Some(near.room_name));
+
+        // The closest room is busy spawning its own creeps.
+        let busy_near = AssistingRoomCandidate {
+            queued_spawn_ticks: 298,
+            ..near
+        };
+        assert_eq!(choose_assisting_room(&[far, middle, busy_near], &claimer_body), Some(middle.room_name));
+
+        // A second spawn halves the load.
+        let busy_near_with_two_spawns = AssistingRoomCandidate {
+            number_of_spawns: 2,
+            ..busy_near
+        };
+        assert_eq!(
+            choose_assisting_room(&[far, middle, busy_near_with_two_spawns], &claimer_body),
+            Some(near.room_name)
+        );
+
+        // Among equally close rooms, the less busy one is chosen.
+        let idle_middle = candidate("W1N3", 3, 0);
+        assert_eq!(choose_assisting_room(&[far, middle, idle_middle, busy_near], &claimer_body), Some(idle_middle.room_name));
+    }
+
+    #[test]
+    fn test_rooms_unable_to_spawn_skipped() {
+        let claimer_body = CreepBody::from(vec![(Move, 1), (Claim, 1)]);
+        // A freshly claimed room without spawns, a room with too little energy capacity and a room
+        // too far away.
+        let without_spawns = AssistingRoomCandidate {
+            number_of_spawns: 0,
+            ..candidate("W1N1", 0, 0)
+        };
+        let low_capacity = AssistingRoomCandidate {
+            spawn_energy_capacity: 550,
+            ..candidate("W2N1", 2, 0)
+        };
+        let too_far = candidate("W20N1", 20, 0);
+        assert_eq!(choose_assisting_room(&[without_spawns, low_capacity, too_far], &claimer_body), None);
+    }
+}
@@ -0,0 +1,237 @@
+use rustc_hash::FxHashMap;
+use screeps::{game, ObjectId, Position, RoomName, StructureSpawn};
+use screeps::Part::Claim;
+use screeps::StructureType::Spawn;
+use crate::creeps::creep_body::CreepBody;
+use crate::creeps::creeps::{for_each_creep, CreepRef};
+use crate::economy::room_eco_config::RoomEcoConfig;
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::kernel::sleep::sleep;
+use crate::local_debug;
+use crate::room_states::room_states::with_room_state;
+use crate::spawning::spawn_schedule::with_spawn_schedule;
+use crate::utils::result_utils::ResultUtils;
+
+const DEBUG: bool = true;
+
+/// A spawn starts renewing creeps after being idle with no queued spawns for this many ticks.
+const MIN_IDLE_TICKS_BEFORE_RENEWAL: u32 = 5;
+/// Essential creeps with fewer ticks to live than this are renewed when next to an idle spawn.
+const RENEWAL_TTL_THRESHOLD: u32 = 500;
+/// A creep is renewed until it has at least this many ticks to live.
+const RENEWAL_TARGET_TTL: u32 = 1200;
+/// Renewal uses less spawn time per tick of life than spawning, so it is preferred even when its
+/// energy cost per tick of life is slightly higher due to rounding.
+const RENEWAL_COST_TOLERANCE: f32 = 1.05;
+
+/// Renews essential creeps next to spawns that have nothing else to do instead of letting them
+/// expire and spawning their replacements. A creep is renewed until it reaches the target ticks to
+/// live, the spawn is needed to spawn a creep, or there is not enough energy to renew it without
+/// delaying the next scheduled spawn.
+pub async fn renew_creeps(room_name: RoomName) {
+    let mut idle_ticks: FxHashMap<ObjectId<StructureSpawn>, u32> = FxHashMap::default();
+    let mut renewed_creeps: FxHashMap<ObjectId<StructureSpawn>, CreepRef> = FxHashMap::default();
+
+    loop {
+        let (queue_empty, idle_spawn_ids, reserved_energy) = with_spawn_schedule(room_name, |room_spawn_schedule| {
+            let idle_spawn_ids = room_spawn_schedule
+                .spawns_in_progress
+                .iter()
+                .filter_map(|(&spawn_id, event)| event.is_none().then_some(spawn_id))
+                .collect::<Vec<_>>();
+            // The energy for the earliest future spawn is not used for renewal.
+            let reserved_energy = room_spawn_schedule
+                .future_spawns
+                .values()
+                .next()
+                .and_then(|events| events.values().map(|event| event.energy_cost).max())
+                .unwrap_or(0);
+            (room_spawn_schedule.current_spawns.is_empty(), idle_spawn_ids, reserved_energy)
+        });
+
+        let mut updated_idle_ticks = FxHashMap::default();
+        if queue_empty {
+            for spawn_id in idle_spawn_ids {
+                updated_idle_ticks.insert(spawn_id, idle_ticks.get(&spawn_id).copied().unwrap_or(0) + 1);
+            }
+        }
+        idle_ticks = updated_idle_ticks;
+        renewed_creeps.retain(|spawn_id, _| idle_ticks.contains_key(spawn_id));
+
+        for (&spawn_id, &spawn_idle_ticks) in idle_ticks.iter() {
+            if spawn_idle_ticks >= MIN_IDLE_TICKS_BEFORE_RENEWAL {
+                renew_next_to_spawn(room_name, spawn_id, reserved_energy, &mut renewed_creeps);
+            }
+        }
+
+        sleep(1).await;
+    }
+}
+
+/// Renews the creep currently being renewed by the spawn or, if there is none, selects the essential
+/// creep next to the spawn with the fewest ticks to live that is worth renewing.
+fn renew_next_to_spawn(
+    room_name: RoomName,
+    spawn_id: ObjectId<StructureSpawn>,
+    reserved_energy: u32,
+    renewed_creeps: &mut FxHashMap<ObjectId<StructureSpawn>, CreepRef>
+) {
+    with_room_state(room_name, |room_state| {
+        let Some(eco_config) = room_state.eco_config.as_ref() else {
+            return;
+        };
+        if !eco_config.renewal_allowed {
+            renewed_creeps.clear();
+            return;
+        }
+        let Some(spawn_pos) = room_state
+            .structures_with_type::<StructureSpawn>(Spawn)
+            .find_map(|(xy, id)| (id == spawn_id).then(|| xy.to_pos(room_name)))
+        else {
+            return;
+        };
+
+        let keeps_renewing = renewed_creeps.get(&spawn_id).is_some_and(|creep_ref| {
+            let mut creep = creep_ref.borrow_mut();
+            !creep.dead && creep.travel_state.pos.is_near_to(spawn_pos) && creep.ticks_to_live() < RENEWAL_TARGET_TTL
+        });
+        if !keeps_renewing {
+            renewed_creeps.remove(&spawn_id);
+            if let Some(creep_ref) = select_creep_to_renew(room_name, spawn_pos, eco_config, renewed_creeps) {
+                renewed_creeps.insert(spawn_id, creep_ref);
+            }
+        }
+
+        let Some(creep_ref) = renewed_creeps.get(&spawn_id) else {
+            return;
+        };
+        let mut creep = creep_ref.borrow_mut();
+        let renew_energy_cost = creep.body.renew_energy_cost();
+        if room_state.resources.spawn_energy < reserved_energy + renew_energy_cost {
+            // The energy is needed to spawn creeps.
+            return;
+        }
+        let Some(spawn) = game::get_object_by_id_typed(&spawn_id) else {
+            return;
+        };
+        let Ok(creep_obj) = creep.screeps_obj() else {
+            return;
+        };
+        let result = spawn.renew_creep(creep_obj);
+        if result.is_ok() {
+            local_debug!("Renewing {} in spawn {} in {}.", creep.name, spawn_id, room_name);
+            room_state.resources.spawn_energy -= renew_energy_cost;
+        }
+        result.warn_if_err(&format!("Failed to renew {} in {}", creep.name, room_name));
+    });
+}
+
+/// Finds the creep belonging to the room next to the spawn with the fewest ticks to live below
+/// the threshold among the essential ones worth renewing that are not already being renewed.
+fn select_creep_to_renew(
+    room_name: RoomName,
+    spawn_pos: Position,
+    eco_config: &RoomEcoConfig,
+    renewed_creeps: &FxHashMap<ObjectId<StructureSpawn>, CreepRef>
+) -> Option<CreepRef> {
+    let mut best_creep: Option<(u32, CreepRef)> = None;
+    for_each_creep(|creep_ref| {
+        if renewed_creeps.values().any(|renewed_creep_ref| renewed_creep_ref.as_ptr() == creep_ref.as_ptr()) {
+            return;
+        }
+        let mut creep = creep_ref.borrow_mut();
+        if creep.home_room != Some(room_name) || !creep.travel_state.pos.is_near_to(spawn_pos) {
+            return;
+        }
+        let Some(role_body) = eco_config.essential_role_body(creep.role) else {
+            return;
+        };
+        if !renewal_preferred(&creep.body, role_body) {
+            return;
+        }
+        let ticks_to_live = creep.ticks_to_live();
+        if ticks_to_live == 0 || ticks_to_live >= RENEWAL_TTL_THRESHOLD || creep.spawning() {
+            return;
+        }
+        if best_creep.as_ref().is_none_or(|&(best_ticks_to_live, _)| ticks_to_live < best_ticks_to_live) {
+            best_creep = Some((ticks_to_live, creep_ref.clone()));
+        }
+    });
+    best_creep.map(|(_, creep_ref)| creep_ref)
+}
+
+/// Decides whether a creep with given body should be renewed rather than left to expire and be
+/// replaced by a new creep with the current body of its role. The creep is not renewed if the new
+/// body is more expensive, i.e., the spawn energy capacity increased since the creep was spawned.
+/// Otherwise, the energy cost per tick of life of both is compared. Creeps with claim parts cannot
+/// be renewed and boosted creeps would lose their boosts.
+pub fn renewal_preferred(body: &CreepBody, role_body: &CreepBody) -> bool {
+    if body.count_parts(Claim) > 0 || body.parts.values().any(|&(_, boosted)| boosted > 0) {
+        return false;
+    }
+
+    if role_body.energy_cost() > body.energy_cost() {
+        return false;
+    }
+
+    let renewal_cost_per_tick = body.renew_energy_cost() as f32 / body.renew_ticks() as f32;
+    let respawn_cost_per_tick = role_body.energy_cost() as f32 / role_body.lifetime() as f32;
+    renewal_cost_per_tick <= respawn_cost_per_tick * RENEWAL_COST_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Part::{Carry, Claim, Move, Work};
+    use crate::creeps::creep_body::CreepBody;
+    use crate::spawning::renew_creeps::renewal_preferred;
+
+    #[test]
+    fn test_renew_energy_cost() {
+        let hauler_body = CreepBody::from(vec![(Move, 5), (Carry, 5)]);
+        assert_eq!(hauler_body.energy_cost(), 500);
+        assert_eq!(hauler_body.renew_energy_cost(), 20);
+        assert_eq!(hauler_body.renew_ticks(), 60);
+
+        let miner_body = CreepBody::from(vec![(Move, 1), (Work, 5)]);
+        assert_eq!(miner_body.energy_cost(), 550);
+        assert_eq!(miner_body.renew_energy_cost(), 37);
+        assert_eq!(miner_body.renew_ticks(), 100);
+    }
+
+    #[test]
+    fn test_renewal_preferred_for_same_body() {
+        let hauler_body = CreepBody::from(vec![(Move, 5), (Carry, 5)]);
+        assert!(renewal_preferred(&hauler_body, &hauler_body));
+        let miner_body = CreepBody::from(vec![(Move, 1), (Work, 5)]);
+        assert!(renewal_preferred(&miner_body, &miner_body));
+        // The rounding of renewed ticks to live makes renewal a bit more expensive here.
+        let odd_body = CreepBody::from(vec![(Move, 3), (Work, 4)]);
+        assert!(renewal_preferred(&odd_body, &odd_body));
+    }
+
+    #[test]
+    fn test_respawn_preferred_for_bigger_body() {
+        let small_hauler_body = CreepBody::from(vec![(Move, 3), (Carry, 3)]);
+        let hauler_body = CreepBody::from(vec![(Move, 5), (Carry, 5)]);
+        assert!(!renewal_preferred(&small_hauler_body, &hauler_body));
+    }
+
+    #[test]
+    fn test_respawn_preferred_when_renewal_is_expensive() {
+        // Renewing a creep bigger than the current body of its role costs more than spawning
+        // a cheaper replacement.
+        let big_body = CreepBody::from(vec![(Move, 1), (Work, 1), (Carry, 1)]);
+        let cheap_body = CreepBody::from(vec![(Move, 1), (Carry, 1)]);
+        assert!(big_body.renew_energy_cost() * 1500 > cheap_body.energy_cost() * big_body.renew_ticks());
+        assert!(!renewal_preferred(&big_body, &cheap_body));
+    }
+
+    #[test]
+    fn test_claim_and_boosted_creeps_not_renewed() {
+        let claimer_body = CreepBody::from(vec![(Move, 1), (Claim, 1)]);
+        assert!(!renewal_preferred(&claimer_body, &claimer_body));
+        let mut boosted_body = CreepBody::from(vec![(Move, 1), (Work, 5)]);
+        boosted_body.parts.insert(Work, (5, 5));
+        assert!(!renewal_preferred(&boosted_body, &boosted_body));
+    }
+}
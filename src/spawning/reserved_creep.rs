@@ -2,11 +2,13 @@ use std::cell::RefCell;
 use std::ops::{Deref, DerefMut};
 use log::{debug, trace, warn};
 use rustc_hash::FxHashMap;
-use screeps::{RoomName, RoomXY};
+use std::rc::Rc;
+use screeps::{Part, RoomName, RoomXY};
 use crate::creeps::creep::Creep;
 use crate::creeps::creeps::CreepRef;
 use crate::{a, u};
 use crate::creeps::creep_role::CreepRole;
+use crate::creeps::generic_creep::GenericCreep;
 use crate::geometry::room_xy::RoomXYUtils;
 use crate::travel::nearest_room::find_nearest_owned_room;
 
@@ -74,7 +76,10 @@ impl Drop for ReservedCreep {
 
 pub fn register_unassigned_creep(unassigned_creeps: &mut FxHashMap<RoomName, FxHashMap<CreepRole, FxHashMap<u32, CreepRef>>>, creep_ref: &CreepRef) {
     let creep = creep_ref.borrow();
-    if let Some(room_name) = find_nearest_owned_room(creep.travel_state.pos.room_name(), 0) {
+    let home_room = creep
+        .home_room
+        .or_else(|| find_nearest_owned_room(creep.travel_state.pos.room_name(), 0));
+    if let Some(room_name) = home_room {
         debug!("Registering unregistered creep {} as unassigned in room {}.", creep.name, room_name);
         let previous_data = unassigned_creeps
             .entry(room_name)
@@ -88,33 +93,170 @@ pub fn register_unassigned_creep(unassigned_creeps: &mut FxHashMap<RoomName, FxH
     }
 }
 
-/// Finds an unreserved creep with given role. Any alive creep can be returned, even a currently
-/// spawning one.
-// TODO Option with min_ttl.
+/// Body cost of a reused creep may exceed the cost of the required parts at most this many times.
+const MAX_REUSED_BODY_COST_RATIO: u32 = 2;
+
+/// Requirements for an existing creep to be reused instead of spawning a new one.
+#[derive(Debug, Clone, Copy)]
+pub struct CreepRequirements<'a> {
+    /// The room the creep belongs to.
+    pub room_name: RoomName,
+    /// Minimum number of ticks the creep has left to live.
+    pub min_ttl: u32,
+    /// Minimum number of parts of each type. When not empty, the cost of the creep's body may also
+    /// not exceed the cost of these parts too much.
+    pub min_parts: &'a [(Part, u8)],
+}
+
+impl CreepRequirements<'_> {
+    /// Requirements satisfied by any creep of the room.
+    pub fn any_in_room(room_name: RoomName) -> Self {
+        CreepRequirements {
+            room_name,
+            min_ttl: 0,
+            min_parts: &[],
+        }
+    }
+
+    pub fn is_met_by<C>(&self, creep: &mut C) -> bool
+    where
+        C: GenericCreep,
+    {
+        let body = creep.get_body();
+        let has_parts = self
+            .min_parts
+            .iter()
+            .all(|&(part, count)| body.count_parts(part) >= count);
+        let required_cost = self
+            .min_parts
+            .iter()
+            .map(|&(part, count)| part.cost() * count as u32)
+            .sum::<u32>();
+        let cost_within_tolerance = required_cost == 0 || body.energy_cost() <= required_cost * MAX_REUSED_BODY_COST_RATIO;
+        has_parts && cost_within_tolerance && (self.min_ttl == 0 || creep.get_ticks_to_live() >= self.min_ttl)
+    }
+}
+
+/// Finds an unreserved creep with given role meeting the requirements. Any alive creep can be
+/// returned, even a currently spawning one. Prefers the creep closest to `preferred_xy`, if given.
 pub fn find_unassigned_creep(
-    room_name: RoomName,
     role: CreepRole,
+    requirements: &CreepRequirements,
     preferred_xy: Option<RoomXY>,
 ) -> Option<ReservedCreep> {
     with_unassigned_creeps(|creeps| {
-        let role_creeps = creeps.get_mut(&room_name)?.get_mut(&role)?;
-        while !role_creeps.is_empty() {
-            let creep_number = if let Some(preferred_xy) = preferred_xy {
-                let min_dist_creep_data = role_creeps
-                    .iter()
-                    .min_by_key(|(creep_number, creep_ref)| {
-                        creep_ref.borrow_mut().travel_state.pos.xy().dist(preferred_xy)
-                    });
-                *u!(min_dist_creep_data).0
-            } else {
-                *u!(role_creeps.keys().next())
-            };
-            let creep_ref = u!(role_creeps.remove(&creep_number));
-            if creep_ref.borrow().dead {
-                continue;
-            }
-            return Some(ReservedCreep::new(creep_ref.clone()));
-        }
-        None
+        let role_creeps = creeps.get_mut(&requirements.room_name)?.get_mut(&role)?;
+        role_creeps.retain(|_, creep_ref| !creep_ref.borrow().dead);
+        let creep_number = select_creep(role_creeps, requirements, preferred_xy)?;
+        let creep_ref = u!(role_creeps.remove(&creep_number));
+        Some(ReservedCreep::new(creep_ref))
     })
-}
\ No newline at end of file
+}
+
+/// Number of the creep meeting the requirements, closest to `preferred_xy` if given.
+fn select_creep<C>(
+    creeps: &FxHashMap<u32, Rc<RefCell<C>>>,
+    requirements: &CreepRequirements,
+    preferred_xy: Option<RoomXY>,
+) -> Option<u32>
+where
+    C: GenericCreep,
+{
+    let mut matching_creeps = creeps
+        .iter()
+        .filter(|(_, creep_ref)| requirements.is_met_by(&mut *creep_ref.borrow_mut()));
+    if let Some(preferred_xy) = preferred_xy {
+        matching_creeps
+            .min_by_key(|(_, creep_ref)| creep_ref.borrow().get_travel_state().pos.xy().dist(preferred_xy))
+            .map(|(&creep_number, _)| creep_number)
+    } else {
+        matching_creeps.next().map(|(&creep_number, _)| creep_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use rustc_hash::FxHashMap;
+    use screeps::{Position, RoomName};
+    use screeps::Part::{Carry, Move, Work};
+    use crate::creeps::creep::Creep;
+    use crate::creeps::creep_role::CreepRole::{Hauler, Miner};
+    use crate::creeps::test_creep::TestCreep;
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::room_states::room_state::test_empty_unowned_room_name;
+    use crate::spawning::reserved_creep::{
+        find_unassigned_creep,
+        register_unassigned_creep,
+        select_creep,
+        with_unassigned_creeps,
+        CreepRequirements
+    };
+
+    #[test]
+    fn test_select_creep_meeting_requirements() {
+        let room_name = test_empty_unowned_room_name();
+        let pos = |x: u8| Position::new_from_raw(x, 10, room_name);
+        let test_creep = |id: u32, x: u8, ticks_to_live: u32, parts: Vec<(screeps::Part, u8)>| {
+            let mut creep = TestCreep::new(id, pos(x), parts.into());
+            creep.ticks_to_live = ticks_to_live;
+            Rc::new(RefCell::new(creep))
+        };
+
+        let creeps = FxHashMap::from_iter([
+            // Closest, but about to die.
+            (1, test_creep(1, 10, 20, vec![(Move, 1), (Work, 5)])),
+            // Lacking work parts.
+            (2, test_creep(2, 11, 1000, vec![(Move, 1), (Work, 3)])),
+            // Far too expensive for the job.
+            (3, test_creep(3, 12, 1000, vec![(Move, 10), (Work, 10), (Carry, 10)])),
+            // Better than required, but not by much.
+            (4, test_creep(4, 30, 1000, vec![(Move, 3), (Work, 6)])),
+            (5, test_creep(5, 40, 1000, vec![(Move, 1), (Work, 5)])),
+        ]);
+
+        let min_parts = [(Move, 1), (Work, 5)];
+        let requirements = CreepRequirements {
+            room_name,
+            min_ttl: 100,
+            min_parts: &min_parts,
+        };
+        assert_eq!(select_creep(&creeps, &requirements, Some(pos(10).xy())), Some(4));
+        assert_eq!(select_creep(&creeps, &requirements, Some(pos(45).xy())), Some(5));
+
+        let any_requirements = CreepRequirements::any_in_room(room_name);
+        assert_eq!(select_creep(&creeps, &any_requirements, Some(pos(10).xy())), Some(1));
+
+        let impossible_parts = [(Work, 20)];
+        let impossible_requirements = CreepRequirements {
+            min_parts: &impossible_parts,
+            ..requirements
+        };
+        assert_eq!(select_creep(&creeps, &impossible_requirements, None), None);
+    }
+
+    #[test]
+    fn test_unassigned_creeps_scoped_to_home_room() {
+        let room_name = test_empty_unowned_room_name();
+        let other_room_name = RoomName::new("W2N1").unwrap();
+        // The creep is in the room, but belongs to another one.
+        let creep_ref = Rc::new(RefCell::new(Creep::new(
+            "miner1".into(),
+            None,
+            Miner,
+            1,
+            Some(other_room_name),
+            vec![(Move, 1), (Work, 5)].into(),
+            Position::new_from_raw(10, 10, room_name)
+        )));
+        with_unassigned_creeps(|unassigned_creeps| {
+            register_unassigned_creep(unassigned_creeps, &creep_ref);
+        });
+
+        assert!(find_unassigned_creep(Miner, &CreepRequirements::any_in_room(room_name), None).is_none());
+        assert!(find_unassigned_creep(Hauler, &CreepRequirements::any_in_room(other_room_name), None).is_none());
+        let reserved_creep = find_unassigned_creep(Miner, &CreepRequirements::any_in_room(other_room_name), None);
+        assert!(reserved_creep.is_some_and(|creep| Rc::ptr_eq(&creep.as_ref(), &creep_ref)));
+    }
+}
@@ -54,7 +54,7 @@ pub fn cancel_scheduled_creep(room_name: RoomName, spawn_promise: SpawnPromiseRe
         // Skipping this case and just letting the creep spawn to be used as an idle creep later.
         if spawn_promise.spawn_id.is_none() {
             // Removing the spawn event from `current_spawns`.
-            let maybe_removed_event = room_spawn_schedule.current_spawns.extract_if(|&(_, id), _| id == spawn_promise.id).next();
+            let maybe_removed_event = room_spawn_schedule.current_spawns.extract_if(.., |&(_, id), _| id == spawn_promise.id).next();
             // If it wasn't there, the only remaining place is `future_spawns`.
             if maybe_removed_event.is_none() {
                 let mut cursor = room_spawn_schedule.future_spawns.lower_bound_mut(Bound::Unbounded);
@@ -9,16 +9,24 @@ use std::cell::RefCell;
 use std::cmp::max;
 use std::future::Future;
 use std::rc::Rc;
+use crate::creeps::creep_body::CreepBody;
 use crate::creeps::creeps::CreepRef;
 use crate::economy::room_eco_stats::SpawnPoolStats;
 use crate::room_states::room_states::with_room_state;
-use crate::spawning::reserved_creep::{find_unassigned_creep, ReservedCreep};
+use crate::spawning::recycle_creeps::{recycle_creep, recycle_spawn};
+use crate::spawning::reserved_creep::{find_unassigned_creep, CreepRequirements, ReservedCreep};
 use crate::spawning::scheduling_creeps::{cancel_scheduled_creep, schedule_creep};
 use crate::spawning::spawn_schedule::{SpawnPromise, SpawnRequest};
 use crate::travel::surface::Surface;
 use crate::travel::travel_spec::TravelSpec;
 use crate::utils::uid::UId;
 
+/// Existing creeps with fewer ticks to live are not reused in place of a new creep.
+const MIN_REUSED_CREEP_TTL: u32 = 100;
+/// Prespawned creeps are scheduled to arrive at the workplace this many ticks before the current
+/// creep dies, to account for delays in spawning and travel.
+const PRESPAWN_MARGIN_TICKS: u32 = 10;
+
 #[derive(Debug)]
 pub struct SpawnPoolOptions {
     travel_spec: Option<TravelSpec>,
@@ -120,7 +128,8 @@ impl Drop for SpawnPool {
             }
 
             if let Some((_, current_process)) = element.current_creep_and_process.take() {
-                kill(current_process, ());
+                // The process may have already finished on its own.
+                kill(current_process, ()).ok();
             }
         }
 
@@ -173,8 +182,8 @@ impl SpawnPool {
     {
         if self.include_all_unassigned {
             while let Some(reserved_creep) = find_unassigned_creep(
-                self.room_name,
                 self.base_spawn_request.role,
+                &CreepRequirements::any_in_room(self.room_name),
                 self.travel_spec.as_ref().map(|travel_spec| travel_spec.target.xy())
             ) {
                 self.initial_creeps.push(reserved_creep);
@@ -383,10 +392,28 @@ impl SpawnPoolElement {
                     base_spawn_request.role
                 );
                 let (_, current_process) = u!(self.current_creep_and_process.take());
-                kill(current_process, ());
+                // The process may have already finished on its own.
+                kill(current_process, ()).ok();
             }
         }
 
+        // If the current creep is about to die, stopping its work and sending it to be recycled.
+        // The prespawned creep, if there is one, takes over below.
+        let maybe_recycle_spawn = self
+            .current_creep_and_process
+            .as_ref()
+            .and_then(|(current_creep, _)| recycle_spawn(room_name, &current_creep.as_ref()));
+        if let Some(spawn_id) = maybe_recycle_spawn {
+            let (current_creep, current_process) = u!(self.current_creep_and_process.take());
+            kill(current_process, ()).ok();
+            let wrapper_priority = current_process_wrapped_meta().borrow().priority;
+            schedule(
+                &format!("recycle_{}", current_creep.borrow().name),
+                wrapper_priority.saturating_sub(1),
+                recycle_creep(room_name, current_creep, spawn_id),
+            );
+        }
+
         // If there is a prespawned creep, we check if it spawned already and handle its movement to
         // the target location (if supplied). At the beginning we also use this to spawn the first
         // creep.
@@ -477,9 +504,20 @@ impl SpawnPoolElement {
                     if self.respawn {
                         // Trying to get an existing creep before spawning a new one.
                         // If that fails, a new one will be scheduled.
-                        find_unassigned_creep(
+                        let min_parts = base_spawn_request
+                            .body
+                            .parts
+                            .iter()
+                            .map(|(&part, &(count, _))| (part, count))
+                            .collect::<Vec<_>>();
+                        let requirements = CreepRequirements {
                             room_name,
+                            min_ttl: MIN_REUSED_CREEP_TTL,
+                            min_parts: &min_parts,
+                        };
+                        find_unassigned_creep(
                             base_spawn_request.role,
+                            &requirements,
                             travel_spec.as_ref().map(|travel_spec| travel_spec.target.xy()),
                         ).inspect(|creep| {
                             debug!("Found idle {} creep.", base_spawn_request.role);
@@ -528,25 +566,37 @@ impl SpawnPoolElement {
             // the latter case, we want the creep spawned as fast as possible.
             let mut spawn_request = base_spawn_request.clone();
             if let Some((current_creep, _)) = self.current_creep_and_process.as_ref() {
-                // The prespawning case.
+                // The prespawning case. Creeps of roles that are prespawned are replaced so that
+                // the new creep arrives at the workplace when the current one dies. Others are
+                // replaced when the current one dies.
                 let creep_death_tick = game_tick() + current_creep.borrow_mut().ticks_to_live();
-                let preferred_spawn_pos = spawn_request.preferred_spawns[0].pos;
-                // TODO Cache this, maybe just by moving out of the scope of the loop.
-                let creep_travel_ticks = travel_spec
-                    .as_ref()
-                    .map(|travel_spec| {
-                        predicted_travel_ticks(
-                            preferred_spawn_pos,
-                            travel_spec.target,
-                            1,
-                            travel_spec.range,
-                            &spawn_request.body,
-                            Surface::Plain // TODO
-                        )
-                    })
-                    .unwrap_or(0);
-
-                let min_preferred_tick = creep_death_tick - creep_travel_ticks;
+                let prespawned = with_room_state(room_name, |room_state| {
+                    room_state
+                        .eco_config
+                        .as_ref()
+                        .is_some_and(|eco_config| eco_config.is_prespawned(base_spawn_request.role))
+                }).unwrap_or(false);
+                let min_preferred_tick = if prespawned {
+                    let preferred_spawn_pos = spawn_request.preferred_spawns[0].pos;
+                    // TODO Cache this, maybe just by moving out of the scope of the loop.
+                    let creep_travel_ticks = travel_spec
+                        .as_ref()
+                        .map(|travel_spec| {
+                            predicted_travel_ticks(
+                                preferred_spawn_pos,
+                                travel_spec.target,
+                                1,
+                                travel_spec.range,
+                                &spawn_request.body,
+                                Surface::Plain // TODO
+                            )
+                        })
+                        .unwrap_or(0);
+                    prespawn_tick(creep_death_tick, &spawn_request.body, creep_travel_ticks)
+                } else {
+                    creep_death_tick
+                };
+
                 // TODO Implement the margin properly even if creep_travel_ticks exceeeds base tick
                 //      range.
                 let max_preferred_tick = max(
@@ -595,4 +645,48 @@ impl SpawnPoolElement {
             .as_ref()
             .map_or(0, |(creep, _)| creep.as_ref().borrow_mut().ticks_to_live())
     }
-}
\ No newline at end of file
+}
+
+/// The tick in which the replacement of a creep dying in given tick should start spawning for it to
+/// arrive at the workplace, given number of ticks of travel away from the spawn, right before
+/// the current creep dies.
+pub fn prespawn_tick(creep_death_tick: u32, body: &CreepBody, travel_ticks: u32) -> u32 {
+    creep_death_tick.saturating_sub(body.spawn_duration() + travel_ticks + PRESPAWN_MARGIN_TICKS)
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::Part::{Move, Work};
+    use screeps::Position;
+    use crate::creeps::creep_body::CreepBody;
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::room_states::room_state::test_empty_unowned_room_name;
+    use crate::spawning::spawn_pool::{prespawn_tick, PRESPAWN_MARGIN_TICKS};
+    use crate::travel::surface::Surface;
+    use crate::travel::travel::predicted_travel_ticks;
+
+    #[test]
+    fn test_prespawn_tick_for_miner_far_from_spawn() {
+        let room_name = test_empty_unowned_room_name();
+        let body = CreepBody::from(vec![(Move, 5), (Work, 10)]);
+        let spawn_pos = Position::new_from_raw(10, 10, room_name);
+        let work_pos = Position::new_from_raw(30, 10, room_name);
+
+        // 20 tiles at two ticks per tile.
+        let travel_ticks = predicted_travel_ticks(spawn_pos, work_pos, 1, 0, &body, Surface::Plain);
+        assert_eq!(travel_ticks, 40);
+        assert_eq!(body.spawn_duration(), 45);
+
+        let creep_death_tick = 10000;
+        let spawn_start_tick = prespawn_tick(creep_death_tick, &body, travel_ticks);
+        assert_eq!(spawn_start_tick, creep_death_tick - 85 - PRESPAWN_MARGIN_TICKS);
+        // The replacement arrives before the current creep dies.
+        assert!(spawn_start_tick + body.spawn_duration() + travel_ticks < creep_death_tick);
+    }
+
+    #[test]
+    fn test_prespawn_tick_early_in_the_game() {
+        let body = CreepBody::from(vec![(Move, 5), (Work, 10)]);
+        assert_eq!(prespawn_tick(50, &body, 40), 0);
+    }
+}
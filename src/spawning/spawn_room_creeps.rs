@@ -8,7 +8,6 @@ use crate::u;
 use log::{debug, trace, warn};
 use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::{game, HasPosition, ObjectId, RawObjectId, RoomName, SpawnOptions, StructureSpawn};
-use std::collections::Bound;
 use screeps::StructureType::Spawn;
 use crate::spawning::preferred_spawn::PreferredSpawn;
 use crate::spawning::reserved_creep::ReservedCreep;
@@ -25,6 +24,9 @@ pub fn spawn_room_creeps(room_name: RoomName) {
     // TODO update spawn_start_tick as now+1 when there is not enough energy
     
     let current_tick = game_tick();
+    let (lifecycle, spawn_energy) = with_room_state(room_name, |room_state| {
+        (room_state.lifecycle, room_state.resources.spawn_energy)
+    }).unwrap_or_default();
 
     with_spawn_schedule(room_name, |room_spawn_schedule| {
         // Moving the spawn events for the current tick from future_spawns into current_spawns.
@@ -75,53 +77,95 @@ pub fn spawn_room_creeps(room_name: RoomName) {
         }
 
         if !idle_spawns.is_empty() {
-            // Iterate over the current spawns in priority (and ID) order, where the highest number
-            // is the most urgent. When one with a preferred spawn that is idle is found,
-            // execute it and remove the spawn from idle ones. Continue until there are no more
-            // idle spawns or no more current spawn events. Also clean up all expired spawn events
-            // on the way.
+            // Cleaning up the spawn requests that already expired or will not make it in time.
+            room_spawn_schedule.current_spawns.retain(|_, event| {
+                if event.request.tick.1 < current_tick + event.spawn_duration {
+                    debug!(
+                        "Spawn request for {} expired in {} and was cancelled.",
+                        event.request.role, room_name
+                    );
+                    event.promise.borrow_mut().cancelled = true;
+                    false
+                } else {
+                    true
+                }
+            });
+
+            // Iterating over the current spawns in priority (and ID) order, where the highest
+            // number is the most urgent, and assigning idle spawns to them as long as there is
+            // energy, without letting less urgent ones use the energy reserved for more urgent ones.
+            // The ones the room is not allowed to spawn in its current lifecycle are skipped.
             // TODO For proper prioritization of spawns, check how much time there is left to spawn
             //      current highest priority one and let a lower priority one spawn first if the
             //      higher priority one will still make it in time and the lower priority one
             //      otherwise would not.
-            let mut cursor = room_spawn_schedule.current_spawns.upper_bound_mut(Bound::Unbounded);
-            while !idle_spawns.is_empty() {
-                if let Some((_, event)) = cursor.prev() {
-                    if event.request.tick.1 < current_tick + event.spawn_duration {
-                        // The spawn request already expired or will not make it in time.
-                        // Cancelling it.
-                        let (_, event) = u!(cursor.remove_next());
-                        debug!(
-                            "Spawn request for {} expired in {} and was cancelled.",
-                            event.request.role, room_name
-                        );
-                        event.promise.borrow_mut().cancelled = true;
-                        continue;
-                    }
-
-                    let maybe_preferred_spawn = event
-                        .request
-                        .preferred_spawns
-                        .iter()
-                        .find(|PreferredSpawn { id, .. }| idle_spawns.contains(id))
-                        .map(|preferred_spawn| preferred_spawn.id);
-                    if let Some(preferred_spawn) = maybe_preferred_spawn {
-                        idle_spawns.remove(&preferred_spawn);
-                        if try_execute_spawn_event(room_name, preferred_spawn, event) {
-                            let (_, event) = u!(cursor.remove_next());
-                            room_spawn_schedule
-                                .spawns_in_progress
-                                .insert(preferred_spawn, Some(event));
-                        }
-                    }
-                } else {
-                    break;
+            let allowed_keys = room_spawn_schedule
+                .current_spawns
+                .iter()
+                .rev()
+                .filter_map(|(&key, event)| lifecycle.allows_spawn(event.request.priority).then_some(key))
+                .collect::<Vec<_>>();
+            let assignments = assign_spawns(
+                allowed_keys.iter().map(|key| {
+                    let event = &room_spawn_schedule.current_spawns[key];
+                    (event.energy_cost, event.request.preferred_spawns.as_slice())
+                }),
+                &mut idle_spawns,
+                spawn_energy
+            );
+
+            for (ix, spawn_id) in assignments {
+                let key = allowed_keys[ix];
+                if try_execute_spawn_event(room_name, spawn_id, &room_spawn_schedule.current_spawns[&key]) {
+                    let event = u!(room_spawn_schedule.current_spawns.remove(&key));
+                    room_spawn_schedule
+                        .spawns_in_progress
+                        .insert(spawn_id, Some(event));
                 }
             }
         }
     });
 }
 
+/// Assigns idle spawns to spawn events with given energy costs and preferred spawns, ordered from
+/// the most urgent. An event is assigned its first idle preferred spawn if there is enough energy
+/// left for it. Otherwise, the spawn is kept idle and the energy is reserved for the event while it
+/// accumulates, so that less urgent events cannot use it. Returns the indexes of assigned events
+/// along with their spawns and removes the spawns from the idle ones.
+pub fn assign_spawns<'a, I>(
+    events: I,
+    idle_spawns: &mut FxHashSet<ObjectId<StructureSpawn>>,
+    spawn_energy: u32
+) -> Vec<(usize, ObjectId<StructureSpawn>)>
+where
+    I: Iterator<Item = (u32, &'a [PreferredSpawn])>,
+{
+    let mut assignments = Vec::new();
+    let mut available_energy = spawn_energy;
+
+    for (ix, (energy_cost, preferred_spawns)) in events.enumerate() {
+        if idle_spawns.is_empty() {
+            break;
+        }
+
+        let Some(spawn_id) = preferred_spawns
+            .iter()
+            .find(|preferred_spawn| idle_spawns.contains(&preferred_spawn.id))
+            .map(|preferred_spawn| preferred_spawn.id)
+        else {
+            continue;
+        };
+        idle_spawns.remove(&spawn_id);
+
+        if energy_cost <= available_energy {
+            assignments.push((ix, spawn_id));
+        }
+        available_energy = available_energy.saturating_sub(energy_cost);
+    }
+
+    assignments
+}
+
 fn try_execute_spawn_event(room_name: RoomName, spawn_id: ObjectId<StructureSpawn>, event: &SpawnEvent) -> bool {
     u!(with_room_state(room_name, |room_state| {
         if event.energy_cost > room_state.resources.spawn_energy {
@@ -140,12 +184,18 @@ fn try_execute_spawn_event(room_name: RoomName, spawn_id: ObjectId<StructureSpaw
         // fails to spawn.
         let creep = register_creep(
             event.request.role,
+            event.request.home_room.unwrap_or(room_name),
             event.request.body.clone(),
             spawn_pos
         );
 
         // Issuing the spawn intent.
-        let spawn_options = SpawnOptions::default();
+        let mut spawn_options = SpawnOptions::default();
+        if let Some(preferred_spawn) = event.request.preferred_spawns.iter().find(|preferred_spawn| preferred_spawn.id == spawn_id) {
+            if !preferred_spawn.directions.is_empty() {
+                spawn_options = spawn_options.directions(&preferred_spawn.directions);
+            }
+        }
         let spawn_result = spawn
             .spawn_creep_with_options(&event.request.body.parts_vec(), &creep.borrow().name, &spawn_options);
 
@@ -167,6 +217,11 @@ fn try_execute_spawn_event(room_name: RoomName, spawn_id: ObjectId<StructureSpaw
 
         // Updating the amount of available energy.
         room_state.resources.spawn_energy -= event.energy_cost;
+        if event.request.home_room.is_some_and(|home_room| home_room != room_name) {
+            if let Some(eco_stats) = room_state.eco_stats.as_mut() {
+                eco_stats.register_assisted_spawn(event.energy_cost);
+            }
+        }
 
         let promise = event.promise.clone();
         let spawn_duration = event.spawn_duration;
@@ -253,4 +308,77 @@ pub fn update_spawn_list(room_name: RoomName) {
             }
         });
     });
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashSet;
+    use screeps::{ObjectId, Position, RawObjectId, StructureSpawn};
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::room_states::room_state::test_empty_unowned_room_name;
+    use crate::spawning::preferred_spawn::PreferredSpawn;
+    use crate::spawning::spawn_room_creeps::assign_spawns;
+
+    fn test_spawn(packed_id: u128) -> PreferredSpawn {
+        PreferredSpawn {
+            id: ObjectId::<StructureSpawn>::from(RawObjectId::from_packed(packed_id)),
+            directions: Vec::new(),
+            extra_cost: 0,
+            pos: Position::new_from_raw(10 + packed_id as u8, 10, test_empty_unowned_room_name()),
+        }
+    }
+
+    #[test]
+    fn test_more_urgent_spawn_event_preempts_less_urgent() {
+        let spawn1 = test_spawn(1);
+        let spawn2 = test_spawn(2);
+        let both_spawns = vec![spawn1.clone(), spawn2.clone()];
+
+        let mut idle_spawns = FxHashSet::from_iter([spawn1.id]);
+        let events = [(300, both_spawns.as_slice()), (200, both_spawns.as_slice())];
+        let assignments = assign_spawns(events.into_iter(), &mut idle_spawns, 500);
+        assert_eq!(assignments, vec![(0, spawn1.id)]);
+        assert!(idle_spawns.is_empty());
+
+        let mut idle_spawns = FxHashSet::from_iter([spawn1.id, spawn2.id]);
+        let assignments = assign_spawns(events.into_iter(), &mut idle_spawns, 500);
+        assert_eq!(assignments, vec![(0, spawn1.id), (1, spawn2.id)]);
+    }
+
+    #[test]
+    fn test_energy_reserved_for_more_urgent_spawn_event() {
+        let spawn1 = test_spawn(1);
+        let spawn2 = test_spawn(2);
+        let first_spawn = vec![spawn1.clone(), spawn2.clone()];
+        let second_spawn = vec![spawn2.clone(), spawn1.clone()];
+        let events = [(550, first_spawn.as_slice()), (300, second_spawn.as_slice())];
+
+        // The less urgent event could be afforded, but it would delay the more urgent one.
+        let mut idle_spawns = FxHashSet::from_iter([spawn1.id, spawn2.id]);
+        let assignments = assign_spawns(events.into_iter(), &mut idle_spawns, 400);
+        assert!(assignments.is_empty());
+
+        // Once there is enough energy for both, both are spawned.
+        let mut idle_spawns = FxHashSet::from_iter([spawn1.id, spawn2.id]);
+        let assignments = assign_spawns(events.into_iter(), &mut idle_spawns, 850);
+        assert_eq!(assignments, vec![(0, spawn1.id), (1, spawn2.id)]);
+
+        // With enough energy only for the more urgent one, the other one waits.
+        let mut idle_spawns = FxHashSet::from_iter([spawn1.id, spawn2.id]);
+        let assignments = assign_spawns(events.into_iter(), &mut idle_spawns, 600);
+        assert_eq!(assignments, vec![(0, spawn1.id)]);
+    }
+
+    #[test]
+    fn test_no_energy_reserved_for_spawn_event_without_idle_spawn() {
+        let spawn1 = test_spawn(1);
+        let spawn2 = test_spawn(2);
+        let only_first_spawn = vec![spawn1.clone()];
+        let only_second_spawn = vec![spawn2.clone()];
+        let events = [(550, only_first_spawn.as_slice()), (300, only_second_spawn.as_slice())];
+
+        // The first spawn is busy, so the more urgent event cannot be spawned now anyway.
+        let mut idle_spawns = FxHashSet::from_iter([spawn2.id]);
+        let assignments = assign_spawns(events.into_iter(), &mut idle_spawns, 300);
+        assert_eq!(assignments, vec![(1, spawn2.id)]);
+    }
+}
@@ -8,6 +8,7 @@ use crate::creeps::creep_body::CreepBody;
 use crate::room_states::room_state::RoomState;
 use crate::spawning::preferred_spawn::{best_spawns, PreferredSpawn};
 use crate::spawning::reserved_creep::ReservedCreep;
+use crate::utils::game_tick::game_tick;
 use crate::utils::priority::Priority;
 use crate::utils::uid::UId;
 
@@ -41,6 +42,76 @@ pub struct RoomSpawnSchedule {
     pub spawns_in_progress: FxHashMap<ObjectId<StructureSpawn>, Option<SpawnEvent>>,
 }
 
+/// The state of a spawn event in the schedule.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SpawnQueueEntryState {
+    /// Being spawned in given spawn until given tick.
+    Spawning(ObjectId<StructureSpawn>, u32),
+    /// Waiting for an idle spawn or enough energy.
+    Waiting,
+    /// Scheduled to start spawning in given tick.
+    Scheduled(u32),
+}
+
+/// A summary of a spawn event in the schedule, exposed for introspection.
+#[derive(Debug, Clone)]
+pub struct SpawnQueueEntry {
+    pub role: CreepRole,
+    pub body: CreepBody,
+    pub priority: Priority,
+    pub energy_cost: u32,
+    pub state: SpawnQueueEntryState,
+}
+
+impl RoomSpawnSchedule {
+    /// Lists the spawn events being spawned, then the ones waiting from the most urgent, then
+    /// the scheduled ones from the earliest.
+    pub fn queue_entries(&self) -> Vec<SpawnQueueEntry> {
+        let entry = |event: &SpawnEvent, state: SpawnQueueEntryState| SpawnQueueEntry {
+            role: event.request.role,
+            body: event.request.body.clone(),
+            priority: event.request.priority,
+            energy_cost: event.energy_cost,
+            state,
+        };
+
+        let spawning = self.spawns_in_progress.iter().filter_map(|(&spawn_id, maybe_event)| {
+            let event = maybe_event.as_ref()?;
+            let spawn_end_tick = event.promise.borrow().spawn_end_tick.unwrap_or(0);
+            Some(entry(event, SpawnQueueEntryState::Spawning(spawn_id, spawn_end_tick)))
+        });
+        let waiting = self
+            .current_spawns
+            .values()
+            .rev()
+            .map(|event| entry(event, SpawnQueueEntryState::Waiting));
+        let scheduled = self.future_spawns.iter().flat_map(|(&tick, events)| {
+            events
+                .values()
+                .map(move |event| entry(event, SpawnQueueEntryState::Scheduled(tick)))
+        });
+
+        spawning.chain(waiting).chain(scheduled).collect()
+    }
+
+    /// The total number of ticks the spawns in the room need to finish the creeps being spawned and
+    /// the ones waiting for a spawn, not counting the scheduled ones.
+    pub fn queued_spawn_ticks(&self) -> u32 {
+        let spawning_ticks = self
+            .spawns_in_progress
+            .values()
+            .flatten()
+            .map(|event| event.promise.borrow().spawn_end_tick.map_or(0, |tick| tick.saturating_sub(game_tick())))
+            .sum::<u32>();
+        let waiting_ticks = self
+            .current_spawns
+            .values()
+            .map(|event| event.spawn_duration)
+            .sum::<u32>();
+        spawning_ticks + waiting_ticks
+    }
+}
+
 /// A scheduled spawn.
 #[derive(Debug)]
 pub struct SpawnEvent {
@@ -90,6 +161,9 @@ pub struct SpawnRequest {
     /// Spawns in the order of preference. Must list all valid spawns and be ordered by `extra_cost`.
     pub preferred_spawns: Vec<PreferredSpawn>,
     pub tick: (u32, u32),
+    /// The room the spawned creep belongs to if it is not the room spawning it, e.g., when spawning
+    /// creeps for a room without spawns.
+    pub home_room: Option<RoomName>,
 }
 
 /// A spawn request with empty body, zero tick and no spawn preference.
@@ -103,5 +177,6 @@ pub fn generic_base_spawn_request(room_state: &RoomState, role: CreepRole) -> Sp
         priority: Priority(100),
         preferred_spawns,
         tick: (0, 0),
+        home_room: None,
     }
 }
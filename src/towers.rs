@@ -1,7 +1,443 @@
-use std::cmp::{max, min};
-use screeps::{TOWER_FALLOFF, TOWER_FALLOFF_RANGE, TOWER_OPTIMAL_RANGE, TOWER_POWER_ATTACK};
+use std::cmp::Reverse;
+use screeps::{
+    find,
+    game,
+    Creep,
+    HasPosition,
+    MaybeHasId,
+    ObjectId,
+    ResourceType,
+    Room,
+    RoomName,
+    RoomXY,
+    Structure,
+    StructureTower,
+    HEAL_POWER,
+    RANGED_HEAL_POWER,
+    TOWER_ENERGY_COST,
+    TOWER_FALLOFF,
+    TOWER_FALLOFF_RANGE,
+    TOWER_OPTIMAL_RANGE,
+    TOWER_POWER_ATTACK,
+    TOWER_POWER_HEAL,
+    TOWER_POWER_REPAIR,
+};
+use screeps::game::get_object_by_id_typed;
+use screeps::StructureType::{Rampart, Road, Tower};
+use crate::config::TOWER_REPAIR_MIN_STORAGE_ENERGY;
+use crate::construction::triage_repair_sites::RepairSiteData;
+use crate::defense::threat::{body_threat, hostile_body};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::kernel::sleep::sleep;
+use crate::local_debug;
+use crate::room_states::room_state::RoomState;
+use crate::room_states::room_states::with_room_state;
+use crate::utils::get_object_by_id::structure_object_by_id;
+use crate::utils::result_utils::ResultUtils;
+
+const DEBUG: bool = true;
+
+/// Towers repair only when they have at least this much energy, so that there is enough left to
+/// fight off an attack.
+const MIN_TOWER_ENERGY_TO_REPAIR: u32 = 500;
+/// Maximum range at which a creep heals another one.
+const RANGED_HEAL_RANGE: u8 = 3;
 
 pub fn tower_attack_power(dist: u8) -> u16 {
-    let effective_dist = max(TOWER_OPTIMAL_RANGE, min(TOWER_FALLOFF_RANGE, dist));
-    (TOWER_POWER_ATTACK - ((TOWER_POWER_ATTACK as f64 * TOWER_FALLOFF) as u32) * ((effective_dist - TOWER_OPTIMAL_RANGE) / (TOWER_FALLOFF_RANGE - TOWER_OPTIMAL_RANGE)) as u32) as u16
-}
\ No newline at end of file
+    tower_power(TOWER_POWER_ATTACK, dist) as u16
+}
+
+pub fn tower_heal_power(dist: u8) -> u32 {
+    tower_power(TOWER_POWER_HEAL, dist)
+}
+
+pub fn tower_repair_power(dist: u8) -> u32 {
+    tower_power(TOWER_POWER_REPAIR, dist)
+}
+
+/// Power of a tower action at given distance, falling off linearly from full power at
+/// `TOWER_OPTIMAL_RANGE` to a quarter of it at `TOWER_FALLOFF_RANGE`.
+fn tower_power(power: u32, dist: u8) -> u32 {
+    let effective_dist = dist.clamp(TOWER_OPTIMAL_RANGE, TOWER_FALLOFF_RANGE);
+    let falloff = (power as f64 * TOWER_FALLOFF) as u32;
+    power - falloff * (effective_dist - TOWER_OPTIMAL_RANGE) as u32 / (TOWER_FALLOFF_RANGE - TOWER_OPTIMAL_RANGE) as u32
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TowerData {
+    pub id: ObjectId<StructureTower>,
+    pub xy: RoomXY,
+    pub energy: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct HostileData {
+    pub id: ObjectId<Creep>,
+    pub xy: RoomXY,
+    pub hits: u32,
+    /// Number of active `Heal` parts, with boosted ones counted multiple times according to
+    /// the boost.
+    pub heal_parts: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DamagedCreepData {
+    pub id: ObjectId<Creep>,
+    pub xy: RoomXY,
+    pub missing_hits: u32,
+}
+
+/// Everything the towers in a room need to decide what to do in the current tick.
+#[derive(Debug, Clone, Default)]
+pub struct TowersSnapshot {
+    pub towers: Vec<TowerData>,
+    pub hostiles: Vec<HostileData>,
+    pub damaged_creeps: Vec<DamagedCreepData>,
+    /// Critical repair sites the towers may repair, in the order of their priority.
+    pub repair_sites: Vec<RepairSiteData>,
+    pub storage_energy: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TowerAction {
+    Attack(ObjectId<Creep>),
+    Heal(ObjectId<Creep>),
+    Repair(ObjectId<Structure>),
+}
+
+/// Each tick, has the towers in the room attack hostiles, heal damaged creeps or repair critical
+/// ramparts and roads, in that order of precedence.
+pub async fn operate_towers(room_name: RoomName) {
+    loop {
+        with_room_state(room_name, |room_state| {
+            room_state.tower_attacks = 0;
+            if let Some(room) = game::rooms().get(room_name) {
+                let snapshot = towers_snapshot(&room, room_state);
+                for (tower_id, action) in plan_tower_actions(&snapshot) {
+                    if execute_tower_action(tower_id, action) && matches!(action, TowerAction::Attack(_)) {
+                        room_state.tower_attacks += 1;
+                    }
+                }
+            }
+        });
+
+        sleep(1).await;
+    }
+}
+
+/// Decides what each tower does in the current tick. When hostiles are present, all towers focus
+/// fire on the one that dies the fastest, but only if the damage dealt exceeds the healing
+/// the hostiles around it are capable of. Only without hostiles, the towers heal damaged creeps,
+/// the most damaged first, and if there are none and there is enough energy in the storage, they
+/// repair the repair sites in the given order.
+pub fn plan_tower_actions(snapshot: &TowersSnapshot) -> Vec<(ObjectId<StructureTower>, TowerAction)> {
+    let towers = snapshot
+        .towers
+        .iter()
+        .filter(|tower| tower.energy >= TOWER_ENERGY_COST)
+        .collect::<Vec<_>>();
+    let mut actions = Vec::new();
+    if towers.is_empty() {
+        return actions;
+    }
+
+    if let Some(target) = focus_fire_target(&towers, &snapshot.hostiles) {
+        local_debug!("Towers attacking {} at {}.", target.id, target.xy);
+        for tower in towers.iter() {
+            actions.push((tower.id, TowerAction::Attack(target.id)));
+        }
+        return actions;
+    }
+
+    if !snapshot.hostiles.is_empty() {
+        return actions;
+    }
+
+    if !snapshot.damaged_creeps.is_empty() {
+        let mut missing_hits = snapshot
+            .damaged_creeps
+            .iter()
+            .map(|creep| (creep, creep.missing_hits))
+            .collect::<Vec<_>>();
+        for tower in towers.iter() {
+            let Some((creep, creep_missing_hits)) = missing_hits
+                .iter_mut()
+                .filter(|(_, creep_missing_hits)| *creep_missing_hits > 0)
+                .max_by_key(|(creep, creep_missing_hits)| (*creep_missing_hits, Reverse(creep.xy.dist(tower.xy))))
+            else {
+                break;
+            };
+            actions.push((tower.id, TowerAction::Heal(creep.id)));
+            *creep_missing_hits = creep_missing_hits.saturating_sub(tower_heal_power(creep.xy.dist(tower.xy)));
+        }
+        return actions;
+    }
+
+    if snapshot.storage_energy >= TOWER_REPAIR_MIN_STORAGE_ENERGY {
+        let mut hits_to_repair = snapshot
+            .repair_sites
+            .iter()
+            .map(|repair_site| (repair_site, repair_site.hits_to_repair))
+            .collect::<Vec<_>>();
+        for tower in towers.iter().filter(|tower| tower.energy >= MIN_TOWER_ENERGY_TO_REPAIR) {
+            let Some((repair_site, site_hits_to_repair)) = hits_to_repair
+                .iter_mut()
+                .find(|(_, site_hits_to_repair)| *site_hits_to_repair > 0)
+            else {
+                break;
+            };
+            actions.push((tower.id, TowerAction::Repair(repair_site.id)));
+            *site_hits_to_repair = site_hits_to_repair.saturating_sub(tower_repair_power(repair_site.xy.dist(tower.xy)));
+        }
+    }
+
+    actions
+}
+
+/// The hostile that dies the fastest when all given towers attack it, accounting for the healing of
+/// the hostiles within range, or `None` if no hostile takes more damage than it can be healed.
+fn focus_fire_target<'a>(towers: &[&TowerData], hostiles: &'a [HostileData]) -> Option<&'a HostileData> {
+    hostiles
+        .iter()
+        .filter_map(|target| {
+            let damage = towers
+                .iter()
+                .map(|tower| tower_attack_power(tower.xy.dist(target.xy)) as u32)
+                .sum::<u32>();
+            let heal = hostiles
+                .iter()
+                .map(|healer| {
+                    let dist = healer.xy.dist(target.xy);
+                    if dist <= 1 {
+                        healer.heal_parts * HEAL_POWER
+                    } else if dist <= RANGED_HEAL_RANGE {
+                        healer.heal_parts * RANGED_HEAL_POWER
+                    } else {
+                        0
+                    }
+                })
+                .sum::<u32>();
+            let net_damage = damage.checked_sub(heal).filter(|&net_damage| net_damage > 0)?;
+            Some((target, target.hits.div_ceil(net_damage), net_damage))
+        })
+        .min_by_key(|&(_, ticks_to_kill, net_damage)| (ticks_to_kill, Reverse(net_damage)))
+        .map(|(target, _, _)| target)
+}
+
+fn towers_snapshot(room: &Room, room_state: &RoomState) -> TowersSnapshot {
+    let towers = room_state
+        .structures_with_type::<StructureTower>(Tower)
+        .filter_map(|(xy, id)| {
+            let tower = get_object_by_id_typed(&id)?;
+            Some(TowerData {
+                id,
+                xy,
+                energy: tower.store().get_used_capacity(Some(ResourceType::Energy)),
+            })
+        })
+        .collect::<Vec<_>>();
+    if towers.is_empty() {
+        return TowersSnapshot::default();
+    }
+
+    let hostiles = room
+        .find(find::HOSTILE_CREEPS, None)
+        .into_iter()
+        .filter_map(|creep| Some(HostileData {
+            id: creep.try_id()?,
+            xy: creep.pos().xy(),
+            hits: creep.hits(),
+            heal_parts: body_threat(&hostile_body(&creep)).total_heal / HEAL_POWER,
+        }))
+        .collect();
+
+    let damaged_creeps = room
+        .find(find::MY_CREEPS, None)
+        .into_iter()
+        .filter(|creep| creep.hits() < creep.hits_max())
+        .filter_map(|creep| Some(DamagedCreepData {
+            id: creep.try_id()?,
+            xy: creep.pos().xy(),
+            missing_hits: creep.hits_max() - creep.hits(),
+        }))
+        .collect();
+
+    let repair_sites = room_state
+        .triaged_repair_sites
+        .critical
+        .iter()
+        .filter(|repair_site| matches!(repair_site.structure_type, Rampart | Road))
+        .cloned()
+        .collect();
+
+    TowersSnapshot {
+        towers,
+        hostiles,
+        damaged_creeps,
+        repair_sites,
+        storage_energy: room_state.resources.storage_energy,
+    }
+}
+
+/// Executes the action of the tower. Returns whether it succeeded.
+fn execute_tower_action(tower_id: ObjectId<StructureTower>, action: TowerAction) -> bool {
+    let Some(tower) = get_object_by_id_typed(&tower_id) else {
+        return false;
+    };
+    let result = match action {
+        TowerAction::Attack(id) => get_object_by_id_typed(&id).map(|creep| tower.attack(&creep)),
+        TowerAction::Heal(id) => get_object_by_id_typed(&id).map(|creep| tower.heal(&creep)),
+        TowerAction::Repair(id) => structure_object_by_id(id)
+            .ok()
+            .and_then(|structure| structure.as_repairable().map(|repairable| tower.repair(repairable))),
+    };
+    let Some(result) = result else {
+        return false;
+    };
+    result.warn_if_err(&format!("Failed to execute tower action {:?}", action));
+    result.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{ObjectId, StructureType, TOWER_CAPACITY};
+    use crate::config::TOWER_REPAIR_MIN_STORAGE_ENERGY;
+    use crate::construction::triage_repair_sites::RepairSiteData;
+    use crate::towers::{
+        plan_tower_actions,
+        tower_attack_power,
+        tower_heal_power,
+        DamagedCreepData,
+        HostileData,
+        TowerAction,
+        TowerData,
+        TowersSnapshot
+    };
+    use crate::utils::test_utils::xy;
+
+    fn tower(id: u128, x: u8, y: u8) -> TowerData {
+        TowerData {
+            id: ObjectId::from_packed(id),
+            xy: xy(x, y),
+            energy: TOWER_CAPACITY,
+        }
+    }
+
+    fn hostile(id: u128, x: u8, y: u8, hits: u32, heal_parts: u32) -> HostileData {
+        HostileData {
+            id: ObjectId::from_packed(id),
+            xy: xy(x, y),
+            hits,
+            heal_parts,
+        }
+    }
+
+    fn snapshot(towers: Vec<TowerData>, hostiles: Vec<HostileData>) -> TowersSnapshot {
+        TowersSnapshot {
+            towers,
+            hostiles,
+            ..TowersSnapshot::default()
+        }
+    }
+
+    #[test]
+    fn test_tower_power_falloff() {
+        assert_eq!(tower_attack_power(1), 600);
+        assert_eq!(tower_attack_power(5), 600);
+        assert_eq!(tower_attack_power(10), 450);
+        assert_eq!(tower_attack_power(20), 150);
+        assert_eq!(tower_attack_power(40), 150);
+        assert_eq!(tower_heal_power(10), 300);
+    }
+
+    #[test]
+    fn test_towers_focus_fire_on_fastest_dying_hostile() {
+        let towers = vec![tower(1, 10, 10), tower(2, 12, 10)];
+        // A weak creep far away and a tougher one close to the towers.
+        let far_hostile = hostile(3, 40, 10, 300, 0);
+        let close_hostile = hostile(4, 11, 12, 1500, 0);
+        let actions = plan_tower_actions(&snapshot(towers.clone(), vec![far_hostile, close_hostile]));
+        assert_eq!(actions, vec![
+            (towers[0].id, TowerAction::Attack(far_hostile.id)),
+            (towers[1].id, TowerAction::Attack(far_hostile.id)),
+        ]);
+
+        let weaker_close_hostile = hostile(4, 11, 12, 1200, 0);
+        let actions = plan_tower_actions(&snapshot(towers.clone(), vec![hostile(3, 40, 10, 600, 0), weaker_close_hostile]));
+        assert_eq!(actions[0], (towers[0].id, TowerAction::Attack(weaker_close_hostile.id)));
+    }
+
+    #[test]
+    fn test_towers_do_not_shoot_when_outhealed() {
+        let towers = vec![tower(1, 10, 10)];
+        // 150 damage at this range against 13 heal parts of the target itself.
+        let healer = hostile(2, 40, 40, 1000, 13);
+        assert!(plan_tower_actions(&snapshot(towers.clone(), vec![healer])).is_empty());
+
+        // Ranged healing from a creep two tiles away still counts.
+        let target = hostile(3, 38, 40, 1000, 0);
+        let ranged_healer = hostile(4, 40, 40, 1000, 38);
+        assert!(plan_tower_actions(&snapshot(towers.clone(), vec![target, ranged_healer])).is_empty());
+
+        // A creep out of range of the healers is shot.
+        let lone_target = hostile(5, 30, 40, 1000, 0);
+        assert_eq!(
+            plan_tower_actions(&snapshot(towers.clone(), vec![target, ranged_healer, lone_target])),
+            vec![(towers[0].id, TowerAction::Attack(lone_target.id))]
+        );
+    }
+
+    #[test]
+    fn test_towers_heal_and_repair() {
+        let towers = vec![tower(1, 10, 10), tower(2, 30, 30)];
+        let repair_site = RepairSiteData {
+            id: ObjectId::from_packed(5),
+            structure_type: StructureType::Rampart,
+            xy: xy(20, 20),
+            hits_to_repair: 10_000,
+            target_hits: 25_000,
+            ticks_to_critical: Some(0),
+        };
+        let mut snapshot = TowersSnapshot {
+            towers: towers.clone(),
+            damaged_creeps: vec![
+                DamagedCreepData {
+                    id: ObjectId::from_packed(3),
+                    xy: xy(10, 12),
+                    missing_hits: 200,
+                },
+                DamagedCreepData {
+                    id: ObjectId::from_packed(4),
+                    xy: xy(30, 32),
+                    missing_hits: 1000,
+                },
+            ],
+            repair_sites: vec![repair_site.clone()],
+            storage_energy: TOWER_REPAIR_MIN_STORAGE_ENERGY,
+            ..TowersSnapshot::default()
+        };
+
+        // The most damaged creep is healed first.
+        let actions = plan_tower_actions(&snapshot);
+        assert_eq!(actions, vec![
+            (towers[0].id, TowerAction::Heal(ObjectId::from_packed(4))),
+            (towers[1].id, TowerAction::Heal(ObjectId::from_packed(4))),
+        ]);
+
+        // No healing while there are hostiles, even ones the towers do not shoot at.
+        snapshot.hostiles = vec![hostile(6, 40, 40, 1000, 60)];
+        assert!(plan_tower_actions(&snapshot).is_empty());
+        snapshot.hostiles.clear();
+
+        snapshot.damaged_creeps.clear();
+        let actions = plan_tower_actions(&snapshot);
+        assert_eq!(actions, vec![
+            (towers[0].id, TowerAction::Repair(repair_site.id)),
+            (towers[1].id, TowerAction::Repair(repair_site.id)),
+        ]);
+
+        // No repairs with too little energy in the storage.
+        snapshot.storage_energy -= 1;
+        assert!(plan_tower_actions(&snapshot).is_empty());
+    }
+}
@@ -4,4 +4,7 @@ pub mod travel_spec;
 pub mod surface;
 pub mod traffic;
 pub mod step_utils;
-pub mod nearest_room;
\ No newline at end of file
+pub mod nearest_room;
+pub mod path_cache;pub mod move_intents;
+pub mod route;
+pub mod park;
@@ -0,0 +1,114 @@
+use std::cell::RefCell;
+use rustc_hash::{FxHashMap, FxHashSet};
+use screeps::Position;
+use crate::utils::game_tick::game_tick;
+
+/// Move intents of creeps in the current tick, from their current position to the adjacent one
+/// they are moving to.
+#[derive(Debug, Default)]
+pub struct MoveIntents {
+    tick: Option<u32>,
+    intents: FxHashMap<Position, Position>,
+}
+
+impl MoveIntents {
+    pub fn register(&mut self, from: Position, to: Position) {
+        self.intents.insert(from, to);
+    }
+
+    /// The position the creep at given position is moving to in this tick, if any.
+    pub fn target(&self, from: Position) -> Option<Position> {
+        self.intents.get(&from).copied()
+    }
+
+    /// The move intents in the order in which they should be issued. A creep moving into a tile
+    /// occupied by another moving creep, e.g., one being shoved out of its way, comes after it.
+    /// Creeps in a cycle, e.g., two creeps swapping places, are issued one right after another.
+    pub fn ordered(&self) -> Vec<(Position, Position)> {
+        let mut sources = self.intents.keys().copied().collect::<Vec<_>>();
+        sources.sort_by_key(|pos| pos.packed_repr());
+
+        let mut ordered = Vec::with_capacity(self.intents.len());
+        let mut issued = FxHashSet::default();
+        for source in sources.into_iter() {
+            // Following the chain of creeps each moving into the tile of the next one until reaching
+            // a tile that is being vacated or already was in the chain.
+            let mut chain = Vec::new();
+            let mut pos = source;
+            while !issued.contains(&pos) && !chain.contains(&pos) {
+                let Some(target) = self.target(pos) else {
+                    break;
+                };
+                chain.push(pos);
+                pos = target;
+            }
+
+            for &from in chain.iter().rev() {
+                issued.insert(from);
+                ordered.push((from, self.intents[&from]));
+            }
+        }
+
+        ordered
+    }
+}
+
+thread_local! {
+    static MOVE_INTENTS: RefCell<MoveIntents> = RefCell::new(MoveIntents::default());
+}
+
+/// Runs the function on the move intents registered in the current tick.
+pub fn with_move_intents<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut MoveIntents) -> R,
+{
+    MOVE_INTENTS.with(|move_intents| {
+        let mut move_intents = move_intents.borrow_mut();
+        if move_intents.tick != Some(game_tick()) {
+            move_intents.tick = Some(game_tick());
+            move_intents.intents.clear();
+        }
+        f(&mut move_intents)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{Position, RoomName};
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::travel::move_intents::MoveIntents;
+
+    fn pos(x: u8, y: u8) -> Position {
+        Position::new_from_raw(x, y, RoomName::new("W1N1").unwrap())
+    }
+
+    #[test]
+    fn test_chain_ordered_from_front() {
+        let mut move_intents = MoveIntents::default();
+        move_intents.register(pos(10, 10), pos(11, 10));
+        move_intents.register(pos(11, 10), pos(12, 10));
+        move_intents.register(pos(12, 10), pos(13, 10));
+        move_intents.register(pos(20, 20), pos(21, 21));
+
+        let ordered = move_intents.ordered();
+        assert_eq!(ordered.len(), 4);
+        let ix = |from: Position| ordered.iter().position(|&(pos, _)| pos == from).unwrap();
+        assert!(ix(pos(12, 10)) < ix(pos(11, 10)));
+        assert!(ix(pos(11, 10)) < ix(pos(10, 10)));
+    }
+
+    #[test]
+    fn test_swap_ordered_together() {
+        let mut move_intents = MoveIntents::default();
+        move_intents.register(pos(10, 10), pos(11, 10));
+        move_intents.register(pos(11, 10), pos(10, 10));
+        move_intents.register(pos(9, 10), pos(10, 10));
+
+        let ordered = move_intents.ordered();
+        assert_eq!(ordered.len(), 3);
+        let ix = |from: Position| ordered.iter().position(|&(pos, _)| pos == from).unwrap();
+        assert_eq!(ix(pos(10, 10)).abs_diff(ix(pos(11, 10))), 1);
+        assert_eq!(move_intents.target(pos(11, 10)), Some(pos(10, 10)));
+        assert_eq!(move_intents.target(pos(12, 10)), None);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+use rustc_hash::FxHashSet;
+use screeps::{RoomXY, StructureType};
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::room_matrix::RoomMatrix;
+use crate::creeps::creeps::{for_each_creep, CreepRef};
+use crate::geometry::room_xy::RoomXYUtils;
+use crate::local_debug;
+use crate::room_states::room_state::RoomState;
+use crate::travel::surface::Surface;
+use crate::travel::travel::travel;
+use crate::travel::travel_spec::TravelSpec;
+
+const DEBUG: bool = true;
+
+/// Maximum distance from the creep to search for a parking tile.
+const MAX_PARKING_DIST: u8 = 10;
+
+/// Moves an idle creep to the nearest tile where it is not in the way of other creeps, as chosen
+/// by `parking_xy`. Does nothing if the creep is outside of the room, already on its way to
+/// a parking tile or there is no such tile nearby.
+pub fn park(creep_ref: &CreepRef, room_state: &RoomState) {
+    let (creep_pos, parking) = {
+        let creep = creep_ref.borrow();
+        let travel_state = &creep.travel_state;
+        let parking = !travel_state.arrived && travel_state.spec.as_ref().is_some_and(|spec| spec.range == 0);
+        (travel_state.pos, parking)
+    };
+    if creep_pos.room_name() != room_state.room_name || parking {
+        return;
+    }
+
+    // Tiles of other creeps and where they are going to stand.
+    let mut occupied_xys = FxHashSet::default();
+    for_each_creep(|other_creep_ref| {
+        if Rc::ptr_eq(other_creep_ref, creep_ref) {
+            return;
+        }
+        let other_creep = other_creep_ref.borrow();
+        let other_travel_state = &other_creep.travel_state;
+        if other_travel_state.pos.room_name() == room_state.room_name {
+            occupied_xys.insert(other_travel_state.pos.xy());
+        }
+        if let Some(travel_spec) = other_travel_state.spec.as_ref() {
+            if travel_spec.range == 0 && travel_spec.target.room_name() == room_state.room_name {
+                occupied_xys.insert(travel_spec.target.xy());
+            }
+        }
+    });
+
+    if let Some(xy) = parking_xy(room_state, creep_pos.xy(), &occupied_xys) {
+        if xy != creep_pos.xy() {
+            local_debug!("Parking creep {} at {}.", creep_ref.borrow().name, xy);
+            travel(creep_ref, TravelSpec::new(xy.to_pos(room_state.room_name), 0));
+        }
+    }
+}
+
+/// The nearest tile reachable from the start tile where a creep can stand without blocking others.
+/// It must not be a planned or existing road, reserved in the plan, a container or a work tile, be
+/// next to the storage, on the room boundary or occupied. It also must not be a tile where
+/// a structure is or will be built, except for a rampart.
+pub fn parking_xy(room_state: &RoomState, start_xy: RoomXY, occupied_xys: &FxHashSet<RoomXY>) -> Option<RoomXY> {
+    let excluded_xys = excluded_parking_xys(room_state);
+
+    let mut visited = RoomMatrix::new(false);
+    visited.set(start_xy, true);
+    let mut queue = VecDeque::from([(start_xy, 0u8)]);
+    while let Some((xy, dist)) = queue.pop_front() {
+        if !occupied_xys.contains(&xy) && !excluded_xys.contains(&xy) && is_parking_tile(room_state, xy) {
+            return Some(xy);
+        }
+
+        if dist < MAX_PARKING_DIST {
+            for near in xy.around() {
+                if !visited.get(near) && room_state.tile_surface(near) != Surface::Obstacle {
+                    visited.set(near, true);
+                    queue.push_back((near, dist + 1));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Work tiles of miners and upgraders and the tiles next to the storage.
+fn excluded_parking_xys(room_state: &RoomState) -> FxHashSet<RoomXY> {
+    let mut excluded_xys = FxHashSet::default();
+    for source_data in room_state.sources.iter() {
+        excluded_xys.extend(source_data.work_xy);
+        excluded_xys.extend(source_data.drop_mining_xys.iter().copied());
+    }
+    if let Some(controller_data) = room_state.controller.as_ref() {
+        excluded_xys.extend(controller_data.work_xy);
+    }
+
+    let mut storage_xys = room_state
+        .structures
+        .get(&StructureType::Storage)
+        .map(|storages| storages.keys().copied().collect::<Vec<_>>())
+        .unwrap_or_default();
+    if let Some(plan) = room_state.plan.as_ref() {
+        excluded_xys.insert(plan.controller.work_xy);
+        excluded_xys.extend(plan.sources.iter().map(|source| source.work_xy));
+        excluded_xys.extend(plan.mineral.iter().map(|mineral| mineral.work_xy));
+        storage_xys.extend(plan.tiles.find_structure_xys(StructureType::Storage));
+    }
+    for storage_xy in storage_xys.into_iter() {
+        excluded_xys.extend(storage_xy.around());
+    }
+
+    excluded_xys
+}
+
+/// Whether the tile itself, with its current and planned structures, is suitable for parking.
+fn is_parking_tile(room_state: &RoomState, xy: RoomXY) -> bool {
+    if xy.is_on_boundary() || !matches!(room_state.tile_surface(xy), Surface::Plain | Surface::Swamp) {
+        return false;
+    }
+    if room_state.structures_matrix.get(xy).iter().any(|structure_type| structure_type != StructureType::Rampart) {
+        return false;
+    }
+    if let Some(plan) = room_state.plan.as_ref() {
+        let tile = plan.tiles.get(xy);
+        if tile.reserved() || tile.structures().iter().any(|structure_type| structure_type != StructureType::Rampart) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashSet;
+    use screeps::{RoomXY, StructureType};
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::geometry::room_xy::RoomXYUtils;
+    use crate::room_planning::plan::{Plan, PlanScore, PlannedControllerData};
+    use crate::room_planning::planned_tile::PlannedTile;
+    use crate::room_planning::stamps::core_stamp;
+    use crate::room_states::room_state::{empty_unowned_room_state, RoomState};
+    use crate::travel::park::parking_xy;
+    use crate::utils::test_utils::xy;
+
+    /// Room with the core stamp with its top left corner at (20, 20) and the storage at (21, 21).
+    fn core_room_state() -> RoomState {
+        let mut room_state = empty_unowned_room_state();
+        let mut tiles = RoomMatrix::<PlannedTile>::default();
+        for (stamp_xy, tile) in core_stamp().iter() {
+            tiles.set(xy(stamp_xy.x.u8() + 20, stamp_xy.y.u8() + 20), tile);
+        }
+        let controller = PlannedControllerData {
+            work_xy: xy(30, 30),
+            link_xy: xy(31, 31),
+        };
+        room_state.plan = Some(Plan::new(tiles, controller, Vec::new(), None, PlanScore::default(), Vec::new(), Vec::new()));
+        room_state
+    }
+
+    fn assert_parkable(room_state: &RoomState, parking_xy: RoomXY) {
+        let plan = room_state.plan.as_ref().unwrap();
+        let tile = plan.tiles.get(parking_xy);
+        assert!(!tile.reserved());
+        assert!(!tile.structures().road());
+        assert!(tile.structures().iter().all(|structure_type| structure_type == StructureType::Rampart));
+        assert!(parking_xy.dist(xy(21, 21)) > 1);
+    }
+
+    #[test]
+    fn test_parking_off_core_roads() {
+        let room_state = core_room_state();
+
+        // From the road on the top of the core, the nearest free tile is right outside of it.
+        let parked_xy = parking_xy(&room_state, xy(23, 20), &FxHashSet::default()).unwrap();
+        assert_parkable(&room_state, parked_xy);
+        assert_eq!(parked_xy.dist(xy(23, 20)), 1);
+        assert!(parked_xy.y.u8() < 20);
+
+        // From a reserved tile inside the core, the creep leaves the core.
+        let parked_xy = parking_xy(&room_state, xy(22, 22), &FxHashSet::default()).unwrap();
+        assert_parkable(&room_state, parked_xy);
+        assert!(!(20..=26).contains(&parked_xy.x.u8()) || !(20..=26).contains(&parked_xy.y.u8()));
+
+        // A tile that is already fine is kept.
+        assert_eq!(parking_xy(&room_state, xy(10, 10), &FxHashSet::default()), Some(xy(10, 10)));
+    }
+
+    #[test]
+    fn test_parking_avoids_occupied_and_work_tiles() {
+        let room_state = core_room_state();
+        // The controller work tile is not a parking tile.
+        let parked_xy = parking_xy(&room_state, xy(30, 30), &FxHashSet::default()).unwrap();
+        assert_ne!(parked_xy, xy(30, 30));
+        assert_eq!(parked_xy.dist(xy(30, 30)), 1);
+
+        let occupied_xys = FxHashSet::from_iter([xy(10, 10)]);
+        let parked_xy = parking_xy(&room_state, xy(10, 10), &occupied_xys).unwrap();
+        assert_eq!(parked_xy.dist(xy(10, 10)), 1);
+    }
+}
@@ -0,0 +1,269 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use rustc_hash::FxHashMap;
+use screeps::{Position, RoomName};
+use crate::errors::XiError;
+use crate::kernel::broadcast::Broadcast;
+use crate::local_debug;
+use crate::room_states::room_state::StructuresChange;
+use crate::room_states::room_states::with_room_state;
+use crate::travel::travel::find_path;
+use crate::travel::travel_spec::TravelSpec;
+
+const DEBUG: bool = true;
+
+/// Paths are shared by creeps starting within the same square area of this size.
+const AREA_SIZE: u8 = 5;
+/// The cache is cleared when it has more entries than this.
+const MAX_CACHED_PATHS: usize = 2000;
+
+/// Paths are cached by the room and area of the start position, the target and the range.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct PathCacheKey {
+    pub room_name: RoomName,
+    pub area: (u8, u8),
+    pub target: Position,
+    pub range: u8,
+}
+
+impl PathCacheKey {
+    pub fn new(pos: Position, target: Position, range: u8) -> Self {
+        PathCacheKey {
+            room_name: pos.room_name(),
+            area: (pos.x().u8() / AREA_SIZE, pos.y().u8() / AREA_SIZE),
+            target,
+            range,
+        }
+    }
+}
+
+/// A path serialized as packed positions, in the order of travel, along with the generations of
+/// the structures in the rooms it crosses at the time it was found.
+#[derive(Debug)]
+struct CachedPath {
+    packed_path: Vec<u32>,
+    generations: Vec<(RoomName, u32)>,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PathCacheStats {
+    pub hits: u32,
+    pub misses: u32,
+    /// Number of cached paths dropped since the structures in a room they cross changed.
+    pub invalidations: u32,
+}
+
+/// Cache of paths found by the pathfinder. A path is shared by all creeps starting in any of the
+/// areas it passes through. Cached paths are invalidated lazily when the structures in any of
+/// the rooms they cross change, as signalled by `structures_broadcast` of the room.
+#[derive(Debug, Default)]
+pub struct PathCache {
+    paths: FxHashMap<PathCacheKey, Rc<CachedPath>>,
+    /// Broadcasts of structure changes of the rooms, whose number of broadcasts is the generation
+    /// of the structures in the room.
+    structures_broadcasts: FxHashMap<RoomName, Broadcast<StructuresChange>>,
+    pub stats: PathCacheStats,
+}
+
+impl PathCache {
+    /// Starts tracking the generation of the structures in the room using its structures broadcast.
+    pub fn watch_room(&mut self, room_name: RoomName, structures_broadcast: Broadcast<StructuresChange>) {
+        self.structures_broadcasts.insert(room_name, structures_broadcast);
+    }
+
+    pub fn is_watched(&self, room_name: RoomName) -> bool {
+        self.structures_broadcasts.contains_key(&room_name)
+    }
+
+    fn generation(&self, room_name: RoomName) -> u32 {
+        self.structures_broadcasts
+            .get(&room_name)
+            .map_or(0, |structures_broadcast| structures_broadcast.broadcasts_count())
+    }
+
+    /// The cached path from given position, in the form of a stack like in `TravelState`, if there
+    /// is a valid one for the area of the position passing next to it. The path is joined at its
+    /// last tile in range 1 of the position, so that a creep that is already in the middle of it
+    /// resumes from there.
+    pub fn get(&mut self, pos: Position, target: Position, range: u8) -> Option<Vec<Position>> {
+        let key = PathCacheKey::new(pos, target, range);
+        let Some(cached_path) = self.paths.get(&key).cloned() else {
+            self.stats.misses += 1;
+            return None;
+        };
+
+        let valid = cached_path
+            .generations
+            .iter()
+            .all(|&(room_name, generation)| self.generation(room_name) == generation);
+        if !valid {
+            local_debug!("Cached path from {} to {} was invalidated.", pos, target);
+            self.paths.remove(&key);
+            self.stats.invalidations += 1;
+            self.stats.misses += 1;
+            return None;
+        }
+
+        let path = cached_path
+            .packed_path
+            .iter()
+            .map(|&packed| Position::from_packed(packed))
+            .collect::<Vec<_>>();
+        let Some(join_ix) = path.iter().rposition(|&path_pos| path_pos.get_range_to(pos) <= 1) else {
+            // The position deviated from the path.
+            self.stats.misses += 1;
+            return None;
+        };
+        let next_ix = if path[join_ix] == pos { join_ix + 1 } else { join_ix };
+
+        self.stats.hits += 1;
+        Some(path[next_ix..].iter().rev().copied().collect())
+    }
+
+    /// Caches the path from given position, in the form of a stack like in `TravelState`, for
+    /// the area of the position and all the areas the path passes through.
+    pub fn insert(&mut self, pos: Position, target: Position, range: u8, path: &[Position]) {
+        if self.paths.len() >= MAX_CACHED_PATHS {
+            local_debug!("Clearing the path cache.");
+            self.paths.clear();
+        }
+
+        let mut generations = Vec::new();
+        for room_name in path.iter().map(|path_pos| path_pos.room_name()).chain([pos.room_name()]) {
+            if !generations.iter().any(|&(generation_room_name, _)| generation_room_name == room_name) {
+                generations.push((room_name, self.generation(room_name)));
+            }
+        }
+
+        let cached_path = Rc::new(CachedPath {
+            packed_path: path.iter().rev().map(|path_pos| path_pos.packed_repr()).collect(),
+            generations,
+        });
+        // The last tile of the path is skipped, as creeps starting there have already arrived.
+        for &path_pos in [pos].iter().chain(path.iter().skip(1)) {
+            self.paths.insert(PathCacheKey::new(path_pos, target, range), cached_path.clone());
+        }
+    }
+}
+
+thread_local! {
+    static PATH_CACHE: RefCell<PathCache> = RefCell::new(PathCache::default());
+}
+
+pub fn with_path_cache<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut PathCache) -> R,
+{
+    PATH_CACHE.with(|path_cache| f(&mut path_cache.borrow_mut()))
+}
+
+pub fn path_cache_stats() -> PathCacheStats {
+    with_path_cache(|path_cache| path_cache.stats)
+}
+
+/// Finds a path like `find_path`, reusing the cached one if possible and caching the new one
+/// otherwise.
+pub fn find_cached_path(start_pos: Position, travel_spec: &TravelSpec) -> Result<Vec<Position>, XiError> {
+    if let Some(path) = with_path_cache(|path_cache| path_cache.get(start_pos, travel_spec.target, travel_spec.range)) {
+        return Ok(path);
+    }
+
+    let path = find_path(start_pos, travel_spec)?;
+
+    for room_name in path.iter().map(|pos| pos.room_name()).chain([start_pos.room_name()]) {
+        if !with_path_cache(|path_cache| path_cache.is_watched(room_name)) {
+            if let Some(structures_broadcast) = with_room_state(room_name, |room_state| room_state.structures_broadcast.clone_primed()) {
+                with_path_cache(|path_cache| path_cache.watch_room(room_name, structures_broadcast));
+            }
+        }
+    }
+    with_path_cache(|path_cache| path_cache.insert(start_pos, travel_spec.target, travel_spec.range, &path));
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::{Position, RoomName};
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::kernel::broadcast::Broadcast;
+    use crate::room_states::room_state::StructuresChange;
+    use crate::travel::path_cache::{PathCache, PathCacheStats};
+
+    fn room_name() -> RoomName {
+        RoomName::new("W1N1").unwrap()
+    }
+
+    fn pos(x: u8, y: u8) -> Position {
+        Position::new_from_raw(x, y, room_name())
+    }
+
+    /// Path from (10, 10) to (20, 10) in the form of a stack.
+    fn straight_path() -> Vec<Position> {
+        (11..=20).rev().map(|x| pos(x, 10)).collect()
+    }
+
+    #[test]
+    fn test_cached_path_reused() {
+        let mut path_cache = PathCache::default();
+        let target = pos(20, 10);
+        assert_eq!(path_cache.get(pos(10, 10), target, 0), None);
+        path_cache.insert(pos(10, 10), target, 0, &straight_path());
+
+        assert_eq!(path_cache.get(pos(10, 10), target, 0), Some(straight_path()));
+        // A creep starting next to the path in the same area joins it.
+        let mut expected_path = straight_path();
+        expected_path.pop();
+        assert_eq!(path_cache.get(pos(11, 11), target, 0), Some(expected_path));
+        // A different range is a different path.
+        assert_eq!(path_cache.get(pos(10, 10), target, 1), None);
+
+        assert_eq!(path_cache.stats, PathCacheStats {
+            hits: 2,
+            misses: 2,
+            invalidations: 0,
+        });
+    }
+
+    #[test]
+    fn test_resumption_mid_path() {
+        let mut path_cache = PathCache::default();
+        let target = pos(20, 10);
+        path_cache.insert(pos(10, 10), target, 0, &straight_path());
+
+        // A creep in the middle of the path, in another area than the start, continues from where
+        // it is.
+        let path = path_cache.get(pos(16, 10), target, 0).unwrap();
+        assert_eq!(path, (17..=20).rev().map(|x| pos(x, 10)).collect::<Vec<_>>());
+        // A creep pushed off the path by one tile steps back onto it further along.
+        let path = path_cache.get(pos(16, 11), target, 0).unwrap();
+        assert_eq!(path, (17..=20).rev().map(|x| pos(x, 10)).collect::<Vec<_>>());
+        // A creep that deviated further does not use the path.
+        assert_eq!(path_cache.get(pos(16, 12), target, 0), None);
+        assert_eq!(path_cache.stats.hits, 2);
+        assert_eq!(path_cache.stats.misses, 1);
+    }
+
+    #[test]
+    fn test_invalidation_on_structures_broadcast() {
+        let mut path_cache = PathCache::default();
+        let structures_broadcast = Broadcast::<StructuresChange>::default();
+        path_cache.watch_room(room_name(), structures_broadcast.clone_primed());
+        let target = pos(20, 10);
+        path_cache.insert(pos(10, 10), target, 0, &straight_path());
+        assert!(path_cache.get(pos(10, 10), target, 0).is_some());
+
+        structures_broadcast.broadcast(StructuresChange::default());
+        assert_eq!(path_cache.get(pos(10, 10), target, 0), None);
+        assert_eq!(path_cache.stats.invalidations, 1);
+
+        // The recomputed path is valid until the next change.
+        path_cache.insert(pos(10, 10), target, 0, &straight_path());
+        assert!(path_cache.get(pos(10, 10), target, 0).is_some());
+        // Paths through other areas are dropped lazily, when used.
+        assert_eq!(path_cache.get(pos(16, 10), target, 0).map(|path| path.len()), Some(4));
+        structures_broadcast.broadcast(StructuresChange::default());
+        assert_eq!(path_cache.get(pos(16, 10), target, 0), None);
+        assert_eq!(path_cache.stats.invalidations, 2);
+    }
+}
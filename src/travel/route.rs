@@ -0,0 +1,364 @@
+use std::cell::RefCell;
+use log::info;
+use rustc_hash::FxHashMap;
+use screeps::{game, RoomName, RoomXY, ROOM_SIZE};
+use screeps::game::map::FindRouteOptions;
+use crate::errors::XiError;
+use crate::errors::XiError::RouteNotFound;
+use crate::local_debug;
+use crate::room_states::room_state::{RoomDesignation, RoomState};
+use crate::room_states::room_states::with_room_state;
+use crate::travel::surface::Surface;
+use crate::utils::game_tick::game_tick;
+
+const DEBUG: bool = true;
+
+/// Number of ticks a room is avoided after a creep was killed in it.
+const BLACKLIST_TTL: u32 = 1500;
+/// Number of ticks a found route is reused.
+const ROUTE_CACHE_TTL: u32 = 500;
+
+const HIGHWAY_ROOM_COST: f64 = 1.0;
+const ROOM_COST: f64 = 2.0;
+const HOSTILE_ROOM_COST: f64 = 10.0;
+
+/// Preferences of rooms on the route.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct RoutePrefs {
+    /// Whether enemy and invader rooms are impassable rather than just expensive.
+    pub avoid_hostile_rooms: bool,
+    /// Whether highways are cheaper than other rooms.
+    pub prefer_highways: bool,
+}
+
+impl Default for RoutePrefs {
+    fn default() -> Self {
+        RoutePrefs {
+            avoid_hostile_rooms: true,
+            prefer_highways: true,
+        }
+    }
+}
+
+/// The cost of entering a room with given designation on the route. Infinite cost means the room
+/// is impassable. Rooms without a room state have unknown designation.
+pub fn room_route_cost(designation: Option<RoomDesignation>, blacklisted: bool, prefs: RoutePrefs) -> f64 {
+    if blacklisted {
+        return f64::INFINITY;
+    }
+    match designation {
+        Some(RoomDesignation::Enemy | RoomDesignation::Invader | RoomDesignation::SourceKeeper) => {
+            if prefs.avoid_hostile_rooms {
+                f64::INFINITY
+            } else {
+                HOSTILE_ROOM_COST
+            }
+        }
+        Some(RoomDesignation::Highway) if prefs.prefer_highways => HIGHWAY_ROOM_COST,
+        _ => ROOM_COST,
+    }
+}
+
+/// Whether the room is a highway, i.e., one of the rooms without controllers between sectors.
+pub fn is_highway(room_name: RoomName) -> bool {
+    // Coordinates of W0 and N0 are -1.
+    let sector_coord = |coord: i32| if coord < 0 { -coord - 1 } else { coord };
+    sector_coord(room_name.x_coord()) % 10 == 0 || sector_coord(room_name.y_coord()) % 10 == 0
+}
+
+/// Whether the room is one of the source keeper rooms, i.e., the 3x3 rooms around the center of
+/// a sector except for the center room itself.
+pub fn is_source_keeper_room(room_name: RoomName) -> bool {
+    // Coordinates of W0 and N0 are -1.
+    let sector_coord = |coord: i32| if coord < 0 { (-coord - 1) % 10 } else { coord % 10 };
+    let x = sector_coord(room_name.x_coord());
+    let y = sector_coord(room_name.y_coord());
+    (4..=6).contains(&x) && (4..=6).contains(&y) && (x, y) != (5, 5)
+}
+
+/// Passable exit tiles of the room leading to the adjacent room with given name.
+pub fn exit_xys(room_state: &RoomState, next_room_name: RoomName) -> Result<Vec<RoomXY>, XiError> {
+    let dx = next_room_name.x_coord() - room_state.room_name.x_coord();
+    let dy = next_room_name.y_coord() - room_state.room_name.y_coord();
+    let boundary = ROOM_SIZE - 1;
+    let xys = match (dx, dy) {
+        (-1, 0) => (1..boundary).map(|y| (0, y)).collect::<Vec<_>>(),
+        (1, 0) => (1..boundary).map(|y| (boundary, y)).collect(),
+        (0, -1) => (1..boundary).map(|x| (x, 0)).collect(),
+        (0, 1) => (1..boundary).map(|x| (x, boundary)).collect(),
+        _ => Err(RouteNotFound)?,
+    };
+    Ok(xys
+        .into_iter()
+        .filter_map(|xy| RoomXY::try_from(xy).ok())
+        .filter(|&xy| room_state.tile_surface(xy) != Surface::Obstacle)
+        .collect())
+}
+
+/// Finder of routes between rooms, i.e., `game::map::find_route` outside of tests.
+pub trait RouteFinder {
+    /// The rooms on the route after the starting one, given the cost of entering each room.
+    fn find_route(
+        &self,
+        from: RoomName,
+        to: RoomName,
+        room_cost: &mut dyn FnMut(RoomName) -> f64
+    ) -> Result<Vec<RoomName>, XiError>;
+}
+
+pub struct GameRouteFinder;
+
+impl RouteFinder for GameRouteFinder {
+    fn find_route(
+        &self,
+        from: RoomName,
+        to: RoomName,
+        room_cost: &mut dyn FnMut(RoomName) -> f64
+    ) -> Result<Vec<RoomName>, XiError> {
+        let options = FindRouteOptions::new().room_callback(|room_name, _| room_cost(room_name));
+        game::map::find_route(from, to, Some(options))
+            .map(|steps| steps.into_iter().map(|step| step.room).collect())
+            .or(Err(RouteNotFound))
+    }
+}
+
+#[derive(Debug)]
+struct CachedRoute {
+    rooms: Vec<RoomName>,
+    expiry_tick: u32,
+}
+
+/// Routes between rooms avoiding hostile rooms and the ones where creeps were recently killed.
+#[derive(Debug, Default)]
+pub struct Router {
+    /// Rooms where creeps were killed along with the tick until which they are avoided.
+    blacklist: FxHashMap<RoomName, u32>,
+    routes: FxHashMap<(RoomName, RoomName, RoutePrefs), CachedRoute>,
+}
+
+impl Router {
+    /// Avoids the room until `BLACKLIST_TTL` ticks after given tick. Cached routes through it are
+    /// dropped.
+    pub fn blacklist(&mut self, room_name: RoomName, tick: u32) {
+        self.blacklist.insert(room_name, tick + BLACKLIST_TTL);
+        self.routes.retain(|_, route| !route.rooms.contains(&room_name));
+    }
+
+    pub fn is_blacklisted(&self, room_name: RoomName, tick: u32) -> bool {
+        self.blacklist.get(&room_name).is_some_and(|&expiry_tick| tick < expiry_tick)
+    }
+
+    /// The rooms on the route from one room to another, after the starting one. The rooms are
+    /// given costs according to `room_route_cost`, except the target room which is always
+    /// passable.
+    pub fn find_route<R, D>(
+        &mut self,
+        route_finder: &R,
+        from: RoomName,
+        to: RoomName,
+        prefs: RoutePrefs,
+        tick: u32,
+        designation: D
+    ) -> Result<Vec<RoomName>, XiError>
+    where
+        R: RouteFinder,
+        D: Fn(RoomName) -> Option<RoomDesignation>,
+    {
+        self.blacklist.retain(|_, &mut expiry_tick| tick < expiry_tick);
+
+        let key = (from, to, prefs);
+        if let Some(route) = self.routes.get(&key) {
+            if tick < route.expiry_tick {
+                return Ok(route.rooms.clone());
+            }
+        }
+
+        let rooms = route_finder.find_route(from, to, &mut |room_name| {
+            if room_name == to {
+                ROOM_COST
+            } else {
+                room_route_cost(designation(room_name), self.is_blacklisted(room_name, tick), prefs)
+            }
+        })?;
+        local_debug!("Route from {} to {}: {:?}.", from, to, rooms);
+
+        self.routes.insert(key, CachedRoute {
+            rooms: rooms.clone(),
+            expiry_tick: tick + ROUTE_CACHE_TTL,
+        });
+        Ok(rooms)
+    }
+}
+
+thread_local! {
+    static ROUTER: RefCell<Router> = RefCell::new(Router::default());
+}
+
+fn with_router<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Router) -> R,
+{
+    ROUTER.with(|router| f(&mut router.borrow_mut()))
+}
+
+/// The rooms on the route from one room to another, after the starting one, avoiding rooms
+/// according to their designation and recent creep deaths.
+pub fn find_route(from: RoomName, to: RoomName, prefs: RoutePrefs) -> Result<Vec<RoomName>, XiError> {
+    with_router(|router| {
+        router.find_route(&GameRouteFinder, from, to, prefs, game_tick(), |room_name| {
+            with_room_state(room_name, |room_state| room_state.designation)
+        })
+    })
+}
+
+/// Makes routes avoid the room where a creep was killed for a while, unless it is an owned room.
+pub fn register_creep_killed(room_name: RoomName) {
+    let owned = with_room_state(room_name, |room_state| room_state.designation == RoomDesignation::Owned);
+    if owned != Some(true) {
+        info!("Avoiding {} in routes after a creep was killed there.", room_name);
+        with_router(|router| router.blacklist(room_name, game_tick()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use screeps::{RoomName, RoomXY};
+    use screeps::Terrain::Wall;
+    use crate::errors::XiError;
+    use crate::room_states::room_state::{empty_unowned_room_state, RoomDesignation};
+    use crate::travel::route::{
+        exit_xys,
+        is_highway,
+        room_route_cost,
+        RouteFinder,
+        Router,
+        RoutePrefs,
+        BLACKLIST_TTL,
+        HIGHWAY_ROOM_COST,
+        HOSTILE_ROOM_COST,
+        ROOM_COST,
+    };
+    use crate::utils::test_utils::room_name;
+
+    /// Route finder that goes through the cheaper of two rooms between W1N1 and W3N1, either
+    /// W2N1 or W1N2 and W2N2.
+    #[derive(Default)]
+    struct MockRouteFinder {
+        calls: Cell<u32>,
+    }
+
+    impl RouteFinder for MockRouteFinder {
+        fn find_route(
+            &self,
+            _from: RoomName,
+            to: RoomName,
+            room_cost: &mut dyn FnMut(RoomName) -> f64
+        ) -> Result<Vec<RoomName>, XiError> {
+            self.calls.set(self.calls.get() + 1);
+            let direct = room_cost(room_name("W2N1"));
+            let detour = room_cost(room_name("W1N2")) + room_cost(room_name("W2N2"));
+            if direct.is_finite() && direct <= detour {
+                Ok(vec![room_name("W2N1"), to])
+            } else if detour.is_finite() {
+                Ok(vec![room_name("W1N2"), room_name("W2N2"), to])
+            } else {
+                Err(XiError::RouteNotFound)
+            }
+        }
+    }
+
+    #[test]
+    fn test_room_route_cost() {
+        let prefs = RoutePrefs::default();
+        assert_eq!(room_route_cost(None, false, prefs), ROOM_COST);
+        assert_eq!(room_route_cost(Some(RoomDesignation::NotOwned), false, prefs), ROOM_COST);
+        assert_eq!(room_route_cost(Some(RoomDesignation::Highway), false, prefs), HIGHWAY_ROOM_COST);
+        assert_eq!(room_route_cost(Some(RoomDesignation::Enemy), false, prefs), f64::INFINITY);
+        assert_eq!(room_route_cost(Some(RoomDesignation::Invader), false, prefs), f64::INFINITY);
+        assert_eq!(room_route_cost(Some(RoomDesignation::SourceKeeper), false, prefs), f64::INFINITY);
+        assert_eq!(room_route_cost(Some(RoomDesignation::Portal), false, prefs), ROOM_COST);
+        assert_eq!(room_route_cost(Some(RoomDesignation::Owned), true, prefs), f64::INFINITY);
+
+        let lenient_prefs = RoutePrefs {
+            avoid_hostile_rooms: false,
+            prefer_highways: false,
+        };
+        assert_eq!(room_route_cost(Some(RoomDesignation::Enemy), false, lenient_prefs), HOSTILE_ROOM_COST);
+        assert_eq!(room_route_cost(Some(RoomDesignation::Highway), false, lenient_prefs), ROOM_COST);
+    }
+
+    #[test]
+    fn test_is_highway() {
+        assert!(is_highway(room_name("W0N5")));
+        assert!(is_highway(room_name("E10S3")));
+        assert!(is_highway(room_name("W3N20")));
+        assert!(!is_highway(room_name("W1N1")));
+        assert!(!is_highway(room_name("E5S5")));
+    }
+
+    #[test]
+    fn test_exit_xys() {
+        let mut room_state = empty_unowned_room_state();
+        room_state.terrain.set((0, 10).try_into().unwrap(), Wall);
+        let xys = exit_xys(&room_state, room_name("W2N1")).unwrap();
+        assert_eq!(xys.len(), 47);
+        assert!(xys.iter().all(|xy| xy.x.u8() == 0));
+        assert!(!xys.contains(&RoomXY::try_from((0, 10)).unwrap()));
+
+        let xys = exit_xys(&room_state, room_name("W1N0")).unwrap();
+        assert!(xys.iter().all(|xy| xy.y.u8() == 49));
+        assert!(exit_xys(&room_state, room_name("W3N1")).is_err());
+    }
+
+    #[test]
+    fn test_route_avoids_hostile_and_blacklisted_rooms() {
+        let route_finder = MockRouteFinder::default();
+        let mut router = Router::default();
+        let from = room_name("W1N1");
+        let to = room_name("W3N1");
+        let no_designation = |_| None;
+
+        let route = router.find_route(&route_finder, from, to, RoutePrefs::default(), 0, no_designation).unwrap();
+        assert_eq!(route, vec![room_name("W2N1"), to]);
+        // The route is cached.
+        router.find_route(&route_finder, from, to, RoutePrefs::default(), 1, no_designation).unwrap();
+        assert_eq!(route_finder.calls.get(), 1);
+
+        let enemy_designation = |name: RoomName| (name == room_name("W2N1")).then_some(RoomDesignation::Enemy);
+        let route = router.find_route(&route_finder, from, to, RoutePrefs::default(), 1000, enemy_designation).unwrap();
+        assert_eq!(route, vec![room_name("W1N2"), room_name("W2N2"), to]);
+
+        // The hostile target room itself is still passable.
+        let route = router.find_route(&route_finder, from, room_name("W2N1"), RoutePrefs::default(), 1000, enemy_designation);
+        assert!(route.is_ok());
+    }
+
+    #[test]
+    fn test_blacklist_expiry() {
+        let route_finder = MockRouteFinder::default();
+        let mut router = Router::default();
+        let from = room_name("W1N1");
+        let to = room_name("W3N1");
+        let no_designation = |_| None;
+
+        router.find_route(&route_finder, from, to, RoutePrefs::default(), 0, no_designation).unwrap();
+        router.blacklist(room_name("W2N1"), 10);
+        assert!(router.is_blacklisted(room_name("W2N1"), 10));
+
+        // The cached route through the blacklisted room was dropped.
+        let route = router.find_route(&route_finder, from, to, RoutePrefs::default(), 11, no_designation).unwrap();
+        assert_eq!(route, vec![room_name("W1N2"), room_name("W2N2"), to]);
+        assert_eq!(route_finder.calls.get(), 2);
+
+        router.blacklist(room_name("W2N2"), 20);
+        assert!(router.find_route(&route_finder, from, to, RoutePrefs::default(), 21, no_designation).is_err());
+
+        let expiry_tick = 20 + BLACKLIST_TTL;
+        assert!(router.is_blacklisted(room_name("W2N2"), expiry_tick - 1));
+        assert!(!router.is_blacklisted(room_name("W2N1"), expiry_tick));
+        assert!(!router.is_blacklisted(room_name("W2N2"), expiry_tick));
+        let route = router.find_route(&route_finder, from, to, RoutePrefs::default(), expiry_tick, no_designation).unwrap();
+        assert_eq!(route, vec![room_name("W2N1"), to]);
+    }
+}
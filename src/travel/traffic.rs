@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use rustc_hash::{FxHashMap, FxHashSet};
 use screeps::{HasPosition, ObjectId, Position};
 use std::collections::hash_map::Entry;
@@ -6,7 +6,7 @@ use std::hash::Hash;
 use std::iter::zip;
 use std::rc::Rc;
 use enum_iterator::all;
-use log::warn;
+use log::{debug, warn};
 use crate::geometry::position_utils::PositionUtils;
 use crate::kernel::sleep::sleep;
 use crate::room_states::room_states::{with_room_state, with_room_states, RoomStates};
@@ -20,12 +20,24 @@ use crate::creeps::generic_creep::GenericCreep;
 use crate::geometry::grid_direction::{direction_to_offset, GridDirection};
 use crate::geometry::rect::{ball, Rect};
 use crate::geometry::room_xy::RoomXYUtils;
+use crate::construction::road_usage::register_step;
 use crate::travel::surface::Surface;
-use crate::travel::travel::find_path;
+use crate::travel::move_intents::with_move_intents;
+use crate::travel::path_cache::path_cache_stats;
+use crate::travel::travel::find_path_when_stuck;
+use crate::utils::game_tick::game_tick;
 use crate::utils::result_utils::ResultUtils;
 
 const DEBUG: bool = true;
 
+/// Number of ticks between logging the path cache stats.
+const PATH_CACHE_STATS_INTERVAL: u32 = 100;
+
+thread_local! {
+    /// The last tick in which the move intents were issued.
+    static MOVE_INTENTS_TICK: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
 enum RepathData {
     Blocked,
     Adjusted {
@@ -45,6 +57,10 @@ pub fn register_creep_pos(creep_ref: &CreepRef) {
         if creep.travel_state.pos == expected_pos {
             creep.travel_state.path.pop();
 
+            with_room_state(creep_pos.room_name(), |room_state| {
+                register_step(&mut room_state.road_usage, creep_pos.xy());
+            });
+
             if let Some(&next_pos) = creep.travel_state.path.last() {
                 let next_pos_dist = next_pos.get_range_to(creep.travel_state.pos);
                 if next_pos_dist != 1 {
@@ -79,6 +95,15 @@ pub fn register_creep_pos(creep_ref: &CreepRef) {
             repath_required = true;
         }
     }
+
+    // Creeps blocked for a few ticks, e.g., by a new construction site or other creeps, repath
+    // around the tile they were unable to enter.
+    let fatigued = creep.fatigue().is_ok_and(|fatigue| fatigue > 0);
+    let stuck_repath = creep.travel_state.update_stuck_state(creep_pos, !fatigued);
+    if let Some(stuck_repath) = stuck_repath {
+        local_debug!("Creep {} is stuck at {}. Repathing with {:?}.", creep.name, creep_pos.f(), stuck_repath);
+        repath_required = true;
+    }
     
     if let Some(travel_spec) = creep.travel_state.spec.as_ref() {
         if travel_spec.is_in_target_rect(creep_pos) {
@@ -94,7 +119,7 @@ pub fn register_creep_pos(creep_ref: &CreepRef) {
             creep.travel_state.arrival_broadcast.broadcast(Ok(creep_pos));
         } else if repath_required {
             local_debug!("Repathing.");
-            match find_path(creep_pos, travel_spec) {
+            match find_path_when_stuck(creep_pos, travel_spec, stuck_repath) {
                 Ok(path) => {
                     // Reusing the existing broadcast.
                     local_debug!("Chosen path: {:?}.", creep.travel_state.path);
@@ -120,8 +145,8 @@ pub async fn move_creeps() {
         //      In this case, it might be preferable to just wait and go at a fraction of the speed,
         //      e.g., when the alternative is swamp.
         let mut fatigued_creeps = FxHashSet::default();
-        // TODO Also include immovable creeps.
-        let mut fatigued_creeps_pos = FxHashSet::default();
+        // Positions of fatigued and immovable creeps. These creeps cannot be shoved away.
+        let mut immobile_creeps_pos = FxHashSet::default();
 
         for_each_creep(|creep_ref| {
             let mut creep = creep_ref.borrow_mut();
@@ -133,7 +158,9 @@ pub async fn move_creeps() {
             if fatigue > 0 {
                 target_pos = current_pos;
                 fatigued_creeps.insert(creep_id);
-                fatigued_creeps_pos.insert(current_pos);
+                immobile_creeps_pos.insert(current_pos);
+            } else if creep.get_ticks_per_tile(Surface::Plain) == u8::MAX {
+                immobile_creeps_pos.insert(current_pos);
             }
 
             match creeps_by_target_pos.entry(target_pos) {
@@ -153,62 +180,103 @@ pub async fn move_creeps() {
         });
 
         with_room_states(|room_states| {
-            resolve_conflicts(room_states, creeps_by_target_pos, conflicted_creeps, fatigued_creeps_pos);
+            resolve_conflicts(room_states, creeps_by_target_pos, conflicted_creeps, immobile_creeps_pos);
         });
 
         // TODO Visualization of creep paths.
-        for_each_creep(|creep_ref| {
-            let mut creep = creep_ref.borrow_mut();
+        issue_move_intents(&fatigued_creeps);
 
-            if let Some(&next_pos) = creep.travel_state.path.last() {
-                let creep_id = u!(creep.screeps_id());
-                let fatigued = fatigued_creeps.contains(&creep_id);
-                if DEBUG {
-                    if let Some(travel_spec) = creep.travel_state.spec.as_ref() {
-                        local_debug!(
-                            "Moving creep {} towards {} (range {}). Current tile is {} and next tile is {}. Fatigued: {}.",
-                            creep.name,
-                            travel_spec.target.f(),
-                            travel_spec.range,
-                            creep.travel_state.pos.f(),
-                            next_pos.f(),
-                            fatigued
-                        );
-                    } else {
-                        local_debug!(
-                            "Moving creep {} out of the way from {} to {}. Fatigued: {}.",
-                            creep.name,
-                            creep.travel_state.pos.f(),
-                            next_pos.f(),
-                            fatigued
-                        );
-                    }
-                }
-                if next_pos == creep.travel_state.pos {
-                    // `creep_pos` being equal to the current position means the creep is supposed
-                    // to stay put for a tick as a result of conflict resolution. In this case,
-                    // the position is simply removed from the path.
-                    creep.travel_state.path.pop();
-                } else if !fatigued {
-                    // If the creep is fatigued, it cannot move. Returning next position to
-                    // the path. Otherwise, the creep moves along the path.
-                    let direction = u!(creep.travel_state.pos.get_direction_to(next_pos));
-                    let result = creep.move_direction(direction);
-                    if result.is_err() {
-                        result.warn_if_err(&format!(
-                            "Could not move creep {} to {}",
-                            creep.name,
-                            direction
-                        ));
-                        // If the move failed, returning the pos to the next position.
-                        creep.travel_state.path.push(next_pos);
-                    }
+        if game_tick().is_multiple_of(PATH_CACHE_STATS_INTERVAL) {
+            debug!("Path cache stats: {:?}.", path_cache_stats());
+        }
+
+        sleep(1).await;
+    }
+}
+
+/// Issues move intents of all creeps along their paths, except the fatigued ones.
+fn issue_move_intents(fatigued_creeps: &FxHashSet<ObjectId<screeps::Creep>>) {
+    let mut moving_creeps = FxHashMap::default();
+    for_each_creep(|creep_ref| {
+        let mut creep = creep_ref.borrow_mut();
+
+        if let Some(&next_pos) = creep.travel_state.path.last() {
+            let creep_id = u!(creep.screeps_id());
+            let fatigued = fatigued_creeps.contains(&creep_id);
+            if DEBUG {
+                if let Some(travel_spec) = creep.travel_state.spec.as_ref() {
+                    local_debug!(
+                        "Moving creep {} towards {} (range {}). Current tile is {} and next tile is {}. Fatigued: {}.",
+                        creep.name,
+                        travel_spec.target.f(),
+                        travel_spec.range,
+                        creep.travel_state.pos.f(),
+                        next_pos.f(),
+                        fatigued
+                    );
+                } else {
+                    local_debug!(
+                        "Moving creep {} out of the way from {} to {}. Fatigued: {}.",
+                        creep.name,
+                        creep.travel_state.pos.f(),
+                        next_pos.f(),
+                        fatigued
+                    );
                 }
             }
-        });
+            if next_pos == creep.travel_state.pos {
+                // `creep_pos` being equal to the current position means the creep is supposed
+                // to stay put for a tick as a result of conflict resolution. In this case,
+                // the position is simply removed from the path.
+                creep.travel_state.path.pop();
+            } else if !fatigued {
+                // If the creep is fatigued, it cannot move. Otherwise, the creep moves along
+                // the path.
+                let creep_pos = creep.travel_state.pos;
+                with_move_intents(|move_intents| move_intents.register(creep_pos, next_pos));
+                moving_creeps.insert(creep_pos, creep_ref.clone());
+            }
+        }
+    });
 
-        sleep(1).await;
+    // Creeps shoved out of the way move before the creeps moving into their tiles.
+    for (creep_pos, next_pos) in with_move_intents(|move_intents| move_intents.ordered()) {
+        let Some(creep_ref) = moving_creeps.get(&creep_pos) else {
+            continue;
+        };
+        let mut creep = creep_ref.borrow_mut();
+        let direction = u!(creep_pos.get_direction_to(next_pos));
+        let result = creep.move_direction(direction);
+        if result.is_err() {
+            result.warn_if_err(&format!(
+                "Could not move creep {} to {}",
+                creep.name,
+                direction
+            ));
+            // If the move failed, returning the pos to the next position.
+            creep.travel_state.path.push(next_pos);
+        }
     }
+
+    MOVE_INTENTS_TICK.with(|tick| tick.set(Some(game_tick())));
+}
+
+/// Issues the move intents without resolving conflicts when `move_creeps` has not done so yet in
+/// this tick, to be used in the last call.
+pub fn issue_pending_move_intents() {
+    if MOVE_INTENTS_TICK.with(|tick| tick.get()) == Some(game_tick()) {
+        return;
+    }
+
+    let mut fatigued_creeps = FxHashSet::default();
+    for_each_creep(|creep_ref| {
+        let mut creep = creep_ref.borrow_mut();
+        if creep.fatigue().is_ok_and(|fatigue| fatigue > 0) {
+            fatigued_creeps.insert(u!(creep.screeps_id()));
+        }
+    });
+
+    issue_move_intents(&fatigued_creeps);
 }
 
 // TODO Never resolve conflicts by shoving creeps into room border.
@@ -274,6 +342,9 @@ where
         let intent_cost = 1000u32;
         // The cost of one tick worth of travelling or waiting.
         let ttl_cost = 1000u32;
+        // The cost of a creep without a path stepping onto a road, where it is likely to be in
+        // the way of other creeps. Higher than the TTL needed to move through any other tile.
+        let idle_on_road_cost = 500u32;
         
         // A shortest path in 3 x 3 square can have at most 4 moves. This can cost as much as
         // 4 * 49 * 5 * TTL cost. This value has to be higher than that to take priority.
@@ -479,8 +550,14 @@ where
                         // The cost of movement is an intent and number of TTL lost.
                         if target_rect.contains(xy) {
                             // Simply moving to another location where work can be done.
-                            // Slightly preferring tiles from which the creep can move faster.
-                            intent_cost + creep.get_ticks_per_tile(surface) as u32
+                            // Creeps without a path are shoved off the roads so that they do not
+                            // block them again. Otherwise, slightly preferring tiles from which
+                            // the creep can move faster.
+                            if path_len == 0 && surface == Surface::Road {
+                                intent_cost + idle_on_road_cost
+                            } else {
+                                intent_cost + creep.get_ticks_per_tile(surface) as u32
+                            }
                         } else {
                             // Wasting a number of ticks on travel instead of work.
                             intent_cost + creep.get_ticks_per_tile(surface) as u32 * ttl_cost
@@ -642,14 +719,16 @@ mod tests {
     use log::LevelFilter::Trace;
     use log::trace;
     use rustc_hash::{FxHashMap, FxHashSet};
-    use screeps::{Part, Position, RoomName};
-    use screeps::Terrain::{Swamp};
+    use screeps::{ObjectId, Part, Position, RoomName};
+    use screeps::StructureType::Road;
+    use screeps::Terrain::{Swamp, Wall};
     use crate::creeps::generic_creep::GenericCreep;
     use crate::creeps::test_creep::TestCreep;
     use crate::geometry::position_utils::PositionUtils;
     use crate::logging::init_logging;
     use crate::room_states::room_state::test_empty_unowned_room_name;
     use crate::room_states::room_states::test_room_states;
+    use crate::travel::move_intents::MoveIntents;
     use crate::travel::traffic::resolve_conflicts;
     use crate::travel::travel_spec::TravelSpec;
 
@@ -790,4 +869,79 @@ mod tests {
             vec![Position::new_from_raw(10, 10, test_room_name)]
         );
     }
+
+    #[test]
+    fn test_idle_creep_shoved_off_road_in_corridor() {
+        init_logging(Trace);
+
+        let mut creeps_by_target_pos = FxHashMap::default();
+        let mut conflicted_creeps = FxHashMap::default();
+
+        let test_room_name = test_empty_unowned_room_name();
+
+        let mut room_states = test_room_states();
+        let room_state = room_states.get_mut(&test_room_name).unwrap();
+        // A corridor of roads with an alcove diagonally behind the idle creep.
+        for x in 4..=16u8 {
+            room_state.terrain.set((x, 9).try_into().unwrap(), Wall);
+            if x != 9 {
+                room_state.terrain.set((x, 11).try_into().unwrap(), Wall);
+            }
+        }
+        for x in 5..=15u8 {
+            room_state
+                .structures
+                .entry(Road)
+                .or_default()
+                .insert((x, 10).try_into().unwrap(), ObjectId::from_packed(x as u128));
+        }
+        room_state.update_structures_matrix();
+
+        let test_idle_creep = Rc::new(RefCell::new(TestCreep::new(
+            1,
+            Position::new_from_raw(10, 10, test_room_name),
+            vec![Part::Work, Part::Move].into()
+        )));
+
+        let test_hauler = Rc::new(RefCell::new(TestCreep::new(
+            2,
+            Position::new_from_raw(9, 10, test_room_name),
+            vec![Part::Carry, Part::Move].into()
+        )));
+        test_hauler.borrow_mut().get_travel_state_mut().path = (10..=13u8)
+            .rev()
+            .map(|x| Position::new_from_raw(x, 10, test_room_name))
+            .collect();
+        test_hauler.borrow_mut().get_travel_state_mut().spec = Some(TravelSpec::new(
+            Position::new_from_raw(13, 10, test_room_name),
+            0
+        ));
+
+        creeps_by_target_pos.insert(test_idle_creep.borrow().get_travel_state().pos, (1, test_idle_creep.clone()));
+        conflicted_creeps.insert(1, test_idle_creep.clone());
+        conflicted_creeps.insert(2, test_hauler.clone());
+
+        resolve_conflicts(&room_states, creeps_by_target_pos, conflicted_creeps, FxHashSet::default());
+
+        // The idle creep steps aside into the alcove rather than further along the road.
+        assert_eq!(
+            test_idle_creep.borrow().get_travel_state().path,
+            vec![Position::new_from_raw(9, 11, test_room_name)]
+        );
+        assert_eq!(
+            test_hauler.borrow().get_travel_state().path.last(),
+            Some(&Position::new_from_raw(10, 10, test_room_name))
+        );
+
+        // The shove is issued before the hauler's move.
+        let mut move_intents = MoveIntents::default();
+        for creep_ref in [&test_hauler, &test_idle_creep] {
+            let creep = creep_ref.borrow();
+            move_intents.register(creep.get_travel_state().pos, *creep.get_travel_state().path.last().unwrap());
+        }
+        assert_eq!(move_intents.ordered(), vec![
+            (Position::new_from_raw(10, 10, test_room_name), Position::new_from_raw(9, 11, test_room_name)),
+            (Position::new_from_raw(9, 10, test_room_name), Position::new_from_raw(10, 10, test_room_name)),
+        ]);
+    }
 }
\ No newline at end of file
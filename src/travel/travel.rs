@@ -1,21 +1,44 @@
 use crate::creeps::creeps::CreepRef;
 use crate::kernel::broadcast::Broadcast;
 use crate::local_debug;
-use screeps::{FindPathOptions, Position};
+use screeps::{CostMatrix, FindPathOptions, Position};
 use screeps::Path::Vectorized;
 use screeps::pathfinder::MultiRoomCostResult;
 use crate::errors::XiError;
 use crate::creeps::creep_body::CreepBody;
-use crate::errors::XiError::PathNotFound;
+use crate::errors::XiError::{PathNotFound, RouteNotFound};
 use crate::geometry::position_utils::PositionUtils;
 use crate::geometry::room_xy::RoomXYUtils;
+use crate::room_states::room_states::with_room_state;
+use crate::travel::path_cache::find_cached_path;
+use crate::travel::route::{exit_xys, find_route, RoutePrefs};
 use crate::travel::step_utils::StepUtils;
 use crate::travel::surface::Surface;
 use crate::travel::travel_spec::TravelSpec;
+use crate::travel::travel_state::StuckRepath;
 
 const DEBUG: bool = true;
 
+/// The cost of the tile a stuck creep was unable to enter, making paths around it preferred
+/// unless they are much longer.
+const BLOCKED_TILE_COST: u8 = 20;
+
 pub fn travel(creep_ref: &CreepRef, travel_spec: TravelSpec) -> Broadcast<Result<Position, XiError>> {
+    travel_with_path_finder(creep_ref, travel_spec, find_path)
+}
+
+/// Travels like `travel` to given range of the target, but reuses paths cached by other creeps
+/// starting nearby. A fresh path is found when the cached one was invalidated by structure
+/// changes or the creep deviated from it. Like all paths to other rooms, it leads room by room
+/// along the route found by `find_route`.
+pub fn move_to_cached(creep_ref: &CreepRef, target: Position, range: u8) -> Broadcast<Result<Position, XiError>> {
+    travel_with_path_finder(creep_ref, TravelSpec::new(target, range), find_cached_path)
+}
+
+fn travel_with_path_finder<F>(creep_ref: &CreepRef, travel_spec: TravelSpec, path_finder: F) -> Broadcast<Result<Position, XiError>>
+where
+    F: FnOnce(Position, &TravelSpec) -> Result<Vec<Position>, XiError>,
+{
     let mut creep = creep_ref.borrow_mut();
     let creep_pos = creep.travel_state.pos;
     local_debug!(
@@ -31,7 +54,7 @@ pub fn travel(creep_ref: &CreepRef, travel_spec: TravelSpec) -> Broadcast<Result
     } else {
         creep.travel_state.arrived = false;
         
-        match find_path(creep_pos, &travel_spec) {
+        match path_finder(creep_pos, &travel_spec) {
             Ok(path) => {
                 local_debug!("Chosen path: {:?}.", creep.travel_state.path);
                 creep.travel_state.spec = Some(travel_spec);
@@ -48,54 +71,90 @@ pub fn travel(creep_ref: &CreepRef, travel_spec: TravelSpec) -> Broadcast<Result
 }
 
 pub fn find_path(start_pos: Position, travel_spec: &TravelSpec) -> Result<Vec<Position>, XiError> {
+    find_path_when_stuck(start_pos, travel_spec, None)
+}
+
+/// Finds a path like `find_path`, but for a creep that got stuck, penalizing the tile it was
+/// unable to enter and possibly avoiding other creeps.
+pub fn find_path_when_stuck(
+    start_pos: Position,
+    travel_spec: &TravelSpec,
+    stuck_repath: Option<StuckRepath>
+) -> Result<Vec<Position>, XiError> {
+    if start_pos.room_name() != travel_spec.target.room_name() {
+        return find_path_to_next_room(start_pos, travel_spec, stuck_repath);
+    }
+
+    let blocked_pos = stuck_repath.and_then(|stuck_repath| stuck_repath.blocked_pos);
     let options = FindPathOptions::<_, MultiRoomCostResult>::default()
-        .ignore_creeps(true)
-        .serialize(false);
+        .ignore_creeps(!stuck_repath.is_some_and(|stuck_repath| stuck_repath.avoid_creeps))
+        .serialize(false)
+        .cost_callback(move |room_name, cost_matrix: CostMatrix| {
+            match blocked_pos.filter(|pos| pos.room_name() == room_name) {
+                Some(pos) => {
+                    cost_matrix.set(pos.x().u8(), pos.y().u8(), BLOCKED_TILE_COST);
+                    MultiRoomCostResult::CostMatrix(cost_matrix)
+                }
+                None => MultiRoomCostResult::Default,
+            }
+        });
     let steps = start_pos.find_path_to(&travel_spec.target, Some(options));
     local_debug!("Path from {} to {}: {:?}.", start_pos.f(), travel_spec.target.f(), steps);
     // TODO Check if the full path was actually found.
     if let Vectorized(mut steps) = steps {
         let room_name = start_pos.room_name();
 
-        if room_name == travel_spec.target.room_name() {
-            // Removing the last step while the second-to-last is in the target rect.
-            while steps.get(steps.len() - 2).map_or(false, |step| travel_spec.is_in_target_rect(step.pos(room_name))) {
-                steps.pop();
-            }
+        // Removing the last step while the second-to-last is in the target rect.
+        while steps.get(steps.len() - 2).map_or(false, |step| travel_spec.is_in_target_rect(step.pos(room_name))) {
+            steps.pop();
         }
 
         // The path coming from `find_path_to` is not a stack.
         steps.reverse();
 
-        // TODO Multi-room path.
-        let mut path = steps.into_iter()
+        let path = steps.into_iter()
                 .map(|step| step.pos(room_name))
                 .collect::<Vec<_>>();
 
-        if room_name == travel_spec.target.room_name() {
-            if path.first().map_or(false, |&pos| travel_spec.is_in_target_rect(pos)) {
-                Ok(path)
-            } else {
-                local_debug!("The last tile in the path is not in target rect. Only a partial path was found.");
-                Err(PathNotFound)
-            }
+        if path.first().map_or(false, |&pos| travel_spec.is_in_target_rect(pos)) {
+            Ok(path)
         } else {
-            let ends_on_room_boundary = path.first().map_or(false, |&pos| {
-                pos.xy().is_on_boundary()
-            });
-            if ends_on_room_boundary {
-                path[0] = path[0].matching_boundary_pos();
-                Ok(path)
-            } else {
-                local_debug!("The last tile in the path is not on the room boundary.");
-                Err(PathNotFound)
-            }
+            local_debug!("The last tile in the path is not in target rect. Only a partial path was found.");
+            Err(PathNotFound)
         }
     } else {
         unreachable!();
     }
 }
 
+/// Finds a path to the exit of the room towards the next room on the route to the target's room.
+/// The last position of the path is the matching one in the next room, where the creep appears
+/// after stepping on the exit. The creep repaths from there.
+fn find_path_to_next_room(
+    start_pos: Position,
+    travel_spec: &TravelSpec,
+    stuck_repath: Option<StuckRepath>
+) -> Result<Vec<Position>, XiError> {
+    let room_name = start_pos.room_name();
+    let route = find_route(room_name, travel_spec.target.room_name(), RoutePrefs::default())?;
+    let next_room_name = *route.first().ok_or(RouteNotFound)?;
+    let exit_xys = with_room_state(room_name, |room_state| exit_xys(room_state, next_room_name))
+        .ok_or(PathNotFound)??;
+    let exit_xy = exit_xys
+        .into_iter()
+        .filter(|&xy| xy != start_pos.xy())
+        .min_by_key(|&xy| xy.dist(start_pos.xy()))
+        .ok_or(PathNotFound)?;
+    local_debug!(
+        "Pathing from {} to {} towards {} on the route {:?}.",
+        start_pos.f(), exit_xy, next_room_name, route
+    );
+
+    let mut path = find_path_when_stuck(start_pos, &TravelSpec::new(exit_xy.to_pos(room_name), 0), stuck_repath)?;
+    path[0] = path[0].matching_boundary_pos();
+    Ok(path)
+}
+
 /// Best effort estimate how many ticks it takes to travel `start_range` tiles from source to
 /// `range` from target with a creep with given `body`. Takes into consideration if roads are
 /// expected or not.
@@ -3,6 +3,20 @@ use crate::errors::XiError;
 use crate::kernel::broadcast::Broadcast;
 use crate::travel::travel_spec::TravelSpec;
 
+/// Number of ticks without progress after which a creep is considered stuck and repaths.
+const STUCK_TICKS: u32 = 3;
+/// Number of repaths of a stuck creep that ignore other creeps. The following ones avoid them.
+const MAX_STUCK_REPATHS_IGNORING_CREEPS: u8 = 2;
+
+/// How to repath a creep that got stuck.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StuckRepath {
+    /// The tile the creep was unable to enter, penalized in the new path.
+    pub blocked_pos: Option<Position>,
+    /// Whether other creeps are treated as obstacles.
+    pub avoid_creeps: bool,
+}
+
 #[derive(Debug)]
 pub struct TravelState {
     /// Current position, updated near the beginning of the tick.
@@ -15,8 +29,12 @@ pub struct TravelState {
     pub arrived: bool,
     /// Broadcast that the creep arrived at travel spec location.
     pub arrival_broadcast: Broadcast<Result<Position, XiError>>,
-    // /// Number of ticks for which the creep was unable to make any progress when moving.
-    // pub no_progress_ticks: u32,
+    /// Positions of the creep in the last three ticks, the most recent first.
+    pub last_positions: [Position; 3],
+    /// Number of ticks for which the creep was unable to make any progress when moving.
+    pub stuck_ticks: u32,
+    /// Number of repaths since the creep last made progress.
+    pub stuck_repaths: u8,
 }
 
 impl TravelState {
@@ -27,6 +45,9 @@ impl TravelState {
             path: Vec::default(),
             arrived: true,
             arrival_broadcast: Broadcast::default(),
+            last_positions: [pos; 3],
+            stuck_ticks: 0,
+            stuck_repaths: 0,
         }
     }
     
@@ -34,6 +55,36 @@ impl TravelState {
         self.path.last().cloned().unwrap_or(self.pos)
     }
     
+    /// Records the current position of the creep and decides whether it got stuck and needs to
+    /// repath. The creep makes progress when it enters a tile it was not on in the last three ticks,
+    /// so oscillating between tiles is not progress. Creeps not trying to move, e.g., fatigued ones,
+    /// are not getting stuck.
+    pub fn update_stuck_state(&mut self, pos: Position, moving: bool) -> Option<StuckRepath> {
+        let progressed = !self.last_positions.contains(&pos);
+        self.last_positions = [pos, self.last_positions[0], self.last_positions[1]];
+
+        if progressed || self.path.is_empty() {
+            self.stuck_ticks = 0;
+            self.stuck_repaths = 0;
+            return None;
+        }
+        if !moving {
+            return None;
+        }
+
+        self.stuck_ticks += 1;
+        if self.stuck_ticks < STUCK_TICKS {
+            return None;
+        }
+
+        self.stuck_ticks = 0;
+        self.stuck_repaths = self.stuck_repaths.saturating_add(1);
+        Some(StuckRepath {
+            blocked_pos: self.path.iter().rev().copied().find(|&path_pos| path_pos != pos),
+            avoid_creeps: self.stuck_repaths > MAX_STUCK_REPATHS_IGNORING_CREEPS,
+        })
+    }
+
     pub fn is_in_target_rect(&self) -> bool {
         if let Some(spec) = &self.spec {
             spec.is_in_target_rect(self.pos)
@@ -41,4 +92,71 @@ impl TravelState {
             true
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use screeps::{Position, RoomName};
+    use crate::geometry::position_utils::PositionUtils;
+    use crate::travel::travel_state::{StuckRepath, TravelState};
+
+    fn pos(x: u8, y: u8) -> Position {
+        Position::new_from_raw(x, y, RoomName::new("W1N1").unwrap())
+    }
+
+    #[test]
+    fn test_progressing_creep_not_stuck() {
+        let mut travel_state = TravelState::new(pos(10, 10));
+        travel_state.path = (11..=20).rev().map(|x| pos(x, 10)).collect();
+        for x in 11..=15 {
+            travel_state.path.pop();
+            assert_eq!(travel_state.update_stuck_state(pos(x, 10), true), None);
+            assert_eq!(travel_state.stuck_ticks, 0);
+        }
+        assert_eq!(travel_state.last_positions, [pos(15, 10), pos(14, 10), pos(13, 10)]);
+    }
+
+    #[test]
+    fn test_blocked_creep_repaths_and_escalates() {
+        let mut travel_state = TravelState::new(pos(10, 10));
+        travel_state.path = vec![pos(12, 10), pos(11, 10)];
+
+        let mut repaths = Vec::new();
+        for _ in 0..9 {
+            if let Some(stuck_repath) = travel_state.update_stuck_state(pos(10, 10), true) {
+                repaths.push(stuck_repath);
+            }
+        }
+        let penalized_repath = StuckRepath {
+            blocked_pos: Some(pos(11, 10)),
+            avoid_creeps: false,
+        };
+        assert_eq!(repaths, vec![
+            penalized_repath,
+            penalized_repath,
+            StuckRepath {
+                blocked_pos: Some(pos(11, 10)),
+                avoid_creeps: true,
+            },
+        ]);
+
+        // Progress resets the escalation.
+        assert_eq!(travel_state.update_stuck_state(pos(11, 11), true), None);
+        assert_eq!(travel_state.stuck_repaths, 0);
+    }
+
+    #[test]
+    fn test_oscillating_creep_stuck() {
+        let mut travel_state = TravelState::new(pos(10, 10));
+        travel_state.path = vec![pos(12, 10), pos(11, 10)];
+        assert_eq!(travel_state.update_stuck_state(pos(11, 11), true), None);
+        assert_eq!(travel_state.update_stuck_state(pos(10, 10), true), None);
+        assert_eq!(travel_state.update_stuck_state(pos(11, 11), true), None);
+        assert_eq!(travel_state.stuck_ticks, 2);
+        // Staying put because of fatigue does not count.
+        assert_eq!(travel_state.update_stuck_state(pos(11, 11), false), None);
+        assert_eq!(travel_state.stuck_ticks, 2);
+        let stuck_repath = travel_state.update_stuck_state(pos(10, 10), true).unwrap();
+        assert_eq!(stuck_repath.blocked_pos, Some(pos(11, 10)));
+        assert!(!stuck_repath.avoid_creeps);
+    }
+}
@@ -9,6 +9,7 @@ pub mod local_debug;
 pub mod get_object_by_id;
 pub mod unchecked_transferable;
 pub mod unchecked_withdrawable;
+pub mod unchecked_has_store;
 pub mod single_tick_cache;
 pub mod priority;
 pub mod uid;
@@ -21,4 +22,6 @@ pub mod permutation;
 pub mod sampling;
 pub mod avg_vector;
 pub mod debug_mark;
-pub mod decay;
\ No newline at end of file
+pub mod decay;
+#[cfg(test)]
+pub mod test_utils;
\ No newline at end of file
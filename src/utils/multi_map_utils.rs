@@ -1,7 +1,7 @@
 use crate::u;
 use std::collections::btree_map::Entry as BEntry;
 use std::collections::hash_map::Entry as HEntry;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::hash::{BuildHasher, Hash};
 use std::iter::once;
 
@@ -141,3 +141,59 @@ where
         }
     }
 }
+
+/// Multimap with values under each key in FIFO order, i.e., values are pushed to the back and popped from the front.
+impl<K, V> MultiMapUtils<K, V> for BTreeMap<K, VecDeque<V>>
+where
+    K: Ord,
+{
+    fn push_or_insert(&mut self, key: K, value: V) {
+        self.entry(key).or_default().push_back(value);
+    }
+
+    fn pop_from_key(&mut self, key: K) -> Option<V> {
+        match self.entry(key) {
+            BEntry::Occupied(mut e) => {
+                let result = u!(e.get_mut().pop_front());
+                if e.get().is_empty() {
+                    e.remove();
+                }
+                Some(result)
+            }
+            BEntry::Vacant(_) => None,
+        }
+    }
+}
+
+impl<K, V> OrderedMultiMapUtils<K, V> for BTreeMap<K, VecDeque<V>>
+where
+    K: Ord + Clone,
+{
+    fn pop_from_first(&mut self) -> Option<(K, V)> {
+        match self.first_entry() {
+            Some(mut e) => {
+                let key = e.key().clone();
+                let value = u!(e.get_mut().pop_front());
+                if e.get().is_empty() {
+                    e.remove();
+                }
+                Some((key, value))
+            }
+            None => None,
+        }
+    }
+
+    fn pop_from_last(&mut self) -> Option<(K, V)> {
+        match self.last_entry() {
+            Some(mut e) => {
+                let key = e.key().clone();
+                let value = u!(e.get_mut().pop_front());
+                if e.get().is_empty() {
+                    e.remove();
+                }
+                Some((key, value))
+            }
+            None => None,
+        }
+    }
+}
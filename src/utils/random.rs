@@ -1,9 +1,122 @@
-#[cfg(not(test))]
+use std::cell::Cell;
+use screeps::RoomName;
+use crate::utils::game_tick::game_tick;
+
+thread_local! {
+    /// The random source of `random`, seeded with the game tick it is first used in.
+    static GLOBAL_RANDOM: SeededRandom = SeededRandom::new(game_tick() as u64);
+    /// The seed set with `set_seed`, used instead of the default seed by the next call to `seed_for_room` only.
+    static SEED_OVERRIDE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// A random number in range `[0, 1)` from the global seeded random source.
 pub fn random() -> f64 {
-    js_sys::Math::random()
+    GLOBAL_RANDOM.with(|rng| rng.random())
+}
+
+/// Reseeds the global random source and makes the next call to `seed_for_room` return the seed, e.g., to replay
+/// planning of a room with the seed from a bug report.
+pub fn set_seed(seed: u64) {
+    SEED_OVERRIDE.with(|seed_override| seed_override.set(Some(seed)));
+    GLOBAL_RANDOM.with(|rng| rng.set_seed(seed));
+}
+
+/// The seed of random computations concerning the room, e.g., its planning. Derived from the game
+/// tick and the room name unless overridden with `set_seed`. The override is cleared once used so that it only applies
+/// to a single room.
+pub fn seed_for_room(room_name: RoomName) -> u64 {
+    SEED_OVERRIDE
+        .with(|seed_override| seed_override.take())
+        .unwrap_or_else(|| ((game_tick() as u64) << 32) ^ room_name.packed_repr() as u64)
+}
+
+/// A source of random numbers in range `[0, 1)` that can be injected into code which should also be runnable outside
+/// of the game, e.g., deterministically in tests.
+pub trait RandomSource {
+    fn random(&self) -> f64;
+}
+
+/// Random source using the global `random` function.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GlobalRandom;
+
+impl RandomSource for GlobalRandom {
+    fn random(&self) -> f64 {
+        random()
+    }
+}
+
+/// Deterministic xorshift64* random source.
+#[derive(Clone, Debug)]
+pub struct SeededRandom {
+    state: Cell<u64>,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        SeededRandom {
+            state: Cell::new(Self::initial_state(seed)),
+        }
+    }
+
+    pub fn set_seed(&self, seed: u64) {
+        self.state.set(Self::initial_state(seed));
+    }
+
+    fn initial_state(seed: u64) -> u64 {
+        // The state of xorshift may not be zero.
+        (seed ^ 0x9e37_79b9_7f4a_7c15).max(1)
+    }
+
+    fn next_u64(&self) -> u64 {
+        let mut x = self.state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn random(&self) -> f64 {
+        // Using the top 53 bits to fill the mantissa.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
 }
 
 #[cfg(test)]
-pub fn random() -> f64 {
-    rand::random::<f64>()
-}
\ No newline at end of file
+mod tests {
+    use screeps::RoomName;
+    use crate::utils::random::{random, seed_for_room, set_seed, RandomSource, SeededRandom};
+
+    #[test]
+    fn test_seeded_random_is_deterministic_and_in_range() {
+        let a = SeededRandom::new(42);
+        let b = SeededRandom::new(42);
+        for _ in 0..1000 {
+            let x = a.random();
+            assert_eq!(x, b.random());
+            assert!((0.0..1.0).contains(&x));
+        }
+    }
+
+    #[test]
+    fn test_set_seed_replays_global_random() {
+        set_seed(7);
+        let numbers = (0..10).map(|_| random()).collect::<Vec<_>>();
+        set_seed(7);
+        assert_eq!((0..10).map(|_| random()).collect::<Vec<_>>(), numbers);
+
+        let seeded = SeededRandom::new(7);
+        assert_eq!((0..10).map(|_| seeded.random()).collect::<Vec<_>>(), numbers);
+    }
+
+    #[test]
+    fn test_seed_override_is_used_once() {
+        let room_name = RoomName::new("W1N1").unwrap();
+        set_seed(7);
+        assert_eq!(seed_for_room(room_name), 7);
+        assert_ne!(seed_for_room(room_name), 7);
+    }
+}
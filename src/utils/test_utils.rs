@@ -0,0 +1,11 @@
+use screeps::{RoomName, RoomXY};
+
+/// Room coordinates for use in tests, panicking when out of the room bounds.
+pub fn xy(x: u8, y: u8) -> RoomXY {
+    (x, y).try_into().unwrap()
+}
+
+/// Room name for use in tests, panicking when it is invalid.
+pub fn room_name(name: &str) -> RoomName {
+    RoomName::new(name).unwrap()
+}
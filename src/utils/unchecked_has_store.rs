@@ -0,0 +1,12 @@
+use js_sys::Reflect;
+use screeps::{HasStore, RoomObject, Store};
+use wasm_bindgen::JsCast;
+use crate::u;
+
+pub struct UncheckedHasStore<'a>(pub &'a RoomObject);
+
+impl HasStore for UncheckedHasStore<'_> {
+    fn store(&self) -> Store {
+        u!(Reflect::get(self.0, &"store".into())).unchecked_into()
+    }
+}
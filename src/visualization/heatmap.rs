@@ -0,0 +1,185 @@
+use std::cell::RefCell;
+use rustc_hash::FxHashMap;
+use screeps::{RoomName, RoomXY};
+use crate::algorithms::matrix_common::MatrixCommon;
+use crate::algorithms::weighted_distance_matrix::obstacle_cost;
+use crate::visualization::room_visualization::{visualize, Visualization};
+
+/// Colors of the tiles of a heatmap, from the lowest to the highest value.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Palette {
+    /// Blue regardless of the value.
+    Blue,
+    /// Red regardless of the value.
+    Red,
+    /// From blue through purple to red.
+    Heat,
+}
+
+impl Palette {
+    /// The color of a tile with given intensity between 0 and 1.
+    pub fn color(self, intensity: f32) -> String {
+        let red = (255.0 * intensity.clamp(0.0, 1.0)).round() as u8;
+        let (r, g, b) = match self {
+            Palette::Blue => (0, 0, 255),
+            Palette::Red => (255, 0, 0),
+            Palette::Heat => (red, 0, 255 - red),
+        };
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+/// Values that can be shown in a heatmap. `None` for the values that are not shown, i.e., the
+/// obstacle cost.
+pub trait HeatmapValue: Copy + PartialEq {
+    fn heatmap_value(self) -> Option<f32>;
+}
+
+impl HeatmapValue for u8 {
+    fn heatmap_value(self) -> Option<f32> {
+        (self != obstacle_cost::<u8>()).then_some(self as f32)
+    }
+}
+
+impl HeatmapValue for u16 {
+    fn heatmap_value(self) -> Option<f32> {
+        (self != obstacle_cost::<u16>()).then_some(self as f32)
+    }
+}
+
+impl HeatmapValue for f32 {
+    fn heatmap_value(self) -> Option<f32> {
+        self.is_finite().then_some(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeatmapTile {
+    pub xy: RoomXY,
+    pub value: f32,
+    /// The value scaled linearly to be 0 for the minimum and 1 for the maximum, or 0.5 when all
+    /// values are the same.
+    pub intensity: f32,
+}
+
+/// The tiles of the matrix with values that are shown, with their intensities.
+pub fn heatmap_tiles<M, T>(matrix: &M) -> Vec<HeatmapTile>
+where
+    M: MatrixCommon<T>,
+    T: HeatmapValue,
+{
+    let values = matrix
+        .iter()
+        .filter_map(|(xy, value)| value.heatmap_value().map(|value| (xy, value)))
+        .collect::<Vec<_>>();
+    let min_value = values.iter().map(|&(_, value)| value).fold(f32::INFINITY, f32::min);
+    let max_value = values.iter().map(|&(_, value)| value).fold(f32::NEG_INFINITY, f32::max);
+    let range = max_value - min_value;
+    values
+        .into_iter()
+        .map(|(xy, value)| HeatmapTile {
+            xy,
+            value,
+            intensity: if range > 0.0 { (value - min_value) / range } else { 0.5 },
+        })
+        .collect()
+}
+
+/// Shows the matrix as colored tiles with opacity scaled between the minimum and maximum value,
+/// skipping the obstacle cost.
+pub fn heatmap<M, T>(room_name: RoomName, matrix: &M, palette: Palette)
+where
+    M: MatrixCommon<T>,
+    T: HeatmapValue,
+{
+    visualize(room_name, Visualization::Heatmap(heatmap_tiles(matrix), palette));
+}
+
+thread_local! {
+    /// The name of the heatmap selected to be shown.
+    static SHOWN_HEATMAP_NAME: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Heatmaps registered under the selected name by their rooms.
+    static REGISTERED_HEATMAPS: RefCell<FxHashMap<RoomName, (Vec<HeatmapTile>, Palette)>> =
+        RefCell::new(FxHashMap::default());
+}
+
+/// Selects the name of the heatmap to show or hides it when `None`, clearing the heatmaps registered
+/// so far.
+pub fn select_heatmap(name: Option<String>) {
+    SHOWN_HEATMAP_NAME.with(|shown_name| *shown_name.borrow_mut() = name);
+    REGISTERED_HEATMAPS.with(|heatmaps| heatmaps.borrow_mut().clear());
+}
+
+/// Whether the heatmap with given name is selected to be shown, so that the matrix should be
+/// registered.
+pub fn heatmap_selected(name: &str) -> bool {
+    SHOWN_HEATMAP_NAME.with(|shown_name| shown_name.borrow().as_deref() == Some(name))
+}
+
+/// Registers the matrix to be shown in the room as a heatmap if the name is selected, replacing the
+/// previously registered one.
+pub fn register_heatmap<M, T>(room_name: RoomName, name: &str, matrix: &M, palette: Palette)
+where
+    M: MatrixCommon<T>,
+    T: HeatmapValue,
+{
+    if heatmap_selected(name) {
+        let tiles = heatmap_tiles(matrix);
+        REGISTERED_HEATMAPS.with(|heatmaps| heatmaps.borrow_mut().insert(room_name, (tiles, palette)));
+    }
+}
+
+/// Shows the registered heatmaps in their rooms.
+pub fn show_registered_heatmaps() {
+    REGISTERED_HEATMAPS.with(|heatmaps| {
+        for (&room_name, (tiles, palette)) in heatmaps.borrow().iter() {
+            visualize(room_name, Visualization::Heatmap(tiles.clone(), *palette));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::matrix_common::MatrixCommon;
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::visualization::heatmap::{heatmap_tiles, Palette};
+    use crate::utils::test_utils::xy;
+
+    #[test]
+    fn test_normalization_skips_obstacles() {
+        let mut matrix = RoomMatrix::new(255u8);
+        matrix.set(xy(1, 1), 10);
+        matrix.set(xy(2, 1), 20);
+        matrix.set(xy(3, 1), 30);
+        let tiles = heatmap_tiles(&matrix);
+        assert_eq!(
+            tiles.iter().map(|tile| (tile.xy, tile.value, tile.intensity)).collect::<Vec<_>>(),
+            vec![(xy(1, 1), 10.0, 0.0), (xy(2, 1), 20.0, 0.5), (xy(3, 1), 30.0, 1.0)]
+        );
+
+        let mut matrix = RoomMatrix::new(u16::MAX);
+        matrix.set(xy(1, 1), 1000);
+        matrix.set(xy(2, 1), 1000);
+        let tiles = heatmap_tiles(&matrix);
+        assert_eq!(tiles.iter().map(|tile| tile.intensity).collect::<Vec<_>>(), vec![0.5, 0.5]);
+
+        let mut matrix = RoomMatrix::new(f32::NAN);
+        matrix.set(xy(1, 1), -1.0);
+        matrix.set(xy(2, 1), 3.0);
+        let tiles = heatmap_tiles(&matrix);
+        assert_eq!(tiles.iter().map(|tile| tile.intensity).collect::<Vec<_>>(), vec![0.0, 1.0]);
+
+        assert!(heatmap_tiles(&RoomMatrix::new(255u8)).is_empty());
+    }
+
+    #[test]
+    fn test_palette_colors() {
+        assert_eq!(Palette::Blue.color(0.0), "#0000ff");
+        assert_eq!(Palette::Blue.color(1.0), "#0000ff");
+        assert_eq!(Palette::Red.color(0.3), "#ff0000");
+        assert_eq!(Palette::Heat.color(0.0), "#0000ff");
+        assert_eq!(Palette::Heat.color(0.5), "#80007f");
+        assert_eq!(Palette::Heat.color(1.0), "#ff0000");
+        assert_eq!(Palette::Heat.color(2.0), "#ff0000");
+    }
+}
@@ -0,0 +1,166 @@
+use rustc_hash::FxHashMap;
+use screeps::StructureType::{Container, Storage};
+use screeps::{LineStyle, Position, RoomName, RoomVisual, TextAlign, TextStyle};
+use crate::creeps::creeps::for_each_creep;
+use crate::hauling::hauler_routes::with_hauler_routes;
+use crate::hauling::requests::{with_haul_requests, HaulRequestKind};
+use crate::room_states::room_state::RoomState;
+use crate::spawning::spawn_schedule::{with_spawn_schedule, SpawnQueueEntry, SpawnQueueEntryState};
+use crate::utils::game_tick::game_tick;
+
+const SPAWN_QUEUE_LINE_HEIGHT: f32 = 0.6;
+const WITHDRAW_ROUTE_COLOR: &str = "#ffd700";
+const DEPOSIT_ROUTE_COLOR: &str = "#60ff60";
+const BADGE_COLOR: &str = "#ff9030";
+
+/// Shows the routes of the haulers of the room, the numbers of unfulfilled haul requests of its
+/// storage and containers and its spawn queue, starting `top` below the top left corner.
+pub fn show_logistics_overlay(room_name: RoomName, room_state: &RoomState, top: f32) {
+    show_hauler_routes(room_name);
+    show_haul_request_badges(room_name, room_state);
+    show_spawn_queue(room_name, room_state.resources.spawn_energy, top);
+}
+
+/// Draws lines from each hauler with assigned requests through the targets of the requests, in the
+/// order they are fulfilled. Lines between rooms are skipped.
+fn show_hauler_routes(room_name: RoomName) {
+    let routes = with_hauler_routes(room_name, |routes| routes.clone());
+    if routes.is_empty() {
+        return;
+    }
+
+    let mut creep_positions = FxHashMap::default();
+    for_each_creep(|creep_ref| {
+        let creep = creep_ref.borrow();
+        if routes.contains_key(&creep.name) {
+            creep_positions.insert(creep.name.clone(), creep.travel_state.pos);
+        }
+    });
+
+    for (creep_name, route) in routes.iter() {
+        let Some(&creep_pos) = creep_positions.get(creep_name) else {
+            continue;
+        };
+        let mut from = creep_pos;
+        for &(kind, to) in route.iter() {
+            if from.room_name() == to.room_name() {
+                let color = match kind {
+                    HaulRequestKind::WithdrawRequest => WITHDRAW_ROUTE_COLOR,
+                    HaulRequestKind::DepositRequest => DEPOSIT_ROUTE_COLOR,
+                };
+                RoomVisual::new(Some(to.room_name())).line(
+                    (from.x().u8() as f32, from.y().u8() as f32),
+                    (to.x().u8() as f32, to.y().u8() as f32),
+                    Some(LineStyle::default().color(color).width(0.1).opacity(0.6)),
+                );
+            }
+            from = to;
+        }
+    }
+}
+
+/// Shows the number of haul requests that are not fully reserved by haulers next to the storage and
+/// each container of the room.
+fn show_haul_request_badges(room_name: RoomName, room_state: &RoomState) {
+    let mut counts = FxHashMap::<Position, u32>::default();
+    for structure_type in [Storage, Container] {
+        if let Some(xys) = room_state.structures.get(&structure_type) {
+            for &xy in xys.keys() {
+                counts.insert(Position::new(xy.x, xy.y, room_name), 0);
+            }
+        }
+    }
+    with_haul_requests(room_name, |haul_requests| {
+        for request in haul_requests.withdraw_requests.values().chain(haul_requests.deposit_requests.values()) {
+            let request = request.borrow();
+            if request.unreserved_amount() > 0 {
+                if let Some(count) = counts.get_mut(&request.pos) {
+                    *count += 1;
+                }
+            }
+        }
+    });
+
+    let vis = RoomVisual::new(Some(room_name));
+    let style = TextStyle::default()
+        .font(0.4)
+        .color("#000")
+        .background_color(BADGE_COLOR)
+        .background_padding(0.05);
+    for (pos, count) in counts.into_iter() {
+        if count > 0 {
+            vis.text(pos.x().u8() as f32 + 0.35, pos.y().u8() as f32 - 0.25, count.to_string(), Some(style.clone()));
+        }
+    }
+}
+
+/// Lists the spawn schedule of the room in its top left corner, starting `top` below it.
+fn show_spawn_queue(room_name: RoomName, spawn_energy: u32, top: f32) {
+    let entries = with_spawn_schedule(room_name, |room_spawn_schedule| room_spawn_schedule.queue_entries());
+    let vis = RoomVisual::new(Some(room_name));
+    let style = TextStyle::default().font(0.5).align(TextAlign::Left);
+    for (i, text) in spawn_queue_panel_lines(&entries, spawn_energy, game_tick()).into_iter().enumerate() {
+        vis.text(0.5, top + 0.75 + SPAWN_QUEUE_LINE_HEIGHT * i as f32, text, Some(style.clone()));
+    }
+}
+
+/// Lines of the spawn queue panel, one per entry, with the priority, role, body, energy available
+/// out of the energy cost unless already spawning, and the state.
+pub fn spawn_queue_panel_lines(entries: &[SpawnQueueEntry], spawn_energy: u32, tick: u32) -> Vec<String> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (energy, state) = match entry.state {
+                SpawnQueueEntryState::Spawning(_, spawn_end_tick) => (
+                    format!("{}E", entry.energy_cost),
+                    format!("spawning for {}", spawn_end_tick.saturating_sub(tick)),
+                ),
+                SpawnQueueEntryState::Waiting => (
+                    format!("{}/{}E", spawn_energy.min(entry.energy_cost), entry.energy_cost),
+                    "waiting".to_string(),
+                ),
+                SpawnQueueEntryState::Scheduled(scheduled_tick) => (
+                    format!("{}/{}E", spawn_energy.min(entry.energy_cost), entry.energy_cost),
+                    format!("in {}", scheduled_tick.saturating_sub(tick)),
+                ),
+            };
+            format!("{} {} {} {} {}", entry.priority, entry.role, entry.body, energy, state)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::ObjectId;
+    use screeps::Part::{Carry, Move, Work};
+    use crate::creeps::creep_body::CreepBody;
+    use crate::creeps::creep_role::CreepRole::{Hauler, Miner, Upgrader};
+    use crate::spawning::spawn_schedule::{SpawnQueueEntry, SpawnQueueEntryState};
+    use crate::utils::priority::Priority;
+    use crate::visualization::logistics_overlay::spawn_queue_panel_lines;
+
+    #[test]
+    fn test_spawn_queue_panel_lines() {
+        let entry = |role, body: Vec<_>, priority, energy_cost, state| SpawnQueueEntry {
+            role,
+            body: CreepBody::from(body),
+            priority: Priority(priority),
+            energy_cost,
+            state,
+        };
+        let entries = [
+            entry(Miner, vec![(Work, 2), (Move, 1)], 200, 250, SpawnQueueEntryState::Spawning(ObjectId::from_packed(1), 120)),
+            entry(Hauler, vec![(Carry, 4), (Move, 2)], 150, 300, SpawnQueueEntryState::Waiting),
+            entry(Upgrader, vec![(Work, 1), (Carry, 1), (Move, 1)], 100, 200, SpawnQueueEntryState::Scheduled(150)),
+        ];
+
+        let lines = spawn_queue_panel_lines(&entries, 220, 100);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(&format!("{} {} ", Priority(200), Miner)));
+        assert!(lines[0].ends_with(" 250E spawning for 20"));
+        assert!(lines[1].ends_with(" 220/300E waiting"));
+        assert!(lines[2].ends_with(" 200/200E in 50"));
+
+        assert!(spawn_queue_panel_lines(&[], 300, 100).is_empty());
+    }
+}
@@ -1,2 +1,5 @@
 pub mod show_visualizations;
-pub mod room_visualization;
\ No newline at end of file
+pub mod room_visualization;
+pub mod heatmap;
+pub mod logistics_overlay;
+pub mod process_panel;
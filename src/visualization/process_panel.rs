@@ -0,0 +1,84 @@
+use screeps::{RoomName, RoomVisual, TextAlign, TextStyle};
+use crate::kernel::kernel::process_tree;
+use crate::kernel::process::ProcessInfo;
+
+pub const PROCESS_PANEL_LINE_HEIGHT: f32 = 0.6;
+/// The number of processes listed in the panel.
+pub const PROCESS_PANEL_SIZE: usize = 10;
+
+/// Lists the processes using the most CPU with their states in the top left corner of the room.
+/// Returns the height of the panel.
+pub fn show_process_panel(room_name: RoomName) -> f32 {
+    let lines = process_panel_lines(&process_tree(), PROCESS_PANEL_SIZE);
+    let vis = RoomVisual::new(Some(room_name));
+    let style = TextStyle::default().font(0.5).align(TextAlign::Left).color("#c0c0ff");
+    for (i, text) in lines.iter().enumerate() {
+        vis.text(0.5, 0.75 + PROCESS_PANEL_LINE_HEIGHT * i as f32, text.clone(), Some(style.clone()));
+    }
+    PROCESS_PANEL_LINE_HEIGHT * lines.len() as f32
+}
+
+/// Lines of the process panel, a header and one line per each of up to `n` processes using the most
+/// CPU, with the CPU used, name and state. Processes without measured CPU usage are listed last.
+pub fn process_panel_lines(processes: &[ProcessInfo], n: usize) -> Vec<String> {
+    let mut processes = processes.iter().collect::<Vec<_>>();
+    processes.sort_by(|a, b| match (a.cpu_used, b.cpu_used) {
+        (Some(a_cpu), Some(b_cpu)) => b_cpu.total_cmp(&a_cpu),
+        (a_cpu, b_cpu) => b_cpu.is_some().cmp(&a_cpu.is_some()),
+    });
+
+    let header = if processes.iter().any(|info| info.cpu_used.is_some()) {
+        format!("{} processes", processes.len())
+    } else {
+        format!("{} processes, CPU not measured yet", processes.len())
+    };
+
+    let mut lines = vec![header];
+    lines.extend(processes.into_iter().take(n).map(|info| {
+        let cpu_used = info
+            .cpu_used
+            .map(|cpu_used| format!("{:.2}", cpu_used))
+            .unwrap_or_else(|| "-".to_string());
+        format!("{} {}-{} {}", cpu_used, info.pid, info.name, info.state)
+    }));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kernel::process::{PId, ProcessInfo, ProcessState};
+    use crate::utils::priority::Priority;
+    use crate::visualization::process_panel::process_panel_lines;
+
+    fn process(name: &str, cpu_used: Option<f64>) -> ProcessInfo {
+        ProcessInfo {
+            pid: PId::new(),
+            parent_pid: None,
+            name: name.to_string(),
+            priority: Priority(100),
+            state: ProcessState::Active,
+            cpu_used,
+        }
+    }
+
+    #[test]
+    fn test_process_panel_lines() {
+        let processes = [
+            process("idle", None),
+            process("light", Some(0.25)),
+            process("heavy", Some(3.0)),
+            process("medium", Some(1.5)),
+        ];
+
+        let lines = process_panel_lines(&processes, 3);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "4 processes");
+        assert!(lines[1].starts_with("3.00 ") && lines[1].ends_with("-heavy active"));
+        assert!(lines[2].starts_with("1.50 ") && lines[2].ends_with("-medium active"));
+        assert!(lines[3].starts_with("0.25 ") && lines[3].ends_with("-light active"));
+
+        let lines = process_panel_lines(&processes[..1], 3);
+        assert_eq!(lines[0], "1 processes, CPU not measured yet");
+        assert!(lines[1].starts_with("- ") && lines[1].ends_with("-idle active"));
+    }
+}
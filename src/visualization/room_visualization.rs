@@ -8,23 +8,44 @@ use petgraph::prelude::EdgeRef;
 use petgraph::stable_graph::StableGraph;
 use petgraph::Undirected;
 use rustc_hash::FxHashMap;
-use screeps::StructureType::{Rampart, Road};
+use crate::room_planning::plan::Plan;
+use crate::visualization::heatmap::{heatmap_tiles, HeatmapTile, Palette};
 use crate::room_planning::planned_tile::PlannedTile;
 use crate::room_states::room_state::StructuresMap;
+use crate::geometry::room_xy::RoomXYUtils;
 use crate::u;
 
 #[derive(Debug)]
 pub enum Visualization {
     Matrix(Box<RoomMatrix<u8>>),
+    Heatmap(Vec<HeatmapTile>, Palette),
     Graph(StableGraph<RoomXY, u8, Undirected, u16>),
     NodeLabels(StableGraph<RoomXY, u8, Undirected, u16>, FxHashMap<NodeIndex<u16>, String>),
     Structures(StructuresMap),
     // TODO whole plan with displaying stats, not just tiles
-    Plan(RoomMatrix<PlannedTile>),
+    /// Planned tiles with the minimum RCL up to the given one.
+    Plan(Box<RoomMatrix<PlannedTile>>, u8),
     Text(String),
 }
 use Visualization::*;
 
+/// A single element of the visualization of a plan.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PlanVisual {
+    /// A road on a tile without any adjacent roads.
+    Road(RoomXY),
+    /// A line between two adjacent road tiles.
+    RoadSegment(RoomXY, RoomXY),
+    Structure(RoomXY, StructureType),
+    Rampart(RoomXY),
+    /// A small label with the minimum RCL of the tile.
+    MinRcl(RoomXY, u8),
+}
+
+const PLAN_ROAD_COLOR: &str = "#aaa";
+const PLAN_RAMPART_COLOR: &str = "#0f0";
+const PLAN_MIN_RCL_COLOR: &str = "#ff0";
+
 pub trait RoomVisualExtExt {
     fn arrow(&self, from: RoomXY, to: RoomXY, style: Option<LineStyle>);
 }
@@ -62,44 +83,10 @@ pub fn visualize(room_name: RoomName, visualization: Visualization) {
     let mut vis = RoomVisualExt::new(room_name);
     match visualization {
         Matrix(matrix) => {
-            let mut min_value = 255;
-            let mut max_non_255_value = 0;
-            for (_, value) in matrix.iter() {
-                if value < min_value {
-                    min_value = value;
-                }
-                if value != 255 && value > max_non_255_value {
-                    max_non_255_value = value;
-                }
-            }
-            let range = (max_non_255_value - min_value) as f32;
-            for (xy, value) in matrix.iter() {
-                if value != 255 {
-                    let opacity = if range > 0.0 {
-                        0.2 + 0.6 * (value - min_value) as f32 / range
-                    } else {
-                        0.4
-                    };
-                    vis.rect(
-                        xy.x.u8() as f32 - 0.5,
-                        xy.y.u8() as f32 - 0.5,
-                        1.0,
-                        1.0,
-                        Some(RectStyle::default().fill("#00f").opacity(opacity)),
-                    );
-                    vis.text(
-                        xy.x.u8() as f32,
-                        xy.y.u8() as f32 + 0.15,
-                        value.to_string(),
-                        Some(
-                            TextStyle::default()
-                                .font(0.5)
-                                .color("#fff")
-                                .opacity(1.0),
-                        ),
-                    );
-                }
-            }
+            draw_heatmap(&mut vis, &heatmap_tiles(matrix.as_ref()), Palette::Blue);
+        },
+        Heatmap(tiles, palette) => {
+            draw_heatmap(&mut vis, &tiles, palette);
         },
         Graph(graph) => {
             for node in graph.node_indices() {
@@ -139,35 +126,46 @@ pub fn visualize(room_name: RoomName, visualization: Visualization) {
                 }
             }
         },
-        Plan(planned_tiles) => {
-            for (xy, tile) in planned_tiles.iter() {
-                if tile.structures().road() {
-                    vis.structure_roomxy(xy, Road, 0.6);
-                }
-            }
-            for (xy, tile) in planned_tiles.iter() {
-                if let Ok(structure_type) = StructureType::try_from(tile.structures().main()) {
-                    vis.structure_roomxy(xy, structure_type, 0.6);
-                }
-            }
-            for (xy, tile) in planned_tiles.iter() {
-                if tile.structures().rampart() {
-                    vis.structure_roomxy(xy, Rampart, 0.6);
-                }
-            }
-            for (xy, tile) in planned_tiles.iter() {
-                if tile.min_rcl() != 0 {
-                    vis.text(
-                        xy.x.u8() as f32,
-                        xy.y.u8() as f32 + 0.3,
-                        tile.min_rcl().to_string(),
-                        Some(
-                            TextStyle::default()
-                                .font(0.8)
-                                .color("#00f")
-                                .opacity(0.8)
-                        ),
-                    );
+        Plan(planned_tiles, max_rcl) => {
+            for plan_visual in plan_visuals(&planned_tiles, max_rcl) {
+                match plan_visual {
+                    PlanVisual::Road(xy) => {
+                        vis.circle(xy.x.u8() as f32, xy.y.u8() as f32, Some(
+                            CircleStyle::default().fill(PLAN_ROAD_COLOR).radius(0.15).opacity(0.6)
+                        ));
+                    },
+                    PlanVisual::RoadSegment(from, to) => {
+                        vis.line(
+                            (from.x.u8() as f32, from.y.u8() as f32),
+                            (to.x.u8() as f32, to.y.u8() as f32),
+                            Some(LineStyle::default().color(PLAN_ROAD_COLOR).width(0.15).opacity(0.6)),
+                        );
+                    },
+                    PlanVisual::Structure(xy, structure_type) => {
+                        vis.structure_roomxy(xy, structure_type, 0.6);
+                    },
+                    PlanVisual::Rampart(xy) => {
+                        vis.rect(
+                            xy.x.u8() as f32 - 0.5,
+                            xy.y.u8() as f32 - 0.5,
+                            1.0,
+                            1.0,
+                            Some(RectStyle::default().fill(PLAN_RAMPART_COLOR).opacity(0.2)),
+                        );
+                    },
+                    PlanVisual::MinRcl(xy, min_rcl) => {
+                        vis.text(
+                            xy.x.u8() as f32 + 0.3,
+                            xy.y.u8() as f32 + 0.45,
+                            min_rcl.to_string(),
+                            Some(
+                                TextStyle::default()
+                                    .font(0.3)
+                                    .color(PLAN_MIN_RCL_COLOR)
+                                    .opacity(0.8)
+                            ),
+                        );
+                    },
                 }
             }
         },
@@ -175,4 +173,117 @@ pub fn visualize(room_name: RoomName, visualization: Visualization) {
             vis.text(24.5, 1.35, text, Some(TextStyle::default().font(1.0)));
         },
     }
-}
\ No newline at end of file
+}
+
+fn draw_heatmap(vis: &mut RoomVisualExt, tiles: &[HeatmapTile], palette: Palette) {
+    for tile in tiles.iter() {
+        vis.rect(
+            tile.xy.x.u8() as f32 - 0.5,
+            tile.xy.y.u8() as f32 - 0.5,
+            1.0,
+            1.0,
+            Some(RectStyle::default().fill(&palette.color(tile.intensity)).opacity(0.2 + 0.6 * tile.intensity)),
+        );
+        let text = if tile.value.fract() == 0.0 {
+            format!("{}", tile.value)
+        } else {
+            format!("{:.1}", tile.value)
+        };
+        vis.text(
+            tile.xy.x.u8() as f32,
+            tile.xy.y.u8() as f32 + 0.15,
+            text,
+            Some(
+                TextStyle::default()
+                    .font(0.5)
+                    .color("#fff")
+                    .opacity(1.0),
+            ),
+        );
+    }
+}
+
+/// Shows the structures of the plan with the minimum RCL up to `max_rcl`.
+pub fn visualize_plan(room_name: RoomName, plan: &Plan, max_rcl: u8) {
+    visualize(room_name, Plan(Box::new(plan.tiles.clone()), max_rcl));
+}
+
+/// Elements of the visualization of the planned tiles with the minimum RCL up to `max_rcl`, in the
+/// order of drawing: roads, then other structures, ramparts and labels with the minimum RCL.
+pub fn plan_visuals(planned_tiles: &RoomMatrix<PlannedTile>, max_rcl: u8) -> Vec<PlanVisual> {
+    let shown = |xy: RoomXY| {
+        let tile = planned_tiles.get(xy);
+        !tile.structures().is_empty() && tile.min_rcl() <= max_rcl
+    };
+    let shown_road = |xy: RoomXY| shown(xy) && planned_tiles.get(xy).structures().road();
+
+    let mut result = Vec::new();
+    for xy in planned_tiles.iter_xy().filter(|&xy| shown_road(xy)) {
+        let mut isolated = true;
+        for near in xy.around().filter(|&near| shown_road(near)) {
+            isolated = false;
+            // Each segment is added once, from the tile with the lower index.
+            if xy.to_index() < near.to_index() {
+                result.push(PlanVisual::RoadSegment(xy, near));
+            }
+        }
+        if isolated {
+            result.push(PlanVisual::Road(xy));
+        }
+    }
+    for (xy, tile) in planned_tiles.iter() {
+        if shown(xy) {
+            if let Ok(structure_type) = StructureType::try_from(tile.structures().main()) {
+                result.push(PlanVisual::Structure(xy, structure_type));
+            }
+        }
+    }
+    for (xy, tile) in planned_tiles.iter() {
+        if shown(xy) && tile.structures().rampart() {
+            result.push(PlanVisual::Rampart(xy));
+        }
+    }
+    for (xy, tile) in planned_tiles.iter() {
+        if shown(xy) {
+            result.push(PlanVisual::MinRcl(xy, tile.min_rcl()));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use screeps::StructureType::{Extension, Rampart, Road, Spawn};
+    use crate::algorithms::room_matrix::RoomMatrix;
+    use crate::room_planning::planned_tile::{BasePart, PlannedTile};
+    use crate::visualization::room_visualization::{plan_visuals, PlanVisual};
+    use crate::utils::test_utils::xy;
+
+    #[test]
+    fn test_plan_visuals() {
+        let mut planned_tiles = RoomMatrix::new(PlannedTile::default());
+        for (tile_xy, structure_type, min_rcl) in [
+            (xy(10, 10), Spawn, 1),
+            (xy(10, 10), Rampart, 1),
+            (xy(11, 11), Road, 2),
+            (xy(12, 11), Road, 2),
+            (xy(13, 12), Road, 6),
+            (xy(20, 20), Road, 3),
+            (xy(21, 21), Extension, 7),
+        ] {
+            planned_tiles.merge_structure(tile_xy, structure_type, BasePart::Interior, false).unwrap();
+            planned_tiles.set_min_rcl(tile_xy, min_rcl);
+        }
+
+        assert_eq!(plan_visuals(&planned_tiles, 4), vec![
+            PlanVisual::RoadSegment(xy(11, 11), xy(12, 11)),
+            PlanVisual::Road(xy(20, 20)),
+            PlanVisual::Structure(xy(10, 10), Spawn),
+            PlanVisual::Rampart(xy(10, 10)),
+            PlanVisual::MinRcl(xy(10, 10), 1),
+            PlanVisual::MinRcl(xy(11, 11), 2),
+            PlanVisual::MinRcl(xy(12, 11), 2),
+            PlanVisual::MinRcl(xy(20, 20), 3),
+        ]);
+    }
+}
@@ -1,11 +1,18 @@
 use crate::algorithms::matrix_common::MatrixCommon;
+use crate::cpu_management::cpu_level;
+use crate::cpu_management::CpuFeature::Visualizations;
+use crate::creeps::creeps::for_each_creep;
 use crate::kernel::sleep::sleep;
 use crate::profiler::measure_time;
 use crate::room_states::room_states::for_each_owned_room;
 use crate::utils::find::get_structure;
 use room_visual_ext::RoomVisualExt;
 use screeps::StructureType::{Rampart, Road};
-use screeps::{game, StructureType};
+use screeps::{game, RoomVisual, StructureType, TextStyle};
+use crate::visualization::heatmap::show_registered_heatmaps;
+use crate::visualization::logistics_overlay::show_logistics_overlay;
+use crate::visualization::process_panel::show_process_panel;
+use std::cmp::Reverse;
 
 const CURRENT_RCL_PLAN_OPACITY: f32 = 0.4;
 const RCL8_PLAN_OPACITY: f32 = 0.12;
@@ -13,8 +20,16 @@ const RCL8_PLAN_OPACITY: f32 = 0.12;
 pub async fn show_visualizations() {
     loop {
         // TODO This should be more dynamic.
-        if game::cpu::tick_limit() - game::cpu::get_used() > 100.0 {
+        if cpu_level().enables(Visualizations) && game::cpu::tick_limit() - game::cpu::get_used() > 100.0 {
             measure_time("show_visualizations", || {
+                // The room with the highest RCL, where the process panel is shown.
+                let mut capital = None;
+                for_each_owned_room(|room_name, room_state| {
+                    if capital.is_none_or(|(rcl, name)| (room_state.rcl, Reverse(room_name)) > (rcl, Reverse(name))) {
+                        capital = Some((room_state.rcl, room_name));
+                    }
+                });
+
                 for_each_owned_room(|room_name, room_state| {
                     if let Some(plan) = room_state.plan.as_ref() {
                         let mut vis = RoomVisualExt::new(room_name);
@@ -69,10 +84,39 @@ pub async fn show_visualizations() {
                             }
                         }
                     }
+
+                    let top = if capital.is_some_and(|(_, capital_name)| capital_name == room_name) {
+                        show_process_panel(room_name)
+                    } else {
+                        0.0
+                    };
+                    show_logistics_overlay(room_name, room_state, top);
                 });
+
+                show_stuck_creeps();
+                show_registered_heatmaps();
             });
         }
 
         sleep(1).await;
     }
 }
+
+/// Shows the number of ticks stuck creeps have been unable to make progress above them.
+fn show_stuck_creeps() {
+    let style = TextStyle::default().font(0.4).color("#ff6060");
+    for_each_creep(|creep_ref| {
+        let creep = creep_ref.borrow();
+        let travel_state = &creep.travel_state;
+        if travel_state.stuck_ticks > 0 || travel_state.stuck_repaths > 0 {
+            let pos = travel_state.pos;
+            let text = format!("stuck {}/{}", travel_state.stuck_ticks, travel_state.stuck_repaths);
+            RoomVisual::new(Some(pos.room_name())).text(
+                pos.x().u8() as f32,
+                pos.y().u8() as f32 - 0.5,
+                text,
+                Some(style.clone())
+            );
+        }
+    });
+}